@@ -0,0 +1,230 @@
+use std::sync::Arc;
+
+use maud::{html, Markup};
+use serde::Serialize;
+
+use crate::transform::BodyTransform;
+
+/// Live regions added to every full page with `server.announcements.live_regions`,
+/// announcements are swapped into them.
+pub const LIVE_REGION_ID: &str = "blandwork-announcer";
+pub const ASSERTIVE_REGION_ID: &str = "blandwork-announcer-assertive";
+
+/// Trigger carrying every announcement, for pages without the live regions.
+pub const ANNOUNCE: &str = "blandwork:announce";
+
+/// After-settle trigger moving focus, handled by the integration script.
+pub const FOCUS: &str = "blandwork:focus";
+
+// hidden from sight but not from screen readers
+const VISUALLY_HIDDEN: &str = "position:absolute;width:1px;height:1px;padding:0;margin:-1px;overflow:hidden;clip:rect(0,0,0,0);white-space:nowrap;border:0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Politeness {
+    /// Read once the screen reader is idle.
+    Polite,
+
+    /// Interrupts, reserve for errors.
+    Assertive,
+}
+
+impl Politeness {
+    fn region(&self) -> &'static str {
+        match self {
+            Politeness::Polite => LIVE_REGION_ID,
+            Politeness::Assertive => ASSERTIVE_REGION_ID,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Politeness::Polite => "polite",
+            Politeness::Assertive => "assertive",
+        }
+    }
+
+    // `status` implies polite, an assertive region is an `alert`
+    fn role(&self) -> &'static str {
+        match self {
+            Politeness::Polite => "status",
+            Politeness::Assertive => "alert",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Announcement {
+    pub message: String,
+    pub politeness: Politeness,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Focus {
+    pub selector: String,
+}
+
+/// The (empty) live regions, rendered once per full page.
+pub fn live_region() -> Markup {
+    html! {
+        @for politeness in [Politeness::Polite, Politeness::Assertive] {
+            div id=(politeness.region()) aria-live=(politeness.as_str()) aria-atomic="true" role=(politeness.role()) style=(VISUALLY_HIDDEN) {}
+        }
+    }
+}
+
+/// Insert the live regions just before `</body>`, or append them when there is none.
+pub fn inject_live_region(page: String) -> String {
+    let region: String = live_region().into_string();
+
+    match page.rfind("</body>") {
+        Some(index) => {
+            let mut page: String = page;
+            page.insert_str(index, &region);
+            page
+        },
+        None => page + &region
+    }
+}
+
+/// Adds the live regions to full pages, after the other transforms.
+pub(crate) fn live_regions() -> BodyTransform {
+    Arc::new(|_, html| inject_live_region(html))
+}
+
+/// Out of band swaps filling the live regions, announcements of a region are read in order.
+pub(crate) fn announcements_oob(announcements: &[Announcement]) -> Markup {
+    html! {
+        @for politeness in [Politeness::Polite, Politeness::Assertive] {
+            @let messages: Vec<&str> = announcements.iter()
+                .filter(|a| a.politeness == politeness)
+                .map(|a| a.message.as_str())
+                .collect();
+
+            @if !messages.is_empty() {
+                div id=(politeness.region()) hx-swap-oob="innerHTML" {
+                    (messages.join(" "))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use axum_htmx::{HX_REQUEST, HX_TRIGGER, HX_TRIGGER_AFTER_SETTLE};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{config::Announcements, context::ContextLayer, test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{inject_live_region, Politeness, LIVE_REGION_ID};
+
+    async fn results(Extension(accessor): Extension<ContextAccessor>) -> Markup {
+        let mut context = accessor.context().await;
+        context.announce("5 results loaded", Politeness::Polite);
+        context.announce("page 2 of 4", Politeness::Polite);
+        context.announce("filters reset", Politeness::Assertive);
+        context.focus("#first-result");
+
+        html! { ul { li #first-result { "one" } } }
+    }
+
+    async fn send(htmx: bool, live_regions: bool) -> (String, Option<String>) {
+        let router = Router::new()
+            .route("/results", get(results))
+            .layer(ContextLayer::new())
+            .layer(Extension(Arc::new(Announcements { live_regions })));
+
+        let mut request = Request::builder().uri("/results");
+        if htmx {
+            request = request.header(HX_REQUEST, "true");
+        }
+
+        let response = router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let focus = response.headers().get(HX_TRIGGER_AFTER_SETTLE).map(|v| v.to_str().unwrap().to_owned());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (String::from_utf8(body.to_vec()).unwrap(), focus)
+    }
+
+    #[tokio::test]
+    async fn test_announcements_swap_into_live_region() {
+        let (body, focus) = send(true, true).await;
+
+        assert!(body.starts_with(r#"<ul><li id="first-result">one</li></ul>"#));
+        assert!(body.contains(r#"<div id="blandwork-announcer" hx-swap-oob="innerHTML">5 results loaded page 2 of 4</div>"#));
+        assert!(body.contains(r#"<div id="blandwork-announcer-assertive" hx-swap-oob="innerHTML">filters reset</div>"#));
+        assert_eq!(focus.as_deref(), Some(r##"{"blandwork:focus":{"selector":"#first-result"}}"##));
+    }
+
+    #[tokio::test]
+    async fn test_announcements_without_live_regions() {
+        let router = Router::new()
+            .route("/results", get(results))
+            .layer(ContextLayer::new());
+
+        let request = Request::get("/results").header(HX_REQUEST, "true").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        let trigger = response.headers()[HX_TRIGGER].to_str().unwrap().to_owned();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        // the integration script reads them out
+        assert_eq!(&body[..], br#"<ul><li id="first-result">one</li></ul>"#);
+        assert!(trigger.contains("blandwork:announce"));
+        assert!(trigger.contains("filters reset"));
+    }
+
+    #[tokio::test]
+    async fn test_full_page_is_untouched() {
+        let (body, focus) = send(false, true).await;
+
+        assert_eq!(body, r#"<ul><li id="first-result">one</li></ul>"#);
+        assert!(focus.is_none());
+    }
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Results;
+
+    impl Feature for Results {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/results", get(|| async { html! { p { "results" } } })))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_live_regions_behind_config() {
+        let page = |live_regions: bool| async move {
+            let mut config = Config::default();
+            config.server.announcements.live_regions = live_regions;
+
+            let app = App::new(config, TestTemplate).register_feature(Results).build();
+            let response = router(&app).oneshot(Request::get("/results").body(Body::empty()).unwrap()).await.unwrap();
+            String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+        };
+
+        assert_eq!(page(false).await, "<html><body><p>results</p></body></html>");
+        assert_eq!(page(true).await.matches(LIVE_REGION_ID).count(), 2);
+    }
+
+    #[test]
+    fn test_live_region_injected_once() {
+        let page = inject_live_region("<html><body><main></main></body></html>".to_owned());
+
+        assert_eq!(page.matches(LIVE_REGION_ID).count(), 2);
+        assert!(page.contains(r#"aria-live="polite" aria-atomic="true" role="status""#));
+        assert!(page.contains(r#"aria-live="assertive" aria-atomic="true" role="alert""#));
+        assert!(page.ends_with("</div></body></html>"));
+    }
+}
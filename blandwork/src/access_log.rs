@@ -0,0 +1,332 @@
+use std::{
+    future::Future, net::SocketAddr, pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH}
+};
+
+use axum::{body::Body, extract::{ConnectInfo, Request}};
+use http_body::Body as _;
+use hyper::{
+    header::{HeaderValue, CONTENT_LENGTH, REFERER, USER_AGENT},
+    HeaderMap, Response
+};
+use serde_json::json;
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::{
+    clock::civil_from_days, config::AccessLogFormat,
+    cookies::{CookieJar, CookieSettings}, experiment::ExperimentAssignments,
+    offline::REPLAYED
+};
+
+pub const REQUEST_ID: &str = "x-request-id";
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Receives every formatted access log line, stdout unless replaced.
+pub type AccessLogSink = Arc<dyn Fn(String) + Send + Sync>;
+
+/// One finished request.
+#[derive(Debug, Clone)]
+pub struct AccessEntry {
+    pub time: SystemTime,
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub status: u16,
+
+    // None for streamed bodies of unknown length
+    pub bytes: Option<u64>,
+    pub duration: Duration,
+    pub request_id: String,
+    pub client_ip: Option<String>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+
+    // experiment assignments of the browser, `checkout=b,pricing=a`
+    pub experiments: Option<String>,
+
+    // submitted from the browser's offline queue
+    pub replayed: bool,
+
+    // queries run through `Db` and their total time, None without a database
+    pub db_queries: Option<u64>,
+    pub db_duration: Option<Duration>,
+}
+
+/// (year, month 1-12, day, hour, minute, second) in UTC
+fn civil(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let seconds: u64 = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days: i64 = (seconds / 86400) as i64;
+    let rest: u64 = seconds % 86400;
+
+    let (year, month, day) = civil_from_days(days);
+
+    (year, month, day, rest / 3600, (rest % 3600) / 60, rest % 60)
+}
+
+fn quoted(value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        None => "\"-\"".to_owned(),
+    }
+}
+
+impl AccessEntry {
+    /// Apache combined format, followed by the duration in milliseconds and the request id.
+    pub fn combined(&self) -> String {
+        let (year, month, day, hour, minute, second) = civil(self.time);
+
+        format!("{ip} - - [{day:02}/{month}/{year}:{hour:02}:{minute:02}:{second:02} +0000] \"{method} {path} {version}\" {status} {bytes} {referer} {agent} {duration} {id}",
+            ip = self.client_ip.as_deref().unwrap_or("-"),
+            month = MONTHS[(month - 1) as usize],
+            method = self.method,
+            path = self.path,
+            version = self.version,
+            status = self.status,
+            bytes = self.bytes.map(|bytes| bytes.to_string()).unwrap_or("-".to_owned()),
+            referer = quoted(&self.referer),
+            agent = quoted(&self.user_agent),
+            duration = self.duration.as_millis(),
+            id = self.request_id
+        )
+    }
+
+    pub fn json(&self) -> String {
+        let (year, month, day, hour, minute, second) = civil(self.time);
+
+        json!({
+            "time": format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z"),
+            "method": self.method,
+            "path": self.path,
+            "version": self.version,
+            "status": self.status,
+            "bytes": self.bytes,
+            "duration_ms": self.duration.as_secs_f64() * 1000.0,
+            "request_id": self.request_id,
+            "client_ip": self.client_ip,
+            "referer": self.referer,
+            "user_agent": self.user_agent,
+            "experiments": self.experiments,
+            "replayed": self.replayed,
+            "db_queries": self.db_queries,
+            "db_ms": self.db_duration.map(|duration| duration.as_secs_f64() * 1000.0),
+        }).to_string()
+    }
+}
+
+/// One line per request in `combined` or `json` format, independent of the tracing output.
+/// The json lines carry the browser's experiment assignments for attribution.
+/// Reuses an incoming `X-Request-Id` (or generates one) and returns it on the response.
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    format: AccessLogFormat,
+    sink: AccessLogSink,
+}
+
+impl AccessLogLayer {
+    pub fn new(format: AccessLogFormat) -> Self {
+        Self { format, sink: Arc::new(|line: String| println!("{line}")) }
+    }
+
+    /// Send the lines somewhere else than stdout.
+    pub fn with_sink(mut self, sink: impl Fn(String) + Send + Sync + 'static) -> Self {
+        self.sink = Arc::new(sink);
+        self
+    }
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogService { inner, format: self.format, sink: self.sink.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLogService<S> {
+    inner: S,
+    format: AccessLogFormat,
+    sink: AccessLogSink,
+}
+
+fn header(headers: &HeaderMap, name: impl hyper::header::AsHeaderName) -> Option<String> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(|value| value.to_owned())
+}
+
+fn experiments(req: &Request) -> Option<String> {
+    let settings: Arc<CookieSettings> = req.extensions().get::<Arc<CookieSettings>>().cloned().unwrap_or_default();
+
+    CookieJar::from_request(req, &settings)
+        .get::<ExperimentAssignments>()
+        .map(|assignments| assignments.label())
+        .filter(|label| !label.is_empty())
+}
+
+impl<S> Service<Request> for AccessLogService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        if self.format == AccessLogFormat::None {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let started: Instant = Instant::now();
+
+        let request_id: String = header(req.headers(), REQUEST_ID)
+            .filter(|id| !id.is_empty() && id.len() <= 128)
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        if let Ok(value) = HeaderValue::from_str(&request_id) {
+            req.headers_mut().insert(REQUEST_ID, value);
+        }
+
+        let mut entry: AccessEntry = AccessEntry {
+            time: SystemTime::now(),
+            method: req.method().to_string(),
+            path: req.uri().path_and_query().map(|path| path.to_string()).unwrap_or_else(|| req.uri().path().to_owned()),
+            version: format!("{:?}", req.version()),
+            status: 0,
+            bytes: None,
+            duration: Duration::ZERO,
+            request_id,
+            client_ip: req.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip().to_string()),
+            referer: header(req.headers(), REFERER),
+            user_agent: header(req.headers(), USER_AGENT),
+            experiments: experiments(&req),
+            replayed: req.headers().contains_key(REPLAYED),
+            db_queries: None,
+            db_duration: None,
+        };
+
+        let format: AccessLogFormat = self.format;
+        let sink: AccessLogSink = self.sink.clone();
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response: Response<Body> = inner.await?;
+
+            entry.status = response.status().as_u16();
+            entry.duration = started.elapsed();
+            entry.bytes = header(response.headers(), CONTENT_LENGTH)
+                .and_then(|length| length.parse().ok())
+                .or(response.body().size_hint().exact());
+
+            #[cfg(feature = "postgres")]
+            if let Some(totals) = response.extensions().get::<crate::db::QueryTotals>() {
+                entry.db_queries = Some(totals.queries);
+                entry.db_duration = Some(totals.duration);
+            }
+
+            if let Ok(value) = HeaderValue::from_str(&entry.request_id) {
+                response.headers_mut().insert(REQUEST_ID, value);
+            }
+
+            match format {
+                AccessLogFormat::Combined => sink(entry.combined()),
+                AccessLogFormat::Json => sink(entry.json()),
+                AccessLogFormat::None => {},
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{Arc, Mutex}, time::{Duration, UNIX_EPOCH}};
+
+    use axum::{body::Body, extract::Request, routing::get, Router};
+    use tower::ServiceExt;
+
+    use crate::config::AccessLogFormat;
+
+    use super::{AccessEntry, AccessLogLayer, REQUEST_ID};
+
+    fn entry() -> AccessEntry {
+        AccessEntry {
+            time: UNIX_EPOCH + Duration::from_secs(971_182_536),
+            method: "GET".to_owned(),
+            path: "/invoices?page=2".to_owned(),
+            version: "HTTP/1.1".to_owned(),
+            status: 200,
+            bytes: Some(2326),
+            duration: Duration::from_millis(12),
+            request_id: "abc".to_owned(),
+            client_ip: Some("127.0.0.1".to_owned()),
+            referer: None,
+            user_agent: Some("curl/8.0".to_owned()),
+            experiments: Some("checkout=b".to_owned()),
+            replayed: false,
+            db_queries: Some(7),
+            db_duration: Some(Duration::from_millis(12)),
+        }
+    }
+
+    #[test]
+    fn test_combined() {
+        assert_eq!(
+            entry().combined(),
+            "127.0.0.1 - - [10/Oct/2000:12:55:36 +0000] \"GET /invoices?page=2 HTTP/1.1\" 200 2326 \"-\" \"curl/8.0\" 12 abc"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let line: serde_json::Value = serde_json::from_str(&entry().json()).unwrap();
+
+        assert_eq!(line["time"], "2000-10-10T12:55:36Z");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["bytes"], 2326);
+        assert_eq!(line["request_id"], "abc");
+        assert_eq!(line["referer"], serde_json::Value::Null);
+        assert_eq!(line["experiments"], "checkout=b");
+        assert_eq!(line["db_queries"], 7);
+        assert_eq!(line["db_ms"], 12.0);
+    }
+
+    #[tokio::test]
+    async fn test_layer() {
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+
+        let router = Router::new()
+            .route("/hello", get(|| async { "hello" }))
+            .layer(AccessLogLayer::new(AccessLogFormat::Json).with_sink(move |line| sink.lock().unwrap().push(line)));
+
+        let request = Request::builder()
+            .uri("/hello")
+            .header(REQUEST_ID, "req-1")
+            .header(crate::offline::REPLAYED, "true")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()[REQUEST_ID], "req-1");
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+
+        let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["method"], "GET");
+        assert_eq!(line["path"], "/hello");
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["bytes"], 5);
+        assert_eq!(line["request_id"], "req-1");
+        assert_eq!(line["replayed"], true);
+    }
+}
@@ -1,25 +1,70 @@
-use std::{mem, str::FromStr, time::Duration, vec};
-use axum::{ response::IntoResponse, Extension, Router};
-use bb8::Pool;
-use bb8_postgres::PostgresConnectionManager;
+use std::{future::Future, io, mem, path::PathBuf, sync::{Arc, Mutex}, vec};
+use axum::{handler::Handler, http::Extensions, response::IntoResponse, Extension, Router};
 use hyper::StatusCode;
 use tokio::net::TcpListener;
 use tracing_subscriber::{layer::SubscriberExt, Registry};
-use tower::builder::ServiceBuilder;
+use tower::{builder::ServiceBuilder, Layer};
 use tower_http::{
     compression::CompressionLayer, 
     cors::CorsLayer, 
-    timeout::TimeoutLayer,
     services::ServeDir, 
     trace::TraceLayer};
 
 use crate::{
-    context::ContextLayer,
-    template::{TemplateLayer, Template},
-    db::ConnectionPool, 
-    feature::Feature, Config
+    template::Template,
+    feature::Feature, 
+    livereload::LiveReload, 
+    audit::HeaderAuditLayer, 
+    clock::{Clock, SharedClock, SystemClock}, 
+    assets::AssetManifest, 
+    methods::OptionsLayer, 
+    cache::{ResponseCache, ResponseCacheLayer}, 
+    cdn::{CdnLayer, CdnPolicy, CdnPurger, HttpPurger, NoopPurger, RouteClass}, 
+    transform::BodyTransform, 
+    logging::{FeatureSpanLayer, LogLevels}, 
+    events::EventBus, 
+    socket::Connections, 
+    onboarding::{aggregate, OnboardingItems}, 
+    takeout::{user_data_providers, UserDataProviders},
+    nav_tree::{self, nav_trees, NavTrees},
+    submenu::{feature_menus, FeatureMenus},
+    registry::{assert_unique, FeatureRegistry},
+    operations::Operations,
+    jobs::{Jobs, MemoryJobStore},
+    listen::Listening,
+    group::{GroupMount, RouteGroup, RouteGroups},
+    widget::{self, EmbedTokens},
+    embedded::TemplateSources,
+    cors::AppCorsLayer,
+    feature_config::{parse_feature_configs, FeatureConfigs},
+    a11y::live_regions,
+    head_assets::{head_assets_transform, HeadAssets},
+    diagnostics::{check_features, DiagnosticReport},
+    panic::{log_panic, CatchPanicLayer, ErrorReporter, PanicReport},
+    reload::ConfigReloader,
+    coalesce::RenderCoalescer,
+    concurrency::{sequence_meta, CriticalSections}, 
+    timeouts::RouteTimeoutLayer,
+    recording::{FileSink, MemoryRecorder, RecordingLayer},
+    locale::{hreflang, LocalizedRoutes},
+    toggle::{admin_token, FeatureHandle, FeatureToggles}, 
+    morph::{self, morph_script, MorphCheckLayer}, 
+    content::ContentOverlay, 
+    offline::{offline_meta, IdempotencyLayer}, 
+    schedule::{ScheduledJob, Scheduler, UtcOffset}, 
+    limits::LimitsLayer, 
+    access_log::AccessLogLayer, 
+    slash::TrailingSlashLayer, 
+    config::{AccessLogFormat, JobBackend, Recording, TrailingSlash}, 
+    cookies::CookieSettings, 
+    context::ContextLayer, 
+    template::TemplateLayer, Config, Context
 };
 
+// the ConnectionPool typestate, `App::connect`
+#[cfg(feature = "postgres")]
+mod postgres;
+
 #[derive(Clone)]
 pub struct NoPool;
 
@@ -33,7 +78,7 @@ pub struct App<P, F, T> where T: Template {
     config: Config,
 
     // application router
-    router: Router,
+    pub(crate) router: Router,
 
     // application template
     template: T,
@@ -45,12 +90,129 @@ pub struct App<P, F, T> where T: Template {
     // optional and only matters for Extension() on router
     // Features could use it in their handlers, but we can't know that during build.
     pub pool: P,
+
+    // time source for framework timers, swapped for a TestClock in tests
+    clock: SharedClock,
+
+    // full page post-processing, see BodyTransform
+    transforms: Vec<BodyTransform>,
+
+    // reloadable log filter, installed by `run`
+    log_levels: LogLevels,
+
+    // user router customizations applied by `build`
+    hooks: RouterHooks,
+
+    // in-process domain events, features subscribe during `build`
+    events: EventBus,
+
+    // open WebSocket connections, closed by `run` on shutdown
+    connections: Connections,
+
+    // runtime enabled flags of the features, registered by `build`
+    toggles: FeatureToggles,
+
+    // CDN purge run by `ResponseCache::invalidate_tag`
+    purger: Arc<dyn CdnPurger>,
+
+    // `Feature::schedule` jobs collected by `build`, started by `run`
+    schedule: Vec<ScheduledJob>,
+
+    // state of the work deferred with `Context::defer`
+    operations: Operations,
+
+    // every feature's route groups, recorded by `build`
+    groups: RouteGroups,
+
+    // templates embedded by features, overlaid by the app's directory
+    templates: TemplateSources,
+
+    // receives the panics caught by `CatchPanicLayer`
+    reporter: ErrorReporter,
+
+    // the safelisted config applied again on SIGHUP, see `config_file`
+    reloader: ConfigReloader,
+
+    // background job queue, handlers registered by `build`, workers started by `run`
+    jobs: Jobs,
+
+    // keys of `Context::serialize_on`
+    sections: CriticalSections,
+
+    // `run` installs the global subscriber, see `with_tracing`
+    tracing: bool,
+}
+
+type RouterHook = Arc<Mutex<Option<Box<dyn FnOnce(Router) -> Router + Send>>>>;
+
+/// `App::map_router` and `App::after_core_layers` closures, in registration order.
+#[derive(Clone, Default)]
+struct RouterHooks {
+    map_router: Vec<RouterHook>,
+    after_core_layers: Vec<RouterHook>,
+}
+
+impl RouterHooks {
+    fn hook(f: impl FnOnce(Router) -> Router + Send + 'static) -> RouterHook {
+        Arc::new(Mutex::new(Some(Box::new(f))))
+    }
+
+    fn apply(hooks: &[RouterHook], mut router: Router) -> Router {
+        for hook in hooks {
+            // closures are FnOnce, a second build skips them
+            match hook.lock().unwrap().take() {
+                Some(hook) => router = hook(router),
+                None => tracing::warn!("router hook already applied by a previous build"),
+            }
+        }
+
+        router
+    }
+
+    fn map_router(&self, router: Router) -> Router {
+        RouterHooks::apply(&self.map_router, router)
+    }
+
+    fn after_core_layers(&self, router: Router) -> Router {
+        RouterHooks::apply(&self.after_core_layers, router)
+    }
 }
 
 impl<T> App<NoPool, NoFeatures, T> where T: Template {
     pub fn new(config: Config, template: T) -> App<NoPool, NoFeatures, T> {
+        // `environment = "ephemeral"` in the file
+        let config: Config = match config.is_ephemeral() {
+            true => config.ephemeral(),
+            false => config,
+        };
+
+        let purger: Arc<dyn CdnPurger> = match config.server.cdn.purge.as_ref().and_then(HttpPurger::from_config) {
+            Some(purger) => Arc::new(purger),
+            None => Arc::new(NoopPurger),
+        };
+
+        let log_levels: LogLevels = LogLevels::from_config(&config.server.logging);
+        let reloader: ConfigReloader = ConfigReloader::new(&config, log_levels.clone());
+
         App{
+            log_levels,
             config,
+            clock: Arc::new(SystemClock),
+            transforms: Vec::new(),
+            hooks: RouterHooks::default(),
+            events: EventBus::new(),
+            connections: Connections::default(),
+            toggles: FeatureToggles::default(),
+            purger,
+            schedule: Vec::new(),
+            operations: Operations::memory(),
+            groups: RouteGroups::default(),
+            templates: TemplateSources::default(),
+            reporter: log_panic(),
+            reloader,
+            sections: CriticalSections::default(),
+            tracing: true,
+            jobs: Jobs::memory(),
             template,
             router: Router::new(),
             pool: NoPool,
@@ -59,32 +221,195 @@ impl<T> App<NoPool, NoFeatures, T> where T: Template {
     }
 }
 
-impl<T> App<NoPool, NoFeatures, T> where T: Template + 'static {
-    pub async fn connect(&mut self) -> App<ConnectionPool, NoFeatures, T> { 
-        let tokio_config = tokio_postgres::config::Config::from_str(
-            &self.config.database.connection_string()
-        )
-        .unwrap();
+impl<P, F, T> App<P, F, T> where T: Template {
+    /// Replace the clock used by framework timers, see `TestClock`.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Run without external services, for demos and integration tests (`TestClient::ephemeral`):
+    /// jobs and operations stay in memory and `run` listens on a free port, printing the URL.
+    /// Same as `environment = "ephemeral"`, call it before `build`.
+    pub fn ephemeral(mut self) -> Self {
+        self.config = self.config.clone().ephemeral();
+        self.jobs = self.jobs.clone().with_store(MemoryJobStore::default());
+        self.operations = Operations::memory();
+        self
+    }
+
+    /// Replace the CDN purger configured by `server.cdn.purge`, e.g. a vendor API client.
+    pub fn cdn_purger(mut self, purger: impl CdnPurger + 'static) -> Self {
+        self.purger = Arc::new(purger);
+        self
+    }
+
+    /// The router `build` assembled, to drive the app in process without binding a socket
+    /// (`tower::ServiceExt::oneshot`), or to nest it into another axum application.
+    pub fn into_router(self) -> Router {
+        self.router
+    }
+
+    /// `server.host:server.port`, bound by `listen`.
+    fn address(&self) -> String {
+        format!("{host}:{port}", host=self.config.server.host, port=self.config.server.port)
+    }
+
+    /// Leave the global subscriber to the application, e.g. one embedding the app next
+    /// to other services which already initialized tracing. `run` installs it by default,
+    /// the log filter endpoint then has no effect.
+    ///
+    /// A subscriber already set when `run` installs its own (by the application or an earlier
+    /// app of the process) is kept: `run` logs a warning instead of panicking.
+    pub fn with_tracing(mut self, enabled: bool) -> Self {
+        self.tracing = enabled;
+        self
+    }
+
+    /// Pretty stdout logs filtered by the reloadable log levels, unless disabled by `with_tracing`.
+    fn install_subscriber(&self) {
+        if !self.tracing {
+            return;
+        }
+
+        // tracing_subscriber::fmt::fmt().with_env_filter(EnvFilter::from_default_env()).init();
+        let stdout = tracing_subscriber::fmt::layer().pretty();
+        let subscriber = Registry::default()
+            .with(self.log_levels.layer())
+            .with(stdout);
     
-        let pg_mgr: PostgresConnectionManager<tokio_postgres::NoTls> = PostgresConnectionManager::new(tokio_config, tokio_postgres::NoTls);
-        
-        let pool: ConnectionPool = match Pool::builder()
-            .max_size(10)
-            // .min_idle(1)
-            .build(pg_mgr).await {
-                Ok(pool) => pool,
-                Err(e) => panic!("App error: {e:?}"),
-            };
+        // already installed by the application or a previous run
+        if tracing::subscriber::set_global_default(subscriber).is_err() {
+            tracing::warn!("a global subscriber is already set, the log filter endpoint has no effect");
+        }
 
-        return App{
-            config: self.config.clone(),
-            router: self.router.clone(),
-            pool,
-            features: NoFeatures,
-            template: self.template.clone()
-        };
+        // reported once there is a subscriber to see it
+        if let Err(e) = self.log_levels.validate() {
+            tracing::warn!("{e}, logging at info");
+        }
+    }
+
+    /// The collected `Feature::schedule` jobs, evaluated in `server.schedule.timezone`.
+    fn scheduler(&self) -> Scheduler {
+        let offset: UtcOffset = self.config.server.schedule.timezone.parse().unwrap_or_else(|e| {
+            tracing::warn!("{e}, scheduled jobs use UTC");
+            UtcOffset::default()
+        });
+
+        Scheduler::new(self.schedule.clone(), self.clock.clone(), offset, self.events.clone())
+            .leadership(self.jobs.store())
+    }
+
+    /// `handler` as the app's 404, wrapped like a web group: a plain request gets the page,
+    /// a boosted one the fragment.
+    fn fallback_router<H, M>(&self, handler: H) -> Router
+    where
+        H: Handler<M, ()>,
+        M: 'static,
+        T: 'static,
+    {
+        let template: TemplateLayer<T> = TemplateLayer::new(self.template.clone())
+            .live_reload(LiveReload::enabled(&self.config))
+            .transforms(self.transforms.clone());
+
+        Router::new()
+            .fallback(handler)
+            .layer(template)
+            .layer(ContextLayer::new())
+    }
+
+    /// Where `Context::defer` keeps operation state, in memory by default.
+    /// A `PostgresOperationStore` keeps it across restarts.
+    pub fn operations(mut self, operations: Operations) -> Self {
+        self.operations = operations;
+        self
+    }
+
+    /// Bounds and requester identity of `Context::serialize_on`, keys are shared by
+    /// every requester and waited on for 5 seconds by default.
+    pub fn critical_sections(mut self, sections: CriticalSections) -> Self {
+        self.sections = sections;
+        self
+    }
+
+    /// Where handler panics are reported (besides the 500 page), logged by default.
+    pub fn error_reporter(mut self, reporter: impl Fn(&PanicReport) + Send + Sync + 'static) -> Self {
+        self.reporter = Arc::new(reporter);
+        self
+    }
+
+    /// The file the config was loaded from, reloaded on SIGHUP and by `POST /_blandwork/config/reload`
+    /// (development or `server.reload.token`). Only its safelisted settings apply, see `ConfigReloader`.
+    pub fn config_file(self, path: impl Into<PathBuf>) -> Self {
+        self.reloader.set_path(path.into());
+        self
+    }
+
+    pub fn config_reloader(&self) -> &ConfigReloader {
+        &self.reloader
     }
 
+    /// The route groups of every feature with their policies, filled by `build`.
+    pub fn route_groups(&self) -> &RouteGroups {
+        &self.groups
+    }
+
+    /// Directory of the app's templates, `templates` by default. Its files replace the
+    /// templates of the same name embedded by features.
+    pub fn templates_dir(self, dir: impl Into<PathBuf>) -> Self {
+        self.templates.set_dir(dir);
+        self
+    }
+
+    /// Templates embedded by the features, overlaid by the app's directory, filled by `build`.
+    pub fn template_sources(&self) -> &TemplateSources {
+        &self.templates
+    }
+
+    /// Background job queue, shared with handlers as `Extension<Jobs>`.
+    /// Handlers of `Feature::jobs` are registered by `build`.
+    pub fn jobs(&self) -> &Jobs {
+        &self.jobs
+    }
+
+    /// In-process event bus, shared with handlers as `Extension<EventBus>`.
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Switch features on and off at runtime, handles exist once `build` ran.
+    pub fn toggles(&self) -> &FeatureToggles {
+        &self.toggles
+    }
+
+    /// WebSocket connections opened through `ws_route`.
+    pub fn connections(&self) -> &Connections {
+        &self.connections
+    }
+
+    /// Append a transform of the rendered full page HTML, they run in registration order.
+    /// `transform::minify_html` and `transform::rewrite_asset_urls` are provided.
+    pub fn body_transform(mut self, transform: impl Fn(&Context, String) -> String + Send + Sync + 'static) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// Customize the router during `build`, after every feature is merged
+    /// and before the core layers (trace, cors, compression, timeout) are applied.
+    pub fn map_router(mut self, f: impl FnOnce(Router) -> Router + Send + 'static) -> Self {
+        self.hooks.map_router.push(RouterHooks::hook(f));
+        self
+    }
+
+    /// Customize the router during `build`, right after the core layers,
+    /// layers added here wrap them but still see the framework extensions.
+    pub fn after_core_layers(mut self, f: impl FnOnce(Router) -> Router + Send + 'static) -> Self {
+        self.hooks.after_core_layers.push(RouterHooks::hook(f));
+        self
+    }
+}
+
+impl<T> App<NoPool, NoFeatures, T> where T: Template + 'static {
     pub fn register_feature_default<F: Feature + Default + 'static>(&self) ->  App<NoPool, Features, T>{         
         let features: Vec<Box<dyn Feature>> = vec![
             Box::new(F::default())
@@ -92,6 +417,23 @@ impl<T> App<NoPool, NoFeatures, T> where T: Template + 'static {
 
         return App { 
             config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             router: self.router.clone(),
             template: self.template.clone(),
             pool: NoPool,
@@ -106,6 +448,23 @@ impl<T> App<NoPool, NoFeatures, T> where T: Template + 'static {
 
         return App { 
             config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             router: self.router.clone(),
             pool: NoPool,
             template: self.template.clone(),
@@ -115,14 +474,40 @@ impl<T> App<NoPool, NoFeatures, T> where T: Template + 'static {
 }
 
 impl<T> App<NoPool, Features, T> where T: Template + 'static  {
+    /// Every problem keeping the registered features from starting (their config sections,
+    /// head assets, routes mounted twice), without building the app. For the `check` command
+    /// of an application, `DiagnosticReport::to_json` for tooling.
+    pub fn check(&self) -> DiagnosticReport {
+        check_features(&self.features, &self.config)
+    }
+
     pub fn register_feature_default<F: Feature + Default + 'static>(&mut self) ->  App<NoPool, Features, T>{
-        self.features.push(Box::new(F::default()));
+        let feature: F = F::default();
+        assert_unique(&self.features, &feature);
+        self.features.push(Box::new(feature));
 
         // relocate features into new App
         let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
 
         return App { 
             config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             router: self.router.clone(),
             pool: NoPool,
             template: self.template.clone(),
@@ -131,6 +516,7 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
     }
 
     pub fn register_feature(&mut self, feature: impl Feature + 'static) ->  App<NoPool, Features, T>{         
+        assert_unique(&self.features, &feature);
         self.features.push(Box::new(feature));
 
         // relocate features into new App
@@ -138,6 +524,23 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
 
         return App { 
             config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             router: self.router.clone(),
             pool: NoPool,
             template: self.template.clone(),
@@ -145,6 +548,7 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
         };
     }
 
+    /// A plain 404 for unmatched routes, see `apply_fallback_with` for one within the shell.
     pub fn apply_fallback(&mut self) -> App<NoPool, Features, T> {
         let mut router: Router = mem::replace(&mut self.router, Router::new());
         let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
@@ -157,6 +561,71 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
 
         return App { 
             config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            pool: NoPool,
+            template: self.template.clone(),
+            router,
+            features
+        };
+    }
+
+    /// Answer unmatched routes with `handler`, rendered through the template like a web route:
+    /// the shell around a plain request's 404, the fragment alone for a boosted one.
+    ///
+    /// ```ignore
+    /// async fn not_found() -> impl IntoResponse {
+    ///     (StatusCode::NOT_FOUND, html! { h1 { "Page not found" } a href="/" { "Home" } })
+    /// }
+    ///
+    /// App::new(config, AppTemplate).register_feature(Invoices).apply_fallback_with(not_found).build()
+    /// ```
+    pub fn apply_fallback_with<H, M>(&mut self, handler: H) -> App<NoPool, Features, T>
+    where
+        H: Handler<M, ()>,
+        M: 'static,
+    {
+        let fallback: Router = self.fallback_router(handler);
+
+        let mut router: Router = mem::replace(&mut self.router, Router::new());
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+
+        router = router.fallback_service(fallback);
+
+        return App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             pool: NoPool,
             template: self.template.clone(),
             router,
@@ -172,6 +641,23 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
 
         return App {
             config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             pool: NoPool,
             template: self.template.clone(),
             router,
@@ -183,7 +669,24 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
         let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
         
         App { 
-            config: self.config.clone(), 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             router: self.router.clone(), 
             pool: NoPool,
             features,
@@ -194,41 +697,230 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
     pub fn build(&mut self) -> App<NoPool, Features, T>{
         let mut router: Router = mem::replace(&mut self.router, Router::new());
         let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+
+        // every `[features.*]` section and every feature's scripts and styles (one per name),
+        // checked before anything is mounted, all problems reported at once
+        let (configs, head_assets): (FeatureConfigs, HeadAssets) = match (
+            parse_feature_configs(&features, &self.config),
+            HeadAssets::from_features(&features, self.config.server.assets.conflicts),
+        ) {
+            (Ok(configs), Ok(head_assets)) => (configs, head_assets),
+            (configs, head_assets) => {
+                let mut report: DiagnosticReport = DiagnosticReport::default();
+
+                if let Err(e) = configs.as_ref() {
+                    report.extend(DiagnosticReport::from(e));
+                }
+
+                if let Err(e) = head_assets.as_ref() {
+                    report.extend(DiagnosticReport::from(e));
+                }
+
+                report.fail()
+            },
+        };
+
+        for section in configs.unclaimed.iter() {
+            tracing::warn!("[features.{section}] is not claimed by any feature, is the name misspelled?");
+        }
+
+        // development only, None otherwise
+        let live_reload: Option<LiveReload> = LiveReload::from_config(&self.config);
+
+        // the offline queue is switched on by a meta tag, ahead of the application's transforms
+        let mut transforms: Vec<BodyTransform> = self.transforms.clone();
+        if self.config.server.offline.enabled {
+            transforms.insert(0, offline_meta(&self.config.server.offline));
+        }
+
+        // per target sequence of the integration script, stale responses are not swapped
+        if self.config.server.sequencing.enabled {
+            transforms.insert(0, sequence_meta());
+        }
+
+        // morph extension for `morph:` swaps, after the application's scripts
+        if self.config.server.morph.enabled {
+            transforms.push(morph_script());
+        }
+
+        // hreflang alternates of translated pages
+        transforms.push(hreflang());
+
+        // head assets of full pages, fragments get theirs from the context layer
+        if !head_assets.is_empty() {
+            transforms.push(head_assets_transform(head_assets.clone()));
+        }
+
+        // live regions of the announcements, once the page is otherwise complete
+        if self.config.server.announcements.live_regions {
+            transforms.push(live_regions());
+        }
+        let localized: LocalizedRoutes = LocalizedRoutes::default();
+
+        // every feature's getting started steps, for the checklist
+        let onboarding: OnboardingItems = aggregate(&features);
+
+        // every feature's user data, for the takeout export
+        let user_data: UserDataProviders = user_data_providers(&features);
+
+        // every feature's navigation tree, for the shell and the expansion fragments
+        let nav_trees: NavTrees = nav_trees(&features);
+
+        // every feature's top-level link and submenu, for the shell
+        let menus: FeatureMenus = feature_menus(&features);
+
+        // every feature's routers, built once, mounted below
+        let feature_groups: Vec<Vec<RouteGroup>> = features.iter().map(|feature| feature.groups()).collect();
+
+        // every feature and its entry route, for status pages
+        let registry: FeatureRegistry = FeatureRegistry::from_groups(&features, &feature_groups);
+
+        // CDN header rules, None when disabled
+        let cdn: Option<CdnPolicy> = self.config.server.cdn.enabled
+            .then(|| CdnPolicy::live(self.reloader.cdn(), self.config.server.assets.prefix.clone()));
     
-        // 1. scan features and extract links for navigator
-        for feature in features.into_iter() {
-            self.template.register(&feature);
+        // verifies the tokens of widget urls, every widget request is refused without a key
+        let embed_tokens: Option<EmbedTokens> = self.config.server.widgets.key.as_ref()
+            .map(|key| EmbedTokens::new(key.as_bytes()).shared_clock(self.clock.clone()));
 
-            router = match feature.api() {
-                Some(mut api) => {
-                    api = api.layer(ContextLayer::new());
+        // 1. scan features and extract links for navigator, every page's shell links to every feature
+        for feature in features.iter() {
+            self.template.register(feature);
+        }
 
-                    router.merge(api)
-                }, 
-                None => router
-            };
+        // jobs run on the app's clock, a `TestClock` drives their retries
+        self.jobs = self.jobs.clone().shared_clock(self.clock.clone());
 
-            router = match feature.supplemental() {
-                Some(mut supp) => {
-                    supp = supp
-                        .layer(ContextLayer::new());
-                    
-                    router.merge(supp)
-                }, 
-                None => router
+        // 2. scan features and apply routers
+        for ((feature, config), mut groups) in features.into_iter().zip(configs.apply.iter()).zip(feature_groups) {
+            feature.subscribe(&self.events);
+            self.templates.register(&feature.name(), feature.templates_source());
+            self.schedule.extend(feature.schedule());
+
+            for handler in feature.jobs() {
+                self.jobs.register(handler);
+            }
+
+            let span: FeatureSpanLayer = FeatureSpanLayer::new(&feature.name());
+
+            // runtime switch, outermost so a disabled feature does no work
+            let toggle: FeatureHandle = self.toggles.register(&feature.name(), feature.link().map(|link| link.route));
+
+            // translated variants and lazily built routers are web routes like any other
+            let mut web: Option<Router> = None;
+
+            if let Some(lazy) = feature.lazy() {
+                let prefix: String = lazy.prefix().to_owned();
+                web = Some(Router::new().nest_service(&prefix, lazy));
+            }
+
+            for route in feature.localized() {
+                localized.register(&route);
+                web = Some(web.unwrap_or_else(Router::new).merge(route.router()));
+
+                if let Some(redirect) = route.redirect() {
+                    router = router.merge(redirect);
+                }
+            }
+
+            if let Some(web) = web {
+                groups.push(RouteGroup::web("web", web));
+            }
+
+            let mount: GroupMount<T> = GroupMount {
+                template: &self.template,
+                live_reload: live_reload.is_some(),
+                transforms: &transforms,
+                cdn: cdn.as_ref(),
+                span: &span,
+                toggle: &toggle,
+                toggle_redirect: self.config.server.toggles.redirect.clone(),
+                noindex: feature.noindex(),
+                widgets: &self.config.server.widgets,
+                embed_tokens: embed_tokens.as_ref(),
+                cors: feature.cors(),
             };
 
-            router = match feature.web() {
-                Some(mut web) => {
-                    web = web
-                        .layer(TemplateLayer::new(self.template.clone()))
-                        .layer(ContextLayer::new());
-                    
-                    router.merge(web)
-                }, 
-                None => router
-            };
+            for group in groups {
+                router = router.merge(FeatureConfigs::layer(config.as_ref(), self.groups.mount(&feature.name(), group, &mount)));
+            }
+        }
+
+        if let Some(live_reload) = live_reload {
+            router = router.merge(live_reload.router());
+        }
+
+        // browser copies of bridged events
+        router = router.merge(self.events.router());
+
+        // status of operations deferred by handlers
+        router = router.merge(self.operations.router());
+
+        // loader of embedded widgets
+        if embed_tokens.is_some() {
+            router = router.merge(widget::router());
         }
+
+        // children of navigation tree nodes
+        if !nav_trees.is_empty() {
+            router = router.merge(nav_tree::router(nav_trees.clone()));
+        }
+
+        // feature switches, only with an admin token
+        if let Some(token) = admin_token("server.toggles.token", self.config.server.toggles.token.as_deref()) {
+            router = router.merge(self.toggles.router(token));
+        }
+
+        // dead letters of the job queue, only with an admin token
+        if let Some(token) = admin_token("server.jobs.token", self.config.server.jobs.token.as_deref()) {
+            router = router.merge(self.jobs.router(token));
+        }
+
+        if self.config.server.morph.enabled {
+            router = router.merge(morph::router());
+
+            // development only, ids of morphed fragments
+            if self.config.is_development() {
+                router = router.layer(MorphCheckLayer);
+            }
+        }
+
+        // runtime log levels, unauthenticated in development, bearer protected otherwise
+        let logging_token: Option<&str> = admin_token("server.logging.token", self.config.server.logging.token.as_deref());
+        if self.config.is_development() || logging_token.is_some() {
+            router = router.merge(self.log_levels.router(logging_token));
+        }
+
+        let reload_token: Option<&str> = admin_token("server.reload.token", self.config.server.reload.token.as_deref());
+        if self.config.is_development() || reload_token.is_some() {
+            router = router.merge(self.reloader.router(reload_token));
+        }
+
+        // shared response cache, handlers evict tags through the extension
+        // (which also purges the CDN, so it's provided to CDN only setups too)
+        if self.config.server.cache.enabled || self.config.server.cdn.enabled {
+            let cache: ResponseCache = ResponseCache::new(self.config.server.cache.clone(), self.clock.clone())
+                .with_purger(self.purger.clone());
+
+            if self.config.server.cache.enabled {
+                router = router.layer(ResponseCacheLayer::new(cache.clone()));
+            }
+
+            router = router.layer(Extension(cache));
+        }
+
+        // content directories, overlaid roots answer in order
+        for mount in self.config.server.content.iter() {
+            router = router.nest_service(&mount.path, ContentOverlay::from_config(mount));
+        }
+
+        // CDN headers of the asset mounts, feature routers got theirs above
+        if let Some(cdn) = cdn.as_ref() {
+            router = router.layer(CdnLayer::new(cdn.clone(), RouteClass::Static));
+        }
+
+        // escape hatch, every route is merged and no core layer applied yet
+        router = self.hooks.map_router(router);
     
         router = router
 
@@ -255,17 +947,126 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
                     //     format!("ERROR {:#?}", e)
                     //     )
                     // }))
-                
-                    .layer(TraceLayer::new_for_http())
-                    
-                    // Vanilla middleware
-                    .layer(CorsLayer::new())
-                    .layer(CompressionLayer::new())
-                    .layer(TimeoutLayer::new(Duration::from_secs(10)))
             );
 
+        // vanilla middleware, innermost first, each can be left to a parent application
+        let core = &self.config.server.core_layers;
+
+        if core.catch_panic {
+            router = router.layer(CatchPanicLayer::new(self.template.clone()).reporter(self.reporter.clone()));
+        }
+
+        if let Some(timeouts) = RouteTimeoutLayer::from_config(&self.config.server).filter(|_| core.timeout) {
+            router = router.layer(timeouts);
+        }
+
+        if core.compression {
+            router = router.layer(CompressionLayer::new());
+        }
+
+        if core.cors {
+            router = router.layer(AppCorsLayer::new(CorsLayer::new()));
+        }
+
+        if core.trace {
+            router = router.layer(TraceLayer::new_for_http());
+        }
+
+        // escape hatch, layers added here still see the framework extensions
+        router = self.hooks.after_core_layers(router);
+
+        router = router
+
+            // framework time source, event bus, onboarding items, asset urls, cookie settings
+            .layer(Extension(self.clock.clone()))
+            .layer(Extension(self.events.clone()))
+            .layer(Extension(self.connections.clone()))
+            .layer(Extension(self.toggles.clone()))
+            .layer(Extension(Arc::new(self.config.server.theme.clone())))
+            .layer(Extension(Arc::new(self.config.server.shells.clone())))
+            .layer(Extension(localized))
+            .layer(Extension(self.reloader.flags()))
+            .layer(Extension(onboarding))
+            .layer(Extension(user_data))
+            .layer(Extension(nav_trees))
+            .layer(Extension(menus))
+            .layer(Extension(registry))
+            .layer(Extension(self.operations.clone()))
+            .layer(Extension(self.jobs.clone()))
+            .layer(Extension(self.sections.clone()))
+            .layer(Extension(self.groups.clone()))
+            .layer(Extension(self.templates.clone()))
+            .layer(Extension(configs.settings.clone()))
+            .layer(Extension(head_assets.clone()))
+            .layer(Extension(RenderCoalescer::default()))
+            .layer(Extension(Arc::new(AssetManifest::load(&self.config.server.assets))))
+            .layer(Extension(Arc::new(CookieSettings::from_config(&self.config.server.cookies))))
+            .layer(Extension(Arc::new(self.config.server.morph.clone())))
+            .layer(Extension(Arc::new(self.config.server.announcements.clone())))
+            .layer(Extension(Arc::new(self.config.server.triggers.clone())))
+            .layer(Extension(Arc::new(self.config.server.sockets.clone())));
+
+        // replayed offline submissions are processed once
+        if self.config.server.offline.enabled {
+            router = router.layer(IdempotencyLayer::new(self.config.server.offline.idempotency_ttl).with_clock(self.clock.clone()));
+        }
+
+        // sanitized copies of sampled requests for replaying bugs, see `test::replay`
+        if self.config.server.recording.enabled {
+            let recording: &Recording = &self.config.server.recording;
+            let recorder: MemoryRecorder = MemoryRecorder::new(recording.capacity);
+            let mut layer: RecordingLayer = RecordingLayer::new(recording.clone(), self.config.is_development())
+                .sink(Arc::new(recorder.clone()));
+
+            if let Some(dir) = recording.dir.as_deref() {
+                layer = layer.sink(Arc::new(FileSink::new(dir)));
+            }
+
+            router = router.layer(Extension(recorder)).layer(layer);
+        }
+
+        // reject oversized requests before any other work
+        router = router.layer(LimitsLayer::live(self.reloader.limits()));
+
+        // one line per request, including the rejected ones
+        if self.config.server.access_log.format != AccessLogFormat::None {
+            router = router.layer(AccessLogLayer::new(self.config.server.access_log.format));
+        }
+
+        // development only, outermost so it inspects the final response
+        if self.config.is_development() && self.config.server.header_audit.enabled {
+            router = router.layer(HeaderAuditLayer::new(self.config.server.header_audit.clone()));
+        }
+
+        // OPTIONS answered from routing's 405, whose `Allow` is only set once the route has
+        // answered, HEAD is built like GET and measured by axum's routes
+        router = Router::new().fallback_service(OptionsLayer.layer(router));
+
+        // wraps the finished router, a Router::layer would run after routing
+        if self.config.server.trailing_slash != TrailingSlash::Strict {
+            let normalized = TrailingSlashLayer::new(self.config.server.trailing_slash).layer(router);
+            router = Router::new().fallback_service(normalized);
+        }
+
         return App {
             config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
             pool: self.pool.clone(),
             template: self.template.clone(),
             features: Vec::new(),
@@ -273,248 +1074,423 @@ impl<T> App<NoPool, Features, T> where T: Template + 'static  {
         };
     }
 
-    pub async fn run(&mut self) {
-        let listener: TcpListener = TcpListener::bind(format!("{host}:{port}", host=self.config.server.host, port=self.config.server.port))
-            .await
-            .unwrap();
-        
-        // tracing_subscriber::fmt::fmt().with_env_filter(EnvFilter::from_default_env()).init();
-        let stdout = tracing_subscriber::fmt::layer().pretty();
-        let subscriber = Registry::default().with(stdout);
-    
-        tracing::subscriber::set_global_default(subscriber)
-            .expect("Unable to set global subscriber");
-        
-        axum::serve(listener, self.router.clone()).await.unwrap();
+    /// Binds `server.host:server.port` without serving yet, `port = 0` picks a free port.
+    /// For tests and embedding, `run` also installs the log subscriber and reports bind errors.
+    pub async fn listen(&mut self) -> io::Result<Listening> {
+        let listener: TcpListener = TcpListener::bind(self.address()).await?;
+
+        let mut extensions: Extensions = Extensions::new();
+        extensions.insert(self.events.clone());
+
+        Listening::new(
+            listener,
+            self.router.clone(),
+            self.connections.clone(),
+            self.scheduler(),
+            self.jobs.clone(),
+            self.config.server.jobs.clone(),
+            extensions,
+        )
     }
-}
 
-impl<T> App<ConnectionPool, NoFeatures, T>  where T: Template + 'static  {
-    pub fn register_feature_default<F: Feature + Default + 'static>(&self) ->  App<ConnectionPool, Features, T>{         
-        let features: Vec<Box<dyn Feature + 'static>> = vec![
-            Box::new(F::default())
-        ];
+    /// Serves until SIGINT or SIGTERM, requests in flight are answered before returning.
+    pub async fn run(self) {
+        self.run_with_shutdown(std::future::pending()).await
+    }
 
-        return App { 
-            config: self.config.clone(),
-            router: self.router.clone(),
-            pool: self.pool.clone(),
-            template: self.template.clone(),
-            features,
+    /// `run`, also stopping once `shutdown` completes, e.g. a management endpoint's signal.
+    pub async fn run_with_shutdown(mut self, shutdown: impl Future<Output = ()> + Send + 'static) {
+        let listening: Listening = match self.listen().await {
+            Ok(listening) => listening,
+            Err(e) => DiagnosticReport::bind(&self.address(), &e).fail(),
         };
+
+        self.install_subscriber();
+
+        // the port may be picked by the system, the only way to find an ephemeral demo
+        tracing::info!("listening on http://{}", listening.local_addr());
+        tracing::info!("log filter: {}", self.log_levels.filter());
+
+        self.reloader.listen_for_hangup();
+
+        if self.config.server.jobs.backend == JobBackend::Postgres {
+            tracing::warn!("server.jobs.backend is postgres but the app is not connected, jobs are kept in memory");
+        }
+
+        // scheduled and queued jobs in progress finish before returning
+        if let Err(e) = listening.serve_until(shutdown).await {
+            tracing::error!("server error: {e}");
+        }
     }
+}
 
-    pub fn register_feature(&self, feature: impl Feature + 'static) ->  App<ConnectionPool, Features, T>{         
-        let features: Vec<Box<dyn Feature + 'static>> = vec![
-            Box::new(feature)
-        ];
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
 
-        return App { 
-            config: self.config.clone(),
-            router: self.router.clone(),
-            pool: self.pool.clone(),
-            template: self.template.clone(),
-            features,
-        };
+    use axum::{
+        body::{to_bytes, Body}, extract::{Path, Request}, http::HeaderValue, 
+        middleware::map_response, response::Response, routing::get, Router
+    };
+    use hyper::{header::{ACCEPT_ENCODING, ALLOW, CONTENT_ENCODING, CONTENT_LENGTH, LOCATION}, Method, StatusCode};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, test::router, App, Config, Context, Feature, JobHandler, RouteTimeout, Template, TrailingSlash};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
     }
 
-    pub fn template<F: Template + 'static>(&mut self, template: T) -> App<NoPool, NoFeatures, T> {
-        App { 
-            config: self.config.clone(), 
-            router: self.router.clone(), 
-            pool: NoPool,
-            features: NoFeatures,
-            template,
+    struct TestFeature;
+
+    impl Feature for TestFeature {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/test/web", get(|| async { html! { p { "hello" } } })))
         }
     }
 
-}
+    fn app() -> Router {
+        App::new(Config::default(), TestTemplate)
+            .register_feature(TestFeature)
+            .build()
+            .into_router()
+    }
 
-impl<T> App<ConnectionPool, Features, T> where T: Template + 'static  {
-    pub fn register_feature_default<F: Feature + Default + 'static>(&mut self) ->  App<ConnectionPool, Features, T>{
-        self.features.push(Box::new(F::default()));
+    async fn send(method: Method) -> axum::response::Response {
+        let request = Request::builder()
+            .method(method)
+            .uri("/test/web")
+            .body(Body::empty())
+            .unwrap();
 
-        // relocate features into new App
-        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+        app().oneshot(request).await.unwrap()
+    }
 
-        return App { 
-            config: self.config.clone(),
-            router: self.router.clone(),
-            pool: self.pool.clone(),
-            template: self.template.clone(),
-            features,
+    #[tokio::test]
+    async fn test_head_measures_the_page() {
+        let get = send(Method::GET).await;
+        let page = to_bytes(get.into_body(), usize::MAX).await.unwrap();
+
+        let head = send(Method::HEAD).await;
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(head.headers().get(CONTENT_LENGTH).unwrap(), page.len().to_string().as_str());
+
+        let body = to_bytes(head.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_head_of_a_cached_page_matches_get() {
+        let mut config = Config::default();
+        config.server.cache.enabled = true;
+
+        let router = App::new(config, TestTemplate).register_feature(TestFeature).build().into_router();
+        let request = |method: Method| Request::builder().method(method).uri("/test/web").body(Body::empty()).unwrap();
+
+        let get = router.clone().oneshot(request(Method::GET)).await.unwrap();
+        let page = to_bytes(get.into_body(), usize::MAX).await.unwrap();
+
+        let head = router.oneshot(request(Method::HEAD)).await.unwrap();
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(head.headers()[CONTENT_LENGTH], page.len().to_string());
+
+        let body = to_bytes(head.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_options_lists_allowed_methods() {
+        let response = send(Method::OPTIONS).await;
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let allow = response.headers()[ALLOW].to_str().unwrap();
+        assert!(allow.contains("GET"));
+        assert!(allow.contains("HEAD"));
+        assert!(allow.contains("OPTIONS"));
+    }
+
+    #[tokio::test]
+    async fn test_body_transforms_run_in_order_on_full_pages() {
+        let app = App::new(Config::default(), TestTemplate)
+            .register_feature(TestFeature)
+            .body_transform(|_, html| html.replace("hello", "hi"))
+            .body_transform(|_, html| html.replace("hi", "hey"))
+            .build();
+
+        let request = Request::builder().uri("/test/web").body(Body::empty()).unwrap();
+        let page = router(&app).oneshot(request).await.unwrap();
+        let page = to_bytes(page.into_body(), usize::MAX).await.unwrap();
+
+        assert_eq!(page, "<html><body><p>hey</p></body></html>");
+
+        // boosted fragments are sent untouched
+        let request = Request::builder()
+            .uri("/test/web")
+            .header("HX-Request", "true")
+            .header("HX-Boosted", "true")
+            .body(Body::empty())
+            .unwrap();
+        let fragment = router(&app).oneshot(request).await.unwrap();
+        let fragment = to_bytes(fragment.into_body(), usize::MAX).await.unwrap();
+
+        assert_eq!(fragment, "<p>hello</p>");
+    }
+
+    #[tokio::test]
+    async fn test_core_layers_can_be_disabled() {
+        let send = |compression: bool| async move {
+            let mut config = Config::default();
+            config.server.core_layers.compression = compression;
+
+            let app = App::new(config, TestTemplate).register_feature(TestFeature).build();
+            let request = Request::builder()
+                .uri("/test/web")
+                .header(ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap();
+
+            router(&app).oneshot(request).await.unwrap()
         };
+
+        assert_eq!(send(true).await.headers()[CONTENT_ENCODING], "gzip");
+        assert!(send(false).await.headers().get(CONTENT_ENCODING).is_none());
     }
 
-    pub fn register_feature(&mut self, feature: impl Feature + 'static) ->  App<ConnectionPool, Features, T>{         
-        self.features.push(Box::new(feature));
+    #[tokio::test]
+    async fn test_run_with_shutdown() {
+        let mut config = Config::default();
+        config.server.host = "127.0.0.1".to_owned();
+        config.server.port = 0;
 
-        // relocate features into new App
-        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+        let app = App::new(config, TestTemplate).register_feature(TestFeature).build();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
 
-        return App { 
-            config: self.config.clone(),
-            router: self.router.clone(),
-            pool: self.pool.clone(),
-            template: self.template.clone(),
-            features,
+        // on this task, the app's features aren't Send
+        let run = app.run_with_shutdown(async { let _ = stopped.await; });
+        let signal = async {
+            tokio::task::yield_now().await;
+            stop.send(()).unwrap();
         };
+
+        // returns instead of serving forever
+        tokio::time::timeout(Duration::from_secs(5), async { tokio::join!(run, signal) }).await.unwrap();
     }
 
-    pub fn apply_fallback(&mut self) -> App<ConnectionPool, Features, T> {
-        let mut router: Router = mem::replace(&mut self.router, Router::new());
-        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+    #[tokio::test]
+    async fn test_two_apps_in_one_process() {
+        let mut config = Config::default();
+        config.server.host = "127.0.0.1".to_owned();
+        config.server.port = 0;
+
+        // the second default app finds the first one's subscriber, the last leaves it alone
+        let apps = [
+            App::new(config.clone(), TestTemplate).register_feature(TestFeature).build(),
+            App::new(config.clone(), TestTemplate).register_feature(TestFeature).build(),
+            App::new(config, TestTemplate).with_tracing(false).register_feature(TestFeature).build(),
+        ];
 
-        async fn handler_404() -> impl IntoResponse {
-            (StatusCode::NOT_FOUND, "nothing to see here")
+        // on this task, the app's features aren't Send
+        for app in apps {
+            tokio::time::timeout(Duration::from_secs(5), app.run_with_shutdown(async {})).await.unwrap();
         }
+    }
 
-        router = router.fallback(handler_404);
+    struct ReportFeature;
 
-        return App { 
-            config: self.config.clone(),
-            pool: self.pool.clone(),
-            template: self.template.clone(),
-            router,
-            features
+    impl Feature for ReportFeature {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/reports/:secs", get(|Path(secs): Path<u64>| async move {
+                tokio::time::sleep(Duration::from_secs(secs)).await;
+                html! { "done" }
+            })))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_timeout() {
+        let send = |timeout: u64, path: &'static str| async move {
+            let mut config = Config::default();
+            config.server.request_timeout_secs = timeout;
+            config.server.route_timeouts = vec![RouteTimeout { route: "/reports/90*".to_owned(), timeout: Duration::from_secs(60) }];
+
+            let app = App::new(config, TestTemplate).register_feature(ReportFeature).build();
+            router(&app).oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap().status()
         };
+
+        assert_eq!(send(30, "/reports/45").await, StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(send(30, "/reports/15").await, StatusCode::OK);
+
+        // disabled, the report runs as long as it takes unless its route has a timeout
+        assert_eq!(send(0, "/reports/600").await, StatusCode::OK);
+        assert_eq!(send(0, "/reports/900").await, StatusCode::REQUEST_TIMEOUT);
     }
 
-    pub fn apply_extension<S: Clone + Send + Sync + 'static>(&mut self, state: S) -> App<ConnectionPool, Features, T> {
-        let mut router: Router = mem::replace(&mut self.router, Router::new());
-        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
-        
-        router = router.layer(Extension(state));
+    #[tokio::test]
+    async fn test_disabled_feature() {
+        let app = App::new(Config::default(), TestTemplate).register_feature(TestFeature).build();
+        let send = || router(&app).oneshot(Request::builder().uri("/test/web").body(Body::empty()).unwrap());
 
-        return App {
-            config: self.config.clone(),
-            pool: self.pool.clone(),
-            template: self.template.clone(),
-            router,
-            features,
+        assert_eq!(send().await.unwrap().status(), StatusCode::OK);
+
+        assert!(app.toggles().set("TestFeature", false));
+        assert_eq!(send().await.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        app.toggles().handle("TestFeature").unwrap().enable();
+        assert_eq!(send().await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_modes() {
+        let send = |mode: TrailingSlash, uri: &'static str| async move {
+            let mut config = Config::default();
+            config.server.trailing_slash = mode;
+
+            let app = App::new(config, TestTemplate).register_feature(TestFeature).build();
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+            router(&app).oneshot(request).await.unwrap()
         };
+
+        assert_eq!(send(TrailingSlash::Strict, "/test/web/").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(send(TrailingSlash::Strict, "/test/web").await.status(), StatusCode::OK);
+
+        let redirect = send(TrailingSlash::Redirect, "/test/web/?page=2").await;
+        assert_eq!(redirect.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(redirect.headers()[LOCATION], "/test/web?page=2");
+
+        // never a protocol relative url to another host
+        let redirect = send(TrailingSlash::Redirect, "//evil.com/").await;
+        assert_eq!(redirect.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(redirect.headers()[LOCATION], "/evil.com");
+
+        let ignored = send(TrailingSlash::Ignore, "/test/web/").await;
+        assert_eq!(ignored.status(), StatusCode::OK);
+        let body = to_bytes(ignored.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("<p>hello</p>"));
     }
 
-    pub fn template<F: Template + 'static>(&mut self, template: T) -> App<ConnectionPool, Features, T> {
-        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
-        
-        App { 
-            config: self.config.clone(), 
-            router: self.router.clone(), 
-            pool: self.pool.clone(),
-            features,
-            template,
+    struct AdminFeature;
+
+    impl Feature for AdminFeature {
+        fn noindex(&self) -> bool {
+            true
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/admin", get(|| async { html! { p { "admin" } } })))
         }
     }
 
-    pub fn build(&mut self) -> App<ConnectionPool, Features, T>{
-        let mut router: Router = mem::replace(&mut self.router, Router::new());
-        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
-    
-        // 1. scan features and extract links for navigator
-        // for feature in features.iter() {
-        //     self.layout.register(feature)
-        // };
+    #[tokio::test]
+    async fn test_noindex_feature() {
+        let app = App::new(Config::default(), TestTemplate)
+            .register_feature(TestFeature)
+            .register_feature(AdminFeature)
+            .build();
 
-        // 2. scan features and apply routers
-        for feature in features.iter() {
-            router = match feature.api() {
-                Some(mut api) => {
-                    api = api.layer(ContextLayer::new());
+        let admin = router(&app).oneshot(Request::builder().uri("/admin").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(admin.status(), StatusCode::OK);
+        assert_eq!(admin.headers()["x-robots-tag"], "noindex");
 
-                    router.merge(api)
-                }, 
-                None => router
-            };
+        let public = router(&app).oneshot(Request::builder().uri("/test/web").body(Body::empty()).unwrap()).await.unwrap();
+        assert!(public.headers().get("x-robots-tag").is_none());
+    }
 
-            router = match feature.supplemental() {
-                Some(mut supp) => {
-                    supp = supp
-                        .layer(ContextLayer::new());
-                    
-                    router.merge(supp)
-                }, 
-                None => router
-            };
+    #[tokio::test]
+    async fn test_router_hooks() {
+        let app = App::new(Config::default(), TestTemplate)
+            .register_feature(TestFeature)
+            .map_router(|router| router.route("/hooked", get(|| async { "hooked" })))
+            .after_core_layers(|router| router.layer(map_response(|mut response: Response| async {
+                response.headers_mut().insert("x-hooked", HeaderValue::from_static("1"));
+                response
+            })))
+            .build();
+
+        for uri in ["/test/web", "/hooked"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            let response = router(&app).oneshot(request).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["x-hooked"], "1");
+        }
+    }
 
-            router = match feature.web() {
-                Some(mut web) => {
-                    web = web
-                        .layer(TemplateLayer::new(self.template.clone()))
-                        .layer(ContextLayer::new());
-                       
-                    router.merge(web)
-                }, 
-                None => router
-            };
+    #[tokio::test]
+    async fn test_fallback_is_plain() {
+        let app = App::new(Config::default(), TestTemplate)
+            .register_feature(TestFeature)
+            .apply_fallback()
+            .build()
+            .into_router();
+
+        let request = Request::get("/api/missing").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"nothing to see here");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_with() {
+        async fn not_found() -> (StatusCode, Markup) {
+            (StatusCode::NOT_FOUND, html! { h1 { "Page not found" } })
         }
-    
-        router = router
 
-            // web assets (css, javascript, etc)
-            // .nest_service("/web", ServeDir::new(self.config.server.asset_path.clone()))
-            
-            // core layers
-            .layer(
-                ServiceBuilder::new()
-                
-                    // build a layer for handling HTMX templating
-                    // requirements
-                        // define navigator (remove from extension)
-                        // handle boost/non-boosted request
-                    
-                    // raw handlers only need to return
+        let app = App::new(Config::default(), TestTemplate)
+            .register_feature(TestFeature)
+            .apply_fallback_with(not_found)
+            .build()
+            .into_router();
 
-                    // requires more finesse
-                    // https://docs.rs/axum/latest/axum/error_handling/index.html
+        let request = Request::get("/missing").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
 
-                    // .layer(HandleErrorLayer::new(|m: Method, u: Uri, e: BoxError| async {
-                    //     (
-                    //     hyper::StatusCode::REQUEST_TIMEOUT,
-                    //     format!("ERROR {:#?}", e)
-                    //     )
-                    // }))
-                
-                    .layer(TraceLayer::new_for_http())
-                    
-                    // Vanilla middleware
-                    .layer(CorsLayer::new())
-                    .layer(CompressionLayer::new())
-                    .layer(TimeoutLayer::new(Duration::from_secs(10)))
-                        
-            )
-
-            // base extensions (database connection)
-            .layer(Extension(self.pool.clone()));
-            
-            // others? Feature specific data/configurations?
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.starts_with(b"<html><body><h1>Page not found</h1>"));
 
-        return App {
-            config: self.config.clone(),
-            pool: self.pool.clone(),
-            template: self.template.clone(),
-            features,
-            router,
-        };
+        let request = Request::get("/missing")
+            .header("HX-Request", "true")
+            .header("HX-Boosted", "true")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "<h1>Page not found</h1>");
     }
 
-    pub async fn run(&mut self) {
-        let listener: TcpListener = TcpListener::bind(format!("{host}:{port}", host=self.config.server.host, port=self.config.server.port))
-            .await
-            .unwrap();
-        
-        // tracing_subscriber::fmt::fmt().with_env_filter(EnvFilter::from_default_env()).init();
-        let stdout = tracing_subscriber::fmt::layer().pretty();
-        let subscriber = Registry::default().with(stdout);
-    
-        tracing::subscriber::set_global_default(subscriber)
-            .expect("Unable to set global subscriber");
-        
-        axum::serve(listener, self.router.clone()).await.unwrap();
+    struct ExportFeature;
+
+    impl Feature for ExportFeature {
+        fn jobs(&self) -> Vec<JobHandler> {
+            vec![JobHandler::new("export", |_| async { Ok(()) })]
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
+    #[tokio::test]
+    async fn test_jobs_use_app_clock() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000);
 
+        let app = App::new(Config::default(), TestTemplate)
+            .with_clock(TestClock::at(start))
+            .register_feature(ExportFeature)
+            .build();
+
+        app.jobs().enqueue("export", &()).await.unwrap();
+
+        // queued at the test clock's time, not the system's
+        let claimed = app.jobs().store().claim("worker", start, start, 10).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+    }
 }
@@ -0,0 +1,981 @@
+use std::{future::Future, io, mem, str::FromStr, sync::Arc, time::Duration};
+use axum::{handler::Handler, http::Extensions, response::IntoResponse, Extension, Router};
+use bb8::Builder;
+use bb8_postgres::PostgresConnectionManager;
+use hyper::StatusCode;
+use tokio::net::TcpListener;
+use tower::{builder::ServiceBuilder, Layer};
+use tower_http::{
+    compression::CompressionLayer, 
+    cors::CorsLayer, 
+    trace::TraceLayer};
+
+use crate::{
+    template::Template,
+    db::{self, ConnectError, ConnectionPool, QueryLogger}, 
+    feature::Feature, 
+    livereload::LiveReload, 
+    audit::HeaderAuditLayer, 
+    assets::AssetManifest, 
+    methods::OptionsLayer, 
+    cache::{ResponseCache, ResponseCacheLayer}, 
+    cdn::{CdnLayer, CdnPolicy, RouteClass}, 
+    logging::FeatureSpanLayer, 
+    limits::LimitsLayer, 
+    access_log::AccessLogLayer, 
+    onboarding::{aggregate, OnboardingItems}, 
+    takeout::{user_data_providers, UserDataProviders},
+    nav_tree::{self, nav_trees, NavTrees},
+    submenu::{feature_menus, FeatureMenus},
+    registry::{assert_unique, FeatureRegistry},
+    group::{GroupMount, RouteGroup},
+    jobs::{Jobs, PostgresJobStore},
+    listen::Listening,
+    widget::{self, EmbedTokens},
+    coalesce::RenderCoalescer,
+    cors::AppCorsLayer,
+    feature_config::{parse_feature_configs, FeatureConfigs},
+    a11y::live_regions,
+    head_assets::{head_assets_transform, HeadAssets},
+    diagnostics::{check_features, DiagnosticReport},
+    panic::CatchPanicLayer,
+    concurrency::sequence_meta, 
+    timeouts::RouteTimeoutLayer,
+    recording::{FileSink, MemoryRecorder, RecordingLayer},
+    locale::{hreflang, LocalizedRoutes},
+    toggle::{admin_token, FeatureHandle}, 
+    morph::{self, morph_script, MorphCheckLayer}, 
+    content::ContentOverlay, 
+    offline::{offline_meta, IdempotencyLayer}, 
+    transform::BodyTransform, 
+    slash::TrailingSlashLayer, 
+    config::{AccessLogFormat, Database, JobBackend, Recording, TrailingSlash, Warmup}, 
+    cookies::CookieSettings
+};
+
+use super::{App, Features, NoFeatures, NoPool};
+
+impl<T> App<NoPool, NoFeatures, T> where T: Template + 'static {
+    /// The app with its database pool. Fails instead of panicking when the database can't be
+    /// reached, to retry with a backoff or start degraded.
+    ///
+    /// ```ignore
+    /// #[tokio::main]
+    /// async fn main() -> Result<(), ConnectError> {
+    ///     App::new(Config::default(), VanillaTemplate::default())
+    ///         .connect().await?
+    ///         .register_feature_default::<InvoicesFeature>()
+    ///         .build()
+    ///         .run().await;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn connect(&mut self) -> Result<App<ConnectionPool, NoFeatures, T>, ConnectError> {
+        let tokio_config = tokio_postgres::config::Config::from_str(&self.config.database.connection_string())
+            .map_err(ConnectError::Config)?;
+    
+        let pg_mgr: PostgresConnectionManager<tokio_postgres::NoTls> = PostgresConnectionManager::new(tokio_config, tokio_postgres::NoTls);
+        
+        let database: &Database = &self.config.database;
+        let warmup: &Warmup = &database.warmup;
+
+        // a warmup keeps at least its connections open, bb8 refuses a pool smaller than min_idle
+        let min_idle: Option<u32> = match warmup.enabled {
+            true => Some(database.pool_min_idle.unwrap_or(0).max(warmup.min_idle)),
+            false => database.pool_min_idle,
+        };
+
+        let builder: Builder<PostgresConnectionManager<tokio_postgres::NoTls>> = db::pool_builder(&database.pool)
+            .max_size(database.pool_max_size.max(min_idle.unwrap_or(0)).max(1))
+            .min_idle(min_idle)
+            .connection_timeout(database.pool_connection_timeout.max(Duration::from_millis(1)));
+
+        let pool: ConnectionPool = match warmup.enabled {
+            true => builder.build_unchecked(pg_mgr),
+            false => builder.build(pg_mgr).await
+                .map_err(ConnectError::Pool)?,
+        };
+
+        if warmup.enabled {
+            let elapsed: Duration = db::warmup(&pool, warmup).await.map_err(ConnectError::Warmup)?;
+            tracing::info!("database warmup: {} connections in {elapsed:?}", warmup.min_idle);
+        }
+
+        // queue shared by every instance, same handlers
+        let jobs: Jobs = match self.config.server.jobs.backend {
+            JobBackend::Postgres => {
+                let store: PostgresJobStore = PostgresJobStore::new(pool.clone());
+                store.migrate().await.map_err(ConnectError::Jobs)?;
+
+                self.jobs.clone().with_store(store)
+            },
+            JobBackend::Memory => self.jobs.clone(),
+        };
+
+        Ok(App{
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs,
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            router: self.router.clone(),
+            pool,
+            features: NoFeatures,
+            template: self.template.clone()
+        })
+    }
+}
+
+impl<T> App<ConnectionPool, NoFeatures, T>  where T: Template + 'static  {
+    pub fn register_feature_default<F: Feature + Default + 'static>(&self) ->  App<ConnectionPool, Features, T>{         
+        let features: Vec<Box<dyn Feature + 'static>> = vec![
+            Box::new(F::default())
+        ];
+
+        return App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            router: self.router.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            features,
+        };
+    }
+
+    pub fn register_feature(&self, feature: impl Feature + 'static) ->  App<ConnectionPool, Features, T>{         
+        let features: Vec<Box<dyn Feature + 'static>> = vec![
+            Box::new(feature)
+        ];
+
+        return App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            router: self.router.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            features,
+        };
+    }
+
+    pub fn template<F: Template + 'static>(&mut self, template: T) -> App<NoPool, NoFeatures, T> {
+        App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            router: self.router.clone(), 
+            pool: NoPool,
+            features: NoFeatures,
+            template,
+        }
+    }
+
+}
+
+impl<T> App<ConnectionPool, Features, T> where T: Template + 'static  {
+    /// Every problem keeping the registered features from starting (their config sections,
+    /// head assets, routes mounted twice), without building the app. For the `check` command
+    /// of an application, `DiagnosticReport::to_json` for tooling.
+    pub fn check(&self) -> DiagnosticReport {
+        check_features(&self.features, &self.config)
+    }
+
+    pub fn register_feature_default<F: Feature + Default + 'static>(&mut self) ->  App<ConnectionPool, Features, T>{
+        let feature: F = F::default();
+        assert_unique(&self.features, &feature);
+        self.features.push(Box::new(feature));
+
+        // relocate features into new App
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+
+        return App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            router: self.router.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            features,
+        };
+    }
+
+    pub fn register_feature(&mut self, feature: impl Feature + 'static) ->  App<ConnectionPool, Features, T>{         
+        assert_unique(&self.features, &feature);
+        self.features.push(Box::new(feature));
+
+        // relocate features into new App
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+
+        return App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            router: self.router.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            features,
+        };
+    }
+
+    /// A plain 404 for unmatched routes, see `apply_fallback_with` for one within the shell.
+    pub fn apply_fallback(&mut self) -> App<ConnectionPool, Features, T> {
+        let mut router: Router = mem::replace(&mut self.router, Router::new());
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+
+        async fn handler_404() -> impl IntoResponse {
+            (StatusCode::NOT_FOUND, "nothing to see here")
+        }
+
+        router = router.fallback(handler_404);
+
+        return App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            router,
+            features
+        };
+    }
+
+    /// Answer unmatched routes with `handler`, rendered through the template like a web route:
+    /// the shell around a plain request's 404, the fragment alone for a boosted one.
+    ///
+    /// ```ignore
+    /// async fn not_found() -> impl IntoResponse {
+    ///     (StatusCode::NOT_FOUND, html! { h1 { "Page not found" } a href="/" { "Home" } })
+    /// }
+    ///
+    /// App::new(config, AppTemplate).register_feature(Invoices).apply_fallback_with(not_found).build()
+    /// ```
+    pub fn apply_fallback_with<H, M>(&mut self, handler: H) -> App<ConnectionPool, Features, T>
+    where
+        H: Handler<M, ()>,
+        M: 'static,
+    {
+        let fallback: Router = self.fallback_router(handler);
+
+        let mut router: Router = mem::replace(&mut self.router, Router::new());
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+
+        router = router.fallback_service(fallback);
+
+        return App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            router,
+            features
+        };
+    }
+
+    pub fn apply_extension<S: Clone + Send + Sync + 'static>(&mut self, state: S) -> App<ConnectionPool, Features, T> {
+        let mut router: Router = mem::replace(&mut self.router, Router::new());
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+        
+        router = router.layer(Extension(state));
+
+        return App {
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            router,
+            features,
+        };
+    }
+
+    pub fn template<F: Template + 'static>(&mut self, template: T) -> App<ConnectionPool, Features, T> {
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+        
+        App { 
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            router: self.router.clone(), 
+            pool: self.pool.clone(),
+            features,
+            template,
+        }
+    }
+
+    pub fn build(&mut self) -> App<ConnectionPool, Features, T>{
+        let mut router: Router = mem::replace(&mut self.router, Router::new());
+        let features: Vec<Box<dyn Feature>> = mem::replace(&mut self.features, Vec::new());
+
+        // every `[features.*]` section and every feature's scripts and styles (one per name),
+        // checked before anything is mounted, all problems reported at once
+        let (configs, head_assets): (FeatureConfigs, HeadAssets) = match (
+            parse_feature_configs(&features, &self.config),
+            HeadAssets::from_features(&features, self.config.server.assets.conflicts),
+        ) {
+            (Ok(configs), Ok(head_assets)) => (configs, head_assets),
+            (configs, head_assets) => {
+                let mut report: DiagnosticReport = DiagnosticReport::default();
+
+                if let Err(e) = configs.as_ref() {
+                    report.extend(DiagnosticReport::from(e));
+                }
+
+                if let Err(e) = head_assets.as_ref() {
+                    report.extend(DiagnosticReport::from(e));
+                }
+
+                report.fail()
+            },
+        };
+
+        for section in configs.unclaimed.iter() {
+            tracing::warn!("[features.{section}] is not claimed by any feature, is the name misspelled?");
+        }
+
+        // development only, None otherwise
+        let live_reload: Option<LiveReload> = LiveReload::from_config(&self.config);
+
+        // the offline queue is switched on by a meta tag, ahead of the application's transforms
+        let mut transforms: Vec<BodyTransform> = self.transforms.clone();
+        if self.config.server.offline.enabled {
+            transforms.insert(0, offline_meta(&self.config.server.offline));
+        }
+
+        // per target sequence of the integration script, stale responses are not swapped
+        if self.config.server.sequencing.enabled {
+            transforms.insert(0, sequence_meta());
+        }
+
+        // morph extension for `morph:` swaps, after the application's scripts
+        if self.config.server.morph.enabled {
+            transforms.push(morph_script());
+        }
+
+        // hreflang alternates of translated pages
+        transforms.push(hreflang());
+
+        // head assets of full pages, fragments get theirs from the context layer
+        if !head_assets.is_empty() {
+            transforms.push(head_assets_transform(head_assets.clone()));
+        }
+
+        // live regions of the announcements, once the page is otherwise complete
+        if self.config.server.announcements.live_regions {
+            transforms.push(live_regions());
+        }
+        let localized: LocalizedRoutes = LocalizedRoutes::default();
+
+        // every feature's getting started steps, for the checklist
+        let onboarding: OnboardingItems = aggregate(&features);
+
+        // every feature's user data, for the takeout export
+        let user_data: UserDataProviders = user_data_providers(&features);
+
+        // every feature's navigation tree, for the shell and the expansion fragments
+        let nav_trees: NavTrees = nav_trees(&features);
+
+        // every feature's top-level link and submenu, for the shell
+        let menus: FeatureMenus = feature_menus(&features);
+
+        // every feature's routers, built once, mounted below
+        let feature_groups: Vec<Vec<RouteGroup>> = features.iter().map(|feature| feature.groups()).collect();
+
+        // every feature and its entry route, for status pages
+        let registry: FeatureRegistry = FeatureRegistry::from_groups(&features, &feature_groups);
+
+        // CDN header rules, None when disabled
+        let cdn: Option<CdnPolicy> = self.config.server.cdn.enabled
+            .then(|| CdnPolicy::live(self.reloader.cdn(), self.config.server.assets.prefix.clone()));
+    
+        // 1. scan features and extract links for navigator, every page's shell links to every feature
+        for feature in features.iter() {
+            self.template.register(feature);
+        }
+
+        // verifies the tokens of widget urls, every widget request is refused without a key
+        let embed_tokens: Option<EmbedTokens> = self.config.server.widgets.key.as_ref()
+            .map(|key| EmbedTokens::new(key.as_bytes()).shared_clock(self.clock.clone()));
+
+        // jobs run on the app's clock, a `TestClock` drives their retries
+        self.jobs = self.jobs.clone().shared_clock(self.clock.clone());
+
+        // 2. scan features and apply routers
+        for ((feature, config), mut groups) in features.iter().zip(configs.apply.iter()).zip(feature_groups) {
+            feature.subscribe(&self.events);
+            self.templates.register(&feature.name(), feature.templates_source());
+            self.schedule.extend(feature.schedule());
+
+            for handler in feature.jobs() {
+                self.jobs.register(handler);
+            }
+
+            let span: FeatureSpanLayer = FeatureSpanLayer::new(&feature.name());
+
+            // runtime switch, outermost so a disabled feature does no work
+            let toggle: FeatureHandle = self.toggles.register(&feature.name(), feature.link().map(|link| link.route));
+
+            // translated variants and lazily built routers are web routes like any other
+            let mut web: Option<Router> = None;
+
+            if let Some(lazy) = feature.lazy() {
+                let prefix: String = lazy.prefix().to_owned();
+                web = Some(Router::new().nest_service(&prefix, lazy));
+            }
+
+            for route in feature.localized() {
+                localized.register(&route);
+                web = Some(web.unwrap_or_else(Router::new).merge(route.router()));
+
+                if let Some(redirect) = route.redirect() {
+                    router = router.merge(redirect);
+                }
+            }
+
+            if let Some(web) = web {
+                groups.push(RouteGroup::web("web", web));
+            }
+
+            let mount: GroupMount<T> = GroupMount {
+                template: &self.template,
+                live_reload: live_reload.is_some(),
+                transforms: &transforms,
+                cdn: cdn.as_ref(),
+                span: &span,
+                toggle: &toggle,
+                toggle_redirect: self.config.server.toggles.redirect.clone(),
+                noindex: feature.noindex(),
+                widgets: &self.config.server.widgets,
+                embed_tokens: embed_tokens.as_ref(),
+                cors: feature.cors(),
+            };
+
+            for group in groups {
+                router = router.merge(FeatureConfigs::layer(config.as_ref(), self.groups.mount(&feature.name(), group, &mount)));
+            }
+        }
+
+        if let Some(live_reload) = live_reload {
+            router = router.merge(live_reload.router());
+        }
+
+        // browser copies of bridged events
+        router = router.merge(self.events.router());
+
+        // status of operations deferred by handlers
+        router = router.merge(self.operations.router());
+
+        // loader of embedded widgets
+        if embed_tokens.is_some() {
+            router = router.merge(widget::router());
+        }
+
+        // children of navigation tree nodes
+        if !nav_trees.is_empty() {
+            router = router.merge(nav_tree::router(nav_trees.clone()));
+        }
+
+        // feature switches, only with an admin token
+        if let Some(token) = admin_token("server.toggles.token", self.config.server.toggles.token.as_deref()) {
+            router = router.merge(self.toggles.router(token));
+        }
+
+        // dead letters of the job queue, only with an admin token
+        if let Some(token) = admin_token("server.jobs.token", self.config.server.jobs.token.as_deref()) {
+            router = router.merge(self.jobs.router(token));
+        }
+
+        if self.config.server.morph.enabled {
+            router = router.merge(morph::router());
+
+            // development only, ids of morphed fragments
+            if self.config.is_development() {
+                router = router.layer(MorphCheckLayer);
+            }
+        }
+
+        // runtime log levels, unauthenticated in development, bearer protected otherwise
+        let logging_token: Option<&str> = admin_token("server.logging.token", self.config.server.logging.token.as_deref());
+        if self.config.is_development() || logging_token.is_some() {
+            router = router.merge(self.log_levels.router(logging_token));
+        }
+
+        let reload_token: Option<&str> = admin_token("server.reload.token", self.config.server.reload.token.as_deref());
+        if self.config.is_development() || reload_token.is_some() {
+            router = router.merge(self.reloader.router(reload_token));
+        }
+
+        // shared response cache, handlers evict tags through the extension
+        // (which also purges the CDN, so it's provided to CDN only setups too)
+        if self.config.server.cache.enabled || self.config.server.cdn.enabled {
+            let cache: ResponseCache = ResponseCache::new(self.config.server.cache.clone(), self.clock.clone())
+                .with_purger(self.purger.clone());
+
+            if self.config.server.cache.enabled {
+                router = router.layer(ResponseCacheLayer::new(cache.clone()));
+            }
+
+            router = router.layer(Extension(cache));
+        }
+
+        // content directories, overlaid roots answer in order
+        for mount in self.config.server.content.iter() {
+            router = router.nest_service(&mount.path, ContentOverlay::from_config(mount));
+        }
+
+        // CDN headers of the asset mounts, feature routers got theirs above
+        if let Some(cdn) = cdn.as_ref() {
+            router = router.layer(CdnLayer::new(cdn.clone(), RouteClass::Static));
+        }
+
+        // escape hatch, every route is merged and no core layer applied yet
+        router = self.hooks.map_router(router);
+    
+        router = router
+
+            // web assets (css, javascript, etc)
+            // .nest_service("/web", ServeDir::new(self.config.server.asset_path.clone()))
+            
+            // core layers
+            .layer(
+                ServiceBuilder::new()
+                
+                    // build a layer for handling HTMX templating
+                    // requirements
+                        // define navigator (remove from extension)
+                        // handle boost/non-boosted request
+                    
+                    // raw handlers only need to return
+
+                    // requires more finesse
+                    // https://docs.rs/axum/latest/axum/error_handling/index.html
+
+                    // .layer(HandleErrorLayer::new(|m: Method, u: Uri, e: BoxError| async {
+                    //     (
+                    //     hyper::StatusCode::REQUEST_TIMEOUT,
+                    //     format!("ERROR {:#?}", e)
+                    //     )
+                    // }))
+            );
+
+        // vanilla middleware, innermost first, each can be left to a parent application
+        let core = &self.config.server.core_layers;
+
+        if core.catch_panic {
+            router = router.layer(CatchPanicLayer::new(self.template.clone()).reporter(self.reporter.clone()));
+        }
+
+        if let Some(timeouts) = RouteTimeoutLayer::from_config(&self.config.server).filter(|_| core.timeout) {
+            router = router.layer(timeouts);
+        }
+
+        if core.compression {
+            router = router.layer(CompressionLayer::new());
+        }
+
+        if core.cors {
+            router = router.layer(AppCorsLayer::new(CorsLayer::new()));
+        }
+
+        if core.trace {
+            router = router.layer(TraceLayer::new_for_http());
+        }
+
+        // escape hatch, layers added here still see the framework extensions
+        router = self.hooks.after_core_layers(router);
+
+        router = router
+
+            // base extensions (database connection, time source, event bus, onboarding items, asset urls, cookie settings)
+            .layer(Extension(self.pool.clone()))
+            .layer(Extension(QueryLogger::from_config(&self.config)))
+            .layer(Extension(self.clock.clone()))
+            .layer(Extension(self.events.clone()))
+            .layer(Extension(self.connections.clone()))
+            .layer(Extension(self.toggles.clone()))
+            .layer(Extension(Arc::new(self.config.server.theme.clone())))
+            .layer(Extension(Arc::new(self.config.server.shells.clone())))
+            .layer(Extension(localized))
+            .layer(Extension(self.reloader.flags()))
+            .layer(Extension(onboarding))
+            .layer(Extension(user_data))
+            .layer(Extension(nav_trees))
+            .layer(Extension(menus))
+            .layer(Extension(registry))
+            .layer(Extension(self.operations.clone()))
+            .layer(Extension(self.jobs.clone()))
+            .layer(Extension(self.sections.clone()))
+            .layer(Extension(self.groups.clone()))
+            .layer(Extension(self.templates.clone()))
+            .layer(Extension(configs.settings.clone()))
+            .layer(Extension(head_assets.clone()))
+            .layer(Extension(RenderCoalescer::default()))
+            .layer(Extension(Arc::new(AssetManifest::load(&self.config.server.assets))))
+            .layer(Extension(Arc::new(CookieSettings::from_config(&self.config.server.cookies))))
+            .layer(Extension(Arc::new(self.config.server.morph.clone())))
+            .layer(Extension(Arc::new(self.config.server.announcements.clone())))
+            .layer(Extension(Arc::new(self.config.server.triggers.clone())))
+            .layer(Extension(Arc::new(self.config.server.sockets.clone())));
+            
+            // others? Feature specific data/configurations?
+
+        // replayed offline submissions are processed once
+        if self.config.server.offline.enabled {
+            router = router.layer(IdempotencyLayer::new(self.config.server.offline.idempotency_ttl).with_clock(self.clock.clone()));
+        }
+
+        // sanitized copies of sampled requests for replaying bugs, see `test::replay`
+        if self.config.server.recording.enabled {
+            let recording: &Recording = &self.config.server.recording;
+            let recorder: MemoryRecorder = MemoryRecorder::new(recording.capacity);
+            let mut layer: RecordingLayer = RecordingLayer::new(recording.clone(), self.config.is_development())
+                .sink(Arc::new(recorder.clone()));
+
+            if let Some(dir) = recording.dir.as_deref() {
+                layer = layer.sink(Arc::new(FileSink::new(dir)));
+            }
+
+            router = router.layer(Extension(recorder)).layer(layer);
+        }
+
+        // reject oversized requests before any other work
+        router = router.layer(LimitsLayer::live(self.reloader.limits()));
+
+        // one line per request, including the rejected ones
+        if self.config.server.access_log.format != AccessLogFormat::None {
+            router = router.layer(AccessLogLayer::new(self.config.server.access_log.format));
+        }
+
+        // development only, outermost so it inspects the final response
+        if self.config.is_development() && self.config.server.header_audit.enabled {
+            router = router.layer(HeaderAuditLayer::new(self.config.server.header_audit.clone()));
+        }
+
+        // OPTIONS answered from routing's 405, whose `Allow` is only set once the route has
+        // answered, HEAD is built like GET and measured by axum's routes
+        router = Router::new().fallback_service(OptionsLayer.layer(router));
+
+        // wraps the finished router, a Router::layer would run after routing
+        if self.config.server.trailing_slash != TrailingSlash::Strict {
+            let normalized = TrailingSlashLayer::new(self.config.server.trailing_slash).layer(router);
+            router = Router::new().fallback_service(normalized);
+        }
+
+        return App {
+            config: self.config.clone(),
+            clock: self.clock.clone(),
+            transforms: self.transforms.clone(),
+            log_levels: self.log_levels.clone(),
+            hooks: self.hooks.clone(),
+            purger: self.purger.clone(),
+            schedule: self.schedule.clone(),
+            operations: self.operations.clone(),
+            groups: self.groups.clone(),
+            templates: self.templates.clone(),
+            reporter: self.reporter.clone(),
+            reloader: self.reloader.clone(),
+            sections: self.sections.clone(),
+            tracing: self.tracing,
+            jobs: self.jobs.clone(),
+            events: self.events.clone(),
+            connections: self.connections.clone(),
+            toggles: self.toggles.clone(),
+            pool: self.pool.clone(),
+            template: self.template.clone(),
+            features,
+            router,
+        };
+    }
+
+    /// Binds `server.host:server.port` without serving yet, `port = 0` picks a free port.
+    /// For tests and embedding, `run` also installs the log subscriber and reports bind errors.
+    pub async fn listen(&mut self) -> io::Result<Listening> {
+        let listener: TcpListener = TcpListener::bind(self.address()).await?;
+
+        let mut extensions: Extensions = Extensions::new();
+        extensions.insert(self.pool.clone());
+        extensions.insert(self.events.clone());
+
+        Listening::new(
+            listener,
+            self.router.clone(),
+            self.connections.clone(),
+            self.scheduler().extension(self.pool.clone()),
+            self.jobs.clone(),
+            self.config.server.jobs.clone(),
+            extensions,
+        )
+    }
+
+    /// Serves until SIGINT or SIGTERM, requests in flight are answered before returning
+    /// and the pool's connections are closed once returned.
+    pub async fn run(self) {
+        self.run_with_shutdown(std::future::pending()).await
+    }
+
+    /// `run`, also stopping once `shutdown` completes, e.g. a management endpoint's signal.
+    pub async fn run_with_shutdown(mut self, shutdown: impl Future<Output = ()> + Send + 'static) {
+        let listening: Listening = match self.listen().await {
+            Ok(listening) => listening,
+            Err(e) => DiagnosticReport::bind(&self.address(), &e).fail(),
+        };
+
+        self.install_subscriber();
+
+        // the port may be picked by the system, the only way to find an ephemeral demo
+        tracing::info!("listening on http://{}", listening.local_addr());
+        tracing::info!("log filter: {}", self.log_levels.filter());
+
+        self.reloader.listen_for_hangup();
+
+        // scheduled and queued jobs in progress finish before returning
+        if let Err(e) = listening.serve_until(shutdown).await {
+            tracing::error!("server error: {e}");
+        }
+
+        // the app, its router and its job store hold the other handles of the pool
+        let pool: ConnectionPool = self.pool.clone();
+        drop(self);
+
+        db::drain(pool).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{str::FromStr, time::Duration};
+
+    use maud::{html, Markup};
+    use tokio_postgres::{config::Host, NoTls};
+    use uuid::Uuid;
+
+    use crate::{db::ConnectError, App, Config, Context, Feature, Template};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Idle;
+
+    impl Feature for Idle {}
+
+    // against a database: BLANDWORK_TEST_DATABASE_URL=postgresql://... cargo test -- --ignored
+    #[tokio::test]
+    #[ignore = "needs BLANDWORK_TEST_DATABASE_URL"]
+    async fn test_run_closes_the_pool() {
+        let url: String = std::env::var("BLANDWORK_TEST_DATABASE_URL").expect("BLANDWORK_TEST_DATABASE_URL");
+        let admin = tokio_postgres::Config::from_str(&url).unwrap();
+        let (client, connection) = admin.connect(NoTls).await.unwrap();
+        tokio::spawn(connection);
+
+        // a database of its own, no other test's connections are counted
+        let database: String = format!("blandwork_drain_{}", Uuid::new_v4().simple());
+        client.batch_execute(&format!("CREATE DATABASE {database}")).await.unwrap();
+
+        let mut config = Config::default();
+        config.server.host = "127.0.0.1".to_owned();
+        config.server.port = 0;
+        config.database.host = match &admin.get_hosts()[0] {
+            Host::Tcp(host) => host.clone(),
+            #[cfg(unix)]
+            Host::Unix(path) => path.to_string_lossy().into_owned(),
+        };
+        config.database.port = admin.get_ports()[0] as u32;
+        config.database.username = admin.get_user().unwrap_or("postgres").to_owned();
+        config.database.password = admin.get_password().map(|password| String::from_utf8_lossy(password).into_owned()).unwrap_or_default();
+        config.database.database = database.clone();
+        config.database.warmup.enabled = true;
+        config.database.warmup.min_idle = 2;
+
+        let sessions = || async {
+            let row = client.query_one("SELECT count(*) FROM pg_stat_activity WHERE datname = $1", &[&database]).await.unwrap();
+            row.get::<_, i64>(0)
+        };
+
+        let app = App::new(config, TestTemplate).with_tracing(false).connect().await.unwrap().register_feature(Idle).build();
+        assert!(sessions().await >= 2);
+
+        tokio::time::timeout(Duration::from_secs(10), app.run_with_shutdown(async {})).await.unwrap();
+
+        // the server notices the terminated sessions on its own time
+        for _ in 0..100 {
+            if sessions().await == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(sessions().await, 0);
+        client.batch_execute(&format!("DROP DATABASE {database}")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_unreachable() {
+        let mut config = Config::default();
+        config.database.host = "127.0.0.1".to_owned();
+        config.database.port = 1;
+        config.database.warmup.enabled = true;
+        config.database.warmup.retries = 0;
+        config.database.pool_connection_timeout = Duration::from_millis(50);
+
+        // an error to retry on, the process keeps running
+        let result = App::new(config, TestTemplate).connect().await;
+
+        assert!(matches!(result, Err(ConnectError::Warmup(_))));
+    }
+}
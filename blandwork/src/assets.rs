@@ -0,0 +1,126 @@
+use std::{collections::HashMap, fs};
+
+use serde_json::Value;
+
+use crate::config::Assets;
+
+/// Maps logical asset names (`css/output.css`) to the fingerprinted files
+/// produced by the frontend build, read from its `manifest.json`.
+///
+/// Both a flat manifest (`{"css/output.css": "css/output.3f2a.css"}`) and
+/// the Vite style (`{"css/output.css": {"file": "css/output.3f2a.css"}}`) are understood.
+/// Without a manifest names resolve to their raw path.
+#[derive(Debug, Clone)]
+pub struct AssetManifest {
+    prefix: String,
+    entries: HashMap<String, String>,
+}
+
+impl Default for AssetManifest {
+    fn default() -> Self {
+        Self { prefix: "/web".to_owned(), entries: HashMap::new() }
+    }
+}
+
+impl AssetManifest {
+    pub fn load(config: &Assets) -> Self {
+        let empty: AssetManifest = AssetManifest {
+            prefix: config.prefix.clone(),
+            entries: HashMap::new(),
+        };
+
+        let Some(path) = &config.manifest else {
+            return empty;
+        };
+
+        let json: String = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::debug!("no asset manifest at {path} ({e}), using raw asset paths");
+                return empty;
+            }
+        };
+
+        match AssetManifest::from_json(&config.prefix, &json) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                tracing::warn!("invalid asset manifest {path}: {e}, using raw asset paths");
+                empty
+            }
+        }
+    }
+
+    pub fn from_json(prefix: &str, json: &str) -> Result<Self, serde_json::Error> {
+        let raw: HashMap<String, Value> = serde_json::from_str(json)?;
+
+        let entries: HashMap<String, String> = raw.into_iter()
+            .filter_map(|(name, entry)| {
+                let file: String = match entry {
+                    Value::String(file) => file,
+                    Value::Object(mut fields) => match fields.remove("file") {
+                        Some(Value::String(file)) => file,
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+
+                Some((name.trim_start_matches('/').to_owned(), file))
+            })
+            .collect();
+
+        Ok(Self { prefix: prefix.to_owned(), entries })
+    }
+
+    /// Public URL for a logical asset name, fingerprinted when the manifest knows it.
+    pub fn url(&self, name: &str) -> String {
+        let name: &str = name.trim_start_matches('/');
+
+        let file: &str = self.entries.get(name)
+            .map(|file| file.as_str())
+            .unwrap_or(name);
+
+        format!("{}/{}", self.prefix.trim_end_matches('/'), file.trim_start_matches('/'))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::Assets;
+
+    use super::AssetManifest;
+
+    #[test]
+    fn test_flat_manifest() {
+        let manifest = AssetManifest::from_json("/web", r#"{"css/output.css": "css/output.3f2a.css"}"#).unwrap();
+
+        assert_eq!(manifest.url("css/output.css"), "/web/css/output.3f2a.css");
+        assert_eq!(manifest.url("/css/output.css"), "/web/css/output.3f2a.css");
+    }
+
+    #[test]
+    fn test_vite_manifest() {
+        let manifest = AssetManifest::from_json("/web/", r#"{
+            "js/main.js": {"file": "assets/main.8c1d.js", "isEntry": true}
+        }"#).unwrap();
+
+        assert_eq!(manifest.url("js/main.js"), "/web/assets/main.8c1d.js");
+    }
+
+    #[test]
+    fn test_fallback_to_raw_path() {
+        let manifest = AssetManifest::from_json("/web", r#"{"css/output.css": "css/output.3f2a.css"}"#).unwrap();
+
+        assert_eq!(manifest.url("htmx_integration.js"), "/web/htmx_integration.js");
+    }
+
+    #[test]
+    fn test_missing_manifest() {
+        let manifest = AssetManifest::load(&Assets {
+            prefix: "/static".to_owned(),
+            manifest: Some("does/not/exist.json".to_owned()),
+            ..Default::default()
+        });
+
+        assert_eq!(manifest.url("css/output.css"), "/static/css/output.css");
+    }
+}
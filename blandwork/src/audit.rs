@@ -0,0 +1,409 @@
+use std::{
+    fmt::Display, future::Future, pin::Pin,
+    task::{Context as TaskContext, Poll}
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::Request, http::response::Parts, response::IntoResponse
+};
+use hyper::{
+    header::{
+        HeaderName, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE,
+        SET_COOKIE, STRICT_TRANSPORT_SECURITY, VARY
+    },
+    HeaderMap, Response, StatusCode
+};
+use tower::{Layer, Service};
+
+use crate::{config::HeaderAudit, stream::Streaming};
+
+/// Marker placed in the response extensions by routes that answer with
+/// either a full page or a fragment depending on the HTMX request headers.
+#[derive(Clone, Copy, Debug)]
+pub struct DualRepresentation;
+
+/// Headers the framework owns and which must never appear twice.
+const FRAMEWORK_HEADERS: [&str; 9] = [
+    "content-type",
+    "content-length",
+    "x-request-id",
+    "hx-trigger",
+    "hx-trigger-after-settle",
+    "hx-trigger-after-swap",
+    "hx-redirect",
+    "hx-location",
+    "hx-refresh",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    ContentLength,
+    ContentType,
+    CookieCache,
+    DuplicateHeaders,
+    Vary,
+    Hsts,
+}
+
+impl Rule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rule::ContentLength => "content_length",
+            Rule::ContentType => "content_type",
+            Rule::CookieCache => "cookie_cache",
+            Rule::DuplicateHeaders => "duplicate_headers",
+            Rule::Vary => "vary",
+            Rule::Hsts => "hsts",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.rule.as_str(), self.message)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Sniffed {
+    Html,
+    Json,
+    Other,
+}
+
+fn sniff(body: &[u8]) -> Sniffed {
+    let start: Option<&u8> = body.iter().find(|b| !b.is_ascii_whitespace());
+
+    match start {
+        Some(b'<') => Sniffed::Html,
+        Some(b'{') | Some(b'[') if serde_json::from_slice::<serde_json::Value>(body).is_ok() => Sniffed::Json,
+        _ => Sniffed::Other,
+    }
+}
+
+fn header_contains(headers: &HeaderMap, name: HeaderName, needle: &str) -> bool {
+    headers.get_all(&name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.to_ascii_lowercase().contains(needle))
+}
+
+/// Check a final response against the enabled rules.
+pub fn audit(parts: &Parts, body: &[u8], rules: &HeaderAudit) -> Vec<Violation> {
+    let mut violations: Vec<Violation> = Vec::new();
+    let headers: &HeaderMap = &parts.headers;
+
+    if rules.content_length {
+        let declared: Option<usize> = headers.get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+
+        if let Some(declared) = declared {
+            if declared != body.len() {
+                violations.push(Violation {
+                    rule: Rule::ContentLength,
+                    message: format!("Content-Length is {declared} but the body is {} bytes", body.len())
+                });
+            }
+        }
+    }
+
+    // encoded bodies can't be sniffed
+    if rules.content_type && !body.is_empty() && !headers.contains_key(CONTENT_ENCODING) {
+        let content_type: String = headers.get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+
+        let consistent: bool = match sniff(body) {
+            Sniffed::Html => content_type.contains("html") || content_type.contains("xml"),
+            Sniffed::Json => content_type.contains("json"),
+            Sniffed::Other => true,
+        };
+
+        if !consistent {
+            violations.push(Violation {
+                rule: Rule::ContentType,
+                message: format!("Content-Type `{content_type}` does not match the body ({:?})", sniff(body))
+            });
+        }
+    }
+
+    if rules.cookie_cache && headers.contains_key(SET_COOKIE) && header_contains(headers, CACHE_CONTROL, "public") {
+        violations.push(Violation {
+            rule: Rule::CookieCache,
+            message: "Set-Cookie sent on a response marked Cache-Control: public".to_owned()
+        });
+    }
+
+    if rules.duplicate_headers {
+        for name in FRAMEWORK_HEADERS {
+            let count: usize = headers.get_all(name).iter().count();
+
+            if count > 1 {
+                violations.push(Violation {
+                    rule: Rule::DuplicateHeaders,
+                    message: format!("{name} appears {count} times")
+                });
+            }
+        }
+    }
+
+    if rules.vary && parts.extensions.get::<DualRepresentation>().is_some() && !header_contains(headers, VARY, "hx-request") {
+        violations.push(Violation {
+            rule: Rule::Vary,
+            message: "page/fragment response without Vary: HX-Request".to_owned()
+        });
+    }
+
+    if rules.hsts && headers.contains_key(STRICT_TRANSPORT_SECURITY) {
+        violations.push(Violation {
+            rule: Rule::Hsts,
+            message: "Strict-Transport-Security sent in development".to_owned()
+        });
+    }
+
+    violations
+}
+
+/// Development only layer, applied outermost so it sees the final response.
+#[derive(Clone)]
+pub struct HeaderAuditLayer {
+    rules: HeaderAudit
+}
+
+impl HeaderAuditLayer {
+    pub fn new(rules: HeaderAudit) -> Self {
+        Self { rules }
+    }
+}
+
+impl<S> Layer<S> for HeaderAuditLayer {
+    type Service = HeaderAuditService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HeaderAuditService {
+            inner,
+            rules: self.rules.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HeaderAuditService<S> {
+    inner: S,
+    rules: HeaderAudit,
+}
+
+impl<S> Service<Request> for HeaderAuditService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let path: String = req.uri().path().to_owned();
+        let rules: HeaderAudit = self.rules.clone();
+
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let response: Response<Body> = inner.await?;
+
+            // never buffer event streams (live reload, sse) or streamed bodies
+            let streaming: bool = response.extensions().get::<Streaming>().is_some();
+
+            if streaming || header_contains(response.headers(), CONTENT_TYPE, "text/event-stream") {
+                return Ok(response);
+            }
+
+            let (parts, body) = response.into_parts();
+
+            let bytes: Bytes = match to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    tracing::warn!(path = %path, "header audit could not read the response body: {e}");
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                }
+            };
+
+            let violations: Vec<Violation> = audit(&parts, &bytes, &rules);
+
+            for violation in violations.iter() {
+                tracing::warn!(rule = violation.rule.as_str(), path = %path, "header audit: {}", violation.message);
+            }
+
+            if rules.strict && !violations.is_empty() {
+                let report: String = violations.iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n");
+
+                return Ok((StatusCode::INTERNAL_SERVER_ERROR, report).into_response());
+            }
+
+            Ok(Response::from_parts(parts, Body::from(bytes)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use axum::{body::Body, extract::Request};
+    use hyper::{
+        header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, SET_COOKIE, STRICT_TRANSPORT_SECURITY, VARY},
+        Response, StatusCode
+    };
+    use tower::{service_fn, Layer, ServiceExt};
+
+    use crate::config::HeaderAudit;
+
+    use super::{audit, DualRepresentation, HeaderAuditLayer, Rule, Violation};
+
+    fn check(response: Response<&'static str>) -> Vec<Violation> {
+        let (parts, body) = response.into_parts();
+        audit(&parts, body.as_bytes(), &HeaderAudit::default())
+    }
+
+    fn rules(violations: Vec<Violation>) -> Vec<Rule> {
+        violations.into_iter().map(|v| v.rule).collect()
+    }
+
+    #[test]
+    fn test_clean_response() {
+        let response = Response::builder()
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CONTENT_LENGTH, "9")
+            .body("<b>hi</b>")
+            .unwrap();
+
+        assert!(check(response).is_empty());
+    }
+
+    #[test]
+    fn test_content_length_mismatch() {
+        let response = Response::builder()
+            .header(CONTENT_TYPE, "text/html")
+            .header(CONTENT_LENGTH, "3")
+            .body("<b>hi</b>")
+            .unwrap();
+
+        assert_eq!(rules(check(response)), vec![Rule::ContentLength]);
+    }
+
+    #[test]
+    fn test_content_type_mismatch() {
+        let html = Response::builder()
+            .header(CONTENT_TYPE, "text/plain")
+            .body("<b>hi</b>")
+            .unwrap();
+
+        let json = Response::builder()
+            .header(CONTENT_TYPE, "text/html")
+            .body("{\"name\": \"value\"}")
+            .unwrap();
+
+        assert_eq!(rules(check(html)), vec![Rule::ContentType]);
+        assert_eq!(rules(check(json)), vec![Rule::ContentType]);
+    }
+
+    #[test]
+    fn test_cookie_on_public_cache() {
+        let response = Response::builder()
+            .header(SET_COOKIE, "id=1")
+            .header(CACHE_CONTROL, "public, max-age=60")
+            .body("")
+            .unwrap();
+
+        assert_eq!(rules(check(response)), vec![Rule::CookieCache]);
+    }
+
+    #[test]
+    fn test_duplicate_framework_headers() {
+        let response = Response::builder()
+            .header("x-request-id", "a")
+            .header("x-request-id", "b")
+            .body("")
+            .unwrap();
+
+        let violations = check(response);
+
+        assert_eq!(violations[0].rule, Rule::DuplicateHeaders);
+        assert_eq!(violations[0].message, "x-request-id appears 2 times");
+    }
+
+    #[test]
+    fn test_vary_on_dual_representation() {
+        let missing = Response::builder()
+            .extension(DualRepresentation)
+            .body("")
+            .unwrap();
+
+        let present = Response::builder()
+            .extension(DualRepresentation)
+            .header(VARY, "HX-Request")
+            .body("")
+            .unwrap();
+
+        assert_eq!(rules(check(missing)), vec![Rule::Vary]);
+        assert!(check(present).is_empty());
+    }
+
+    #[test]
+    fn test_hsts_in_development() {
+        let response = Response::builder()
+            .header(STRICT_TRANSPORT_SECURITY, "max-age=63072000")
+            .body("")
+            .unwrap();
+
+        assert_eq!(rules(check(response)), vec![Rule::Hsts]);
+    }
+
+    #[test]
+    fn test_rule_disabled() {
+        let (parts, body) = Response::builder()
+            .header(STRICT_TRANSPORT_SECURITY, "max-age=63072000")
+            .body("")
+            .unwrap()
+            .into_parts();
+
+        let rules = HeaderAudit { hsts: false, ..Default::default() };
+
+        assert!(audit(&parts, body.as_bytes(), &rules).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode() {
+        let handler = service_fn(|_: Request| async {
+            Ok::<_, Infallible>(Response::builder()
+                .header(CONTENT_TYPE, "text/plain")
+                .body(Body::from("<b>hi</b>"))
+                .unwrap())
+        });
+
+        let lenient = HeaderAuditLayer::new(HeaderAudit::default()).layer(handler);
+        let strict = HeaderAuditLayer::new(HeaderAudit { strict: true, ..Default::default() }).layer(handler);
+
+        let response = lenient.oneshot(Request::new(Body::empty())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = strict.oneshot(Request::new(Body::empty())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,418 @@
+use std::{
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use maud::{html, Markup};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    clock::{SharedClock, SystemClock},
+    config::{self, Config}
+};
+
+/// Signed render time and nonce of the guarded form.
+pub const GUARD_FIELD: &str = "_bw_guard";
+
+/// Proof of work solution, filled in by the integration script on submit.
+pub const POW_FIELD: &str = "_bw_pow";
+
+/// Name of the rejection metric.
+pub const BOT_REJECTED: &str = "bot:rejected";
+
+// moves the honeypot out of view without display:none, which some bots skip
+const HONEYPOT_STYLE: &str = "position:absolute;left:-10000px;width:1px;height:1px;overflow:hidden";
+
+// reserved for honeypot names, application fields must not use it
+const HONEYPOT_PREFIX: &str = "bw_";
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits: u32 = 0;
+
+    for byte in bytes {
+        bits += byte.leading_zeros();
+
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
+
+/// Why a submission was rejected, only ever logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    MissingToken,
+    BadSignature,
+    HoneypotFilled,
+    TooFast,
+    Expired,
+    ProofOfWork,
+}
+
+/// A submission that failed one of the checks.
+///
+/// Carries the submitted values (without the guard fields) so the form can be
+/// rendered again for a person who was caught by mistake.
+#[derive(Debug, Clone)]
+pub struct BotRejected {
+    values: Vec<(String, String)>,
+}
+
+impl BotRejected {
+    pub fn values(&self) -> &[(String, String)] {
+        &self.values
+    }
+
+    /// Submitted value of `name`, for filling the retry render.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Generic error above the form rendered again, never says which check failed.
+    pub fn retry(&self, form: Markup) -> Markup {
+        html! {
+            div .form-error role="alert" {
+                "Something went wrong, please try again."
+            }
+            (form)
+        }
+    }
+}
+
+/// Hidden fields to place inside a form checked by `guard`, rendered fresh every time.
+pub fn bot_guard(guard: &BotGuard) -> Markup {
+    guard.fields()
+}
+
+/// Honeypot, time-trap and optional proof of work for public forms.
+///
+/// ```ignore
+/// fn contact_form(guard: &BotGuard, values: &[(String, String)]) -> Markup {
+///     html! {
+///         form hx-post="/contact" {
+///             (bot_guard(guard))
+///             input name="email" value=(value(values, "email"));
+///         }
+///     }
+/// }
+///
+/// async fn contact(Extension(guard): Extension<BotGuard>, Form(fields): Form<Vec<(String, String)>>) -> Markup {
+///     if let Err(rejected) = guard.check(&fields) {
+///         return rejected.retry(contact_form(&guard, rejected.values()));
+///     }
+///     ...
+/// }
+/// ```
+#[derive(Clone)]
+pub struct BotGuard {
+    key: Arc<Vec<u8>>,
+    clock: SharedClock,
+    config: config::BotGuard,
+    rejected: Arc<AtomicU64>,
+}
+
+impl BotGuard {
+    pub fn new(key: impl Into<Vec<u8>>, config: config::BotGuard) -> Self {
+        Self {
+            key: Arc::new(key.into()),
+            clock: Arc::new(SystemClock),
+            config,
+            rejected: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Signs with `server.cookies.key`, without one a key is generated
+    /// and forms rendered before a restart can no longer be submitted.
+    pub fn from_config(config: &Config) -> Self {
+        let key: Vec<u8> = match &config.server.cookies.key {
+            Some(key) => key.as_bytes().to_vec(),
+            None => {
+                tracing::warn!("server.cookies.key is not configured, bot guard tokens will not survive a restart");
+                [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat()
+            }
+        };
+
+        Self::new(key, config.server.bot_guard.clone())
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Submissions rejected since startup.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn mac(&self, parts: &[&str]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+
+        for part in parts {
+            mac.update(part.as_bytes());
+            mac.update(b".");
+        }
+
+        mac
+    }
+
+    /// Honeypot name for a render, only the server can tell it apart from a real field.
+    fn honeypot(&self, nonce: &str) -> String {
+        let digest = self.mac(&["honeypot", nonce]).finalize().into_bytes();
+
+        format!("{HONEYPOT_PREFIX}{}", hex(&digest[..6]))
+    }
+
+    fn token(&self, rendered_at: u128, nonce: &str) -> String {
+        let rendered_at: String = rendered_at.to_string();
+        let signature = self.mac(&["token", &rendered_at, nonce]).finalize().into_bytes();
+
+        format!("{rendered_at}.{nonce}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    fn fields(&self) -> Markup {
+        let nonce: String = URL_SAFE_NO_PAD.encode(Uuid::new_v4().as_bytes());
+        let rendered_at: u128 = self.clock.now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+
+        let token: String = self.token(rendered_at, &nonce);
+
+        html! {
+            div style=(HONEYPOT_STYLE) aria-hidden="true" {
+                label { "Leave this field empty"
+                    input type="text" name=(self.honeypot(&nonce)) value="" tabindex="-1" autocomplete="off";
+                }
+            }
+            input type="hidden" name=(GUARD_FIELD) value=(token);
+            @if self.config.proof_of_work > 0 {
+                input type="hidden" name=(POW_FIELD) value="" data-bw-pow=(self.config.proof_of_work);
+            }
+        }
+    }
+
+    fn verify(&self, fields: &[(String, String)]) -> Result<(), Reason> {
+        let field = |name: &str| fields.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str());
+
+        let token: &str = field(GUARD_FIELD).ok_or(Reason::MissingToken)?;
+
+        let mut parts = token.splitn(3, '.');
+        let (rendered_at, nonce, signature) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(rendered_at), Some(nonce), Some(signature)) => (rendered_at, nonce, signature),
+            _ => return Err(Reason::MissingToken),
+        };
+
+        let signature: Vec<u8> = URL_SAFE_NO_PAD.decode(signature).map_err(|_| Reason::BadSignature)?;
+        self.mac(&["token", rendered_at, nonce])
+            .verify_slice(&signature)
+            .map_err(|_| Reason::BadSignature)?;
+
+        // a bot filling every input also fills the one it can not see
+        if field(&self.honeypot(nonce)).map(|value| !value.is_empty()).unwrap_or(false) {
+            return Err(Reason::HoneypotFilled);
+        }
+
+        let rendered_at: u64 = rendered_at.parse().map_err(|_| Reason::BadSignature)?;
+        let rendered_at: SystemTime = UNIX_EPOCH + Duration::from_millis(rendered_at);
+        let elapsed: Duration = self.clock.now().duration_since(rendered_at).unwrap_or_default();
+
+        if elapsed < self.config.min_elapsed {
+            return Err(Reason::TooFast);
+        }
+
+        if elapsed > self.config.max_age {
+            return Err(Reason::Expired);
+        }
+
+        if self.config.proof_of_work > 0 {
+            let solution: &str = field(POW_FIELD).ok_or(Reason::ProofOfWork)?;
+            let digest = Sha256::digest(format!("{token}:{solution}").as_bytes());
+
+            if leading_zero_bits(&digest) < self.config.proof_of_work as u32 {
+                return Err(Reason::ProofOfWork);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a submitted form, the rejection keeps the values for the retry render.
+    pub fn check(&self, fields: &[(String, String)]) -> Result<(), BotRejected> {
+        let reason: Reason = match self.verify(fields) {
+            Ok(_) => return Ok(()),
+            Err(reason) => reason,
+        };
+
+        let total: u64 = self.rejected.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::info!(metric = BOT_REJECTED, total, "rejected form submission: {reason:?}");
+
+        let values: Vec<(String, String)> = fields.iter()
+            .filter(|(key, _)| key != GUARD_FIELD && key != POW_FIELD && !key.starts_with(HONEYPOT_PREFIX))
+            .cloned()
+            .collect();
+
+        Err(BotRejected { values })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use sha2::{Digest, Sha256};
+
+    use crate::{config, TestClock};
+
+    use super::{bot_guard, leading_zero_bits, BotGuard, GUARD_FIELD, POW_FIELD};
+
+    fn guard(clock: &TestClock, proof_of_work: u8) -> BotGuard {
+        let config = config::BotGuard {
+            min_elapsed: Duration::from_secs(3),
+            max_age: Duration::from_secs(60),
+            proof_of_work,
+        };
+
+        BotGuard::new("secret", config).with_clock(Arc::new(clock.clone()))
+    }
+
+    fn attribute<'a>(markup: &'a str, before: &str) -> &'a str {
+        let start: usize = markup.find(before).unwrap() + before.len();
+        let end: usize = start + markup[start..].find('"').unwrap();
+        &markup[start..end]
+    }
+
+    /// Render the fields and submit them like a browser would.
+    fn submission(guard: &BotGuard, honeypot: &str) -> Vec<(String, String)> {
+        let markup: String = bot_guard(guard).into_string();
+
+        vec![
+            (attribute(&markup, r#"type="text" name=""#).to_owned(), honeypot.to_owned()),
+            (GUARD_FIELD.to_owned(), attribute(&markup, r#"name="_bw_guard" value=""#).to_owned()),
+            ("email".to_owned(), "someone@example.com".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn test_accepts_a_person() {
+        let clock = TestClock::default();
+        let guard = guard(&clock, 0);
+        let fields = submission(&guard, "");
+
+        clock.advance(Duration::from_secs(10));
+
+        assert!(guard.check(&fields).is_ok());
+        assert_eq!(guard.rejected(), 0);
+    }
+
+    #[test]
+    fn test_rejects_filled_honeypot() {
+        let clock = TestClock::default();
+        let guard = guard(&clock, 0);
+        let fields = submission(&guard, "http://spam.example.com");
+
+        clock.advance(Duration::from_secs(10));
+
+        assert!(guard.check(&fields).is_err());
+        assert_eq!(guard.rejected(), 1);
+    }
+
+    #[test]
+    fn test_elapsed_boundaries() {
+        let clock = TestClock::default();
+        let guard = guard(&clock, 0);
+        let fields = submission(&guard, "");
+
+        clock.advance(Duration::from_millis(2999));
+        assert!(guard.check(&fields).is_err());
+
+        clock.advance(Duration::from_millis(1));
+        assert!(guard.check(&fields).is_ok());
+
+        clock.advance(Duration::from_secs(57));
+        assert!(guard.check(&fields).is_ok());
+
+        clock.advance(Duration::from_millis(1));
+        assert!(guard.check(&fields).is_err());
+    }
+
+    #[test]
+    fn test_rejects_tampered_or_missing_token() {
+        let clock = TestClock::default();
+        let guard = guard(&clock, 0);
+        let mut fields = submission(&guard, "");
+
+        clock.advance(Duration::from_secs(10));
+
+        // claim the form was rendered an hour earlier
+        let token: String = fields[1].1.clone();
+        let (rendered_at, rest) = token.split_once('.').unwrap();
+        let earlier: u64 = rendered_at.parse::<u64>().unwrap() - 3_600_000;
+        fields[1].1 = format!("{earlier}.{rest}");
+        assert!(guard.check(&fields).is_err());
+
+        fields.remove(1);
+        assert!(guard.check(&fields).is_err());
+        assert_eq!(guard.rejected(), 2);
+    }
+
+    #[test]
+    fn test_honeypot_name_varies_per_render() {
+        let clock = TestClock::default();
+        let guard = guard(&clock, 0);
+
+        let first = submission(&guard, "");
+        let second = submission(&guard, "");
+
+        assert!(first[0].0.starts_with("bw_"));
+        assert_ne!(first[0].0, second[0].0);
+    }
+
+    #[test]
+    fn test_rejection_preserves_values() {
+        let clock = TestClock::default();
+        let guard = guard(&clock, 0);
+        let fields = submission(&guard, "");
+
+        let rejected = guard.check(&fields).unwrap_err();
+
+        assert_eq!(rejected.values(), &[("email".to_owned(), "someone@example.com".to_owned())]);
+        assert_eq!(rejected.value("email"), Some("someone@example.com"));
+
+        let retry: String = rejected.retry(maud::html! { form {} }).into_string();
+        assert!(retry.contains("Something went wrong"));
+    }
+
+    #[test]
+    fn test_proof_of_work() {
+        let clock = TestClock::default();
+        let guard = guard(&clock, 8);
+        let mut fields = submission(&guard, "");
+
+        clock.advance(Duration::from_secs(10));
+        assert!(guard.check(&fields).is_err());
+
+        let token: String = fields[1].1.clone();
+        let solution: u64 = (0..).find(|n| {
+            leading_zero_bits(&Sha256::digest(format!("{token}:{n}").as_bytes())) >= 8
+        }).unwrap();
+
+        fields.push((POW_FIELD.to_owned(), solution.to_string()));
+        assert!(guard.check(&fields).is_ok());
+    }
+}
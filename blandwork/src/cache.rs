@@ -0,0 +1,684 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future, pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll},
+    time::{Duration, SystemTime}
+};
+
+use axum::{body::{to_bytes, Body, Bytes}, extract::Request};
+use axum_htmx::{HX_TRIGGER, HX_TRIGGER_AFTER_SETTLE, HX_TRIGGER_AFTER_SWAP};
+use hyper::{
+    header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, SET_COOKIE},
+    header::HeaderValue, HeaderMap, Method, Response, StatusCode
+};
+use tower::{Layer, Service, ServiceExt};
+
+use crate::{cdn::{CdnPurger, NoopPurger}, clock::SharedClock, config::Cache, stream::Streaming, CurrentUser};
+
+/// Response extension carrying the tags recorded with `Context::cache_tag`.
+#[derive(Debug, Clone)]
+pub struct CacheTags(pub Vec<String>);
+
+/// Response extension carrying the identity set with `Context::cache_key_segment`.
+#[derive(Debug, Clone)]
+pub struct CacheSegment(pub String);
+
+type Identity = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    tags: Vec<String>,
+    stored_at: SystemTime,
+
+    // personalized entries live for `personalized_ttl` and are never served stale
+    ttl: Duration,
+    stale: Duration,
+
+    // a stale hit started a refresh, later stale hits don't start another
+    revalidating: bool,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<String, Entry>,
+
+    // tag -> keys of the entries that reported it
+    tags: HashMap<String, HashSet<String>>,
+
+    // bumped by every invalidation, a render started before one is not stored
+    generation: u64,
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry: Entry = self.entries.remove(key)?;
+
+        for tag in entry.tags.iter() {
+            if let Some(keys) = self.tags.get_mut(tag) {
+                keys.remove(key);
+
+                if keys.is_empty() {
+                    self.tags.remove(tag);
+                }
+            }
+        }
+
+        Some(entry)
+    }
+
+    fn oldest(&self) -> Option<String> {
+        self.entries.iter()
+            .min_by_key(|(_, entry)| entry.stored_at)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+enum Lookup {
+    Fresh(Response<Body>),
+    Stale(Response<Body>),
+    Miss,
+}
+
+/// Shared cache of full GET responses, invalidated by age or by tag.
+///
+/// Only enable it for pages which are the same for every visitor,
+/// responses setting cookies or marked `private`/`no-store` are never stored.
+///
+/// Personalized pages opt in with `server.cache.personalized`: requests with an identity
+/// (the signed in `CurrentUser` unless `identity` says otherwise) are then looked up
+/// under that identity only, and their response is stored when the handler declared it
+/// with `Context::cache_key_segment` for the same identity. Anything else rendered for
+/// them is not stored. Every identity gets its own copy of a page, so the entries grow
+/// with users times pages: they share `max_entries` with the shared pages (evicting the
+/// oldest first) and expire after the short `personalized_ttl`, without a stale window.
+///
+/// `RenderCoalescer` is for shared renders only: concurrent misses of one identity's
+/// page are not coalesced, and a personalized render must never be given a shared key.
+#[derive(Clone)]
+pub struct ResponseCache {
+    state: Arc<Mutex<CacheState>>,
+    config: Cache,
+    clock: SharedClock,
+
+    // CDN copies evicted along with the local entries
+    purger: Arc<dyn CdnPurger>,
+
+    // the cache key segment of a request, see `Context::cache_key_segment`
+    identity: Identity,
+}
+
+impl ResponseCache {
+    pub fn new(config: Cache, clock: SharedClock) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CacheState::default())),
+            config,
+            clock,
+            purger: Arc::new(NoopPurger),
+            identity: Arc::new(|request: &Request| request.extensions().get::<CurrentUser>().map(|user| user.0.clone())),
+        }
+    }
+
+    /// How requests are identified for personalized entries, e.g. by role instead of user.
+    /// Has to agree with the segments handlers pass to `Context::cache_key_segment`.
+    pub fn identity(mut self, identity: impl Fn(&Request) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+
+    /// Purge invalidated tags from the CDN as well, see `CdnPurger`.
+    pub fn with_purger(mut self, purger: Arc<dyn CdnPurger>) -> Self {
+        self.purger = purger;
+        self
+    }
+
+    /// None when the request must not be answered from the cache,
+    /// else the key and the identity it is personalized for.
+    fn key(&self, request: &Request) -> Option<(String, Option<String>)> {
+        if !matches!(*request.method(), Method::GET | Method::HEAD) || request.headers().contains_key(AUTHORIZATION) {
+            return None;
+        }
+
+        // pages, boosted pages and fragments share a url, see Vary: HX-Request, HX-Boosted
+        let htmx: bool = request.headers().contains_key("hx-request");
+        let boosted: bool = request.headers().contains_key("hx-boosted");
+        let key: String = format!("{}{}{}", htmx as u8, boosted as u8, request.uri());
+
+        match self.config.personalized.then(|| (self.identity)(request)).flatten() {
+            Some(segment) => Some((format!("{segment}|{key}"), Some(segment))),
+            None => Some((key, None)),
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Lookup {
+        let mut state = self.state.lock().unwrap();
+        let now: SystemTime = self.clock.now();
+
+        let Some(entry) = state.entries.get_mut(key) else {
+            return Lookup::Miss;
+        };
+
+        let age: Duration = now.duration_since(entry.stored_at).unwrap_or_default();
+
+        if age < entry.ttl {
+            return Lookup::Fresh(entry.response());
+        }
+
+        if age < entry.ttl + entry.stale {
+            if entry.revalidating {
+                return Lookup::Fresh(entry.response());
+            }
+
+            entry.revalidating = true;
+            return Lookup::Stale(entry.response());
+        }
+
+        state.remove(key);
+        Lookup::Miss
+    }
+
+    /// The headers of a stored GET with its Content-Length, HEAD neither refreshes nor stores.
+    fn head(&self, key: &str) -> Option<Response<Body>> {
+        let state = self.state.lock().unwrap();
+        let entry: &Entry = state.entries.get(key)?;
+
+        let age: Duration = self.clock.now().duration_since(entry.stored_at).unwrap_or_default();
+        if age >= entry.ttl + entry.stale {
+            return None;
+        }
+
+        let mut response: Response<Body> = Response::new(Body::empty());
+        *response.status_mut() = entry.status;
+        *response.headers_mut() = entry.headers.clone();
+        response.headers_mut().insert(CONTENT_LENGTH, HeaderValue::from(entry.body.len()));
+        Some(response)
+    }
+
+    /// Taken when a render starts, see `store`.
+    fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    fn cacheable(response: &Response<Body>) -> bool {
+        let headers: &HeaderMap = response.headers();
+
+        let private: bool = headers.get_all(CACHE_CONTROL).iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("private") || value.contains("no-store"));
+
+        let stream: bool = headers.get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.starts_with("text/event-stream"))
+            .unwrap_or(false);
+
+        let streaming: bool = response.extensions().get::<Streaming>().is_some();
+
+        response.status() == StatusCode::OK && !private && !stream && !streaming && !headers.contains_key(SET_COOKIE)
+    }
+
+    /// Store (or refresh) the entry for `key`, the tags it reports replace the previous ones.
+    /// Personalized for `segment`, the response has to be declared for it. A render started
+    /// at `generation` is not stored once a tag was invalidated since, it may be stale.
+    async fn store(&self, key: String, segment: Option<String>, generation: u64, response: Response<Body>) -> Response<Body> {
+        let declared: Option<&str> = response.extensions().get::<CacheSegment>().map(|segment| segment.0.as_str());
+
+        if declared != segment.as_deref() {
+            tracing::debug!(?declared, "{key} is not cached, the response is not for the requester's identity");
+            self.state.lock().unwrap().remove(&key);
+            return response;
+        }
+
+        if !ResponseCache::cacheable(&response) {
+            self.state.lock().unwrap().remove(&key);
+            return response;
+        }
+
+        let (parts, body) = response.into_parts();
+
+        let body: Bytes = match to_bytes(body, usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("unable to buffer response for the cache: {e}");
+                self.state.lock().unwrap().remove(&key);
+
+                let mut response: Response<Body> = Response::new(Body::empty());
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return response;
+            }
+        };
+
+        let mut tags: Vec<String> = parts.extensions.get::<CacheTags>()
+            .map(|tags| tags.0.clone())
+            .unwrap_or_default();
+
+        if tags.len() > self.config.max_tags_per_entry {
+            tracing::warn!("{key} reported {} cache tags, keeping the first {}", tags.len(), self.config.max_tags_per_entry);
+            tags.truncate(self.config.max_tags_per_entry);
+        }
+
+        // triggers are for the request which rendered the page, not for every later hit
+        let mut headers: HeaderMap = parts.headers.clone();
+        for trigger in [HX_TRIGGER, HX_TRIGGER_AFTER_SETTLE, HX_TRIGGER_AFTER_SWAP] {
+            headers.remove(trigger);
+        }
+
+        let entry: Entry = Entry {
+            status: parts.status,
+            headers,
+            body: body.clone(),
+            tags,
+            stored_at: self.clock.now(),
+            ttl: if segment.is_some() { self.config.personalized_ttl } else { self.config.ttl },
+            stale: if segment.is_some() { Duration::ZERO } else { self.config.stale },
+            revalidating: false,
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+
+            if state.generation != generation {
+                tracing::debug!("{key} is not cached, a tag was invalidated while it rendered");
+                return Response::from_parts(parts, Body::from(body));
+            }
+
+            state.remove(&key);
+
+            while state.entries.len() >= self.config.max_entries.max(1) {
+                match state.oldest() {
+                    Some(oldest) => { state.remove(&oldest); },
+                    None => break,
+                }
+            }
+
+            for tag in entry.tags.iter() {
+                state.tags.entry(tag.clone()).or_default().insert(key.clone());
+            }
+
+            state.entries.insert(key, entry);
+        }
+
+        Response::from_parts(parts, Body::from(body))
+    }
+
+    /// Evict every entry tagged with `tag` (across routes), returns how many were evicted.
+    /// A large count on every write usually means the tag is too broad.
+    /// The CDN purge runs in the background, failures are logged.
+    pub fn invalidate_tag(&self, tag: &str) -> usize {
+        let mut state = self.state.lock().unwrap();
+
+        let keys: HashSet<String> = state.tags.remove(tag).unwrap_or_default();
+        state.generation += 1;
+
+        for key in keys.iter() {
+            state.remove(key);
+        }
+
+        tracing::info!(tag, evicted = keys.len(), "cache tag invalidated");
+
+        let purger: Arc<dyn CdnPurger> = self.purger.clone();
+        let tag: String = tag.to_owned();
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = purger.purge(std::slice::from_ref(&tag)).await {
+                        tracing::error!("unable to purge {tag} from the cdn: {e}");
+                    }
+                });
+            },
+            Err(_) => tracing::debug!(tag, "no runtime, cdn purge skipped"),
+        }
+
+        keys.len()
+    }
+
+    /// Number of cached entries currently tagged with `tag`.
+    pub fn tagged(&self, tag: &str) -> usize {
+        self.state.lock().unwrap().tags.get(tag).map(|keys| keys.len()).unwrap_or(0)
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.tags.clear();
+        state.generation += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Entry {
+    fn response(&self) -> Response<Body> {
+        let mut response: Response<Body> = Response::new(Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseCacheLayer {
+    cache: ResponseCache,
+}
+
+impl ResponseCacheLayer {
+    pub fn new(cache: ResponseCache) -> Self {
+        Self { cache }
+    }
+}
+
+impl<S> Layer<S> for ResponseCacheLayer {
+    type Service = ResponseCacheService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ResponseCacheService { inner, cache: self.cache.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct ResponseCacheService<S> {
+    inner: S,
+    cache: ResponseCache,
+}
+
+impl<S> Service<Request> for ResponseCacheService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let cache: ResponseCache = self.cache.clone();
+
+        let Some((key, segment)) = cache.key(&req) else {
+            return Box::pin(self.inner.call(req));
+        };
+
+        if req.method() == Method::HEAD {
+            return match cache.head(&key) {
+                Some(response) => Box::pin(async move { Ok(response) }),
+                None => Box::pin(self.inner.call(req)),
+            };
+        }
+
+        match cache.lookup(&key) {
+            Lookup::Fresh(response) => {
+                return Box::pin(async move { Ok(response) });
+            },
+            Lookup::Stale(response) => {
+                // stale while revalidate, the refreshed entry re-reports its tags
+                let (parts, _body) = req.into_parts();
+                let inner: S = self.inner.clone();
+                let generation: u64 = cache.generation();
+
+                tokio::spawn(async move {
+                    let request: Request = Request::from_parts(parts, Body::empty());
+
+                    // the error is dropped before awaiting the store, it needn't be Send
+                    let response: Option<Response<Body>> = inner.oneshot(request).await.ok();
+
+                    if let Some(response) = response {
+                        cache.store(key, segment, generation, response).await;
+                    }
+                });
+
+                return Box::pin(async move { Ok(response) });
+            },
+            Lookup::Miss => {}
+        }
+
+        let generation: u64 = cache.generation();
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let response: Response<Body> = inner.await?;
+
+            Ok(cache.store(key, segment, generation, response).await)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use axum_htmx::HX_TRIGGER;
+    use hyper::{header::CONTENT_LENGTH, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, config::Cache, context::ContextLayer, ContextAccessor};
+
+    use super::{ResponseCache, ResponseCacheLayer};
+
+    fn config() -> Cache {
+        Cache { enabled: true, ttl: Duration::from_secs(60), stale: Duration::from_secs(60), ..Default::default() }
+    }
+
+    async fn tagged(Extension(accessor): Extension<ContextAccessor>) -> &'static str {
+        accessor.context().await.cache_tag("invoice:42");
+        "invoice 42"
+    }
+
+    fn app(cache: &ResponseCache) -> Router {
+        Router::new()
+            .route("/invoices/42", get(tagged))
+            .route("/dashboard", get(tagged))
+            .route("/about", get(|| async { "about" }))
+            .layer(ContextLayer::new())
+            .layer(ResponseCacheLayer::new(cache.clone()))
+    }
+
+    async fn send(router: &Router, uri: &str) -> StatusCode {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_tag_across_routes() {
+        let cache = ResponseCache::new(config(), Arc::new(TestClock::new()));
+        let router = app(&cache);
+
+        for uri in ["/invoices/42", "/dashboard", "/about"] {
+            assert_eq!(send(&router, uri).await, StatusCode::OK);
+        }
+
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.tagged("invoice:42"), 2);
+
+        assert_eq!(cache.invalidate_tag("invoice:42"), 2);
+
+        // the untagged route survives
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.tagged("invoice:42"), 0);
+        assert_eq!(cache.invalidate_tag("invoice:42"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_revalidation_refreshes_tags() {
+        let clock = TestClock::new();
+        let cache = ResponseCache::new(config(), Arc::new(clock.clone()));
+        let renders = Arc::new(AtomicUsize::new(0));
+
+        let counter = renders.clone();
+        let router = Router::new()
+            .route("/invoices", get(move |Extension(accessor): Extension<ContextAccessor>| async move {
+                let render = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                accessor.context().await.cache_tag(format!("invoice:{render}"));
+                "invoices"
+            }))
+            .layer(ContextLayer::new())
+            .layer(ResponseCacheLayer::new(cache.clone()));
+
+        send(&router, "/invoices").await;
+        send(&router, "/invoices").await;
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+
+        // stale: served from the cache while the route renders again in the background
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(send(&router, "/invoices").await, StatusCode::OK);
+
+        for _ in 0..100 {
+            if cache.tagged("invoice:2") == 1 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(renders.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.tagged("invoice:1"), 0);
+        assert_eq!(cache.tagged("invoice:2"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_during_render_is_kept() {
+        let cache = ResponseCache::new(config(), Arc::new(TestClock::new()));
+        let renders = Arc::new(AtomicUsize::new(0));
+
+        let (counter, invalidated) = (renders.clone(), cache.clone());
+        let router = Router::new()
+            .route("/invoices/42", get(move |Extension(accessor): Extension<ContextAccessor>| async move {
+                accessor.context().await.cache_tag("invoice:42");
+
+                // the invoice changes while its first render is under way
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    invalidated.invalidate_tag("invoice:42");
+                }
+                "invoice 42"
+            }))
+            .layer(ContextLayer::new())
+            .layer(ResponseCacheLayer::new(cache.clone()));
+
+        send(&router, "/invoices/42").await;
+        assert!(cache.is_empty());
+
+        send(&router, "/invoices/42").await;
+        send(&router, "/invoices/42").await;
+        assert_eq!(renders.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.tagged("invoice:42"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_triggers_are_not_replayed() {
+        let cache = ResponseCache::new(config(), Arc::new(TestClock::new()));
+        let router = Router::new()
+            .route("/invoices", get(|| async { ([(HX_TRIGGER, "invoice:saved")], "invoices") }))
+            .layer(ResponseCacheLayer::new(cache.clone()));
+
+        let get = || async { router.clone().oneshot(Request::get("/invoices").body(Body::empty()).unwrap()).await.unwrap() };
+
+        assert_eq!(get().await.headers()[HX_TRIGGER], "invoice:saved");
+
+        let cached = get().await;
+        assert!(!cached.headers().contains_key(HX_TRIGGER));
+        assert_eq!(to_bytes(cached.into_body(), usize::MAX).await.unwrap(), "invoices");
+    }
+
+    #[tokio::test]
+    async fn test_head_from_the_stored_page() {
+        let cache = ResponseCache::new(config(), Arc::new(TestClock::new()));
+        let renders = Arc::new(AtomicUsize::new(0));
+
+        let counter = renders.clone();
+        let router = Router::new()
+            .route("/about", get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                "about us"
+            }))
+            .layer(ResponseCacheLayer::new(cache.clone()));
+
+        let head = |router: Router| async move {
+            router.oneshot(Request::head("/about").body(Body::empty()).unwrap()).await.unwrap()
+        };
+
+        // nothing stored yet, the route answers and HEAD stores nothing
+        head(router.clone()).await;
+        assert_eq!(renders.load(Ordering::SeqCst), 1);
+        assert!(cache.is_empty());
+
+        send(&router, "/about").await;
+
+        let response = head(router.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "8");
+        assert!(to_bytes(response.into_body(), usize::MAX).await.unwrap().is_empty());
+        assert_eq!(renders.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_entries() {
+        let cache = ResponseCache::new(Cache { max_entries: 2, ..config() }, Arc::new(TestClock::new()));
+        let router = app(&cache);
+
+        send(&router, "/invoices/42").await;
+        send(&router, "/dashboard").await;
+        send(&router, "/about").await;
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.tagged("invoice:42"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_personalized_per_identity() {
+        let cache = ResponseCache::new(Cache { personalized: true, ..config() }, Arc::new(TestClock::new()))
+            .identity(|request| request.headers().get("x-user").and_then(|user| user.to_str().ok()).map(str::to_owned));
+        let renders = Arc::new(AtomicUsize::new(0));
+
+        let counter = renders.clone();
+        let router = Router::new()
+            .route("/dashboard", get(move |Extension(accessor): Extension<ContextAccessor>, request: Request| async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let user = request.headers().get("x-user").map(|user| user.to_str().unwrap().to_owned());
+
+                match user {
+                    Some(user) => {
+                        accessor.context().await.cache_key_segment(user.clone());
+                        format!("hello {user}")
+                    },
+                    None => "hello stranger".to_owned(),
+                }
+            }))
+            .route("/undeclared", get(|| async { "for anyone?" }))
+            .layer(ContextLayer::new())
+            .layer(ResponseCacheLayer::new(cache.clone()));
+
+        async fn body(router: &Router, uri: &str, user: Option<&str>) -> String {
+            let mut request = Request::get(uri);
+            if let Some(user) = user {
+                request = request.header("x-user", user);
+            }
+
+            let response = router.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+            String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+        }
+
+        for _ in 0..2 {
+            assert_eq!(body(&router, "/dashboard", Some("ada")).await, "hello ada");
+            assert_eq!(body(&router, "/dashboard", Some("bob")).await, "hello bob");
+            assert_eq!(body(&router, "/dashboard", None).await, "hello stranger");
+        }
+
+        // one render per identity and one shared
+        assert_eq!(renders.load(Ordering::SeqCst), 3);
+        assert_eq!(cache.len(), 3);
+
+        // identified requests only store what was declared for them
+        body(&router, "/undeclared", Some("ada")).await;
+        assert_eq!(cache.len(), 3);
+    }
+}
@@ -0,0 +1,479 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    future::Future, net::IpAddr, pin::Pin,
+    task::{Context as TaskContext, Poll}
+};
+
+use async_trait::async_trait;
+use axum::{body::{Body, Bytes}, extract::Request, Router};
+use http_body_util::Full;
+use hyper::{
+    header::{HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+    Method, Response, StatusCode, Uri
+};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor
+};
+use serde_json::json;
+use tower::{Layer, Service};
+
+use crate::{cache::CacheTags, config::{Cdn, CdnPurge}, reload::Live};
+
+/// Response extension carrying the keys recorded with `Context::surrogate_key`.
+#[derive(Debug, Clone)]
+pub struct SurrogateKeys(pub Vec<String>);
+
+/// Which kind of router produced the response, matched by the class rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    Web,
+    Api,
+    Supplemental,
+    Static,
+}
+
+impl RouteClass {
+    fn name(&self) -> &'static str {
+        match self {
+            RouteClass::Web => "web",
+            RouteClass::Api => "api",
+            RouteClass::Supplemental => "supplemental",
+            RouteClass::Static => "static",
+        }
+    }
+}
+
+/// `Config.server.cdn` resolved for the layers, see `CdnLayer`.
+#[derive(Clone)]
+pub struct CdnPolicy {
+    // header sets are replaced by a config reload
+    config: Live<Cdn>,
+
+    // paths of the static class, the asset prefix
+    static_prefix: String,
+}
+
+impl CdnPolicy {
+    pub fn new(config: Cdn, static_prefix: impl Into<String>) -> Self {
+        Self::live(Live::new(config), static_prefix)
+    }
+
+    /// The policy of a config read on every response, see `ConfigReloader`.
+    pub fn live(config: Live<Cdn>, static_prefix: impl Into<String>) -> Self {
+        let cdn = config.load();
+
+        for rule in cdn.rules.iter() {
+            if !cdn.sets.contains_key(&rule.set) {
+                tracing::warn!("cdn rule for {} uses the unknown header set {}", rule.route, rule.set);
+            }
+        }
+
+        Self { config, static_prefix: static_prefix.into() }
+    }
+
+    /// Name of the header set applied to `path` served by a `class` router.
+    pub fn select(&self, class: RouteClass, path: &str) -> Option<String> {
+        select(&self.config.load(), class, path).map(|set| set.to_owned())
+    }
+
+    /// Headers for a response, `keys` are its cache tags and surrogate keys.
+    /// Headers referencing `{keys}` are skipped when there are none.
+    fn headers(&self, class: RouteClass, path: &str, keys: &[String]) -> Vec<(HeaderName, HeaderValue)> {
+        let config = self.config.load();
+
+        let joined: String = keys.join(" ");
+        let mut headers: Vec<(HeaderName, HeaderValue)> = Vec::new();
+
+        let set = select(&config, class, path).and_then(|set| config.sets.get(set));
+
+        for (name, template) in set.into_iter().flatten() {
+            if template.contains("{keys}") && keys.is_empty() {
+                continue;
+            }
+
+            let value: String = template
+                .replace("{keys}", &joined)
+                .replace("{path}", path);
+
+            match (HeaderName::try_from(name.as_str()), HeaderValue::from_str(&value)) {
+                (Ok(name), Ok(value)) => headers.push((name, value)),
+                _ => tracing::warn!("invalid cdn header {name}: {value}"),
+            }
+        }
+
+        let surrogate: &str = &config.surrogate_key_header;
+
+        if !surrogate.is_empty() && !keys.is_empty() && !headers.iter().any(|(name, _)| name.as_str().eq_ignore_ascii_case(surrogate)) {
+            if let (Ok(name), Ok(value)) = (HeaderName::try_from(surrogate), HeaderValue::from_str(&joined)) {
+                headers.push((name, value));
+            }
+        }
+
+        headers
+    }
+}
+
+/// Name of the header set of `config` applied to `path` served by a `class` router.
+fn select<'a>(config: &'a Cdn, class: RouteClass, path: &str) -> Option<&'a str> {
+    // (precedence, prefix length), the first declared rule wins a tie
+    let mut best: Option<((u8, usize), &str)> = None;
+
+    for rule in config.rules.iter() {
+        let rank: (u8, usize) = if rule.route == path {
+            (3, path.len())
+        } else if let Some(prefix) = rule.route.strip_suffix('*').filter(|_| rule.route.starts_with('/')) {
+            // `/invoices/*` covers `/invoices` as well
+            match path.starts_with(prefix) || prefix.strip_suffix('/') == Some(path) {
+                true => (2, prefix.len()),
+                false => continue,
+            }
+        } else if rule.route == class.name() {
+            (1, 0)
+        } else {
+            continue
+        };
+
+        if best.map(|(current, _)| rank > current).unwrap_or(true) {
+            best = Some((rank, rule.set.as_str()));
+        }
+    }
+
+    best.map(|(_, set)| set)
+}
+
+/// Apply the CDN policy to a feature router, a no-op when the policy is disabled.
+pub(crate) fn route_class(router: Router, policy: Option<&CdnPolicy>, class: RouteClass) -> Router {
+    match policy {
+        Some(policy) => router.layer(CdnLayer::new(policy.clone(), class)),
+        None => router,
+    }
+}
+
+/// Sets the policy's headers on responses, outside of the Context middleware
+/// so it sees the cache tags and surrogate keys. Headers set by the handler are kept.
+#[derive(Clone)]
+pub struct CdnLayer {
+    policy: CdnPolicy,
+    class: RouteClass,
+}
+
+impl CdnLayer {
+    pub fn new(policy: CdnPolicy, class: RouteClass) -> Self {
+        Self { policy, class }
+    }
+}
+
+impl<S> Layer<S> for CdnLayer {
+    type Service = CdnService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CdnService { inner, policy: self.policy.clone(), class: self.class }
+    }
+}
+
+#[derive(Clone)]
+pub struct CdnService<S> {
+    inner: S,
+    policy: CdnPolicy,
+    class: RouteClass,
+}
+
+impl<S> Service<Request> for CdnService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let path: String = req.uri().path().to_owned();
+
+        // the static layer wraps the whole router
+        if self.class == RouteClass::Static && !path.starts_with(&self.policy.static_prefix) {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let policy: CdnPolicy = self.policy.clone();
+        let class: RouteClass = self.class;
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response: Response<Body> = inner.await?;
+
+            let mut keys: Vec<String> = response.extensions().get::<CacheTags>()
+                .map(|tags| tags.0.clone())
+                .unwrap_or_default();
+
+            for key in response.extensions().get::<SurrogateKeys>().map(|keys| keys.0.clone()).unwrap_or_default() {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+
+            for (name, value) in policy.headers(class, &path, &keys) {
+                if !response.headers().contains_key(&name) {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PurgeError {
+    Endpoint(String),
+    Request(String),
+    Status(StatusCode),
+}
+
+impl Display for PurgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PurgeError::Endpoint(e) => write!(f, "invalid cdn purge endpoint: {e}"),
+            PurgeError::Request(e) => write!(f, "purge request failed: {e}"),
+            PurgeError::Status(status) => write!(f, "purge endpoint answered {status}"),
+        }
+    }
+}
+
+impl Error for PurgeError {}
+
+/// Evicts CDN copies by surrogate key, called by `ResponseCache::invalidate_tag`
+/// so the CDN and the local cache are invalidated together.
+#[async_trait]
+pub trait CdnPurger: Send + Sync {
+    async fn purge(&self, keys: &[String]) -> Result<(), PurgeError>;
+}
+
+/// Default purger, nothing sits in front of the app.
+pub struct NoopPurger;
+
+#[async_trait]
+impl CdnPurger for NoopPurger {
+    async fn purge(&self, _keys: &[String]) -> Result<(), PurgeError> {
+        Ok(())
+    }
+}
+
+/// POSTs `{"keys": [..]}` to a purge endpoint with an optional bearer token.
+/// Plain http only, put a vendor purger (or a local proxy) in front of https APIs.
+/// https endpoints are refused, and so is a token sent anywhere but to loopback.
+pub struct HttpPurger {
+    endpoint: Uri,
+    token: Option<String>,
+    client: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl HttpPurger {
+    pub fn new(endpoint: Uri, token: Option<String>) -> Result<Self, PurgeError> {
+        if endpoint.scheme_str() != Some("http") {
+            return Err(PurgeError::Endpoint(format!("{endpoint} is not plain http, put a local proxy in front of https APIs")));
+        }
+
+        // the token would cross the network in the clear
+        if token.is_some() && !is_loopback(&endpoint) {
+            return Err(PurgeError::Endpoint(format!("{endpoint} is not loopback, a token is only sent to a local proxy")));
+        }
+
+        Ok(Self {
+            endpoint,
+            token,
+            client: Client::builder(TokioExecutor::new()).build_http()
+        })
+    }
+
+    pub fn from_config(config: &CdnPurge) -> Option<Self> {
+        let purger: Result<Self, PurgeError> = config.endpoint.parse::<Uri>()
+            .map_err(|e| PurgeError::Endpoint(format!("{}: {e}", config.endpoint)))
+            .and_then(|endpoint| HttpPurger::new(endpoint, config.token.clone()));
+
+        match purger {
+            Ok(purger) => Some(purger),
+            Err(e) => {
+                tracing::error!("{e}, cdn purging is disabled");
+                None
+            }
+        }
+    }
+}
+
+fn is_loopback(endpoint: &Uri) -> bool {
+    match endpoint.host() {
+        Some("localhost") => true,
+        Some(host) => host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+#[async_trait]
+impl CdnPurger for HttpPurger {
+    async fn purge(&self, keys: &[String]) -> Result<(), PurgeError> {
+        let body: String = json!({ "keys": keys }).to_string();
+
+        let mut request = hyper::Request::builder()
+            .method(Method::POST)
+            .uri(self.endpoint.clone())
+            .header(CONTENT_TYPE, "application/json");
+
+        if let Some(token) = self.token.as_ref() {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        let request = request.body(Full::new(Bytes::from(body)))
+            .map_err(|e| PurgeError::Request(e.to_string()))?;
+
+        let response = self.client.request(request).await
+            .map_err(|e| PurgeError::Request(e.to_string()))?;
+
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(PurgeError::Status(response.status())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::{Arc, Mutex}, time::Duration};
+
+    use async_trait::async_trait;
+    use axum::{body::Body, extract::Request, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, config::{Cache, Cdn, CdnPurge, CdnRule}, context::ContextLayer, ResponseCache, ContextAccessor};
+
+    use super::{route_class, CdnPolicy, CdnPurger, HttpPurger, PurgeError, RouteClass};
+
+    fn rule(route: &str, set: &str) -> CdnRule {
+        CdnRule { route: route.to_owned(), set: set.to_owned() }
+    }
+
+    fn policy() -> CdnPolicy {
+        let mut sets: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        sets.insert("pages".to_owned(), BTreeMap::from([
+            ("surrogate-control".to_owned(), "max-age=300".to_owned()),
+        ]));
+        sets.insert("invoices".to_owned(), BTreeMap::from([
+            ("cdn-cache-control".to_owned(), "max-age=60".to_owned()),
+            ("cache-tag".to_owned(), "{keys}".to_owned()),
+        ]));
+        sets.insert("about".to_owned(), BTreeMap::from([
+            ("cdn-cache-control".to_owned(), "max-age=86400".to_owned()),
+        ]));
+
+        CdnPolicy::new(Cdn {
+            enabled: true,
+            sets,
+            rules: vec![
+                rule("web", "pages"),
+                rule("/invoices/*", "invoices"),
+                rule("/invoices/archive/*", "about"),
+                rule("/about", "about"),
+            ],
+            ..Default::default()
+        }, "/web")
+    }
+
+    #[test]
+    fn test_rule_precedence() {
+        let policy = policy();
+
+        assert_eq!(policy.select(RouteClass::Web, "/dashboard").as_deref(), Some("pages"));
+        assert_eq!(policy.select(RouteClass::Api, "/dashboard").as_deref(), None);
+        assert_eq!(policy.select(RouteClass::Web, "/invoices/42").as_deref(), Some("invoices"));
+        assert_eq!(policy.select(RouteClass::Web, "/invoices").as_deref(), Some("invoices"));
+        assert_eq!(policy.select(RouteClass::Web, "/invoicesx").as_deref(), Some("pages"));
+        assert_eq!(policy.select(RouteClass::Api, "/invoices/42").as_deref(), Some("invoices"));
+        assert_eq!(policy.select(RouteClass::Web, "/invoices/archive/2023").as_deref(), Some("about"));
+        assert_eq!(policy.select(RouteClass::Web, "/about").as_deref(), Some("about"));
+    }
+
+    #[tokio::test]
+    async fn test_dynamic_keys() {
+        let router = Router::new()
+            .route("/invoices/42", get(|Extension(accessor): Extension<ContextAccessor>| async move {
+                let mut ctx = accessor.context().await;
+                ctx.cache_tag("invoice:42");
+                ctx.surrogate_key("customer:7");
+                "invoice 42"
+            }))
+            .route("/invoices", get(|| async { "invoices" }))
+            .layer(ContextLayer::new());
+
+        let router = route_class(router, Some(&policy()), RouteClass::Web);
+
+        let request = Request::builder().uri("/invoices/42").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()["cdn-cache-control"], "max-age=60");
+        assert_eq!(response.headers()["cache-tag"], "invoice:42 customer:7");
+        assert_eq!(response.headers()["surrogate-key"], "invoice:42 customer:7");
+
+        // no keys, the templated header is left out
+        let request = Request::builder().uri("/invoices").body(Body::empty()).unwrap();
+        let response = router.oneshot(request).await.unwrap();
+
+        assert_eq!(response.headers()["cdn-cache-control"], "max-age=60");
+        assert!(response.headers().get("cache-tag").is_none());
+        assert!(response.headers().get("surrogate-key").is_none());
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingPurger(Arc<Mutex<Vec<Vec<String>>>>);
+
+    #[async_trait]
+    impl CdnPurger for RecordingPurger {
+        async fn purge(&self, keys: &[String]) -> Result<(), PurgeError> {
+            self.0.lock().unwrap().push(keys.to_vec());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_purge_on_invalidation() {
+        let purger = RecordingPurger::default();
+        let cache = ResponseCache::new(Cache { enabled: true, ..Default::default() }, Arc::new(TestClock::new()))
+            .with_purger(Arc::new(purger.clone()));
+
+        cache.invalidate_tag("invoice:42");
+
+        for _ in 0..100 {
+            if !purger.0.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        assert_eq!(*purger.0.lock().unwrap(), vec![vec!["invoice:42".to_owned()]]);
+    }
+
+    #[test]
+    fn test_purge_endpoints() {
+        let purger = |endpoint: &str, token: Option<&str>| HttpPurger::from_config(&CdnPurge {
+            endpoint: endpoint.to_owned(),
+            token: token.map(|token| token.to_owned()),
+        });
+
+        assert!(purger("http://cdn.internal/purge", None).is_some());
+        assert!(purger("http://127.0.0.1:8080/purge", Some("secret")).is_some());
+        assert!(purger("http://[::1]:8080/purge", Some("secret")).is_some());
+        assert!(purger("http://localhost/purge", Some("secret")).is_some());
+
+        // never sent over plain http to another host, nor to an https API over plain http
+        assert!(purger("http://cdn.example/purge", Some("secret")).is_none());
+        assert!(purger("https://api.cdn.example/purge", Some("secret")).is_none());
+        assert!(purger("https://api.cdn.example/purge", None).is_none());
+    }
+}
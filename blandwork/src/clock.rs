@@ -0,0 +1,242 @@
+use std::{
+    future::Future, pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime}
+};
+
+use tokio::sync::oneshot;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Source of time for framework timers (TTLs, expiry, windows).
+/// Tracing timestamps and request durations always use real time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    fn sleep_until(&self, deadline: SystemTime) -> Sleep;
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep_until(&self, deadline: SystemTime) -> Sleep {
+        let duration: Duration = deadline.duration_since(SystemTime::now()).unwrap_or_default();
+
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Human readable age: "just now", "5 minutes ago", "2 days ago".
+pub fn relative_time(elapsed: Duration) -> String {
+    let seconds: u64 = elapsed.as_secs();
+
+    let (value, unit) = match seconds {
+        0..=59 => return "just now".to_owned(),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    match value {
+        1 => format!("1 {unit} ago"),
+        _ => format!("{value} {unit}s ago"),
+    }
+}
+
+/// Days since the unix epoch to (year, month 1-12, day), proleptic Gregorian calendar.
+/// http://howardhinnant.github.io/date_algorithms.html
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z: i64 = days + 719468;
+    let era: i64 = z.div_euclid(146097);
+    let doe: i64 = z - era * 146097;
+    let yoe: i64 = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy: i64 = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp: i64 = (5 * doy + 2) / 153;
+    let day: u32 = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month: u32 = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+
+    (yoe + era * 400 + (month <= 2) as i64, month, day)
+}
+
+/// Inverse of `civil_from_days`.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year: i64 = if month <= 2 { year - 1 } else { year };
+    let era: i64 = year.div_euclid(400);
+    let yoe: i64 = year - era * 400;
+    let mp: i64 = (month as i64 + 9) % 12;
+    let doy: i64 = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe: i64 = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+struct TestClockState {
+    now: SystemTime,
+    sleepers: Vec<(SystemTime, oneshot::Sender<()>)>,
+}
+
+/// Manually advanced clock for tests, advancing wakes every sleep whose deadline has passed.
+///
+/// ```
+/// use std::time::Duration;
+/// use blandwork::{Clock, TestClock};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let clock = TestClock::new();
+///
+/// // a session which expires in one hour
+/// let expires_at = clock.now() + Duration::from_secs(3600);
+/// let expiry = tokio::spawn(clock.sleep_until(expires_at));
+///
+/// assert!(clock.now() < expires_at);
+///
+/// clock.advance(Duration::from_secs(3600));
+///
+/// assert!(clock.now() >= expires_at);
+/// expiry.await.unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TestClock {
+    state: Arc<Mutex<TestClockState>>
+}
+
+impl TestClock {
+    pub fn new() -> Self {
+        Self::at(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+    }
+
+    pub fn at(now: SystemTime) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(TestClockState { now, sleepers: Vec::new() }))
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.now += duration;
+
+        let now: SystemTime = state.now;
+        let sleepers = std::mem::take(&mut state.sleepers);
+
+        for (deadline, waker) in sleepers {
+            match deadline <= now {
+                // the sleeper may have been dropped already
+                true => { let _ = waker.send(()); },
+                false => state.sleepers.push((deadline, waker)),
+            }
+        }
+    }
+
+    /// Number of sleeps waiting for the clock to move.
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().sleepers.len()
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> SystemTime {
+        self.state.lock().unwrap().now
+    }
+
+    fn sleep_until(&self, deadline: SystemTime) -> Sleep {
+        let mut state = self.state.lock().unwrap();
+
+        if deadline <= state.now {
+            return Box::pin(async {});
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        state.sleepers.push((deadline, sender));
+
+        Box::pin(async move {
+            let _ = receiver.await;
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{civil_from_days, days_from_civil, relative_time, Clock, TestClock};
+
+    #[tokio::test]
+    async fn test_advance_wakes_due_sleeps() {
+        let clock = TestClock::new();
+
+        let short = tokio::spawn(clock.sleep(Duration::from_secs(10)));
+        let long = tokio::spawn(clock.sleep(Duration::from_secs(100)));
+
+        assert_eq!(clock.pending(), 2);
+
+        clock.advance(Duration::from_secs(10));
+        short.await.unwrap();
+
+        assert_eq!(clock.pending(), 1);
+        assert!(!long.is_finished());
+
+        clock.advance(Duration::from_secs(90));
+        long.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_past_deadline_is_ready() {
+        let clock = TestClock::new();
+        let past = clock.now() - Duration::from_secs(1);
+
+        clock.sleep_until(past).await;
+
+        assert_eq!(clock.pending(), 0);
+    }
+
+    #[test]
+    fn test_relative_time() {
+        assert_eq!(relative_time(Duration::from_secs(5)), "just now");
+        assert_eq!(relative_time(Duration::from_secs(60)), "1 minute ago");
+        assert_eq!(relative_time(Duration::from_secs(5 * 60 + 30)), "5 minutes ago");
+        assert_eq!(relative_time(Duration::from_secs(2 * 3600)), "2 hours ago");
+        assert_eq!(relative_time(Duration::from_secs(3 * 86400)), "3 days ago");
+    }
+
+    #[test]
+    fn test_clones_share_time() {
+        let clock = TestClock::new();
+        let other = clock.clone();
+        let start = clock.now();
+
+        other.advance(Duration::from_secs(3600));
+
+        assert_eq!(clock.now().duration_since(start).unwrap(), Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11240), (2000, 10, 10));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+
+        for days in [-1, 0, 59, 11240, 19782, 19783, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
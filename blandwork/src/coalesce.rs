@@ -0,0 +1,148 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}
+};
+
+use maud::Markup;
+use tokio::sync::OnceCell;
+
+type Flight = Arc<OnceCell<Markup>>;
+
+/// Identifies a render which is the same for every visitor.
+/// There is deliberately no key derived from the request: anything personalized
+/// (user, locale, permissions, cookies) must not be coalesced.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoalesceKey(String);
+
+impl CoalesceKey {
+    /// `CoalesceKey::shared(format!("pricing:{plan}"))`
+    pub fn shared(key: impl Into<String>) -> Self {
+        Self(key.into())
+    }
+}
+
+/// Single-flight for expensive non-personalized renders, shared by the app as
+/// `Extension<RenderCoalescer>`. Concurrent renders of the same key wait for the
+/// first one instead of rendering N times (the herd after a cache expiry),
+/// nothing is kept once the render finished, see `ResponseCache` for that.
+///
+/// ```ignore
+/// async fn pricing(Extension(renders): Extension<RenderCoalescer>) -> Markup {
+///     renders.render(CoalesceKey::shared("pricing"), || async { expensive_pricing_table().await }).await
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct RenderCoalescer {
+    flights: Arc<Mutex<HashMap<CoalesceKey, Flight>>>,
+
+    // renders answered by another request's computation
+    coalesced: Arc<AtomicU64>,
+}
+
+impl RenderCoalescer {
+    pub async fn render<F, Fut>(&self, key: CoalesceKey, render: F) -> Markup
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Markup>,
+    {
+        let flight: Flight = self.flights.lock().unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+
+        let mut rendered: bool = false;
+
+        // a cancelled first render is taken over by the next waiter
+        let markup: Markup = flight.get_or_init(|| async {
+            rendered = true;
+            render().await
+        }).await.clone();
+
+        if rendered {
+            let mut flights = self.flights.lock().unwrap();
+
+            if flights.get(&key).is_some_and(|current| Arc::ptr_eq(current, &flight)) {
+                flights.remove(&key);
+            }
+        } else {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+        }
+
+        markup
+    }
+
+    /// Renders answered by a concurrent identical render so far.
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// Renders currently in progress.
+    pub fn in_flight(&self) -> usize {
+        self.flights.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+    use maud::html;
+    use tokio::sync::Semaphore;
+
+    use super::{CoalesceKey, RenderCoalescer};
+
+    #[tokio::test]
+    async fn test_concurrent_renders_share_one() {
+        let renders = RenderCoalescer::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Semaphore::new(0));
+
+        let pages = (0..4).map(|_| {
+            let (renders, runs, release) = (renders.clone(), runs.clone(), release.clone());
+
+            tokio::spawn(async move {
+                renders.render(CoalesceKey::shared("pricing"), || async {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    let _ = release.acquire().await;
+                    html! { p { "pricing" } }
+                }).await
+            })
+        }).collect::<Vec<_>>();
+
+        while runs.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // let every request join the flight before it lands
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        release.add_permits(1);
+
+        for page in pages {
+            assert_eq!(page.await.unwrap().into_string(), "<p>pricing</p>");
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(renders.coalesced(), 3);
+        assert_eq!(renders.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_keys_and_sequential_renders() {
+        let renders = RenderCoalescer::default();
+        let runs = AtomicUsize::new(0);
+
+        for key in ["pricing", "pricing", "about"] {
+            renders.render(CoalesceKey::shared(key), || async {
+                runs.fetch_add(1, Ordering::SeqCst);
+                html! { p { (key) } }
+            }).await;
+        }
+
+        // nothing is kept between flights
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(renders.coalesced(), 0);
+    }
+}
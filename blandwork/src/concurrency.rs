@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap, error::Error, fmt::Display,
+    sync::{Arc, Mutex as SyncMutex, Weak},
+    time::Duration
+};
+
+use axum::response::{IntoResponse, Response};
+use hyper::{HeaderMap, StatusCode};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::transform::BodyTransform;
+
+/// Per target request sequence stamped by the integration script (`server.sequencing`)
+/// and echoed by `ContextService`, the script drops responses older than the last one
+/// it swapped into that target.
+pub const SEQUENCE: &str = "x-blandwork-seq";
+
+/// Enables the sequence stamping of the integration script, added to full pages.
+pub(crate) fn sequence_meta() -> BodyTransform {
+    Arc::new(|_, html| match html.find("</head>") {
+        Some(index) => {
+            let mut html: String = html;
+            html.insert_str(index, "<meta name=\"blandwork-sequence\">");
+            html
+        },
+        None => html,
+    })
+}
+
+type Identity = Arc<dyn Fn(&HeaderMap) -> Option<String> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SectionError {
+    /// Another request held the key for longer than the wait, answered with 409.
+    Timeout(String),
+
+    // `Context::serialize_on` outside of a built App
+    Unavailable,
+}
+
+impl Display for SectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SectionError::Timeout(key) => write!(f, "timed out waiting for {key}"),
+            SectionError::Unavailable => write!(f, "critical sections are only available in a built App"),
+        }
+    }
+}
+
+impl Error for SectionError {}
+
+impl IntoResponse for SectionError {
+    fn into_response(self) -> Response {
+        match self {
+            SectionError::Timeout(_) => (StatusCode::CONFLICT, "This is being saved by another request, try again.").into_response(),
+            SectionError::Unavailable => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+}
+
+/// Keys of a requester whose mutations run one at a time, see `Context::serialize_on`.
+/// Waiting requests enter in arrival order, one waiting longer than `wait` gets a 409.
+///
+/// ```ignore
+/// App::new(config, template)
+///     .critical_sections(CriticalSections::new().identity(|headers| session_id(headers)))
+/// ```
+#[derive(Clone)]
+pub struct CriticalSections {
+    // released keys are dropped on the next entry
+    locks: Arc<SyncMutex<HashMap<String, Weak<Mutex<()>>>>>,
+
+    // scopes keys to the requester, None shares them between everyone
+    identity: Identity,
+
+    wait: Duration,
+}
+
+impl Default for CriticalSections {
+    fn default() -> Self {
+        Self {
+            locks: Arc::new(SyncMutex::new(HashMap::new())),
+            identity: Arc::new(|_: &HeaderMap| None),
+            wait: Duration::from_secs(5),
+        }
+    }
+}
+
+impl CriticalSections {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How the requester (their session) is identified, e.g. from a session cookie.
+    pub fn identity(mut self, identity: impl Fn(&HeaderMap) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+
+    /// Longest a request waits for a key before giving up with a 409.
+    pub fn wait(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Wait for `key` of the requester, it is held until the returned guard is dropped.
+    pub async fn enter(&self, headers: &HeaderMap, key: &str) -> Result<Serialized, SectionError> {
+        let scoped: String = match (self.identity)(headers) {
+            Some(owner) => format!("{owner}:{key}"),
+            None => key.to_owned(),
+        };
+
+        let lock: Arc<Mutex<()>> = {
+            let mut locks = self.locks.lock().unwrap();
+            locks.retain(|_, lock| lock.strong_count() > 0);
+
+            match locks.get(&scoped).and_then(Weak::upgrade) {
+                Some(lock) => lock,
+                None => {
+                    let lock: Arc<Mutex<()>> = Arc::new(Mutex::new(()));
+                    locks.insert(scoped, Arc::downgrade(&lock));
+                    lock
+                }
+            }
+        };
+
+        match tokio::time::timeout(self.wait, lock.lock_owned()).await {
+            Ok(guard) => Ok(Serialized { _guard: guard }),
+            Err(_) => Err(SectionError::Timeout(key.to_owned())),
+        }
+    }
+}
+
+/// A held key of `CriticalSections`, released on drop.
+pub struct Serialized {
+    _guard: OwnedMutexGuard<()>,
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{Arc, Mutex}, time::Duration};
+
+    use axum::{body::Body, extract::{Path, Request}, routing::post, Extension, Router};
+    use axum_htmx::HX_REQUEST;
+    use hyper::StatusCode;
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{CriticalSections, SectionError, SEQUENCE};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    // saves of one key, `log` records when each started and finished
+    #[derive(Clone)]
+    struct Saves {
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Feature for Saves {
+        fn supplemental(&self) -> Option<Router> {
+            Some(Router::new()
+                .route("/save/:name", post(save))
+                .layer(Extension(self.clone())))
+        }
+    }
+
+    async fn save(
+        Extension(saves): Extension<Saves>,
+        Extension(accessor): Extension<ContextAccessor>,
+        Path(name): Path<String>
+    ) -> Result<Markup, SectionError> {
+        let _section = accessor.context().await.serialize_on("invoice:42").await?;
+
+        saves.log.lock().unwrap().push(format!("{name} start"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        saves.log.lock().unwrap().push(format!("{name} end"));
+
+        Ok(html! { "saved" })
+    }
+
+    fn app(saves: &Saves, wait: Duration) -> Router {
+        let mut app = App::new(Config::default(), TestTemplate)
+            .critical_sections(CriticalSections::new().wait(wait))
+            .register_feature(saves.clone());
+
+        router(&app.build())
+    }
+
+    fn request(name: &str) -> Request {
+        Request::post(format!("/save/{name}"))
+            .header(HX_REQUEST, "true")
+            .header(SEQUENCE, "7")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_serialize_on_queues() {
+        let saves = Saves { log: Arc::new(Mutex::new(Vec::new())) };
+        let router = app(&saves, Duration::from_secs(5));
+
+        let (a, b) = tokio::join!(router.clone().oneshot(request("a")), router.clone().oneshot(request("b")));
+        assert_eq!(a.unwrap().status(), StatusCode::OK);
+        assert_eq!(b.unwrap().status(), StatusCode::OK);
+
+        // one after the other, never interleaved
+        let log = saves.log.lock().unwrap().clone();
+        assert_eq!(log.len(), 4);
+        assert!(log[0].ends_with("start") && log[1].ends_with("end"));
+        assert_eq!(log[0].split(' ').next(), log[1].split(' ').next());
+        assert_eq!(log[2].split(' ').next(), log[3].split(' ').next());
+    }
+
+    #[tokio::test]
+    async fn test_serialize_on_timeout() {
+        let saves = Saves { log: Arc::new(Mutex::new(Vec::new())) };
+        let router = app(&saves, Duration::from_millis(10));
+
+        let (a, b) = tokio::join!(router.clone().oneshot(request("a")), router.clone().oneshot(request("b")));
+        let mut statuses = vec![a.unwrap().status(), b.unwrap().status()];
+        statuses.sort();
+
+        assert_eq!(statuses, vec![StatusCode::OK, StatusCode::CONFLICT]);
+        assert_eq!(saves.log.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_echo() {
+        let saves = Saves { log: Arc::new(Mutex::new(Vec::new())) };
+        let response = app(&saves, Duration::from_secs(5)).oneshot(request("a")).await.unwrap();
+
+        assert_eq!(response.headers()[SEQUENCE], "7");
+    }
+}
@@ -1,18 +1,69 @@
 use std::{
+    collections::BTreeMap,
     error::Error, 
     fs::File, 
-    io::{BufReader, Read}
+    io::{BufReader, Read}, 
+    time::Duration
 };
 
 use serde::Deserialize;
 
-#[derive(Deserialize, Clone, Debug, Default)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Database {
     pub host: String,
     pub database: String,
     pub port: u32,
     pub username: String,
     pub password: String,
+
+    /// Most connections the pool opens, 2 behind PgBouncer, more for heavy workloads.
+    #[serde(default = "pool_max_size")]
+    pub pool_max_size: u32,
+
+    /// Connections the pool keeps open, at least `warmup.min_idle` when warming up.
+    #[serde(default)]
+    pub pool_min_idle: Option<u32>,
+
+    /// Waiting longer for a connection is an error, a cold start against an unreachable
+    /// database fails instead of hanging.
+    #[serde(default = "pool_connection_timeout", deserialize_with = "crate::units::duration::deserialize")]
+    pub pool_connection_timeout: Duration,
+
+    #[serde(default)]
+    pub warmup: Warmup,
+
+    #[serde(default)]
+    pub pool: Pooling,
+
+    #[serde(default)]
+    pub logging: QueryLogging,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            database: String::new(),
+            port: 0,
+            username: String::new(),
+            password: String::new(),
+            pool_max_size: pool_max_size(),
+            pool_min_idle: None,
+            pool_connection_timeout: pool_connection_timeout(),
+            warmup: Default::default(),
+            pool: Default::default(),
+            logging: Default::default(),
+        }
+    }
+}
+
+fn pool_max_size() -> u32 {
+    10
+}
+
+// bb8's own default
+fn pool_connection_timeout() -> Duration {
+    Duration::from_secs(30)
 }
 
 impl Database {
@@ -27,72 +78,1375 @@ impl Database {
     }
 }
 
+/// Connections established by `App::connect` before traffic is accepted.
+/// Startup fails when the database can't be reached within `retries` attempts.
 #[derive(Deserialize, Clone, Debug)]
-pub struct Server {
-    pub host: String,
-    pub port: i32,
+#[serde(default)]
+pub struct Warmup {
+    pub enabled: bool,
+
+    // the pool keeps at least as many open, see `Database::pool_min_idle`
+    pub min_idle: u32,
+    pub retries: u32,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub backoff: Duration,
 }
 
-impl Default for Server {
+impl Default for Warmup {
     fn default() -> Self {
         Self { 
-            host: "0.0.0.0".to_owned(), 
-            port: 3001
+            enabled: false, 
+            min_idle: 2,
+            retries: 3,
+            backoff: Duration::from_secs(1)
         }
     }
 }
 
+/// Replacement of connections the server or a NAT may have dropped.
+/// Every `reaper_rate` the pool closes connections idle for longer than `idle_timeout`
+/// (the default stays under the ~350s idle limit of common NATs) or open for longer than
+/// `max_lifetime`, whichever comes first, and reconnects up to `pool_min_idle`.
+/// A connection may outlive either by up to `reaper_rate`, `0s` disables a limit.
+/// `test_on_check_out` pings a connection before handing it out.
 #[derive(Deserialize, Clone, Debug)]
-pub struct Config {
-    pub database: Database,
-    pub server: Server
+#[serde(default)]
+pub struct Pooling {
+    pub test_on_check_out: bool,
+
+    #[serde(deserialize_with = "crate::units::option_duration::deserialize")]
+    pub idle_timeout: Option<Duration>,
+
+    #[serde(deserialize_with = "crate::units::option_duration::deserialize")]
+    pub max_lifetime: Option<Duration>,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub reaper_rate: Duration,
 }
 
-impl Default for Config {
+impl Default for Pooling {
+    fn default() -> Self {
+        Self {
+            test_on_check_out: true,
+            idle_timeout: Some(Duration::from_secs(5 * 60)),
+            max_lifetime: Some(Duration::from_secs(30 * 60)),
+            reaper_rate: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Queries slower than `slow` are logged, their parameters only with `include_params`.
+/// In development a request running more than `max_queries` queries is reported (N+1).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct QueryLogging {
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub slow: Duration,
+
+    pub include_params: bool,
+    pub max_queries: u32,
+}
+
+impl Default for QueryLogging {
+    fn default() -> Self {
+        Self {
+            slow: Duration::from_millis(500),
+            include_params: false,
+            max_queries: 20,
+        }
+    }
+}
+
+/// Development only browser refresh when watched files change.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LiveReload {
+    pub enabled: bool,
+
+    // directories (or files) polled for modifications
+    pub watch: Vec<String>,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub interval: Duration,
+}
+
+impl Default for LiveReload {
     fn default() -> Self {
         Self { 
-            database: Default::default(),
-            server: Default::default() 
+            enabled: true, 
+            watch: vec!["web".to_owned(), "templates".to_owned()],
+            interval: Duration::from_millis(500)
         }
     }
 }
 
-impl Config {
-    pub fn from_path(path: &str) -> Result<Self, Box<dyn Error>> {
-        let file: File = File::open(path)?;
+/// Development only response inspection, each rule can be switched off individually.
+/// `strict` turns violations into 500 responses (useful for CI runs of the tests).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct HeaderAudit {
+    pub enabled: bool,
+    pub strict: bool,
+    pub content_length: bool,
+    pub content_type: bool,
+    pub cookie_cache: bool,
+    pub duplicate_headers: bool,
+    pub vary: bool,
+    pub hsts: bool,
+}
 
-        // Wrap the file in a BufReader to efficiently read the file line by line
-        let mut reader: BufReader<File> = BufReader::new(file);
-    
-        // Iterate over each line in the file
-        let mut buffer: String = String::new();
-        reader.read_to_string(&mut buffer)?;
+impl Default for HeaderAudit {
+    fn default() -> Self {
+        Self { 
+            enabled: true,
+            strict: false,
+            content_length: true,
+            content_type: true,
+            cookie_cache: true,
+            duplicate_headers: true,
+            vary: true,
+            hsts: true
+        }
+    }
+}
 
-        let config: Config = toml::from_str(&buffer)?;
-        Ok(config)
+/// Static asset URLs, `manifest` is the `manifest.json` written by the frontend build.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Assets {
+    pub prefix: String,
+    pub manifest: Option<String>,
+
+    // features declaring one head asset in different versions, see `Feature::head_assets`
+    pub conflicts: AssetConflicts,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self { 
+            prefix: "/web".to_owned(), 
+            manifest: Some("web/dist/manifest.json".to_owned()),
+            conflicts: AssetConflicts::default(),
+        }
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::Config;
+/// `error` refuses to start naming both features, `highest` serves the highest version with a warning.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetConflicts {
+    #[default]
+    Error,
+    Highest,
+}
 
-    #[test]
-    fn test_config() {
-        let config: Config = toml::from_str(r#"
-            [database]
-            host = 'HOSTNAME'
-            port = 1234
-            database = 'DB_NAME'
-            username = 'USERNAME'
-            password = 'PASSWORD'
+/// Directories overlaid at one URL path, `roots` are searched in order and the first
+/// match is served, e.g. `{ path = "/web", roots = ["themes/acme/web", "web"] }`.
+/// `negotiate = false` serves images as named instead of their AVIF/WebP siblings.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ContentMount {
+    pub path: String,
+    pub roots: Vec<String>,
 
-            [server]
-            host = 'HOSTNAME'
-            port = 1234
-        "#).unwrap();
+    #[serde(default = "negotiate_content")]
+    pub negotiate: bool,
+}
 
-        println!("{:#?}", config);
+impl Default for ContentMount {
+    fn default() -> Self {
+        Self { path: String::new(), roots: Vec::new(), negotiate: negotiate_content() }
+    }
+}
+
+fn negotiate_content() -> bool {
+    true
+}
+
+/// Shared response cache for GET pages, off by default.
+/// Entries older than `ttl` are served while revalidating for up to `stale` longer,
+/// `ctx.cache_tag` lets mutations evict exactly the pages that rendered an entity.
+/// With `personalized` the pages of identified requests are cached per identity instead,
+/// for `personalized_ttl`, see `Context::cache_key_segment`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Cache {
+    pub enabled: bool,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub ttl: Duration,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub stale: Duration,
+
+    // bounds the entries and with them the tag index
+    pub max_entries: usize,
+    pub max_tags_per_entry: usize,
+
+    pub personalized: bool,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub personalized_ttl: Duration,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self { 
+            enabled: false,
+            ttl: Duration::from_secs(60),
+            stale: Duration::from_secs(60),
+            max_entries: 1000,
+            max_tags_per_entry: 32,
+            personalized: false,
+            personalized_ttl: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Response headers for a CDN in front of the app, off by default.
+/// `rules` map a route class (`web`, `api`, `supplemental`, `static`), a path (`/about`)
+/// or a prefix (`/invoices/*`, also matching `/invoices`) to one of the named header `sets`. Paths beat prefixes,
+/// longer prefixes beat shorter ones and prefixes beat classes.
+/// Values may use `{keys}` (the response's surrogate keys) and `{path}`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Cdn {
+    pub enabled: bool,
+    pub sets: BTreeMap<String, BTreeMap<String, String>>,
+    pub rules: Vec<CdnRule>,
+
+    // cache tags and `ctx.surrogate_key` values, empty disables
+    pub surrogate_key_header: String,
+
+    // invoked by `ResponseCache::invalidate_tag`
+    pub purge: Option<CdnPurge>,
+}
+
+impl Default for Cdn {
+    fn default() -> Self {
+        Self { 
+            enabled: false,
+            sets: BTreeMap::new(),
+            rules: Vec::new(),
+            surrogate_key_header: "Surrogate-Key".to_owned(),
+            purge: None
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CdnRule {
+    pub route: String,
+    pub set: String,
+}
+
+/// Generic purge endpoint, receives `{"keys": [..]}` with a bearer `token`. Plain http,
+/// the token only to a loopback proxy, see `HttpPurger`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct CdnPurge {
+    pub endpoint: String,
+    pub token: Option<String>,
+}
+
+/// Encodings produced for uploaded images.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+/// Responsive image variants, `widths` larger than the original are skipped
+/// and every variant is also produced in the original format.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Images {
+    // url prefix stored files are served from
+    pub prefix: String,
+    pub widths: Vec<u32>,
+    pub formats: Vec<ImageFormat>,
+}
+
+impl Default for Images {
+    fn default() -> Self {
+        Self { 
+            prefix: "/media".to_owned(),
+            widths: vec![320, 640, 1280],
+            formats: vec![ImageFormat::Webp],
+        }
+    }
+}
+
+/// Upload quotas in bytes, None is unlimited. `tenants` overrides `tenant_bytes`
+/// for a tenant, `features` caps what one feature stores per tenant.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Quotas {
+    pub tenant_bytes: Option<u64>,
+    pub tenants: BTreeMap<String, u64>,
+    pub features: BTreeMap<String, u64>,
+}
+
+/// Log filtering, `filter` uses the `RUST_LOG` syntax (which overrides it when set),
+/// `targets` adds per-target levels: `"sqlx" = "warn"` or `"[feature{name=Billing}]" = "debug"`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Logging {
+    pub filter: String,
+    pub targets: BTreeMap<String, String>,
+
+    // mounts POST /_blandwork/log-level, bearer protected, outside of development as well
+    pub token: Option<String>,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self { 
+            filter: "info".to_owned(),
+            targets: BTreeMap::new(),
+            token: None,
+        }
+    }
+}
+
+/// Requests over these limits are rejected before routing,
+/// 431 for headers and 414 for the URI.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Limits {
+    pub max_headers: usize,
+
+    // total of every header name and value
+    #[serde(deserialize_with = "crate::units::byte_size::deserialize")]
+    pub max_header_bytes: u64,
+
+    // path and query
+    pub max_uri_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { 
+            max_headers: 100,
+            max_header_bytes: 16 * 1024,
+            max_uri_length: 8 * 1024
+        }
+    }
+}
+
+/// Typed cookies, `key` signs cookies declared `SIGNED`.
+/// With `trust_proxy` an `X-Forwarded-Proto: https` request gets `Secure` cookies.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Cookies {
+    pub key: Option<String>,
+    pub trust_proxy: bool,
+}
+
+/// Format of the per request access log lines.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    #[default]
+    None,
+    Combined,
+    Json,
+}
+
+/// Machine readable access log written to stdout, off by default.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct AccessLog {
+    pub format: AccessLogFormat,
+}
+
+/// The framework's core middleware, each can be turned off when a parent application
+/// already provides it (double compression, conflicting CORS headers).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct CoreLayers {
+    pub cors: bool,
+    pub compression: bool,
+    pub trace: bool,
+    pub timeout: bool,
+
+    // handler panics answered with `Template::error`, see `CatchPanicLayer`
+    pub catch_panic: bool,
+}
+
+impl Default for CoreLayers {
+    fn default() -> Self {
+        Self { cors: true, compression: true, trace: true, timeout: true, catch_panic: true }
+    }
+}
+
+/// `Feature::schedule` jobs. Cron expressions are evaluated in `timezone`,
+/// `UTC` (the default) or a fixed offset like `+02:00`. Named zones and daylight
+/// saving changes are not supported, pick the offset (or UTC) the jobs should follow.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Schedule {
+    pub timezone: String,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self { timezone: "UTC".to_owned() }
+    }
+}
+
+/// Offline tolerant submissions: the integration script queues hx-post/put requests which
+/// failed on the network and replays them every `retry` (and when the browser is back online),
+/// responses to keyed submissions are kept for `idempotency_ttl` so a replay is answered once.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Offline {
+    pub enabled: bool,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub retry: Duration,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub idempotency_ttl: Duration,
+}
+
+impl Default for Offline {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retry: Duration::from_secs(30),
+            idempotency_ttl: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// Shells chosen by whether the request has a user (`CurrentUser`), by name, see
+/// `Template::shell`. Unset, the template's `page` frames both.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Shells {
+    pub anonymous_shell: Option<String>,
+    pub authenticated_shell: Option<String>,
+}
+
+/// Out of order swap protection: the integration script stamps requests with a sequence
+/// per target and drops responses older than the last one swapped into it.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Sequencing {
+    pub enabled: bool,
+}
+
+/// Reloading the config file at runtime (`App::config_file`) on SIGHUP, `token` also mounts
+/// `/_blandwork/config` behind it as a bearer token (mounted unauthenticated in development).
+/// Only `[features]`, `server.logging` levels, `server.limits` and `server.cdn.sets` are reloaded.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct ConfigReload {
+    pub token: Option<String>,
+}
+
+/// Morph swaps: serves the morph extension script and lets `Context::swap_morph` and the
+/// built-in helpers ask for `morph:` swaps. In development morphed fragments are checked
+/// for repeated ids and list items without one.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Morph {
+    pub enabled: bool,
+}
+
+/// Screen reader announcements of `Context::announce`. `live_regions` adds the two (empty)
+/// ARIA live regions to every full page and swaps a fragment's announcements into them,
+/// without them announcements are sent as the `blandwork:announce` trigger alone.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Announcements {
+    pub live_regions: bool,
+}
+
+/// WebSocket handshakes of `ws_route`: a browser's `Origin` has to match the request's host
+/// or be one of `origins` (`https://app.example`), others are refused with 403.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Sockets {
+    pub origins: Vec<String>,
+}
+
+/// Runtime feature switches: `token` mounts the bearer protected `/_blandwork/features`
+/// endpoint, pages of a disabled feature redirect to `redirect` instead of answering 503.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Toggles {
+    pub token: Option<String>,
+    pub redirect: Option<String>,
+}
+
+/// Request recording for reproducing bugs, see `RecordingLayer`. Every request is
+/// recorded in development, the `sample` fraction of them in production.
+/// Values of `redact_headers`, and of `redact_fields` in form and JSON bodies,
+/// are replaced before a record is kept. `dir` also writes each record to a file.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Recording {
+    pub enabled: bool,
+    pub sample: f64,
+    pub capacity: usize,
+    pub max_body: usize,
+    pub dir: Option<String>,
+    pub redact_headers: Vec<String>,
+    pub redact_fields: Vec<String>,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample: 0.01,
+            capacity: 200,
+            max_body: 64 * 1024,
+            dir: None,
+            redact_headers: ["cookie", "authorization", "proxy-authorization", "x-api-key"].map(String::from).to_vec(),
+            redact_fields: ["password", "token", "secret", "csrf"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// Class the shell puts on `<html>` before any script runs, see `Context::theme_class`.
+/// The `cookie` picks one of `themes`, first-time visitors and unknown values get `default`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Theme {
+    pub default: String,
+    pub cookie: String,
+    pub themes: Vec<String>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            default: "light".to_owned(),
+            cookie: "theme".to_owned(),
+            themes: vec!["light".to_owned(), "dark".to_owned()],
+        }
+    }
+}
+
+/// What a trigger payload containing a `NaN` or infinite float turns into, JSON has no
+/// representation for them.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NonFinite {
+    /// The trigger is logged as an error and left out of the response.
+    #[default]
+    Reject,
+    /// The number is sent as `null`.
+    Null,
+}
+
+/// Serialization of `HX-Trigger` payloads, see `Context::add_trigger`.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct TriggerData {
+    pub non_finite: NonFinite,
+}
+
+/// Sites allowed to embed a widget, see `RouteGroup::widget`.
+/// `frame_ancestors` may frame it (`Content-Security-Policy: frame-ancestors`),
+/// `origins` may fetch it for a script injected div (CORS).
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct WidgetEmbed {
+    pub frame_ancestors: Vec<String>,
+    pub origins: Vec<String>,
+}
+
+/// Embeddable widgets: `key` verifies the embed tokens (`EmbedTokens`), without it every
+/// widget request is refused. `embeds` are keyed by widget name, `[server.widgets.embeds.booking]`.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Widgets {
+    pub key: Option<String>,
+    pub embeds: BTreeMap<String, WidgetEmbed>,
+}
+
+/// Where `Jobs` are queued: `memory` (the default) is lost on restart, `postgres`
+/// is shared by every instance of a connected app.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobBackend {
+    #[default]
+    Memory,
+    Postgres,
+}
+
+/// Background jobs, see `Jobs`. Workers claim up to `batch` due jobs every `poll`,
+/// a job running longer than `lease` is assumed abandoned and claimed again.
+/// `token` enables the `JOBS_ROUTE` admin endpoint.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct JobQueue {
+    pub backend: JobBackend,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub poll: Duration,
+
+    pub batch: usize,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub lease: Duration,
+
+    pub token: Option<String>,
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self {
+            backend: JobBackend::Memory,
+            poll: Duration::from_secs(1),
+            batch: 10,
+            lease: Duration::from_secs(300),
+            token: None,
+        }
+    }
+}
+
+/// Honeypot and time-trap on public forms, see `BotGuard`.
+/// Submissions faster than `min_elapsed` or older than `max_age` are rejected,
+/// `proof_of_work` is the number of leading zero bits the browser has to find (0 disables it).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BotGuard {
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub min_elapsed: Duration,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub max_age: Duration,
+
+    pub proof_of_work: u8,
+}
+
+impl Default for BotGuard {
+    fn default() -> Self {
+        Self {
+            min_elapsed: Duration::from_secs(3),
+            max_age: Duration::from_secs(24 * 60 * 60),
+            proof_of_work: 0,
+        }
+    }
+}
+
+/// Timeout of the paths matching `route`, exact (`/reports/yearly`) or a prefix ending in `*`
+/// (`/export/*`). Paths without an entry keep `server.request_timeout_secs`, if any.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct RouteTimeout {
+    pub route: String,
+
+    #[serde(deserialize_with = "crate::units::duration::deserialize")]
+    pub timeout: Duration,
+}
+
+fn request_timeout_secs() -> u64 {
+    10
+}
+
+/// How `/users/` relates to `/users`.
+/// `strict` keeps them distinct, `redirect` answers 308 with the path
+/// without the slash and `ignore` routes both to the same handler.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    #[default]
+    Strict,
+    Redirect,
+    Ignore,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Server {
+    pub host: String,
+    pub port: i32,
+
+    #[serde(default)]
+    pub live_reload: LiveReload,
+
+    #[serde(default)]
+    pub header_audit: HeaderAudit,
+
+    #[serde(default)]
+    pub assets: Assets,
+
+    #[serde(default)]
+    pub content: Vec<ContentMount>,
+
+    #[serde(default)]
+    pub cache: Cache,
+
+    #[serde(default)]
+    pub images: Images,
+
+    #[serde(default)]
+    pub quotas: Quotas,
+
+    #[serde(default)]
+    pub logging: Logging,
+
+    #[serde(default)]
+    pub limits: Limits,
+
+    #[serde(default)]
+    pub cookies: Cookies,
+
+    #[serde(default)]
+    pub bot_guard: BotGuard,
+
+    #[serde(default)]
+    pub trailing_slash: TrailingSlash,
+
+    #[serde(default)]
+    pub cdn: Cdn,
+
+    #[serde(default)]
+    pub access_log: AccessLog,
+
+    #[serde(default)]
+    pub schedule: Schedule,
+
+    #[serde(default)]
+    pub core_layers: CoreLayers,
+
+    /// Timeout of every request in seconds, `route_timeouts` override it per path.
+    /// `0` leaves the paths without a route timeout unbounded (streaming endpoints).
+    #[serde(default = "request_timeout_secs")]
+    pub request_timeout_secs: u64,
+
+    #[serde(default)]
+    pub route_timeouts: Vec<RouteTimeout>,
+
+    #[serde(default)]
+    pub offline: Offline,
+
+    #[serde(default)]
+    pub morph: Morph,
+
+    #[serde(default)]
+    pub announcements: Announcements,
+
+    #[serde(default)]
+    pub toggles: Toggles,
+
+    #[serde(default)]
+    pub recording: Recording,
+
+    #[serde(default)]
+    pub theme: Theme,
+
+    #[serde(default)]
+    pub triggers: TriggerData,
+
+    #[serde(default)]
+    pub widgets: Widgets,
+
+    #[serde(default)]
+    pub jobs: JobQueue,
+
+    #[serde(default)]
+    pub sequencing: Sequencing,
+
+    #[serde(default)]
+    pub shells: Shells,
+
+    #[serde(default)]
+    pub reload: ConfigReload,
+
+    #[serde(default)]
+    pub sockets: Sockets,
+}
+
+impl Server {
+    /// `request_timeout_secs`, None when disabled.
+    pub fn request_timeout(&self) -> Option<Duration> {
+        match self.request_timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self { 
+            host: "0.0.0.0".to_owned(), 
+            port: 3001,
+            live_reload: Default::default(),
+            header_audit: Default::default(),
+            assets: Default::default(),
+            content: Default::default(),
+            cache: Default::default(),
+            images: Default::default(),
+            quotas: Default::default(),
+            logging: Default::default(),
+            limits: Default::default(),
+            cookies: Default::default(),
+            bot_guard: Default::default(),
+            trailing_slash: Default::default(),
+            cdn: Default::default(),
+            access_log: Default::default(),
+            schedule: Default::default(),
+            core_layers: Default::default(),
+            request_timeout_secs: request_timeout_secs(),
+            route_timeouts: Default::default(),
+            offline: Default::default(),
+            morph: Default::default(),
+            announcements: Default::default(),
+            toggles: Default::default(),
+            recording: Default::default(),
+            theme: Default::default(),
+            triggers: Default::default(),
+            widgets: Default::default(),
+            jobs: Default::default(),
+            sequencing: Default::default(),
+            shells: Default::default(),
+            reload: Default::default(),
+            sockets: Default::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    Development,
+    #[default]
+    Production,
+
+    /// Demos and integration tests without external services, see `Config::ephemeral`.
+    Ephemeral,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "ConfigFile")]
+pub struct Config {
+    pub database: Database,
+    pub server: Server,
+    pub environment: Environment,
+
+    /// Named flags for gating UI, `[features] beta_ui = true`, see `Context::feature_enabled`.
+    pub features: BTreeMap<String, bool>,
+
+    /// The tables of `[features]`, `[features.billing] stripe_key = "..."`, parsed by the
+    /// feature claiming them, see `Feature::config_schema`.
+    pub feature_sections: BTreeMap<String, toml::Table>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { 
+            database: Default::default(),
+            server: Default::default(),
+            environment: Default::default(),
+            features: Default::default(),
+            feature_sections: Default::default(),
+        }
+    }
+}
+
+// the file as written, `[features]` holds flags and feature sections side by side
+#[derive(Deserialize)]
+struct ConfigFile {
+    database: Database,
+    server: Server,
+
+    #[serde(default)]
+    environment: Environment,
+
+    #[serde(default)]
+    features: BTreeMap<String, toml::Value>,
+}
+
+impl TryFrom<ConfigFile> for Config {
+    type Error = String;
+
+    fn try_from(file: ConfigFile) -> Result<Self, Self::Error> {
+        let mut features: BTreeMap<String, bool> = BTreeMap::new();
+        let mut feature_sections: BTreeMap<String, toml::Table> = BTreeMap::new();
+
+        for (name, value) in file.features {
+            match value {
+                toml::Value::Boolean(enabled) => { features.insert(name, enabled); },
+                toml::Value::Table(section) => { feature_sections.insert(name, section); },
+                value => return Err(format!("features.{name}: expected a flag or a table, found {}", value.type_str())),
+            }
+        }
+
+        Ok(Self { database: file.database, server: file.server, environment: file.environment, features, feature_sections })
+    }
+}
+
+impl Config {
+    pub fn is_development(&self) -> bool {
+        self.environment == Environment::Development
+    }
+
+    pub fn is_ephemeral(&self) -> bool {
+        self.environment == Environment::Ephemeral
+    }
+
+    /// The `ephemeral` profile: jobs are queued in memory whatever `server.jobs.backend` says
+    /// and the server listens on a free port, printed by `run`.
+    pub fn ephemeral(mut self) -> Self {
+        self.environment = Environment::Ephemeral;
+        self.server.jobs.backend = JobBackend::Memory;
+        self.server.port = 0;
+        self
+    }
+
+    /// A flag missing from `[features]` is off.
+    pub fn feature_enabled(&self, name: &str) -> bool {
+        self.features.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn from_path(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file: File = File::open(path)?;
+
+        // Wrap the file in a BufReader to efficiently read the file line by line
+        let mut reader: BufReader<File> = BufReader::new(file);
+    
+        // Iterate over each line in the file
+        let mut buffer: String = String::new();
+        reader.read_to_string(&mut buffer)?;
+
+        let config: Config = toml::from_str(&buffer)?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, JobBackend};
+
+    #[test]
+    fn test_config() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+        "#).unwrap();
+
+        println!("{:#?}", config);
+    }
+
+    #[test]
+    fn test_config_warmup() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [database.warmup]
+            enabled = true
+            min_idle = 4
+            backoff = '250ms'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+        "#).unwrap();
+
+        assert!(config.database.warmup.enabled);
+        assert_eq!(config.database.warmup.min_idle, 4);
+        assert_eq!(config.database.warmup.retries, 3);
+        assert_eq!(config.database.warmup.backoff, std::time::Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_config_pool_reaping() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [database.pool]
+            idle_timeout = '2m'
+            max_lifetime = '0s'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+        "#).unwrap();
+
+        assert!(config.database.pool.test_on_check_out);
+        assert_eq!(config.database.pool.idle_timeout, Some(std::time::Duration::from_secs(120)));
+        assert_eq!(config.database.pool.max_lifetime, None);
+        assert_eq!(config.database.pool.reaper_rate, std::time::Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_config_pool_size() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            pool_max_size = 2
+            pool_min_idle = 1
+            pool_connection_timeout = '5s'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+        "#).unwrap();
+
+        assert_eq!(config.database.pool_max_size, 2);
+        assert_eq!(config.database.pool_min_idle, Some(1));
+        assert_eq!(config.database.pool_connection_timeout, std::time::Duration::from_secs(5));
+        assert_eq!(Config::default().database.pool_max_size, 10);
+    }
+
+    #[test]
+    fn test_config_query_logging() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [database.logging]
+            slow = '200ms'
+            max_queries = 50
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+        "#).unwrap();
+
+        assert_eq!(config.database.logging.slow, std::time::Duration::from_millis(200));
+        assert!(!config.database.logging.include_params);
+        assert_eq!(config.database.logging.max_queries, 50);
+    }
+
+    #[test]
+    fn test_config_feature_flags() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [features]
+            beta_ui = true
+            dark_mode = false
+        "#).unwrap();
+
+        assert!(config.feature_enabled("beta_ui"));
+        assert!(!config.feature_enabled("dark_mode"));
+        assert!(!config.feature_enabled("missing"));
+    }
+
+    #[test]
+    fn test_config_feature_sections() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [features]
+            beta_ui = true
+
+            [features.billing]
+            stripe_key = 'sk_test'
+        "#).unwrap();
+
+        assert!(config.feature_enabled("beta_ui"));
+        assert!(!config.feature_enabled("billing"));
+        assert_eq!(config.feature_sections["billing"]["stripe_key"].as_str(), Some("sk_test"));
+    }
+
+    #[test]
+    fn test_config_route_timeouts() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [[server.route_timeouts]]
+            route = '/export/*'
+            timeout = '120s'
+        "#).unwrap();
+
+        assert_eq!(config.server.route_timeouts, vec![super::RouteTimeout { route: "/export/*".to_owned(), timeout: std::time::Duration::from_secs(120) }]);
+    }
+
+    #[test]
+    fn test_config_quotas() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [server.quotas]
+            tenant_bytes = 1048576
+
+            [server.quotas.features]
+            Avatars = 4096
+        "#).unwrap();
+
+        assert_eq!(config.server.quotas.tenant_bytes, Some(1048576));
+        assert!(config.server.quotas.tenants.is_empty());
+        assert_eq!(config.server.quotas.features["Avatars"], 4096);
+    }
+
+    #[test]
+    fn test_config_content_mounts() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [[server.content]]
+            path = '/web'
+            roots = ['themes/acme/web', 'web']
+
+            [[server.content]]
+            path = '/media'
+            roots = ['media']
+            negotiate = false
+        "#).unwrap();
+
+        assert_eq!(config.server.content.len(), 2);
+        assert_eq!(config.server.content[0].path, "/web");
+        assert_eq!(config.server.content[0].roots, vec!["themes/acme/web", "web"]);
+        assert!(config.server.content[0].negotiate);
+        assert!(!config.server.content[1].negotiate);
+    }
+
+    #[test]
+    fn test_config_core_layers() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [server.core_layers]
+            cors = false
+            compression = false
+        "#).unwrap();
+
+        assert!(!config.server.core_layers.cors);
+        assert!(!config.server.core_layers.compression);
+        assert!(config.server.core_layers.trace);
+        assert!(config.server.core_layers.timeout);
+    }
+
+    #[test]
+    fn test_config_theme() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [server.theme]
+            default = 'dark'
+            themes = ['dark', 'light', 'sepia']
+        "#).unwrap();
+
+        assert_eq!(config.server.theme.default, "dark");
+        assert_eq!(config.server.theme.cookie, "theme");
+        assert_eq!(config.server.theme.themes.len(), 3);
+        assert_eq!(Config::default().server.theme.default, "light");
+    }
+
+    #[test]
+    fn test_config_widgets() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [server.widgets]
+            key = 'secret'
+
+            [server.widgets.embeds.booking]
+            frame_ancestors = ['https://customer.example']
+        "#).unwrap();
+
+        assert_eq!(config.server.widgets.key.as_deref(), Some("secret"));
+        assert_eq!(config.server.widgets.embeds["booking"].frame_ancestors, vec!["https://customer.example"]);
+        assert!(config.server.widgets.embeds["booking"].origins.is_empty());
+        assert!(Config::default().server.widgets.key.is_none());
+    }
+
+    #[test]
+    fn test_config_jobs() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [server.jobs]
+            backend = 'postgres'
+            poll = '5s'
+        "#).unwrap();
+
+        assert_eq!(config.server.jobs.backend, JobBackend::Postgres);
+        assert_eq!(config.server.jobs.poll, std::time::Duration::from_secs(5));
+        assert_eq!(config.server.jobs.batch, 10);
+        assert_eq!(Config::default().server.jobs.backend, JobBackend::Memory);
+    }
+
+    #[test]
+    fn test_config_request_timeout() {
+        let config: Config = toml::from_str(r#"
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+            request_timeout_secs = 0
+        "#).unwrap();
+
+        assert_eq!(config.server.request_timeout(), None);
+        assert_eq!(Config::default().server.request_timeout(), Some(std::time::Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_config_ephemeral() {
+        let config: Config = toml::from_str(r#"
+            environment = 'ephemeral'
+
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [server.jobs]
+            backend = 'postgres'
+        "#).unwrap();
+
+        assert!(config.is_ephemeral());
+
+        let config: Config = config.ephemeral();
+        assert_eq!(config.server.jobs.backend, JobBackend::Memory);
+        assert_eq!(config.server.port, 0);
+    }
+
+    #[test]
+    fn test_config_environment() {
+        let config: Config = toml::from_str(r#"
+            environment = 'development'
+
+            [database]
+            host = 'HOSTNAME'
+            port = 1234
+            database = 'DB_NAME'
+            username = 'USERNAME'
+            password = 'PASSWORD'
+
+            [server]
+            host = 'HOSTNAME'
+            port = 1234
+
+            [server.live_reload]
+            watch = ['web/dist']
+            interval = '1s'
+        "#).unwrap();
+
+        assert!(config.is_development());
+        assert_eq!(config.server.live_reload.watch, vec!["web/dist".to_owned()]);
+        assert_eq!(config.server.live_reload.interval, std::time::Duration::from_secs(1));
+        assert!(!Config::default().is_development());
     }
 
     #[test]
@@ -0,0 +1,351 @@
+use std::{
+    convert::Infallible, future::Future, path::PathBuf, pin::Pin,
+    task::{Context as TaskContext, Poll}
+};
+
+use axum::{body::Body, extract::Request, http::{request::Parts, HeaderMap, HeaderValue, Uri}};
+use hyper::{
+    header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY},
+    Method, Response, StatusCode
+};
+use sha2::{Digest, Sha256};
+use tower::{Service, ServiceExt};
+use tower_http::services::ServeDir;
+
+use crate::config::{ContentMount, ImageFormat};
+
+/// Several directories served at one URL prefix, searched in order: the first root
+/// holding the requested file answers. A theme or branding directory listed before
+/// the defaults shadows single files without copying the whole tree.
+///
+/// JPEG and PNG requests are negotiated: a client accepting `image/avif` or `image/webp`
+/// gets `photo.jpg.avif` or `photo.jpg.webp` when the root serving `photo.jpg` holds one.
+/// URLs (and the asset manifest) keep naming the original.
+///
+/// ```ignore
+/// router.nest_service("/web", ContentOverlay::new().root("themes/acme/web").root("web"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ContentOverlay {
+    roots: Vec<PathBuf>,
+    negotiate: bool,
+}
+
+impl Default for ContentOverlay {
+    fn default() -> Self {
+        Self { roots: Vec::new(), negotiate: true }
+    }
+}
+
+impl ContentOverlay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve AVIF/WebP siblings of images to clients accepting them, on by default.
+    pub fn negotiate(mut self, enabled: bool) -> Self {
+        self.negotiate = enabled;
+        self
+    }
+
+    /// Add a root searched after the ones added before it.
+    pub fn root(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.roots.push(dir.into());
+        self
+    }
+
+    pub fn from_config(mount: &ContentMount) -> Self {
+        mount.roots.iter()
+            .fold(Self::new(), |overlay, root| overlay.root(root))
+            .negotiate(mount.negotiate)
+    }
+}
+
+/// Formats the client takes in place of a JPEG or PNG, preferred first. Only explicit
+/// types count, browsers send `*/*` whether or not they decode AVIF.
+fn accepted_variants(headers: &HeaderMap) -> Vec<ImageFormat> {
+    let mut accepted: Vec<(f32, ImageFormat)> = Vec::new();
+
+    let ranges = headers.get_all(ACCEPT).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+
+    for range in ranges {
+        let mut params = range.split(';').map(str::trim);
+
+        let format: ImageFormat = match params.next().map(str::to_ascii_lowercase).as_deref() {
+            Some("image/avif") => ImageFormat::Avif,
+            Some("image/webp") => ImageFormat::Webp,
+            _ => continue,
+        };
+
+        let q: f32 = params
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+
+        if q > 0.0 {
+            accepted.push((q, format));
+        }
+    }
+
+    // stable, AVIF stays ahead of WebP at equal quality
+    accepted.sort_by_key(|(_, format)| *format != ImageFormat::Avif);
+    accepted.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    accepted.into_iter().map(|(_, format)| format).collect()
+}
+
+/// The sibling `{path}.{extension}` of the requested file, query included.
+fn variant_uri(uri: &Uri, format: ImageFormat) -> Option<Uri> {
+    let path_and_query: String = match uri.query() {
+        Some(query) => format!("{}.{}?{query}", uri.path(), format.extension()),
+        None => format!("{}.{}", uri.path(), format.extension()),
+    };
+
+    Uri::builder().path_and_query(path_and_query).build().ok()
+}
+
+/// Weak validator of a negotiated response, distinct per variant of the same image.
+fn etag(response: &Response<Body>, format: ImageFormat) -> Option<HeaderValue> {
+    let modified: &HeaderValue = response.headers().get(LAST_MODIFIED)?;
+    let length: &HeaderValue = response.headers().get(CONTENT_LENGTH)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(modified.as_bytes());
+    hasher.update(b"|");
+    hasher.update(length.as_bytes());
+
+    let digest: String = hasher.finalize()[..8].iter().map(|byte| format!("{byte:02x}")).collect();
+
+    HeaderValue::from_str(&format!("W/\"{digest}-{}\"", format.extension())).ok()
+}
+
+fn none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+
+    // weak comparison, the W/ prefix is ignored on both sides
+    let opaque: &str = etag.trim_start_matches("W/");
+
+    headers.get_all(IF_NONE_MATCH).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == opaque)
+}
+
+/// Serve the best accepted variant the root holds, else the original. `None` when
+/// the root has neither.
+async fn negotiated(root: &PathBuf, parts: &Parts, original: ImageFormat, variants: &[ImageFormat]) -> Option<Response<Body>> {
+    // ServeDir only knows dates, entity tags are checked below
+    let mut head: Parts = parts.clone();
+    if head.headers.contains_key(IF_NONE_MATCH) {
+        head.headers.remove(IF_MODIFIED_SINCE);
+    }
+
+    let candidates = variants.iter()
+        .filter_map(|format| variant_uri(&parts.uri, *format).map(|uri| (*format, Some(uri))))
+        .chain(std::iter::once((original, None)));
+
+    for (format, uri) in candidates {
+        let mut request: Request = Request::from_parts(head.clone(), Body::empty());
+        if let Some(uri) = uri {
+            *request.uri_mut() = uri;
+        }
+
+        let response = match ServeDir::new(root).oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        };
+
+        if response.status() == StatusCode::NOT_FOUND {
+            continue;
+        }
+
+        let mut response: Response<Body> = response.map(Body::new);
+
+        if response.status() == StatusCode::OK {
+            response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(format.mime()));
+
+            if let Some(etag) = etag(&response, format) {
+                if none_match(&parts.headers, &etag) {
+                    let mut not_modified: Response<Body> = Response::builder()
+                        .status(StatusCode::NOT_MODIFIED)
+                        .body(Body::empty())
+                        .unwrap();
+
+                    if let Some(modified) = response.headers().get(LAST_MODIFIED) {
+                        not_modified.headers_mut().insert(LAST_MODIFIED, modified.clone());
+                    }
+                    response = not_modified;
+                }
+
+                response.headers_mut().insert(ETAG, etag);
+            }
+        }
+
+        return Some(response);
+    }
+
+    None
+}
+
+impl Service<Request> for ContentOverlay {
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let roots: Vec<PathBuf> = self.roots.clone();
+
+        // JPEG and PNG depend on Accept, even for clients that get the original
+        let negotiable: Option<ImageFormat> = ImageFormat::from_key(req.uri().path())
+            .filter(|format| matches!(format, ImageFormat::Jpeg | ImageFormat::Png))
+            .filter(|_| self.negotiate && matches!(*req.method(), Method::GET | Method::HEAD));
+
+        Box::pin(async move {
+            // static files have no body worth keeping, every root gets the same head
+            let (parts, _) = req.into_parts();
+
+            if let Some(original) = negotiable {
+                let variants: Vec<ImageFormat> = accepted_variants(&parts.headers);
+
+                for root in roots.iter() {
+                    if let Some(mut response) = negotiated(root, &parts, original, &variants).await {
+                        response.headers_mut().append(VARY, HeaderValue::from_static("Accept"));
+                        return Ok(response);
+                    }
+                }
+
+                let mut response: Response<Body> = Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap();
+                response.headers_mut().append(VARY, HeaderValue::from_static("Accept"));
+                return Ok(response);
+            }
+
+            for root in roots {
+                let request: Request = Request::from_parts(parts.clone(), Body::empty());
+                let response = ServeDir::new(root).oneshot(request).await?;
+
+                if response.status() != StatusCode::NOT_FOUND {
+                    return Ok(response.map(Body::new));
+                }
+            }
+
+            Ok(Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use axum::{body::{to_bytes, Body}, extract::Request, Router};
+    use hyper::{header::{HeaderName, ACCEPT, CONTENT_TYPE, ETAG, IF_NONE_MATCH, VARY}, Response, StatusCode};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use super::ContentOverlay;
+
+    fn tree(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blandwork-content-{}", Uuid::new_v4()));
+
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        dir
+    }
+
+    async fn get(router: &Router, uri: &str) -> (StatusCode, String) {
+        let response = router.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_override_shadows_base() {
+        let base = tree(&[("css/site.css", "base site"), ("js/main.js", "base main")]);
+        let theme = tree(&[("css/site.css", "theme site")]);
+
+        let router = Router::new().nest_service("/web", ContentOverlay::new().root(&theme).root(&base));
+
+        assert_eq!(get(&router, "/web/css/site.css").await, (StatusCode::OK, "theme site".to_owned()));
+        assert_eq!(get(&router, "/web/js/main.js").await, (StatusCode::OK, "base main".to_owned()));
+        assert_eq!(get(&router, "/web/missing.css").await.0, StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(base).unwrap();
+        fs::remove_dir_all(theme).unwrap();
+    }
+
+    fn header(response: &Response<Body>, name: HeaderName) -> String {
+        response.headers().get(name).map(|value| value.to_str().unwrap().to_owned()).unwrap_or_default()
+    }
+
+    // status, content type, etag, vary and body of /web/photo.jpg
+    async fn image(router: &Router, accept: &str) -> (StatusCode, String, String, String, String) {
+        let request = Request::get("/web/photo.jpg").header(ACCEPT, accept).body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+
+        let (status, content_type, etag, vary) =
+            (response.status(), header(&response, CONTENT_TYPE), header(&response, ETAG), header(&response, VARY));
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, content_type, etag, vary, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_image_negotiation() {
+        let root = tree(&[("photo.jpg", "jpeg"), ("photo.jpg.avif", "avif"), ("photo.jpg.webp", "webp"), ("logo.png", "png")]);
+        let router = Router::new().nest_service("/web", ContentOverlay::new().root(&root));
+
+        let avif = image(&router, "image/avif,image/webp,image/apng,*/*;q=0.8").await;
+        assert_eq!((avif.0, avif.1.as_str(), avif.4.as_str()), (StatusCode::OK, "image/avif", "avif"));
+        assert_eq!(avif.3, "Accept");
+
+        let webp = image(&router, "image/webp,*/*").await;
+        assert_eq!((webp.1.as_str(), webp.4.as_str()), ("image/webp", "webp"));
+
+        // q=0 refuses a format, wildcards never admit one
+        let jpeg = image(&router, "image/avif;q=0,image/*").await;
+        assert_eq!((jpeg.1.as_str(), jpeg.4.as_str()), ("image/jpeg", "jpeg"));
+        assert_eq!(jpeg.3, "Accept");
+
+        // every representation has its own validator
+        assert!(avif.2.starts_with("W/\"") && avif.2.ends_with("-avif\""));
+        assert_ne!(avif.2, webp.2);
+        assert_ne!(webp.2, jpeg.2);
+
+        let request = Request::get("/web/photo.jpg")
+            .header(ACCEPT, "image/webp")
+            .header(IF_NONE_MATCH, &webp.2)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::NOT_MODIFIED);
+
+        let request = Request::get("/web/photo.jpg")
+            .header(ACCEPT, "image/avif")
+            .header(IF_NONE_MATCH, &webp.2)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(router.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+
+        // images without siblings, and other files, are served as named
+        assert_eq!(get(&router, "/web/logo.png").await, (StatusCode::OK, "png".to_owned()));
+
+        let plain = Router::new().nest_service("/web", ContentOverlay::new().root(&root).negotiate(false));
+        let off = image(&plain, "image/avif").await;
+        assert_eq!((off.1.as_str(), off.3.as_str(), off.4.as_str()), ("image/jpeg", "", "jpeg"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,43 +1,101 @@
 use std::{
-    collections::HashMap, 
+    collections::{BTreeMap, HashMap}, 
     fmt::Display, future::Future, pin::Pin, 
-    sync::Arc, task::{Context as TaskContext, Poll}
+    sync::Arc, task::{Context as TaskContext, Poll}, time::SystemTime
 };
 use tokio::sync::{Mutex, MutexGuard};
 
-use axum::{extract::Request, http::HeaderValue};
-use axum_htmx::{HX_BOOSTED, HX_REQUEST, HX_TRIGGER};
-use hyper::{HeaderMap, Response};
+use axum::{extract::{MatchedPath, Request}, http::HeaderValue, response::IntoResponse};
+use axum::body::{to_bytes, Body};
+use axum_htmx::{HX_BOOSTED, HX_CURRENT_URL, HX_REQUEST, HX_RESWAP, HX_RETARGET, HX_TRIGGER, HX_TRIGGER_AFTER_SETTLE, HX_TRIGGER_AFTER_SWAP};
+use hyper::{header::{CONTENT_TYPE, REFERER, SET_COOKIE, USER_AGENT}, HeaderMap, Method, Response, StatusCode};
+use maud::{html, Markup};
 use serde::{ser::SerializeMap, Serialize};
 use serde_json::to_string;
 use tower::{Layer, Service};
 use uuid::Uuid;
 
+#[cfg(feature = "postgres")]
+use crate::db::{QueryCache, QueryStats, QueryTotals};
+use crate::{
+    a11y::{announcements_oob, Announcement, Focus, Politeness, ANNOUNCE, FOCUS},
+    assets::AssetManifest, clock::{SharedClock, SystemClock}, cache::{CacheSegment, CacheTags}, cdn::SurrogateKeys, memo::Memo,
+    concurrency::{CriticalSections, SectionError, Serialized, SEQUENCE},
+    cookies::{parse_cookies, percent_decode, CookieError, CookieJar, CookieSettings, TypedCookie},
+    events::{EventBus, HandlerError}, experiment::{Conversion, ExperimentAssignments, CONVERSION},
+    offline::REPLAYED, forms::{FormErrors, FORM_ERROR},
+    config::{Announcements, Morph, NonFinite, Shells, Theme, TriggerData}, finite::has_non_finite, morph::Morphed, theme::theme_class, toggle::{FeatureFlags, FeatureToggles},
+    locale::{request_locale, LocalizedRoutes}, recording::RequestInfo,
+    group::RouteGroups, feature::Link, feature_config::FeatureSettings, head_assets::{assets_oob, HeadAssets}, last_visited::{covers, LastVisited}, embedded::TemplateSources, list::EmptyState, submenu::FeatureMenus, redirect::{HtmxRedirect, Redirection}, widget::EMBED_HEADER,
+    operations::{OperationError, OperationHandle, OperationProgress, Operations, Outcome}
+};
+
+// user agent fragments of crawlers, matched lower cased
+const CRAWLERS: [&str; 6] = ["bot", "crawler", "spider", "slurp", "facebookexternalhit", "preview"];
+
 pub trait Serializable: Send + Sync {
-    fn serialize(&self) -> String;
+    fn serialize(&self) -> Result<String, serde_json::Error>;
+
+    /// Whether a `NaN` or infinite float is part of the data, see `server.triggers.non_finite`.
+    fn has_non_finite(&self) -> bool;
 }
 
 impl<T> Serializable for T
 where
     T: Serialize + Send + Sync,
 {
-    fn serialize(&self) -> String {
-        to_string(self).unwrap()
+    fn serialize(&self) -> Result<String, serde_json::Error> {
+        to_string(self)
+    }
+
+    fn has_non_finite(&self) -> bool {
+        has_non_finite(self)
+    }
+}
+
+/// When HTMX fires an event, each timing has its own header, see https://htmx.org/headers/hx-trigger/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timing {
+    /// `HX-Trigger`, as soon as the response is received.
+    #[default]
+    Immediate,
+
+    /// `HX-Trigger-After-Settle`, once the new content has settled.
+    AfterSettle,
+
+    /// `HX-Trigger-After-Swap`, once the new content has been swapped in.
+    AfterSwap,
+}
+
+impl Timing {
+    pub fn header(&self) -> &'static str {
+        match self {
+            Timing::Immediate => HX_TRIGGER,
+            Timing::AfterSettle => HX_TRIGGER_AFTER_SETTLE,
+            Timing::AfterSwap => HX_TRIGGER_AFTER_SWAP,
+        }
     }
 }
 
 pub struct Event {
     pub key: String,
-    pub data: Option<Box<dyn Serializable>>
+    pub data: Option<Box<dyn Serializable>>,
+    pub timing: Timing,
 }
 
 impl Event {
     pub fn new<T: Serializable + 'static>(key: String, data: T) -> Self {
-        Self { key, data: Some(Box::new(data)) }
+        Self { key, data: Some(Box::new(data)), timing: Timing::Immediate }
     }
 
     pub fn empty(key: String) -> Self{
-        Self {key, data: None}
+        Self {key, data: None, timing: Timing::Immediate}
+    }
+
+    /// The same event fired at `timing`: `Event::empty("chart".to_owned()).at(Timing::AfterSwap)`.
+    pub fn at(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
     }
 }
 
@@ -48,7 +106,7 @@ impl Serialize for Event {
             // Flatten the `data` field if it exists
             if let Some(ref data) = self.data {
                 let mut map = serializer.serialize_map(None)?;
-                let json_string = data.serialize();
+                let json_string = data.serialize().map_err(serde::ser::Error::custom)?;
                 let data_map: HashMap<String, serde_json::Value> = serde_json::from_str(&json_string).map_err(serde::ser::Error::custom)?;
                 
                 for (k, v) in data_map {
@@ -62,22 +120,92 @@ impl Serialize for Event {
 }
 
 pub struct Triggers {
-    triggers: Vec<Event>
+    triggers: Vec<Event>,
+    non_finite: NonFinite,
 }
 
 impl Triggers {
     pub fn new() -> Self {
-        Self { triggers: Vec::new() }
+        Self { triggers: Vec::new(), non_finite: NonFinite::default() }
+    }
+
+    /// Payloads with a `NaN` or infinite float are handled as `non_finite` says.
+    pub fn non_finite(mut self, non_finite: NonFinite) -> Self {
+        self.non_finite = non_finite;
+        self
     }
 
     pub fn add(&mut self, event: Event) {
         self.triggers.push(event)
     }
 
-    fn group_triggers(&self) -> HashMap<String, Vec<&Event>> {
+    pub fn extend(&mut self, events: impl IntoIterator<Item = Event>) {
+        self.triggers.extend(events)
+    }
+
+    pub fn clear(&mut self) {
+        self.triggers.clear()
+    }
+
+    /// Value for the HX-Trigger header, None without triggers (rather than `{}`).
+    /// Anything outside of visible ASCII is written as a JSON `\uXXXX` escape,
+    /// failures are logged and produce no header rather than failing the request.
+    pub fn header_value(&self) -> Option<HeaderValue> {
+        self.header_value_at(Timing::Immediate)
+    }
+
+    /// `header_value` of the triggers fired at `timing`, for the header `Timing::header` names.
+    pub fn header_value_at(&self, timing: Timing) -> Option<HeaderValue> {
+        let timed: TimedTriggers = TimedTriggers { triggers: self, timing };
+
+        for event in timed.events().filter(|event| self.rejects(event)) {
+            tracing::error!("trigger {} has a NaN or infinite number in its data and is not sent", event.key);
+        }
+
+        // true as well when there are none
+        if timed.events().all(|event| self.rejects(event)) {
+            return None;
+        }
+
+        let json: String = match to_string(&timed) {
+            Ok(json) => json,
+            Err(e) => {
+                tracing::error!("unable to serialize triggers: {e}");
+                return None;
+            }
+        };
+
+        match HeaderValue::from_str(&escape_non_ascii(&json)) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::error!("triggers are not a valid header value: {e}");
+                None
+            }
+        }
+    }
+
+    fn rejects(&self, event: &Event) -> bool {
+        self.non_finite == NonFinite::Reject && event.data.as_ref().is_some_and(|data| data.has_non_finite())
+    }
+
+}
+
+/// The triggers of one header, a key used at several timings is serialized in each of them.
+struct TimedTriggers<'a> {
+    triggers: &'a Triggers,
+    timing: Timing,
+}
+
+impl<'a> TimedTriggers<'a> {
+    fn events(&self) -> impl Iterator<Item = &'a Event> {
+        let (triggers, timing): (&'a Triggers, Timing) = (self.triggers, self.timing);
+        triggers.triggers.iter().filter(move |event| event.timing == timing)
+    }
+
+    fn group_triggers(&self) -> HashMap<String, Vec<&'a Event>> {
         let mut grouped_events: HashMap<String, Vec<&Event>> = HashMap::new();
     
-        for event in self.triggers.iter() {
+        for event in self.events().filter(|event| !self.triggers.rejects(event)) {
             grouped_events.entry(event.key.clone())
                 .or_insert_with(Vec::new)
                 .push(event);
@@ -87,7 +215,16 @@ impl Triggers {
     }
 }
 
+/// The `HX-Trigger` (immediate) triggers, see `Triggers::header_value_at` for the others.
 impl Serialize for Triggers {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer {
+        Serialize::serialize(&TimedTriggers { triggers: self, timing: Timing::Immediate }, serializer)
+    }
+}
+
+impl<'a> Serialize for TimedTriggers<'a> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer {
@@ -108,6 +245,27 @@ impl Serialize for Triggers {
     }
 }
 
+/// serde_json already escapes control characters inside strings,
+/// header values additionally can't carry DEL or non-ASCII bytes.
+fn escape_non_ascii(json: &str) -> String {
+    let mut escaped: String = String::with_capacity(json.len());
+
+    for c in json.chars() {
+        if c.is_ascii() && !c.is_ascii_control() {
+            escaped.push(c);
+            continue;
+        }
+
+        let mut buffer: [u16; 2] = [0; 2];
+
+        for unit in c.encode_utf16(&mut buffer) {
+            escaped.push_str(&format!("\\u{:04x}", unit));
+        }
+    }
+
+    escaped
+}
+
 impl Display for Triggers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", to_string(self).unwrap())
@@ -118,12 +276,102 @@ pub struct Ctx {
     pub context_id: String,
     pub path: String,
 
+    // where `refresh` sends a plain request: a GET itself, query included, else its Referer
+    reload: Option<String>,
+
     // request headers
     headers: HeaderMap,
 
+    // HX-Current-URL, the page the browser is on when HTMX made the request
+    current_url: Option<String>,
+
     // response triggers
     triggers: Triggers,
 
+    // fingerprinted asset urls, shared by every request
+    assets: Arc<AssetManifest>,
+
+    // entities rendered into the response, see ResponseCache
+    cache_tags: Vec<String>,
+
+    // identity the response is personalized for, see `Context::cache_key_segment`
+    cache_segment: Option<String>,
+
+    // extra CDN purge keys, emitted with the cache tags
+    surrogate_keys: Vec<String>,
+
+    // typed cookies read and queued by handlers
+    cookies: CookieJar,
+
+    // screen reader announcements and focus target for swapped content
+    announcements: Vec<Announcement>,
+    focus: Option<String>,
+
+    // failed validation of the submitted form, and where its fragment is swapped
+    form_errors: Option<FormErrors>,
+    retarget: Option<(String, String)>,
+
+    // replaces the handler's response, see `Context::redirect`
+    redirect: Option<Redirection>,
+
+    // `morph:` swap asked for, only honoured when the app serves the morph script
+    morph: Option<&'static str>,
+    morph_enabled: bool,
+
+    // announcements are swapped into the page's live regions, see `server.announcements`
+    live_regions: bool,
+
+    // the app's time source, real time outside of a built App
+    clock: SharedClock,
+
+    // application event bus, None outside of a built App
+    events: Option<EventBus>,
+
+    // runtime feature switches, None outside of a built App
+    toggles: Option<FeatureToggles>,
+
+    // `[features]` of the config, None outside of a built App
+    flags: Option<FeatureFlags>,
+
+    // translated paths of the app and the locale derived from them, see `LocalizedRoute`
+    localized: Option<LocalizedRoutes>,
+    locale: Option<String>,
+
+    // `<html>` class from the theme cookie or `server.theme.default`
+    theme: String,
+
+    // state of deferred work, None outside of a built App
+    operations: Option<Operations>,
+
+    // every feature's route groups, for policy aware navigation
+    groups: Option<RouteGroups>,
+
+    // top-level links and submenus of the features, None outside of a built App
+    menus: Option<FeatureMenus>,
+
+    // rendered navigation trees of full pages by feature, see `NavTree`
+    nav_trees: BTreeMap<String, Markup>,
+
+    // keys mutations are serialized on, None outside of a built App
+    sections: Option<CriticalSections>,
+
+    // the signed in user, see `CurrentUser`
+    user: Option<String>,
+
+    // shells by authentication (`server.shells`) and this request's choice
+    shells: Option<Arc<Shells>>,
+    shell: Option<String>,
+
+    // templates of the features and the app, for partials like the empty state
+    templates: Option<TemplateSources>,
+
+    // the keys of the feature sections exposed to templates
+    settings: Option<FeatureSettings>,
+
+    // every feature's head assets and the ones this response needs
+    head_assets: Option<HeadAssets>,
+    required_assets: Vec<String>,
+
     // features are accessed from layout!
     // features: Vec<Box<dyn Feature>>
 }
@@ -133,50 +381,638 @@ impl Ctx {
         let headers: HeaderMap = request.headers().clone();
         let path: String = request.uri().path().to_owned();
 
+        let reload: Option<String> = match *request.method() {
+            Method::GET | Method::HEAD => request.uri().path_and_query().map(|uri| uri.as_str().to_owned()),
+            _ => headers.get(REFERER).and_then(|value| value.to_str().ok()).map(|value| value.to_owned()),
+        };
+        let assets: Arc<AssetManifest> = request.extensions()
+            .get::<Arc<AssetManifest>>()
+            .cloned()
+            .unwrap_or_default();
+        let cookies: CookieJar = match request.extensions().get::<Arc<CookieSettings>>() {
+            Some(settings) => CookieJar::from_request(request, settings),
+            None => CookieJar::from_request(request, &CookieSettings::default()),
+        };
+
+        let localized: Option<LocalizedRoutes> = request.extensions().get::<LocalizedRoutes>().cloned();
+        let locale: Option<String> = request_locale(localized.as_ref(), &path, &headers);
+
+        let theme: String = match request.extensions().get::<Arc<Theme>>() {
+            Some(theme) => theme_class(theme, &headers),
+            None => theme_class(&Theme::default(), &headers),
+        };
+
+        let non_finite: NonFinite = request.extensions().get::<Arc<TriggerData>>()
+            .map(|triggers| triggers.non_finite)
+            .unwrap_or_default();
+
+        let current_url: Option<String> = headers.get(HX_CURRENT_URL)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_owned());
+
         Ctx {
             context_id: Uuid::new_v4().to_string(),
             path,
+            reload,
+            current_url,
             headers,
-            triggers: Triggers::new(),
+            triggers: Triggers::new().non_finite(non_finite),
+            assets,
+            cache_tags: Vec::new(),
+            cache_segment: None,
+            surrogate_keys: Vec::new(),
+            cookies,
+            announcements: Vec::new(),
+            focus: None,
+            form_errors: None,
+            retarget: None,
+            redirect: None,
+            morph: None,
+            morph_enabled: request.extensions().get::<Arc<Morph>>().map(|morph| morph.enabled).unwrap_or(false),
+            live_regions: request.extensions().get::<Arc<Announcements>>().map(|announcements| announcements.live_regions).unwrap_or(false),
+            clock: request.extensions().get::<SharedClock>().cloned().unwrap_or_else(|| Arc::new(SystemClock)),
+            events: request.extensions().get::<EventBus>().cloned(),
+            toggles: request.extensions().get::<FeatureToggles>().cloned(),
+            flags: request.extensions().get::<FeatureFlags>().cloned(),
+            localized,
+            locale,
+            theme,
+            operations: request.extensions().get::<Operations>().cloned(),
+            groups: request.extensions().get::<RouteGroups>().cloned(),
+            menus: request.extensions().get::<FeatureMenus>().cloned(),
+            nav_trees: BTreeMap::new(),
+            sections: request.extensions().get::<CriticalSections>().cloned(),
+            user: request.extensions().get::<CurrentUser>().map(|user| user.0.clone()),
+            shells: request.extensions().get::<Arc<Shells>>().cloned(),
+            shell: None,
+            templates: request.extensions().get::<TemplateSources>().cloned(),
+            settings: request.extensions().get::<FeatureSettings>().cloned(),
+            head_assets: request.extensions().get::<HeadAssets>().cloned(),
+            required_assets: Vec::new(),
         }
     }
 }
 
+/// The signed in user, inserted into the request's extensions by the app's authentication
+/// middleware (any layer outside the routes). Read with `Context::user`.
+///
+/// ```ignore
+/// request.extensions_mut().insert(CurrentUser(session.user_id.to_string()));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrentUser(pub String);
+
 #[derive(Clone)]
-pub struct ContextAccessor(Arc<Mutex<Ctx>>);
+pub struct ContextAccessor {
+    ctx: Arc<Mutex<Ctx>>,
+
+    // kept outside of Ctx so a handler holding the Context guard can still query
+    #[cfg(feature = "postgres")]
+    queries: Arc<QueryCache>,
+
+    // counted by every Db of the request, reported in Server-Timing and the access log
+    #[cfg(feature = "postgres")]
+    query_stats: Arc<QueryStats>,
+
+    // same as queries, fragments rendering concurrently share it
+    memo: Arc<Memo>,
+}
 
 impl ContextAccessor { 
     pub async fn context(&self) -> Context {
-        let ctx = self.0.lock().await;
+        let ctx = self.ctx.lock().await;
         Context(ctx)
     }
 
     pub fn from_request(request: &Request) -> Self {
         let ctx: Ctx = Ctx::build(&request);
-        return ContextAccessor(Arc::new(Mutex::new(ctx)));
+        return ContextAccessor {
+            ctx: Arc::new(Mutex::new(ctx)),
+            #[cfg(feature = "postgres")]
+            queries: Arc::new(QueryCache::default()),
+            #[cfg(feature = "postgres")]
+            query_stats: Arc::new(QueryStats::new(request.uri().path())),
+            memo: Arc::new(Memo::default()),
+        };
+    }
+
+    /// Compute an expensive lookup once per request, e.g. the current user loaded by
+    /// the auth layer, the navigator and a fragment. Concurrent callers wait for the
+    /// first one, an error is returned to its caller and not kept.
+    ///
+    /// ```ignore
+    /// let settings: Arc<Settings> = accessor.memo("settings", || Settings::load(&db)).await?;
+    /// ```
+    pub async fn memo<T, E, F, Fut>(&self, key: &str, compute: F) -> Result<Arc<T>, E>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.memo.get_or_try_init(key, compute).await
+    }
+
+    /// Request scoped cache used by `Db::query_cached`.
+    #[cfg(feature = "postgres")]
+    pub fn queries(&self) -> &QueryCache {
+        &self.queries
+    }
+
+    /// Queries run so far by this request.
+    #[cfg(feature = "postgres")]
+    pub fn query_stats(&self) -> &QueryStats {
+        &self.query_stats
     }
 }
 
 pub struct Context<'a>(MutexGuard<'a, Ctx>);
 
+/// What a long lived task (a WebSocket handler) keeps of the request that started it,
+/// owned so the request's Context is released. Response side effects are gone.
+#[derive(Clone)]
+pub struct DetachedContext {
+    context_id: String,
+    path: String,
+    current_url: Option<String>,
+    headers: HeaderMap,
+    cookies: CookieJar,
+    events: Option<EventBus>,
+}
+
+impl DetachedContext {
+    pub fn id(&self) -> &str {
+        &self.context_id
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn current_url(&self) -> Option<&str> {
+        self.current_url.as_deref()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn typed_cookie<T: TypedCookie>(&self) -> Option<T> {
+        self.cookies.get()
+    }
+
+    pub fn events(&self) -> Option<&EventBus> {
+        self.events.as_ref()
+    }
+}
+
 impl<'a> Context<'a> {
 
-    pub fn title(&self) -> String {
-        // match self.navigator.current_link() {
-        //     Some(l) => {
-        //         l.title.to_owned()
-        //     },
-        //     None => {
-        //         "".to_owned()
-        //     }
-        // }
-        "".to_owned()
+    /// Title of the active top-level link, `default` on pages no link leads to.
+    pub fn title(&self, default: &str) -> String {
+        self.0.menus.as_ref()
+            .and_then(|menus| menus.active(self))
+            .map(|menu| menu.link.title.clone())
+            .unwrap_or_else(|| default.to_owned())
     }
 
     pub fn id(&self) -> String {
         return self.0.context_id.clone();
     }
+
+    /// An owned copy of the request's identity, headers and cookies.
+    pub fn detach(&self) -> DetachedContext {
+        DetachedContext {
+            context_id: self.0.context_id.clone(),
+            path: self.0.path.clone(),
+            current_url: self.0.current_url.clone(),
+            headers: self.0.headers.clone(),
+            cookies: self.0.cookies.clone(),
+            events: self.0.events.clone(),
+        }
+    }
     
+    /// URL of a static asset, fingerprinted when the asset manifest knows it.
+    pub fn asset(&self, name: &str) -> String {
+        self.0.assets.url(name)
+    }
+
+    /// Marks the response as depending on an entity (`invoice:42`),
+    /// `ResponseCache::invalidate_tag` evicts every cached response tagged with it.
+    pub fn cache_tag(&mut self, tag: impl Into<String>) {
+        let tag: String = tag.into();
+
+        if !self.0.cache_tags.contains(&tag) {
+            self.0.cache_tags.push(tag);
+        }
+    }
+
+    /// Declares the page personalized for `segment` (the user id, or a role when the page
+    /// only depends on that), the response cache then stores it for that identity alone.
+    /// Only stored with `server.cache.personalized` when `segment` is the requester's
+    /// identity, see `ResponseCache::identity`.
+    pub fn cache_key_segment(&mut self, segment: impl Into<String>) {
+        self.0.cache_segment = Some(segment.into());
+    }
+
+    /// Adds a CDN purge key (`invoice:42`) to the response's surrogate keys,
+    /// unlike `cache_tag` it does not tag the local response cache entry.
+    pub fn surrogate_key(&mut self, key: impl Into<String>) {
+        let key: String = key.into();
+
+        if !self.0.surrogate_keys.contains(&key) {
+            self.0.surrogate_keys.push(key);
+        }
+    }
+
+    /// A raw cookie of the request, e.g. one set by client side script, percent decoded.
+    /// Unlike `typed_cookie` the value is neither signed nor versioned.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        parse_cookies(&self.0.headers)
+            .into_iter()
+            .find(|(cookie, _)| cookie == name)
+            .map(|(_, value)| percent_decode(&value))
+    }
+
+    /// Every cookie of the request in header order, values percent decoded.
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        parse_cookies(&self.0.headers)
+            .into_iter()
+            .map(|(name, value)| (name, percent_decode(&value)))
+            .collect()
+    }
+
+    /// A typed cookie, `None` when missing, tampered with or not migratable.
+    pub fn typed_cookie<T: TypedCookie>(&self) -> Option<T> {
+        self.0.cookies.get()
+    }
+
+    /// Queue a typed cookie for the response, the last write of a cookie wins.
+    pub fn set_cookie<T: TypedCookie>(&mut self, value: &T) -> Result<(), CookieError> {
+        self.0.cookies.set(value)
+    }
+
+    pub fn remove_cookie<T: TypedCookie>(&mut self) {
+        self.0.cookies.remove::<T>()
+    }
+
+    /// Announce a change to screen readers ("5 results loaded"),
+    /// announcements of one request are read in order.
+    pub fn announce(&mut self, message: impl Into<String>, politeness: Politeness) {
+        self.0.announcements.push(Announcement { message: message.into(), politeness });
+    }
+
+    /// Move focus to `selector` once the swapped content has settled.
+    pub fn focus(&mut self, selector: impl Into<String>) {
+        self.0.focus = Some(selector.into());
+    }
+
+    /// Record a conversion (`signup_completed`) tagged with the session's experiment assignments,
+    /// published on `CONVERSION` for `Conversions`. Nothing is recorded without an assignment.
+    pub fn conversion(&self, name: impl Into<String>, value: f64) {
+        let Some(assignments) = self.typed_cookie::<ExperimentAssignments>().filter(|a| !a.variants.is_empty()) else {
+            return;
+        };
+
+        let Some(events) = self.0.events.as_ref() else {
+            tracing::warn!("conversion recorded outside of an application, dropped");
+            return;
+        };
+
+        // server side only, never broadcast or triggered
+        events.publish(&CONVERSION, &Conversion {
+            name: name.into(),
+            value,
+            assignments: assignments.variants,
+            recorded_at: self.0.clock.now(),
+        });
+    }
+
+    /// Now on the app's clock (`App::with_clock`), real time outside of a built App.
+    pub fn now(&self) -> SystemTime {
+        self.0.clock.now()
+    }
+
+    /// False when `route` is the nav link of a feature disabled at runtime.
+    pub fn link_enabled(&self, route: &str) -> bool {
+        self.0.toggles.as_ref().map(|toggles| toggles.link_enabled(route)).unwrap_or(true)
+    }
+
+    /// Load the head asset `name` (and its dependencies) with this response: placed in the
+    /// full page, or appended to the page's head for fragments, see `Feature::head_assets`.
+    pub fn require_asset(&mut self, name: &str) {
+        if !self.0.required_assets.iter().any(|required| required == name) {
+            self.0.required_assets.push(name.to_owned());
+        }
+    }
+
+    pub fn required_assets(&self) -> Vec<String> {
+        self.0.required_assets.clone()
+    }
+
+    /// An exposed key of the `[features.{section}]` table, see `ConfigSchema::expose`.
+    ///
+    /// ```ignore
+    /// html! { @if let Some(currency) = context.feature_setting("billing", "currency").and_then(|v| v.as_str()) { (currency) } }
+    /// ```
+    pub fn feature_setting(&self, section: &str, key: &str) -> Option<&toml::Value> {
+        self.0.settings.as_ref().and_then(|settings| settings.get(section, key))
+    }
+
+    /// The `[features]` flag `name`, off when missing.
+    ///
+    /// ```ignore
+    /// html! { @if context.feature_enabled("beta_ui") { (beta_toolbar()) } }
+    /// ```
+    pub fn feature_enabled(&self, name: &str) -> bool {
+        self.0.flags.as_ref().map(|flags| flags.is_enabled(name)).unwrap_or(false)
+    }
+
+    /// Run `job` past the request, answer with the returned handle: a `202` fragment
+    /// polling the job's progress until it completes, see `Operations`.
+    pub async fn defer<F, Fut>(&self, job: F) -> Result<OperationHandle, OperationError>
+    where
+        F: FnOnce(OperationProgress) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Outcome, HandlerError>> + Send + 'static,
+    {
+        let operations: &Operations = self.0.operations.as_ref().ok_or(OperationError::Unavailable)?;
+
+        operations.start(operations.owner(&self.0.headers), job).await
+    }
+
+    /// Links of the route groups the request may reach, see `RouteGroup::link`.
+    pub fn visible_links(&self) -> Vec<Link> {
+        self.0.groups.as_ref().map(|groups| groups.visible_links(self)).unwrap_or_default()
+    }
+
+    /// The top-level link of every feature for the shell's navigation, `active` on the one
+    /// leading to the page: the most specific route covering the path (`/sample-too` is not
+    /// below `/sample`), none when no link covers it. Empty outside of a built App.
+    pub fn links(&self) -> Vec<Link> {
+        let Some(menus) = self.0.menus.as_ref() else {
+            return Vec::new();
+        };
+
+        let active: Option<&str> = menus.active(self).map(|menu| menu.link.route.as_str());
+
+        menus.0.iter()
+            .map(|menu| Link { active: active == Some(menu.link.route.as_str()), ..menu.link.clone() })
+            .collect()
+    }
+
+    /// Name of the feature whose top-level link is active, see `Feature::link`.
+    pub fn active_feature(&self) -> Option<String> {
+        self.0.menus.as_ref()
+            .and_then(|menus| menus.active(self))
+            .map(|menu| menu.feature.clone())
+    }
+
+    /// Where the top-level `link` leads: the page last visited below it when the link
+    /// has `remember`, None for its route. The remembered page has to still be below the
+    /// route of one of the app's links, else (e.g. the route was renamed) it is dropped.
+    pub fn remembered_url(&self, link: &Link) -> Option<String> {
+        if !link.remember {
+            return None;
+        }
+
+        let current: bool = self.0.menus.as_ref()?.0.iter()
+            .any(|menu| menu.link.remember && menu.link.route == link.route);
+
+        let visited: LastVisited = self.typed_cookie()?;
+        let url: &str = visited.get(&link.route).filter(|_| current)?;
+
+        covers(&self.url_for(&link.route), url_path(url)).then(|| url.to_owned())
+    }
+
+    /// Remembers `url` (path and query) for the most specific top-level link with
+    /// `remember` covering it.
+    pub(crate) fn remember_visit(&mut self, url: &str) {
+        let path: &str = url_path(url);
+
+        let Some(route) = self.0.menus.as_ref().and_then(|menus| menus.0.iter()
+            .filter(|menu| menu.link.remember && covers(&self.url_for(&menu.link.route), path))
+            .max_by_key(|menu| menu.link.route.len())
+            .map(|menu| menu.link.route.clone())) else {
+            return;
+        };
+
+        let mut visited: LastVisited = self.typed_cookie().unwrap_or_default();
+
+        if visited.record(&route, url) {
+            if let Err(e) = self.set_cookie(&visited) {
+                tracing::warn!("unable to remember the visit of {url}: {e}");
+            }
+        }
+    }
+
+    /// Sub-navigation of the active feature, None when it has none, see `Feature::submenu`
+    /// and `render_submenu` for the default markup.
+    pub fn submenu(&self) -> Option<Vec<Link>> {
+        self.0.menus.as_ref()
+            .and_then(|menus| menus.active(self))
+            .filter(|menu| !menu.submenu.is_empty())
+            .map(|menu| menu.submenu.clone())
+    }
+
+    /// The navigation tree of feature `name` expanded down to the page, empty for
+    /// fragments and features without one, see `Feature::nav_tree`.
+    pub fn nav_tree(&self, name: &str) -> Markup {
+        self.0.nav_trees.get(name).cloned().unwrap_or_else(|| html!{})
+    }
+
+    /// The signed in user, see `CurrentUser`.
+    pub fn user(&self) -> Option<&str> {
+        self.0.user.as_deref()
+    }
+
+    /// Sign the user in (or out with `None`) for the rest of the request, e.g. a login
+    /// handler whose page is then framed by the authenticated shell.
+    pub fn set_user(&mut self, user: Option<String>) {
+        self.0.user = user;
+    }
+
+    /// Frame this page with the template's shell `name` (`Template::shell`).
+    ///
+    /// Precedence, first wins: a `RouteGroup::shell` of the feature, this choice,
+    /// `server.shells.authenticated_shell` or `anonymous_shell` by `user`, `Template::page`.
+    pub fn use_shell(&mut self, name: &str) {
+        self.0.shell = Some(name.to_owned());
+    }
+
+    /// Name of the shell framing this page, None for `Template::page`, see `use_shell`.
+    pub fn shell(&self) -> Option<String> {
+        if let Some(shell) = self.0.shell.as_ref() {
+            return Some(shell.clone());
+        }
+
+        let shells: &Shells = self.0.shells.as_deref()?;
+
+        match self.0.user.is_some() {
+            true => shells.authenticated_shell.clone(),
+            false => shells.anonymous_shell.clone(),
+        }
+    }
+
+    /// Wait until no other request of the requester holds `key`, conflicting saves then
+    /// run one after the other instead of interleaving. Held until the guard is dropped,
+    /// a request waiting too long gets `SectionError::Timeout` (409), see `CriticalSections`.
+    ///
+    /// ```ignore
+    /// let _section = accessor.context().await.serialize_on(&format!("invoice:{id}")).await?;
+    /// ```
+    pub async fn serialize_on(&self, key: &str) -> Result<Serialized, SectionError> {
+        let sections: &CriticalSections = self.0.sections.as_ref().ok_or(SectionError::Unavailable)?;
+        sections.enter(&self.0.headers, key).await
+    }
+
+    /// The empty state of a list, table or search of the active feature, through
+    /// `{feature}/empty-state.html` or `empty-state.html` when the app has them, see `EmptyState`.
+    pub fn empty_state(&self, message: &str, cta: Option<(&str, &str)>) -> Markup {
+        let mut empty: EmptyState = EmptyState::new(message);
+
+        if let Some((label, url)) = cta {
+            empty = empty.cta(label, url);
+        }
+
+        match self.0.templates.as_ref() {
+            Some(templates) => empty.resolve(templates, self.active_feature().as_deref()),
+            None => empty.render(),
+        }
+    }
+
+    /// Whether the request comes from a search engine or link preview crawler, which
+    /// runs no JavaScript: lazy regions render inline for it, see `LazyRegion`.
+    pub fn is_crawler(&self) -> bool {
+        let agent: String = self.0.headers.get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        CRAWLERS.iter().any(|crawler| agent.contains(crawler))
+    }
+
+    pub(crate) fn set_nav_trees(&mut self, trees: BTreeMap<String, Markup>) {
+        self.0.nav_trees = trees;
+    }
+
+    /// Class for the shell's `<html>`, `dark` for a user who picked the dark theme.
+    pub fn theme_class(&self) -> &str {
+        &self.0.theme
+    }
+
+    /// From the translated path requested, the `locale` cookie or `Accept-Language`.
+    pub fn locale(&self) -> Option<String> {
+        self.0.locale.clone()
+    }
+
+    /// `path` translated to the request's locale when it is a `LocalizedRoute`,
+    /// otherwise unchanged: `url_for("/pricing")` is `/de/preise` for German requests.
+    pub fn url_for(&self, path: &str) -> String {
+        self.0.localized.as_ref()
+            .and_then(|localized| localized.path_for(path, self.0.locale.as_deref()))
+            .unwrap_or_else(|| path.to_owned())
+    }
+
+    /// (locale, path) of every translation of the requested path, empty when untranslated.
+    pub fn alternates(&self) -> Vec<(String, String)> {
+        self.0.localized.as_ref()
+            .map(|localized| localized.alternates(&self.0.path))
+            .unwrap_or_default()
+    }
+
+    /// The browser's address as sent by HTMX (`HX-Current-URL`), `None` for plain requests.
+    pub fn current_url(&self) -> Option<String> {
+        self.0.current_url.clone()
+    }
+
+    /// Path of the page the user is on: for boosted requests the request path is the
+    /// navigation target, the page comes from `HX-Current-URL` when present.
+    pub fn current_path(&self) -> String {
+        match (self.is_boosted(), self.0.current_url.as_deref()) {
+            (true, Some(url)) => url_path(url).to_owned(),
+            _ => self.0.path.clone(),
+        }
+    }
+
+    /// Merge the fragment into the target instead of replacing it (`HX-Reswap: morph:innerHTML`),
+    /// keeping focus, scroll and selection. A no-op unless `server.morph.enabled`, list items
+    /// need stable ids (`morph_id`).
+    pub fn swap_morph(&mut self) {
+        if self.0.morph_enabled {
+            self.0.morph = Some("morph:innerHTML");
+        }
+    }
+
+    /// As `swap_morph` for fragments replacing their target element (`morph:outerHTML`).
+    pub fn swap_morph_outer(&mut self) {
+        if self.0.morph_enabled {
+            self.0.morph = Some("morph:outerHTML");
+        }
+    }
+
+    /// Fire `blandwork:form-error` with the field errors once the re-rendered form settled,
+    /// see `FORM_ERROR` for the event detail. With `FormErrors::form` the fragment replaces
+    /// that element (`HX-Retarget`, `HX-Reswap: outerHTML`). The last call wins.
+    /// Respond with 200: htmx does not swap error statuses by default.
+    pub fn add_error_trigger(&mut self, errors: FormErrors) {
+        if let Some(form) = errors.form.as_ref() {
+            self.retarget(form.clone(), "outerHTML");
+        }
+
+        self.0.form_errors = Some(errors);
+    }
+
+    /// Swap the response into `selector` with `swap` instead of what the request asked for.
+    pub fn retarget(&mut self, selector: impl Into<String>, swap: impl Into<String>) {
+        self.0.retarget = Some((selector.into(), swap.into()));
+    }
+
+    /// Answer with a full page navigation to `uri` whatever the handler returns: `HX-Redirect`
+    /// for HTMX, a `303` with `Location` otherwise. Cookies queued on the context are still
+    /// sent, e.g. the session of a login. The last of `redirect`, `location` and `refresh` wins.
+    ///
+    /// ```ignore
+    /// async fn logout(Extension(accessor): Extension<ContextAccessor>) -> impl IntoResponse {
+    ///     let mut context = accessor.context().await;
+    ///     context.remove_cookie::<Session>();
+    ///     context.redirect("/login");
+    /// }
+    /// ```
+    pub fn redirect(&mut self, uri: &str) {
+        self.0.redirect = Some(HtmxRedirect::new(self.is_htmx()).to(uri).see_other());
+    }
+
+    /// As `redirect` without a reload, `HX-Location` swapping `uri` into `target` with `swap`.
+    /// See `HtmxRedirect::location_with`.
+    pub fn location(&mut self, uri: &str, target: Option<&str>, swap: Option<&str>) {
+        self.0.redirect = Some(HtmxRedirect::new(self.is_htmx()).location_with(uri, target, swap).see_other());
+    }
+
+    /// As `redirect` to the current page, `HX-Refresh: true` for HTMX. A plain GET is sent to
+    /// itself, query included, a submission to its `Referer`. Without one the handler's response
+    /// is kept, a `303` to the submission's route may not have a GET to answer it.
+    pub fn refresh(&mut self) {
+        self.0.redirect = match (self.is_htmx(), self.0.reload.as_deref()) {
+            (false, None) => None,
+            (htmx, uri) => Some(HtmxRedirect::new(htmx).refresh(uri.unwrap_or_default()).see_other()),
+        };
+    }
+
+    /// Back to the list at `list_route`, scrolled to the created row, see `HtmxRedirect::to_row`.
+    pub fn redirect_to_row(&self, list_route: &str, row_id: &str) -> Redirection {
+        HtmxRedirect::new(self.is_htmx()).to_row(list_route, row_id)
+    }
+
+    /// The embed token of a widget request, verified on the routes of `RouteGroup::widget`.
+    pub fn embed_token(&self) -> Option<&str> {
+        self.0.headers.get(EMBED_HEADER).and_then(|value| value.to_str().ok())
+    }
+
+    /// A submission replayed from the browser's offline queue (`X-Blandwork-Replayed`),
+    /// possibly long after the user made it.
+    pub fn is_replayed(&self) -> bool {
+        self.0.headers.contains_key(REPLAYED)
+    }
+
     pub fn is_htmx(&self) -> bool {
         return self.0.headers.contains_key(HX_REQUEST);
     }
@@ -193,17 +1029,64 @@ impl<'a> Context<'a> {
         self.0.triggers.add(Event::empty(key));
     }
 
-    pub fn triggers(&self) -> HeaderValue {
-        self.0.triggers.to_string().parse().unwrap()
+    /// A trigger sent in `HX-Trigger-After-Settle`, e.g. initializing a chart of the new markup.
+    pub fn add_trigger_after_settle<E: Serializable + 'static>(&mut self, key: String, data: E) {
+        self.0.triggers.add(Event::new(key, data).at(Timing::AfterSettle));
+    }
+
+    /// A trigger sent in `HX-Trigger-After-Swap`.
+    pub fn add_trigger_after_swap<E: Serializable + 'static>(&mut self, key: String, data: E) {
+        self.0.triggers.add(Event::new(key, data).at(Timing::AfterSwap));
+    }
+
+    /// Several triggers at once, in order: `context.add_triggers(regions.map(Event::empty))`.
+    pub fn add_triggers(&mut self, events: impl IntoIterator<Item = Event>) {
+        self.0.triggers.extend(events);
+    }
+
+    /// Drops every trigger added so far, for an error path suppressing the success ones.
+    pub fn clear_triggers(&mut self) {
+        self.0.triggers.clear();
+    }
+
+    pub fn triggers(&self) -> Option<HeaderValue> {
+        self.0.triggers.header_value()
+    }
+}
+
+/// `/invoices/42` out of `https://example.com/invoices/42?tab=2#items`.
+fn url_path(url: &str) -> &str {
+    let rest: &str = match url.find("://") {
+        Some(scheme) => {
+            let authority: &str = &url[scheme + 3..];
+            authority.find('/').map(|slash| &authority[slash..]).unwrap_or("/")
+        },
+        None => url,
+    };
+
+    let end: usize = rest.find(['?', '#']).unwrap_or(rest.len());
+
+    match &rest[..end] {
+        "" => "/",
+        path => path,
     }
 }
 
 #[derive(Clone)]
-pub struct ContextLayer;
+pub struct ContextLayer {
+    // record visited pages for `Link::remember`, only for the pages of `web()`
+    remember: bool,
+}
 
 impl ContextLayer {
     pub fn new() -> Self {
-        Self { }
+        Self { remember: false }
+    }
+
+    /// Records the pages answered for `Link::remember`, see `LastVisited`.
+    pub fn remember_visits(mut self) -> Self {
+        self.remember = true;
+        self
     }
 }
 
@@ -213,6 +1096,7 @@ impl<S> Layer<S> for ContextLayer {
     fn layer(&self, inner: S) -> Self::Service {
         ContextService { 
             inner,
+            remember: self.remember,
         }
     }
 }
@@ -220,6 +1104,7 @@ impl<S> Layer<S> for ContextLayer {
 #[derive(Clone)]
 pub struct ContextService<S> {
     inner: S,
+    remember: bool,
 }
 
 impl<S> Service<Request> for ContextService<S>
@@ -241,6 +1126,14 @@ where
         // build context
         let accessor: ContextAccessor = ContextAccessor::from_request(&req);
 
+        // the matched route, only known inside the router
+        let route: Option<String> = req.extensions().get::<MatchedPath>().map(|path| path.as_str().to_owned());
+
+        // a page of a route the router knows, to return to through the navigator
+        let visit: Option<String> = (self.remember && req.method() == Method::GET && route.is_some())
+            .then(|| req.uri().path_and_query().map(|url| url.as_str().to_owned()))
+            .flatten();
+
         // send the context into the handler
         let extensions = req.extensions_mut();
         extensions.insert( accessor.clone());
@@ -250,15 +1143,81 @@ where
         Box::pin(async move {
             let mut response: Response<axum::body::Body> = inner.await?;
 
-            let context: Context = accessor.context().await;
+            let mut context: Context = accessor.context().await;
 
             tracing::info!("context layer wrap {:#?}", context.is_boosted());
+
+            // recorded by the handler, its own response is dropped
+            let redirected: bool = context.0.redirect.is_some();
+            if let Some(redirect) = context.0.redirect.take() {
+                response = redirect.into_response();
+            }
+
+            // recomputations avoided by memo, visible in the browser's timing panel
+            let saved: u64 = accessor.memo.saved();
+            if saved > 0 {
+                if let Ok(timing) = HeaderValue::from_str(&format!("memo;desc=\"{saved} saved\"")) {
+                    response.headers_mut().append("server-timing", timing);
+                }
+            }
+
+            #[cfg(feature = "postgres")]
+            {
+                let totals: QueryTotals = accessor.query_stats.totals();
+                if totals.queries > 0 {
+                    if let Ok(timing) = HeaderValue::from_str(&totals.server_timing()) {
+                        response.headers_mut().append("server-timing", timing);
+                    }
+                    response.extensions_mut().insert(totals);
+                }
+            }
+
+            if let Some(url) = visit.filter(|_| !redirected && response.status() == StatusCode::OK && (context.is_boosted() || !context.is_htmx())) {
+                context.remember_visit(&url);
+            }
+
+            for cookie in context.0.cookies.headers() {
+                response.headers_mut().append(SET_COOKIE, cookie);
+            }
+
+            response.extensions_mut().insert(RequestInfo { route, locale: context.locale() });
+
+            // the integration script drops responses older than the last swapped into their target
+            if let Some(sequence) = context.0.headers.get(SEQUENCE) {
+                response.headers_mut().insert(SEQUENCE, sequence.clone());
+            }
+
+            if !context.0.cache_tags.is_empty() {
+                response.extensions_mut().insert(CacheTags(context.0.cache_tags.clone()));
+            }
+
+            if let Some(segment) = context.0.cache_segment.as_ref() {
+                response.extensions_mut().insert(CacheSegment(segment.clone()));
+            }
+
+            if !context.0.surrogate_keys.is_empty() {
+                response.extensions_mut().insert(SurrogateKeys(context.0.surrogate_keys.clone()));
+            }
             
-            if context.is_boosted() {
-                // HX-Trigger https://htmx.org/headers/hx-trigger/
-                let mut headers: HeaderMap = HeaderMap::new();
-                headers.insert(HX_TRIGGER, context.triggers());
-                response.headers_mut().extend(headers);
+            if context.is_htmx() {
+                // an explicit retarget below sets its own swap
+                if let Some(swap) = context.0.morph.take() {
+                    response.headers_mut().insert(HX_RESWAP, HeaderValue::from_static(swap));
+                    response.extensions_mut().insert(Morphed);
+                }
+
+                let announcements: Option<Markup> = accessibility(&mut response, &mut context);
+                let assets: Option<Markup> = fragment_assets(&mut context);
+                response = append_html(response, announcements.into_iter().chain(assets).collect()).await;
+            }
+
+            // boosted or not (hx-get), a header per timing https://htmx.org/headers/hx-trigger/
+            if context.is_htmx() {
+                for timing in [Timing::Immediate, Timing::AfterSettle, Timing::AfterSwap] {
+                    if let Some(triggers) = context.0.triggers.header_value_at(timing) {
+                        response.headers_mut().insert(timing.header(), triggers);
+                    }
+                }
             }
             tracing::info!("context layer end");
             Ok(response)
@@ -267,11 +1226,86 @@ where
 
 }
 
+/// The head assets required by a fragment, appended out of band.
+fn fragment_assets(context: &mut Context<'_>) -> Option<Markup> {
+    let required: Vec<String> = std::mem::take(&mut context.0.required_assets);
+
+    context.0.head_assets.as_ref().and_then(|assets| assets_oob(assets, &required))
+}
+
+/// Live region swaps, focus and form error directives for an HTMX response,
+/// the swaps are returned to be appended.
+fn accessibility(response: &mut Response<Body>, context: &mut Context<'_>) -> Option<Markup> {
+    let announcements: Vec<Announcement> = std::mem::take(&mut context.0.announcements);
+
+    // sent with the handler's own after settle triggers
+    if let Some(selector) = context.0.focus.take() {
+        context.0.triggers.add(Event::new(FOCUS.to_owned(), Focus { selector }).at(Timing::AfterSettle));
+    }
+
+    if let Some(errors) = context.0.form_errors.take() {
+        context.0.triggers.add(Event::new(FORM_ERROR.to_owned(), errors).at(Timing::AfterSettle));
+    }
+
+    if let Some((selector, swap)) = context.0.retarget.take() {
+        if let (Ok(selector), Ok(swap)) = (HeaderValue::from_str(&selector), HeaderValue::from_str(&swap)) {
+            response.headers_mut().insert(HX_RETARGET, selector);
+            response.headers_mut().insert(HX_RESWAP, swap);
+        }
+    }
+
+    if announcements.is_empty() {
+        return None;
+    }
+
+    // fallback for pages without the live regions
+    for announcement in announcements.iter() {
+        context.add_trigger(ANNOUNCE.to_owned(), announcement.clone());
+    }
+
+    context.0.live_regions.then(|| announcements_oob(&announcements))
+}
+
+/// `html` after the body of an HTML response, buffered once whatever the number of fragments.
+async fn append_html(response: Response<Body>, html: Vec<Markup>) -> Response<Body> {
+    let is_html: bool = response.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("text/html"))
+        .unwrap_or(false);
+
+    if html.is_empty() || !is_html {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => {
+            let mut body: Vec<u8> = bytes.to_vec();
+            for markup in html {
+                body.extend_from_slice(markup.into_string().as_bytes());
+            }
+
+            parts.headers.remove(hyper::header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(body))
+        },
+        Err(e) => {
+            tracing::error!("unable to append to the response: {e}");
+            Response::from_parts(parts, Body::empty())
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use axum::{body::Body, extract::Request, routing::post, Extension, Router};
+    use axum_htmx::{HX_BOOSTED, HX_CURRENT_URL, HX_REQUEST, HX_TRIGGER, HX_TRIGGER_AFTER_SETTLE, HX_TRIGGER_AFTER_SWAP};
     use serde::Serialize;
+    use tower::ServiceExt;
+
+    use crate::config::NonFinite;
 
-    use super::{Event, Triggers};
+    use super::{url_path, ContextAccessor, ContextLayer, Event, Timing, Triggers};
 
     #[derive(Serialize)]
     pub struct FakeData{
@@ -318,6 +1352,50 @@ mod test {
         assert_eq!(serde_json::to_string(&triggers).unwrap(), "{\"SOME_EVENT_KEY\":[null,{\"name\":\"SOME_EVENT_DATA\"}]}");
     }
 
+    #[test]
+    fn test_trigger_header_value_escapes() {
+        let mut triggers = Triggers::new();
+
+        triggers.add(Event::new("SOME_EVENT_KEY".to_owned(), FakeData{name: "line\nbreak ünï 🎉\u{7f}".to_owned()}));
+
+        let value = triggers.header_value().unwrap();
+
+        assert_eq!(
+            value.to_str().unwrap(),
+            "{\"SOME_EVENT_KEY\":{\"name\":\"line\\nbreak \\u00fcn\\u00ef \\ud83c\\udf89\\u007f\"}}"
+        );
+
+        // still the same JSON once parsed by the client
+        let parsed: serde_json::Value = serde_json::from_str(value.to_str().unwrap()).unwrap();
+        assert_eq!(parsed["SOME_EVENT_KEY"]["name"], "line\nbreak ünï 🎉\u{7f}");
+    }
+
+    #[derive(Serialize)]
+    pub struct Ratio {
+        pub name: String,
+        pub value: f64,
+    }
+
+    #[test]
+    fn test_trigger_non_finite() {
+        let ratio = |value: f64| Event::new("ratio".to_owned(), Ratio { name: "conversion".to_owned(), value });
+
+        let mut triggers = Triggers::new();
+        triggers.add(ratio(f64::NAN));
+        assert!(triggers.header_value().is_none());
+
+        // only the offending trigger is left out
+        triggers.add(Event::empty("saved".to_owned()));
+        assert_eq!(triggers.header_value().unwrap().to_str().unwrap(), "{\"saved\":null}");
+
+        let mut triggers = Triggers::new().non_finite(NonFinite::Null);
+        triggers.add(ratio(f64::INFINITY));
+
+        // the data's keys come out in any order
+        let parsed: serde_json::Value = serde_json::from_str(triggers.header_value().unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(parsed, serde_json::json!({ "ratio": { "name": "conversion", "value": null } }));
+    }
+
     #[test]
     fn test_trigger_serialize_mixed_key() {
         let mut triggers = Triggers::new();
@@ -329,4 +1407,152 @@ mod test {
         println!("{}", serde_json::to_string(&triggers).unwrap());
         // assert_eq!(serde_json::to_string(&triggers).unwrap(), "{\"SOME_EVENT_KEY\":[null,{\"name\":\"SOME_EVENT_DATA\"}]}");
     }
+
+    #[tokio::test]
+    async fn test_bulk_triggers() {
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let accessor = ContextAccessor::from_request(&request);
+        let mut context = accessor.context().await;
+
+        context.empty_trigger("invoices:changed".to_owned());
+        context.add_triggers(["totals:changed", "activity:changed"].map(|key| Event::empty(key.to_owned())));
+        context.add_triggers(vec![Event::new("saved".to_owned(), FakeData { name: "invoice".to_owned() })]);
+
+        let value: serde_json::Value = serde_json::from_str(context.triggers().unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(value, serde_json::json!({
+            "invoices:changed": null,
+            "totals:changed": null,
+            "activity:changed": null,
+            "saved": { "name": "invoice" },
+        }));
+
+        context.clear_triggers();
+        assert!(context.triggers().is_none());
+
+        context.empty_trigger("failed".to_owned());
+        assert_eq!(context.triggers().unwrap().to_str().unwrap(), "{\"failed\":null}");
+    }
+
+    #[tokio::test]
+    async fn test_triggers_of_htmx_requests() {
+        let router = Router::new()
+            .route("/save", post(|Extension(accessor): Extension<ContextAccessor>| async move {
+                accessor.context().await.empty_trigger("saved".to_owned());
+                "saved"
+            }))
+            .route("/noop", post(|| async { "nothing" }))
+            .layer(ContextLayer::new());
+
+        let send = |uri: &'static str, headers: &'static [(&'static str, &'static str)]| {
+            let router = router.clone();
+
+            async move {
+                let mut request = Request::post(uri);
+                for (name, value) in headers {
+                    request = request.header(*name, *value);
+                }
+
+                router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+                    .headers().get(HX_TRIGGER).map(|value| value.to_str().unwrap().to_owned())
+            }
+        };
+
+        let saved = Some("{\"saved\":null}".to_owned());
+
+        // hx-post without boost
+        assert_eq!(send("/save", &[("hx-request", "true")]).await, saved);
+        assert_eq!(send("/save", &[("hx-request", "true"), ("hx-boosted", "true")]).await, saved);
+
+        // no empty `{}` header, and nothing for plain requests
+        assert_eq!(send("/noop", &[("hx-request", "true")]).await, None);
+        assert_eq!(send("/save", &[]).await, None);
+        assert_eq!(Triggers::new().header_value(), None);
+    }
+
+    #[tokio::test]
+    async fn test_trigger_timings() {
+        let router = Router::new()
+            .route("/chart", post(|Extension(accessor): Extension<ContextAccessor>| async move {
+                let mut context = accessor.context().await;
+                context.empty_trigger("saved".to_owned());
+                context.add_trigger_after_swap("chart".to_owned(), FakeData { name: "swap".to_owned() });
+                context.add_trigger_after_settle("chart".to_owned(), FakeData { name: "settle".to_owned() });
+                "chart"
+            }))
+            .layer(ContextLayer::new());
+
+        let response = router.oneshot(Request::post("/chart").header("hx-request", "true").body(Body::empty()).unwrap()).await.unwrap();
+        let header = |name: &str| response.headers().get(name).map(|value| value.to_str().unwrap().to_owned());
+
+        // the same key is sent in each of its headers
+        assert_eq!(header(HX_TRIGGER), Some("{\"saved\":null}".to_owned()));
+        assert_eq!(header(HX_TRIGGER_AFTER_SETTLE), Some("{\"chart\":{\"name\":\"settle\"}}".to_owned()));
+        assert_eq!(header(HX_TRIGGER_AFTER_SWAP), Some("{\"chart\":{\"name\":\"swap\"}}".to_owned()));
+
+        let mut triggers = Triggers::new();
+        triggers.add(Event::empty("chart".to_owned()).at(Timing::AfterSwap));
+        assert_eq!(triggers.header_value(), None);
+        assert_eq!(serde_json::to_string(&triggers).unwrap(), "{}");
+        assert_eq!(triggers.header_value_at(Timing::AfterSwap).unwrap().to_str().unwrap(), "{\"chart\":null}");
+    }
+
+    #[test]
+    fn test_url_path() {
+        assert_eq!(url_path("https://example.com/invoices/42?tab=2#items"), "/invoices/42");
+        assert_eq!(url_path("http://localhost:3001"), "/");
+        assert_eq!(url_path("/relative?x=1"), "/relative");
+    }
+
+    #[tokio::test]
+    async fn test_current_url_of_boosted_request() {
+        let request = Request::builder()
+            .uri("/invoices/42/items")
+            .header(HX_REQUEST, "true")
+            .header(HX_BOOSTED, "true")
+            .header(HX_CURRENT_URL, "https://example.com/invoices/42?tab=2")
+            .body(Body::empty())
+            .unwrap();
+
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert_eq!(context.current_url().as_deref(), Some("https://example.com/invoices/42?tab=2"));
+        assert_eq!(context.current_path(), "/invoices/42");
+    }
+
+    #[tokio::test]
+    async fn test_raw_cookies() {
+        let request = Request::builder()
+            .uri("/")
+            .header("cookie", "theme=dark; consent=%7B%22ads%22%3Afalse%7D")
+            .header("cookie", "cart=a%20b%2Cc; theme=light")
+            .body(Body::empty())
+            .unwrap();
+
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert_eq!(context.cookie("theme").as_deref(), Some("dark"));
+        assert_eq!(context.cookie("consent").as_deref(), Some(r#"{"ads":false}"#));
+        assert_eq!(context.cookie("cart").as_deref(), Some("a b,c"));
+        assert_eq!(context.cookie("missing"), None);
+
+        assert_eq!(context.cookies(), vec![
+            ("theme".to_owned(), "dark".to_owned()),
+            ("consent".to_owned(), r#"{"ads":false}"#.to_owned()),
+            ("cart".to_owned(), "a b,c".to_owned()),
+            ("theme".to_owned(), "light".to_owned()),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_current_path_without_htmx() {
+        let request = Request::builder().uri("/invoices").body(Body::empty()).unwrap();
+
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert_eq!(context.current_url(), None);
+        assert_eq!(context.current_path(), "/invoices");
+    }
 }
@@ -0,0 +1,460 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error, fmt::Display,
+    time::Duration
+};
+
+use axum::{extract::Request, http::HeaderValue};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use hyper::{header::COOKIE, HeaderMap};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha256;
+
+use crate::config::Cookies;
+
+// browsers drop cookies over 4KB, warn a little before that
+const COOKIE_WARN_BYTES: usize = 3686;
+
+// every queued Set-Cookie of one response together
+const RESPONSE_WARN_BYTES: usize = 7372;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie owned by a feature, stored as versioned JSON.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct DismissedBanners { ids: Vec<String> }
+///
+/// impl TypedCookie for DismissedBanners {
+///     const NAME: &'static str = "dismissed_banners";
+/// }
+///
+/// let dismissed: Option<DismissedBanners> = context.typed_cookie();
+/// context.set_cookie(&DismissedBanners { ids })?;
+/// ```
+pub trait TypedCookie: Serialize + DeserializeOwned + Send + Sync + 'static {
+    const NAME: &'static str;
+
+    /// Bump when the serialized shape changes, older cookies go through `migrate`.
+    const VERSION: u32 = 1;
+
+    const MAX_AGE: Option<Duration> = Some(Duration::from_secs(365 * 24 * 60 * 60));
+    const SAME_SITE: SameSite = SameSite::Lax;
+    const HTTP_ONLY: bool = true;
+
+    /// Signed cookies require `server.cookies.key`, tampered values read as `None`.
+    const SIGNED: bool = false;
+
+    /// Upgrade the JSON of an older version, `None` drops the cookie.
+    fn migrate(_version: u32, _value: serde_json::Value) -> Option<Self> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum CookieError {
+    MissingKey(&'static str),
+    Serialize(serde_json::Error),
+}
+
+impl Display for CookieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CookieError::MissingKey(name) => write!(f, "cookie {name} is signed but server.cookies.key is not configured"),
+            CookieError::Serialize(e) => write!(f, "unable to serialize cookie: {e}"),
+        }
+    }
+}
+
+impl Error for CookieError {}
+
+/// Application wide cookie settings, shared by every request.
+#[derive(Debug, Clone, Default)]
+pub struct CookieSettings {
+    key: Option<Vec<u8>>,
+    trust_proxy: bool,
+}
+
+impl CookieSettings {
+    pub fn from_config(config: &Cookies) -> Self {
+        Self {
+            key: config.key.as_ref().map(|key| key.as_bytes().to_vec()),
+            trust_proxy: config.trust_proxy,
+        }
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn signature(key: &[u8], name: &str, payload: &str) -> HmacSha256 {
+    // any key length is accepted by HMAC
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(name.as_bytes());
+    mac.update(b"=");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn encode<T: TypedCookie>(value: &T, key: Option<&[u8]>) -> Result<String, CookieError> {
+    let json: Vec<u8> = serde_json::to_vec(value).map_err(CookieError::Serialize)?;
+    let payload: String = format!("v{}.{}", T::VERSION, URL_SAFE_NO_PAD.encode(json));
+
+    if !T::SIGNED {
+        return Ok(payload);
+    }
+
+    let key: &[u8] = key.ok_or(CookieError::MissingKey(T::NAME))?;
+    let signature: Vec<u8> = signature(key, T::NAME, &payload).finalize().into_bytes().to_vec();
+
+    Ok(format!("{payload}.{}", URL_SAFE_NO_PAD.encode(signature)))
+}
+
+fn decode<T: TypedCookie>(raw: &str, key: Option<&[u8]>) -> Option<T> {
+    let payload: &str = match T::SIGNED {
+        true => {
+            let (payload, signature) = raw.rsplit_once('.')?;
+            let signature: Vec<u8> = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+            self::signature(key?, T::NAME, payload).verify_slice(&signature).ok()?;
+            payload
+        },
+        false => raw,
+    };
+
+    let (version, json) = payload.split_once('.')?;
+    let version: u32 = version.strip_prefix('v')?.parse().ok()?;
+    let json: Vec<u8> = URL_SAFE_NO_PAD.decode(json).ok()?;
+
+    match version {
+        v if v == T::VERSION => serde_json::from_slice(&json).ok(),
+        v if v < T::VERSION => T::migrate(v, serde_json::from_slice(&json).ok()?),
+        // written by a newer deployment
+        _ => None,
+    }
+}
+
+/// `name=value` pairs of the Cookie header(s), in order.
+pub(crate) fn parse_cookies(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers.get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .map(|(name, value)| (name.trim().to_owned(), value.trim().trim_matches('"').to_owned()))
+        .collect()
+}
+
+/// `%XX` escapes of a cookie value decoded, invalid escapes are kept as written.
+pub(crate) fn percent_decode(value: &str) -> String {
+    let bytes: &[u8] = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i: usize = 0;
+
+    while i < bytes.len() {
+        let escaped: Option<u8> = match bytes[i] {
+            b'%' => bytes.get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Incoming cookies and the writes queued for the response.
+/// Several writes of the same cookie in one request produce one Set-Cookie.
+#[derive(Clone)]
+pub(crate) struct CookieJar {
+    incoming: HashMap<String, String>,
+
+    // name -> (value, Set-Cookie header)
+    queued: BTreeMap<String, (String, String)>,
+
+    key: Option<Vec<u8>>,
+
+    // https, directly or through a trusted proxy
+    secure: bool,
+}
+
+impl CookieJar {
+    pub fn from_request(request: &Request, settings: &CookieSettings) -> Self {
+        let forwarded_https: bool = request.headers().get("x-forwarded-proto")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|proto| proto.trim().eq_ignore_ascii_case("https"))
+            .unwrap_or(false);
+
+        let secure: bool = request.uri().scheme_str() == Some("https")
+            || (settings.trust_proxy && forwarded_https);
+
+        let mut incoming: HashMap<String, String> = HashMap::new();
+
+        for (name, value) in parse_cookies(request.headers()) {
+            // the first occurrence is the most specific path
+            incoming.entry(name).or_insert(value);
+        }
+
+        Self { incoming, queued: BTreeMap::new(), key: settings.key.clone(), secure }
+    }
+
+    pub fn get<T: TypedCookie>(&self) -> Option<T> {
+        let raw: &String = match self.queued.get(T::NAME) {
+            Some((value, _)) => value,
+            None => self.incoming.get(T::NAME)?,
+        };
+
+        decode(raw, self.key.as_deref())
+    }
+
+    pub fn set<T: TypedCookie>(&mut self, value: &T) -> Result<(), CookieError> {
+        let encoded: String = encode(value, self.key.as_deref())?;
+        let header: String = self.header::<T>(&encoded, T::MAX_AGE);
+
+        self.queued.insert(T::NAME.to_owned(), (encoded, header));
+        Ok(())
+    }
+
+    pub fn remove<T: TypedCookie>(&mut self) {
+        let header: String = self.header::<T>("", Some(Duration::ZERO));
+
+        self.queued.insert(T::NAME.to_owned(), (String::new(), header));
+    }
+
+    fn header<T: TypedCookie>(&self, value: &str, max_age: Option<Duration>) -> String {
+        let mut header: String = format!("{}={value}; Path=/", T::NAME);
+
+        if let Some(max_age) = max_age {
+            header.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+
+        header.push_str("; SameSite=");
+        header.push_str(T::SAME_SITE.as_str());
+
+        if T::HTTP_ONLY {
+            header.push_str("; HttpOnly");
+        }
+
+        // browsers reject SameSite=None without Secure
+        if self.secure || T::SAME_SITE == SameSite::None {
+            header.push_str("; Secure");
+        }
+
+        header
+    }
+
+    /// Set-Cookie values for the response, warns when nearing browser limits.
+    pub fn headers(&self) -> Vec<HeaderValue> {
+        let mut total: usize = 0;
+
+        let headers: Vec<HeaderValue> = self.queued.iter()
+            .filter_map(|(name, (_, header))| {
+                total += header.len();
+
+                if header.len() > COOKIE_WARN_BYTES {
+                    tracing::warn!("cookie {name} is {} bytes, browsers drop cookies over 4096", header.len());
+                }
+
+                HeaderValue::from_str(header).ok()
+            })
+            .collect();
+
+        if total > RESPONSE_WARN_BYTES {
+            tracing::warn!("{total} bytes of Set-Cookie headers in one response");
+        }
+
+        headers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request};
+    use serde::{Deserialize, Serialize};
+
+    use super::{percent_decode, CookieJar, CookieSettings, SameSite, TypedCookie};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct DismissedV1 {
+        ids: Vec<String>,
+    }
+
+    impl TypedCookie for DismissedV1 {
+        const NAME: &'static str = "dismissed";
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Dismissed {
+        banners: Vec<String>,
+    }
+
+    impl TypedCookie for Dismissed {
+        const NAME: &'static str = "dismissed";
+        const VERSION: u32 = 2;
+
+        fn migrate(version: u32, value: serde_json::Value) -> Option<Self> {
+            let v1: DismissedV1 = match version {
+                1 => serde_json::from_value(value).ok()?,
+                _ => return None,
+            };
+
+            Some(Dismissed { banners: v1.ids })
+        }
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Variant {
+        name: String,
+    }
+
+    impl TypedCookie for Variant {
+        const NAME: &'static str = "variant";
+        const SIGNED: bool = true;
+        const SAME_SITE: SameSite = SameSite::Strict;
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Embed;
+
+    impl TypedCookie for Embed {
+        const NAME: &'static str = "embed";
+        const SAME_SITE: SameSite = SameSite::None;
+        const HTTP_ONLY: bool = false;
+        const MAX_AGE: Option<std::time::Duration> = None;
+    }
+
+    fn settings(key: Option<&str>, trust_proxy: bool) -> CookieSettings {
+        CookieSettings { key: key.map(|key| key.as_bytes().to_vec()), trust_proxy }
+    }
+
+    fn jar(cookies: &str, settings: &CookieSettings) -> CookieJar {
+        let request = Request::builder().uri("/").header("cookie", cookies).body(Body::empty()).unwrap();
+        CookieJar::from_request(&request, settings)
+    }
+
+    /// The value a browser would send back for the first queued cookie.
+    fn sent(jar: &CookieJar) -> String {
+        let header = jar.headers()[0].to_str().unwrap().to_owned();
+        header.split(';').next().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_signed_round_trip() {
+        let settings = settings(Some("secret"), false);
+        let mut writer = jar("", &settings);
+        writer.set(&Variant { name: "b".to_owned() }).unwrap();
+
+        let cookie = sent(&writer);
+        assert_eq!(jar(&cookie, &settings).get::<Variant>(), Some(Variant { name: "b".to_owned() }));
+
+        // tampered payload
+        let (name, value) = cookie.split_once('=').unwrap();
+        let (payload, signature) = value.rsplit_once('.').unwrap();
+        let forged = format!("{name}={}X.{signature}", payload);
+        assert_eq!(jar(&forged, &settings).get::<Variant>(), None);
+
+        // another key
+        assert_eq!(jar(&cookie, &self::settings(Some("other"), false)).get::<Variant>(), None);
+    }
+
+    #[test]
+    fn test_signed_requires_key() {
+        let mut writer = jar("", &settings(None, false));
+
+        assert!(writer.set(&Variant { name: "b".to_owned() }).is_err());
+    }
+
+    #[test]
+    fn test_version_migration() {
+        let settings = settings(None, false);
+        let mut writer = jar("", &settings);
+        writer.set(&DismissedV1 { ids: vec!["welcome".to_owned()] }).unwrap();
+
+        let cookie = sent(&writer);
+        assert!(cookie.starts_with("dismissed=v1."));
+
+        let reader = jar(&cookie, &settings);
+        assert_eq!(reader.get::<Dismissed>(), Some(Dismissed { banners: vec!["welcome".to_owned()] }));
+
+        // a newer version is not understood by older code
+        let mut writer = jar("", &settings);
+        writer.set(&Dismissed { banners: vec![] }).unwrap();
+        assert_eq!(jar(&sent(&writer), &settings).get::<DismissedV1>(), None);
+    }
+
+    #[test]
+    fn test_writes_are_coalesced() {
+        let settings = settings(None, false);
+        let mut jar = jar("", &settings);
+
+        jar.set(&Dismissed { banners: vec!["a".to_owned()] }).unwrap();
+        jar.set(&Dismissed { banners: vec!["a".to_owned(), "b".to_owned()] }).unwrap();
+
+        assert_eq!(jar.headers().len(), 1);
+
+        // reads see the queued write
+        assert_eq!(jar.get::<Dismissed>().unwrap().banners.len(), 2);
+    }
+
+    #[test]
+    fn test_flags_behind_proxy() {
+        let request = |proto: &str| Request::builder()
+            .uri("/")
+            .header("x-forwarded-proto", proto)
+            .body(Body::empty())
+            .unwrap();
+
+        let mut trusted = CookieJar::from_request(&request("https"), &settings(Some("k"), true));
+        trusted.set(&Variant { name: "a".to_owned() }).unwrap();
+        let header = trusted.headers()[0].to_str().unwrap().to_owned();
+        assert!(header.contains("; SameSite=Strict; HttpOnly; Secure"));
+        assert!(header.contains("; Max-Age=31536000"));
+
+        // the header is ignored unless the proxy is trusted
+        let mut untrusted = CookieJar::from_request(&request("https"), &settings(Some("k"), false));
+        untrusted.set(&Variant { name: "a".to_owned() }).unwrap();
+        assert!(!untrusted.headers()[0].to_str().unwrap().contains("Secure"));
+
+        // SameSite=None is always Secure
+        let mut embed = CookieJar::from_request(&request("http"), &settings(None, true));
+        embed.set(&Embed).unwrap();
+        assert_eq!(embed.headers()[0].to_str().unwrap(), "embed=v1.bnVsbA; Path=/; SameSite=None; Secure");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("caf%C3%A9%20au%20lait"), "café au lait");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+        assert_eq!(percent_decode("%+1"), "%+1");
+    }
+}
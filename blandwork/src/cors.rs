@@ -0,0 +1,176 @@
+use std::{convert::Infallible, future::Future, pin::Pin, task::{Context as TaskContext, Poll}};
+
+use axum::{body::Body, extract::Request, http::{header::ACCESS_CONTROL_REQUEST_METHOD, Method}, middleware::map_response, response::Response, Router};
+use tower::{service_fn, Layer, Service, ServiceExt};
+use tower_http::cors::CorsLayer;
+
+// response extension, the request was answered under a `Feature::cors` policy
+#[derive(Debug, Clone, Copy)]
+struct FeatureCors;
+
+/// The feature's routes under its own policy, which answers their preflights and
+/// marks their responses for `AppCorsLayer` to leave alone.
+pub(crate) fn feature_cors(router: Router, cors: CorsLayer) -> Router {
+    router
+        .layer(cors)
+        .layer(map_response(|mut response: Response| async move {
+            response.extensions_mut().insert(FeatureCors);
+            response
+        }))
+}
+
+/// The app-wide CORS policy (`server.core_layers.cors`) for every route without a
+/// `Feature::cors` policy. It runs after routing: a request reaching a feature's own
+/// policy is answered by it alone, preflights included.
+#[derive(Clone)]
+pub struct AppCorsLayer {
+    cors: CorsLayer,
+}
+
+impl AppCorsLayer {
+    pub fn new(cors: CorsLayer) -> Self {
+        Self { cors }
+    }
+}
+
+impl<S> Layer<S> for AppCorsLayer {
+    type Service = AppCorsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AppCorsService { inner, cors: self.cors.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppCorsService<S> {
+    inner: S,
+    cors: CorsLayer,
+}
+
+impl<S> Service<Request> for AppCorsService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // what the policy is evaluated against once the response is known
+        let mut parts: Request = Request::new(Body::empty());
+        *parts.method_mut() = req.method().clone();
+
+        // tower-http answers any OPTIONS as a preflight, a plain one keeps `OptionsLayer`'s answer
+        if req.method() == Method::OPTIONS && !req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD) {
+            *parts.method_mut() = Method::GET;
+        }
+        *parts.uri_mut() = req.uri().clone();
+        *parts.version_mut() = req.version();
+        *parts.headers_mut() = req.headers().clone();
+
+        let cors: CorsLayer = self.cors.clone();
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let response: Response<Body> = inner.await?;
+
+            if response.extensions().get::<FeatureCors>().is_some() {
+                return Ok(response);
+            }
+
+            // a preflight is answered by the policy, anything else gets its headers
+            let mut response: Option<Response<Body>> = Some(response);
+            let answered = cors
+                .layer(service_fn(move |_: Request| std::future::ready(Ok::<_, Infallible>(response.take().unwrap_or_default()))))
+                .oneshot(parts)
+                .await;
+
+            match answered {
+                Ok(response) => Ok(response),
+                Err(never) => match never {},
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request, routing::get, Router};
+    use hyper::{header::{ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN}, Method, StatusCode};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+    use tower_http::cors::CorsLayer;
+
+    use crate::{test::router, App, Config, Context, Feature, Template};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct PublicApi;
+
+    impl Feature for PublicApi {
+        fn name(&self) -> String {
+            "PublicApi".to_owned()
+        }
+
+        fn api(&self) -> Option<Router> {
+            Some(Router::new().route("/api/rates", get(|| async { "{}" })))
+        }
+
+        fn cors(&self) -> Option<CorsLayer> {
+            Some(CorsLayer::permissive())
+        }
+    }
+
+    struct Invoices;
+
+    impl Feature for Invoices {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/invoices", get(|| async { html! { "invoices" } })))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_feature_cors() {
+        let mut app = App::new(Config::default(), TestTemplate).register_feature(PublicApi).register_feature(Invoices);
+        let app = router(&app.build());
+
+        let request = Request::get("/api/rates").header(ORIGIN, "https://partner.example").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+
+        let request = Request::builder().method(Method::OPTIONS).uri("/api/rates")
+            .header(ORIGIN, "https://partner.example")
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(response.headers().contains_key(ACCESS_CONTROL_ALLOW_METHODS));
+
+        // the rest of the app stays under the locked down default
+        let request = Request::get("/invoices").header(ORIGIN, "https://partner.example").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let request = Request::builder().method(Method::OPTIONS).uri("/invoices")
+            .header(ORIGIN, "https://partner.example")
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_METHODS));
+    }
+}
@@ -0,0 +1,477 @@
+use std::{
+    fmt::Display, io::IsTerminal, net::SocketAddr,
+    panic::{catch_unwind, AssertUnwindSafe}
+};
+
+use axum::Router;
+use serde::Serialize;
+
+use crate::{
+    config::AssetConflicts,
+    feature_config::{parse_feature_configs, FeatureConfigError},
+    head_assets::{AssetError, HeadAssets},
+    Config, Feature
+};
+
+/// Where a startup problem comes from, the sections of a `DiagnosticReport`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    ConfigFile,
+    FeatureConfig,
+    Assets,
+    Routes,
+    Bind,
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::ConfigFile => write!(f, "Config file"),
+            Stage::FeatureConfig => write!(f, "Feature config"),
+            Stage::Assets => write!(f, "Head assets"),
+            Stage::Routes => write!(f, "Routes"),
+            Stage::Bind => write!(f, "Listening"),
+        }
+    }
+}
+
+/// The fix suggested for a class of startup problem.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Suggestion {
+    CreateConfigFile { path: String },
+    FixTomlSyntax { line: Option<usize> },
+    AddConfigKey { key: String },
+    FixConfigValue { path: String },
+    FixFeatureSection { section: String, field: Option<String> },
+    AlignAssetVersions { name: String },
+    DeclareAsset { name: String },
+    BreakAssetCycle,
+    SeparateRoutes { route: String },
+    FreePort { port: u16 },
+    UsePortAbove1024 { port: u16 },
+    CheckHost { host: String },
+}
+
+impl Display for Suggestion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Suggestion::CreateConfigFile { path } => write!(f, "create {path} or point the app at the right file"),
+            Suggestion::FixTomlSyntax { line: Some(line) } => write!(f, "fix the TOML syntax on line {line}"),
+            Suggestion::FixTomlSyntax { line: None } => write!(f, "fix the TOML syntax of the file"),
+            Suggestion::AddConfigKey { key } => write!(f, "add `{key}` to the config file"),
+            Suggestion::FixConfigValue { path } => write!(f, "check the type and spelling of {path}"),
+            Suggestion::FixFeatureSection { section, field: Some(field) } => write!(f, "set a valid `{field}` in [features.{section}]"),
+            Suggestion::FixFeatureSection { section, field: None } => write!(f, "complete [features.{section}], the feature's config type lists its fields"),
+            Suggestion::AlignAssetVersions { name } => write!(f, "require one version of {name} in every feature, or set server.assets.conflicts = \"highest\""),
+            Suggestion::DeclareAsset { name } => write!(f, "declare {name} in a feature's head_assets or drop the dependency"),
+            Suggestion::BreakAssetCycle => write!(f, "remove one of the `requires` between these assets"),
+            Suggestion::SeparateRoutes { route } => write!(f, "{route} is mounted twice, rename one or give its group a RouteGroup::prefix"),
+            Suggestion::FreePort { port } => write!(f, "port {port} is already in use, is another instance running? set server.port or stop the other process"),
+            Suggestion::UsePortAbove1024 { port } => write!(f, "port {port} needs elevated privileges, set server.port above 1024"),
+            Suggestion::CheckHost { host } => write!(f, "{host} is not an address of this machine, set server.host (0.0.0.0 for every interface)"),
+        }
+    }
+}
+
+/// One startup problem.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub stage: Stage,
+    pub message: String,
+
+    /// The offending value, when there is one.
+    pub value: Option<String>,
+
+    /// `config.toml:12` or a TOML path like `features.billing.stripe_key`.
+    pub location: Option<String>,
+    pub suggestion: Suggestion,
+}
+
+/// Every problem found before the app could start, grouped by stage when rendered.
+/// `App::check` collects it without starting, `build` and `run` print it before failing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DiagnosticReport {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl DiagnosticReport {
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn extend(&mut self, report: DiagnosticReport) {
+        self.diagnostics.extend(report.diagnostics);
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    /// Sections by stage, ANSI colored with `color`.
+    pub fn render(&self, color: bool) -> String {
+        let paint = |code: &str, text: &str| match color {
+            true => format!("\x1b[{code}m{text}\x1b[0m"),
+            false => text.to_owned(),
+        };
+
+        let mut stages: Vec<Stage> = self.diagnostics.iter().map(|diagnostic| diagnostic.stage).collect();
+        stages.sort();
+        stages.dedup();
+
+        let mut output: String = paint("1;31", &format!("The app can't start, {} problem(s) found\n", self.diagnostics.len()));
+
+        for stage in stages {
+            output.push_str(&format!("\n{}\n", paint("1", &stage.to_string())));
+
+            for diagnostic in self.diagnostics.iter().filter(|diagnostic| diagnostic.stage == stage) {
+                output.push_str(&format!("  {} {}\n", paint("31", "error:"), diagnostic.message));
+
+                if let Some(location) = diagnostic.location.as_deref() {
+                    output.push_str(&format!("    at {}\n", paint("36", location)));
+                }
+
+                if let Some(value) = diagnostic.value.as_deref() {
+                    output.push_str(&format!("    value {}\n", paint("33", value)));
+                }
+
+                output.push_str(&format!("    {} {}\n", paint("32", "fix:"), diagnostic.suggestion));
+            }
+        }
+
+        output
+    }
+
+    /// Print the report to stderr, colored on a terminal.
+    pub fn print(&self) {
+        eprintln!("{}", self.render(std::io::stderr().is_terminal()));
+    }
+
+    /// Print the report and stop: the panic only points at it.
+    pub(crate) fn fail(&self) -> ! {
+        self.print();
+        panic!("startup failed with {} problem(s), see the report above", self.diagnostics.len());
+    }
+
+    /// The config file at `path`, or what is wrong with it.
+    pub fn config_file(path: &str) -> Result<Config, DiagnosticReport> {
+        let source: String = std::fs::read_to_string(path).map_err(|e| DiagnosticReport::from(Diagnostic {
+            stage: Stage::ConfigFile,
+            message: format!("unable to read the config file: {e}"),
+            value: None,
+            location: Some(path.to_owned()),
+            suggestion: Suggestion::CreateConfigFile { path: path.to_owned() },
+        }))?;
+
+        Self::config_source(path, &source)
+    }
+
+    fn config_source(path: &str, source: &str) -> Result<Config, DiagnosticReport> {
+        // syntax first, a well formed file is then checked against the Config
+        if let Err(e) = toml::from_str::<toml::Table>(source) {
+            let line: Option<usize> = line_of(source, e.span());
+
+            return Err(DiagnosticReport::from(Diagnostic {
+                stage: Stage::ConfigFile,
+                message: e.message().trim().to_owned(),
+                value: None,
+                location: Some(located(path, line)),
+                suggestion: Suggestion::FixTomlSyntax { line },
+            }));
+        }
+
+        toml::from_str::<Config>(source).map_err(|e| {
+            let message: String = e.message().trim().to_owned();
+            let line: Option<usize> = line_of(source, e.span());
+
+            let suggestion: Suggestion = match message.strip_prefix("missing field `").and_then(|rest| rest.split_once('`')) {
+                Some((key, _)) => Suggestion::AddConfigKey { key: key.to_owned() },
+                None => Suggestion::FixConfigValue { path: located(path, line) },
+            };
+
+            DiagnosticReport::from(Diagnostic {
+                stage: Stage::ConfigFile,
+                message,
+                value: None,
+                location: Some(located(path, line)),
+                suggestion,
+            })
+        })
+    }
+
+    /// The failure of binding the listener to `address`.
+    pub fn bind(address: &str, error: &std::io::Error) -> Self {
+        let port: u16 = address.parse::<SocketAddr>().map(|address| address.port())
+            .ok()
+            .or_else(|| address.rsplit_once(':').and_then(|(_, port)| port.parse().ok()))
+            .unwrap_or(0);
+        let host: String = address.rsplit_once(':').map(|(host, _)| host.to_owned()).unwrap_or_default();
+
+        let suggestion: Suggestion = match error.kind() {
+            std::io::ErrorKind::AddrInUse => Suggestion::FreePort { port },
+            std::io::ErrorKind::PermissionDenied => Suggestion::UsePortAbove1024 { port },
+            _ => Suggestion::CheckHost { host },
+        };
+
+        DiagnosticReport::from(Diagnostic {
+            stage: Stage::Bind,
+            message: format!("unable to listen: {error}"),
+            value: Some(address.to_owned()),
+            location: Some("server.host, server.port".to_owned()),
+            suggestion,
+        })
+    }
+}
+
+impl From<Diagnostic> for DiagnosticReport {
+    fn from(diagnostic: Diagnostic) -> Self {
+        Self { diagnostics: vec![diagnostic] }
+    }
+}
+
+impl From<&FeatureConfigError> for DiagnosticReport {
+    fn from(error: &FeatureConfigError) -> Self {
+        Self {
+            diagnostics: error.0.iter().map(|problem| Diagnostic {
+                stage: Stage::FeatureConfig,
+                message: problem.message.clone(),
+                value: None,
+                location: Some(match problem.field.as_deref() {
+                    Some(field) => format!("features.{}.{field}", problem.section),
+                    None => format!("features.{}", problem.section),
+                }),
+                suggestion: Suggestion::FixFeatureSection { section: problem.section.clone(), field: problem.field.clone() },
+            }).collect(),
+        }
+    }
+}
+
+impl From<&AssetError> for DiagnosticReport {
+    fn from(error: &AssetError) -> Self {
+        let (value, suggestion): (Option<String>, Suggestion) = match error {
+            AssetError::Conflict { name, first, second } => (
+                Some(format!("{} {} / {} {}", first.0, first.1, second.0, second.1)),
+                Suggestion::AlignAssetVersions { name: name.clone() },
+            ),
+            AssetError::MissingDependency { requires, .. } => (Some(requires.clone()), Suggestion::DeclareAsset { name: requires.clone() }),
+            AssetError::Cycle(names) => (Some(names.join(", ")), Suggestion::BreakAssetCycle),
+        };
+
+        DiagnosticReport::from(Diagnostic {
+            stage: Stage::Assets,
+            message: error.to_string(),
+            value,
+            location: Some("server.assets.conflicts".to_owned()).filter(|_| matches!(error, AssetError::Conflict { .. })),
+            suggestion,
+        })
+    }
+}
+
+/// Every problem of the features against `config`, without mounting anything: the groups'
+/// routers are built once, only to find routes mounted twice.
+pub(crate) fn check_features(features: &[Box<dyn Feature>], config: &Config) -> DiagnosticReport {
+    let mut report: DiagnosticReport = DiagnosticReport::default();
+
+    if let Err(e) = parse_feature_configs(features, config) {
+        report.extend(DiagnosticReport::from(&e));
+    }
+
+    let conflicts: AssetConflicts = config.server.assets.conflicts;
+    if let Err(e) = HeadAssets::from_features(features, conflicts) {
+        report.extend(DiagnosticReport::from(&e));
+    }
+
+    report.extend(route_conflicts(features));
+    report
+}
+
+/// Routes two groups both mount, which axum refuses with a panic when merging them.
+fn route_conflicts(features: &[Box<dyn Feature>]) -> DiagnosticReport {
+    let mut report: DiagnosticReport = DiagnosticReport::default();
+    let mut mounted: Router = Router::new();
+
+    // the merge panics are reported, not printed
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+
+    for feature in features.iter() {
+        for group in feature.groups() {
+            let (group, router): (String, Router) = group.unmounted();
+            let name: String = format!("{}/{group}", feature.name());
+
+            let before: Router = mounted.clone();
+            match catch_unwind(AssertUnwindSafe(move || before.merge(router))) {
+                Ok(merged) => mounted = merged,
+                Err(payload) => {
+                    let message: String = payload.downcast_ref::<String>().cloned()
+                        .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+                        .unwrap_or_else(|| "conflicting routes".to_owned());
+
+                    // "Overlapping method route. Handler for `GET /invoices` already exists"
+                    let route: String = message.split('`').nth(1)
+                        .and_then(|handler| handler.rsplit(' ').next())
+                        .unwrap_or("a route")
+                        .to_owned();
+
+                    report.push(Diagnostic {
+                        stage: Stage::Routes,
+                        message,
+                        value: Some(route.clone()),
+                        location: Some(name),
+                        suggestion: Suggestion::SeparateRoutes { route },
+                    });
+                },
+            }
+        }
+    }
+
+    std::panic::set_hook(hook);
+    report
+}
+
+/// 1 based line of the start of `span`.
+fn line_of(source: &str, span: Option<std::ops::Range<usize>>) -> Option<usize> {
+    span.map(|span| source[..span.start.min(source.len())].matches('\n').count() + 1)
+}
+
+fn located(path: &str, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{path}:{line}"),
+        None => path.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{routing::get, Router};
+    use serde::Deserialize;
+
+    use crate::{feature_config::{ConfigSchema, Invalid, Validate}, head_assets::HeadAsset, Config, Feature};
+
+    use super::{check_features, DiagnosticReport, Stage, Suggestion};
+
+    const CONFIG: &str = "
+        [database]
+        host = 'localhost'
+        port = 5432
+        database = 'app'
+        username = 'app'
+        password = 'secret'
+
+        [server]
+        host = '0.0.0.0'
+        port = 3000
+    ";
+
+    #[test]
+    fn test_config_file() {
+        let report = DiagnosticReport::config_file("/nonexistent/blandwork.toml").err().unwrap();
+        assert_eq!(report.diagnostics[0].suggestion, Suggestion::CreateConfigFile { path: "/nonexistent/blandwork.toml".to_owned() });
+
+        let report = DiagnosticReport::config_source("app.toml", "[server]\nhost = '0.0.0.0\n").err().unwrap();
+        assert_eq!(report.diagnostics[0].stage, Stage::ConfigFile);
+        assert_eq!(report.diagnostics[0].suggestion, Suggestion::FixTomlSyntax { line: Some(2) });
+        assert_eq!(report.diagnostics[0].location.as_deref(), Some("app.toml:2"));
+
+        let report = DiagnosticReport::config_source("app.toml", "[server]\nhost = '0.0.0.0'\nport = 3000\n").err().unwrap();
+        assert_eq!(report.diagnostics[0].suggestion, Suggestion::AddConfigKey { key: "database".to_owned() });
+    }
+
+    #[test]
+    fn test_port_in_use() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = taken.local_addr().unwrap().to_string();
+        let error = std::net::TcpListener::bind(&address).err().unwrap();
+
+        let report = DiagnosticReport::bind(&address, &error);
+        let port = taken.local_addr().unwrap().port();
+
+        assert_eq!(report.diagnostics[0].suggestion, Suggestion::FreePort { port });
+        assert_eq!(report.diagnostics[0].value.as_deref(), Some(address.as_str()));
+        assert!(report.render(false).contains(&format!("fix: port {port} is already in use, is another instance running?")));
+    }
+
+    #[derive(Deserialize)]
+    struct BillingConfig {
+        stripe_key: String,
+    }
+
+    impl Validate for BillingConfig {
+        fn validate(&self) -> Vec<Invalid> {
+            match self.stripe_key.starts_with("sk_") {
+                true => Vec::new(),
+                false => vec![Invalid::new("stripe_key", "is not a secret key")],
+            }
+        }
+    }
+
+    struct Billing;
+
+    impl Feature for Billing {
+        fn name(&self) -> String {
+            "Billing".to_owned()
+        }
+
+        fn config_schema(&self) -> Option<ConfigSchema> {
+            Some(ConfigSchema::new::<BillingConfig>("billing"))
+        }
+
+        fn head_assets(&self) -> Vec<HeadAsset> {
+            vec![HeadAsset::script("chartjs", "3.9.0", "/web/chart-3.js")]
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/invoices", get(|| async { "billing" })))
+        }
+    }
+
+    struct Reports;
+
+    impl Feature for Reports {
+        fn name(&self) -> String {
+            "Reports".to_owned()
+        }
+
+        fn head_assets(&self) -> Vec<HeadAsset> {
+            vec![HeadAsset::script("chartjs", "4.4.1", "/web/chart-4.js")]
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/invoices", get(|| async { "reports" })))
+        }
+    }
+
+    #[test]
+    fn test_feature_problems() {
+        let config: Config = toml::from_str(&format!("{CONFIG}\n[features.billing]\nstripe_key = 'pk_live'")).unwrap();
+        let features: Vec<Box<dyn Feature>> = vec![Box::new(Billing), Box::new(Reports)];
+
+        let report = check_features(&features, &config);
+        let suggestions: Vec<(Stage, Suggestion)> = report.diagnostics.iter()
+            .map(|diagnostic| (diagnostic.stage, diagnostic.suggestion.clone()))
+            .collect();
+
+        assert_eq!(suggestions, vec![
+            (Stage::FeatureConfig, Suggestion::FixFeatureSection { section: "billing".to_owned(), field: Some("stripe_key".to_owned()) }),
+            (Stage::Assets, Suggestion::AlignAssetVersions { name: "chartjs".to_owned() }),
+            (Stage::Routes, Suggestion::SeparateRoutes { route: "/invoices".to_owned() }),
+        ]);
+
+        assert_eq!(report.diagnostics[0].location.as_deref(), Some("features.billing.stripe_key"));
+        assert_eq!(report.diagnostics[1].value.as_deref(), Some("Billing 3.9.0 / Reports 4.4.1"));
+        assert_eq!(report.diagnostics[2].location.as_deref(), Some("Reports/web"));
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["diagnostics"][1]["suggestion"], serde_json::json!({ "kind": "align_asset_versions", "name": "chartjs" }));
+
+        let rendered = report.render(false);
+        assert!(rendered.starts_with("The app can't start, 3 problem(s) found\n\nFeature config\n  error: is not a secret key\n    at features.billing.stripe_key\n"));
+        assert!(!rendered.contains("\x1b["));
+        assert!(report.render(true).contains("\x1b[32mfix:\x1b[0m"));
+    }
+}
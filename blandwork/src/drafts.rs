@@ -0,0 +1,534 @@
+use std::{
+    collections::HashMap, error::Error, fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime}
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::Path,
+    http::HeaderValue,
+    response::{IntoResponse, Response},
+    routing::post, Extension, Router
+};
+use hyper::{header::{COOKIE, SET_COOKIE}, HeaderMap, StatusCode};
+use maud::{html, Markup};
+use uuid::Uuid;
+
+use crate::{
+    clock::{relative_time, Clock, SharedClock, SystemClock},
+    Context, Feature
+};
+
+pub const DRAFT_COOKIE: &str = "blandwork_draft";
+pub const DRAFT_ROUTE: &str = "/_blandwork/drafts";
+
+/// Serialized (urlencoded) form fields saved while the user is typing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Draft {
+    pub owner: String,
+    pub form_id: String,
+    pub fields: String,
+    pub saved_at: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum DraftError {
+    TooLarge { size: usize, max: usize },
+    TooFrequent,
+    Store(String),
+}
+
+impl Display for DraftError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DraftError::TooLarge { size, max } => write!(f, "draft of {size} bytes exceeds the {max} byte limit"),
+            DraftError::TooFrequent => write!(f, "draft saved too frequently"),
+            DraftError::Store(e) => write!(f, "draft store error: {e}"),
+        }
+    }
+}
+
+impl Error for DraftError {}
+
+/// Storage contract for drafts, keyed by (owner, form id).
+#[async_trait]
+pub trait DraftStore: Send + Sync {
+    async fn save(&self, draft: Draft) -> Result<(), DraftError>;
+
+    async fn load(&self, owner: &str, form_id: &str) -> Result<Option<Draft>, DraftError>;
+
+    async fn clear(&self, owner: &str, form_id: &str) -> Result<(), DraftError>;
+
+    /// Remove drafts saved before `before`, returning how many were removed.
+    async fn purge(&self, before: SystemTime) -> Result<u64, DraftError>;
+}
+
+#[derive(Default)]
+pub struct MemoryDraftStore {
+    drafts: Mutex<HashMap<(String, String), Draft>>
+}
+
+#[async_trait]
+impl DraftStore for MemoryDraftStore {
+    async fn save(&self, draft: Draft) -> Result<(), DraftError> {
+        let key = (draft.owner.clone(), draft.form_id.clone());
+        self.drafts.lock().unwrap().insert(key, draft);
+        Ok(())
+    }
+
+    async fn load(&self, owner: &str, form_id: &str) -> Result<Option<Draft>, DraftError> {
+        let key = (owner.to_owned(), form_id.to_owned());
+        Ok(self.drafts.lock().unwrap().get(&key).cloned())
+    }
+
+    async fn clear(&self, owner: &str, form_id: &str) -> Result<(), DraftError> {
+        let key = (owner.to_owned(), form_id.to_owned());
+        self.drafts.lock().unwrap().remove(&key);
+        Ok(())
+    }
+
+    async fn purge(&self, before: SystemTime) -> Result<u64, DraftError> {
+        let mut drafts = self.drafts.lock().unwrap();
+        let count: usize = drafts.len();
+
+        drafts.retain(|_, draft| draft.saved_at >= before);
+
+        Ok((count - drafts.len()) as u64)
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresDraftStore;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::time::SystemTime;
+
+    use async_trait::async_trait;
+
+    use crate::db::ConnectionPool;
+
+    use super::{Draft, DraftError, DraftStore};
+
+    impl From<tokio_postgres::Error> for DraftError {
+        fn from(value: tokio_postgres::Error) -> Self {
+            DraftError::Store(value.to_string())
+        }
+    }
+
+    /// Create with `PostgresDraftStore::TABLE` before use.
+    pub struct PostgresDraftStore {
+        pool: ConnectionPool
+    }
+
+    impl PostgresDraftStore {
+        pub const TABLE: &'static str = "CREATE TABLE IF NOT EXISTS blandwork_drafts (
+            owner TEXT NOT NULL,
+            form_id TEXT NOT NULL,
+            fields TEXT NOT NULL,
+            saved_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (owner, form_id)
+        )";
+
+        pub fn new(pool: ConnectionPool) -> Self {
+            Self { pool }
+        }
+
+        pub async fn migrate(&self) -> Result<(), DraftError> {
+            let connection = self.pool.get().await.map_err(|e| DraftError::Store(e.to_string()))?;
+            connection.batch_execute(Self::TABLE).await?;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl DraftStore for PostgresDraftStore {
+        async fn save(&self, draft: Draft) -> Result<(), DraftError> {
+            let connection = self.pool.get().await.map_err(|e| DraftError::Store(e.to_string()))?;
+
+            connection.execute(
+                "INSERT INTO blandwork_drafts (owner, form_id, fields, saved_at) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (owner, form_id) DO UPDATE SET fields = EXCLUDED.fields, saved_at = EXCLUDED.saved_at",
+                &[&draft.owner, &draft.form_id, &draft.fields, &draft.saved_at]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn load(&self, owner: &str, form_id: &str) -> Result<Option<Draft>, DraftError> {
+            let connection = self.pool.get().await.map_err(|e| DraftError::Store(e.to_string()))?;
+
+            let row = connection.query_opt(
+                "SELECT fields, saved_at FROM blandwork_drafts WHERE owner = $1 AND form_id = $2",
+                &[&owner, &form_id]
+            ).await?;
+
+            Ok(row.map(|row| Draft {
+                owner: owner.to_owned(),
+                form_id: form_id.to_owned(),
+                fields: row.get("fields"),
+                saved_at: row.get("saved_at"),
+            }))
+        }
+
+        async fn clear(&self, owner: &str, form_id: &str) -> Result<(), DraftError> {
+            let connection = self.pool.get().await.map_err(|e| DraftError::Store(e.to_string()))?;
+
+            connection.execute(
+                "DELETE FROM blandwork_drafts WHERE owner = $1 AND form_id = $2",
+                &[&owner, &form_id]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn purge(&self, before: SystemTime) -> Result<u64, DraftError> {
+            let connection = self.pool.get().await.map_err(|e| DraftError::Store(e.to_string()))?;
+
+            Ok(connection.execute("DELETE FROM blandwork_drafts WHERE saved_at < $1", &[&before]).await?)
+        }
+    }
+}
+
+/// hx attributes for a form which autosaves, see `autosave_attrs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autosave {
+    pub url: String,
+    pub trigger: String,
+}
+
+/// ```ignore
+/// let autosave = autosave_attrs("post-editor", Duration::from_secs(2));
+/// html! {
+///     form hx-post=(autosave.url) hx-trigger=(autosave.trigger) hx-swap="none" { ... }
+/// }
+/// ```
+pub fn autosave_attrs(form_id: &str, interval: Duration) -> Autosave {
+    Autosave {
+        url: format!("{DRAFT_ROUTE}/{form_id}"),
+        trigger: format!("input changed delay:{}ms", interval.as_millis()),
+    }
+}
+
+/// Draft owner from the draft cookie, set by the first autosave.
+pub fn draft_owner(headers: &HeaderMap) -> Option<String> {
+    headers.get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == DRAFT_COOKIE)
+        .map(|(_, value)| value.to_owned())
+}
+
+/// Draft autosave and restore.
+/// Registered as a feature it mounts the supplemental autosave route.
+#[derive(Clone)]
+pub struct Drafts {
+    store: Arc<dyn DraftStore>,
+
+    // None follows the app's clock in requests, real time outside of them
+    clock: Option<SharedClock>,
+
+    // largest accepted serialized form
+    max_bytes: usize,
+
+    // minimum time between two saves of the same form
+    min_interval: Duration,
+
+    // drafts older than this are removed by `purge_expired`
+    ttl: Duration,
+}
+
+impl Drafts {
+    pub fn new(store: impl DraftStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            clock: None,
+            max_bytes: 64 * 1024,
+            min_interval: Duration::from_secs(1),
+            ttl: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self::new(MemoryDraftStore::default())
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn min_interval(mut self, min_interval: Duration) -> Self {
+        self.min_interval = min_interval;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    // the clock given with `with_clock`, else the app's one
+    fn now(&self, app: Option<&SharedClock>) -> SystemTime {
+        match self.clock.as_ref().or(app) {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+
+    pub async fn save(&self, owner: &str, form_id: &str, fields: String) -> Result<Draft, DraftError> {
+        self.save_at(owner, form_id, fields, self.now(None)).await
+    }
+
+    async fn save_at(&self, owner: &str, form_id: &str, fields: String, now: SystemTime) -> Result<Draft, DraftError> {
+        if fields.len() > self.max_bytes {
+            return Err(DraftError::TooLarge { size: fields.len(), max: self.max_bytes });
+        }
+
+        if let Some(previous) = self.store.load(owner, form_id).await? {
+            let elapsed: Duration = now.duration_since(previous.saved_at).unwrap_or_default();
+
+            if elapsed < self.min_interval {
+                return Err(DraftError::TooFrequent);
+            }
+        }
+
+        let draft: Draft = Draft {
+            owner: owner.to_owned(),
+            form_id: form_id.to_owned(),
+            fields,
+            saved_at: now,
+        };
+
+        self.store.save(draft.clone()).await?;
+
+        Ok(draft)
+    }
+
+    /// The draft for a form when it is newer than the last saved version of the record
+    /// (`None` when the record was never saved).
+    pub async fn restore(&self, owner: &str, form_id: &str, record_saved_at: Option<SystemTime>) -> Result<Option<Draft>, DraftError> {
+        let draft: Option<Draft> = self.store.load(owner, form_id).await?;
+
+        Ok(draft.filter(|draft| match record_saved_at {
+            Some(saved_at) => draft.saved_at > saved_at,
+            None => true,
+        }))
+    }
+
+    /// Call after the form was submitted successfully.
+    pub async fn clear(&self, owner: &str, form_id: &str) -> Result<(), DraftError> {
+        self.store.clear(owner, form_id).await
+    }
+
+    /// Remove expired drafts, meant to run periodically.
+    pub async fn purge_expired(&self) -> Result<u64, DraftError> {
+        self.store.purge(self.now(None) - self.ttl).await
+    }
+
+    /// Dismissible "restore draft from 5 minutes ago?" banner. The serialized fields are
+    /// carried along, `htmx_integration.js` fills the form (`id` = form id, or the form
+    /// autosaving to it) with them on Restore.
+    pub fn restore_banner(&self, context: &Context, draft: &Draft) -> Markup {
+        let now: SystemTime = self.clock.as_ref().map_or_else(|| context.now(), |clock| clock.now());
+        let age: Duration = now.duration_since(draft.saved_at).unwrap_or_default();
+
+        html!{
+            div .draft-restore role="status" data-draft-form=(draft.form_id) data-draft-fields=(draft.fields) {
+                "Restore draft from " (relative_time(age)) "?"
+                button type="button" data-draft-restore=(draft.form_id) { "Restore" }
+                button type="button" onclick="this.parentElement.remove()" { "Dismiss" }
+            }
+        }
+    }
+
+    async fn autosave(
+        Extension(drafts): Extension<Drafts>,
+        clock: Option<Extension<SharedClock>>,
+        Path(form_id): Path<String>,
+        headers: HeaderMap,
+        fields: String
+    ) -> Response {
+        let (owner, new_owner) = match draft_owner(&headers) {
+            Some(owner) => (owner, false),
+            None => (Uuid::new_v4().to_string(), true),
+        };
+
+        let now: SystemTime = drafts.now(clock.as_ref().map(|Extension(clock)| clock));
+
+        let mut response: Response = match drafts.save_at(&owner, &form_id, fields, now).await {
+            Ok(_) => StatusCode::NO_CONTENT.into_response(),
+            Err(DraftError::TooLarge { .. }) => StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+            Err(DraftError::TooFrequent) => StatusCode::TOO_MANY_REQUESTS.into_response(),
+            Err(e) => {
+                tracing::error!("unable to save draft {form_id}: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+
+        if new_owner {
+            let cookie: String = format!("{DRAFT_COOKIE}={owner}; Path=/; HttpOnly; SameSite=Lax");
+
+            if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+                response.headers_mut().insert(SET_COOKIE, cookie);
+            }
+        }
+
+        response
+    }
+}
+
+impl Feature for Drafts {
+    fn supplemental(&self) -> Option<Router> {
+        Some(Router::new()
+            .route(&format!("{DRAFT_ROUTE}/:form_id"), post(Drafts::autosave))
+            .layer(Extension(self.clone())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{body::Body, extract::Request};
+
+    use crate::{clock::{Clock, SharedClock}, ContextAccessor, TestClock};
+
+    use super::{autosave_attrs, Draft, DraftError, Drafts, DRAFT_ROUTE};
+
+    const INTEGRATION_SCRIPT: &str = include_str!("../../web/htmx_integration.js");
+
+    fn drafts(clock: &TestClock) -> Drafts {
+        Drafts::memory()
+            .with_clock(clock.clone())
+            .max_bytes(32)
+    }
+
+    #[tokio::test]
+    async fn test_save_restore_round_trip() {
+        let clock = TestClock::new();
+        let drafts = drafts(&clock);
+
+        drafts.save("owner", "post", "title=Hello&body=World".to_owned()).await.unwrap();
+
+        let draft = drafts.restore("owner", "post", None).await.unwrap().unwrap();
+
+        assert_eq!(draft.fields, "title=Hello&body=World");
+        assert_eq!(draft.saved_at, clock.now());
+        assert!(drafts.restore("someone-else", "post", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_restore_newer_than_record() {
+        let clock = TestClock::new();
+        let drafts = drafts(&clock);
+        let record_saved_at = clock.now();
+
+        clock.advance(Duration::from_secs(60));
+        drafts.save("owner", "post", "body=draft".to_owned()).await.unwrap();
+
+        assert!(drafts.restore("owner", "post", Some(record_saved_at)).await.unwrap().is_some());
+        assert!(drafts.restore("owner", "post", Some(clock.now() + Duration::from_secs(1))).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_clear_on_submit() {
+        let clock = TestClock::new();
+        let drafts = drafts(&clock);
+
+        drafts.save("owner", "post", "body=draft".to_owned()).await.unwrap();
+        drafts.clear("owner", "post").await.unwrap();
+
+        assert!(drafts.restore("owner", "post", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_size_cap() {
+        let clock = TestClock::new();
+        let drafts = drafts(&clock);
+
+        let result = drafts.save("owner", "post", "body=".to_owned() + &"x".repeat(32)).await;
+
+        assert!(matches!(result, Err(DraftError::TooLarge { size: 37, max: 32 })));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_and_expiry() {
+        let clock = TestClock::new();
+        let drafts = drafts(&clock);
+
+        drafts.save("owner", "post", "body=a".to_owned()).await.unwrap();
+
+        let result = drafts.save("owner", "post", "body=ab".to_owned()).await;
+        assert!(matches!(result, Err(DraftError::TooFrequent)));
+
+        clock.advance(Duration::from_secs(1));
+        drafts.save("owner", "post", "body=ab".to_owned()).await.unwrap();
+
+        clock.advance(Duration::from_secs(8 * 24 * 60 * 60));
+        assert_eq!(drafts.purge_expired().await.unwrap(), 1);
+    }
+
+    fn context(clock: &TestClock) -> ContextAccessor {
+        let mut request = Request::builder().uri("/posts/1/edit").body(Body::empty()).unwrap();
+        request.extensions_mut().insert::<SharedClock>(Arc::new(clock.clone()));
+
+        ContextAccessor::from_request(&request)
+    }
+
+    #[tokio::test]
+    async fn test_restore_banner() {
+        let clock = TestClock::new();
+        let drafts = drafts(&clock);
+
+        let draft = drafts.save("owner", "post", "body=draft".to_owned()).await.unwrap();
+        clock.advance(Duration::from_secs(5 * 60));
+
+        let banner = drafts.restore_banner(&context(&TestClock::new()).context().await, &draft).into_string();
+
+        assert!(banner.contains("Restore draft from 5 minutes ago?"));
+        assert!(banner.contains("data-draft-fields=\"body=draft\""));
+    }
+
+    #[tokio::test]
+    async fn test_restore_banner_on_app_clock() {
+        let clock = TestClock::new();
+        let draft = Draft { owner: "owner".to_owned(), form_id: "post".to_owned(), fields: "body=draft".to_owned(), saved_at: clock.now() };
+        clock.advance(Duration::from_secs(2 * 60 * 60));
+
+        let banner = Drafts::memory().restore_banner(&context(&clock).context().await, &draft).into_string();
+
+        assert!(banner.contains("Restore draft from 2 hours ago?"));
+    }
+
+    // the banner's attributes are what the integration script reads
+    #[tokio::test]
+    async fn test_restore_contract() {
+        let clock = TestClock::new();
+        let draft = Draft { owner: "owner".to_owned(), form_id: "post".to_owned(), fields: "body=draft".to_owned(), saved_at: clock.now() };
+        let banner = Drafts::memory().restore_banner(&context(&clock).context().await, &draft).into_string();
+
+        assert!(banner.contains(r#"data-draft-fields="body=draft""#));
+        assert!(banner.contains(r#"<button type="button" data-draft-restore="post">"#));
+
+        for read in ["button[data-draft-restore]", "[data-draft-fields]", "dataset.draftRestore", "dataset.draftFields", DRAFT_ROUTE] {
+            assert!(INTEGRATION_SCRIPT.contains(read), "htmx_integration.js does not read {read}");
+        }
+    }
+
+    #[test]
+    fn test_autosave_attrs() {
+        let autosave = autosave_attrs("post", Duration::from_secs(2));
+
+        assert_eq!(autosave.url, "/_blandwork/drafts/post");
+        assert_eq!(autosave.trigger, "input changed delay:2000ms");
+    }
+}
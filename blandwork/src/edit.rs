@@ -0,0 +1,451 @@
+use std::{error::Error, fmt::Display, future::Future, pin::Pin, sync::Arc};
+
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    routing::get, Extension, Form, Router
+};
+use hyper::StatusCode;
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+
+use crate::{cache::ResponseCache, ContextAccessor};
+
+/// A field's current value and the version it was read at
+/// (an `updated_at` timestamp or a version column, rendered as text).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldValue {
+    pub value: String,
+    pub version: String,
+}
+
+impl FieldValue {
+    pub fn new(value: impl Into<String>, version: impl ToString) -> Self {
+        Self { value: value.into(), version: version.to_string() }
+    }
+}
+
+#[derive(Debug)]
+pub enum EditError {
+    NotFound,
+
+    /// The record changed since the edit form was rendered, carries the newer value.
+    Conflict(FieldValue),
+
+    /// Messages shown under the input.
+    Invalid(Vec<String>),
+
+    Failed(String),
+}
+
+impl Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditError::NotFound => write!(f, "record not found"),
+            EditError::Conflict(current) => write!(f, "record modified concurrently (now at version {})", current.version),
+            EditError::Invalid(errors) => write!(f, "invalid value: {}", errors.join(", ")),
+            EditError::Failed(e) => write!(f, "unable to save: {e}"),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+/// `field:updated` trigger detail.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldUpdated {
+    pub id: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EditForm {
+    value: String,
+    version: String,
+}
+
+/// Re-rendered forms keep the input's focus and caret when morph swaps are enabled.
+async fn morphed(accessor: &ContextAccessor, form: Markup) -> Response {
+    accessor.context().await.swap_morph_outer();
+    form.into_response()
+}
+
+type EditFuture = Pin<Box<dyn Future<Output = Result<FieldValue, EditError>> + Send>>;
+type Load = Arc<dyn Fn(String) -> EditFuture + Send + Sync>;
+type Save = Arc<dyn Fn(String, String, String) -> EditFuture + Send + Sync>;
+type Validate = Arc<dyn Fn(&str) -> Result<(), Vec<String>> + Send + Sync>;
+type Tag = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Click to edit a single field of a record, with optimistic locking.
+///
+/// `router()` serves `{base}/:id/{field}` (display, PUT to save) and `{base}/:id/{field}/edit`,
+/// merge it into the feature's supplemental router and render `display` in the page.
+/// The version read with the value travels in the edit form, a save against a newer
+/// version answers a conflict fragment offering to overwrite or cancel.
+/// Fragments are answered 200 so HTMX swaps them.
+///
+/// ```ignore
+/// let title = EditInPlace::new("/posts", "title",
+///     move |id| { let db = db.clone(); async move { db.post_title(&id).await } },
+///     move |id, value, version| { let db = db.clone(); async move { db.set_post_title(&id, &value, &version).await } })
+///     .validate(|value| if value.is_empty() { Err(vec!["Title is required".to_owned()]) } else { Ok(()) })
+///     .invalidates(|id| format!("post:{id}"));
+/// ```
+#[derive(Clone)]
+pub struct EditInPlace {
+    base: String,
+    field: String,
+    load: Load,
+    save: Save,
+    validate: Option<Validate>,
+    tag: Option<Tag>,
+}
+
+impl EditInPlace {
+    /// `save(id, value, version)` must only write when the record is still at `version`
+    /// (`UPDATE .. WHERE version = $3`) and answer `EditError::Conflict` otherwise.
+    /// `base` can't contain path parameters.
+    pub fn new<L, LFut, S, SFut>(base: &str, field: &str, load: L, save: S) -> Self
+    where
+        L: Fn(String) -> LFut + Send + Sync + 'static,
+        LFut: Future<Output = Result<FieldValue, EditError>> + Send + 'static,
+        S: Fn(String, String, String) -> SFut + Send + Sync + 'static,
+        SFut: Future<Output = Result<FieldValue, EditError>> + Send + 'static,
+    {
+        Self {
+            base: base.trim_end_matches('/').to_owned(),
+            field: field.to_owned(),
+            load: Arc::new(move |id: String| -> EditFuture { Box::pin(load(id)) }),
+            save: Arc::new(move |id: String, value: String, version: String| -> EditFuture { Box::pin(save(id, value, version)) }),
+            validate: None,
+            tag: None,
+        }
+    }
+
+    /// Checked before `save`, failures re-render the edit form with the messages.
+    pub fn validate(mut self, validate: impl Fn(&str) -> Result<(), Vec<String>> + Send + Sync + 'static) -> Self {
+        self.validate = Some(Arc::new(validate));
+        self
+    }
+
+    /// Cache tag evicted from the `ResponseCache` (and CDN) after a save.
+    pub fn invalidates(mut self, tag: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.tag = Some(Arc::new(tag));
+        self
+    }
+
+    fn url(&self, id: &str) -> String {
+        format!("{}/{id}/{}", self.base, self.field)
+    }
+
+    fn element_id(&self, id: &str) -> String {
+        format!("{}-{id}", self.field)
+    }
+
+    /// Name of the trigger sent after a save, `title:updated`.
+    pub fn trigger(&self) -> String {
+        format!("{}:updated", self.field)
+    }
+
+    /// The read-only state, a click swaps in the edit form.
+    pub fn display(&self, id: &str, current: &FieldValue) -> Markup {
+        html!{
+            span #(self.element_id(id)) .edit-in-place
+                hx-get=(format!("{}/edit", self.url(id)))
+                hx-trigger="click"
+                hx-target="this"
+                hx-swap="outerHTML"
+                role="button"
+                tabindex="0" {
+                (current.value)
+            }
+        }
+    }
+
+    /// The edit state, the version read with the value is submitted along.
+    pub fn edit_form(&self, id: &str, value: &str, version: &str, errors: &[String]) -> Markup {
+        html!{
+            form #(self.element_id(id)) .edit-in-place
+                hx-put=(self.url(id))
+                hx-target="this"
+                hx-swap="outerHTML"
+                hx-sync="this:drop" {
+                input type="text" name="value" value=(value) aria-invalid=[(!errors.is_empty()).then_some("true")] autofocus;
+                input type="hidden" name="version" value=(version);
+                button type="submit" { "Save" }
+                button type="button" hx-get=(self.url(id)) hx-target="closest form" hx-swap="outerHTML" { "Cancel" }
+                @if !errors.is_empty() {
+                    ul .errors role="alert" {
+                        @for error in errors { li { (error) } }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Someone else saved first: shows their value, overwriting submits ours against their version.
+    pub fn conflict(&self, id: &str, mine: &str, current: &FieldValue) -> Markup {
+        html!{
+            form #(self.element_id(id)) .edit-in-place.conflict
+                hx-put=(self.url(id))
+                hx-target="this"
+                hx-swap="outerHTML"
+                hx-sync="this:drop" {
+                p role="alert" { "This was changed while you were editing. It is now: " strong { (current.value) } }
+                input type="hidden" name="value" value=(mine);
+                input type="hidden" name="version" value=(current.version);
+                button type="submit" { "Overwrite with mine" }
+                button type="button" hx-get=(self.url(id)) hx-target="closest form" hx-swap="outerHTML" { "Cancel" }
+            }
+        }
+    }
+
+    fn failure(&self, e: EditError) -> Response {
+        match e {
+            EditError::NotFound => StatusCode::NOT_FOUND.into_response(),
+            e => {
+                tracing::error!(field = %self.field, "edit in place failed: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+
+    async fn show(&self, id: String) -> Response {
+        match (self.load)(id.clone()).await {
+            Ok(current) => self.display(&id, &current).into_response(),
+            Err(e) => self.failure(e),
+        }
+    }
+
+    async fn edit(&self, id: String) -> Response {
+        match (self.load)(id.clone()).await {
+            Ok(current) => self.edit_form(&id, &current.value, &current.version, &[]).into_response(),
+            Err(e) => self.failure(e),
+        }
+    }
+
+    async fn update(&self, id: String, form: EditForm, accessor: ContextAccessor, cache: Option<ResponseCache>) -> Response {
+        if let Some(validate) = self.validate.as_ref() {
+            if let Err(errors) = validate(&form.value) {
+                return morphed(&accessor, self.edit_form(&id, &form.value, &form.version, &errors)).await;
+            }
+        }
+
+        // cheap early detection, `save` still guards the write itself
+        let current: FieldValue = match (self.load)(id.clone()).await {
+            Ok(current) => current,
+            Err(e) => return self.failure(e),
+        };
+
+        if current.version != form.version {
+            return morphed(&accessor, self.conflict(&id, &form.value, &current)).await;
+        }
+
+        let saved: FieldValue = match (self.save)(id.clone(), form.value.clone(), form.version).await {
+            Ok(saved) => saved,
+            Err(EditError::Conflict(current)) => return morphed(&accessor, self.conflict(&id, &form.value, &current)).await,
+            Err(EditError::Invalid(errors)) => return morphed(&accessor, self.edit_form(&id, &form.value, &current.version, &errors)).await,
+            Err(e) => return self.failure(e),
+        };
+
+        if let (Some(tag), Some(cache)) = (self.tag.as_ref(), cache) {
+            cache.invalidate_tag(&tag(&id));
+        }
+
+        accessor.context().await.add_trigger(self.trigger(), FieldUpdated { id: id.clone(), value: saved.value.clone() });
+
+        self.display(&id, &saved).into_response()
+    }
+
+    /// The display, edit and save routes, see `EditInPlace`.
+    pub fn router(&self) -> Router {
+        let (show, edit, update) = (self.clone(), self.clone(), self.clone());
+
+        Router::new()
+            .route(&format!("{}/:id/{}", self.base, self.field), get(move |Path(id): Path<String>| async move {
+                show.show(id).await
+            })
+            .put(move |
+                Path(id): Path<String>,
+                Extension(accessor): Extension<ContextAccessor>,
+                cache: Option<Extension<ResponseCache>>,
+                Form(form): Form<EditForm>
+            | async move {
+                update.update(id, form, accessor, cache.map(|Extension(cache)| cache)).await
+            }))
+            .route(&format!("{}/:id/{}/edit", self.base, self.field), get(move |Path(id): Path<String>| async move {
+                edit.edit(id).await
+            }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{Arc, Mutex}, time::Duration};
+
+    use axum::{
+        body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router
+    };
+    use axum_htmx::{HX_BOOSTED, HX_REQUEST, HX_RESWAP, HX_TRIGGER};
+    use hyper::{header::CONTENT_TYPE, Method, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, config::{Cache, Morph}, context::ContextLayer, ContextAccessor, ResponseCache, ResponseCacheLayer};
+
+    use super::{EditError, EditInPlace, FieldValue};
+
+    // (title, version)
+    type Post = Arc<Mutex<(String, u64)>>;
+
+    fn title(post: &Post) -> EditInPlace {
+        let (load, save) = (post.clone(), post.clone());
+
+        EditInPlace::new("/posts", "title",
+            move |_id| {
+                let (title, version) = load.lock().unwrap().clone();
+                async move { Ok(FieldValue::new(title, version)) }
+            },
+            move |_id, value, version| {
+                let mut post = save.lock().unwrap();
+                let result = match post.1.to_string() == version {
+                    true => {
+                        *post = (value, post.1 + 1);
+                        Ok(FieldValue::new(post.0.clone(), post.1))
+                    },
+                    false => Err(EditError::Conflict(FieldValue::new(post.0.clone(), post.1))),
+                };
+                async move { result }
+            })
+            .validate(|value| match value.trim().is_empty() {
+                true => Err(vec!["Title is required".to_owned()]),
+                false => Ok(()),
+            })
+            .invalidates(|id| format!("post:{id}"))
+    }
+
+    fn app(post: &Post, cache: &ResponseCache) -> Router {
+        Router::new()
+            .merge(title(post).router())
+            .route("/posts/1", get(|Extension(accessor): Extension<ContextAccessor>| async move {
+                accessor.context().await.cache_tag("post:1");
+                "post 1"
+            }))
+            .layer(ContextLayer::new())
+            .layer(ResponseCacheLayer::new(cache.clone()))
+            .layer(Extension(cache.clone()))
+    }
+
+    async fn send(router: &Router, method: Method, uri: &str, form: Option<&str>) -> (StatusCode, Option<String>, String) {
+        let mut request = Request::builder().method(method).uri(uri)
+            .header(HX_REQUEST, "true")
+            .header(HX_BOOSTED, "true");
+
+        if form.is_some() {
+            request = request.header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+        }
+
+        let body = form.map(|form| Body::from(form.to_owned())).unwrap_or(Body::empty());
+        let response = router.clone().oneshot(request.body(body).unwrap()).await.unwrap();
+
+        let status = response.status();
+        let trigger = response.headers().get(HX_TRIGGER).map(|value| value.to_str().unwrap().to_owned());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, trigger, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn setup() -> (Post, ResponseCache, Router) {
+        let post: Post = Arc::new(Mutex::new(("Hello".to_owned(), 1)));
+        let cache = ResponseCache::new(Cache { enabled: true, ttl: Duration::from_secs(60), ..Default::default() }, Arc::new(TestClock::new()));
+        let router = app(&post, &cache);
+
+        (post, cache, router)
+    }
+
+    #[tokio::test]
+    async fn test_happy_path() {
+        let (post, cache, router) = setup();
+
+        // a cached page showing the post
+        send(&router, Method::GET, "/posts/1", None).await;
+        assert_eq!(cache.tagged("post:1"), 1);
+
+        let (_, _, display) = send(&router, Method::GET, "/posts/1/title", None).await;
+        assert!(display.contains("hx-get=\"/posts/1/title/edit\""));
+        assert!(display.contains("Hello"));
+
+        let (_, _, form) = send(&router, Method::GET, "/posts/1/title/edit", None).await;
+        assert!(form.contains("hx-put=\"/posts/1/title\""));
+        assert!(form.contains("name=\"version\" value=\"1\""));
+
+        let (status, trigger, display) = send(&router, Method::PUT, "/posts/1/title", Some("value=Hello+world&version=1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(display.contains("Hello world"));
+        assert_eq!(*post.lock().unwrap(), ("Hello world".to_owned(), 2));
+
+        // trigger and invalidation
+        let trigger = trigger.unwrap();
+        assert!(trigger.contains("title:updated"));
+        assert!(trigger.contains("Hello world"));
+        assert_eq!(cache.tagged("post:1"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_version_conflict() {
+        let (post, cache, router) = setup();
+        send(&router, Method::GET, "/posts/1", None).await;
+
+        // someone else saved version 2 meanwhile
+        *post.lock().unwrap() = ("Their title".to_owned(), 2);
+
+        let (status, trigger, conflict) = send(&router, Method::PUT, "/posts/1/title", Some("value=Mine&version=1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(conflict.contains("Their title"));
+        assert!(conflict.contains("Overwrite with mine"));
+        assert!(conflict.contains("name=\"value\" value=\"Mine\""));
+        assert!(conflict.contains("name=\"version\" value=\"2\""));
+
+        assert!(trigger.is_none());
+        assert_eq!(cache.tagged("post:1"), 1);
+        assert_eq!(post.lock().unwrap().0, "Their title");
+
+        // overwrite submits against their version
+        send(&router, Method::PUT, "/posts/1/title", Some("value=Mine&version=2")).await;
+        assert_eq!(*post.lock().unwrap(), ("Mine".to_owned(), 3));
+    }
+
+    #[tokio::test]
+    async fn test_validation_errors() {
+        let (post, cache, router) = setup();
+        send(&router, Method::GET, "/posts/1", None).await;
+
+        let (status, trigger, form) = send(&router, Method::PUT, "/posts/1/title", Some("value=+&version=1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(form.contains("Title is required"));
+        assert!(form.contains("aria-invalid=\"true\""));
+
+        assert!(trigger.is_none());
+        assert_eq!(cache.tagged("post:1"), 1);
+        assert_eq!(*post.lock().unwrap(), ("Hello".to_owned(), 1));
+    }
+
+    #[tokio::test]
+    async fn test_morphs_rerendered_forms_when_enabled() {
+        let reswap = |enabled: bool| async move {
+            let (post, cache, _) = setup();
+            let router = app(&post, &cache).layer(Extension(Arc::new(Morph { enabled })));
+
+            let request = Request::put("/posts/1/title")
+                .header(HX_REQUEST, "true")
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("value=+&version=1"))
+                .unwrap();
+
+            let response = router.oneshot(request).await.unwrap();
+            response.headers().get(HX_RESWAP).map(|value| value.to_str().unwrap().to_owned())
+        };
+
+        assert_eq!(reswap(true).await.as_deref(), Some("morph:outerHTML"));
+        assert_eq!(reswap(false).await, None);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, RwLock}
+};
+
+/// Template sources features ship inside their crate (`Feature::templates_source`),
+/// overlaid by the app's template directory: a file of the same name there wins,
+/// so an app can restyle a library feature without forking it. Filled by `build`,
+/// the loader of the app's template engine reads through it:
+///
+/// ```ignore
+/// let sources = app.template_sources().clone();
+/// environment.set_loader(move |name| Ok(sources.get(name)));
+/// ```
+#[derive(Clone)]
+pub struct TemplateSources {
+    inner: Arc<RwLock<Sources>>,
+}
+
+struct Sources {
+    dir: PathBuf,
+
+    // name -> (feature, source)
+    embedded: BTreeMap<String, (String, String)>,
+}
+
+impl Default for TemplateSources {
+    fn default() -> Self {
+        Self::new("templates")
+    }
+}
+
+impl TemplateSources {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { inner: Arc::new(RwLock::new(Sources { dir: dir.into(), embedded: BTreeMap::new() })) }
+    }
+
+    pub(crate) fn set_dir(&self, dir: impl Into<PathBuf>) {
+        self.inner.write().unwrap().dir = dir.into();
+    }
+
+    /// The first feature registering a name keeps it.
+    pub(crate) fn register(&self, feature: &str, templates: Vec<(String, String)>) {
+        let mut inner = self.inner.write().unwrap();
+
+        for (name, source) in templates {
+            if let Some((owner, _)) = inner.embedded.get(&name) {
+                tracing::warn!("template {name} of {feature} is already provided by {owner}, ignored");
+                continue;
+            }
+
+            if app_file(&inner.dir, &name).is_some() {
+                tracing::debug!("template {name} of {feature} is overridden by the app");
+            }
+
+            inner.embedded.insert(name, (feature.to_owned(), source));
+        }
+    }
+
+    /// The app's file when there is one, else the source embedded by a feature.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let inner = self.inner.read().unwrap();
+
+        if let Some(path) = app_file(&inner.dir, name) {
+            match std::fs::read_to_string(&path) {
+                Ok(source) => return Some(source),
+                Err(e) => tracing::error!("unable to read template {}: {e}", path.display()),
+            }
+        }
+
+        inner.embedded.get(name).map(|(_, source)| source.clone())
+    }
+
+    /// The feature whose embedded template `name` is, None for unknown names.
+    pub fn feature(&self, name: &str) -> Option<String> {
+        self.inner.read().unwrap().embedded.get(name).map(|(feature, _)| feature.clone())
+    }
+
+    /// Names of the embedded templates the app replaces with its own file.
+    pub fn overridden(&self) -> Vec<String> {
+        let inner = self.inner.read().unwrap();
+
+        inner.embedded.keys()
+            .filter(|name| app_file(&inner.dir, name).is_some())
+            .cloned()
+            .collect()
+    }
+}
+
+/// `dir/name` when it's a file, names reaching outside of `dir` never are.
+fn app_file(dir: &Path, name: &str) -> Option<PathBuf> {
+    let relative: &Path = Path::new(name);
+
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+        return None;
+    }
+
+    Some(dir.join(relative)).filter(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod test {
+    use maud::{html, Markup};
+    use uuid::Uuid;
+
+    use crate::{App, Config, Context, Feature, Template};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Comments;
+
+    impl Feature for Comments {
+        fn name(&self) -> String {
+            "Comments".to_owned()
+        }
+
+        fn templates_source(&self) -> Vec<(String, String)> {
+            vec![
+                ("comments/list.html".to_owned(), "<ul>{% for c in comments %}<li>{{ c }}</li>{% endfor %}</ul>".to_owned()),
+                ("comments/form.html".to_owned(), "<form></form>".to_owned()),
+            ]
+        }
+    }
+
+    struct Reviews;
+
+    impl Feature for Reviews {
+        fn templates_source(&self) -> Vec<(String, String)> {
+            vec![("comments/form.html".to_owned(), "<form class=\"review\"></form>".to_owned())]
+        }
+    }
+
+    #[test]
+    fn test_app_overrides_feature() {
+        let dir = std::env::temp_dir().join(format!("blandwork-embedded-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("comments")).unwrap();
+        std::fs::write(dir.join("comments/list.html"), "<ol></ol>").unwrap();
+
+        let mut app = App::new(Config::default(), TestTemplate)
+            .templates_dir(&dir)
+            .register_feature(Comments)
+            .register_feature(Reviews);
+
+        let sources = app.template_sources().clone();
+        app.build();
+
+        assert_eq!(sources.get("comments/list.html").unwrap(), "<ol></ol>");
+        assert_eq!(sources.get("comments/form.html").unwrap(), "<form></form>");
+        assert_eq!(sources.feature("comments/form.html").unwrap(), "Comments");
+        assert_eq!(sources.overridden(), vec!["comments/list.html"]);
+
+        assert!(sources.get("missing.html").is_none());
+        assert!(sources.get("../escape.html").is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,765 @@
+use std::{
+    collections::HashMap, error::Error, future::Future,
+    marker::PhantomData, pin::Pin,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, RwLock},
+    time::{Duration, SystemTime}
+};
+
+use axum::Router;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc::{self, UnboundedReceiver, UnboundedSender}};
+#[cfg(any(feature = "sse", feature = "ws", test))]
+use tokio::{sync::broadcast::error::RecvError, time::Instant};
+
+use crate::{clock::{SharedClock, SystemClock}, Context};
+
+/// Server sent stream of the events published with `Published::broadcast`.
+pub const EVENTS_ROUTE: &str = "/_blandwork/events";
+
+pub type HandlerError = Box<dyn Error + Send + Sync>;
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+type Handler = Arc<dyn Fn(AppEvent) -> HandlerFuture + Send + Sync>;
+
+/// A named topic carrying payloads of type `T`.
+///
+/// ```ignore
+/// pub const INVOICE_PAID: Topic<InvoicePaid> = Topic::new("invoice.paid");
+/// ```
+pub struct Topic<T> {
+    key: &'static str,
+    payload: PhantomData<fn() -> T>,
+}
+
+impl<T> Topic<T> {
+    pub const fn new(key: &'static str) -> Self {
+        Self { key, payload: PhantomData }
+    }
+
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+}
+
+/// An in-process domain event, the payload is kept as JSON.
+#[derive(Debug, Clone)]
+pub struct AppEvent {
+    pub topic: String,
+    pub payload: Value,
+    pub published_at: SystemTime,
+}
+
+/// An event a subscriber could not handle within its attempts.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub subscriber: String,
+    pub event: AppEvent,
+    pub error: String,
+    pub attempts: u32,
+}
+
+struct Subscriber {
+    name: String,
+    topic: &'static str,
+    handler: Handler,
+    sender: UnboundedSender<AppEvent>,
+
+    // taken by the worker, started on the first delivery so subscribing needs no runtime
+    receiver: Mutex<Option<UnboundedReceiver<AppEvent>>>,
+}
+
+/// In-process publish/subscribe between features, unrelated to HX-Trigger.
+///
+/// `publish` only enqueues, every subscriber handles its events in order
+/// on its own task, so a slow or failing subscriber delays nobody else.
+/// Failures are retried with a doubling backoff, then recorded as a `DeadLetter`.
+#[derive(Clone)]
+pub struct EventBus {
+    subscribers: Arc<RwLock<Vec<Arc<Subscriber>>>>,
+    dead_letters: Arc<Mutex<Vec<DeadLetter>>>,
+    client: broadcast::Sender<AppEvent>,
+    clock: SharedClock,
+
+    // attempts per event and subscriber, including the first
+    max_attempts: u32,
+
+    // wait after the first failure, doubled for every further one
+    backoff: Duration,
+
+    // merge rules of browser copies per topic, and how much they saved
+    coalescing: Arc<RwLock<HashMap<String, Coalescing>>>,
+    coalesce_stats: Arc<CoalesceStats>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (client, _) = broadcast::channel(64);
+
+        Self {
+            subscribers: Arc::new(RwLock::new(Vec::new())),
+            dead_letters: Arc::new(Mutex::new(Vec::new())),
+            client,
+            clock: Arc::new(SystemClock),
+            max_attempts: 5,
+            backoff: Duration::from_secs(1),
+            coalescing: Arc::new(RwLock::new(HashMap::new())),
+            coalesce_stats: Arc::new(CoalesceStats::default()),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn retries(mut self, max_attempts: u32, backoff: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+
+    /// Run `handler` for every event published on `topic`, `name` identifies it in logs.
+    pub fn subscribe<T, F, Fut>(&self, topic: &Topic<T>, name: &str, handler: F)
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let handler: Handler = Arc::new(move |event: AppEvent| {
+            let handler = handler.clone();
+
+            Box::pin(async move {
+                let payload: T = serde_json::from_value(event.payload)?;
+                handler(payload).await
+            })
+        });
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.subscribers.write().unwrap().push(Arc::new(Subscriber {
+            name: name.to_owned(),
+            topic: topic.key,
+            handler,
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }));
+    }
+
+    /// Enqueue `payload` for every subscriber of `topic`, returns immediately.
+    pub fn publish<T: Serialize>(&self, topic: &Topic<T>, payload: &T) -> Published {
+        let payload: Value = serde_json::to_value(payload).unwrap_or_else(|e| {
+            tracing::error!("unable to serialize {} event: {e}", topic.key);
+            Value::Null
+        });
+
+        let event: AppEvent = AppEvent { topic: topic.key.to_owned(), payload, published_at: self.clock.now() };
+
+        for subscriber in self.subscribers.read().unwrap().iter().filter(|s| s.topic == topic.key) {
+            self.start(subscriber);
+
+            if subscriber.sender.send(event.clone()).is_err() {
+                tracing::error!("event subscriber {} stopped, dropping {}", subscriber.name, topic.key);
+            }
+        }
+
+        Published { event, client: self.client.clone() }
+    }
+
+    fn start(&self, subscriber: &Arc<Subscriber>) {
+        let Some(mut receiver) = subscriber.receiver.lock().unwrap().take() else {
+            return;
+        };
+
+        let bus: EventBus = self.clone();
+        let subscriber: Arc<Subscriber> = subscriber.clone();
+
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                bus.deliver(&subscriber, event).await;
+            }
+        });
+    }
+
+    async fn deliver(&self, subscriber: &Subscriber, event: AppEvent) {
+        let mut backoff: Duration = self.backoff;
+
+        for attempt in 1..=self.max_attempts {
+            let error: HandlerError = match (subscriber.handler)(event.clone()).await {
+                Ok(_) => return,
+                Err(e) => e,
+            };
+
+            if attempt == self.max_attempts {
+                tracing::error!(subscriber = %subscriber.name, topic = %event.topic, "event dead lettered after {attempt} attempts: {error}");
+
+                self.dead_letters.lock().unwrap().push(DeadLetter {
+                    subscriber: subscriber.name.clone(),
+                    event,
+                    error: error.to_string(),
+                    attempts: attempt,
+                });
+                return;
+            }
+
+            tracing::warn!(subscriber = %subscriber.name, topic = %event.topic, "event handler failed (attempt {attempt}): {error}");
+
+            self.clock.sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    /// Events no subscriber could handle, oldest first.
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.dead_letters.lock().unwrap().clone()
+    }
+
+    /// Copies sent by `Published::broadcast`, for the SSE stream and sockets.
+    #[cfg(any(feature = "sse", feature = "ws", test))]
+    pub(crate) fn browser_events(&self) -> broadcast::Receiver<AppEvent> {
+        self.client.subscribe()
+    }
+
+    /// Merge rapid browser copies of `topic` before they reach the SSE stream and sockets,
+    /// the rule is shared by every connection.
+    ///
+    /// ```ignore
+    /// bus.coalesce(&UNREAD, Coalescing::sum("count", Duration::from_millis(250)).keyed_by("conversation"));
+    /// ```
+    pub fn coalesce<T>(&self, topic: &Topic<T>, coalescing: Coalescing) {
+        self.coalescing.write().unwrap().insert(topic.key().to_owned(), coalescing);
+    }
+
+    /// `browser_events` with the coalescing rules applied, one per connection.
+    #[cfg(any(feature = "sse", feature = "ws", test))]
+    pub(crate) fn coalesced_events(&self) -> CoalescedEvents {
+        CoalescedEvents {
+            events: self.browser_events(),
+            rules: self.coalescing.clone(),
+            stats: self.coalesce_stats.clone(),
+            pending: Vec::new(),
+            closed: false,
+        }
+    }
+
+    /// Browser copies received, merged away and delivered, over every connection.
+    pub fn coalesce_counts(&self) -> CoalesceCounts {
+        CoalesceCounts {
+            received: self.coalesce_stats.received.load(Ordering::Relaxed),
+            merged: self.coalesce_stats.merged.load(Ordering::Relaxed),
+            delivered: self.coalesce_stats.delivered.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The `EVENTS_ROUTE` stream, empty without the sse feature.
+    pub fn router(&self) -> Router {
+        #[cfg(feature = "sse")]
+        let router: Router = sse::router(self);
+
+        #[cfg(not(feature = "sse"))]
+        let router: Router = Router::new();
+
+        router
+    }
+}
+
+// keys waiting in one connection's windows, the oldest is flushed early beyond it
+#[cfg(any(feature = "sse", feature = "ws", test))]
+const MAX_PENDING: usize = 256;
+
+// items kept by `Merge::Accumulate` per window, the oldest are dropped
+#[cfg(any(feature = "sse", feature = "ws", test))]
+const MAX_ACCUMULATED: usize = 100;
+
+/// How browser copies with the same topic and key are merged within a window.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Merge {
+    /// Only the latest payload, for counters and badges.
+    KeepLast,
+
+    /// The payloads as an array in publish order, for feed items.
+    Accumulate,
+
+    /// The latest payload with the numeric `field` summed over the window, for increments.
+    Sum(String),
+}
+
+/// A topic's merge rule, see `EventBus::coalesce`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coalescing {
+    merge: Merge,
+    window: Duration,
+
+    // payload field telling apart the events of a topic (`conversation`), None merges all of them
+    key: Option<String>,
+
+    // delivered as published
+    realtime: bool,
+}
+
+impl Coalescing {
+    pub fn keep_last(window: Duration) -> Self {
+        Self { merge: Merge::KeepLast, window, key: None, realtime: false }
+    }
+
+    pub fn accumulate(window: Duration) -> Self {
+        Self { merge: Merge::Accumulate, window, key: None, realtime: false }
+    }
+
+    pub fn sum(field: &str, window: Duration) -> Self {
+        Self { merge: Merge::Sum(field.to_owned()), window, key: None, realtime: false }
+    }
+
+    /// Never merged nor delayed, for keys which must arrive at once (typing indicators).
+    pub fn realtime() -> Self {
+        Self { merge: Merge::KeepLast, window: Duration::ZERO, key: None, realtime: true }
+    }
+
+    pub fn keyed_by(mut self, field: &str) -> Self {
+        self.key = Some(field.to_owned());
+        self
+    }
+
+    #[cfg(any(feature = "sse", feature = "ws", test))]
+    fn key(&self, event: &AppEvent) -> Option<String> {
+        self.key.as_ref().and_then(|field| event.payload.get(field)).map(|value| value.to_string())
+    }
+
+    /// The payload delivered for a window holding only `event`.
+    #[cfg(any(feature = "sse", feature = "ws", test))]
+    fn start(&self, event: AppEvent) -> AppEvent {
+        match self.merge {
+            Merge::Accumulate => AppEvent { payload: Value::Array(vec![event.payload]), ..event },
+            _ => event,
+        }
+    }
+
+    #[cfg(any(feature = "sse", feature = "ws", test))]
+    fn merge(&self, merged: &mut AppEvent, event: AppEvent) {
+        match &self.merge {
+            Merge::KeepLast => *merged = event,
+            Merge::Accumulate => {
+                if let Value::Array(items) = &mut merged.payload {
+                    items.push(event.payload);
+
+                    if items.len() > MAX_ACCUMULATED {
+                        items.remove(0);
+                    }
+                }
+                merged.published_at = event.published_at;
+            },
+            Merge::Sum(field) => {
+                let total: Option<Value> = match (merged.payload.get(field), event.payload.get(field)) {
+                    (Some(Value::Number(a)), Some(Value::Number(b))) => match (a.as_i64(), b.as_i64()) {
+                        (Some(a), Some(b)) => Some(json!(a.saturating_add(b))),
+                        _ => a.as_f64().zip(b.as_f64()).map(|(a, b)| json!(a + b)),
+                    },
+                    _ => None,
+                };
+
+                *merged = event;
+
+                if let (Some(total), Value::Object(payload)) = (total, &mut merged.payload) {
+                    payload.insert(field.clone(), total);
+                }
+            },
+        }
+    }
+}
+
+#[derive(Default)]
+struct CoalesceStats {
+    received: AtomicU64,
+    merged: AtomicU64,
+    delivered: AtomicU64,
+}
+
+/// How much churn coalescing saved, see `EventBus::coalesce_counts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoalesceCounts {
+    pub received: u64,
+    pub merged: u64,
+    pub delivered: u64,
+}
+
+#[cfg(any(feature = "sse", feature = "ws", test))]
+struct Pending {
+    topic: String,
+    key: Option<String>,
+    event: AppEvent,
+    deadline: Instant,
+}
+
+/// One connection's browser copies, merged per (topic, key) until their window ends.
+/// Whatever is pending is delivered once the bus closes, and dropped with the connection.
+#[cfg(any(feature = "sse", feature = "ws", test))]
+pub(crate) struct CoalescedEvents {
+    events: broadcast::Receiver<AppEvent>,
+    rules: Arc<RwLock<HashMap<String, Coalescing>>>,
+    stats: Arc<CoalesceStats>,
+    pending: Vec<Pending>,
+    closed: bool,
+}
+
+#[cfg(any(feature = "sse", feature = "ws", test))]
+impl CoalescedEvents {
+    fn deliver(&self, event: AppEvent) -> Option<AppEvent> {
+        self.stats.delivered.fetch_add(1, Ordering::Relaxed);
+        Some(event)
+    }
+
+    /// The pending window ending first.
+    fn flush(&mut self) -> Option<AppEvent> {
+        let (index, _) = self.pending.iter().enumerate().min_by_key(|(_, pending)| pending.deadline)?;
+        let pending: Pending = self.pending.remove(index);
+
+        self.deliver(pending.event)
+    }
+
+    /// An event to deliver now, None when it was merged or opened a window.
+    fn merge(&mut self, event: AppEvent) -> Option<AppEvent> {
+        self.stats.received.fetch_add(1, Ordering::Relaxed);
+
+        let rule: Option<Coalescing> = self.rules.read().unwrap().get(&event.topic).cloned();
+
+        let Some(rule) = rule.filter(|rule| !rule.realtime) else {
+            return self.deliver(event);
+        };
+
+        let key: Option<String> = rule.key(&event);
+
+        if let Some(pending) = self.pending.iter_mut().find(|pending| pending.topic == event.topic && pending.key == key) {
+            rule.merge(&mut pending.event, event);
+            self.stats.merged.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let flushed: Option<AppEvent> = match self.pending.len() >= MAX_PENDING {
+            true => self.flush(),
+            false => None,
+        };
+
+        self.pending.push(Pending {
+            topic: event.topic.clone(),
+            key,
+            deadline: Instant::now() + rule.window,
+            event: rule.start(event),
+        });
+
+        flushed
+    }
+
+    /// The next event to send, None once the bus is closed and nothing is pending.
+    pub(crate) async fn recv(&mut self) -> Option<AppEvent> {
+        loop {
+            if self.closed {
+                return self.flush();
+            }
+
+            let deadline: Option<Instant> = self.pending.iter().map(|pending| pending.deadline).min();
+
+            let received = tokio::select! {
+                _ = async {
+                    match deadline {
+                        Some(deadline) => tokio::time::sleep_until(deadline).await,
+                        None => std::future::pending().await,
+                    }
+                } => None,
+                event = self.events.recv() => Some(event),
+            };
+
+            match received {
+                None => return self.flush(),
+                Some(Ok(event)) => if let Some(event) = self.merge(event) {
+                    return Some(event);
+                },
+                // a lagged connection misses events, it keeps the stream
+                Some(Err(RecvError::Lagged(_))) => continue,
+                Some(Err(RecvError::Closed)) => self.closed = true,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "sse")]
+mod sse {
+    use std::convert::Infallible;
+
+    use axum::{
+        response::sse::{Event, KeepAlive, Sse},
+        routing::get, Extension, Router
+    };
+    use tokio::sync::mpsc;
+    use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+    use super::{EventBus, EVENTS_ROUTE};
+
+    pub(super) fn router(bus: &EventBus) -> Router {
+        Router::new()
+            .route(EVENTS_ROUTE, get(events))
+            .layer(Extension(bus.clone()))
+    }
+
+    async fn events(Extension(bus): Extension<EventBus>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        let mut events = bus.coalesced_events();
+        let (sender, receiver) = mpsc::channel(16);
+
+        // ends with the browser's stream, pending windows are dropped with it
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = sender.closed() => break,
+                    event = events.recv() => match event {
+                        Some(event) => event,
+                        None => break,
+                    },
+                };
+
+                if sender.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stream = ReceiverStream::new(receiver)
+            .map(|event| Ok(Event::default().event(event.topic).data(event.payload.to_string())));
+
+        Sse::new(stream).keep_alive(KeepAlive::default())
+    }
+}
+
+/// A published event, optionally copied to the browser.
+pub struct Published {
+    event: AppEvent,
+    client: broadcast::Sender<AppEvent>,
+}
+
+impl Published {
+    /// Send a copy to every browser listening on `EVENTS_ROUTE`.
+    pub fn broadcast(self) -> Self {
+        // no receivers simply means no browser is listening
+        let _ = self.client.send(self.event.clone());
+        self
+    }
+
+    /// Send a copy with the current response as an HX-Trigger.
+    pub fn trigger(self, context: &mut Context) -> Self {
+        // trigger details are flattened into an object
+        let payload: Value = match &self.event.payload {
+            Value::Object(_) => self.event.payload.clone(),
+            Value::Null => {
+                context.empty_trigger(self.event.topic.clone());
+                return self;
+            },
+            other => json!({ "value": other }),
+        };
+
+        context.add_trigger(self.event.topic.clone(), payload);
+        self
+    }
+
+    pub fn event(&self) -> &AppEvent {
+        &self.event
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{atomic::{AtomicU32, Ordering}, Arc, Mutex},
+        time::Duration
+    };
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::{json, Value};
+
+    use crate::ContextAccessor;
+
+    use super::{AppEvent, Coalescing, EventBus, HandlerError, Topic};
+
+    async fn wait_until(done: impl Fn() -> bool) {
+        for _ in 0..1000 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct InvoicePaid {
+        id: u32,
+    }
+
+    const INVOICE_PAID: Topic<InvoicePaid> = Topic::new("invoice.paid");
+
+    fn bus() -> EventBus {
+        EventBus::new().retries(3, Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn test_every_subscriber_receives_in_order() {
+        let bus = bus();
+        let notifications = Arc::new(Mutex::new(Vec::new()));
+        let audit = Arc::new(Mutex::new(Vec::new()));
+
+        for received in [notifications.clone(), audit.clone()] {
+            bus.subscribe(&INVOICE_PAID, "subscriber", move |paid: InvoicePaid| {
+                let received = received.clone();
+                async move {
+                    received.lock().unwrap().push(paid.id);
+                    Ok::<(), HandlerError>(())
+                }
+            });
+        }
+
+        for id in 1..=3 {
+            bus.publish(&INVOICE_PAID, &InvoicePaid { id });
+        }
+
+        wait_until(|| audit.lock().unwrap().len() == 3 && notifications.lock().unwrap().len() == 3).await;
+
+        assert_eq!(*notifications.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(*audit.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_failing_subscriber_does_not_block_others() {
+        let bus = bus();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let delivered = Arc::new(AtomicU32::new(0));
+
+        let counter = attempts.clone();
+        bus.subscribe(&INVOICE_PAID, "broken", move |_: InvoicePaid| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err::<(), HandlerError>("mail server down".into())
+            }
+        });
+
+        let counter = delivered.clone();
+        bus.subscribe(&INVOICE_PAID, "working", move |_: InvoicePaid| {
+            let counter = counter.clone();
+            async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok::<(), HandlerError>(())
+            }
+        });
+
+        bus.publish(&INVOICE_PAID, &InvoicePaid { id: 7 });
+
+        wait_until(|| delivered.load(Ordering::SeqCst) == 1).await;
+        wait_until(|| !bus.dead_letters().is_empty()).await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        let dead_letters = bus.dead_letters();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].subscriber, "broken");
+        assert_eq!(dead_letters[0].attempts, 3);
+        assert_eq!(dead_letters[0].event.payload["id"], 7);
+    }
+
+    #[tokio::test]
+    async fn test_bridge_emits_client_copies() {
+        let bus = bus();
+        let mut browser = bus.client.subscribe();
+
+        let request = axum::extract::Request::builder().uri("/").body(axum::body::Body::empty()).unwrap();
+        let accessor = ContextAccessor::from_request(&request);
+        let mut context = accessor.context().await;
+
+        bus.publish(&INVOICE_PAID, &InvoicePaid { id: 9 })
+            .broadcast()
+            .trigger(&mut context);
+
+        let broadcast = browser.recv().await.unwrap();
+        assert_eq!(broadcast.topic, "invoice.paid");
+
+        let trigger = context.triggers().unwrap();
+        assert_eq!(trigger.to_str().unwrap(), r#"{"invoice.paid":{"id":9}}"#);
+    }
+
+    const UNREAD: Topic<Value> = Topic::new("unread");
+
+    /// Everything the connection delivers, collected in the background.
+    fn deliveries(bus: &EventBus) -> Arc<Mutex<Vec<AppEvent>>> {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let mut events = bus.coalesced_events();
+
+        let collected = delivered.clone();
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                collected.lock().unwrap().push(event);
+            }
+        });
+
+        delivered
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_is_summed() {
+        let bus = bus();
+        bus.coalesce(&UNREAD, Coalescing::sum("count", Duration::from_millis(25)).keyed_by("conversation"));
+        let delivered = deliveries(&bus);
+
+        for _ in 0..100 {
+            bus.publish(&UNREAD, &json!({ "conversation": 1, "count": 1 })).broadcast();
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        bus.publish(&UNREAD, &json!({ "conversation": 2, "count": 5 })).broadcast();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let delivered = delivered.lock().unwrap();
+        let first: Vec<&AppEvent> = delivered.iter().filter(|event| event.payload["conversation"] == 1).collect();
+
+        assert!(first.len() <= 5, "{} deliveries", first.len());
+        assert_eq!(first.iter().map(|event| event.payload["count"].as_i64().unwrap()).sum::<i64>(), 100);
+        assert!(delivered.iter().any(|event| event.payload == json!({ "conversation": 2, "count": 5 })));
+
+        let counts = bus.coalesce_counts();
+        assert_eq!(counts.received, 101);
+        assert_eq!(counts.delivered, delivered.len() as u64);
+        assert_eq!(counts.merged, counts.received - counts.delivered);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keep_last() {
+        let bus = bus();
+        bus.coalesce(&UNREAD, Coalescing::keep_last(Duration::from_millis(50)));
+        let delivered = deliveries(&bus);
+
+        for count in 1..=5 {
+            bus.publish(&UNREAD, &json!({ "count": count })).broadcast();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let delivered = delivered.lock().unwrap();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0].payload["count"], 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_realtime_bypass() {
+        let bus = bus();
+        bus.coalesce(&UNREAD, Coalescing::realtime());
+        let delivered = deliveries(&bus);
+
+        for count in 1..=5 {
+            bus.publish(&UNREAD, &json!({ "count": count })).broadcast();
+        }
+        wait_until(|| delivered.lock().unwrap().len() == 5).await;
+
+        let counts: Vec<Value> = delivered.lock().unwrap().iter().map(|event| event.payload["count"].clone()).collect();
+        assert_eq!(counts, vec![json!(1), json!(2), json!(3), json!(4), json!(5)]);
+        assert_eq!(bus.coalesce_counts().merged, 0);
+    }
+}
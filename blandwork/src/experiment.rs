@@ -0,0 +1,395 @@
+use std::{
+    collections::BTreeMap,
+    error::Error, fmt::Display,
+    sync::{Arc, Mutex},
+    time::SystemTime
+};
+
+use async_trait::async_trait;
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+
+use crate::{cookies::TypedCookie, events::{EventBus, Topic}, Feature};
+
+/// Published by `Context::conversion`, server side only: assignments never reach the browser.
+pub const CONVERSION: Topic<Conversion> = Topic::new("experiment.conversion");
+
+#[derive(Debug)]
+pub enum ExperimentError {
+    Store(String),
+}
+
+impl Display for ExperimentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExperimentError::Store(e) => write!(f, "conversion store error: {e}"),
+        }
+    }
+}
+
+impl Error for ExperimentError {}
+
+/// Sticky experiment -> variant assignments of the browser, written by whatever assigns
+/// variants and read back for attribution (access log, conversions).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentAssignments {
+    pub variants: BTreeMap<String, String>,
+}
+
+impl TypedCookie for ExperimentAssignments {
+    const NAME: &'static str = "blandwork_experiments";
+}
+
+impl ExperimentAssignments {
+    pub fn assign(&mut self, experiment: &str, variant: &str) {
+        self.variants.insert(experiment.to_owned(), variant.to_owned());
+    }
+
+    pub fn variant(&self, experiment: &str) -> Option<&str> {
+        self.variants.get(experiment).map(|variant| variant.as_str())
+    }
+
+    /// `checkout=b,pricing=a`, used as a log label.
+    pub fn label(&self) -> String {
+        self.variants.iter()
+            .map(|(experiment, variant)| format!("{experiment}={variant}"))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+/// A conversion event tagged with every active assignment of the session.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Conversion {
+    pub name: String,
+    pub value: f64,
+    pub assignments: BTreeMap<String, String>,
+    pub recorded_at: SystemTime,
+}
+
+/// Conversions of one variant, grouped by conversion name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantConversions {
+    pub experiment: String,
+    pub variant: String,
+    pub conversion: String,
+    pub count: u64,
+    pub value: f64,
+}
+
+#[async_trait]
+pub trait ConversionStore: Send + Sync {
+    async fn record(&self, conversion: &Conversion) -> Result<(), ExperimentError>;
+
+    /// Ordered by experiment, variant and conversion.
+    async fn summary(&self) -> Result<Vec<VariantConversions>, ExperimentError>;
+}
+
+#[derive(Default)]
+pub struct MemoryConversionStore {
+    conversions: Mutex<Vec<Conversion>>,
+}
+
+#[async_trait]
+impl ConversionStore for MemoryConversionStore {
+    async fn record(&self, conversion: &Conversion) -> Result<(), ExperimentError> {
+        self.conversions.lock().unwrap().push(conversion.clone());
+        Ok(())
+    }
+
+    async fn summary(&self) -> Result<Vec<VariantConversions>, ExperimentError> {
+        let mut groups: BTreeMap<(String, String, String), (u64, f64)> = BTreeMap::new();
+
+        for conversion in self.conversions.lock().unwrap().iter() {
+            for (experiment, variant) in conversion.assignments.iter() {
+                let group = groups.entry((experiment.clone(), variant.clone(), conversion.name.clone())).or_default();
+                group.0 += 1;
+                group.1 += conversion.value;
+            }
+        }
+
+        Ok(groups.into_iter()
+            .map(|((experiment, variant, conversion), (count, value))| VariantConversions { experiment, variant, conversion, count, value })
+            .collect())
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresConversionStore;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use async_trait::async_trait;
+    use uuid::Uuid;
+
+    use crate::db::ConnectionPool;
+
+    use super::{Conversion, ConversionStore, ExperimentError, VariantConversions};
+
+    impl From<tokio_postgres::Error> for ExperimentError {
+        fn from(value: tokio_postgres::Error) -> Self {
+            ExperimentError::Store(value.to_string())
+        }
+    }
+
+    /// One row per conversion and assignment. Create with `PostgresConversionStore::TABLE` before use.
+    pub struct PostgresConversionStore {
+        pool: ConnectionPool
+    }
+
+    impl PostgresConversionStore {
+        pub const TABLE: &'static str = "CREATE TABLE IF NOT EXISTS blandwork_conversions (
+            conversion_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            value DOUBLE PRECISION NOT NULL,
+            experiment TEXT NOT NULL,
+            variant TEXT NOT NULL,
+            recorded_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (conversion_id, experiment)
+        )";
+
+        pub fn new(pool: ConnectionPool) -> Self {
+            Self { pool }
+        }
+
+        pub async fn migrate(&self) -> Result<(), ExperimentError> {
+            let connection = self.pool.get().await.map_err(|e| ExperimentError::Store(e.to_string()))?;
+            connection.batch_execute(Self::TABLE).await?;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl ConversionStore for PostgresConversionStore {
+        async fn record(&self, conversion: &Conversion) -> Result<(), ExperimentError> {
+            let mut connection = self.pool.get().await.map_err(|e| ExperimentError::Store(e.to_string()))?;
+            let transaction = connection.transaction().await?;
+            let id: String = Uuid::new_v4().to_string();
+
+            for (experiment, variant) in conversion.assignments.iter() {
+                transaction.execute(
+                    "INSERT INTO blandwork_conversions (conversion_id, name, value, experiment, variant, recorded_at) VALUES ($1, $2, $3, $4, $5, $6)",
+                    &[&id, &conversion.name, &conversion.value, experiment, variant, &conversion.recorded_at]
+                ).await?;
+            }
+
+            transaction.commit().await?;
+            Ok(())
+        }
+
+        async fn summary(&self) -> Result<Vec<VariantConversions>, ExperimentError> {
+            let connection = self.pool.get().await.map_err(|e| ExperimentError::Store(e.to_string()))?;
+
+            let rows = connection.query(
+                "SELECT experiment, variant, name, COUNT(*) AS count, SUM(value) AS value
+                FROM blandwork_conversions
+                GROUP BY experiment, variant, name
+                ORDER BY experiment, variant, name",
+                &[]
+            ).await?;
+
+            Ok(rows.iter().map(|row| VariantConversions {
+                experiment: row.get("experiment"),
+                variant: row.get("variant"),
+                conversion: row.get("name"),
+                count: row.get::<_, i64>("count") as u64,
+                value: row.get("value"),
+            }).collect())
+        }
+    }
+}
+
+/// Writes the published conversions to a store and summarizes them per variant.
+/// Register it as a feature (it subscribes to `CONVERSION`) and keep a clone for the admin page.
+///
+/// ```ignore
+/// let conversions = Conversions::memory();
+/// let app = App::new(config, template).register_feature(conversions.clone());
+///
+/// // admin handler
+/// conversions.panel().await
+/// ```
+#[derive(Clone)]
+pub struct Conversions {
+    store: Arc<dyn ConversionStore>,
+}
+
+impl Conversions {
+    pub fn new(store: impl ConversionStore + 'static) -> Self {
+        Self { store: Arc::new(store) }
+    }
+
+    pub fn memory() -> Self {
+        Self::new(MemoryConversionStore::default())
+    }
+
+    pub async fn summary(&self) -> Result<Vec<VariantConversions>, ExperimentError> {
+        self.store.summary().await
+    }
+
+    /// Counts and summed values per experiment, variant and conversion, no statistics.
+    pub async fn panel(&self) -> Result<Markup, ExperimentError> {
+        Ok(conversions_panel(&self.summary().await?))
+    }
+}
+
+impl Feature for Conversions {
+    fn subscribe(&self, events: &EventBus) {
+        let store = self.store.clone();
+
+        events.subscribe(&CONVERSION, "conversions", move |conversion: Conversion| {
+            let store = store.clone();
+
+            async move {
+                store.record(&conversion).await?;
+                Ok(())
+            }
+        });
+    }
+}
+
+pub fn conversions_panel(summary: &[VariantConversions]) -> Markup {
+    html!{
+        section .experiments aria-label="Experiment conversions" {
+            @if summary.is_empty() {
+                p { "No conversions recorded yet." }
+            } @else {
+                table {
+                    thead {
+                        tr { th { "Experiment" } th { "Variant" } th { "Conversion" } th { "Count" } th { "Value" } }
+                    }
+                    tbody {
+                        @for row in summary {
+                            tr {
+                                td { (row.experiment) }
+                                td { (row.variant) }
+                                td { (row.conversion) }
+                                td { (row.count) }
+                                td { (format!("{:.2}", row.value)) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::{Arc, Mutex}, time::{Duration, UNIX_EPOCH}};
+
+    use axum::{body::Body, extract::Request, http::header::COOKIE, routing::post, Extension, Router};
+    use axum_htmx::{HX_BOOSTED, HX_REQUEST, HX_TRIGGER};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use tower::ServiceExt;
+
+    use crate::{clock::{SharedClock, TestClock}, context::ContextLayer, events::{EventBus, HandlerError}, ContextAccessor, Feature};
+
+    use super::{conversions_panel, Conversion, CONVERSION, ConversionStore, Conversions, ExperimentAssignments, MemoryConversionStore, VariantConversions};
+
+    fn conversion(name: &str, value: f64, assignments: &[(&str, &str)]) -> Conversion {
+        Conversion {
+            name: name.to_owned(),
+            value,
+            assignments: assignments.iter().map(|(e, v)| (e.to_string(), v.to_string())).collect::<BTreeMap<_, _>>(),
+            recorded_at: UNIX_EPOCH + Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_assignment_label() {
+        let mut assignments = ExperimentAssignments::default();
+        assignments.assign("pricing", "a");
+        assignments.assign("checkout", "b");
+
+        assert_eq!(assignments.label(), "checkout=b,pricing=a");
+        assert_eq!(assignments.variant("checkout"), Some("b"));
+    }
+
+    #[tokio::test]
+    async fn test_summary_groups_per_variant() {
+        let store = MemoryConversionStore::default();
+
+        store.record(&conversion("signup_completed", 1.0, &[("checkout", "a"), ("pricing", "b")])).await.unwrap();
+        store.record(&conversion("signup_completed", 1.0, &[("checkout", "b")])).await.unwrap();
+        store.record(&conversion("signup_completed", 1.0, &[("checkout", "b")])).await.unwrap();
+        store.record(&conversion("purchase", 49.5, &[("checkout", "b")])).await.unwrap();
+
+        let summary = store.summary().await.unwrap();
+        let row = |experiment: &str, variant: &str, conversion: &str, count: u64, value: f64| VariantConversions {
+            experiment: experiment.to_owned(), variant: variant.to_owned(), conversion: conversion.to_owned(), count, value
+        };
+
+        assert_eq!(summary, vec![
+            row("checkout", "a", "signup_completed", 1, 1.0),
+            row("checkout", "b", "purchase", 1, 49.5),
+            row("checkout", "b", "signup_completed", 2, 2.0),
+            row("pricing", "b", "signup_completed", 1, 1.0),
+        ]);
+
+        let panel = conversions_panel(&summary).into_string();
+        assert!(panel.contains("<td>purchase</td><td>1</td><td>49.50</td>"));
+    }
+
+    #[tokio::test]
+    async fn test_conversion_carries_assignments() {
+        let bus = EventBus::new();
+        let conversions = Conversions::memory();
+        conversions.subscribe(&bus);
+
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let times = recorded.clone();
+        bus.subscribe(&CONVERSION, "times", move |conversion: Conversion| {
+            times.lock().unwrap().push(conversion.recorded_at);
+            async { Ok::<(), HandlerError>(()) }
+        });
+
+        let clock: SharedClock = Arc::new(TestClock::at(UNIX_EPOCH + Duration::from_secs(1000)));
+        let router = Router::new()
+            .route("/signup", post(|Extension(accessor): Extension<ContextAccessor>| async move {
+                accessor.context().await.conversion("signup_completed", 1.0);
+            }))
+            .layer(ContextLayer::new())
+            .layer(Extension(bus))
+            .layer(Extension(clock));
+
+        let mut assignments = ExperimentAssignments::default();
+        assignments.assign("checkout", "b");
+        assignments.assign("pricing", "a");
+
+        let cookie = format!("blandwork_experiments=v1.{}", URL_SAFE_NO_PAD.encode(serde_json::to_vec(&assignments).unwrap()));
+
+        let request = Request::post("/signup")
+            .header(COOKIE, cookie)
+            .header(HX_REQUEST, "true")
+            .header(HX_BOOSTED, "true")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+
+        // assignments stay on the server
+        assert!(response.headers().get(HX_TRIGGER).is_none());
+
+        // without assignments nothing is attributed
+        router.oneshot(Request::post("/signup").body(Body::empty()).unwrap()).await.unwrap();
+
+        let mut summary = Vec::new();
+        for _ in 0..1000 {
+            summary = conversions.summary().await.unwrap();
+            if !summary.is_empty() && !recorded.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let variants: Vec<(&str, &str, u64)> = summary.iter()
+            .map(|row| (row.experiment.as_str(), row.variant.as_str(), row.count))
+            .collect();
+
+        assert_eq!(variants, vec![("checkout", "b", 1), ("pricing", "a", 1)]);
+
+        // at the app's time
+        assert_eq!(*recorded.lock().unwrap(), vec![UNIX_EPOCH + Duration::from_secs(1000)]);
+    }
+}
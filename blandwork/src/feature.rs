@@ -1,8 +1,11 @@
-use axum::Router;
+use std::sync::Arc;
+
+use axum::{http::HeaderValue, middleware::map_response, response::Response, Router};
 use maud::{html, Markup};
 use serde::Serialize;
+use tower_http::cors::CorsLayer;
 
-use crate::{ConnectionPool, Context};
+use crate::{feature_config::ConfigSchema, group::RouteGroup, head_assets::HeadAsset, jobs::JobHandler, lazy::LazyRouter, locale::LocalizedRoute, nav_tree::NavTree, onboarding::OnboardingItem, schedule::ScheduledJob, takeout::UserDataProvider, Context, EventBus};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Link {
@@ -11,17 +14,47 @@ pub struct Link {
     pub label: String,
     pub route: String,
     pub icon: Option<String>,
-    pub css: Option<String>
+    pub css: Option<String>,
+
+    /// Lead back to the page last visited below the route (filters, page) instead of
+    /// the route itself, see `LastVisited`.
+    pub remember: bool,
 }
 impl Link {
-    pub fn render(&self, _: &Context) -> Markup {
-        let active_class: String = match self.active {
+    /// Explicitly marked active, or the page the user is on (see `Context::current_path`)
+    /// is the link's route or below it.
+    pub fn is_active(&self, context: &Context) -> bool {
+        if self.active {
+            return true;
+        }
+
+        let path: String = context.current_path();
+        let route: String = context.url_for(&self.route);
+        let route: &str = route.trim_end_matches('/');
+
+        match route.is_empty() {
+            true => path == "/",
+            false => path == route || path.starts_with(&format!("{route}/")),
+        }
+    }
+
+    /// Nothing while the link's feature is disabled, see `FeatureToggles`.
+    pub fn render(&self, context: &Context) -> Markup {
+        if !context.link_enabled(&self.route) {
+            return html!{};
+        }
+
+        let active_class: String = match self.is_active(context) {
             true => "bg-gray-400".to_owned(),
             false => "bg-gray-600".to_owned()
         };
 
+        let canonical: String = context.url_for(&self.route);
+        let remembered: Option<String> = context.remembered_url(self);
+
         html!{
-            a href=(self.route)
+            a href=(remembered.as_deref().unwrap_or(&canonical))
+                data-canonical=[remembered.is_some().then_some(&canonical)]
                 hx-target="#content"
                 hx-swap="innerHTML"
                 class={"w-14 h-14 my-1 flex justify-center items-center no-underline duration-200 rounded-xl hover:bg-gray-500 " (active_class) ""} {
@@ -35,6 +68,17 @@ impl Link {
 /// They are meant to be for definition and configuration purposes
 /// and are not accessible during requests.
 pub trait Feature {
+
+    /// Identifies the feature in logs (`feature{name=..}` spans), defaults to the type name.
+    fn name(&self) -> String {
+        let path: &str = std::any::type_name::<Self>();
+
+        // drop the module path, keeping generic parameters intact
+        let base: &str = path.split('<').next().unwrap_or(path);
+        let start: usize = base.rfind("::").map(|i| i + 2).unwrap_or(0);
+
+        path[start..].to_owned()
+    }
     
     /// Navigation hook to the entrypoint into the feature
     fn link(&self) -> Option<Link> {
@@ -45,13 +89,20 @@ pub trait Feature {
         None
     }
 
+    /// Sub-navigation shown by the shell while the feature's `link` is active,
+    /// see `Context::submenu`.
+    fn submenu(&self) -> Option<Vec<Link>> {
+        None
+    }
+
     /// API endpoints exposed from the feature
     fn api(&self) -> Option<Router> {
         return None;
     }
 
     /// Supplemental endpoints are routes that should only be accessed from the web endpoints.
-    /// These routes are wrapped in the Context middleware and are not HTMX aware.
+    /// These routes are wrapped in the Context middleware and are not HTMX aware,
+    /// requests not made by HTMX (direct navigation) are answered 404, any method is allowed.
     /// use cases:
     /// - Search Results
     fn supplemental(&self) -> Option<Router> {
@@ -64,10 +115,119 @@ pub trait Feature {
     fn web(&self) -> Option<Router> {
         return None;
     }
+
+    /// Named route groups with their own policy, shell, prefix and layers. By default the
+    /// `api`, `supplemental` and `web` routers as groups of the same names, a feature
+    /// overriding it serves only the groups it returns (besides `lazy` and `localized`).
+    fn groups(&self) -> Vec<RouteGroup> {
+        let mut groups: Vec<RouteGroup> = Vec::new();
+
+        if let Some(api) = self.api() {
+            groups.push(RouteGroup::api("api", api));
+        }
+
+        if let Some(supplemental) = self.supplemental() {
+            groups.push(RouteGroup::supplemental("supplemental", supplemental));
+        }
+
+        if let Some(web) = self.web() {
+            groups.push(RouteGroup::web("web", web));
+        }
+
+        groups
+    }
+
+    /// Web endpoints under a prefix whose router is built by the first request instead of
+    /// during `build`, mounted alongside `web`.
+    fn lazy(&self) -> Option<LazyRouter> {
+        None
+    }
+
+    /// Web endpoints served under a translated path per locale, mounted alongside `web`.
+    fn localized(&self) -> Vec<LocalizedRoute> {
+        Vec::new()
+    }
+
+    /// The `[features.{section}]` table of the feature and the type it is read into,
+    /// validated by `build`, which refuses to start on any problem. Handlers get it as an
+    /// `Extension<FeatureConfig<T>>`, templates the exposed keys, see `ConfigSchema`.
+    fn config_schema(&self) -> Option<ConfigSchema> {
+        None
+    }
+
+    /// Scripts and styles of the feature, deduplicated by name across features and ordered
+    /// by their dependencies. Global ones are on every full page, the others where a handler
+    /// asks for them with `Context::require_asset`.
+    fn head_assets(&self) -> Vec<HeadAsset> {
+        Vec::new()
+    }
+
+    /// CORS policy of every route of the feature, e.g. `CorsLayer::permissive()` for a public
+    /// JSON API. It is the outermost layer of the feature's groups, so it answers their
+    /// preflights before the toggle and policies run, and the app-wide policy
+    /// (`server.core_layers.cors`) leaves its responses alone, see `AppCorsLayer`.
+    fn cors(&self) -> Option<CorsLayer> {
+        None
+    }
+
+    /// Keep every route of the feature out of search engines (admin areas, supplemental routes),
+    /// responses carry `X-Robots-Tag: noindex`.
+    fn noindex(&self) -> bool {
+        false
+    }
+
+    /// Subscribe to application events, called once by `App::build`.
+    fn subscribe(&self, _events: &EventBus) {}
+
+    /// "Getting started" steps shown by the `ChecklistFeature`, collected by `App::build`.
+    fn onboarding(&self) -> Vec<OnboardingItem> {
+        Vec::new()
+    }
+
+    /// Cron scheduled jobs (nightly cleanups, reports), started by `App::run`.
+    fn schedule(&self) -> Vec<ScheduledJob> {
+        Vec::new()
+    }
+
+    /// Handlers of the background jobs the feature queues with `Jobs::enqueue`, collected by `App::build`.
+    fn jobs(&self) -> Vec<JobHandler> {
+        Vec::new()
+    }
+
+    /// Templates shipped inside the feature's crate as (name, source) pairs,
+    /// a file of the same name in the app's template directory takes precedence.
+    /// See `TemplateSources`.
+    fn templates_source(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
+    /// Hierarchical navigation loaded a level at a time, placed by the template
+    /// with `Context::nav_tree(name)`.
+    fn nav_tree(&self) -> Option<Arc<dyn NavTree>> {
+        None
+    }
+
+    /// Everything the feature holds about a user, exported by the `TakeoutFeature`.
+    fn user_data(&self) -> Option<UserDataProvider> {
+        None
+    }
 }
 
 pub type FeatureError = Box<dyn std::error::Error>;
 
+async fn noindex_header(mut response: Response) -> Response {
+    response.headers_mut().insert("x-robots-tag", HeaderValue::from_static("noindex"));
+    response
+}
+
+/// Applies `Feature::noindex` to one of the feature routers.
+pub(crate) fn robots(router: Router, noindex: bool) -> Router {
+    match noindex {
+        true => router.layer(map_response(noindex_header)),
+        false => router,
+    }
+}
+
 pub trait Component {
     fn render(&self, context: &Context) -> Markup {
         html!{
@@ -76,4 +236,53 @@ pub trait Component {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request};
+    use axum_htmx::{HX_BOOSTED, HX_CURRENT_URL, HX_REQUEST};
+
+    use crate::ContextAccessor;
+
+    use super::Link;
+
+    fn link(route: &str) -> Link {
+        Link { active: false, title: route.to_owned(), label: route.to_owned(), route: route.to_owned(), icon: None, css: None, remember: false }
+    }
+
+    #[tokio::test]
+    async fn test_active_link_follows_current_url_when_boosted() {
+        // boosted navigation from the invoices page to a search fragment
+        let request = Request::builder()
+            .uri("/search/results")
+            .header(HX_REQUEST, "true")
+            .header(HX_BOOSTED, "true")
+            .header(HX_CURRENT_URL, "https://example.com/invoices/42?tab=items")
+            .body(Body::empty())
+            .unwrap();
+
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert!(link("/invoices").is_active(&context));
+        assert!(!link("/search").is_active(&context));
+        assert!(!link("/invoice").is_active(&context));
+        assert!(!link("/").is_active(&context));
+    }
+
+    #[tokio::test]
+    async fn test_active_link_uses_request_path_otherwise() {
+        let request = Request::builder()
+            .uri("/search/results")
+            .header(HX_CURRENT_URL, "https://example.com/invoices/42")
+            .body(Body::empty())
+            .unwrap();
+
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert!(link("/search").is_active(&context));
+        assert!(!link("/invoices").is_active(&context));
+    }
+}
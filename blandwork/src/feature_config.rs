@@ -0,0 +1,373 @@
+use std::{collections::BTreeMap, error::Error, fmt::Display, ops::Deref, sync::Arc};
+
+use axum::{Extension, Router};
+use serde::de::DeserializeOwned;
+
+use crate::{Config, Feature};
+
+/// A field of a feature's config failing validation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invalid {
+    pub field: String,
+    pub message: String,
+}
+
+impl Invalid {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self { field: field.to_owned(), message: message.into() }
+    }
+}
+
+/// Checks of a feature's config beyond its types, run before the app is built.
+pub trait Validate {
+    fn validate(&self) -> Vec<Invalid> {
+        Vec::new()
+    }
+}
+
+/// One problem of a `[features.{section}]` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    pub section: String,
+
+    // None when the table could not be read at all (missing field, wrong type)
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.field.as_deref() {
+            Some(field) => write!(f, "features.{}.{field}: {}", self.section, self.message),
+            None => write!(f, "features.{}: {}", self.section, self.message),
+        }
+    }
+}
+
+/// Every problem of every feature's config, `build` refuses to start with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureConfigError(pub Vec<ConfigProblem>);
+
+impl Display for FeatureConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid feature config:")?;
+
+        for problem in self.0.iter() {
+            write!(f, "\n  {problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for FeatureConfigError {}
+
+/// The typed config of a feature, an extension of every route of the feature.
+///
+/// ```ignore
+/// async fn checkout(Extension(config): Extension<FeatureConfig<BillingConfig>>) -> Markup {
+///     let client = StripeClient::new(&config.stripe_key);
+///     ...
+/// }
+/// ```
+#[derive(Debug)]
+pub struct FeatureConfig<T>(pub Arc<T>);
+
+impl<T> Clone for FeatureConfig<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Deref for FeatureConfig<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// the parsed config as an extension of the feature's routes
+type Apply = Arc<dyn Fn(Router) -> Router + Send + Sync>;
+
+type Parse = Arc<dyn Fn(&toml::Table) -> Result<Apply, Vec<ConfigProblem>> + Send + Sync>;
+
+/// The `[features.{section}]` table a feature claims and the type it is read into,
+/// see `Feature::config_schema`.
+///
+/// ```ignore
+/// #[derive(Deserialize)]
+/// struct BillingConfig { stripe_key: String, currency: String }
+///
+/// impl Validate for BillingConfig {
+///     fn validate(&self) -> Vec<Invalid> {
+///         match self.stripe_key.starts_with("sk_") {
+///             true => Vec::new(),
+///             false => vec![Invalid::new("stripe_key", "is not a secret key")],
+///         }
+///     }
+/// }
+///
+/// fn config_schema(&self) -> Option<ConfigSchema> {
+///     Some(ConfigSchema::new::<BillingConfig>("billing").expose(&["currency"]))
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ConfigSchema {
+    section: String,
+    parse: Parse,
+    exposed: Vec<String>,
+}
+
+impl ConfigSchema {
+    pub fn new<T: DeserializeOwned + Validate + Send + Sync + 'static>(section: &str) -> Self {
+        let name: String = section.to_owned();
+
+        let parse: Parse = Arc::new(move |table: &toml::Table| {
+            let config: T = T::deserialize(toml::Value::Table(table.clone())).map_err(|e| vec![ConfigProblem {
+                section: name.clone(),
+                field: None,
+                message: e.message().trim().to_owned(),
+            }])?;
+
+            let problems: Vec<ConfigProblem> = config.validate().into_iter()
+                .map(|invalid| ConfigProblem { section: name.clone(), field: Some(invalid.field), message: invalid.message })
+                .collect();
+
+            if !problems.is_empty() {
+                return Err(problems);
+            }
+
+            let config: FeatureConfig<T> = FeatureConfig(Arc::new(config));
+            let apply: Apply = Arc::new(move |router: Router| router.layer(Extension(config.clone())));
+
+            Ok(apply)
+        });
+
+        Self { section: section.to_owned(), parse, exposed: Vec::new() }
+    }
+
+    /// Keys of the table templates may read through `Context::feature_setting`,
+    /// nothing is exposed by default (keys, secrets).
+    pub fn expose(mut self, keys: &[&str]) -> Self {
+        self.exposed.extend(keys.iter().map(|key| key.to_string()));
+        self
+    }
+}
+
+/// The exposed keys of every feature's config, by section.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureSettings(Arc<BTreeMap<String, toml::Table>>);
+
+impl FeatureSettings {
+    pub fn get(&self, section: &str, key: &str) -> Option<&toml::Value> {
+        self.0.get(section).and_then(|table| table.get(key))
+    }
+}
+
+/// What `build` makes of the feature sections.
+pub(crate) struct FeatureConfigs {
+    // by feature, in registration order
+    pub apply: Vec<Option<Apply>>,
+    pub settings: FeatureSettings,
+
+    // sections no feature claims, likely typos, warned about by `build`
+    pub unclaimed: Vec<String>,
+}
+
+impl FeatureConfigs {
+    /// The feature's routes with its config as an extension.
+    pub fn layer(apply: Option<&Apply>, router: Router) -> Router {
+        match apply {
+            Some(apply) => apply(router),
+            None => router,
+        }
+    }
+}
+
+/// Read the section of every feature declaring a schema, a missing section is read as an
+/// empty table (the type's defaults). Every problem is collected before failing.
+pub(crate) fn parse_feature_configs(features: &[Box<dyn Feature>], config: &Config) -> Result<FeatureConfigs, FeatureConfigError> {
+    let mut apply: Vec<Option<Apply>> = Vec::new();
+    let mut settings: BTreeMap<String, toml::Table> = BTreeMap::new();
+    let mut problems: Vec<ConfigProblem> = Vec::new();
+    let empty: toml::Table = toml::Table::new();
+
+    for feature in features.iter() {
+        let Some(schema) = feature.config_schema() else {
+            apply.push(None);
+            continue;
+        };
+
+        let table: &toml::Table = config.feature_sections.get(&schema.section).unwrap_or(&empty);
+
+        match (schema.parse)(table) {
+            Ok(parsed) => apply.push(Some(parsed)),
+            Err(found) => {
+                problems.extend(found);
+                apply.push(None);
+            },
+        }
+
+        let exposed: toml::Table = table.iter()
+            .filter(|(key, _)| schema.exposed.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        settings.insert(schema.section.clone(), exposed);
+    }
+
+    if !problems.is_empty() {
+        return Err(FeatureConfigError(problems));
+    }
+
+    let unclaimed: Vec<String> = config.feature_sections.keys()
+        .filter(|section| !settings.contains_key(*section))
+        .cloned()
+        .collect();
+
+    Ok(FeatureConfigs { apply, settings: FeatureSettings(Arc::new(settings)), unclaimed })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use maud::{html, Markup};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use crate::{test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{parse_feature_configs, ConfigProblem, ConfigSchema, FeatureConfig, Invalid, Validate};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct BillingConfig {
+        stripe_key: String,
+        currency: String,
+    }
+
+    impl Validate for BillingConfig {
+        fn validate(&self) -> Vec<Invalid> {
+            match self.stripe_key.starts_with("sk_") {
+                true => Vec::new(),
+                false => vec![Invalid::new("stripe_key", "is not a secret key")],
+            }
+        }
+    }
+
+    struct Billing;
+
+    impl Feature for Billing {
+        fn config_schema(&self) -> Option<ConfigSchema> {
+            Some(ConfigSchema::new::<BillingConfig>("billing").expose(&["currency"]))
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/billing", get(|
+                Extension(config): Extension<FeatureConfig<BillingConfig>>,
+                Extension(accessor): Extension<ContextAccessor>
+            | async move {
+                let context = accessor.context().await;
+
+                html! {
+                    (config.stripe_key) " " (config.currency)
+                    " " (context.feature_setting("billing", "currency").is_some())
+                    " " (context.feature_setting("billing", "stripe_key").is_some())
+                }
+            })))
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct MailerConfig {
+        #[allow(dead_code)]
+        sender: String,
+    }
+
+    impl Validate for MailerConfig {}
+
+    struct Mailer;
+
+    impl Feature for Mailer {
+        fn config_schema(&self) -> Option<ConfigSchema> {
+            Some(ConfigSchema::new::<MailerConfig>("mailer"))
+        }
+    }
+
+    fn config(features: &str) -> Config {
+        toml::from_str(&format!("
+            [database]
+            host = 'localhost'
+            port = 5432
+            database = 'app'
+            username = 'app'
+            password = 'secret'
+
+            [server]
+            host = '0.0.0.0'
+            port = 3000
+
+            {features}
+        ")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_typed_config() {
+        let config = config("
+            [features.billing]
+            stripe_key = 'sk_test'
+            currency = 'EUR'
+        ");
+
+        let mut app = App::new(config, TestTemplate).register_feature(Billing);
+        let response = router(&app.build()).oneshot(Request::get("/billing").body(Body::empty()).unwrap()).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        // only the whitelisted currency reaches templates
+        assert_eq!(body, "<html><body>sk_test EUR true false</body></html>");
+    }
+
+    #[test]
+    fn test_aggregated_problems() {
+        let config = config("
+            [features.billing]
+            stripe_key = 'pk_live'
+            currency = 'EUR'
+        ");
+
+        let features: Vec<Box<dyn Feature>> = vec![Box::new(Billing), Box::new(Mailer)];
+        let error = parse_feature_configs(&features, &config).err().unwrap();
+
+        assert_eq!(error.0, vec![
+            ConfigProblem { section: "billing".to_owned(), field: Some("stripe_key".to_owned()), message: "is not a secret key".to_owned() },
+            ConfigProblem { section: "mailer".to_owned(), field: None, message: "missing field `sender`".to_owned() },
+        ]);
+        assert_eq!(error.to_string(), "invalid feature config:\n  features.billing.stripe_key: is not a secret key\n  features.mailer: missing field `sender`");
+    }
+
+    #[test]
+    fn test_unclaimed_section() {
+        let config = config("
+            [features.billing]
+            stripe_key = 'sk_test'
+            currency = 'EUR'
+
+            [features.biling]
+            stripe_key = 'sk_test'
+        ");
+
+        let features: Vec<Box<dyn Feature>> = vec![Box::new(Billing)];
+        let configs = parse_feature_configs(&features, &config).ok().unwrap();
+
+        assert_eq!(configs.unclaimed, vec!["biling".to_owned()]);
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt::{self, Display};
+
+use serde::{ser, Serialize};
+
+/// Whether a `NaN` or infinite float appears anywhere in `value`. `serde_json` quietly
+/// writes those as `null`, `server.triggers.non_finite = "reject"` drops the trigger instead.
+pub(crate) fn has_non_finite<T: Serialize + ?Sized>(value: &T) -> bool {
+    matches!(value.serialize(Finder), Err(Stop::NonFinite))
+}
+
+#[derive(Debug)]
+enum Stop {
+    NonFinite,
+    // anything the value's own Serialize fails with, left for serde_json to report
+    Other,
+}
+
+impl Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::NonFinite => write!(f, "non-finite float"),
+            Stop::Other => write!(f, "unable to serialize"),
+        }
+    }
+}
+
+impl std::error::Error for Stop {}
+
+impl ser::Error for Stop {
+    fn custom<M: Display>(_: M) -> Self {
+        Stop::Other
+    }
+}
+
+/// Walks a value without writing it, stopping at the first non-finite float.
+struct Finder;
+
+impl ser::Serializer for Finder {
+    type Ok = ();
+    type Error = Stop;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_f32(self, v: f32) -> Result<(), Stop> {
+        if v.is_finite() { Ok(()) } else { Err(Stop::NonFinite) }
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Stop> {
+        if v.is_finite() { Ok(()) } else { Err(Stop::NonFinite) }
+    }
+
+    fn serialize_bool(self, _: bool) -> Result<(), Stop> { Ok(()) }
+    fn serialize_i8(self, _: i8) -> Result<(), Stop> { Ok(()) }
+    fn serialize_i16(self, _: i16) -> Result<(), Stop> { Ok(()) }
+    fn serialize_i32(self, _: i32) -> Result<(), Stop> { Ok(()) }
+    fn serialize_i64(self, _: i64) -> Result<(), Stop> { Ok(()) }
+    fn serialize_u8(self, _: u8) -> Result<(), Stop> { Ok(()) }
+    fn serialize_u16(self, _: u16) -> Result<(), Stop> { Ok(()) }
+    fn serialize_u32(self, _: u32) -> Result<(), Stop> { Ok(()) }
+    fn serialize_u64(self, _: u64) -> Result<(), Stop> { Ok(()) }
+    fn serialize_char(self, _: char) -> Result<(), Stop> { Ok(()) }
+    fn serialize_str(self, _: &str) -> Result<(), Stop> { Ok(()) }
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), Stop> { Ok(()) }
+    fn serialize_none(self) -> Result<(), Stop> { Ok(()) }
+    fn serialize_unit(self) -> Result<(), Stop> { Ok(()) }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), Stop> { Ok(()) }
+
+    fn serialize_unit_variant(self, _: &'static str, _: u32, _: &'static str) -> Result<(), Stop> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Stop> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _: &'static str, value: &T) -> Result<(), Stop> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _: &'static str, _: u32, _: &'static str, value: &T) -> Result<(), Stop> {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self, Stop> { Ok(self) }
+    fn serialize_tuple(self, _: usize) -> Result<Self, Stop> { Ok(self) }
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self, Stop> { Ok(self) }
+
+    fn serialize_tuple_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self, Stop> {
+        Ok(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self, Stop> { Ok(self) }
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, Stop> { Ok(self) }
+
+    fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self, Stop> {
+        Ok(self)
+    }
+}
+
+impl ser::SerializeSeq for Finder {
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+        value.serialize(Finder)
+    }
+
+    fn end(self) -> Result<(), Stop> { Ok(()) }
+}
+
+impl ser::SerializeTuple for Finder {
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+        value.serialize(Finder)
+    }
+
+    fn end(self) -> Result<(), Stop> { Ok(()) }
+}
+
+impl ser::SerializeTupleStruct for Finder {
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+        value.serialize(Finder)
+    }
+
+    fn end(self) -> Result<(), Stop> { Ok(()) }
+}
+
+impl ser::SerializeTupleVariant for Finder {
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+        value.serialize(Finder)
+    }
+
+    fn end(self) -> Result<(), Stop> { Ok(()) }
+}
+
+impl ser::SerializeMap for Finder {
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Stop> {
+        key.serialize(Finder)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Stop> {
+        value.serialize(Finder)
+    }
+
+    fn end(self) -> Result<(), Stop> { Ok(()) }
+}
+
+impl ser::SerializeStruct for Finder {
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &'static str, value: &T) -> Result<(), Stop> {
+        value.serialize(Finder)
+    }
+
+    fn end(self) -> Result<(), Stop> { Ok(()) }
+}
+
+impl ser::SerializeStructVariant for Finder {
+    type Ok = ();
+    type Error = Stop;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, _: &'static str, value: &T) -> Result<(), Stop> {
+        value.serialize(Finder)
+    }
+
+    fn end(self) -> Result<(), Stop> { Ok(()) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde::Serialize;
+
+    use super::has_non_finite;
+
+    #[derive(Serialize)]
+    struct Point {
+        x: f64,
+        y: Option<f32>,
+        tags: HashMap<String, Vec<f64>>,
+    }
+
+    #[test]
+    fn test_has_non_finite() {
+        let point = |x: f64, y: Option<f32>, tag: f64| Point { x, y, tags: HashMap::from([("t".to_owned(), vec![1.0, tag])]) };
+
+        assert!(!has_non_finite(&point(1.5, Some(2.0), 3.0)));
+        assert!(has_non_finite(&point(f64::NAN, None, 3.0)));
+        assert!(has_non_finite(&point(1.5, Some(f32::INFINITY), 3.0)));
+        assert!(has_non_finite(&point(1.5, None, f64::NEG_INFINITY)));
+    }
+}
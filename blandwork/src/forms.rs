@@ -0,0 +1,142 @@
+use serde::Serialize;
+
+/// After-settle trigger fired by `Context::add_error_trigger` once the re-rendered form is swapped in.
+///
+/// Client contract, `evt.detail` of `blandwork:form-error`:
+///
+/// ```json
+/// {
+///     "form": "#signup",
+///     "errors": [
+///         { "field": "email", "messages": ["is required"] },
+///         { "field": "password", "messages": ["is too short", "needs a digit"] }
+///     ]
+/// }
+/// ```
+///
+/// `form` is the selector the fragment was retargeted to (or `null`), `errors` keeps the
+/// order the fields were added in, `field` is the `name` of the input. The integration
+/// script scrolls to and focuses the first invalid input and announces the error count.
+pub const FORM_ERROR: &str = "blandwork:form-error";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub messages: Vec<String>,
+}
+
+/// Field errors of a failed submission, in the order they should be visited.
+///
+/// ```ignore
+/// let errors = FormErrors::new()
+///     .field("email", "is required")
+///     .field("password", "is too short")
+///     .form("#signup");
+///
+/// let mut context = accessor.context().await;
+/// context.add_error_trigger(errors);
+/// return signup_form(&input, &errors).into_response();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FormErrors {
+    pub form: Option<String>,
+    pub errors: Vec<FieldError>,
+}
+
+impl FormErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a message to `field`, several messages of a field are kept together.
+    pub fn field(mut self, field: &str, message: impl Into<String>) -> Self {
+        match self.errors.iter_mut().find(|error| error.field == field) {
+            Some(error) => error.messages.push(message.into()),
+            None => self.errors.push(FieldError { field: field.to_owned(), messages: vec![message.into()] }),
+        }
+        self
+    }
+
+    /// Swap the re-rendered fragment into `selector` (`HX-Retarget`) instead of the request's target,
+    /// e.g. the whole form when the submit button targeted a result list.
+    pub fn form(mut self, selector: &str) -> Self {
+        self.form = Some(selector.to_owned());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Messages of `field`, for rendering them under the input.
+    pub fn messages(&self, field: &str) -> &[String] {
+        self.errors.iter()
+            .find(|error| error.field == field)
+            .map(|error| error.messages.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request, response::IntoResponse, routing::post, Extension, Router};
+    use axum_htmx::{HX_REQUEST, HX_RESWAP, HX_RETARGET, HX_TRIGGER_AFTER_SETTLE};
+    use maud::html;
+    use tower::ServiceExt;
+
+    use crate::{context::ContextLayer, ContextAccessor};
+
+    use super::FormErrors;
+
+    async fn signup(Extension(accessor): Extension<ContextAccessor>) -> impl IntoResponse {
+        let errors = FormErrors::new()
+            .field("email", "is required")
+            .field("password", "is too short")
+            .field("password", "needs a digit")
+            .form("#signup");
+
+        let mut context = accessor.context().await;
+        context.focus("#signup");
+        context.add_error_trigger(errors.clone());
+
+        html! { form #signup { p { (errors.messages("password").join(", ")) } } }
+    }
+
+    async fn send(htmx: bool) -> axum::response::Response {
+        let router = Router::new().route("/signup", post(signup)).layer(ContextLayer::new());
+
+        let mut request = Request::post("/signup");
+        if htmx {
+            request = request.header(HX_REQUEST, "true");
+        }
+
+        router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_error_trigger_and_retarget() {
+        let response = send(true).await;
+
+        assert_eq!(response.headers()[HX_RETARGET], "#signup");
+        assert_eq!(response.headers()[HX_RESWAP], "outerHTML");
+
+        let settle: serde_json::Value = serde_json::from_str(response.headers()[HX_TRIGGER_AFTER_SETTLE].to_str().unwrap()).unwrap();
+
+        assert_eq!(settle["blandwork:focus"]["selector"], "#signup");
+        assert_eq!(settle["blandwork:form-error"], serde_json::json!({
+            "form": "#signup",
+            "errors": [
+                { "field": "email", "messages": ["is required"] },
+                { "field": "password", "messages": ["is too short", "needs a digit"] }
+            ]
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_plain_submission_has_no_htmx_headers() {
+        let response = send(false).await;
+
+        assert!(response.headers().get(HX_RETARGET).is_none());
+        assert!(response.headers().get(HX_TRIGGER_AFTER_SETTLE).is_none());
+    }
+}
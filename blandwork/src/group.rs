@@ -0,0 +1,483 @@
+use std::{
+    fmt::Debug, future::Future, pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context as TaskContext, Poll}
+};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    response::{IntoResponse, Response},
+    Router
+};
+use axum_htmx::HX_REQUEST;
+use hyper::StatusCode;
+use maud::Markup;
+use tower::{Layer, Service};
+use tower_http::cors::CorsLayer;
+
+use crate::{
+    cdn::{route_class, CdnPolicy, RouteClass},
+    config::Widgets,
+    context::ContextLayer,
+    cors::feature_cors,
+    feature::{robots, Link},
+    guard::HtmxOnlyLayer,
+    logging::FeatureSpanLayer,
+    redirect::HtmxRedirect,
+    template::TemplateLayer,
+    toggle::{FeatureGuardLayer, FeatureHandle},
+    transform::BodyTransform,
+    widget::{EmbedTokens, WidgetLayer, WidgetShell},
+    Context, ContextAccessor, Template
+};
+
+/// How the routes of a group are served, see `Feature::web`, `Feature::api` and `Feature::supplemental`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteKind {
+    Web,
+    Api,
+    Supplemental,
+
+    /// Embedded by third-party sites, see `RouteGroup::widget`.
+    Widget,
+}
+
+type Allow = Arc<dyn Fn(&Context) -> bool + Send + Sync>;
+
+/// Who may reach a group's routes. Denied requests get a `403`, or a redirect
+/// (`HX-Redirect` for HTMX requests) when `redirect` is set.
+///
+/// ```ignore
+/// Policy::new("signed_in", |context| context.typed_cookie::<Session>().is_some()).redirect("/login")
+/// ```
+#[derive(Clone)]
+pub struct Policy {
+    name: String,
+    allow: Allow,
+    redirect: Option<String>,
+}
+
+impl Policy {
+    pub fn new(name: &str, allow: impl Fn(&Context) -> bool + Send + Sync + 'static) -> Self {
+        Self { name: name.to_owned(), allow: Arc::new(allow), redirect: None }
+    }
+
+    pub fn redirect(mut self, to: &str) -> Self {
+        self.redirect = Some(to.to_owned());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn allows(&self, context: &Context) -> bool {
+        (self.allow)(context)
+    }
+}
+
+impl Debug for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Policy").field("name", &self.name).field("redirect", &self.redirect).finish()
+    }
+}
+
+type Shell = Arc<dyn Fn(&Context, Markup) -> Markup + Send + Sync>;
+type GroupLayer = Arc<dyn Fn(Router) -> Router + Send + Sync>;
+
+/// One tier of a feature's routes (public pages, app pages, admin pages) with its own
+/// policy, shell, prefix and layers, see `Feature::groups`.
+///
+/// ```ignore
+/// RouteGroup::web("admin", admin_routes())
+///     .prefix("/admin")
+///     .policy(Policy::new("admin", is_admin))
+///     .shell(|context, body| admin_shell(context, body))
+/// ```
+pub struct RouteGroup {
+    name: String,
+    kind: RouteKind,
+    router: Router,
+    policy: Option<Policy>,
+
+    // web groups only, replaces the app template
+    shell: Option<Shell>,
+    prefix: Option<String>,
+
+    // widget groups only, inlined into the widget shell
+    css: Option<String>,
+
+    // applied innermost, in order
+    layers: Vec<GroupLayer>,
+
+    // navigation entry, hidden from `Context::visible_links` when the policy denies
+    link: Option<Link>,
+}
+
+impl RouteGroup {
+    /// The name and the routes under the prefix, without any layer, see `App::check`.
+    pub(crate) fn unmounted(self) -> (String, Router) {
+        match self.prefix.as_deref() {
+            Some(prefix) => (self.name, Router::new().nest(prefix, self.router)),
+            None => (self.name, self.router),
+        }
+    }
+
+    pub fn new(name: &str, kind: RouteKind, router: Router) -> Self {
+        Self { name: name.to_owned(), kind, router, policy: None, shell: None, prefix: None, css: None, layers: Vec::new(), link: None }
+    }
+
+    pub fn kind(&self) -> RouteKind {
+        self.kind
+    }
+
+    pub fn web(name: &str, router: Router) -> Self {
+        Self::new(name, RouteKind::Web, router)
+    }
+
+    pub fn api(name: &str, router: Router) -> Self {
+        Self::new(name, RouteKind::Api, router)
+    }
+
+    pub fn supplemental(name: &str, router: Router) -> Self {
+        Self::new(name, RouteKind::Supplemental, router)
+    }
+
+    /// Pages embedded by third-party sites, in an iframe or a div injected by `embed.js`.
+    /// Requests need an embed token of the widget `name` (`EmbedTokens`), the sites of
+    /// `[server.widgets.embeds.<name>]` may frame or fetch it, the main app's cookies
+    /// never reach the handlers. Pages are framed by a minimal widget shell.
+    pub fn widget(name: &str, router: Router) -> Self {
+        Self::new(name, RouteKind::Widget, router)
+    }
+
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Page frame of the group instead of the app template, ignored outside web groups.
+    pub fn shell(mut self, shell: impl Fn(&Context, Markup) -> Markup + Send + Sync + 'static) -> Self {
+        self.shell = Some(Arc::new(shell));
+        self
+    }
+
+    /// Critical CSS inlined into the shell of a widget group.
+    pub fn inline_css(mut self, css: &str) -> Self {
+        self.css = Some(css.to_owned());
+        self
+    }
+
+    /// Nest the routes, `/users` answers `/admin/users` with a prefix of `/admin`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Extra layers of the group, outside its template so their headers are kept,
+    /// e.g. `|router| router.layer(AuditLayer)`.
+    pub fn layer(mut self, layer: impl Fn(Router) -> Router + Send + Sync + 'static) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    pub fn link(mut self, link: Link) -> Self {
+        self.link = Some(link);
+        self
+    }
+}
+
+/// A mounted group as recorded by `build`.
+#[derive(Debug, Clone)]
+pub struct RouteGroupEntry {
+    pub feature: String,
+    pub name: String,
+    pub kind: RouteKind,
+    pub prefix: Option<String>,
+    pub policy: Option<Policy>,
+    pub link: Option<Link>,
+}
+
+/// Every feature's groups, in mount order, see `App::route_groups`.
+#[derive(Clone, Default)]
+pub struct RouteGroups {
+    entries: Arc<RwLock<Vec<RouteGroupEntry>>>,
+}
+
+/// What `build` shares with every group of a feature.
+pub(crate) struct GroupMount<'a, T: Template> {
+    pub template: &'a T,
+    pub live_reload: bool,
+    pub transforms: &'a [BodyTransform],
+    pub cdn: Option<&'a CdnPolicy>,
+    pub span: &'a FeatureSpanLayer,
+    pub toggle: &'a FeatureHandle,
+    pub toggle_redirect: Option<String>,
+    pub noindex: bool,
+    pub widgets: &'a Widgets,
+    pub embed_tokens: Option<&'a EmbedTokens>,
+    pub cors: Option<CorsLayer>,
+}
+
+#[derive(Clone)]
+struct ShellTemplate(Shell);
+
+impl Template for ShellTemplate {
+    fn page(&self, context: &Context, body: Markup) -> Markup {
+        (self.0)(context, body)
+    }
+}
+
+impl RouteGroups {
+    pub fn entries(&self) -> Vec<RouteGroupEntry> {
+        self.entries.read().unwrap().clone()
+    }
+
+    /// Links of the groups whose policy allows the request.
+    pub(crate) fn visible_links(&self, context: &Context) -> Vec<Link> {
+        self.entries.read().unwrap().iter()
+            .filter(|entry| entry.policy.as_ref().map(|policy| policy.allows(context)).unwrap_or(true))
+            .filter_map(|entry| entry.link.clone())
+            .collect()
+    }
+
+    /// The group's router with the layers of its kind, recorded under `feature`.
+    pub(crate) fn mount<T: Template + 'static>(&self, feature: &str, group: RouteGroup, mount: &GroupMount<T>) -> Router {
+        self.entries.write().unwrap().push(RouteGroupEntry {
+            feature: feature.to_owned(),
+            name: group.name.clone(),
+            kind: group.kind,
+            prefix: group.prefix.clone(),
+            policy: group.policy.clone(),
+            link: group.link.clone(),
+        });
+
+        let mut router: Router = match group.prefix.as_deref() {
+            Some(prefix) => Router::new().nest(prefix, group.router),
+            None => group.router,
+        };
+
+        if group.kind == RouteKind::Web {
+            router = match group.shell {
+                Some(shell) => router.layer(TemplateLayer::new(ShellTemplate(shell)).live_reload(mount.live_reload).transforms(mount.transforms.to_vec())),
+                None => router.layer(TemplateLayer::new(mount.template.clone()).live_reload(mount.live_reload).transforms(mount.transforms.to_vec())),
+            };
+        }
+
+        if group.kind == RouteKind::Widget {
+            router = router.layer(TemplateLayer::new(WidgetShell { css: group.css }).transforms(mount.transforms.to_vec()));
+        }
+
+        // outside the template, which rebuilds the page without the headers they set
+        for layer in group.layers.iter() {
+            router = layer(router);
+        }
+
+        // outside the template, a denial is not wrapped into a page
+        if let Some(policy) = group.policy {
+            router = router.layer(PolicyLayer { policy });
+        }
+
+        router = match group.kind {
+            RouteKind::Web => router.layer(ContextLayer::new().remember_visits()),
+            _ => router.layer(ContextLayer::new()),
+        };
+
+        if group.kind == RouteKind::Supplemental {
+            router = router.layer(HtmxOnlyLayer);
+        }
+
+        // outside the context, which is then built without the main app's cookies
+        if group.kind == RouteKind::Widget {
+            router = router.layer(WidgetLayer::new(&group.name, mount.embed_tokens.cloned(), mount.widgets.embeds.get(&group.name)));
+        }
+
+        let guard: FeatureGuardLayer = match group.kind {
+            RouteKind::Web => FeatureGuardLayer::new(mount.toggle.clone()).redirect(mount.toggle_redirect.clone()),
+            _ => FeatureGuardLayer::new(mount.toggle.clone()),
+        };
+
+        router = router
+            .layer(mount.span.clone())
+            .layer(guard);
+
+        let class: RouteClass = match group.kind {
+            RouteKind::Web | RouteKind::Widget => RouteClass::Web,
+            RouteKind::Api => RouteClass::Api,
+            RouteKind::Supplemental => RouteClass::Supplemental,
+        };
+
+        router = robots(route_class(router, mount.cdn, class), mount.noindex);
+
+        match mount.cors.clone() {
+            Some(cors) => feature_cors(router, cors),
+            None => router,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PolicyLayer {
+    policy: Policy,
+}
+
+impl<S> Layer<S> for PolicyLayer {
+    type Service = PolicyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PolicyService { inner, policy: self.policy.clone() }
+    }
+}
+
+#[derive(Clone)]
+struct PolicyService<S> {
+    inner: S,
+    policy: Policy,
+}
+
+impl<S> Service<Request> for PolicyService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // the ready service is taken, its clone waits for the next call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let policy: Policy = self.policy.clone();
+        let accessor: Option<ContextAccessor> = req.extensions().get::<ContextAccessor>().cloned();
+        let htmx: bool = req.headers().contains_key(HX_REQUEST);
+
+        Box::pin(async move {
+            let allowed: bool = match accessor {
+                Some(accessor) => policy.allows(&accessor.context().await),
+                None => false,
+            };
+
+            if allowed {
+                return inner.call(req).await;
+            }
+
+            tracing::debug!("{} denied by the {} policy", req.uri().path(), policy.name());
+
+            Ok(match policy.redirect.as_deref() {
+                Some(to) => HtmxRedirect::new(htmx).to(to).into_response(),
+                None => StatusCode::FORBIDDEN.into_response(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, http::{header::LOCATION, HeaderValue, StatusCode}, response::Response, routing::get, Router};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{feature::Link, test::router, App, Config, Context, Feature, Template};
+
+    use super::{Policy, RouteGroup, RouteKind};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, context: &Context, body: Markup) -> Markup {
+            html! { html { body .app-shell { nav { @for link in context.visible_links() { (link.label) } } (body) } } }
+        }
+    }
+
+    fn link(route: &str, label: &str) -> Link {
+        Link { active: false, title: label.to_owned(), label: label.to_owned(), route: route.to_owned(), icon: None, css: None, remember: false }
+    }
+
+    fn header(context: &Context, name: &str) -> Option<String> {
+        context.detach().headers().get(name).map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    struct Shop;
+
+    impl Feature for Shop {
+        fn groups(&self) -> Vec<RouteGroup> {
+            vec![
+                RouteGroup::web("public", Router::new().route("/pricing", get(|| async { html! { p { "pricing" } } })))
+                    .shell(|_, body| html! { html { body .marketing-shell { (body) } } }),
+
+                RouteGroup::web("app", Router::new().route("/orders", get(|| async { html! { p { "orders" } } })))
+                    .prefix("/app")
+                    .policy(Policy::new("signed_in", |context| header(context, "x-user").is_some()).redirect("/login"))
+                    .link(link("/app/orders", "Orders")),
+
+                RouteGroup::web("admin", Router::new().route("/users", get(|| async { html! { p { "users" } } })))
+                    .prefix("/admin")
+                    .policy(Policy::new("admin", |context| header(context, "x-role").as_deref() == Some("admin")))
+                    .shell(|_, body| html! { html { body .admin-shell { (body) } } })
+                    .layer(|router| router.layer(axum::middleware::map_response(|mut response: Response| async move {
+                        response.headers_mut().insert("x-admin", HeaderValue::from_static("1"));
+                        response
+                    })))
+                    .link(link("/admin/users", "Users")),
+            ]
+        }
+    }
+
+    async fn get_page(app: &Router, uri: &str, headers: &[(&str, &str)]) -> (StatusCode, Response, String) {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let (parts, body) = response.into_parts();
+        let body = String::from_utf8(to_bytes(body, usize::MAX).await.unwrap().to_vec()).unwrap();
+
+        (status, Response::from_parts(parts, Body::empty()), body)
+    }
+
+    #[tokio::test]
+    async fn test_groups_apply_shell_policy_and_prefix() {
+        let app = App::new(Config::default(), TestTemplate).register_feature(Shop).build();
+        let groups = app.route_groups().entries();
+        let app = router(&app);
+
+        assert_eq!(groups.iter().map(|group| group.name.as_str()).collect::<Vec<_>>(), vec!["public", "app", "admin"]);
+        assert!(groups.iter().all(|group| group.feature == "Shop" && group.kind == RouteKind::Web));
+        assert_eq!(groups[1].prefix.as_deref(), Some("/app"));
+        assert_eq!(groups[2].policy.as_ref().map(|policy| policy.name()), Some("admin"));
+
+        let (status, _, body) = get_page(&app, "/pricing", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"<body class="marketing-shell"><p>pricing</p>"#));
+
+        let (status, response, _) = get_page(&app, "/app/orders", &[]).await;
+        assert_eq!(status, StatusCode::FOUND);
+        assert_eq!(response.headers()[LOCATION], "/login");
+
+        // the app template, showing only the links the request may follow
+        let (status, _, body) = get_page(&app, "/app/orders", &[("x-user", "ada")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"<body class="app-shell"><nav>Orders</nav><p>orders</p>"#));
+
+        let (status, _, _) = get_page(&app, "/admin/users", &[("x-user", "ada")]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, response, body) = get_page(&app, "/admin/users", &[("x-role", "admin")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.headers()["x-admin"], "1");
+        assert!(body.contains(r#"<body class="admin-shell"><p>users</p>"#));
+
+        // unprefixed paths are not mounted
+        let (status, _, _) = get_page(&app, "/users", &[("x-role", "admin")]).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
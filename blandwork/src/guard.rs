@@ -0,0 +1,97 @@
+use std::{future::Future, pin::Pin, task::{Context as TaskContext, Poll}};
+
+use axum::{body::Body, extract::Request, response::IntoResponse};
+use axum_htmx::HX_REQUEST;
+use hyper::{Method, Response, StatusCode};
+use tower::{Layer, Service};
+
+/// Keeps supplemental routes out of reach of direct browser navigation.
+///
+/// Every method is accepted from HTMX (`hx-delete`, `hx-patch`, ...), anything else
+/// is answered 404. OPTIONS passes so the allowed methods can still be listed.
+#[derive(Debug, Clone, Default)]
+pub struct HtmxOnlyLayer;
+
+impl<S> Layer<S> for HtmxOnlyLayer {
+    type Service = HtmxOnlyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HtmxOnlyService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct HtmxOnlyService<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for HtmxOnlyService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let htmx: bool = req.headers().contains_key(HX_REQUEST);
+
+        if !htmx && req.method() != Method::OPTIONS {
+            tracing::debug!(path = req.uri().path(), method = %req.method(), "supplemental route requested without HTMX");
+
+            return Box::pin(async move { Ok(StatusCode::NOT_FOUND.into_response()) });
+        }
+
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Router};
+    use axum_htmx::HX_REQUEST;
+    use hyper::{Method, StatusCode};
+    use tower::ServiceExt;
+
+    use super::HtmxOnlyLayer;
+
+    fn router() -> Router {
+        Router::new()
+            .route("/items/1", get(|| async { "<li>1</li>" })
+                .delete(|| async { "" })
+                .patch(|| async { "<li>1 (edited)</li>" }))
+            .layer(HtmxOnlyLayer)
+    }
+
+    async fn send(method: Method, htmx: bool) -> (StatusCode, String) {
+        let mut request = Request::builder().method(method).uri("/items/1");
+        if htmx {
+            request = request.header(HX_REQUEST, "true");
+        }
+
+        let response = router().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_htmx_mutations_pass() {
+        assert_eq!(send(Method::DELETE, true).await, (StatusCode::OK, "".to_owned()));
+        assert_eq!(send(Method::PATCH, true).await, (StatusCode::OK, "<li>1 (edited)</li>".to_owned()));
+        assert_eq!(send(Method::GET, true).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_direct_requests_blocked() {
+        assert_eq!(send(Method::GET, false).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(Method::DELETE, false).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(send(Method::PATCH, false).await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,438 @@
+use std::{collections::BTreeMap, error::Error, fmt::Display, sync::Arc};
+
+use maud::{html, Markup};
+
+use crate::{config::AssetConflicts, feature::Feature, transform::BodyTransform, Context};
+
+/// Attribute naming the asset of a tag, what the integration script checks before loading it again.
+pub const ASSET_ATTRIBUTE: &str = "data-blandwork-asset";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    Script,
+    Style,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    Head,
+
+    /// Before `</body>`, for scripts expecting the page's elements.
+    BodyEnd,
+}
+
+/// A script or stylesheet a feature needs in the page, deduplicated by name across features.
+///
+/// ```ignore
+/// fn head_assets(&self) -> Vec<HeadAsset> {
+///     vec![
+///         HeadAsset::script("chartjs", "4.4.1", "/web/vendor/chart.umd.js"),
+///         HeadAsset::script("charts", "1.0.0", "/web/js/charts.js").requires(&["chartjs"]).body_end().defer(),
+///     ]
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadAsset {
+    pub name: String,
+    pub version: String,
+    pub kind: AssetKind,
+    pub url: String,
+    pub placement: Placement,
+    pub defer: bool,
+    pub module: bool,
+    pub requires: Vec<String>,
+
+    /// On every full page instead of only the pages requiring it, see `Context::require_asset`.
+    pub global: bool,
+}
+
+impl HeadAsset {
+    pub fn script(name: &str, version: &str, url: &str) -> Self {
+        Self::new(name, version, url, AssetKind::Script)
+    }
+
+    pub fn style(name: &str, version: &str, url: &str) -> Self {
+        Self::new(name, version, url, AssetKind::Style)
+    }
+
+    fn new(name: &str, version: &str, url: &str, kind: AssetKind) -> Self {
+        Self {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            kind,
+            url: url.to_owned(),
+            placement: Placement::Head,
+            defer: false,
+            module: false,
+            requires: Vec::new(),
+            global: false,
+        }
+    }
+
+    /// Assets loaded before this one.
+    pub fn requires(mut self, names: &[&str]) -> Self {
+        self.requires.extend(names.iter().map(|name| name.to_string()));
+        self
+    }
+
+    pub fn body_end(mut self) -> Self {
+        self.placement = Placement::BodyEnd;
+        self
+    }
+
+    pub fn defer(mut self) -> Self {
+        self.defer = true;
+        self
+    }
+
+    pub fn module(mut self) -> Self {
+        self.module = true;
+        self
+    }
+
+    pub fn global(mut self) -> Self {
+        self.global = true;
+        self
+    }
+
+    pub fn render(&self) -> Markup {
+        match self.kind {
+            AssetKind::Script => html! {
+                script src=(self.url) defer[self.defer] type=[self.module.then_some("module")] data-blandwork-asset=(self.name) {}
+            },
+            AssetKind::Style => html! {
+                link rel="stylesheet" href=(self.url) data-blandwork-asset=(self.name);
+            },
+        }
+    }
+}
+
+/// Why the declared assets can't be served, `build` refuses to start with it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssetError {
+    /// Two features declare the same asset in different versions (`server.assets.conflicts = "error"`).
+    Conflict { name: String, first: (String, String), second: (String, String) },
+    MissingDependency { name: String, requires: String },
+    Cycle(Vec<String>),
+}
+
+impl Display for AssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetError::Conflict { name, first, second } => write!(f,
+                "head asset {name}: {} requires {}, {} requires {}", first.0, first.1, second.0, second.1),
+            AssetError::MissingDependency { name, requires } => write!(f, "head asset {name} requires the undeclared {requires}"),
+            AssetError::Cycle(names) => write!(f, "head assets depend on each other: {}", names.join(", ")),
+        }
+    }
+}
+
+impl Error for AssetError {}
+
+/// Every feature's head assets, one per name, see `Feature::head_assets`.
+#[derive(Debug, Clone, Default)]
+pub struct HeadAssets {
+    // in declaration order, the tie break of the dependency order
+    assets: Arc<Vec<HeadAsset>>,
+}
+
+impl HeadAssets {
+    pub(crate) fn from_features(features: &[Box<dyn Feature>], conflicts: AssetConflicts) -> Result<Self, AssetError> {
+        Self::resolve(features.iter().flat_map(|feature| {
+            let name: String = feature.name();
+            feature.head_assets().into_iter().map(move |asset| (name.clone(), asset))
+        }), conflicts)
+    }
+
+    /// Deduplicate the `(feature, asset)` declarations by name and check their dependencies.
+    pub fn resolve(declared: impl IntoIterator<Item = (String, HeadAsset)>, conflicts: AssetConflicts) -> Result<Self, AssetError> {
+        let mut assets: Vec<HeadAsset> = Vec::new();
+        let mut owners: Vec<String> = Vec::new();
+
+        for (feature, asset) in declared {
+            let Some(index) = assets.iter().position(|known| known.name == asset.name) else {
+                assets.push(asset);
+                owners.push(feature);
+                continue;
+            };
+
+            let known: &HeadAsset = &assets[index];
+
+            if known.version == asset.version {
+                // requested globally by any of them
+                assets[index].global |= asset.global;
+                continue;
+            }
+
+            match conflicts {
+                AssetConflicts::Error => return Err(AssetError::Conflict {
+                    name: asset.name.clone(),
+                    first: (owners[index].clone(), known.version.clone()),
+                    second: (feature, asset.version.clone()),
+                }),
+                AssetConflicts::Highest => {
+                    let (kept, dropped) = match compare_versions(&asset.version, &known.version).is_gt() {
+                        true => (asset.version.clone(), known.version.clone()),
+                        false => (known.version.clone(), asset.version.clone()),
+                    };
+
+                    tracing::warn!("head asset {}: {} and {} disagree, {kept} is served instead of {dropped}", asset.name, owners[index], feature);
+
+                    if kept == asset.version {
+                        let global: bool = known.global || asset.global;
+                        assets[index] = HeadAsset { global, ..asset };
+                        owners[index] = feature;
+                    }
+                },
+            }
+        }
+
+        for asset in assets.iter() {
+            if let Some(missing) = asset.requires.iter().find(|name| !assets.iter().any(|known| &&known.name == name)) {
+                return Err(AssetError::MissingDependency { name: asset.name.clone(), requires: missing.clone() });
+            }
+        }
+
+        let resolved: Self = Self { assets: Arc::new(assets) };
+
+        let everything: Vec<String> = resolved.assets.iter().map(|asset| asset.name.clone()).collect();
+        resolved.order(&everything)?;
+
+        Ok(resolved)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+
+    /// `names`, their dependencies and the global assets, dependencies first and otherwise
+    /// in declaration order, each once.
+    pub fn page(&self, names: &[String]) -> Vec<&HeadAsset> {
+        let mut wanted: Vec<String> = self.assets.iter().filter(|asset| asset.global).map(|asset| asset.name.clone()).collect();
+        wanted.extend(names.iter().cloned());
+
+        self.order(&wanted).unwrap_or_default()
+    }
+
+    /// `names` and their dependencies, dependencies first.
+    fn order(&self, names: &[String]) -> Result<Vec<&HeadAsset>, AssetError> {
+        // the closure of the requested names over their dependencies
+        let mut included: Vec<bool> = vec![false; self.assets.len()];
+        let mut pending: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+
+        while let Some(name) = pending.pop() {
+            let Some(index) = self.index(name) else {
+                tracing::warn!("head asset {name} is not declared by any feature");
+                continue;
+            };
+
+            if !included[index] {
+                included[index] = true;
+                pending.extend(self.assets[index].requires.iter().map(|name| name.as_str()));
+            }
+        }
+
+        // Kahn's algorithm, the first ready asset in declaration order goes next
+        let mut ordered: Vec<&HeadAsset> = Vec::new();
+        let mut placed: Vec<bool> = vec![false; self.assets.len()];
+
+        loop {
+            let ready: Option<usize> = (0..self.assets.len()).find(|&index| {
+                included[index] && !placed[index]
+                    && self.assets[index].requires.iter().all(|name| self.index(name).map(|dependency| placed[dependency]).unwrap_or(true))
+            });
+
+            match ready {
+                Some(index) => {
+                    placed[index] = true;
+                    ordered.push(&self.assets[index]);
+                },
+                None => break,
+            }
+        }
+
+        let stuck: Vec<String> = (0..self.assets.len())
+            .filter(|&index| included[index] && !placed[index])
+            .map(|index| self.assets[index].name.clone())
+            .collect();
+
+        match stuck.is_empty() {
+            true => Ok(ordered),
+            false => Err(AssetError::Cycle(stuck)),
+        }
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.assets.iter().position(|asset| asset.name == name)
+    }
+}
+
+/// Dotted numeric versions compared numerically, anything else as text.
+fn compare_versions(a: &str, b: &str) -> std::cmp::Ordering {
+    let parse = |version: &str| -> Option<Vec<u64>> {
+        version.trim_start_matches('v').split('.').map(|part| part.parse::<u64>().ok()).collect()
+    };
+
+    match (parse(a), parse(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// Full pages get the global and required assets at their placement.
+pub(crate) fn head_assets_transform(assets: HeadAssets) -> BodyTransform {
+    Arc::new(move |context: &Context, html: String| {
+        let page: Vec<&HeadAsset> = assets.page(&context.required_assets());
+
+        if page.is_empty() {
+            return html;
+        }
+
+        let mut placed: BTreeMap<&str, String> = BTreeMap::new();
+
+        for asset in page {
+            let end: &str = match asset.placement {
+                Placement::Head => "</head>",
+                Placement::BodyEnd => "</body>",
+            };
+
+            placed.entry(end).or_default().push_str(&asset.render().into_string());
+        }
+
+        let mut html: String = html;
+
+        for (end, tags) in placed {
+            if let Some(index) = html.rfind(end) {
+                html.insert_str(index, &tags);
+            }
+        }
+
+        html
+    })
+}
+
+/// For fragments swapped into a page which may lack the required assets: appended to the
+/// head out of band, the integration script skips the ones the page already has.
+pub(crate) fn assets_oob(assets: &HeadAssets, names: &[String]) -> Option<Markup> {
+    let required: Vec<&HeadAsset> = assets.order(names).unwrap_or_default();
+
+    if required.is_empty() {
+        return None;
+    }
+
+    Some(html! {
+        div hx-swap-oob="beforeend:head" data-blandwork-assets {
+            @for asset in required {
+                (asset.render())
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use axum_htmx::{HX_BOOSTED, HX_REQUEST};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{config::AssetConflicts, test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{AssetError, HeadAsset, HeadAssets};
+
+    fn declared(assets: &[(&str, HeadAsset)]) -> Vec<(String, HeadAsset)> {
+        assets.iter().map(|(feature, asset)| (feature.to_string(), asset.clone())).collect()
+    }
+
+    fn names(assets: Vec<&HeadAsset>) -> Vec<&str> {
+        assets.into_iter().map(|asset| asset.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_dedup_and_order() {
+        let assets = HeadAssets::resolve(declared(&[
+            ("Reports", HeadAsset::script("reports", "1.0", "/web/reports.js").requires(&["chartjs", "dates"])),
+            ("Reports", HeadAsset::script("chartjs", "4.4.1", "/web/chart.js")),
+            ("Dashboard", HeadAsset::script("chartjs", "4.4.1", "/web/chart.js")),
+            ("Dashboard", HeadAsset::script("dates", "2.0", "/web/dates.js").requires(&["chartjs"])),
+            ("Dashboard", HeadAsset::style("theme", "1.0", "/web/theme.css").global()),
+        ]), AssetConflicts::Error).unwrap();
+
+        assert_eq!(names(assets.page(&["reports".to_owned(), "chartjs".to_owned()])), vec!["chartjs", "dates", "reports", "theme"]);
+        assert_eq!(names(assets.page(&[])), vec!["theme"]);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let conflicting = declared(&[
+            ("Reports", HeadAsset::script("chartjs", "3.9.0", "/web/chart-3.js")),
+            ("Dashboard", HeadAsset::script("chartjs", "4.4.1", "/web/chart-4.js")),
+        ]);
+
+        assert_eq!(HeadAssets::resolve(conflicting.clone(), AssetConflicts::Error).err().unwrap(), AssetError::Conflict {
+            name: "chartjs".to_owned(),
+            first: ("Reports".to_owned(), "3.9.0".to_owned()),
+            second: ("Dashboard".to_owned(), "4.4.1".to_owned()),
+        });
+
+        let assets = HeadAssets::resolve(conflicting, AssetConflicts::Highest).unwrap();
+        assert_eq!(assets.page(&["chartjs".to_owned()])[0].url, "/web/chart-4.js");
+
+        let cycle = declared(&[
+            ("Reports", HeadAsset::script("a", "1", "/a.js").requires(&["b"])),
+            ("Reports", HeadAsset::script("b", "1", "/b.js").requires(&["a"])),
+        ]);
+        assert_eq!(HeadAssets::resolve(cycle, AssetConflicts::Error).err().unwrap(), AssetError::Cycle(vec!["a".to_owned(), "b".to_owned()]));
+    }
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { head { title { "t" } } body { (body) } } }
+        }
+    }
+
+    struct Reports;
+
+    impl Feature for Reports {
+        fn head_assets(&self) -> Vec<HeadAsset> {
+            vec![
+                HeadAsset::script("chartjs", "4.4.1", "/web/chart.js"),
+                HeadAsset::script("reports", "1.0", "/web/reports.js").requires(&["chartjs"]).body_end().defer(),
+            ]
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/reports", get(|Extension(accessor): Extension<ContextAccessor>| async move {
+                accessor.context().await.require_asset("reports");
+                html! { canvas {} }
+            })))
+        }
+    }
+
+    async fn body(app: &Router, request: Request) -> String {
+        let response = app.clone().oneshot(request).await.unwrap();
+        String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_page_and_fragment() {
+        let mut app = App::new(Config::default(), TestTemplate).register_feature(Reports);
+        let app = router(&app.build());
+
+        let page = body(&app, Request::get("/reports").body(Body::empty()).unwrap()).await;
+        assert!(page.contains(r#"<script src="/web/chart.js" data-blandwork-asset="chartjs"></script></head>"#));
+        assert!(page.contains(r#"<canvas></canvas><script src="/web/reports.js" defer data-blandwork-asset="reports"></script></body>"#));
+
+        // swapped into a page which may not have them
+        let fragment = body(&app, Request::get("/reports").header(HX_REQUEST, "true").header(HX_BOOSTED, "true").body(Body::empty()).unwrap()).await;
+        assert!(fragment.starts_with("<canvas></canvas>"));
+        assert!(fragment.contains(concat!(
+            r#"<div hx-swap-oob="beforeend:head" data-blandwork-assets>"#,
+            r#"<script src="/web/chart.js" data-blandwork-asset="chartjs"></script>"#,
+            r#"<script src="/web/reports.js" defer data-blandwork-asset="reports"></script></div>"#
+        )));
+    }
+}
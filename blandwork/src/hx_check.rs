@@ -0,0 +1,503 @@
+//! Static checks of the hx-* attributes in template sources against the route manifest,
+//! for typos which only show up when someone clicks.
+
+use std::{collections::HashSet, error::Error, fmt::Display, io, path::Path};
+
+use crate::test::{resolve, RouteManifest};
+
+/// Attributes holding a URL requested (or pushed) by HTMX.
+const URL_ATTRIBUTES: [&str; 6] = ["hx-get", "hx-post", "hx-put", "hx-patch", "hx-delete", "hx-push-url"];
+
+/// A template file, `path` is only used to report findings.
+#[derive(Debug, Clone)]
+pub struct TemplateSource {
+    pub path: String,
+    pub source: String,
+}
+
+impl TemplateSource {
+    pub fn new(path: &str, source: &str) -> Self {
+        Self { path: path.to_owned(), source: source.to_owned() }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path: &Path = path.as_ref();
+
+        Ok(Self {
+            path: path.display().to_string(),
+            source: std::fs::read_to_string(path)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    UnknownRoute(String),
+    MissingTarget(String),
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::UnknownRoute(path) => write!(f, "no route matches {path}"),
+            Violation::MissingTarget(id) => write!(f, "no element with id {id:?} in the shell or the template"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HxFinding {
+    pub file: String,
+    pub line: usize,
+    pub attribute: String,
+    pub value: String,
+    pub violation: Violation,
+}
+
+impl Display for HxFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}=\"{}\": {}", self.file, self.line, self.attribute, self.value, self.violation)
+    }
+}
+
+/// Findings along with the coverage of the check.
+#[derive(Debug, Clone, Default)]
+pub struct HxReport {
+    pub findings: Vec<HxFinding>,
+
+    // literal URLs and #id targets verified
+    pub checked: usize,
+
+    // values containing template expressions, skipped
+    pub dynamic: usize,
+}
+
+impl HxReport {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Log every finding as a warning, or fail with all of them when strict (CI).
+    pub fn enforce(self, strict: bool) -> Result<Self, HxCheckError> {
+        if strict && !self.is_clean() {
+            return Err(HxCheckError(self.findings));
+        }
+
+        for finding in self.findings.iter() {
+            tracing::warn!("{finding}");
+        }
+
+        tracing::info!("hx check: {} checked, {} dynamic skipped, {} findings", self.checked, self.dynamic, self.findings.len());
+
+        Ok(self)
+    }
+}
+
+#[derive(Debug)]
+pub struct HxCheckError(pub Vec<HxFinding>);
+
+impl Display for HxCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} hx-* findings", self.0.len())?;
+
+        for finding in self.0.iter() {
+            writeln!(f, "  {finding}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for HxCheckError {}
+
+/// Verifies hx-get/post/put/patch/delete/push-url URLs resolve against the manifest (pattern aware)
+/// and `hx-target="#id"` targets exist in the shell or the same template.
+/// Template expressions (`{{ .. }}`, `{% .. %}`) are treated as wildcards by the parser,
+/// values containing one are skipped and counted as dynamic.
+///
+/// ```ignore
+/// #[test]
+/// fn hx_attributes() {
+///     HxCheck::new(manifest)
+///         .shell(TemplateSource::load("templates/shell.html").unwrap())
+///         .template(TemplateSource::load("templates/items.html").unwrap())
+///         .run()
+///         .enforce(true)
+///         .unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct HxCheck {
+    manifest: RouteManifest,
+    shell: Option<TemplateSource>,
+    templates: Vec<TemplateSource>,
+}
+
+impl HxCheck {
+    pub fn new(manifest: RouteManifest) -> Self {
+        Self { manifest, ..Default::default() }
+    }
+
+    /// The page frame, its ids are valid targets for every template.
+    pub fn shell(mut self, shell: TemplateSource) -> Self {
+        self.shell = Some(shell);
+        self
+    }
+
+    pub fn template(mut self, template: TemplateSource) -> Self {
+        self.templates.push(template);
+        self
+    }
+
+    pub fn run(&self) -> HxReport {
+        let mut report: HxReport = HxReport::default();
+
+        let shell: Vec<Attribute> = self.shell.as_ref().map(|shell| scan(&shell.source)).unwrap_or_default();
+        let shell_ids: HashSet<String> = ids(&shell);
+
+        let sources = self.shell.iter().map(|source| (source, shell.clone()))
+            .chain(self.templates.iter().map(|template| (template, scan(&template.source))));
+
+        for (source, attributes) in sources {
+            let own_ids: HashSet<String> = ids(&attributes);
+
+            for attribute in attributes.iter() {
+                let literal: bool = !is_dynamic(&attribute.value);
+
+                let violation: Option<Violation> = if URL_ATTRIBUTES.contains(&attribute.name.as_str()) {
+                    // hx-push-url="true" pushes the requested URL
+                    if attribute.name == "hx-push-url" && matches!(attribute.value.trim(), "true" | "false") {
+                        continue;
+                    }
+
+                    if !literal {
+                        report.dynamic += 1;
+                        continue;
+                    }
+
+                    // external URLs are not ours to check
+                    let Some(path) = resolve("/", &attribute.value) else {
+                        continue;
+                    };
+
+                    report.checked += 1;
+
+                    match self.manifest.is_allowed(&path) || self.manifest.matches(&path) {
+                        true => None,
+                        false => Some(Violation::UnknownRoute(path)),
+                    }
+                } else if attribute.name == "hx-target" {
+                    if !literal {
+                        report.dynamic += 1;
+                        continue;
+                    }
+
+                    // only plain #id selectors, `closest tr`, `this`, classes ... are left alone
+                    let Some(id) = literal_id(&attribute.value) else {
+                        continue;
+                    };
+
+                    report.checked += 1;
+
+                    match shell_ids.contains(id) || own_ids.contains(id) {
+                        true => None,
+                        false => Some(Violation::MissingTarget(id.to_owned())),
+                    }
+                } else {
+                    continue;
+                };
+
+                if let Some(violation) = violation {
+                    report.findings.push(HxFinding {
+                        file: source.path.clone(),
+                        line: attribute.line,
+                        attribute: attribute.name.clone(),
+                        value: attribute.value.clone(),
+                        violation,
+                    });
+                }
+            }
+        }
+
+        report
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Attribute {
+    name: String,
+    value: String,
+    line: usize,
+}
+
+fn is_dynamic(value: &str) -> bool {
+    value.contains("{{") || value.contains("{%")
+}
+
+fn literal_id(selector: &str) -> Option<&str> {
+    let id: &str = selector.trim().strip_prefix('#')?;
+
+    match !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        true => Some(id),
+        false => None,
+    }
+}
+
+fn ids(attributes: &[Attribute]) -> HashSet<String> {
+    attributes.iter()
+        .filter(|attribute| attribute.name == "id" && !is_dynamic(&attribute.value))
+        .map(|attribute| attribute.value.trim().to_owned())
+        .collect()
+}
+
+/// Blank out the inside of `{% .. %}` statements and `{# .. #}` comments, keeping offsets
+/// and line breaks, so quotes and `>` in conditionals do not end tags or values.
+fn blank_statements(source: &str) -> String {
+    let mut blanked: String = String::with_capacity(source.len());
+    let mut rest: &str = source;
+
+    while let Some(start) = rest.find('{') {
+        let close: Option<&str> = match rest[start..].get(..2) {
+            Some("{%") => Some("%}"),
+            Some("{#") => Some("#}"),
+            _ => None,
+        };
+
+        let Some(close) = close else {
+            blanked.push_str(&rest[..start + 1]);
+            rest = &rest[start + 1..];
+            continue;
+        };
+
+        blanked.push_str(&rest[..start]);
+
+        let end: usize = rest[start..].find(close).map(|end| start + end + 2).unwrap_or(rest.len());
+
+        let blank = |text: &str| text.chars().map(|c| if c == '\n' { '\n' } else { ' ' }).collect::<String>();
+
+        // statements keep their delimiters to mark the values they appear in as dynamic
+        match close == "%}" && rest[start..end].len() >= 4 && rest[start..end].ends_with("%}") {
+            true => {
+                blanked.push_str("{%");
+                blanked.push_str(&blank(&rest[start + 2..end - 2]));
+                blanked.push_str("%}");
+            },
+            false => blanked.push_str(&blank(&rest[start..end])),
+        }
+
+        rest = &rest[end..];
+    }
+
+    blanked.push_str(rest);
+    blanked
+}
+
+/// End of the `{{ .. }}` expression starting at `index`.
+fn expression_end(source: &str, index: usize) -> usize {
+    source[index..].find("}}").map(|end| index + end + 2).unwrap_or(source.len())
+}
+
+/// Every attribute of every tag with the line it starts on.
+fn scan(source: &str) -> Vec<Attribute> {
+    let html: String = blank_statements(source);
+    let html: &str = html.as_str();
+    let bytes: &[u8] = html.as_bytes();
+
+    let line = |offset: usize| html[..offset].matches('\n').count() + 1;
+
+    let mut attributes: Vec<Attribute> = Vec::new();
+    let mut index: usize = 0;
+
+    while let Some(offset) = html[index..].find('<') {
+        index += offset + 1;
+
+        if html[index..].starts_with("!--") {
+            index = html[index..].find("-->").map(|end| index + end + 3).unwrap_or(html.len());
+            continue;
+        }
+
+        let name_end: usize = html[index..]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .map(|end| index + end)
+            .unwrap_or(html.len());
+
+        let tag: &str = &html[index..name_end];
+        index = name_end;
+
+        if tag.is_empty() || tag.starts_with('!') {
+            continue;
+        }
+
+        loop {
+            while index < bytes.len() && (bytes[index].is_ascii_whitespace() || bytes[index] == b'/') {
+                index += 1;
+            }
+
+            if index >= bytes.len() || bytes[index] == b'>' {
+                break;
+            }
+
+            // an expression in place of attributes, `<div {{ attrs }}>`
+            if html[index..].starts_with("{{") {
+                index = expression_end(html, index);
+                continue;
+            }
+
+            // conditional attributes, `<li {% if active %}hx-get=".."{% endif %}>`
+            if html[index..].starts_with("{%") {
+                index = html[index..].find("%}").map(|end| index + end + 2).unwrap_or(html.len());
+                continue;
+            }
+
+            let start: usize = index;
+            let name_end: usize = html[index..]
+                .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                .map(|end| index + end)
+                .unwrap_or(html.len());
+
+            let name: String = html[index..name_end].to_ascii_lowercase();
+            index = name_end;
+
+            while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+                index += 1;
+            }
+
+            if index >= bytes.len() || bytes[index] != b'=' {
+                if name.is_empty() {
+                    index += 1;
+                }
+                continue;
+            }
+
+            index += 1;
+
+            while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+                index += 1;
+            }
+
+            let value: &str = match bytes.get(index) {
+                Some(quote @ (b'"' | b'\'')) => {
+                    let value_start: usize = index + 1;
+                    index = value_start;
+
+                    // quotes inside expressions do not end the value
+                    while index < bytes.len() && bytes[index] != *quote {
+                        index = match html[index..].starts_with("{{") {
+                            true => expression_end(html, index),
+                            false => index + 1,
+                        };
+                    }
+
+                    let value_end: usize = index.min(html.len());
+                    index = (index + 1).min(html.len());
+
+                    &html[value_start..value_end]
+                },
+                _ => {
+                    let value_start: usize = index;
+
+                    while index < bytes.len() && !bytes[index].is_ascii_whitespace() && bytes[index] != b'>' {
+                        index = match html[index..].starts_with("{{") {
+                            true => expression_end(html, index),
+                            false => index + 1,
+                        };
+                    }
+
+                    &html[value_start..index.min(html.len())]
+                }
+            };
+
+            attributes.push(Attribute {
+                name,
+                value: value.replace("&amp;", "&"),
+                line: line(start),
+            });
+        }
+    }
+
+    attributes
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::RouteManifest;
+
+    use super::{HxCheck, HxFinding, TemplateSource, Violation};
+
+    const SHELL: &str = r#"<html>
+<body>
+    <nav hx-boost="true"><a href="/items">Items</a></nav>
+    <main id="content"></main>
+</body>
+</html>"#;
+
+    fn check() -> HxCheck {
+        let manifest = RouteManifest::new()
+            .route("/items")
+            .route("/items/:id")
+            .route("/sample/more");
+
+        HxCheck::new(manifest).shell(TemplateSource::new("shell.html", SHELL))
+    }
+
+    #[test]
+    fn test_valid_templates() {
+        let report = check()
+            .template(TemplateSource::new("items.html", r##"<ul id="list">
+    <li hx-get="/items/42" hx-target="#content" hx-push-url="true">Item</li>
+    <button hx-delete="/items/42" hx-target="closest li">x</button>
+    <a hx-get="/sample/more?page=2" hx-target="#list">more</a>
+    <li {% if active %}hx-get="/items"{% endif %}>Back</li>
+</ul>"##))
+            .run();
+
+        assert!(report.is_clean(), "{:?}", report.findings);
+        assert_eq!(report.checked, 6);
+        assert_eq!(report.dynamic, 0);
+    }
+
+    #[test]
+    fn test_findings() {
+        let report = check()
+            .template(TemplateSource::new("sample.html", r##"<div>
+    <a hx-get="/smaple/more" hx-target="#content">more</a>
+    {% if user %}
+    <button hx-post="/items" hx-target="#nowhere">add</button>
+    {% endif %}
+    <a hx-get="/items/{{ item.id }}" hx-target="{{ target }}" hx-push-url='{{ url_for("items") }}'>item</a>
+</div>"##))
+            .run();
+
+        assert_eq!(report.findings, vec![
+            HxFinding {
+                file: "sample.html".to_owned(),
+                line: 2,
+                attribute: "hx-get".to_owned(),
+                value: "/smaple/more".to_owned(),
+                violation: Violation::UnknownRoute("/smaple/more".to_owned()),
+            },
+            HxFinding {
+                file: "sample.html".to_owned(),
+                line: 4,
+                attribute: "hx-target".to_owned(),
+                value: "#nowhere".to_owned(),
+                violation: Violation::MissingTarget("nowhere".to_owned()),
+            },
+        ]);
+
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.dynamic, 3);
+    }
+
+    #[test]
+    fn test_enforce() {
+        let report = check()
+            .template(TemplateSource::new("bad.html", r#"<a hx-get="/nope">x</a>"#))
+            .run();
+
+        assert!(report.clone().enforce(false).is_ok());
+
+        let error = report.enforce(true).unwrap_err();
+        assert_eq!(error.to_string(), "1 hx-* findings\n  bad.html:1: hx-get=\"/nope\": no route matches /nope\n");
+    }
+}
@@ -0,0 +1,323 @@
+use maud::{html, Markup};
+use serde::Serialize;
+
+use crate::{config::{ImageFormat, Images}, Context};
+
+/// Trigger sent once the variants of an image have been stored.
+pub const MEDIA_READY: &str = "media:ready";
+
+impl ImageFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Avif => "avif",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Png => "image/png",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Avif => "image/avif",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        let (_, extension) = key.rsplit_once('.')?;
+
+        match extension.to_ascii_lowercase().as_str() {
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "png" => Some(ImageFormat::Png),
+            "webp" => Some(ImageFormat::Webp),
+            "avif" => Some(ImageFormat::Avif),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageVariant {
+    pub width: u32,
+    pub format: ImageFormat,
+}
+
+impl ImageVariant {
+    /// Storage key of this variant, derived only from the original key:
+    /// `uploads/cat.jpg` at 640 webp is `uploads/cat.jpg.640w.webp`.
+    pub fn key(&self, original: &str) -> String {
+        format!("{original}.{}w.{}", self.width, self.format.extension())
+    }
+}
+
+/// An uploaded image as recorded by the application.
+#[derive(Debug, Clone)]
+pub struct StoredImage {
+    pub key: String,
+    pub width: u32,
+    pub height: u32,
+
+    // css color shown until the variants exist
+    pub placeholder: Option<String>,
+
+    // variants have been generated and stored
+    pub ready: bool,
+}
+
+impl StoredImage {
+    pub fn format(&self) -> ImageFormat {
+        ImageFormat::from_key(&self.key).unwrap_or(ImageFormat::Jpeg)
+    }
+
+    /// Variants to generate, never wider than the original.
+    pub fn variants(&self, config: &Images) -> Vec<ImageVariant> {
+        let mut widths: Vec<u32> = config.widths.iter()
+            .copied()
+            .filter(|width| *width < self.width)
+            .collect();
+        widths.push(self.width);
+        widths.sort_unstable();
+        widths.dedup();
+
+        let mut formats: Vec<ImageFormat> = config.formats.clone();
+        formats.retain(|format| *format != self.format());
+        formats.push(self.format());
+
+        formats.iter()
+            .flat_map(|format| widths.iter().map(|width| ImageVariant { width: *width, format: *format }))
+            .collect()
+    }
+}
+
+fn url(config: &Images, key: &str) -> String {
+    format!("{}/{}", config.prefix.trim_end_matches('/'), key.trim_start_matches('/'))
+}
+
+fn srcset(config: &Images, image: &StoredImage, variants: &[ImageVariant], format: ImageFormat) -> String {
+    variants.iter()
+        .filter(|variant| variant.format == format)
+        .map(|variant| format!("{} {}w", url(config, &variant.key(&image.key)), variant.width))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Responsive `<picture>` for an uploaded image, lazily loaded.
+///
+/// Until the variants are ready a placeholder of the same aspect ratio is rendered,
+/// it replaces itself with the picture when the page receives `media:ready` for this key
+/// (see `media_ready`), `refresh` is the url rendering this helper again. The key is compared
+/// from a `data-` attribute, htmx evaluates the trigger filter as script.
+pub fn img_srcset(config: &Images, image: &StoredImage, sizes: &str, class: &str, alt: &str, refresh: &str) -> Markup {
+    if !image.ready {
+        let style: String = format!(
+            "aspect-ratio:{}/{};background-color:{}",
+            image.width, image.height,
+            image.placeholder.as_deref().unwrap_or("#e5e7eb")
+        );
+
+        return html! {
+            div class=(class) style=(style) role="img" aria-label=(alt)
+                data-media-key=(image.key)
+                hx-get=(refresh)
+                hx-trigger={ (MEDIA_READY) "[detail.key==this.dataset.mediaKey] from:body" }
+                hx-swap="outerHTML" {}
+        };
+    }
+
+    let variants: Vec<ImageVariant> = image.variants(config);
+    let original: ImageFormat = image.format();
+
+    let alternatives: Vec<ImageFormat> = config.formats.iter()
+        .copied()
+        .filter(|format| *format != original)
+        .collect();
+
+    html! {
+        picture {
+            @for format in alternatives.iter() {
+                source type=(format.mime()) srcset=(srcset(config, image, &variants, *format)) sizes=(sizes);
+            }
+            img src=(url(config, &image.key))
+                srcset=(srcset(config, image, &variants, original))
+                sizes=(sizes)
+                width=(image.width)
+                height=(image.height)
+                alt=(alt)
+                class=(class)
+                loading="lazy"
+                decoding="async";
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MediaReady {
+    key: String,
+}
+
+/// Tell the page its placeholder for `key` can be swapped for the picture.
+pub fn media_ready(context: &mut Context, key: &str) {
+    context.add_trigger(MEDIA_READY.to_owned(), MediaReady { key: key.to_owned() });
+}
+
+#[cfg(feature = "images")]
+pub use generate::{dominant_color, generate_variants};
+
+#[cfg(feature = "images")]
+mod generate {
+    use std::io::Cursor;
+
+    use image::{imageops::FilterType, DynamicImage, ImageDecoder, ImageError, ImageReader};
+
+    use crate::config::ImageFormat;
+
+    use super::ImageVariant;
+
+    impl From<ImageFormat> for image::ImageFormat {
+        fn from(value: ImageFormat) -> Self {
+            match value {
+                ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+                ImageFormat::Png => image::ImageFormat::Png,
+                ImageFormat::Webp => image::ImageFormat::WebP,
+                ImageFormat::Avif => image::ImageFormat::Avif,
+            }
+        }
+    }
+
+    /// Decode with the EXIF orientation applied, re-encoding drops all other metadata.
+    fn decode(bytes: &[u8]) -> Result<DynamicImage, ImageError> {
+        let mut decoder = ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()?
+            .into_decoder()?;
+
+        let orientation = decoder.orientation()?;
+
+        let mut image: DynamicImage = DynamicImage::from_decoder(decoder)?;
+        image.apply_orientation(orientation);
+
+        Ok(image)
+    }
+
+    pub fn generate_variants(bytes: &[u8], variants: &[ImageVariant]) -> Result<Vec<(ImageVariant, Vec<u8>)>, ImageError> {
+        let image: DynamicImage = decode(bytes)?;
+
+        variants.iter()
+            .map(|variant| {
+                let resized: DynamicImage = match variant.width < image.width() {
+                    true => image.resize(variant.width, u32::MAX, FilterType::Lanczos3),
+                    false => image.clone(),
+                };
+
+                let mut encoded: Vec<u8> = Vec::new();
+                resized.write_to(&mut Cursor::new(&mut encoded), variant.format.into())?;
+
+                Ok((*variant, encoded))
+            })
+            .collect()
+    }
+
+    /// Average color as a css hex value, used as the placeholder.
+    pub fn dominant_color(bytes: &[u8]) -> Result<String, ImageError> {
+        let pixel = decode(bytes)?
+            .resize_exact(1, 1, FilterType::Triangle)
+            .to_rgb8();
+
+        let [r, g, b] = pixel.get_pixel(0, 0).0;
+
+        Ok(format!("#{r:02x}{g:02x}{b:02x}"))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::io::Cursor;
+
+        use image::{DynamicImage, RgbImage};
+
+        use super::decode;
+
+        // APP1 segment with a single IFD entry: orientation (0x0112) 6, turned 90 degrees
+        const EXIF_ROTATE_90: [u8; 36] = [
+            0xff, 0xe1, 0x00, 0x22,
+            b'E', b'x', b'i', b'f', 0x00, 0x00,
+            b'M', b'M', 0x00, 0x2a, 0x00, 0x00, 0x00, 0x08,
+            0x00, 0x01,
+            0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+
+        #[test]
+        fn test_orientation_rotates() {
+            // landscape pixels, EXIF says the camera was turned
+            let mut jpeg: Vec<u8> = Vec::new();
+            DynamicImage::ImageRgb8(RgbImage::new(4, 2))
+                .write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+                .unwrap();
+            jpeg.splice(2..2, EXIF_ROTATE_90);
+
+            let image = decode(&jpeg).unwrap();
+
+            assert_eq!((image.width(), image.height()), (2, 4));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{ImageFormat, Images};
+
+    use super::{img_srcset, ImageVariant, StoredImage};
+
+    fn image(ready: bool) -> StoredImage {
+        StoredImage { key: "uploads/cat.jpg".to_owned(), width: 800, height: 600, placeholder: Some("#aa8866".to_owned()), ready }
+    }
+
+    #[test]
+    fn test_variant_keys() {
+        let variant = ImageVariant { width: 640, format: ImageFormat::Webp };
+
+        assert_eq!(variant.key("uploads/cat.jpg"), "uploads/cat.jpg.640w.webp");
+        assert_eq!(variant.key("uploads/cat.jpg"), variant.key("uploads/cat.jpg"));
+        assert_ne!(variant.key("uploads/cat.jpg"), variant.key("uploads/dog.jpg"));
+    }
+
+    #[test]
+    fn test_variants_skip_upscaling() {
+        let variants = image(true).variants(&Images::default());
+
+        let widths: Vec<u32> = variants.iter().filter(|v| v.format == ImageFormat::Jpeg).map(|v| v.width).collect();
+        assert_eq!(widths, vec![320, 640, 800]);
+        assert_eq!(variants.len(), 6);
+    }
+
+    #[test]
+    fn test_srcset_markup() {
+        let markup = img_srcset(&Images::default(), &image(true), "(max-width: 640px) 100vw, 50vw", "rounded", "A cat", "/cats/1/picture")
+            .into_string();
+
+        assert_eq!(markup, concat!(
+            r#"<picture>"#,
+            r#"<source type="image/webp" srcset="/media/uploads/cat.jpg.320w.webp 320w, /media/uploads/cat.jpg.640w.webp 640w, /media/uploads/cat.jpg.800w.webp 800w" sizes="(max-width: 640px) 100vw, 50vw">"#,
+            r#"<img src="/media/uploads/cat.jpg" srcset="/media/uploads/cat.jpg.320w.jpg 320w, /media/uploads/cat.jpg.640w.jpg 640w, /media/uploads/cat.jpg.800w.jpg 800w" sizes="(max-width: 640px) 100vw, 50vw" width="800" height="600" alt="A cat" class="rounded" loading="lazy" decoding="async">"#,
+            r#"</picture>"#
+        ));
+    }
+
+    #[test]
+    fn test_placeholder_until_ready() {
+        let placeholder = img_srcset(&Images::default(), &image(false), "100vw", "rounded", "A cat", "/cats/1/picture")
+            .into_string();
+
+        assert!(placeholder.starts_with("<div"));
+        assert!(placeholder.contains("background-color:#aa8866"));
+        assert!(placeholder.contains(r#"data-media-key="uploads/cat.jpg""#));
+        assert!(placeholder.contains(r#"hx-trigger="media:ready[detail.key==this.dataset.mediaKey] from:body""#));
+        assert!(placeholder.contains(r#"hx-get="/cats/1/picture""#));
+
+        let ready = img_srcset(&Images::default(), &image(true), "100vw", "rounded", "A cat", "/cats/1/picture")
+            .into_string();
+
+        assert!(ready.starts_with("<picture>"));
+    }
+}
@@ -0,0 +1,1106 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error, fmt::Display,
+    future::Future, pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime}
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::Path, http::Extensions,
+    response::{IntoResponse, Response},
+    routing::{get, post}, Extension, Json, Router
+};
+use hyper::{HeaderMap, StatusCode};
+use maud::{html, Markup};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{sync::watch, task::JoinHandle};
+use uuid::Uuid;
+
+use crate::{
+    clock::{Clock, SharedClock, SystemClock},
+    config::JobQueue,
+    events::HandlerError,
+    morph::morph_id,
+    toggle::{authorized, AdminToken}
+};
+
+/// Dead letters of the queue (GET) and their requeue (POST `/:id/requeue`), admin token only.
+pub const JOBS_ROUTE: &str = "/_blandwork/jobs";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+
+    // out of attempts, kept until requeued
+    Dead,
+}
+
+/// A queued job as stored, see `Jobs::enqueue`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub kind: String,
+    pub payload: Value,
+    pub run_at: SystemTime,
+
+    // runs started so far, the one in progress included
+    pub attempts: u32,
+    pub state: JobState,
+    pub locked_by: Option<String>,
+    pub locked_at: Option<SystemTime>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    Store(String),
+    Payload(serde_json::Error),
+
+    // no `JobHandler` of the kind was registered
+    UnknownKind(String),
+}
+
+impl Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::Store(e) => write!(f, "job store error: {e}"),
+            JobError::Payload(e) => write!(f, "invalid job payload: {e}"),
+            JobError::UnknownKind(kind) => write!(f, "no handler for jobs of kind {kind}"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+/// Storage contract of the queue, shared by every instance of the app when durable.
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    async fn enqueue(&self, job: Job) -> Result<(), JobError>;
+
+    /// Locks up to `limit` jobs for `worker`: queued ones due at `now`, and running ones
+    /// locked before `stale` (their worker is gone). A job is claimed by one worker only.
+    async fn claim(&self, worker: &str, now: SystemTime, stale: SystemTime, limit: usize) -> Result<Vec<Job>, JobError>;
+
+    /// Removes a job `worker` ran. One claimed by another worker since (its lease went stale) is left alone.
+    async fn complete(&self, id: &str, worker: &str) -> Result<(), JobError>;
+
+    /// Unlocks a run `worker` failed, queued again at `retry_at` or dead without one.
+    async fn fail(&self, id: &str, worker: &str, error: &str, retry_at: Option<SystemTime>) -> Result<(), JobError>;
+
+    async fn dead(&self) -> Result<Vec<Job>, JobError>;
+
+    /// Queues a dead job again with its attempts reset, false when there's no such dead job.
+    async fn requeue(&self, id: &str, now: SystemTime) -> Result<bool, JobError>;
+
+    /// Whether the caller runs the recurring job `name` at `tick`: true for one caller per tick.
+    async fn lead(&self, name: &str, tick: SystemTime) -> Result<bool, JobError>;
+}
+
+/// Single instance queue, lost on restart.
+#[derive(Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<BTreeMap<String, Job>>,
+
+    // recurring job -> last tick run
+    ticks: Mutex<HashMap<String, SystemTime>>,
+}
+
+#[async_trait]
+impl JobStore for MemoryJobStore {
+    async fn enqueue(&self, job: Job) -> Result<(), JobError> {
+        self.jobs.lock().unwrap().insert(job.id.clone(), job);
+        Ok(())
+    }
+
+    async fn claim(&self, worker: &str, now: SystemTime, stale: SystemTime, limit: usize) -> Result<Vec<Job>, JobError> {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        let mut due: Vec<&mut Job> = jobs.values_mut()
+            .filter(|job| match job.state {
+                JobState::Queued => job.run_at <= now,
+                JobState::Running => job.locked_at.map(|at| at < stale).unwrap_or(true),
+                JobState::Dead => false,
+            })
+            .collect();
+
+        due.sort_by_key(|job| job.run_at);
+
+        Ok(due.into_iter().take(limit).map(|job| {
+            job.state = JobState::Running;
+            job.locked_by = Some(worker.to_owned());
+            job.locked_at = Some(now);
+            job.attempts += 1;
+            job.clone()
+        }).collect())
+    }
+
+    async fn complete(&self, id: &str, worker: &str) -> Result<(), JobError> {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        if jobs.get(id).is_some_and(|job| job.locked_by.as_deref() == Some(worker)) {
+            jobs.remove(id);
+        }
+
+        Ok(())
+    }
+
+    async fn fail(&self, id: &str, worker: &str, error: &str, retry_at: Option<SystemTime>) -> Result<(), JobError> {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id).filter(|job| job.locked_by.as_deref() == Some(worker)) {
+            job.last_error = Some(error.to_owned());
+            job.locked_by = None;
+            job.locked_at = None;
+
+            match retry_at {
+                Some(at) => {
+                    job.state = JobState::Queued;
+                    job.run_at = at;
+                },
+                None => job.state = JobState::Dead,
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn dead(&self) -> Result<Vec<Job>, JobError> {
+        Ok(self.jobs.lock().unwrap().values().filter(|job| job.state == JobState::Dead).cloned().collect())
+    }
+
+    async fn requeue(&self, id: &str, now: SystemTime) -> Result<bool, JobError> {
+        match self.jobs.lock().unwrap().get_mut(id) {
+            Some(job) if job.state == JobState::Dead => {
+                job.state = JobState::Queued;
+                job.run_at = now;
+                job.attempts = 0;
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    async fn lead(&self, name: &str, tick: SystemTime) -> Result<bool, JobError> {
+        let mut ticks = self.ticks.lock().unwrap();
+
+        match ticks.get(name) {
+            Some(last) if *last >= tick => Ok(false),
+            _ => {
+                ticks.insert(name.to_owned(), tick);
+                Ok(true)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresJobStore;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::time::SystemTime;
+
+    use async_trait::async_trait;
+    use tokio_postgres::Row;
+
+    use crate::db::ConnectionPool;
+
+    use super::{Job, JobError, JobState, JobStore};
+
+    impl From<tokio_postgres::Error> for JobError {
+        fn from(value: tokio_postgres::Error) -> Self {
+            JobError::Store(value.to_string())
+        }
+    }
+
+    fn state(state: JobState) -> &'static str {
+        match state {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Dead => "dead",
+        }
+    }
+
+    const COLUMNS: &str = "id, kind, payload, run_at, attempts, state, locked_by, locked_at, last_error";
+
+    /// Queue shared by every instance, workers claim with `FOR UPDATE SKIP LOCKED`.
+    /// Selected by `server.jobs.backend = "postgres"`, `App::connect` creates the tables.
+    pub struct PostgresJobStore {
+        pool: ConnectionPool
+    }
+
+    impl PostgresJobStore {
+        pub const TABLE: &'static str = "CREATE TABLE IF NOT EXISTS blandwork_jobs (
+            id TEXT PRIMARY KEY,
+            kind TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            run_at TIMESTAMPTZ NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            state TEXT NOT NULL,
+            locked_by TEXT,
+            locked_at TIMESTAMPTZ,
+            last_error TEXT
+        );
+        CREATE INDEX IF NOT EXISTS blandwork_jobs_due ON blandwork_jobs (state, run_at);
+        CREATE TABLE IF NOT EXISTS blandwork_job_ticks (
+            name TEXT PRIMARY KEY,
+            tick TIMESTAMPTZ NOT NULL
+        )";
+
+        pub fn new(pool: ConnectionPool) -> Self {
+            Self { pool }
+        }
+
+        pub async fn migrate(&self) -> Result<(), JobError> {
+            let connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+            connection.batch_execute(Self::TABLE).await?;
+            Ok(())
+        }
+    }
+
+    fn job(row: &Row) -> Result<Job, JobError> {
+        let payload: String = row.get("payload");
+        let state: String = row.get("state");
+        let attempts: i32 = row.get("attempts");
+
+        Ok(Job {
+            id: row.get("id"),
+            kind: row.get("kind"),
+            payload: serde_json::from_str(&payload).map_err(JobError::Payload)?,
+            run_at: row.get("run_at"),
+            attempts: attempts.max(0) as u32,
+            state: match state.as_str() {
+                "queued" => JobState::Queued,
+                "running" => JobState::Running,
+                "dead" => JobState::Dead,
+                other => return Err(JobError::Store(format!("unknown job state {other}"))),
+            },
+            locked_by: row.get("locked_by"),
+            locked_at: row.get("locked_at"),
+            last_error: row.get("last_error"),
+        })
+    }
+
+    #[async_trait]
+    impl JobStore for PostgresJobStore {
+        async fn enqueue(&self, job: Job) -> Result<(), JobError> {
+            let connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+            let payload: String = job.payload.to_string();
+            let attempts: i32 = job.attempts as i32;
+
+            connection.execute(
+                "INSERT INTO blandwork_jobs (id, kind, payload, run_at, attempts, state) VALUES ($1, $2, $3, $4, $5, $6)",
+                &[&job.id, &job.kind, &payload, &job.run_at, &attempts, &state(job.state)]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn claim(&self, worker: &str, now: SystemTime, stale: SystemTime, limit: usize) -> Result<Vec<Job>, JobError> {
+            let connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+            let limit: i64 = limit as i64;
+
+            let rows = connection.query(&format!(
+                "UPDATE blandwork_jobs SET state = 'running', locked_by = $1, locked_at = $2, attempts = attempts + 1
+                WHERE id IN (
+                    SELECT id FROM blandwork_jobs
+                    WHERE (state = 'queued' AND run_at <= $2) OR (state = 'running' AND locked_at < $3)
+                    ORDER BY run_at
+                    LIMIT $4
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING {COLUMNS}"),
+                &[&worker, &now, &stale, &limit]
+            ).await?;
+
+            let mut jobs: Vec<Job> = rows.iter().map(job).collect::<Result<_, _>>()?;
+            jobs.sort_by_key(|job| job.run_at);
+
+            Ok(jobs)
+        }
+
+        async fn complete(&self, id: &str, worker: &str) -> Result<(), JobError> {
+            let connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+            connection.execute("DELETE FROM blandwork_jobs WHERE id = $1 AND locked_by = $2", &[&id, &worker]).await?;
+            Ok(())
+        }
+
+        async fn fail(&self, id: &str, worker: &str, error: &str, retry_at: Option<SystemTime>) -> Result<(), JobError> {
+            let connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+
+            match retry_at {
+                Some(at) => connection.execute(
+                    "UPDATE blandwork_jobs SET state = 'queued', run_at = $3, last_error = $4, locked_by = NULL, locked_at = NULL WHERE id = $1 AND locked_by = $2",
+                    &[&id, &worker, &at, &error]
+                ).await?,
+                None => connection.execute(
+                    "UPDATE blandwork_jobs SET state = 'dead', last_error = $3, locked_by = NULL, locked_at = NULL WHERE id = $1 AND locked_by = $2",
+                    &[&id, &worker, &error]
+                ).await?,
+            };
+
+            Ok(())
+        }
+
+        async fn dead(&self) -> Result<Vec<Job>, JobError> {
+            let connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+
+            let rows = connection.query(&format!("SELECT {COLUMNS} FROM blandwork_jobs WHERE state = 'dead' ORDER BY run_at"), &[]).await?;
+
+            rows.iter().map(job).collect()
+        }
+
+        async fn requeue(&self, id: &str, now: SystemTime) -> Result<bool, JobError> {
+            let connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+
+            let updated: u64 = connection.execute(
+                "UPDATE blandwork_jobs SET state = 'queued', run_at = $2, attempts = 0 WHERE id = $1 AND state = 'dead'",
+                &[&id, &now]
+            ).await?;
+
+            Ok(updated == 1)
+        }
+
+        async fn lead(&self, name: &str, tick: SystemTime) -> Result<bool, JobError> {
+            let mut connection = self.pool.get().await.map_err(|e| JobError::Store(e.to_string()))?;
+            let transaction = connection.transaction().await?;
+
+            // instances racing for the tick wait on nothing, the loser skips it
+            let locked: bool = transaction.query_one("SELECT pg_try_advisory_xact_lock(hashtext($1))", &[&name]).await?.get(0);
+            if !locked {
+                return Ok(false);
+            }
+
+            // an instance arriving after the winner finds the tick taken
+            let recorded: u64 = transaction.execute(
+                "INSERT INTO blandwork_job_ticks (name, tick) VALUES ($1, $2)
+                ON CONFLICT (name) DO UPDATE SET tick = EXCLUDED.tick WHERE blandwork_job_ticks.tick < EXCLUDED.tick",
+                &[&name, &tick]
+            ).await?;
+
+            transaction.commit().await?;
+
+            Ok(recorded == 1)
+        }
+    }
+
+    // against a database: BLANDWORK_TEST_DATABASE_URL=postgresql://... cargo test -- --ignored
+    #[cfg(test)]
+    mod test {
+        use std::{str::FromStr, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+        use bb8::Pool;
+        use bb8_postgres::PostgresConnectionManager;
+        use serde_json::Value;
+        use tokio_postgres::NoTls;
+        use uuid::Uuid;
+
+        use crate::db::ConnectionPool;
+
+        use super::{Job, JobState, JobStore, PostgresJobStore};
+
+        fn start() -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        }
+
+        /// A store in a schema of its own, so tests running at once don't claim each other's jobs.
+        async fn store() -> (PostgresJobStore, ConnectionPool) {
+            let url: String = std::env::var("BLANDWORK_TEST_DATABASE_URL").expect("BLANDWORK_TEST_DATABASE_URL");
+            let schema: String = format!("blandwork_test_{}", Uuid::new_v4().simple());
+
+            let mut config = tokio_postgres::Config::from_str(&url).unwrap();
+            config.options(format!("-c search_path={schema}"));
+
+            let pool: ConnectionPool = Pool::builder().build(PostgresConnectionManager::new(config, NoTls)).await.unwrap();
+            pool.get().await.unwrap().batch_execute(&format!("CREATE SCHEMA {schema}")).await.unwrap();
+
+            let store = PostgresJobStore::new(pool.clone());
+            store.migrate().await.unwrap();
+
+            (store, pool)
+        }
+
+        async fn drop_schema(pool: &ConnectionPool) {
+            let connection = pool.get().await.unwrap();
+            let schema: String = connection.query_one("SELECT current_schema()", &[]).await.unwrap().get(0);
+            connection.batch_execute(&format!("DROP SCHEMA {schema} CASCADE")).await.unwrap();
+        }
+
+        fn queued(id: &str, run_at: SystemTime) -> Job {
+            Job {
+                id: id.to_owned(),
+                kind: "email".to_owned(),
+                payload: Value::from("user@example.com"),
+                run_at,
+                attempts: 0,
+                state: JobState::Queued,
+                locked_by: None,
+                locked_at: None,
+                last_error: None,
+            }
+        }
+
+        #[tokio::test]
+        #[ignore = "needs BLANDWORK_TEST_DATABASE_URL"]
+        async fn test_claim_skips_locked_rows() {
+            let (store, pool) = store().await;
+            let now = start();
+            let stale = now - Duration::from_secs(300);
+
+            store.enqueue(queued("first", now - Duration::from_secs(2))).await.unwrap();
+            store.enqueue(queued("second", now - Duration::from_secs(1))).await.unwrap();
+            store.enqueue(queued("later", now + Duration::from_secs(60))).await.unwrap();
+
+            // another worker's claim still holds the first row
+            let mut connection = pool.get().await.unwrap();
+            let transaction = connection.transaction().await.unwrap();
+            transaction.execute("SELECT id FROM blandwork_jobs WHERE id = 'first' FOR UPDATE", &[]).await.unwrap();
+
+            let claimed = store.claim("a", now, stale, 10).await.unwrap();
+            assert_eq!(claimed.iter().map(|job| job.id.as_str()).collect::<Vec<_>>(), ["second"]);
+            assert_eq!(claimed[0].locked_by.as_deref(), Some("a"));
+            assert_eq!(claimed[0].attempts, 1);
+
+            transaction.rollback().await.unwrap();
+
+            let claimed = store.claim("b", now, stale, 10).await.unwrap();
+            assert_eq!(claimed.iter().map(|job| job.id.as_str()).collect::<Vec<_>>(), ["first"]);
+
+            // running and not stale, nor due
+            assert!(store.claim("c", now, stale, 10).await.unwrap().is_empty());
+
+            drop(connection);
+            drop_schema(&pool).await;
+        }
+
+        #[tokio::test]
+        #[ignore = "needs BLANDWORK_TEST_DATABASE_URL"]
+        async fn test_only_the_locking_worker_settles() {
+            let (store, pool) = store().await;
+            let now = start();
+
+            store.enqueue(queued("report", now)).await.unwrap();
+            store.claim("a", now, now - Duration::from_secs(300), 10).await.unwrap();
+
+            // a's lease went stale and b took the job over
+            let later = now + Duration::from_secs(301);
+            assert_eq!(store.claim("b", later, later - Duration::from_secs(300), 10).await.unwrap().len(), 1);
+
+            store.complete("report", "a").await.unwrap();
+            store.fail("report", "a", "late", None).await.unwrap();
+            assert!(store.dead().await.unwrap().is_empty());
+
+            let connection = pool.get().await.unwrap();
+            let row = connection.query_one("SELECT locked_by, attempts FROM blandwork_jobs WHERE id = 'report'", &[]).await.unwrap();
+            assert_eq!(row.get::<_, Option<String>>(0).as_deref(), Some("b"));
+            assert_eq!(row.get::<_, i32>(1), 2);
+
+            store.complete("report", "b").await.unwrap();
+            assert_eq!(connection.query_one("SELECT count(*) FROM blandwork_jobs", &[]).await.unwrap().get::<_, i64>(0), 0);
+
+            drop(connection);
+            drop_schema(&pool).await;
+        }
+
+        #[tokio::test]
+        #[ignore = "needs BLANDWORK_TEST_DATABASE_URL"]
+        async fn test_dead_and_requeue() {
+            let (store, pool) = store().await;
+            let now = start();
+            let stale = now - Duration::from_secs(300);
+
+            store.enqueue(queued("invoice", now)).await.unwrap();
+
+            store.claim("a", now, stale, 10).await.unwrap();
+            store.fail("invoice", "a", "smtp unreachable", Some(now + Duration::from_secs(10))).await.unwrap();
+
+            // queued again for the retry, unlocked
+            assert!(store.claim("a", now, stale, 10).await.unwrap().is_empty());
+            let retry = now + Duration::from_secs(10);
+            let claimed = store.claim("a", retry, stale, 10).await.unwrap();
+            assert_eq!(claimed.len(), 1);
+            assert_eq!(claimed[0].attempts, 2);
+
+            store.fail("invoice", "a", "smtp unreachable", None).await.unwrap();
+
+            let dead = store.dead().await.unwrap();
+            assert_eq!(dead.len(), 1);
+            assert_eq!(dead[0].state, JobState::Dead);
+            assert_eq!(dead[0].locked_by, None);
+            assert_eq!(dead[0].last_error.as_deref(), Some("smtp unreachable"));
+
+            assert!(store.requeue("invoice", retry).await.unwrap());
+            assert!(!store.requeue("invoice", retry).await.unwrap());
+            assert!(!store.requeue("missing", retry).await.unwrap());
+            assert!(store.dead().await.unwrap().is_empty());
+
+            let claimed = store.claim("a", retry, stale, 10).await.unwrap();
+            assert_eq!(claimed.len(), 1);
+            assert_eq!(claimed[0].attempts, 1);
+
+            drop_schema(&pool).await;
+        }
+
+        #[tokio::test]
+        #[ignore = "needs BLANDWORK_TEST_DATABASE_URL"]
+        async fn test_lead_once_per_tick() {
+            let (store, pool) = store().await;
+            let other = PostgresJobStore::new(pool.clone());
+
+            // advisory locks are database wide, the name is the test's own
+            let name: String = format!("digest-{}", Uuid::new_v4());
+            let tick = start();
+
+            let (first, second) = tokio::join!(store.lead(&name, tick), other.lead(&name, tick));
+            assert!(first.unwrap() ^ second.unwrap());
+
+            assert!(!store.lead(&name, tick).await.unwrap());
+            assert!(!store.lead(&name, tick - Duration::from_secs(60)).await.unwrap());
+
+            // an instance holding the lock makes the others skip the tick
+            let next = tick + Duration::from_secs(60);
+            let mut connection = pool.get().await.unwrap();
+            let transaction = connection.transaction().await.unwrap();
+            transaction.execute("SELECT pg_advisory_xact_lock(hashtext($1))", &[&name]).await.unwrap();
+
+            assert!(!store.lead(&name, next).await.unwrap());
+
+            transaction.rollback().await.unwrap();
+            assert!(store.lead(&name, next).await.unwrap());
+
+            drop(connection);
+            drop_schema(&pool).await;
+        }
+    }
+}
+
+/// Attempts of a kind of job, failed runs wait `backoff` doubled per attempt (capped at
+/// `max_backoff`) before the next one. Out of attempts the job is dead, see `Jobs::dead_letters`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 5, backoff: Duration::from_secs(10), max_backoff: Duration::from_secs(3600) }
+    }
+}
+
+impl RetryPolicy {
+    /// A single attempt, failures are dead letters right away.
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    /// Wait before the run following failed attempt `attempt` (1 based), None when out of attempts.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        let factor: u32 = 2u32.saturating_pow(attempt.saturating_sub(1));
+
+        Some(self.backoff.saturating_mul(factor).min(self.max_backoff))
+    }
+}
+
+/// What a job handler gets to work with. `extensions` holds the pool of a connected app.
+pub struct QueuedJob {
+    pub id: String,
+    pub kind: String,
+    pub payload: Value,
+    pub attempt: u32,
+    pub extensions: Extensions,
+}
+
+impl QueuedJob {
+    pub fn payload<P: DeserializeOwned>(&self) -> Result<P, serde_json::Error> {
+        serde_json::from_value(self.payload.clone())
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl QueuedJob {
+    pub fn pool(&self) -> Option<&crate::db::ConnectionPool> {
+        self.extensions.get::<crate::db::ConnectionPool>()
+    }
+}
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+type HandlerCallback = Arc<dyn Fn(QueuedJob) -> HandlerFuture + Send + Sync>;
+
+/// Runs the jobs of one kind, contributed by `Feature::jobs`.
+#[derive(Clone)]
+pub struct JobHandler {
+    pub kind: String,
+    pub retry: RetryPolicy,
+    callback: HandlerCallback,
+}
+
+impl JobHandler {
+    pub fn new<F, Fut>(kind: &str, callback: F) -> Self
+    where
+        F: Fn(QueuedJob) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        Self {
+            kind: kind.to_owned(),
+            retry: RetryPolicy::default(),
+            callback: Arc::new(move |job: QueuedJob| -> HandlerFuture { Box::pin(callback(job)) }),
+        }
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+}
+
+/// Background work queued by handlers and run by the workers `App::run` starts
+/// (emails, exports, webhooks). In memory by default, `server.jobs.backend = "postgres"`
+/// keeps the queue across restarts and shares it between instances.
+///
+/// ```ignore
+/// async fn signup(Extension(jobs): Extension<Jobs>, Form(form): Form<Signup>) -> Result<Markup, StatusCode> {
+///     jobs.enqueue("welcome_email", &form.email).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+///     Ok(html! { "Welcome!" })
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Jobs {
+    store: Arc<dyn JobStore>,
+    clock: SharedClock,
+
+    // kind -> handler, registered by `build`
+    handlers: Arc<RwLock<BTreeMap<String, JobHandler>>>,
+}
+
+impl Jobs {
+    pub fn new(store: impl JobStore + 'static) -> Self {
+        Self { store: Arc::new(store), clock: Arc::new(SystemClock), handlers: Arc::default() }
+    }
+
+    pub fn memory() -> Self {
+        Self::new(MemoryJobStore::default())
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub(crate) fn shared_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Same handlers on another store, the backend `server.jobs` selects.
+    pub fn with_store(mut self, store: impl JobStore + 'static) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    pub fn store(&self) -> Arc<dyn JobStore> {
+        self.store.clone()
+    }
+
+    /// The first handler of a kind is kept.
+    pub fn register(&self, handler: JobHandler) {
+        let mut handlers = self.handlers.write().unwrap();
+
+        if handlers.contains_key(&handler.kind) {
+            tracing::warn!("jobs of kind {} already have a handler, ignored", handler.kind);
+            return;
+        }
+
+        handlers.insert(handler.kind.clone(), handler);
+    }
+
+    /// Run as soon as a worker is free, returns the job's id.
+    pub async fn enqueue<P: Serialize + ?Sized>(&self, kind: &str, payload: &P) -> Result<String, JobError> {
+        self.enqueue_at(kind, payload, self.clock.now()).await
+    }
+
+    pub async fn enqueue_at<P: Serialize + ?Sized>(&self, kind: &str, payload: &P, run_at: SystemTime) -> Result<String, JobError> {
+        if !self.handlers.read().unwrap().contains_key(kind) {
+            return Err(JobError::UnknownKind(kind.to_owned()));
+        }
+
+        let job: Job = Job {
+            id: Uuid::new_v4().to_string(),
+            kind: kind.to_owned(),
+            payload: serde_json::to_value(payload).map_err(JobError::Payload)?,
+            run_at,
+            attempts: 0,
+            state: JobState::Queued,
+            locked_by: None,
+            locked_at: None,
+            last_error: None,
+        };
+
+        let id: String = job.id.clone();
+        self.store.enqueue(job).await?;
+
+        Ok(id)
+    }
+
+    /// Jobs out of attempts, oldest first.
+    pub async fn dead_letters(&self) -> Result<Vec<Job>, JobError> {
+        self.store.dead().await
+    }
+
+    pub async fn requeue(&self, id: &str) -> Result<bool, JobError> {
+        self.store.requeue(id, self.clock.now()).await
+    }
+
+    /// Claims the due jobs for `worker` and runs them one after the other, returns how many ran.
+    pub async fn run_due(&self, worker: &str, queue: &JobQueue, extensions: &Extensions) -> Result<usize, JobError> {
+        let now: SystemTime = self.clock.now();
+        let stale: SystemTime = now.checked_sub(queue.lease).unwrap_or(now);
+
+        let claimed: Vec<Job> = self.store.claim(worker, now, stale, queue.batch).await?;
+        let count: usize = claimed.len();
+
+        for job in claimed {
+            let handler: Option<JobHandler> = self.handlers.read().unwrap().get(&job.kind).cloned();
+
+            let Some(handler) = handler else {
+                tracing::error!(job = %job.id, "no handler for jobs of kind {}, dead", job.kind);
+                self.store.fail(&job.id, worker, &format!("no handler for jobs of kind {}", job.kind), None).await?;
+                continue;
+            };
+
+            let queued: QueuedJob = QueuedJob {
+                id: job.id.clone(),
+                kind: job.kind.clone(),
+                payload: job.payload,
+                attempt: job.attempts,
+                extensions: extensions.clone(),
+            };
+
+            match (handler.callback)(queued).await {
+                Ok(()) => self.store.complete(&job.id, worker).await?,
+                Err(e) => {
+                    let retry_at: Option<SystemTime> = handler.retry.delay(job.attempts).map(|delay| self.clock.now() + delay);
+
+                    match retry_at {
+                        Some(_) => tracing::warn!(job = %job.id, kind = %job.kind, attempt = job.attempts, "job failed, retried: {e}"),
+                        None => tracing::error!(job = %job.id, kind = %job.kind, attempt = job.attempts, "job failed, dead: {e}"),
+                    }
+
+                    self.store.fail(&job.id, worker, &e.to_string(), retry_at).await?;
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Polls the queue every `server.jobs.poll` until `JobWorker::shutdown`.
+    pub fn start(&self, queue: &JobQueue, extensions: Extensions) -> JobWorker {
+        let (shutdown, mut receiver) = watch::channel(false);
+        let worker: String = Uuid::new_v4().to_string();
+        let jobs: Jobs = self.clone();
+        let queue: JobQueue = queue.clone();
+
+        let task: JoinHandle<()> = tokio::spawn(async move {
+            loop {
+                let ran: usize = jobs.run_due(&worker, &queue, &extensions).await.unwrap_or_else(|e| {
+                    tracing::error!(worker = %worker, "unable to run queued jobs: {e}");
+                    0
+                });
+
+                // a full batch suggests more are due
+                if ran < queue.batch {
+                    tokio::select! {
+                        _ = jobs.clock.sleep(queue.poll) => {},
+                        _ = receiver.changed() => return,
+                    }
+                }
+
+                if *receiver.borrow() {
+                    return;
+                }
+            }
+        });
+
+        JobWorker { shutdown, task }
+    }
+}
+
+/// The polling task of `Jobs::start`.
+pub struct JobWorker {
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl JobWorker {
+    /// Stop polling and wait for the jobs in progress.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        let _ = self.task.await;
+    }
+}
+
+/// Dead letters for an admin dashboard, each with a button posting to `{requeue}/{id}`
+/// (an app route calling `Jobs::requeue`).
+pub fn dead_letters_panel(jobs: &[Job], requeue: &str) -> Markup {
+    html!{
+        section .dead-letters {
+            @if jobs.is_empty() {
+                p { "No failed jobs." }
+            } @else {
+                table {
+                    thead { tr { th { "Kind" } th { "Attempts" } th { "Error" } th {} } }
+                    tbody {
+                        @for job in jobs {
+                            tr id=(morph_id("job", &job.id)) {
+                                td { (job.kind) }
+                                td { (job.attempts) }
+                                td { (job.last_error.as_deref().unwrap_or_default()) }
+                                td {
+                                    button hx-post=(format!("{requeue}/{}", job.id)) hx-target="closest tr" hx-swap="outerHTML" hx-sync="this:drop" { "Requeue" }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Jobs {
+    /// The `JOBS_ROUTE` admin endpoint, `token` is compared in constant time.
+    pub fn router(&self, token: &str) -> Router {
+        let token: Arc<str> = Arc::from(token);
+
+        Router::new()
+            .route(JOBS_ROUTE, get(dead_letters))
+            .route(&format!("{JOBS_ROUTE}/:id/requeue"), post(requeue))
+            .layer(Extension(self.clone()))
+            .layer(Extension(AdminToken(token)))
+    }
+}
+
+async fn dead_letters(headers: HeaderMap, Extension(token): Extension<AdminToken>, Extension(jobs): Extension<Jobs>) -> Response {
+    if !authorized(&headers, &token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match jobs.dead_letters().await {
+        Ok(dead) => Json(dead).into_response(),
+        Err(e) => {
+            tracing::error!("unable to list dead jobs: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn requeue(
+    headers: HeaderMap,
+    Extension(token): Extension<AdminToken>,
+    Extension(jobs): Extension<Jobs>,
+    Path(id): Path<String>
+) -> Response {
+    if !authorized(&headers, &token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match jobs.requeue(&id).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!("unable to requeue job {id}: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeSet,
+        sync::{atomic::{AtomicUsize, Ordering}, Arc},
+        time::{Duration, SystemTime, UNIX_EPOCH}
+    };
+
+    use axum::http::Extensions;
+
+    use crate::{clock::{Clock, TestClock}, config::JobQueue, events::{EventBus, HandlerError}, schedule::{ScheduledJob, Scheduler, UtcOffset}};
+
+    use super::{JobError, JobHandler, JobState, JobStore, Jobs, MemoryJobStore, RetryPolicy};
+
+    fn start() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    fn queue() -> JobQueue {
+        JobQueue { batch: 10, lease: Duration::from_secs(300), ..JobQueue::default() }
+    }
+
+    fn failing(kind: &str, retry: RetryPolicy, runs: Arc<AtomicUsize>) -> JobHandler {
+        JobHandler::new(kind, move |_| {
+            let runs = runs.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Err::<(), HandlerError>("smtp unreachable".into())
+            }
+        }).retry(retry)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy { max_attempts: 4, backoff: Duration::from_secs(10), max_backoff: Duration::from_secs(30) };
+
+        assert_eq!(policy.delay(1), Some(Duration::from_secs(10)));
+        assert_eq!(policy.delay(2), Some(Duration::from_secs(20)));
+        assert_eq!(policy.delay(3), Some(Duration::from_secs(30)));
+        assert_eq!(policy.delay(4), None);
+        assert_eq!(RetryPolicy::none().delay(1), None);
+    }
+
+    #[tokio::test]
+    async fn test_claim_contention() {
+        let clock = TestClock::at(start());
+        let jobs = Jobs::memory().with_clock(clock.clone());
+        jobs.register(JobHandler::new("export", |_| async { Ok(()) }));
+
+        for n in 0..10 {
+            jobs.enqueue("export", &n).await.unwrap();
+        }
+
+        let store = jobs.store();
+        let now = clock.now();
+        let stale = now - Duration::from_secs(300);
+
+        // two workers polling at once never share a job
+        let (first, second) = tokio::join!(store.claim("a", now, stale, 6), store.claim("b", now, stale, 6));
+        let (first, second) = (first.unwrap(), second.unwrap());
+
+        assert_eq!(first.len() + second.len(), 10);
+
+        let ids: BTreeSet<String> = first.iter().chain(second.iter()).map(|job| job.id.clone()).collect();
+        assert_eq!(ids.len(), 10);
+        assert!(first.iter().all(|job| job.locked_by.as_deref() == Some("a") && job.attempts == 1));
+
+        assert!(store.claim("c", now, stale, 10).await.unwrap().is_empty());
+
+        // the lock of a vanished worker expires
+        clock.advance(Duration::from_secs(301));
+        let now = clock.now();
+        let reclaimed = store.claim("c", now, now - Duration::from_secs(300), 10).await.unwrap();
+
+        assert_eq!(reclaimed.len(), 10);
+        assert!(reclaimed.iter().all(|job| job.attempts == 2));
+
+        // the vanished worker finishing late leaves the new claim alone
+        store.complete(&first[0].id, "a").await.unwrap();
+        store.fail(&first[1].id, "a", "late", None).await.unwrap();
+        assert!(store.dead().await.unwrap().is_empty());
+
+        clock.advance(Duration::from_secs(301));
+        let now = clock.now();
+        assert_eq!(store.claim("d", now, now - Duration::from_secs(300), 10).await.unwrap().len(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_retry_backoff_dead_letter_requeue() {
+        let clock = TestClock::at(start());
+        let jobs = Jobs::memory().with_clock(clock.clone());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let retry = RetryPolicy { max_attempts: 3, backoff: Duration::from_secs(10), max_backoff: Duration::from_secs(3600) };
+        jobs.register(failing("email", retry, runs.clone()));
+
+        assert!(matches!(jobs.enqueue("sms", "hello").await, Err(JobError::UnknownKind(_))));
+        let id = jobs.enqueue("email", "user@example.com").await.unwrap();
+
+        let extensions = Extensions::new();
+        assert_eq!(jobs.run_due("w", &queue(), &extensions).await.unwrap(), 1);
+
+        // waiting 10s after the first failure, then 20s
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(jobs.run_due("w", &queue(), &extensions).await.unwrap(), 0);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(jobs.run_due("w", &queue(), &extensions).await.unwrap(), 1);
+
+        clock.advance(Duration::from_secs(19));
+        assert_eq!(jobs.run_due("w", &queue(), &extensions).await.unwrap(), 0);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(jobs.run_due("w", &queue(), &extensions).await.unwrap(), 1);
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        // out of attempts
+        let dead = jobs.dead_letters().await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].id, id);
+        assert_eq!(dead[0].state, JobState::Dead);
+        assert_eq!(dead[0].attempts, 3);
+        assert_eq!(dead[0].last_error.as_deref(), Some("smtp unreachable"));
+
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(jobs.run_due("w", &queue(), &extensions).await.unwrap(), 0);
+
+        // requeued with fresh attempts
+        assert!(jobs.requeue(&id).await.unwrap());
+        assert!(!jobs.requeue(&id).await.unwrap());
+        assert!(jobs.dead_letters().await.unwrap().is_empty());
+
+        assert_eq!(jobs.run_due("w", &queue(), &extensions).await.unwrap(), 1);
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_recurring_job_runs_once_per_tick() {
+        let clock = TestClock::at(UNIX_EPOCH + Duration::from_secs(1_700_000_000 / 60 * 60 + 30));
+        let store: Arc<dyn JobStore> = Arc::new(MemoryJobStore::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        // two instances of the app sharing the queue
+        let schedules = (0..2).map(|_| {
+            let runs = runs.clone();
+            let job = ScheduledJob::new("digest", "* * * * *", move |_| {
+                let runs = runs.clone();
+                async move {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            });
+
+            Scheduler::new(vec![job], Arc::new(clock.clone()), UtcOffset(0), EventBus::new())
+                .leadership(store.clone())
+                .start()
+        }).collect::<Vec<_>>();
+
+        for _ in 0..100 {
+            if clock.pending() == 2 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        clock.advance(Duration::from_secs(30));
+
+        for _ in 0..100 {
+            if clock.pending() == 2 && runs.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        for schedule in schedules {
+            schedule.shutdown().await;
+        }
+    }
+}
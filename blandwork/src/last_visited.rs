@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+use crate::TypedCookie;
+
+// remembered links, the least recently visited are forgotten beyond it
+const MAX_REMEMBERED: usize = 16;
+
+// longer urls (huge filters) are not remembered, the cookie has to stay small
+const MAX_URL_LENGTH: usize = 512;
+
+/// The page last visited below each top-level link with `Link::remember`, by the link's
+/// route, recorded by `ContextLayer` for full page and boosted GETs of `web()` routes.
+/// The navigator's links lead back there, see `Context::remembered_url`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LastVisited {
+    // (link route, url with query), most recent last
+    pub routes: Vec<(String, String)>,
+}
+
+impl TypedCookie for LastVisited {
+    const NAME: &'static str = "blandwork_last";
+}
+
+impl LastVisited {
+    pub fn get(&self, route: &str) -> Option<&str> {
+        self.routes.iter()
+            .find(|(link, _)| link == route)
+            .map(|(_, url)| url.as_str())
+    }
+
+    /// Remember `url` for the link of `route`, false when nothing changed.
+    pub fn record(&mut self, route: &str, url: &str) -> bool {
+        if url.len() > MAX_URL_LENGTH || self.routes.last().is_some_and(|last| last.0 == route && last.1 == url) {
+            return false;
+        }
+
+        self.routes.retain(|(link, _)| link != route);
+        self.routes.push((route.to_owned(), url.to_owned()));
+
+        if self.routes.len() > MAX_REMEMBERED {
+            self.routes.drain(..self.routes.len() - MAX_REMEMBERED);
+        }
+
+        true
+    }
+}
+
+/// `path` is `route` or below it.
+pub(crate) fn covers(route: &str, path: &str) -> bool {
+    let route: &str = route.trim_end_matches('/');
+
+    match route.is_empty() {
+        true => path == "/",
+        false => path == route || path.starts_with(&format!("{route}/")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Router};
+    use axum_htmx::{HX_BOOSTED, HX_REQUEST};
+    use hyper::header::{COOKIE, SET_COOKIE};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{feature::Link, test::router, App, Config, Context, Feature, Template};
+
+    #[derive(Clone, Default)]
+    struct TestTemplate {
+        links: Vec<Link>,
+    }
+
+    impl Template for TestTemplate {
+        fn register(&mut self, feature: &Box<dyn Feature>) {
+            self.links.extend(feature.link());
+        }
+
+        fn page(&self, context: &Context, body: Markup) -> Markup {
+            html! {
+                html { body {
+                    @for link in self.links.iter() { (link.render(context)) }
+                    (body)
+                } }
+            }
+        }
+    }
+
+    struct Invoices {
+        route: &'static str,
+        remember: bool,
+    }
+
+    impl Feature for Invoices {
+        fn name(&self) -> String {
+            "Invoices".to_owned()
+        }
+
+        fn link(&self) -> Option<Link> {
+            Some(Link {
+                active: false,
+                title: "Invoices".to_owned(),
+                label: "I".to_owned(),
+                route: self.route.to_owned(),
+                icon: None,
+                css: None,
+                remember: self.remember,
+            })
+        }
+
+        fn web(&self) -> Option<Router> {
+            let list = format!("{}/list", self.route);
+
+            Some(Router::new()
+                .route(self.route, get(|| async { html! { "invoices" } }))
+                .route(&list, get(|| async { html! { "the list" } })))
+        }
+
+        fn supplemental(&self) -> Option<Router> {
+            Some(Router::new().route(&format!("{}/rows", self.route), get(|| async { html! { "rows" } })))
+        }
+    }
+
+    fn app(route: &'static str, remember: bool) -> Router {
+        let mut app = App::new(Config::default(), TestTemplate::default()).register_feature(Invoices { route, remember });
+        router(&app.build())
+    }
+
+    async fn send(router: &Router, request: Request) -> (Option<String>, String) {
+        let response = router.clone().oneshot(request).await.unwrap();
+
+        let cookie = response.headers().get_all(SET_COOKIE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .find(|value| value.starts_with("blandwork_last="))
+            .map(|value| value.split(';').next().unwrap().to_owned());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (cookie, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_capture_and_rewrite() {
+        let router = app("/invoices", true);
+
+        // the filtered page, through boosted navigation
+        let request = Request::get("/invoices/list?status=open&page=3")
+            .header(HX_REQUEST, "true")
+            .header(HX_BOOSTED, "true")
+            .body(Body::empty()).unwrap();
+        let (cookie, _) = send(&router, request).await;
+        let cookie = cookie.unwrap();
+
+        // fragments are not pages to return to
+        let request = Request::get("/invoices/rows?page=4").header(HX_REQUEST, "true").header(COOKIE, &cookie).body(Body::empty()).unwrap();
+        assert_eq!(send(&router, request).await.0, None);
+
+        let (_, html) = send(&router, Request::get("/invoices").header(COOKIE, &cookie).body(Body::empty()).unwrap()).await;
+        assert!(html.contains(r#"<a href="/invoices/list?status=open&amp;page=3" data-canonical="/invoices""#));
+    }
+
+    #[tokio::test]
+    async fn test_route_rename_falls_back() {
+        let (cookie, _) = send(&app("/invoices", true), Request::get("/invoices/list?page=2").body(Body::empty()).unwrap()).await;
+
+        // the feature moved to /bills since
+        let (_, html) = send(&app("/bills", true), Request::get("/bills").header(COOKIE, cookie.unwrap()).body(Body::empty()).unwrap()).await;
+        assert!(html.contains(r#"<a href="/bills" hx-target"#));
+        assert!(!html.contains("data-canonical"));
+    }
+
+    #[tokio::test]
+    async fn test_opt_in() {
+        let (cookie, html) = send(&app("/invoices", false), Request::get("/invoices/list?page=2").body(Body::empty()).unwrap()).await;
+
+        assert_eq!(cookie, None);
+        assert!(html.contains(r#"<a href="/invoices" hx-target"#));
+    }
+}
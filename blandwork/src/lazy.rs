@@ -0,0 +1,193 @@
+use std::{
+    convert::Infallible,
+    sync::{Arc, OnceLock},
+    task::{Context as TaskContext, Poll}
+};
+
+use axum::{extract::Request, response::Response, Router};
+use tower::{util::Oneshot, Service, ServiceExt};
+
+type Builder = Arc<dyn Fn() -> Router + Send + Sync>;
+
+/// Web routes under `prefix` whose router is only built by the first request reaching them,
+/// see `Feature::lazy`. Large features then cost nothing at startup:
+///
+/// ```ignore
+/// fn lazy(&self) -> Option<LazyRouter> {
+///     Some(LazyRouter::new("/reports", || Router::new()
+///         .route("/", get(index))
+///         .route("/:id", get(report))))
+/// }
+/// ```
+///
+/// Routes are relative to `prefix` (`/:id` answers `/reports/42`), the template, context
+/// and feature layers are applied as for `Feature::web`.
+#[derive(Clone)]
+pub struct LazyRouter {
+    prefix: String,
+    build: Builder,
+    router: Arc<OnceLock<Router>>,
+}
+
+impl LazyRouter {
+    pub fn new(prefix: &str, build: impl Fn() -> Router + Send + Sync + 'static) -> Self {
+        assert!(prefix.starts_with('/') && prefix.len() > 1, "lazy routers are nested under a prefix, not the root: {prefix}");
+
+        Self {
+            prefix: prefix.trim_end_matches('/').to_owned(),
+            build: Arc::new(build),
+            router: Arc::new(OnceLock::new()),
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Whether a request has built the router yet.
+    pub fn is_built(&self) -> bool {
+        self.router.get().is_some()
+    }
+
+    fn router(&self) -> &Router {
+        self.router.get_or_init(|| {
+            tracing::debug!("building the lazy router of {}", self.prefix);
+            (self.build)()
+        })
+    }
+}
+
+impl Service<Request> for LazyRouter {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Oneshot<Router, Request>;
+
+    fn poll_ready(&mut self, _: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.router().clone().oneshot(request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::{Duration, Instant}};
+
+    use axum::{body::{to_bytes, Body}, extract::{Path, Request}, routing::get, Router};
+    use hyper::StatusCode;
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{test::router, App, Config, Context, Feature, Template};
+
+    use super::LazyRouter;
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Reports {
+        lazy: LazyRouter,
+    }
+
+    impl Feature for Reports {
+        fn lazy(&self) -> Option<LazyRouter> {
+            Some(self.lazy.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_built_on_first_request() {
+        let builds = Arc::new(AtomicUsize::new(0));
+
+        let counter = builds.clone();
+        let lazy = LazyRouter::new("/reports", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Router::new().route("/:id", get(|Path(id): Path<u32>| async move { html! { p { "report " (id) } } }))
+        });
+
+        let app = router(&App::new(Config::default(), TestTemplate).register_feature(Reports { lazy: lazy.clone() }).build());
+        assert!(!lazy.is_built());
+
+        for _ in 0..2 {
+            let response = app.clone().oneshot(Request::get("/reports/42").body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(String::from_utf8(body.to_vec()).unwrap().contains("<body><p>report 42</p></body>"));
+        }
+
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+    }
+
+    // a feature whose routes take `BUILD` to set up, eagerly or lazily
+    const BUILD: Duration = Duration::from_millis(5);
+
+    fn slow_router(builds: &Arc<AtomicUsize>) -> Router {
+        builds.fetch_add(1, Ordering::SeqCst);
+        std::thread::sleep(BUILD);
+        Router::new().route("/", get(|| async { html! { p { "report" } } }))
+    }
+
+    struct Section {
+        index: usize,
+        lazy: bool,
+        builds: Arc<AtomicUsize>,
+    }
+
+    impl Feature for Section {
+        fn name(&self) -> String {
+            format!("section-{}", self.index)
+        }
+
+        fn web(&self) -> Option<Router> {
+            (!self.lazy).then(|| Router::new().nest(&format!("/section-{}", self.index), slow_router(&self.builds)))
+        }
+
+        fn lazy(&self) -> Option<LazyRouter> {
+            let builds = self.builds.clone();
+            self.lazy.then(|| LazyRouter::new(&format!("/section-{}", self.index), move || slow_router(&builds)))
+        }
+    }
+
+    /// How long `build` takes with 50 sections and how many routers it built.
+    fn startup(lazy: bool) -> (Duration, usize) {
+        let builds = Arc::new(AtomicUsize::new(0));
+        let mut app = App::new(Config::default(), TestTemplate).register_feature(Section { index: 0, lazy, builds: builds.clone() });
+
+        for index in 1..50 {
+            app = app.register_feature(Section { index, lazy, builds: builds.clone() });
+        }
+
+        let start = Instant::now();
+        let _ = app.build();
+
+        (start.elapsed(), builds.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_startup_with_50_features() {
+        let (eager, eager_builds) = startup(false);
+        let (lazy, lazy_builds) = startup(true);
+
+        // each eager router is built once, by `build`, the lazy ones by their first request
+        assert_eq!(eager_builds, 50);
+        assert_eq!(lazy_builds, 0);
+
+        assert!(eager >= BUILD * 50);
+        assert!(lazy + BUILD * 40 < eager, "eager {eager:?}, lazy {lazy:?}");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_root_prefix() {
+        LazyRouter::new("/", Router::new);
+    }
+}
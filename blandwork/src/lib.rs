@@ -1,16 +1,185 @@
 mod config;
 mod app;
 mod feature;
+#[cfg(feature = "postgres")]
 mod db;
 mod context;
 mod template;
+#[cfg(feature = "sessions")]
 mod session;
+mod livereload;
+mod audit;
+mod clock;
+mod assets;
+mod drafts;
+mod methods;
+mod cache;
+mod images;
+mod logging;
+mod limits;
+mod cookies;
+mod botguard;
+mod slash;
+mod toggle;
+mod socket;
+mod events;
+mod stream;
+mod memo;
+mod a11y;
+mod guard;
+mod cdn;
+mod access_log;
+mod panic;
+mod onboarding;
+mod schedule;
+mod edit;
+mod coalesce;
+mod concurrency;
+mod content;
+mod hx_check;
+mod experiment;
+mod offline;
+mod forms;
+mod morph;
+mod template_usage;
+mod list;
+mod skeleton;
+mod quota;
+mod locale;
+mod recording;
+mod timeouts;
+mod theme;
+mod takeout;
+mod lazy;
+mod operations;
+mod jobs;
+mod redirect;
+mod group;
+mod finite;
+mod embedded;
+mod widget;
+mod nav_tree;
+mod submenu;
+mod last_visited;
+mod search;
+mod reload;
+mod cors;
+mod optimistic;
+mod feature_config;
+mod head_assets;
+mod diagnostics;
+mod registry;
+mod listen;
+#[cfg(feature = "webauthn")]
+mod webauthn;
 
-pub use config::Config;
-pub use db::{Connection, ConnectionPool};
+pub mod units;
+pub mod test;
+pub mod transform;
+
+pub use config::{AccessLogFormat, AssetConflicts, Config, ConfigReload, ContentMount, Shells, JobBackend, JobQueue, Quotas, Recording, RouteTimeout, Environment, ImageFormat, NonFinite, TrailingSlash, TriggerData, WidgetEmbed, Widgets};
+pub use onboarding::{
+    onboarding_checklist, Checklist, ChecklistEntry, ChecklistFeature, MemoryOnboardingStore, Onboarding, OnboardingError, 
+    OnboardingItem, OnboardingItems, OnboardingPrefs, OnboardingScope, OnboardingStore, OnboardingUpdated, ONBOARDING_ROUTE, ONBOARDING_UPDATED
+};
+#[cfg(feature = "postgres")]
+pub use onboarding::PostgresOnboardingStore;
+pub use experiment::{
+    conversions_panel, Conversion, ConversionStore, Conversions, ExperimentAssignments,
+    ExperimentError, MemoryConversionStore, VariantConversions, CONVERSION
+};
+#[cfg(feature = "postgres")]
+pub use experiment::PostgresConversionStore;
+pub use forms::{FieldError, FormErrors, FORM_ERROR};
+pub use morph::{check_ids, morph_id, MorphCheckLayer, MorphWarning, MORPH_SCRIPT_ROUTE};
+pub use offline::{IdempotencyLayer, IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED, REPLAYED};
+pub use quota::{
+    quota_exceeded, usage_panel, FileStorage, MemoryUsageStore, QuotaLevel, QuotaLimits, QuotaProvider, QuotaScope,
+    StorageError, Usage, UsageStore, QUOTA_EXCEEDED
+};
+#[cfg(feature = "postgres")]
+pub use quota::PostgresUsageStore;
+pub use timeouts::{Deadline, RouteTimeoutLayer};
+pub use recording::{FileSink, MemoryRecorder, RecordSink, RecordingLayer, RequestRecord, RECORD_HEADER};
+pub use locale::{LocalizedRoute, LocalizedRoutes, LOCALE_COOKIE};
+pub use list::{empty_state, render_list, EmptyState, ListQuery, Page, EMPTY_STATE_CLASS, EMPTY_STATE_TEMPLATE, HIGHLIGHT_CLASS, HIGHLIGHT_PARAM};
+pub use skeleton::{lazy_region, skeleton, LazyRegion, SkeletonShape, LAZY_REGION_CLASS, SKELETON_CLASS};
+pub use template_usage::{blocks, references, DynamicInclude, Reference, TemplateAudit, TemplateReport, TemplateUsage, TemplateUse};
+pub use hx_check::{HxCheck, HxCheckError, HxFinding, HxReport, TemplateSource, Violation};
+pub use coalesce::{CoalesceKey, RenderCoalescer};
+pub use concurrency::{CriticalSections, SectionError, Serialized, SEQUENCE};
+pub use content::ContentOverlay;
+pub use edit::{EditError, EditInPlace, FieldUpdated, FieldValue};
+pub use schedule::{Cron, CronError, JobContext, Schedule, ScheduledJob, Scheduler, UtcOffset};
+pub use access_log::{AccessEntry, AccessLogLayer, REQUEST_ID};
+pub use cors::AppCorsLayer;
+pub use diagnostics::{Diagnostic, DiagnosticReport, Stage, Suggestion};
+pub use head_assets::{AssetError, AssetKind, HeadAsset, HeadAssets, Placement, ASSET_ATTRIBUTE};
+pub use feature_config::{ConfigProblem, ConfigSchema, FeatureConfig, FeatureConfigError, FeatureSettings, Invalid, Validate};
+pub use optimistic::{optimistic_delete_attrs, ActionFailure, OptimisticDelete, SoftDeleted, ACTION_FAILED, OPTIMISTIC_HEADER, SOFT_DELETED};
+pub use panic::{CatchPanicLayer, ErrorReporter, PanicReport};
+pub use a11y::{live_region, Announcement, Politeness, ANNOUNCE, ASSERTIVE_REGION_ID, FOCUS, LIVE_REGION_ID};
+pub use assets::AssetManifest;
+pub use botguard::{bot_guard, BotGuard, BotRejected, BOT_REJECTED, GUARD_FIELD, POW_FIELD};
+pub use cache::{CacheSegment, CacheTags, ResponseCache, ResponseCacheLayer};
+pub use cdn::{CdnLayer, CdnPolicy, CdnPurger, HttpPurger, NoopPurger, PurgeError, RouteClass, SurrogateKeys};
+#[cfg(feature = "postgres")]
+pub use db::{ConnectError, Connection, ConnectionPool, Db, DbError, QueryCache, QueryLogger, QueryStats, QueryTotals, QueryWarning};
+pub use guard::HtmxOnlyLayer;
 pub use feature::{Component, Feature, Link, FeatureError};
-pub use context::{Context, ContextAccessor};
-pub use app::App;
+pub use context::{Context, ContextAccessor, CurrentUser, DetachedContext, Event, Timing};
+pub use cookies::{CookieError, SameSite, TypedCookie};
+pub use app::{App, Features, NoPool};
+pub use logging::{feature_target, FeatureSpanLayer, LogLevelError, LogLevels};
+pub use clock::{relative_time, Clock, SharedClock, SystemClock, TestClock};
+pub use events::{AppEvent, CoalesceCounts, Coalescing, DeadLetter, EventBus, HandlerError, Merge, Published, Topic, EVENTS_ROUTE};
+pub use drafts::{autosave_attrs, draft_owner, Autosave, Draft, DraftError, DraftStore, Drafts, MemoryDraftStore};
+#[cfg(feature = "postgres")]
+pub use drafts::PostgresDraftStore;
+pub use images::{img_srcset, media_ready, ImageVariant, StoredImage, MEDIA_READY};
+#[cfg(feature = "images")]
+pub use images::{dominant_color, generate_variants};
+#[cfg(feature = "webauthn")]
+pub use webauthn::{
+    AssertionResponse, Ceremony, Challenge, CredentialStore, MemoryCredentialStore, PasskeyCredential, 
+    PasskeyVerifier, Passkeys, RegistrationResponse, VerifiedRegistration, WebAuthnError
+};
+#[cfg(all(feature = "webauthn", feature = "postgres"))]
+pub use webauthn::PostgresCredentialStore;
+pub use slash::TrailingSlashLayer;
+pub use toggle::{FeatureFlags, FeatureGuardLayer, FeatureHandle, FeatureState, FeatureToggles, FEATURES_ROUTE};
+pub use socket::{Connections, SocketInfo};
+#[cfg(feature = "ws")]
+pub use socket::{ws_route, Socket, WsError, SOCKET_QUEUE};
+pub use lazy::LazyRouter;
+pub use redirect::{HtmxRedirect, Redirection, CONTENT_TARGET};
+pub use embedded::TemplateSources;
+pub use widget::{EmbedToken, EmbedTokenError, EmbedTokens, EMBED_HEADER, EMBED_PARAM, EMBED_SCRIPT_ROUTE};
+pub use search::{Hit, SearchFeature, Searcher, SEARCH_RESULTS_ID, SEARCH_RESULTS_ROUTE, SEARCH_ROUTE};
+pub use last_visited::LastVisited;
+pub use reload::{ConfigReloader, Live, ReloadError, ReloadEvent, CONFIG_ROUTE};
+pub use registry::{FeatureRegistry, InstalledFeature};
+pub use listen::{Listening, ShutdownHandle};
+pub use submenu::{render_submenu, FeatureMenu, FeatureMenus, SUBMENU_ID};
+pub use nav_tree::{NavNode, NavTree, NavTreePrefs, NavTrees, NAV_ACTIVE, NAV_TREE_ROUTE};
+pub use group::{Policy, RouteGroup, RouteGroupEntry, RouteGroups, RouteKind};
+pub use jobs::{
+    dead_letters_panel, Job, JobError, JobHandler, JobState, JobStore, JobWorker, Jobs, MemoryJobStore, QueuedJob, RetryPolicy, JOBS_ROUTE
+};
+#[cfg(feature = "postgres")]
+pub use jobs::PostgresJobStore;
+pub use operations::{
+    MemoryOperationStore, Operation, OperationError, OperationHandle, OperationProgress, OperationState, OperationStore,
+    Operations, Outcome, OPERATIONS_ROUTE
+};
+#[cfg(feature = "postgres")]
+pub use operations::PostgresOperationStore;
+pub use stream::{json_array, Streaming};
+pub use takeout::{
+    takeout_button, ManifestEntry, Section, SectionContent, SectionStatus, Takeout, TakeoutError, TakeoutExport,
+    TakeoutFeature, TakeoutManifest, TakeoutProgress, TakeoutReady, UserDataProvider, UserDataProviders, UserDataScope,
+    TAKEOUT_PROGRESS, TAKEOUT_READY, TAKEOUT_ROUTE
+};
 pub use template::{TemplateLayer, Template};
 
 pub use axum::{Router, routing::get, response::IntoResponse };
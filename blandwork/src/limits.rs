@@ -0,0 +1,139 @@
+use std::{future::Future, pin::Pin, task::{Context as TaskContext, Poll}};
+
+use axum::{body::Body, extract::Request, response::IntoResponse};
+use hyper::{HeaderMap, Response, StatusCode};
+use tower::{Layer, Service};
+
+use crate::{config::Limits, reload::Live};
+
+/// Rejects oversized requests before they reach the handlers
+/// (the context layer clones every request header).
+#[derive(Clone)]
+pub struct LimitsLayer {
+    limits: Live<Limits>,
+}
+
+impl LimitsLayer {
+    /// Limits read on every request, replaced by a config reload.
+    pub fn live(limits: Live<Limits>) -> Self {
+        Self { limits }
+    }
+}
+
+impl<S> Layer<S> for LimitsLayer {
+    type Service = LimitsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LimitsService { inner, limits: self.limits.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct LimitsService<S> {
+    inner: S,
+    limits: Live<Limits>,
+}
+
+fn header_bytes(headers: &HeaderMap) -> u64 {
+    headers.iter()
+        .map(|(name, value)| (name.as_str().len() + value.len()) as u64)
+        .sum()
+}
+
+fn exceeded(limits: &Limits, request: &Request) -> Option<(StatusCode, &'static str)> {
+    let uri_length: usize = request.uri().path_and_query()
+        .map(|path| path.as_str().len())
+        .unwrap_or(0);
+
+    if uri_length > limits.max_uri_length {
+        return Some((StatusCode::URI_TOO_LONG, "URI too long"));
+    }
+
+    if request.headers().len() > limits.max_headers {
+        return Some((StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, "too many request headers"));
+    }
+
+    if header_bytes(request.headers()) > limits.max_header_bytes {
+        return Some((StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, "request headers too large"));
+    }
+
+    None
+}
+
+impl<S> Service<Request> for LimitsService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if let Some((status, reason)) = exceeded(&self.limits.load(), &req) {
+            tracing::warn!(path = req.uri().path(), "rejected request: {reason}");
+
+            return Box::pin(async move { Ok((status, reason).into_response()) });
+        }
+
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request, routing::get, Router};
+    use hyper::StatusCode;
+    use tower::ServiceExt;
+
+    use crate::{config::Limits, reload::Live};
+
+    use super::LimitsLayer;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(LimitsLayer::live(Live::new(Limits { max_headers: 4, max_header_bytes: 64, max_uri_length: 32 })))
+    }
+
+    async fn send(request: Request) -> StatusCode {
+        app().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_within_limits() {
+        let request = Request::builder().uri("/?page=1").header("accept", "text/html").body(Body::empty()).unwrap();
+
+        assert_eq!(send(request).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_uri_too_long() {
+        let uri = format!("/?q={}", "a".repeat(64));
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+        assert_eq!(send(request).await, StatusCode::URI_TOO_LONG);
+    }
+
+    #[tokio::test]
+    async fn test_too_many_headers() {
+        let mut request = Request::builder().uri("/");
+
+        for i in 0..5 {
+            request = request.header(format!("x-header-{i}"), "1");
+        }
+
+        assert_eq!(send(request.body(Body::empty()).unwrap()).await, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_headers_too_large() {
+        let request = Request::builder().uri("/").header("cookie", "a".repeat(100)).body(Body::empty()).unwrap();
+
+        assert_eq!(send(request).await, StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+    }
+}
@@ -0,0 +1,318 @@
+use maud::{html, Markup, PreEscaped};
+use serde::Deserialize;
+
+use crate::{embedded::TemplateSources, redirect::CONTENT_TARGET, Context};
+
+/// Class of the element rendered by `empty_state`, for styling every feature's empty lists alike.
+pub const EMPTY_STATE_CLASS: &str = "empty-state";
+
+/// Template replacing the markup of `EmptyState`, `{feature}/empty-state.html` wins over it
+/// for the pages of that feature. `{{ message }}`, `{{ icon }}` and `{{ cta }}` are substituted.
+pub const EMPTY_STATE_TEMPLATE: &str = "empty-state.html";
+
+/// Query parameter naming the row to reveal, see `HtmxRedirect::to_row`.
+pub const HIGHLIGHT_PARAM: &str = "highlight";
+
+/// Class of the highlighted row, the integration script removes it once the swap settled.
+pub const HIGHLIGHT_CLASS: &str = "blandwork-highlight";
+
+/// The standard "nothing here" fragment of an empty list.
+pub fn empty_state(message: &str) -> Markup {
+    EmptyState::new(message).render()
+}
+
+/// The "nothing here" partial of lists, tables and search results: an icon, the message
+/// and optionally a call to action. `Context::empty_state` renders it through the
+/// templates of the app, see `EMPTY_STATE_TEMPLATE`.
+///
+/// ```ignore
+/// EmptyState::new("No invoices yet").icon("🧾").cta("New invoice", "/invoices/new").render_row(4)
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmptyState {
+    pub message: String,
+    pub icon: Option<String>,
+
+    // (label, url)
+    pub cta: Option<(String, String)>,
+}
+
+impl EmptyState {
+    pub fn new(message: &str) -> Self {
+        Self { message: message.to_owned(), icon: None, cta: None }
+    }
+
+    pub fn icon(mut self, icon: &str) -> Self {
+        self.icon = Some(icon.to_owned());
+        self
+    }
+
+    pub fn cta(mut self, label: &str, url: &str) -> Self {
+        self.cta = Some((label.to_owned(), url.to_owned()));
+        self
+    }
+
+    pub fn render(&self) -> Markup {
+        html! {
+            div class=(EMPTY_STATE_CLASS) role="status" {
+                (self.icon_markup())
+                p { (self.message) }
+                (self.cta_markup())
+            }
+        }
+    }
+
+    /// The partial as the only row of a table with `columns` columns.
+    pub fn render_row(&self, columns: usize) -> Markup {
+        html! {
+            tr { td colspan=(columns.max(1)) { (self.render()) } }
+        }
+    }
+
+    /// The template of `feature`, else the app's, else `render`.
+    pub fn resolve(&self, templates: &TemplateSources, feature: Option<&str>) -> Markup {
+        let source: Option<String> = feature
+            .and_then(|feature| templates.get(&format!("{feature}/{EMPTY_STATE_TEMPLATE}")))
+            .or_else(|| templates.get(EMPTY_STATE_TEMPLATE));
+
+        match source {
+            Some(source) => PreEscaped(source
+                .replace("{{ message }}", &html! { (self.message) }.into_string())
+                .replace("{{ icon }}", &self.icon_markup().into_string())
+                .replace("{{ cta }}", &self.cta_markup().into_string())),
+            None => self.render(),
+        }
+    }
+
+    fn icon_markup(&self) -> Markup {
+        html! {
+            @if let Some(icon) = self.icon.as_deref() {
+                span .empty-state-icon aria-hidden="true" { (icon) }
+            }
+        }
+    }
+
+    fn cta_markup(&self) -> Markup {
+        html! {
+            @if let Some((label, url)) = self.cta.as_ref() {
+                a .empty-state-action href=(url) { (label) }
+            }
+        }
+    }
+}
+
+/// Renders each of `items` with `item` and concatenates the fragments,
+/// `empty` is rendered instead when there are no items.
+///
+/// ```ignore
+/// html! {
+///     ul #invoices {
+///         (render_list(invoices, |invoice| html! { li id=(morph_id("invoice", invoice.id)) { (invoice.number) } }, empty_state("No invoices yet")))
+///     }
+/// }
+/// ```
+pub fn render_list<T>(items: impl IntoIterator<Item = T>, item: impl Fn(&T) -> Markup, empty: Markup) -> Markup {
+    let mut items = items.into_iter().peekable();
+
+    if items.peek().is_none() {
+        return empty;
+    }
+
+    html! {
+        @for entry in items {
+            (item(&entry))
+        }
+    }
+}
+
+/// A page of a paginated list, numbered from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Page {
+    pub number: usize,
+    pub size: usize,
+}
+
+impl Page {
+    pub fn new(number: usize, size: usize) -> Self {
+        Self { number: number.max(1), size: size.max(1) }
+    }
+
+    /// The page holding the row listed after `rows_before` others.
+    pub fn containing(rows_before: usize, size: usize) -> Self {
+        let size: usize = size.max(1);
+        Self::new(rows_before / size + 1, size)
+    }
+
+    /// Rows skipped before the first one of the page, the query's `OFFSET`.
+    pub fn offset(&self) -> usize {
+        (self.number - 1) * self.size
+    }
+}
+
+/// `?page=` and `?highlight=` of a paginated list, extracted with `Query<ListQuery>`:
+///
+/// ```ignore
+/// async fn index(Extension(context): Extension<ContextAccessor>, Query(query): Query<ListQuery>, db: Db) -> Markup {
+///     let page = query.page(25, |row_id| db.invoices_before(row_id));
+///     query.reveal(&mut context.context().await);
+///
+///     html! {
+///         ul #invoices {
+///             (render_list(db.invoices(page.offset(), page.size), |invoice| {
+///                 let id = morph_id("invoice", invoice.id);
+///                 html! { li id=(id) class=[query.row_class(&id)] { (invoice.number) } }
+///             }, empty_state("No invoices yet")))
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListQuery {
+    pub page: Option<usize>,
+    pub highlight: Option<String>,
+}
+
+impl ListQuery {
+    /// The requested page, or with a highlighted row the page holding it:
+    /// `rows_before` counts the rows listed ahead of that row.
+    pub fn page(&self, size: usize, rows_before: impl FnOnce(&str) -> usize) -> Page {
+        match self.highlight.as_deref() {
+            Some(row_id) => Page::containing(rows_before(row_id), size),
+            None => Page::new(self.page.unwrap_or(1), size),
+        }
+    }
+
+    /// `HIGHLIGHT_CLASS` for the highlighted row.
+    pub fn row_class(&self, row_id: &str) -> Option<&'static str> {
+        (self.highlight.as_deref() == Some(row_id)).then_some(HIGHLIGHT_CLASS)
+    }
+
+    /// Scrolls the highlighted row into view once HTMX swapped the list into `CONTENT_TARGET`.
+    pub fn reveal(&self, context: &mut Context) {
+        if let Some(row_id) = self.highlight.as_deref() {
+            context.retarget(CONTENT_TARGET, format!("innerHTML show:#{row_id}:center"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::{Query, Request}, routing::get, Extension, Router};
+    use axum_htmx::{HX_REQUEST, HX_RESWAP, HX_RETARGET};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{embedded::TemplateSources, morph_id, test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{empty_state, render_list, EmptyState, ListQuery, Page};
+
+    struct Invoice {
+        id: u32,
+        number: &'static str,
+    }
+
+    #[test]
+    fn test_populated() {
+        let invoices = vec![Invoice { id: 1, number: "A-1" }, Invoice { id: 2, number: "A-2" }];
+
+        let markup = render_list(invoices, |invoice| html! { li data-id=(invoice.id) { (invoice.number) } }, empty_state("No invoices"));
+
+        assert_eq!(markup.into_string(), "<li data-id=\"1\">A-1</li><li data-id=\"2\">A-2</li>");
+    }
+
+    #[test]
+    fn test_empty() {
+        let markup = render_list(Vec::<Invoice>::new(), |invoice| html! { li { (invoice.number) } }, empty_state("No invoices"));
+
+        assert_eq!(markup.into_string(), "<div class=\"empty-state\" role=\"status\"><p>No invoices</p></div>");
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let empty = EmptyState::new("No invoices").icon("!").cta("New invoice", "/invoices/new");
+        let markup = render_list(Vec::<Invoice>::new(), |invoice| html! { tr { td { (invoice.number) } } }, empty.render_row(3));
+
+        assert_eq!(markup.into_string(), concat!(
+            "<tr><td colspan=\"3\"><div class=\"empty-state\" role=\"status\">",
+            "<span class=\"empty-state-icon\" aria-hidden=\"true\">!</span>",
+            "<p>No invoices</p>",
+            "<a class=\"empty-state-action\" href=\"/invoices/new\">New invoice</a>",
+            "</div></td></tr>"
+        ));
+    }
+
+    #[test]
+    fn test_empty_state_override() {
+        let templates = TemplateSources::new("missing");
+        templates.register("invoices", vec![
+            ("invoices/empty-state.html".to_owned(), "<em>{{ message }}</em>{{ cta }}".to_owned()),
+        ]);
+
+        let empty = EmptyState::new("No <invoices>").cta("New", "/invoices/new");
+        assert_eq!(empty.resolve(&templates, Some("invoices")).into_string(),
+            "<em>No &lt;invoices&gt;</em><a class=\"empty-state-action\" href=\"/invoices/new\">New</a>");
+
+        // other features keep the partial
+        assert_eq!(empty.resolve(&templates, Some("orders")).into_string(), empty.render().into_string());
+    }
+
+    #[test]
+    fn test_page_offset() {
+        assert_eq!(Page::new(0, 25), Page { number: 1, size: 25 });
+        assert_eq!(Page::new(3, 25).offset(), 50);
+
+        assert_eq!(Page::containing(0, 25), Page { number: 1, size: 25 });
+        assert_eq!(Page::containing(24, 25).number, 1);
+        assert_eq!(Page::containing(25, 25).number, 2);
+        assert_eq!(Page::containing(60, 25).offset(), 50);
+
+        let query = ListQuery { page: Some(1), highlight: Some("invoice-61".to_owned()) };
+        assert_eq!(query.page(25, |row_id| if row_id == "invoice-61" { 60 } else { 0 }).number, 3);
+
+        let query = ListQuery { page: Some(2), highlight: None };
+        assert_eq!(query.page(25, |_| unreachable!()).offset(), 25);
+    }
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Invoices;
+
+    impl Feature for Invoices {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/invoices", get(|Extension(accessor): Extension<ContextAccessor>, Query(query): Query<ListQuery>| async move {
+                let page = query.page(2, |row_id| row_id.trim_start_matches("invoice-").parse::<usize>().unwrap() - 1);
+                query.reveal(&mut accessor.context().await);
+
+                html! {
+                    ul #invoices {
+                        (render_list(page.offset() + 1..=page.offset() + page.size, |id| {
+                            let id = morph_id("invoice", id);
+                            html! { li id=(id) class=[query.row_class(&id)] {} }
+                        }, empty_state("No invoices")))
+                    }
+                }
+            })))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_highlighted_row() {
+        let app = router(&App::new(Config::default(), TestTemplate).register_feature(Invoices).build());
+
+        let response = app.oneshot(Request::get("/invoices?highlight=invoice-5").header(HX_REQUEST, "true").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.headers()[HX_RETARGET], "#content");
+        assert_eq!(response.headers()[HX_RESWAP], "innerHTML show:#invoice-5:center");
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap()
+            .contains("<li id=\"invoice-5\" class=\"blandwork-highlight\"></li><li id=\"invoice-6\"></li>"));
+    }
+}
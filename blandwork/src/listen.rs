@@ -0,0 +1,203 @@
+use std::{future::Future, io, net::SocketAddr, sync::Arc};
+
+use axum::{http::Extensions, Router};
+use tokio::{net::TcpListener, sync::watch};
+
+use crate::{config::JobQueue, jobs::{JobWorker, Jobs}, schedule::{Schedule, Scheduler}, socket::Connections};
+
+/// Stops a `Listening` server as Ctrl-C does, e.g. at the end of a test.
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+impl Default for ShutdownHandle {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
+
+impl ShutdownHandle {
+    /// Also stops a server whose `serve` has not started yet.
+    pub fn shutdown(&self) {
+        self.0.send_replace(true);
+    }
+
+    async fn requested(&self) {
+        let mut stop: watch::Receiver<bool> = self.0.subscribe();
+        let _ = stop.wait_for(|stop| *stop).await;
+    }
+}
+
+/// The app bound to its address but not serving yet, see `App::listen`.
+///
+/// ```ignore
+/// let mut config = Config::default();
+/// config.server.port = 0;
+///
+/// let listening = app(config).listen().await?;
+/// let address = listening.local_addr();
+/// let shutdown = listening.shutdown_handle();
+/// let server = tokio::spawn(listening.serve());
+///
+/// let response = reqwest::get(format!("http://{address}/invoices")).await?;
+///
+/// shutdown.shutdown();
+/// server.await??;
+/// ```
+pub struct Listening {
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    router: Router,
+    connections: Connections,
+    scheduler: Scheduler,
+    jobs: Jobs,
+    queue: JobQueue,
+
+    // available to the job handlers (events, pool)
+    extensions: Extensions,
+    shutdown: ShutdownHandle,
+}
+
+impl Listening {
+    pub(crate) fn new(
+        listener: TcpListener,
+        router: Router,
+        connections: Connections,
+        scheduler: Scheduler,
+        jobs: Jobs,
+        queue: JobQueue,
+        extensions: Extensions,
+    ) -> io::Result<Self> {
+        let local_addr: SocketAddr = listener.local_addr()?;
+
+        Ok(Self { listener, local_addr, router, connections, scheduler, jobs, queue, extensions, shutdown: ShutdownHandle::default() })
+    }
+
+    /// The bound address, with the port the system picked for `port = 0`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serves until SIGINT (Ctrl-C), SIGTERM or `ShutdownHandle::shutdown`, then waits for
+    /// the requests, scheduled and queued jobs in progress.
+    pub async fn serve(self) -> io::Result<()> {
+        self.serve_until(std::future::pending()).await
+    }
+
+    /// `serve`, also stopping once `signal` completes (a management endpoint, a parent process).
+    pub async fn serve_until(self, signal: impl Future<Output = ()> + Send + 'static) -> io::Result<()> {
+        let schedule: Schedule = self.scheduler.start();
+        let worker: JobWorker = self.jobs.start(&self.queue, self.extensions);
+
+        // peer addresses for the access log
+        let result: io::Result<()> = axum::serve(self.listener, self.router.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(shutdown(self.connections, self.shutdown, signal))
+            .await;
+
+        schedule.shutdown().await;
+        worker.shutdown().await;
+
+        result
+    }
+}
+
+/// SIGINT (Ctrl-C) or SIGTERM (orchestrators), a signal which can't be listened for never comes.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("unable to listen for SIGINT: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            },
+            Err(e) => {
+                tracing::error!("unable to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
+
+/// A signal, the handle or the caller's future, then close frames to every socket: upgraded
+/// connections are not waited for by the server's graceful shutdown.
+async fn shutdown(connections: Connections, handle: ShutdownHandle, signal: impl Future<Output = ()>) {
+    tokio::select! {
+        _ = shutdown_signal() => {},
+        _ = handle.requested() => {},
+        _ = signal => {},
+    }
+
+    tracing::info!("shutting down");
+    connections.close_all();
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{routing::get, Router};
+    use maud::{html, Markup};
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
+
+    use crate::{App, Config, Context, Feature, Template};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Ping;
+
+    impl Feature for Ping {
+        fn api(&self) -> Option<Router> {
+            Some(Router::new().route("/ping", get(|| async { "pong" })))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_listen_on_free_port() {
+        let mut config = Config::default();
+        config.server.host = "127.0.0.1".to_owned();
+        config.server.port = 0;
+
+        let listening = App::new(config, TestTemplate).register_feature(Ping).build().listen().await.unwrap();
+        let address = listening.local_addr();
+        assert_ne!(address.port(), 0);
+
+        let shutdown = listening.shutdown_handle();
+        let server = tokio::spawn(listening.serve());
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET /ping HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n").await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        // streamed through the layers, chunked without a content length
+        assert!(response.ends_with("\r\n4\r\npong\r\n0\r\n\r\n"));
+
+        shutdown.shutdown();
+        server.await.unwrap().unwrap();
+    }
+}
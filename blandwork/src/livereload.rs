@@ -0,0 +1,232 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, time::SystemTime};
+
+use axum::Router;
+use tokio::sync::broadcast::{self, Sender};
+
+use crate::Config;
+
+#[cfg(feature = "sse")]
+pub const LIVE_RELOAD_ROUTE: &str = "/_blandwork/livereload";
+
+/// Reloads the page when the server announces a change,
+/// and once more after reconnecting (the server itself was restarted).
+pub const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function() {
+    var restarted = false;
+    var source = new EventSource("/_blandwork/livereload");
+    source.addEventListener("reload", function() { window.location.reload(); });
+    source.onerror = function() { restarted = true; };
+    source.onopen = function() { if (restarted) { window.location.reload(); } };
+})();
+</script>"#;
+
+/// Development only notifier pushing `reload` events to connected browsers.
+/// Never constructed outside of `Environment::Development`.
+#[derive(Clone)]
+pub struct LiveReload {
+    sender: Sender<()>
+}
+
+impl LiveReload {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        if !config.is_development() || !config.server.live_reload.enabled {
+            return None;
+        }
+
+        // the browser listens on a server sent event stream
+        if !cfg!(feature = "sse") {
+            tracing::warn!("live reload requires the sse feature, disabled");
+            return None;
+        }
+
+        let (sender, _) = broadcast::channel(16);
+        let live_reload: LiveReload = LiveReload { sender };
+
+        live_reload.watch(config);
+
+        Some(live_reload)
+    }
+
+    /// Whether `from_config` enables live reload, without watching anything.
+    pub(crate) fn enabled(config: &Config) -> bool {
+        config.is_development() && config.server.live_reload.enabled && cfg!(feature = "sse")
+    }
+
+    /// Tell every connected browser to reload.
+    pub fn notify(&self) {
+        // no receivers simply means no browser is open
+        let _ = self.sender.send(());
+    }
+
+    pub fn router(&self) -> Router {
+        #[cfg(feature = "sse")]
+        let router: Router = sse::router(self);
+
+        #[cfg(not(feature = "sse"))]
+        let router: Router = Router::new();
+
+        router
+    }
+
+    /// Poll the configured paths and notify when a file is added, changed or removed.
+    fn watch(&self, config: &Config) {
+        let paths: Vec<PathBuf> = config.server.live_reload.watch.iter().map(PathBuf::from).collect();
+        let period = config.server.live_reload.interval;
+        let live_reload: LiveReload = self.clone();
+
+        let handle = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                tracing::warn!("live reload requires a tokio runtime, file watching disabled");
+                return;
+            }
+        };
+
+        handle.spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            let mut last: Option<Snapshot> = None;
+
+            loop {
+                interval.tick().await;
+
+                // the walk blocks on the file system, off the runtime's workers
+                let walked: Vec<PathBuf> = paths.clone();
+                let Ok(current) = tokio::task::spawn_blocking(move || snapshot(&walked)).await else {
+                    continue;
+                };
+
+                if last.as_ref().is_some_and(|last| *last != current) {
+                    tracing::info!("live reload: change detected in {:?}", paths);
+                    live_reload.notify();
+                }
+
+                last = Some(current);
+            }
+        });
+    }
+}
+
+// modification time of every watched file, a removed file changes it as well
+type Snapshot = BTreeMap<PathBuf, SystemTime>;
+
+fn snapshot(paths: &[PathBuf]) -> Snapshot {
+    let mut files: Snapshot = BTreeMap::new();
+    let mut pending: Vec<PathBuf> = paths.to_vec();
+
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = fs::metadata(&path) else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+            continue;
+        }
+
+        if let Ok(modified) = metadata.modified() {
+            files.insert(path, modified);
+        }
+    }
+
+    files
+}
+
+/// Insert the reload script just before `</body>`, or append it when there is none.
+pub fn inject_script(page: String) -> String {
+    match page.rfind("</body>") {
+        Some(index) => {
+            let mut page: String = page;
+            page.insert_str(index, LIVE_RELOAD_SCRIPT);
+            page
+        },
+        None => page + LIVE_RELOAD_SCRIPT
+    }
+}
+
+#[cfg(feature = "sse")]
+mod sse {
+    use std::convert::Infallible;
+
+    use axum::{
+        response::sse::{Event, KeepAlive, Sse},
+        routing::get, Extension, Router
+    };
+    use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+    use super::{LiveReload, LIVE_RELOAD_ROUTE};
+
+    pub(super) fn router(live_reload: &LiveReload) -> Router {
+        Router::new()
+            .route(LIVE_RELOAD_ROUTE, get(events))
+            .layer(Extension(live_reload.clone()))
+    }
+
+    async fn events(Extension(live_reload): Extension<LiveReload>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        let stream = BroadcastStream::new(live_reload.sender.subscribe())
+            // a lagged receiver still means something changed
+            .map(|_| Ok(Event::default().event("reload").data("reload")));
+
+        Sse::new(stream).keep_alive(KeepAlive::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{config::Environment, Config};
+
+    use super::{inject_script, snapshot, LiveReload, LIVE_RELOAD_SCRIPT};
+
+    #[test]
+    fn test_absent_in_production() {
+        let config: Config = Config::default();
+
+        assert!(LiveReload::from_config(&config).is_none());
+    }
+
+    #[cfg(feature = "sse")]
+    #[tokio::test]
+    async fn test_present_in_development() {
+        let mut config: Config = Config { environment: Environment::Development, ..Default::default() };
+
+        assert!(LiveReload::from_config(&config).is_some());
+
+        config.server.live_reload.enabled = false;
+        assert!(LiveReload::from_config(&config).is_none());
+    }
+
+    // the browser could not listen without the stream
+    #[cfg(not(feature = "sse"))]
+    #[test]
+    fn test_absent_without_sse() {
+        let config: Config = Config { environment: Environment::Development, ..Default::default() };
+
+        assert!(LiveReload::from_config(&config).is_none());
+    }
+
+    #[test]
+    fn test_snapshot_sees_removed_files() {
+        let dir = std::env::temp_dir().join(format!("blandwork-livereload-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("css")).unwrap();
+        std::fs::write(dir.join("app.js"), "1").unwrap();
+        std::fs::write(dir.join("css/app.css"), "2").unwrap();
+
+        let before = snapshot(&[dir.clone()]);
+        assert_eq!(before.len(), 2);
+
+        std::fs::remove_file(dir.join("css/app.css")).unwrap();
+        assert_ne!(snapshot(&[dir.clone()]), before);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_inject_script() {
+        let page: String = inject_script("<html><body><b>hi</b></body></html>".to_owned());
+        assert_eq!(page, format!("<html><body><b>hi</b>{LIVE_RELOAD_SCRIPT}</body></html>"));
+
+        let fragment: String = inject_script("<b>hi</b>".to_owned());
+        assert_eq!(fragment, format!("<b>hi</b>{LIVE_RELOAD_SCRIPT}"));
+    }
+}
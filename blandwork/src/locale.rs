@@ -0,0 +1,343 @@
+use std::sync::{Arc, RwLock};
+
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+    routing::{get, MethodRouter}, Router
+};
+use hyper::{header::{ACCEPT_LANGUAGE, LOCATION}, HeaderMap, StatusCode};
+
+use crate::{cookies::parse_cookies, transform::BodyTransform, Context};
+
+/// Explicit locale choice of the user, wins over `Accept-Language`
+/// but not over the locale of a translated path.
+pub const LOCALE_COOKIE: &str = "locale";
+
+/// One handler served under a translated path per locale:
+///
+/// ```ignore
+/// LocalizedRoute::new("/pricing", get(pricing))
+///     .locale("en", "/en/pricing")
+///     .locale("de", "/de/preise")
+/// ```
+///
+/// The canonical path (`/pricing`) answers `308` to the variant of the request's
+/// locale, or the first variant when none matches.
+pub struct LocalizedRoute {
+    translations: Translations,
+    handler: MethodRouter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Translations {
+    canonical: String,
+
+    // (locale, path) in declaration order, the first is the default
+    variants: Vec<(String, String)>,
+}
+
+impl Translations {
+    fn path(&self, locale: Option<&str>) -> Option<&str> {
+        locale
+            .and_then(|locale| self.variants.iter().find(|(variant, _)| variant == locale))
+            .or(self.variants.first())
+            .map(|(_, path)| path.as_str())
+    }
+}
+
+impl LocalizedRoute {
+    pub fn new(canonical: &str, handler: MethodRouter) -> Self {
+        Self { translations: Translations { canonical: canonical.to_owned(), variants: Vec::new() }, handler }
+    }
+
+    pub fn locale(mut self, locale: &str, path: &str) -> Self {
+        self.translations.variants.push((locale.to_owned(), path.to_owned()));
+        self
+    }
+
+    /// Every variant pointing at the handler.
+    pub(crate) fn router(&self) -> Router {
+        self.translations.variants.iter()
+            .fold(Router::new(), |router, (_, path)| router.route(path, self.handler.clone()))
+    }
+
+    /// The canonical path's redirect, mounted outside of the template.
+    /// None when the canonical path is one of the variants.
+    pub(crate) fn redirect(&self) -> Option<Router> {
+        let translations: Translations = self.translations.clone();
+
+        if translations.variants.is_empty() || translations.variants.iter().any(|(_, path)| *path == translations.canonical) {
+            return None;
+        }
+
+        Some(Router::new().route(&self.translations.canonical, get(move |request: Request| {
+            let translations: Translations = translations.clone();
+
+            async move { canonical_redirect(&translations, request) }
+        })))
+    }
+}
+
+fn canonical_redirect(translations: &Translations, request: Request) -> Response {
+    let locale: Option<String> = preferred_locale(request.headers());
+    let path: &str = translations.path(locale.as_deref()).unwrap_or(&translations.canonical);
+
+    let location: String = match request.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+
+    (StatusCode::PERMANENT_REDIRECT, [(LOCATION, location)]).into_response()
+}
+
+/// The primary language of the `Accept-Language` entry with the highest weight.
+fn accept_language(headers: &HeaderMap) -> Option<String> {
+    let header: &str = headers.get(ACCEPT_LANGUAGE)?.to_str().ok()?;
+
+    header.split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.trim().split(';');
+            let tag: &str = parts.next()?.trim();
+            let weight: f32 = parts
+                .find_map(|parameter| parameter.trim().strip_prefix("q="))
+                .and_then(|weight| weight.parse().ok())
+                .unwrap_or(1.0);
+
+            (!tag.is_empty() && tag != "*" && weight > 0.0).then_some((tag, weight))
+        })
+        .fold(None, |best: Option<(&str, f32)>, (tag, weight)| match best {
+            Some((_, best_weight)) if best_weight >= weight => best,
+            _ => Some((tag, weight)),
+        })
+        .map(|(tag, _)| tag.split('-').next().unwrap_or(tag).to_ascii_lowercase())
+}
+
+/// The locale cookie, then `Accept-Language`.
+fn preferred_locale(headers: &HeaderMap) -> Option<String> {
+    parse_cookies(headers)
+        .into_iter()
+        .find(|(name, _)| name == LOCALE_COOKIE)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+        .or_else(|| accept_language(headers))
+}
+
+/// Translated paths of every `Feature::localized` route, registered by `build`.
+#[derive(Clone, Default)]
+pub struct LocalizedRoutes {
+    routes: Arc<RwLock<Vec<Translations>>>,
+}
+
+impl LocalizedRoutes {
+    pub(crate) fn register(&self, route: &LocalizedRoute) {
+        self.routes.write().unwrap().push(route.translations.clone());
+    }
+
+    fn find<T>(&self, path: &str, found: impl Fn(&Translations) -> Option<T>) -> Option<T> {
+        self.routes.read().unwrap()
+            .iter()
+            .filter(|translations| translations.canonical == path || translations.variants.iter().any(|(_, variant)| variant == path))
+            .find_map(found)
+    }
+
+    /// The locale of a translated `path`, None for canonical and untranslated paths.
+    pub fn locale_of(&self, path: &str) -> Option<String> {
+        self.find(path, |translations| {
+            translations.variants.iter()
+                .find(|(_, variant)| variant == path)
+                .map(|(locale, _)| locale.clone())
+        })
+    }
+
+    /// `path` (canonical or any variant) translated to `locale`.
+    pub fn path_for(&self, path: &str, locale: Option<&str>) -> Option<String> {
+        self.find(path, |translations| translations.path(locale).map(|path| path.to_owned()))
+    }
+
+    /// (locale, path) of every variant of `path`.
+    pub fn alternates(&self, path: &str) -> Vec<(String, String)> {
+        self.find(path, |translations| Some(translations.variants.clone())).unwrap_or_default()
+    }
+}
+
+/// Locale of a request: the translated path matched, the locale cookie, `Accept-Language`.
+pub(crate) fn request_locale(routes: Option<&LocalizedRoutes>, path: &str, headers: &HeaderMap) -> Option<String> {
+    routes
+        .and_then(|routes| routes.locale_of(path))
+        .or_else(|| preferred_locale(headers))
+}
+
+/// `<link rel="alternate" hreflang>` of every variant in the head of translated pages.
+pub(crate) fn hreflang() -> BodyTransform {
+    Arc::new(|context: &Context, html: String| {
+        let alternates: Vec<(String, String)> = context.alternates();
+
+        let Some(index) = html.find("</head>").filter(|_| !alternates.is_empty()) else {
+            return html;
+        };
+
+        let links: String = alternates.iter()
+            .map(|(locale, path)| format!("<link rel=\"alternate\" hreflang=\"{locale}\" href=\"{path}\">"))
+            .collect();
+
+        let mut html: String = html;
+        html.insert_str(index, &links);
+        html
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use hyper::{header::{ACCEPT_LANGUAGE, LOCATION}, HeaderMap, StatusCode};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{test::router, App, Config, Context, ContextAccessor, Feature, Link, Template};
+
+    use super::{accept_language, LocalizedRoute, LocalizedRoutes};
+
+    async fn pricing(Extension(accessor): Extension<ContextAccessor>) -> Markup {
+        let context = accessor.context().await;
+
+        html! {
+            p { "locale " (context.locale().unwrap_or_default()) }
+            a href=(context.url_for("/pricing")) { "pricing" }
+        }
+    }
+
+    fn route() -> LocalizedRoute {
+        LocalizedRoute::new("/pricing", get(pricing))
+            .locale("en", "/en/pricing")
+            .locale("de", "/de/preise")
+    }
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { head { title { "t" } } body { (body) } } }
+        }
+    }
+
+    struct Pricing;
+
+    impl Feature for Pricing {
+        fn link(&self) -> Option<Link> {
+            Some(Link { active: false, title: "Pricing".to_owned(), label: "P".to_owned(), route: "/pricing".to_owned(), icon: None, css: None, remember: false })
+        }
+
+        fn localized(&self) -> Vec<LocalizedRoute> {
+            vec![route()]
+        }
+    }
+
+    fn app() -> Router {
+        router(&App::new(Config::default(), TestTemplate).register_feature(Pricing).build())
+    }
+
+    async fn send(router: &Router, uri: &str, headers: &[(&str, &str)]) -> (StatusCode, HeaderMap, String) {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let response = router.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_accept_language() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_LANGUAGE, "fr;q=0.5, de-CH, en;q=0.8, *;q=0.1".parse().unwrap());
+
+        assert_eq!(accept_language(&headers).as_deref(), Some("de"));
+        assert_eq!(accept_language(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_variants_mounted() {
+        let app = app();
+
+        let (status, _, body) = send(&app, "/en/pricing", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("locale en"));
+
+        let (status, _, body) = send(&app, "/de/preise", &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("locale de"));
+    }
+
+    #[tokio::test]
+    async fn test_locale_precedence() {
+        let app = app();
+
+        // the path wins over the cookie and the header, an explicit choice is not redirected
+        let (status, _, body) = send(&app, "/de/preise", &[("cookie", "locale=en"), ("accept-language", "en")]).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("locale de"));
+
+        let routes = LocalizedRoutes::default();
+        routes.register(&route());
+
+        let locale = |uri: &str, headers: &[(&str, &str)]| {
+            let mut request = Request::get(uri);
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+
+            let request = request.extension(routes.clone()).body(Body::empty()).unwrap();
+            async move { ContextAccessor::from_request(&request).context().await.locale() }
+        };
+
+        assert_eq!(locale("/", &[("cookie", "locale=de"), ("accept-language", "en")]).await.as_deref(), Some("de"));
+        assert_eq!(locale("/", &[("accept-language", "de-DE, en;q=0.5")]).await.as_deref(), Some("de"));
+        assert_eq!(locale("/", &[]).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_url_for() {
+        let app = app();
+
+        assert!(send(&app, "/en/pricing", &[]).await.2.contains(r#"<a href="/en/pricing">pricing</a>"#));
+        assert!(send(&app, "/de/preise", &[]).await.2.contains(r#"<a href="/de/preise">pricing</a>"#));
+
+        // untranslated paths are left alone
+        let request = Request::get("/de/preise").extension({
+            let routes = LocalizedRoutes::default();
+            routes.register(&route());
+            routes
+        }).body(Body::empty()).unwrap();
+
+        let accessor = ContextAccessor::from_request(&request);
+        assert_eq!(accessor.context().await.url_for("/invoices"), "/invoices");
+    }
+
+    #[tokio::test]
+    async fn test_hreflang() {
+        let (_, _, body) = send(&app(), "/de/preise", &[]).await;
+
+        assert!(body.contains(r#"<link rel="alternate" hreflang="en" href="/en/pricing"><link rel="alternate" hreflang="de" href="/de/preise"></head>"#));
+    }
+
+    #[tokio::test]
+    async fn test_canonical_redirect() {
+        let app = app();
+
+        let (status, headers, _) = send(&app, "/pricing?plan=team", &[("accept-language", "de")]).await;
+        assert_eq!(status, StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(headers[LOCATION], "/de/preise?plan=team");
+
+        let (_, headers, _) = send(&app, "/pricing", &[("cookie", "locale=en"), ("accept-language", "de")]).await;
+        assert_eq!(headers[LOCATION], "/en/pricing");
+
+        // unsupported locales get the first variant
+        let (_, headers, _) = send(&app, "/pricing", &[("accept-language", "fr")]).await;
+        assert_eq!(headers[LOCATION], "/en/pricing");
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error, fmt::Display, future::Future, pin::Pin,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex},
+    task::{Context as TaskContext, Poll},
+    time::Duration
+};
+
+use axum::{extract::Request, response::{IntoResponse, Response}, routing::post, Extension, Json, Router};
+use hyper::{HeaderMap, StatusCode};
+use serde::Deserialize;
+use tower::{Layer, Service};
+use tracing::{level_filters::LevelFilter, Instrument, Span};
+use tracing_subscriber::{filter::Directive, reload, EnvFilter, Registry};
+
+use crate::{clock::SharedClock, config::Logging, toggle::{authorized, AdminToken}, units};
+
+pub const LOG_LEVEL_ROUTE: &str = "/_blandwork/log-level";
+
+#[derive(Debug)]
+pub enum LogLevelError {
+    InvalidLevel(String),
+    InvalidTarget(String),
+    InvalidFilter(String),
+    Reload(String),
+}
+
+impl Display for LogLevelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogLevelError::InvalidLevel(level) => write!(f, "unknown level {level:?}, expected off, error, warn, info, debug or trace"),
+            LogLevelError::InvalidTarget(e) => write!(f, "invalid target: {e}"),
+            LogLevelError::InvalidFilter(e) => write!(f, "invalid log filter: {e}"),
+            LogLevelError::Reload(e) => write!(f, "unable to reload the log filter: {e}"),
+        }
+    }
+}
+
+impl Error for LogLevelError {}
+
+/// Directive enabling everything emitted while handling a feature's routes,
+/// e.g. `[feature{name=Billing}]`, see `FeatureSpanLayer`.
+pub fn feature_target(name: &str) -> String {
+    format!("[feature{{name={name}}}]")
+}
+
+#[derive(Default)]
+struct Overrides {
+    // target -> (level, generation), the generation keeps an older revert from undoing a newer change
+    levels: HashMap<String, (LevelFilter, u64)>,
+    generation: u64,
+}
+
+/// The framework log filter: the configured (or `RUST_LOG`) filter, `[server.logging.targets]`
+/// and runtime overrides, reloaded in place by `set`.
+#[derive(Clone)]
+pub struct LogLevels {
+    // (filter, targets) of the config, replaced by a config reload
+    configured: Arc<Mutex<(String, BTreeMap<String, String>)>>,
+    overrides: Arc<Mutex<Overrides>>,
+    handle: Arc<Mutex<Option<reload::Handle<EnvFilter, Registry>>>>,
+
+    // number of sets and reverts applied, lets callers observe a revert
+    changes: Arc<AtomicU64>,
+}
+
+impl LogLevels {
+    /// `RUST_LOG`, when set, replaces `server.logging.filter`.
+    pub fn from_config(config: &Logging) -> Self {
+        let base: String = std::env::var("RUST_LOG").unwrap_or_else(|_| config.filter.clone());
+
+        Self::new(&base, config.targets.clone())
+    }
+
+    pub fn new(filter: &str, targets: BTreeMap<String, String>) -> Self {
+        Self {
+            configured: Arc::new(Mutex::new((filter.to_owned(), targets))),
+            overrides: Arc::new(Mutex::new(Overrides::default())),
+            handle: Arc::new(Mutex::new(None)),
+            changes: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The effective filter, runtime overrides win over configured targets.
+    pub fn filter(&self) -> String {
+        let mut directives: Vec<String> = {
+            let configured = self.configured.lock().unwrap();
+            let (base, targets) = &*configured;
+
+            let mut directives: Vec<String> = vec![base.clone()];
+            directives.extend(targets.iter().map(|(target, level)| format!("{target}={level}")));
+            directives
+        };
+
+        let overrides = self.overrides.lock().unwrap();
+        let mut levels: Vec<(&String, &(LevelFilter, u64))> = overrides.levels.iter().collect();
+        levels.sort_by_key(|(target, _)| *target);
+
+        directives.extend(levels.into_iter().map(|(target, (level, _))| format!("{target}={level}")));
+
+        directives.retain(|directive| !directive.is_empty());
+        directives.join(",")
+    }
+
+    fn env_filter(&self) -> Result<EnvFilter, LogLevelError> {
+        let filter: String = self.filter();
+
+        EnvFilter::try_new(&filter).map_err(|e| LogLevelError::InvalidFilter(format!("{filter:?} ({e})")))
+    }
+
+    /// The effective filter parses, `layer` falls back to `info` otherwise.
+    pub fn validate(&self) -> Result<(), LogLevelError> {
+        self.env_filter().map(|_| ())
+    }
+
+    /// Filter layer for the subscriber, the first layer on the `Registry`. An invalid filter
+    /// logs at `info`, see `validate` to report it once the subscriber is installed.
+    pub fn layer(&self) -> reload::Layer<EnvFilter, Registry> {
+        let filter: EnvFilter = self.env_filter().unwrap_or_else(|_| EnvFilter::new("info"));
+        let (layer, handle) = reload::Layer::new(filter);
+
+        *self.handle.lock().unwrap() = Some(handle);
+
+        layer
+    }
+
+    fn reload(&self) -> Result<(), LogLevelError> {
+        match self.handle.lock().unwrap().as_ref() {
+            Some(handle) => handle.reload(self.env_filter()?).map_err(|e| LogLevelError::Reload(e.to_string())),
+            // nothing installed (tracing owned by the application), keep the state only
+            None => Ok(()),
+        }
+    }
+
+    /// Change the level of `target` (a module path or `feature_target`),
+    /// reverted after `duration` when given.
+    pub fn set(&self, target: &str, level: &str, duration: Option<Duration>, clock: SharedClock) -> Result<(), LogLevelError> {
+        let level: LevelFilter = level.parse().map_err(|_| LogLevelError::InvalidLevel(level.to_owned()))?;
+
+        format!("{target}={level}").parse::<Directive>()
+            .map_err(|e| LogLevelError::InvalidTarget(e.to_string()))?;
+
+        let generation: u64 = {
+            let mut overrides = self.overrides.lock().unwrap();
+            overrides.generation += 1;
+
+            let generation: u64 = overrides.generation;
+            overrides.levels.insert(target.to_owned(), (level, generation));
+            generation
+        };
+
+        self.reload()?;
+        self.changes.fetch_add(1, Ordering::SeqCst);
+
+        tracing::warn!("log level of {target} set to {level}, filter is now {}", self.filter());
+
+        if let Some(duration) = duration {
+            let levels: LogLevels = self.clone();
+            let target: String = target.to_owned();
+
+            tokio::spawn(async move {
+                clock.sleep(duration).await;
+                levels.revert(&target, generation);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn revert(&self, target: &str, generation: u64) {
+        {
+            let mut overrides = self.overrides.lock().unwrap();
+
+            match overrides.levels.get(target) {
+                Some((_, current)) if *current == generation => { overrides.levels.remove(target); },
+                _ => return,
+            }
+        }
+
+        if let Err(e) = self.reload() {
+            tracing::error!("{e}");
+        }
+        self.changes.fetch_add(1, Ordering::SeqCst);
+
+        tracing::warn!("log level of {target} reverted, filter is now {}", self.filter());
+    }
+
+    /// Replace the configured filter and targets (`RUST_LOG` keeps precedence over the filter),
+    /// runtime overrides stay in place.
+    pub(crate) fn reconfigure(&self, config: &Logging) -> Result<(), LogLevelError> {
+        let base: String = std::env::var("RUST_LOG").unwrap_or_else(|_| config.filter.clone());
+        *self.configured.lock().unwrap() = (base, config.targets.clone());
+
+        self.reload()?;
+        self.changes.fetch_add(1, Ordering::SeqCst);
+
+        tracing::warn!("log filter reconfigured, filter is now {}", self.filter());
+        Ok(())
+    }
+
+    /// Number of changes (set or revert) applied so far.
+    pub fn changes(&self) -> u64 {
+        self.changes.load(Ordering::SeqCst)
+    }
+
+    /// `POST /_blandwork/log-level {"target": "...", "level": "debug", "duration": "10m"}`.
+    /// Mounted with `server.logging.token`, requests then need it as a bearer token (compared
+    /// in constant time). In development it is also mounted without a token, unauthenticated.
+    pub fn router(&self, token: Option<&str>) -> Router {
+        let router: Router = Router::new()
+            .route(LOG_LEVEL_ROUTE, post(set_log_level))
+            .layer(Extension(self.clone()));
+
+        match token {
+            Some(token) => router.layer(Extension(AdminToken(Arc::from(token)))),
+            None => router,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LogLevelRequest {
+    target: String,
+    level: String,
+
+    // "30s", "10m", absent keeps the level until restart
+    duration: Option<String>,
+}
+
+async fn set_log_level(
+    headers: HeaderMap,
+    token: Option<Extension<AdminToken>>,
+    Extension(levels): Extension<LogLevels>,
+    Extension(clock): Extension<SharedClock>,
+    Json(request): Json<LogLevelRequest>,
+) -> Response {
+    if let Some(Extension(token)) = token {
+        if !authorized(&headers, &token) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    let duration: Option<Duration> = match request.duration.as_deref().map(units::parse_duration) {
+        Some(Ok(duration)) => Some(duration),
+        Some(Err(e)) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        None => None,
+    };
+
+    match levels.set(&request.target, &request.level, duration, clock) {
+        Ok(()) => (StatusCode::OK, levels.filter()).into_response(),
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+/// Runs a feature's routes inside a `feature{name=..}` span (target `blandwork::feature`),
+/// so its events can be filtered with `feature_target`.
+#[derive(Clone)]
+pub struct FeatureSpanLayer {
+    name: Arc<str>,
+}
+
+impl FeatureSpanLayer {
+    pub fn new(name: &str) -> Self {
+        Self { name: Arc::from(name) }
+    }
+}
+
+impl<S> Layer<S> for FeatureSpanLayer {
+    type Service = FeatureSpanService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FeatureSpanService { inner, name: self.name.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct FeatureSpanService<S> {
+    inner: S,
+    name: Arc<str>,
+}
+
+impl<S> Service<Request> for FeatureSpanService<S>
+where
+    S: Service<Request> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let span: Span = tracing::info_span!(target: "blandwork::feature", "feature", name = %self.name);
+
+        let inner = span.in_scope(|| self.inner.call(req));
+
+        Box::pin(inner.instrument(span))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, sync::{Arc, Mutex}, time::Duration};
+
+    use axum::{body::Body, extract::Request, routing::get, Extension, Router};
+    use hyper::{header, StatusCode};
+    use tower::ServiceExt;
+    use tracing::{field::{Field, Visit}, Event, Subscriber};
+    use tracing_subscriber::{layer::{Context, SubscriberExt}, Layer, Registry};
+
+    use crate::clock::{SharedClock, TestClock};
+
+    use super::{feature_target, FeatureSpanLayer, LogLevels, LOG_LEVEL_ROUTE};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Capture {
+        fn messages(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl Visit for Capture {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.0.lock().unwrap().push(format!("{value:?}"));
+            }
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+            // the tests' own events, not the framework's level changes
+            if event.metadata().module_path() == Some(module_path!()) {
+                event.record(&mut self.clone());
+            }
+        }
+    }
+
+    fn levels(filter: &str) -> LogLevels {
+        LogLevels::new(filter, BTreeMap::new())
+    }
+
+    #[tokio::test]
+    async fn test_feature_span_target() {
+        let levels = levels("warn");
+        levels.set(&feature_target("Billing"), "debug", None, Arc::new(TestClock::new())).unwrap();
+
+        let capture = Capture::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(levels.layer()).with(capture.clone()));
+
+        let router = Router::new()
+            .route("/billing", get(|| async { tracing::debug!("billing handler"); "" }))
+            .layer(FeatureSpanLayer::new("Billing"))
+            .merge(Router::new()
+                .route("/other", get(|| async { tracing::debug!("other handler"); "" }))
+                .layer(FeatureSpanLayer::new("Other")));
+
+        for uri in ["/billing", "/other"] {
+            router.clone().oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap()).await.unwrap();
+        }
+
+        assert_eq!(capture.messages(), vec!["billing handler"]);
+    }
+
+    #[tokio::test]
+    async fn test_runtime_level_and_revert() {
+        let clock = TestClock::new();
+        let levels = levels("warn");
+
+        let capture = Capture::default();
+        let _guard = tracing::subscriber::set_default(Registry::default().with(levels.layer()).with(capture.clone()));
+
+        tracing::debug!(target: "app::billing", "before");
+
+        levels.set("app::billing", "debug", Some(Duration::from_secs(600)), Arc::new(clock.clone())).unwrap();
+        assert_eq!(levels.filter(), "warn,app::billing=debug");
+
+        tracing::debug!(target: "app::billing", "during");
+
+        // let the revert task start waiting on the clock
+        while clock.pending() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        clock.advance(Duration::from_secs(600));
+
+        while levels.changes() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        tracing::debug!(target: "app::billing", "after");
+
+        assert_eq!(levels.filter(), "warn");
+        assert_eq!(capture.messages(), vec!["during"]);
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        let levels = levels("info");
+
+        assert!(levels.set("app", "loud", None, Arc::new(TestClock::new())).is_err());
+        assert_eq!(levels.filter(), "info");
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_requires_token() {
+        let levels = levels("info");
+        let clock: SharedClock = Arc::new(TestClock::new());
+        let router = levels.router(Some("secret")).layer(Extension(clock));
+
+        let request = |authorization: Option<&str>| {
+            let builder = Request::post(LOG_LEVEL_ROUTE).header(header::CONTENT_TYPE, "application/json");
+            let builder = match authorization {
+                Some(value) => builder.header(header::AUTHORIZATION, value),
+                None => builder,
+            };
+
+            builder.body(Body::from(r#"{"target": "app", "level": "debug"}"#)).unwrap()
+        };
+
+        for authorization in [None, Some("Bearer wrong")] {
+            let response = router.clone().oneshot(request(authorization)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        assert_eq!(levels.filter(), "info");
+
+        let response = router.clone().oneshot(request(Some("Bearer secret"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(levels.filter(), "info,app=debug");
+    }
+
+    #[test]
+    fn test_invalid_filter() {
+        assert!(levels("info").validate().is_ok());
+        assert!(levels("app=loud[").validate().is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex}
+};
+
+use tokio::sync::OnceCell;
+
+type Slot = Arc<OnceCell<Arc<dyn Any + Send + Sync>>>;
+
+/// Request scoped results of `ContextAccessor::memo`, keyed by type and key.
+/// Lives on the `ContextAccessor` (dropped with it) and never crosses requests.
+#[derive(Default)]
+pub struct Memo {
+    slots: Mutex<HashMap<(TypeId, String), Slot>>,
+
+    // lookups answered without running the closure
+    saved: AtomicU64,
+}
+
+impl Memo {
+    fn slot<T: 'static>(&self, key: &str) -> Slot {
+        self.slots.lock().unwrap()
+            .entry((TypeId::of::<T>(), key.to_owned()))
+            .or_default()
+            .clone()
+    }
+
+    /// Run `compute` once for `(T, key)`, concurrent callers wait for the first.
+    /// Errors are not stored, the next caller runs `compute` again.
+    pub async fn get_or_try_init<T, E, F, Fut>(&self, key: &str, compute: F) -> Result<Arc<T>, E>
+    where
+        T: Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let slot: Slot = self.slot::<T>(key);
+        let mut computed: bool = false;
+
+        let value = slot.get_or_try_init(|| async {
+            computed = true;
+            let value: T = compute().await?;
+            Ok::<Arc<dyn Any + Send + Sync>, E>(Arc::new(value))
+        }).await?;
+
+        if !computed {
+            self.saved.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // the slot is keyed by TypeId, it can only hold a T
+        Ok(value.clone().downcast::<T>().expect("memo slot holds its key type"))
+    }
+
+    /// Recomputations avoided so far in this request.
+    pub fn saved(&self) -> u64 {
+        self.saved.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+    use super::Memo;
+
+    #[tokio::test]
+    async fn test_single_flight() {
+        let memo = Arc::new(Memo::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let fragments = (0..4).map(|_| {
+            let memo = memo.clone();
+            let runs = runs.clone();
+
+            tokio::spawn(async move {
+                memo.get_or_try_init("settings", || async {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    tokio::task::yield_now().await;
+                    Ok::<String, ()>("dark".to_owned())
+                }).await.unwrap()
+            })
+        }).collect::<Vec<_>>();
+
+        for fragment in fragments {
+            assert_eq!(*fragment.await.unwrap(), "dark");
+        }
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(memo.saved(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_error_is_not_cached() {
+        let memo = Memo::default();
+
+        let failed = memo.get_or_try_init("user", || async { Err::<u32, &str>("timeout") }).await;
+        assert!(failed.is_err());
+
+        let user = memo.get_or_try_init("user", || async { Ok::<u32, &str>(7) }).await;
+        assert_eq!(*user.unwrap(), 7);
+        assert_eq!(memo.saved(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_keys_and_types_are_isolated() {
+        let memo = Memo::default();
+
+        let a = memo.get_or_try_init("a", || async { Ok::<u32, ()>(1) }).await.unwrap();
+        let b = memo.get_or_try_init("b", || async { Ok::<u32, ()>(2) }).await.unwrap();
+        let named = memo.get_or_try_init("a", || async { Ok::<String, ()>("one".to_owned()) }).await.unwrap();
+
+        assert_eq!((*a, *b, named.as_str()), (1, 2, "one"));
+        assert_eq!(memo.saved(), 0);
+    }
+}
@@ -0,0 +1,71 @@
+use std::{future::Future, pin::Pin, task::{Context as TaskContext, Poll}};
+
+use axum::{body::Body, extract::Request, http::HeaderValue};
+use hyper::{header::ALLOW, Method, Response, StatusCode};
+use tower::{Layer, Service};
+
+/// Answers OPTIONS with the methods a route allows instead of a 405.
+///
+/// Routing already knows the allowed methods (the `Allow` header of its 405),
+/// so handlers are never invoked. CORS preflights are answered by the Cors layer.
+#[derive(Debug, Clone, Default)]
+pub struct OptionsLayer;
+
+impl<S> Layer<S> for OptionsLayer {
+    type Service = OptionsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        OptionsService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct OptionsService<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for OptionsService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let options: bool = req.method() == Method::OPTIONS;
+
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let response: Response<Body> = inner.await?;
+
+            if !options || response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                return Ok(response);
+            }
+
+            let Some(allow) = response.headers().get(ALLOW).and_then(|allow| allow.to_str().ok()) else {
+                return Ok(response);
+            };
+
+            let allow: String = match allow.is_empty() {
+                true => "OPTIONS".to_owned(),
+                false => format!("{allow},OPTIONS"),
+            };
+
+            let mut response: Response<Body> = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NO_CONTENT;
+
+            if let Ok(allow) = HeaderValue::from_str(&allow) {
+                response.headers_mut().insert(ALLOW, allow);
+            }
+
+            Ok(response)
+        })
+    }
+}
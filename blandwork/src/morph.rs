@@ -0,0 +1,336 @@
+use std::{
+    collections::BTreeMap, fmt::Display, future::Future, pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll}
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::Request,
+    response::IntoResponse,
+    routing::get, Router
+};
+use hyper::{header::{CACHE_CONTROL, CONTENT_TYPE}, Response};
+use tower::{Layer, Service};
+
+use crate::transform::BodyTransform;
+
+/// The morph extension script, only routed when `server.morph.enabled`.
+pub const MORPH_SCRIPT_ROUTE: &str = "/_blandwork/morph.js";
+
+const MORPH_SCRIPT: &str = include_str!("../../web/morph.js");
+
+// list items are matched by id when morphed, without one they are matched by position
+const KEYED_ELEMENTS: [&str; 2] = ["li", "tr"];
+
+/// A deterministic element id for an entity rendered in a morphed list,
+/// `morph_id("invoice", 42)` is `invoice-42`. Characters not valid in a
+/// selector without escaping are replaced by `-`.
+pub fn morph_id(entity: &str, key: impl Display) -> String {
+    format!("{entity}-{key}")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect()
+}
+
+/// Set on responses swapped with a `morph:` style, see `Context::swap_morph`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Morphed;
+
+/// Markup a morph would merge incorrectly.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MorphWarning {
+    /// Several elements share the id, the morph keeps only one of them in place.
+    RepeatedId { id: String, count: usize },
+
+    /// A list item without id, a reorder morphs the wrong items into each other.
+    MissingId { tag: String, line: usize },
+}
+
+impl Display for MorphWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MorphWarning::RepeatedId { id, count } => write!(f, "id \"{id}\" is used by {count} elements"),
+            MorphWarning::MissingId { tag, line } => write!(f, "<{tag}> on line {line} has no id, see morph_id"),
+        }
+    }
+}
+
+/// The id of a start tag's attributes (`class="a" id="b"`), None without one.
+fn tag_id(attributes: &str) -> Option<String> {
+    let mut rest: &str = attributes;
+
+    while let Some(index) = rest.find("id") {
+        let before: Option<char> = rest[..index].chars().last();
+        let after: &str = rest[index + 2..].trim_start();
+        rest = &rest[index + 2..];
+
+        if before.map(|c| !c.is_ascii_whitespace()).unwrap_or(false) || !after.starts_with('=') {
+            continue;
+        }
+
+        let value: &str = after[1..].trim_start();
+
+        return Some(match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => value[1..].split(quote).next().unwrap_or_default().to_owned(),
+            _ => value.split(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/').next().unwrap_or_default().to_owned(),
+        });
+    }
+
+    None
+}
+
+/// Repeated ids and list items without an id in a morphed fragment.
+pub fn check_ids(html: &str) -> Vec<MorphWarning> {
+    let mut warnings: Vec<MorphWarning> = Vec::new();
+    let mut ids: BTreeMap<String, usize> = BTreeMap::new();
+    let mut position: usize = 0;
+
+    while let Some(offset) = html[position..].find('<') {
+        let start: usize = position + offset + 1;
+        position = start;
+
+        let name_length: usize = html[start..]
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(html.len() - start);
+
+        // closing tags, comments and doctype
+        if name_length == 0 {
+            continue;
+        }
+
+        let name: String = html[start..start + name_length].to_ascii_lowercase();
+
+        // the end of the start tag, '>' inside quoted values doesn't count
+        let mut quote: Option<char> = None;
+        let mut end: usize = html.len();
+        for (index, c) in html[start..].char_indices() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '>') => {
+                    end = start + index;
+                    break;
+                },
+                _ => {},
+            }
+        }
+
+        match tag_id(&html[start + name_length..end]) {
+            Some(id) => *ids.entry(id).or_default() += 1,
+            None if KEYED_ELEMENTS.contains(&name.as_str()) => warnings.push(MorphWarning::MissingId {
+                tag: name,
+                line: html[..start].matches('\n').count() + 1,
+            }),
+            None => {},
+        }
+
+        position = end;
+    }
+
+    for (id, count) in ids.into_iter().filter(|(_, count)| *count > 1) {
+        warnings.push(MorphWarning::RepeatedId { id, count });
+    }
+
+    warnings
+}
+
+/// Adds the morph script to full pages, after the application's own scripts in `<head>`.
+pub(crate) fn morph_script() -> BodyTransform {
+    let script: String = format!("<script src=\"{MORPH_SCRIPT_ROUTE}\" defer></script>");
+
+    Arc::new(move |_, html| match html.find("</head>") {
+        Some(index) => {
+            let mut html: String = html;
+            html.insert_str(index, &script);
+            html
+        },
+        None => html,
+    })
+}
+
+pub(crate) fn router() -> Router {
+    Router::new().route(MORPH_SCRIPT_ROUTE, get(|| async {
+        ([(CONTENT_TYPE, "text/javascript"), (CACHE_CONTROL, "public, max-age=3600")], MORPH_SCRIPT).into_response()
+    }))
+}
+
+/// Development only: logs what `check_ids` finds in morphed fragments.
+#[derive(Clone, Default)]
+pub struct MorphCheckLayer;
+
+impl<S> Layer<S> for MorphCheckLayer {
+    type Service = MorphCheckService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MorphCheckService { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct MorphCheckService<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for MorphCheckService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let path: String = req.uri().path().to_owned();
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let response: Response<Body> = inner.await?;
+
+            let html: bool = response.headers().get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.starts_with("text/html"))
+                .unwrap_or(false);
+
+            if !html || response.extensions().get::<Morphed>().is_none() {
+                return Ok(response);
+            }
+
+            let (parts, body) = response.into_parts();
+
+            let bytes = match to_bytes(body, usize::MAX).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    tracing::error!("unable to check the morphed fragment of {path}: {e}");
+                    return Ok(Response::from_parts(parts, Body::empty()));
+                }
+            };
+
+            for warning in check_ids(&String::from_utf8_lossy(&bytes)) {
+                tracing::warn!("morphed fragment of {path}: {warning}");
+            }
+
+            Ok(Response::from_parts(parts, Body::from(bytes)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use axum_htmx::{HX_REQUEST, HX_RESWAP};
+    use hyper::StatusCode;
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{config::Morph, context::ContextLayer, test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{check_ids, morph_id, MorphWarning, MORPH_SCRIPT_ROUTE};
+
+    #[test]
+    fn test_morph_id() {
+        assert_eq!(morph_id("invoice", 42), "invoice-42");
+        assert_eq!(morph_id("user", "a.b@example.com"), "user-a-b-example-com");
+    }
+
+    #[test]
+    fn test_well_keyed_list() {
+        let html = html! {
+            ul #invoices {
+                @for id in [1, 2, 3] {
+                    li id=(morph_id("invoice", id)) data-label="a > b" { (id) }
+                }
+            }
+        }.into_string();
+
+        assert!(check_ids(&html).is_empty());
+    }
+
+    #[test]
+    fn test_repeated_and_missing_ids() {
+        let html = "<table>\n<tr id=\"row-1\"><td>1</td></tr>\n<tr><td>2</td></tr>\n<tr id='row-1'><td>3</td></tr>\n</table>\n<div data-id=\"x\" id=row-1></div>";
+
+        assert_eq!(check_ids(html), vec![
+            MorphWarning::MissingId { tag: "tr".to_owned(), line: 3 },
+            MorphWarning::RepeatedId { id: "row-1".to_owned(), count: 3 },
+        ]);
+    }
+
+    async fn swap(htmx: bool, enabled: bool) -> Option<String> {
+        let router = Router::new()
+            .route("/invoices", get(|Extension(accessor): Extension<ContextAccessor>| async move {
+                accessor.context().await.swap_morph();
+                html! { li #invoice-1 { "1" } }
+            }))
+            .layer(ContextLayer::new())
+            .layer(Extension(std::sync::Arc::new(Morph { enabled })));
+
+        let mut request = Request::get("/invoices");
+        if htmx {
+            request = request.header(HX_REQUEST, "true");
+        }
+
+        let response = router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+
+        response.headers().get(HX_RESWAP).map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    #[tokio::test]
+    async fn test_swap_modifier() {
+        assert_eq!(swap(true, true).await.as_deref(), Some("morph:innerHTML"));
+
+        // without the script a morph swap would not be understood
+        assert_eq!(swap(true, false).await, None);
+        assert_eq!(swap(false, true).await, None);
+    }
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { head {} body { (body) } } }
+        }
+    }
+
+    struct Invoices;
+
+    impl Feature for Invoices {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/invoices", get(|| async { html! { p { "invoices" } } })))
+        }
+    }
+
+    async fn get_body(router: &Router, uri: &str) -> (StatusCode, String) {
+        let response = router.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_script_gated_by_config() {
+        let app = |enabled: bool| {
+            let mut config = Config::default();
+            config.server.morph.enabled = enabled;
+
+            router(&App::new(config, TestTemplate).register_feature(Invoices).build())
+        };
+
+        let enabled = app(true);
+        let (status, script) = get_body(&enabled, MORPH_SCRIPT_ROUTE).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(script.contains("defineExtension(\"morph\""));
+        assert!(get_body(&enabled, "/invoices").await.1.contains(&format!("<script src=\"{MORPH_SCRIPT_ROUTE}\" defer></script></head>")));
+
+        let disabled = app(false);
+        assert_eq!(get_body(&disabled, MORPH_SCRIPT_ROUTE).await.0, StatusCode::NOT_FOUND);
+        assert!(!get_body(&disabled, "/invoices").await.1.contains(MORPH_SCRIPT_ROUTE));
+    }
+}
@@ -0,0 +1,493 @@
+use std::{collections::{BTreeMap, BTreeSet}, future::Future, pin::Pin, sync::Arc};
+
+use async_trait::async_trait;
+use axum::{extract::Query, http::Extensions, response::{IntoResponse, Response}, routing::{get, post}, Extension, Form, Router};
+use hyper::StatusCode;
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{context::ContextLayer, guard::HtmxOnlyLayer, morph_id, ContextAccessor, Feature, HandlerError, TypedCookie};
+
+/// Child fragments of the navigation trees, expanding a node persists it in `NavTreePrefs`.
+pub const NAV_TREE_ROUTE: &str = "/_blandwork/nav";
+
+/// Triggered by boosted navigation with the new route, moves the active node of the trees.
+pub const NAV_ACTIVE: &str = "blandwork:nav-active";
+
+// expanded nodes kept per tree, the oldest are forgotten beyond it
+const MAX_EXPANDED: usize = 100;
+
+// ancestry walks stop there, a provider linking a node to its ancestor would loop
+const MAX_DEPTH: usize = 32;
+
+/// One entry of a `NavTree`, `has_children` shows the expand button without loading them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NavNode {
+    pub id: String,
+    pub label: String,
+    pub route: String,
+    pub has_children: bool,
+}
+
+impl NavNode {
+    pub fn new(id: impl Into<String>, label: impl Into<String>, route: impl Into<String>) -> Self {
+        Self { id: id.into(), label: label.into(), route: route.into(), has_children: false }
+    }
+
+    pub fn with_children(mut self) -> Self {
+        self.has_children = true;
+        self
+    }
+
+    /// The node's route is `route` or one of its parents.
+    fn covers(&self, route: &str) -> bool {
+        let own: &str = self.route.trim_end_matches('/');
+
+        match own.is_empty() {
+            true => route == "/",
+            false => route == own || route.starts_with(&format!("{own}/")),
+        }
+    }
+}
+
+/// Hierarchical navigation of a feature (docs sections, folders) loaded a level at a time,
+/// see `Feature::nav_tree`. Rendered into the shell by `Context::nav_tree`.
+#[async_trait]
+pub trait NavTree: Send + Sync {
+    /// Children of the node `parent`, the roots for `None`.
+    async fn children(&self, parent: Option<&str>, extensions: &Extensions) -> Result<Vec<NavNode>, HandlerError>;
+
+    /// Nodes from a root down to the node of `route`, expanded on full page loads.
+    /// By default walks down through the children with the longest route covering it,
+    /// providers knowing their parents can answer without loading every level.
+    async fn ancestry(&self, route: &str, extensions: &Extensions) -> Result<Vec<NavNode>, HandlerError> {
+        let mut ancestry: Vec<NavNode> = Vec::new();
+        let mut parent: Option<String> = None;
+
+        while ancestry.len() < MAX_DEPTH {
+            let children: Vec<NavNode> = self.children(parent.as_deref(), extensions).await?;
+
+            let Some(node) = children.into_iter()
+                .filter(|node| node.covers(route))
+                .max_by_key(|node| node.route.len()) else {
+                break;
+            };
+
+            let descend: bool = node.has_children && node.route.trim_end_matches('/') != route;
+            parent = Some(node.id.clone());
+            ancestry.push(node);
+
+            if !descend {
+                break;
+            }
+        }
+
+        Ok(ancestry)
+    }
+}
+
+/// Payload of `NAV_ACTIVE`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct NavActive {
+    pub route: String,
+}
+
+/// Nodes a user expanded, by tree.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NavTreePrefs {
+    pub expanded: BTreeMap<String, Vec<String>>,
+}
+
+impl TypedCookie for NavTreePrefs {
+    const NAME: &'static str = "blandwork_nav";
+}
+
+impl NavTreePrefs {
+    pub fn is_expanded(&self, tree: &str, node: &str) -> bool {
+        self.expanded.get(tree).map(|nodes| nodes.iter().any(|id| id == node)).unwrap_or(false)
+    }
+
+    pub fn expand(&mut self, tree: &str, node: &str) {
+        let nodes: &mut Vec<String> = self.expanded.entry(tree.to_owned()).or_default();
+        nodes.retain(|id| id != node);
+        nodes.push(node.to_owned());
+
+        if nodes.len() > MAX_EXPANDED {
+            nodes.drain(..nodes.len() - MAX_EXPANDED);
+        }
+    }
+
+    pub fn collapse(&mut self, tree: &str, node: &str) {
+        if let Some(nodes) = self.expanded.get_mut(tree) {
+            nodes.retain(|id| id != node);
+
+            if nodes.is_empty() {
+                self.expanded.remove(tree);
+            }
+        }
+    }
+}
+
+/// Every feature's tree by feature name, provided to the shell and the fragments by `build`.
+#[derive(Clone, Default)]
+pub struct NavTrees(pub Arc<Vec<(String, Arc<dyn NavTree>)>>);
+
+pub(crate) fn nav_trees(features: &[Box<dyn Feature>]) -> NavTrees {
+    NavTrees(Arc::new(features.iter()
+        .filter_map(|feature| feature.nav_tree().map(|tree| (feature.name(), tree)))
+        .collect()))
+}
+
+impl NavTrees {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn NavTree>> {
+        self.0.iter().find(|(tree, _)| tree == name).map(|(_, tree)| tree.clone())
+    }
+
+    /// Every tree with the ancestry of `route` and the user's expanded nodes open.
+    pub(crate) async fn render(&self, route: &str, prefs: &NavTreePrefs, extensions: &Extensions) -> BTreeMap<String, Markup> {
+        let mut rendered: BTreeMap<String, Markup> = BTreeMap::new();
+
+        for (name, tree) in self.0.iter() {
+            let ancestry: Vec<NavNode> = tree.ancestry(route, extensions).await.unwrap_or_else(|e| {
+                tracing::error!("unable to resolve the {name} navigation of {route}: {e}");
+                Vec::new()
+            });
+
+            let state: TreeState = TreeState {
+                name,
+                prefs,
+                active: ancestry.last().map(|node| node.id.clone()),
+                ancestry: ancestry.into_iter().map(|node| node.id).collect(),
+                extensions,
+            };
+
+            let roots: Markup = children(tree.as_ref(), None, &state).await;
+
+            rendered.insert(name.clone(), html!{
+                nav class="blandwork-nav-tree" data-nav-tree=(name) {
+                    (roots)
+                }
+            });
+        }
+
+        rendered
+    }
+}
+
+struct TreeState<'a> {
+    name: &'a str,
+    prefs: &'a NavTreePrefs,
+    active: Option<String>,
+    ancestry: BTreeSet<String>,
+    extensions: &'a Extensions,
+}
+
+impl TreeState<'_> {
+    fn is_open(&self, node: &NavNode) -> bool {
+        node.has_children && (self.ancestry.contains(&node.id) || self.prefs.is_expanded(self.name, &node.id))
+    }
+}
+
+/// `<ul>` of the children of `parent`, open children render theirs too.
+fn children<'a>(tree: &'a dyn NavTree, parent: Option<&'a str>, state: &'a TreeState<'a>) -> Pin<Box<dyn Future<Output = Markup> + Send + 'a>> {
+    Box::pin(async move {
+        let nodes: Vec<NavNode> = match tree.children(parent, state.extensions).await {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                tracing::error!("unable to load the {} navigation below {parent:?}: {e}", state.name);
+                return html!{};
+            }
+        };
+
+        let mut items: Vec<Markup> = Vec::with_capacity(nodes.len());
+
+        for node in nodes.iter() {
+            let branch: Option<Markup> = match node.has_children {
+                true => Some(branch(tree, state, &node.id, state.is_open(node)).await),
+                false => None,
+            };
+
+            let active: bool = state.active.as_deref() == Some(node.id.as_str());
+
+            items.push(html!{
+                li {
+                    a href=(node.route)
+                        data-nav-route=(node.route)
+                        class=[active.then_some("active")]
+                        aria-current=[active.then_some("page")]
+                        hx-target="#content"
+                        hx-swap="innerHTML" {
+                            (node.label)
+                        }
+                    @if let Some(branch) = branch {
+                        (branch)
+                    }
+                }
+            });
+        }
+
+        html!{
+            ul role="group" {
+                @for item in items {
+                    (item)
+                }
+            }
+        }
+    })
+}
+
+/// The expand button of `node` and, when open, its children. Swapped as a whole by the button.
+async fn branch(tree: &dyn NavTree, state: &TreeState<'_>, node: &str, open: bool) -> Markup {
+    let vals: String = json!({ "tree": state.name, "node": node }).to_string();
+
+    let children: Option<Markup> = match open {
+        true => Some(children(tree, Some(node), state).await),
+        false => None,
+    };
+
+    html!{
+        div id=(morph_id("nav", format!("{}-{node}", state.name))) class="blandwork-nav-branch" {
+            @if open {
+                button type="button" aria-expanded="true" aria-label="Collapse"
+                    hx-post=(format!("{NAV_TREE_ROUTE}/collapse")) hx-vals=(vals)
+                    hx-target="closest .blandwork-nav-branch" hx-swap="outerHTML" { "▾" }
+            } @else {
+                button type="button" aria-expanded="false" aria-label="Expand"
+                    hx-get=(NAV_TREE_ROUTE) hx-vals=(vals)
+                    hx-target="closest .blandwork-nav-branch" hx-swap="outerHTML" { "▸" }
+            }
+            @if let Some(children) = children {
+                (children)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NavQuery {
+    tree: String,
+    node: String,
+}
+
+/// Expands a node: its branch with the children loaded.
+async fn expand(
+    Extension(trees): Extension<NavTrees>,
+    Extension(accessor): Extension<ContextAccessor>,
+    extensions: Extensions,
+    Query(query): Query<NavQuery>
+) -> Response {
+    toggle(trees, accessor, query, &extensions, true).await
+}
+
+/// Collapses a node: its branch without the children.
+async fn collapse(
+    Extension(trees): Extension<NavTrees>,
+    Extension(accessor): Extension<ContextAccessor>,
+    extensions: Extensions,
+    Form(query): Form<NavQuery>
+) -> Response {
+    toggle(trees, accessor, query, &extensions, false).await
+}
+
+async fn toggle(trees: NavTrees, accessor: ContextAccessor, query: NavQuery, extensions: &Extensions, open: bool) -> Response {
+    let Some(tree) = trees.get(&query.tree) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let mut prefs: NavTreePrefs = accessor.context().await.typed_cookie::<NavTreePrefs>().unwrap_or_default();
+
+    match open {
+        true => prefs.expand(&query.tree, &query.node),
+        false => prefs.collapse(&query.tree, &query.node),
+    }
+
+    if let Err(e) = accessor.context().await.set_cookie(&prefs) {
+        tracing::error!("unable to keep the {} navigation state: {e}", query.tree);
+    }
+
+    let state: TreeState = TreeState {
+        name: &query.tree,
+        prefs: &prefs,
+        active: None,
+        ancestry: BTreeSet::new(),
+        extensions,
+    };
+
+    branch(tree.as_ref(), &state, &query.node, open).await.into_response()
+}
+
+/// Fragments of the trees, requested by the expand buttons only.
+pub(crate) fn router(trees: NavTrees) -> Router {
+    Router::new()
+        .route(NAV_TREE_ROUTE, get(expand))
+        .route(&format!("{NAV_TREE_ROUTE}/collapse"), post(collapse))
+        .layer(ContextLayer::new())
+        .layer(HtmxOnlyLayer)
+        .layer(Extension(trees))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use axum::{body::{to_bytes, Body}, extract::Request, http::Extensions, response::IntoResponse, routing::get, Extension, Router};
+    use axum_htmx::{HX_BOOSTED, HX_REQUEST, HX_TRIGGER};
+    use hyper::{header::{CONTENT_TYPE, COOKIE, SET_COOKIE}, StatusCode};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{test::router, App, Config, Context, ContextAccessor, Feature, HandlerError, Template};
+
+    use super::{NavNode, NavTree, NAV_ACTIVE, NAV_TREE_ROUTE};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, context: &Context, body: Markup) -> Markup {
+            html! { html { body { aside { (context.nav_tree("Docs")) } main { (body) } } } }
+        }
+    }
+
+    // guide > install > linux, guide > usage, reference
+    struct DocsTree;
+
+    #[async_trait]
+    impl NavTree for DocsTree {
+        async fn children(&self, parent: Option<&str>, _: &Extensions) -> Result<Vec<NavNode>, HandlerError> {
+            Ok(match parent {
+                None => vec![
+                    NavNode::new("guide", "Guide", "/docs/guide").with_children(),
+                    NavNode::new("reference", "Reference", "/docs/reference"),
+                ],
+                Some("guide") => vec![
+                    NavNode::new("install", "Install", "/docs/guide/install").with_children(),
+                    NavNode::new("usage", "Usage", "/docs/guide/usage"),
+                ],
+                Some("install") => vec![NavNode::new("linux", "Linux", "/docs/guide/install/linux")],
+                Some(_) => Vec::new(),
+            })
+        }
+    }
+
+    struct Docs;
+
+    impl Feature for Docs {
+        fn name(&self) -> String {
+            "Docs".to_owned()
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/docs/*page", get(|Extension(accessor): Extension<ContextAccessor>| async move {
+                let _ = accessor.context().await;
+                "page".into_response()
+            })))
+        }
+
+        fn nav_tree(&self) -> Option<Arc<dyn NavTree>> {
+            Some(Arc::new(DocsTree))
+        }
+    }
+
+    fn app() -> Router {
+        let mut app = App::new(Config::default(), TestTemplate).register_feature(Docs);
+        router(&app.build())
+    }
+
+    async fn get_page(router: Router, request: Request) -> (StatusCode, hyper::HeaderMap, String) {
+        let response = router.oneshot(request).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+        (parts.status, parts.headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_deep_link_renders_ancestry() {
+        let request = Request::get("/docs/guide/install/linux").body(Body::empty()).unwrap();
+        let (status, _, html) = get_page(app(), request).await;
+
+        assert_eq!(status, StatusCode::OK);
+
+        // every level down to the page is there, the active node is marked
+        assert!(html.contains(r#"href="/docs/guide/usage""#));
+        assert!(html.contains(r##"class="active" aria-current="page" hx-target="#content" hx-swap="innerHTML">Linux"##));
+        assert!(html.contains(r#"aria-expanded="true""#));
+        assert!(!html.contains(r##"class="active" aria-current="page" hx-target="#content" hx-swap="innerHTML">Guide"##));
+
+        // siblings of the ancestry are not loaded
+        let request = Request::get("/docs/reference").body(Body::empty()).unwrap();
+        let (_, _, html) = get_page(app(), request).await;
+
+        assert!(html.contains(r##"aria-current="page" hx-target="#content" hx-swap="innerHTML">Reference"##));
+        assert!(!html.contains("/docs/guide/usage"));
+        assert!(html.contains(r#"aria-expanded="false""#));
+    }
+
+    #[tokio::test]
+    async fn test_lazy_children_persist_expansion() {
+        let request = Request::get(format!("{NAV_TREE_ROUTE}?tree=Docs&node=guide"))
+            .header(HX_REQUEST, "true")
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, html) = get_page(app(), request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.starts_with(r#"<div id="nav-Docs-guide""#));
+        assert!(html.contains("/docs/guide/install"));
+        assert!(!html.contains("/docs/guide/install/linux"));
+
+        let cookie: String = headers.get(SET_COOKIE).unwrap().to_str().unwrap().split(';').next().unwrap().to_owned();
+        assert!(cookie.starts_with("blandwork_nav="));
+
+        // the next full page keeps the node open
+        let request = Request::get("/docs/reference").header(COOKIE, &cookie).body(Body::empty()).unwrap();
+        let (_, _, html) = get_page(app(), request).await;
+        assert!(html.contains("/docs/guide/usage"));
+
+        // collapsing forgets it
+        let request = Request::post(format!("{NAV_TREE_ROUTE}/collapse"))
+            .header(HX_REQUEST, "true")
+            .header(COOKIE, &cookie)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from("tree=Docs&node=guide"))
+            .unwrap();
+        let (status, headers, html) = get_page(app(), request).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(!html.contains("/docs/guide/install"));
+
+        let cookie: String = headers.get(SET_COOKIE).unwrap().to_str().unwrap().split(';').next().unwrap().to_owned();
+        let request = Request::get("/docs/reference").header(COOKIE, &cookie).body(Body::empty()).unwrap();
+        let (_, _, html) = get_page(app(), request).await;
+        assert!(!html.contains("/docs/guide/usage"));
+
+        // unknown trees and direct navigation
+        let request = Request::get(format!("{NAV_TREE_ROUTE}?tree=Blog&node=guide")).header(HX_REQUEST, "true").body(Body::empty()).unwrap();
+        assert_eq!(get_page(app(), request).await.0, StatusCode::NOT_FOUND);
+
+        let request = Request::get(format!("{NAV_TREE_ROUTE}?tree=Docs&node=guide")).body(Body::empty()).unwrap();
+        assert_eq!(get_page(app(), request).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_boosted_navigation_moves_highlight() {
+        let request = Request::get("/docs/guide/usage")
+            .header(HX_REQUEST, "true")
+            .header(HX_BOOSTED, "true")
+            .body(Body::empty())
+            .unwrap();
+        let (_, headers, html) = get_page(app(), request).await;
+
+        // the fragment alone, the tree already on the page is updated by the event
+        assert_eq!(html, "page");
+
+        let trigger: serde_json::Value = serde_json::from_str(headers.get(HX_TRIGGER).unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(trigger[NAV_ACTIVE]["route"], "/docs/guide/usage");
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    collections::HashMap, future::Future, pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll},
+    time::{Duration, SystemTime}
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::Request,
+    response::IntoResponse
+};
+use hyper::{header::{COOKIE, RETRY_AFTER}, http::HeaderValue, HeaderMap, Method, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+
+use crate::{clock::{SharedClock, SystemClock}, config::Offline, transform::BodyTransform, CurrentUser};
+
+/// Sent by the integration script with every hx-post/put/patch/delete while offline tolerance is on.
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Marks a submission replayed from the browser's offline queue.
+pub const REPLAYED: &str = "x-blandwork-replayed";
+
+/// Set on responses answered from an earlier processing of the same key.
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Enables the offline queue of the integration script, added to full pages.
+pub(crate) fn offline_meta(config: &Offline) -> BodyTransform {
+    let meta: String = format!("<meta name=\"blandwork-offline\" content=\"retry={}\">", config.retry.as_millis());
+
+    Arc::new(move |_, html| match html.find("</head>") {
+        Some(index) => {
+            let mut html: String = html;
+            html.insert_str(index, &meta);
+            html
+        },
+        None => html,
+    })
+}
+
+type Identity = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+type Entries = Arc<Mutex<HashMap<String, Entry>>>;
+
+#[derive(Clone)]
+enum Entry {
+    InFlight,
+    Done {
+        at: SystemTime,
+        status: StatusCode,
+        headers: HeaderMap,
+        body: Bytes,
+    },
+}
+
+/// Processes a keyed submission once: a repeat of the same `Idempotency-Key` (a replay
+/// whose first attempt did reach the server) gets the stored response, a repeat while the
+/// first is still processing gets `409`. Server errors are not kept so the retry runs again,
+/// nor is a submission whose client went away before it was answered.
+///
+/// Keys are scoped to the requester (the signed in `CurrentUser`, else the request's cookies,
+/// unless `identity` says otherwise): another requester presenting the same key is processed
+/// on its own and never gets the stored response.
+#[derive(Clone)]
+pub struct IdempotencyLayer {
+    entries: Entries,
+    ttl: Duration,
+    clock: SharedClock,
+    identity: Identity,
+}
+
+impl IdempotencyLayer {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+            clock: Arc::new(SystemClock),
+            identity: Arc::new(|request: &Request| {
+                request.extensions().get::<CurrentUser>().map(|user| format!("user:{}", user.0))
+                    .or_else(|| request.headers().get(COOKIE).map(|cookie| format!("cookie:{}", String::from_utf8_lossy(cookie.as_bytes()))))
+            }),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Who a key belongs to, e.g. the session id read from its cookie.
+    pub fn identity(mut self, identity: impl Fn(&Request) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+}
+
+/// Removes the `InFlight` entry unless the submission was answered, a request dropped
+/// midway (the client went away) would otherwise answer `409` to every replay.
+struct InFlight {
+    entries: Entries,
+    key: Option<String>,
+}
+
+impl InFlight {
+    fn done(mut self, entry: Entry) {
+        if let Some(key) = self.key.take() {
+            self.entries.lock().unwrap().insert(key, entry);
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.entries.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+/// The requester's identity, hashed so raw cookies are not kept as keys.
+fn scope(identity: Option<String>) -> String {
+    let Some(identity) = identity else {
+        return "-".to_owned();
+    };
+
+    Sha256::digest(identity.as_bytes())[..16].iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+impl<S> Layer<S> for IdempotencyLayer {
+    type Service = IdempotencyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        IdempotencyService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct IdempotencyService<S> {
+    inner: S,
+    layer: IdempotencyLayer,
+}
+
+fn replay(status: StatusCode, headers: &HeaderMap, body: &Bytes) -> Response<Body> {
+    let mut response: Response<Body> = Response::new(Body::from(body.clone()));
+
+    *response.status_mut() = status;
+    *response.headers_mut() = headers.clone();
+    response.headers_mut().insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+
+    response
+}
+
+impl<S> Service<Request> for IdempotencyService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let unsafe_method: bool = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+        let key: Option<&str> = req.headers().get(IDEMPOTENCY_KEY).and_then(|value| value.to_str().ok());
+
+        let key: String = match key {
+            Some(key) if unsafe_method && !key.is_empty() && key.len() <= 128 => {
+                format!("{} {} {} {key}", scope((self.layer.identity)(&req)), req.method(), req.uri().path())
+            },
+            _ => return Box::pin(self.inner.call(req)),
+        };
+
+        let now: SystemTime = self.layer.clock.now();
+        let ttl: Duration = self.layer.ttl;
+
+        {
+            let mut entries = self.layer.entries.lock().unwrap();
+
+            entries.retain(|_, entry| match entry {
+                Entry::Done { at, .. } => now.duration_since(*at).unwrap_or_default() < ttl,
+                Entry::InFlight => true,
+            });
+
+            match entries.get(&key) {
+                Some(Entry::Done { status, headers, body, .. }) => {
+                    let response: Response<Body> = replay(*status, headers, body);
+                    return Box::pin(async move { Ok(response) });
+                },
+                Some(Entry::InFlight) => {
+                    let response: Response<Body> = (StatusCode::CONFLICT, [(RETRY_AFTER, "1")]).into_response();
+                    return Box::pin(async move { Ok(response) });
+                },
+                None => {
+                    entries.insert(key.clone(), Entry::InFlight);
+                },
+            }
+        }
+
+        // dropped with the future, the entry is gone unless `done`
+        let in_flight: InFlight = InFlight { entries: self.layer.entries.clone(), key: Some(key) };
+        let clock: SharedClock = self.layer.clock.clone();
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            let response: Response<Body> = inner.await?;
+
+            if response.status().is_server_error() {
+                return Ok(response);
+            }
+
+            let (parts, body) = response.into_parts();
+
+            let body: Bytes = match to_bytes(body, usize::MAX).await {
+                Ok(body) => body,
+                Err(e) => {
+                    tracing::error!("unable to buffer the response of an idempotent request: {e}");
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+                }
+            };
+
+            in_flight.done(Entry::Done {
+                at: clock.now(),
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+            });
+
+            Ok(Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::post, Extension, Router};
+    use hyper::{header::COOKIE, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, config::Offline, context::ContextLayer, ContextAccessor};
+
+    use super::{offline_meta, IdempotencyLayer, IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED, REPLAYED};
+
+    fn router(calls: Arc<AtomicUsize>, clock: TestClock) -> Router {
+        Router::new()
+            .route("/visits", post(move |Extension(accessor): Extension<ContextAccessor>| {
+                let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+
+                async move {
+                    let replayed = accessor.context().await.is_replayed();
+                    format!("visit {call} replayed={replayed}")
+                }
+            }))
+            .route("/fail", post(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+            .route("/hang", post(|| std::future::pending::<StatusCode>()))
+            .layer(ContextLayer::new())
+            .layer(IdempotencyLayer::new(Duration::from_secs(60)).with_clock(Arc::new(clock)))
+    }
+
+    async fn submit(router: &Router, uri: &str, key: Option<&str>, replayed: bool) -> (StatusCode, bool, String) {
+        let mut request = Request::post(uri);
+
+        if let Some(key) = key {
+            request = request.header(IDEMPOTENCY_KEY, key);
+        }
+        if replayed {
+            request = request.header(REPLAYED, "true");
+        }
+
+        let response = router.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let from_store = response.headers().contains_key(IDEMPOTENT_REPLAYED);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, from_store, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_replay_is_processed_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = router(calls.clone(), TestClock::new());
+
+        assert_eq!(submit(&router, "/visits", Some("k1"), false).await, (StatusCode::OK, false, "visit 1 replayed=false".to_owned()));
+
+        // the response got lost, the queued replay must not record a second visit
+        assert_eq!(submit(&router, "/visits", Some("k1"), true).await, (StatusCode::OK, true, "visit 1 replayed=false".to_owned()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // the first attempt never arrived, the replay is processed and flagged
+        assert_eq!(submit(&router, "/visits", Some("k2"), true).await, (StatusCode::OK, false, "visit 2 replayed=true".to_owned()));
+
+        // no key, no deduplication
+        submit(&router, "/visits", None, false).await;
+        submit(&router, "/visits", None, false).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_failed_keys_are_processed_again() {
+        let clock = TestClock::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = router(calls.clone(), clock.clone());
+
+        submit(&router, "/visits", Some("k1"), false).await;
+        clock.advance(Duration::from_secs(61));
+
+        assert_eq!(submit(&router, "/visits", Some("k1"), true).await.2, "visit 2 replayed=true");
+
+        let (status, from_store, _) = submit(&router, "/fail", Some("k3"), false).await;
+        assert_eq!((status, from_store), (StatusCode::SERVICE_UNAVAILABLE, false));
+
+        let (_, from_store, _) = submit(&router, "/fail", Some("k3"), true).await;
+        assert!(!from_store);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_key_is_released() {
+        let router = router(Arc::new(AtomicUsize::new(0)), TestClock::new());
+
+        // the client goes away while the submission is processed
+        let request = Request::post("/hang").header(IDEMPOTENCY_KEY, "k1").body(Body::empty()).unwrap();
+        let abandoned = tokio::time::timeout(Duration::from_millis(10), router.clone().oneshot(request)).await;
+        assert!(abandoned.is_err());
+
+        let request = Request::post("/hang").header(IDEMPOTENCY_KEY, "k1").body(Body::empty()).unwrap();
+        let replay = tokio::time::timeout(Duration::from_millis(10), router.clone().oneshot(request)).await;
+
+        // processed again (and hanging again) instead of an immediate 409
+        assert!(replay.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_scoped_to_the_requester() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let router = router(calls.clone(), TestClock::new());
+
+        let send = |cookie: &'static str| {
+            let request = Request::post("/visits").header(IDEMPOTENCY_KEY, "k1").header(COOKIE, cookie).body(Body::empty()).unwrap();
+            router.clone().oneshot(request)
+        };
+
+        assert!(!send("id=ada").await.unwrap().headers().contains_key(IDEMPOTENT_REPLAYED));
+        assert!(send("id=ada").await.unwrap().headers().contains_key(IDEMPOTENT_REPLAYED));
+
+        // the same key from someone else is theirs alone
+        let other = send("id=bob").await.unwrap();
+        assert!(!other.headers().contains_key(IDEMPOTENT_REPLAYED));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_meta_enables_the_queue() {
+        let transform = offline_meta(&Offline { enabled: true, retry: Duration::from_secs(15), ..Default::default() });
+        let request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert_eq!(
+            transform(&context, "<html><head></head><body></body></html>".to_owned()),
+            "<html><head><meta name=\"blandwork-offline\" content=\"retry=15000\"></head><body></body></html>"
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error, fmt::Display,
+    future::Future, pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime}
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::Request,
+    http::{request::Parts, Extensions},
+    response::{IntoResponse, Response},
+    routing::{get, post}, Extension, Router
+};
+use hyper::StatusCode;
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::{Clock, SharedClock, SystemClock},
+    cookies::TypedCookie,
+    events::{EventBus, Topic, EVENTS_ROUTE},
+    ContextAccessor, Feature
+};
+
+pub const ONBOARDING_ROUTE: &str = "/_blandwork/onboarding";
+
+/// Published (and copied to the browser) when an item is completed,
+/// open checklists refresh themselves and the page may celebrate.
+pub const ONBOARDING_UPDATED: Topic<OnboardingUpdated> = Topic::new("onboarding:updated");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OnboardingUpdated {
+    pub item: String,
+}
+
+#[derive(Debug)]
+pub enum OnboardingError {
+    Store(String),
+}
+
+impl Display for OnboardingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnboardingError::Store(e) => write!(f, "onboarding store error: {e}"),
+        }
+    }
+}
+
+impl Error for OnboardingError {}
+
+/// What a completion check gets to look at: the user and the request extensions
+/// (the pool, the event bus and whatever the application added).
+pub struct OnboardingScope {
+    pub user: String,
+    pub extensions: Extensions,
+}
+
+#[cfg(feature = "postgres")]
+impl OnboardingScope {
+    pub fn pool(&self) -> Option<&crate::db::ConnectionPool> {
+        self.extensions.get::<crate::db::ConnectionPool>()
+    }
+}
+
+type CheckFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+type Check = Arc<dyn Fn(OnboardingScope) -> CheckFuture + Send + Sync>;
+
+/// A "getting started" step reported by a feature, see `Feature::onboarding`.
+#[derive(Clone)]
+pub struct OnboardingItem {
+    pub id: String,
+    pub label: String,
+    pub href: String,
+
+    // lower first, features keep their registration order otherwise
+    pub order: i32,
+
+    check: Check,
+}
+
+impl OnboardingItem {
+    /// `check` decides whether the step is done, an item found done is stored
+    /// and not checked again.
+    pub fn new<F, Fut>(id: &str, label: &str, href: &str, check: F) -> Self
+    where
+        F: Fn(OnboardingScope) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        Self {
+            id: id.to_owned(),
+            label: label.to_owned(),
+            href: href.to_owned(),
+            order: 0,
+            check: Arc::new(move |scope: OnboardingScope| -> CheckFuture { Box::pin(check(scope)) }),
+        }
+    }
+
+    /// Only completed with `Onboarding::complete`.
+    pub fn manual(id: &str, label: &str, href: &str) -> Self {
+        Self::new(id, label, href, |_| async { false })
+    }
+
+    pub fn order(mut self, order: i32) -> Self {
+        self.order = order;
+        self
+    }
+}
+
+/// Every feature's items, provided to the checklist as an extension by `build`.
+#[derive(Clone, Default)]
+pub struct OnboardingItems(pub Arc<Vec<OnboardingItem>>);
+
+pub(crate) fn aggregate(features: &[Box<dyn Feature>]) -> OnboardingItems {
+    let mut items: Vec<OnboardingItem> = features.iter()
+        .flat_map(|feature| feature.onboarding())
+        .collect();
+
+    // stable, equal orders stay in registration order
+    items.sort_by_key(|item| item.order);
+
+    OnboardingItems(Arc::new(items))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecklistEntry {
+    pub id: String,
+    pub label: String,
+    pub href: String,
+    pub done: bool,
+}
+
+/// A user's progress through the items.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checklist {
+    pub entries: Vec<ChecklistEntry>,
+}
+
+impl Checklist {
+    pub fn done(&self) -> usize {
+        self.entries.iter().filter(|entry| entry.done).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.done() == self.entries.len()
+    }
+}
+
+/// Storage contract for completed items, keyed by user.
+#[async_trait]
+pub trait OnboardingStore: Send + Sync {
+    async fn completed(&self, user: &str) -> Result<HashSet<String>, OnboardingError>;
+
+    async fn complete(&self, user: &str, item: &str) -> Result<(), OnboardingError>;
+}
+
+#[derive(Default)]
+pub struct MemoryOnboardingStore {
+    completed: Mutex<HashMap<String, HashSet<String>>>
+}
+
+#[async_trait]
+impl OnboardingStore for MemoryOnboardingStore {
+    async fn completed(&self, user: &str) -> Result<HashSet<String>, OnboardingError> {
+        Ok(self.completed.lock().unwrap().get(user).cloned().unwrap_or_default())
+    }
+
+    async fn complete(&self, user: &str, item: &str) -> Result<(), OnboardingError> {
+        self.completed.lock().unwrap().entry(user.to_owned()).or_default().insert(item.to_owned());
+        Ok(())
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresOnboardingStore;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::{collections::HashSet, time::SystemTime};
+
+    use async_trait::async_trait;
+
+    use crate::db::ConnectionPool;
+
+    use super::{OnboardingError, OnboardingStore};
+
+    impl From<tokio_postgres::Error> for OnboardingError {
+        fn from(value: tokio_postgres::Error) -> Self {
+            OnboardingError::Store(value.to_string())
+        }
+    }
+
+    /// Create with `PostgresOnboardingStore::TABLE` before use.
+    pub struct PostgresOnboardingStore {
+        pool: ConnectionPool
+    }
+
+    impl PostgresOnboardingStore {
+        pub const TABLE: &'static str = "CREATE TABLE IF NOT EXISTS blandwork_onboarding (
+            user_id TEXT NOT NULL,
+            item TEXT NOT NULL,
+            completed_at TIMESTAMPTZ NOT NULL,
+            PRIMARY KEY (user_id, item)
+        )";
+
+        pub fn new(pool: ConnectionPool) -> Self {
+            Self { pool }
+        }
+
+        pub async fn migrate(&self) -> Result<(), OnboardingError> {
+            let connection = self.pool.get().await.map_err(|e| OnboardingError::Store(e.to_string()))?;
+            connection.batch_execute(Self::TABLE).await?;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl OnboardingStore for PostgresOnboardingStore {
+        async fn completed(&self, user: &str) -> Result<HashSet<String>, OnboardingError> {
+            let connection = self.pool.get().await.map_err(|e| OnboardingError::Store(e.to_string()))?;
+
+            let rows = connection.query("SELECT item FROM blandwork_onboarding WHERE user_id = $1", &[&user]).await?;
+
+            Ok(rows.iter().map(|row| row.get("item")).collect())
+        }
+
+        async fn complete(&self, user: &str, item: &str) -> Result<(), OnboardingError> {
+            let connection = self.pool.get().await.map_err(|e| OnboardingError::Store(e.to_string()))?;
+
+            connection.execute(
+                "INSERT INTO blandwork_onboarding (user_id, item, completed_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[&user, &item, &SystemTime::now()]
+            ).await?;
+
+            Ok(())
+        }
+    }
+}
+
+/// Dismissed checklists stay hidden for the browser.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OnboardingPrefs {
+    pub dismissed: bool,
+}
+
+impl TypedCookie for OnboardingPrefs {
+    const NAME: &'static str = "blandwork_onboarding";
+}
+
+type Identity = Arc<dyn Fn(&Parts) -> Option<String> + Send + Sync>;
+
+// user -> (computed at, checklist)
+type ChecklistCache = Arc<Mutex<HashMap<String, (SystemTime, Arc<Checklist>)>>>;
+
+/// Per user onboarding progress, shared by the checklist and the features completing items.
+#[derive(Clone)]
+pub struct Onboarding {
+    store: Arc<dyn OnboardingStore>,
+    clock: SharedClock,
+
+    // resolves the signed in user, no checklist without one
+    identity: Identity,
+
+    cache: ChecklistCache,
+    ttl: Duration,
+
+    // attached by the checklist feature during `build`
+    events: Arc<RwLock<Option<EventBus>>>,
+}
+
+impl Onboarding {
+    pub fn new(store: impl OnboardingStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            clock: Arc::new(SystemClock),
+            identity: Arc::new(|_: &Parts| None),
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            ttl: Duration::from_secs(30),
+            events: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self::new(MemoryOnboardingStore::default())
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How the checklist finds the current user, e.g. from an auth extension.
+    pub fn identity(mut self, identity: impl Fn(&Parts) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+
+    /// How long a computed checklist is reused for the same user.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn cached(&self, user: &str) -> Option<Arc<Checklist>> {
+        let cache = self.cache.lock().unwrap();
+        let (computed_at, checklist) = cache.get(user)?;
+
+        let age: Duration = self.clock.now().duration_since(*computed_at).unwrap_or_default();
+
+        (age < self.ttl).then(|| checklist.clone())
+    }
+
+    async fn compute(&self, user: &str, items: &[OnboardingItem], extensions: &Extensions) -> Result<Arc<Checklist>, OnboardingError> {
+        if let Some(checklist) = self.cached(user) {
+            return Ok(checklist);
+        }
+
+        let completed: HashSet<String> = self.store.completed(user).await?;
+        let mut entries: Vec<ChecklistEntry> = Vec::with_capacity(items.len());
+
+        for item in items {
+            let mut done: bool = completed.contains(&item.id);
+
+            if !done {
+                done = (item.check)(OnboardingScope { user: user.to_owned(), extensions: extensions.clone() }).await;
+
+                if done {
+                    self.store.complete(user, &item.id).await?;
+                }
+            }
+
+            entries.push(ChecklistEntry { id: item.id.clone(), label: item.label.clone(), href: item.href.clone(), done });
+        }
+
+        let checklist: Arc<Checklist> = Arc::new(Checklist { entries });
+        self.cache.lock().unwrap().insert(user.to_owned(), (self.clock.now(), checklist.clone()));
+
+        Ok(checklist)
+    }
+
+    /// The user's checklist, computed once per request and reused for `ttl` afterwards.
+    pub async fn checklist(&self, user: &str, items: &OnboardingItems, accessor: &ContextAccessor, extensions: &Extensions) -> Result<Arc<Checklist>, OnboardingError> {
+        let checklist: Arc<Arc<Checklist>> = accessor.memo(&format!("onboarding:{user}"), || async {
+            self.compute(user, &items.0, extensions).await
+        }).await?;
+
+        Ok(checklist.as_ref().clone())
+    }
+
+    /// Mark an item done ahead of its check, open checklists refresh.
+    pub async fn complete(&self, user: &str, item: &str) -> Result<(), OnboardingError> {
+        self.store.complete(user, item).await?;
+        self.cache.lock().unwrap().remove(user);
+
+        match self.events.read().unwrap().as_ref() {
+            Some(events) => { events.publish(&ONBOARDING_UPDATED, &OnboardingUpdated { item: item.to_owned() }).broadcast(); },
+            None => tracing::warn!("onboarding item {item} completed before the checklist feature was built"),
+        }
+
+        Ok(())
+    }
+}
+
+/// Placeholder for the app shell, loads the checklist and reloads it when an item completes.
+pub fn onboarding_checklist() -> Markup {
+    html!{
+        div #blandwork-onboarding
+            hx-get=(ONBOARDING_ROUTE)
+            hx-trigger="load, onboarding:updated from:body, sse:onboarding:updated"
+            hx-ext="sse"
+            sse-connect=(EVENTS_ROUTE) {}
+    }
+}
+
+fn render(checklist: &Checklist) -> Markup {
+    html!{
+        section .onboarding aria-label="Getting started" {
+            progress value=(checklist.done()) max=(checklist.entries.len()) {
+                (checklist.done()) " of " (checklist.entries.len())
+            }
+            ol {
+                @for entry in checklist.entries.iter() {
+                    li data-onboarding-item=(entry.id) data-done[entry.done] {
+                        a href=(entry.href) { (entry.label) }
+                    }
+                }
+            }
+            button hx-post=(format!("{ONBOARDING_ROUTE}/dismiss")) hx-target="closest section" hx-swap="outerHTML" hx-sync="this:drop" { "Dismiss" }
+        }
+    }
+}
+
+/// Renders the aggregated `Feature::onboarding` items as a fragment, see `onboarding_checklist`.
+#[derive(Clone)]
+pub struct ChecklistFeature {
+    onboarding: Onboarding,
+}
+
+impl ChecklistFeature {
+    pub fn new(onboarding: Onboarding) -> Self {
+        Self { onboarding }
+    }
+
+    async fn fragment(
+        Extension(onboarding): Extension<Onboarding>,
+        Extension(accessor): Extension<ContextAccessor>,
+        items: Option<Extension<OnboardingItems>>,
+        request: Request
+    ) -> Response {
+        let (parts, _) = request.into_parts();
+        let items: OnboardingItems = items.map(|Extension(items)| items).unwrap_or_default();
+
+        let Some(user) = (onboarding.identity)(&parts) else {
+            return html!{}.into_response();
+        };
+
+        let dismissed: bool = accessor.context().await
+            .typed_cookie::<OnboardingPrefs>()
+            .map(|prefs| prefs.dismissed)
+            .unwrap_or(false);
+
+        if dismissed || items.0.is_empty() {
+            return html!{}.into_response();
+        }
+
+        match onboarding.checklist(&user, &items, &accessor, &parts.extensions).await {
+            Ok(checklist) if checklist.is_complete() => html!{}.into_response(),
+            Ok(checklist) => render(&checklist).into_response(),
+            Err(e) => {
+                tracing::error!("unable to load the onboarding checklist of {user}: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+
+    async fn dismiss(Extension(accessor): Extension<ContextAccessor>) -> Response {
+        match accessor.context().await.set_cookie(&OnboardingPrefs { dismissed: true }) {
+            Ok(()) => html!{}.into_response(),
+            Err(e) => {
+                tracing::error!("unable to dismiss the onboarding checklist: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+impl Feature for ChecklistFeature {
+    fn supplemental(&self) -> Option<Router> {
+        Some(Router::new()
+            .route(ONBOARDING_ROUTE, get(ChecklistFeature::fragment))
+            .route(&format!("{ONBOARDING_ROUTE}/dismiss"), post(ChecklistFeature::dismiss))
+            .layer(Extension(self.onboarding.clone())))
+    }
+
+    fn subscribe(&self, events: &EventBus) {
+        *self.onboarding.events.write().unwrap() = Some(events.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+
+    use axum::{
+        body::{to_bytes, Body}, extract::Request,
+        http::{header::{COOKIE, SET_COOKIE}, Extensions}, Extension, Router
+    };
+    use axum_htmx::HX_REQUEST;
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, context::ContextLayer, events::EventBus, ContextAccessor, Feature};
+
+    use super::{aggregate, ChecklistFeature, Onboarding, OnboardingItem, OnboardingItems, ONBOARDING_ROUTE};
+
+    struct Projects;
+
+    impl Feature for Projects {
+        fn onboarding(&self) -> Vec<OnboardingItem> {
+            vec![
+                OnboardingItem::manual("first_project", "Create your first project", "/projects/new"),
+                OnboardingItem::manual("billing", "Connect billing", "/billing").order(10),
+            ]
+        }
+    }
+
+    struct Team;
+
+    impl Feature for Team {
+        fn onboarding(&self) -> Vec<OnboardingItem> {
+            vec![OnboardingItem::manual("invite", "Invite a teammate", "/team/invite")]
+        }
+    }
+
+    fn ids(items: &OnboardingItems) -> Vec<&str> {
+        items.0.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_aggregation_order() {
+        let features: Vec<Box<dyn Feature>> = vec![Box::new(Projects), Box::new(Team)];
+
+        assert_eq!(ids(&aggregate(&features)), vec!["first_project", "invite", "billing"]);
+    }
+
+    fn accessor() -> ContextAccessor {
+        ContextAccessor::from_request(&Request::builder().uri("/").body(Body::empty()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_check_caching() {
+        let clock = TestClock::new();
+        let onboarding = Onboarding::memory().with_clock(clock.clone()).ttl(Duration::from_secs(30));
+        let checks = Arc::new(AtomicUsize::new(0));
+
+        let counter = checks.clone();
+        let items = OnboardingItems(Arc::new(vec![
+            OnboardingItem::new("first_project", "Create your first project", "/projects/new", move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { false }
+            })
+        ]));
+
+        // memoized within a request
+        let request = accessor();
+        onboarding.checklist("u1", &items, &request, &Extensions::new()).await.unwrap();
+        onboarding.checklist("u1", &items, &request, &Extensions::new()).await.unwrap();
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+
+        // cached across requests within the ttl, per user
+        onboarding.checklist("u1", &items, &accessor(), &Extensions::new()).await.unwrap();
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+
+        onboarding.checklist("u2", &items, &accessor(), &Extensions::new()).await.unwrap();
+        assert_eq!(checks.load(Ordering::SeqCst), 2);
+
+        clock.advance(Duration::from_secs(31));
+        onboarding.checklist("u1", &items, &accessor(), &Extensions::new()).await.unwrap();
+        assert_eq!(checks.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_complete_broadcasts() {
+        let onboarding = Onboarding::memory();
+        let bus = EventBus::new();
+        ChecklistFeature::new(onboarding.clone()).subscribe(&bus);
+
+        let mut browser = bus.browser_events();
+        let items = OnboardingItems(Arc::new(vec![OnboardingItem::manual("invite", "Invite a teammate", "/team/invite")]));
+
+        let before = onboarding.checklist("u1", &items, &accessor(), &Extensions::new()).await.unwrap();
+        assert!(!before.is_complete());
+
+        onboarding.complete("u1", "invite").await.unwrap();
+
+        // the cached checklist was dropped
+        let after = onboarding.checklist("u1", &items, &accessor(), &Extensions::new()).await.unwrap();
+        assert!(after.is_complete());
+
+        let event = browser.recv().await.unwrap();
+        assert_eq!(event.topic, "onboarding:updated");
+        assert_eq!(event.payload["item"], "invite");
+    }
+
+    #[tokio::test]
+    async fn test_dismissal_persists() {
+        let onboarding = Onboarding::memory().identity(|_| Some("u1".to_owned()));
+        let items = OnboardingItems(Arc::new(vec![OnboardingItem::manual("invite", "Invite a teammate", "/team/invite")]));
+
+        let router: Router = ChecklistFeature::new(onboarding).supplemental().unwrap()
+            .layer(ContextLayer::new())
+            .layer(Extension(items));
+
+        let send = |method: &str, uri: String, cookie: Option<String>| {
+            let router = router.clone();
+            let mut request = Request::builder().method(method).uri(uri).header(HX_REQUEST, "true");
+            if let Some(cookie) = cookie {
+                request = request.header(COOKIE, cookie);
+            }
+            async move { router.oneshot(request.body(Body::empty()).unwrap()).await.unwrap() }
+        };
+
+        let body = to_bytes(send("GET", ONBOARDING_ROUTE.to_owned(), None).await.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("Invite a teammate"));
+
+        let response = send("POST", format!("{ONBOARDING_ROUTE}/dismiss"), None).await;
+        let cookie: String = response.headers()[SET_COOKIE].to_str().unwrap()
+            .split(';').next().unwrap().to_owned();
+
+        let body = to_bytes(send("GET", ONBOARDING_ROUTE.to_owned(), Some(cookie)).await.into_body(), usize::MAX).await.unwrap();
+        assert!(body.is_empty());
+    }
+}
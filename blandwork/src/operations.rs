@@ -0,0 +1,604 @@
+use std::{
+    collections::HashMap,
+    error::Error, fmt::Display,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime}
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    routing::get, Extension, Router
+};
+use axum_htmx::HX_REDIRECT;
+use hyper::{HeaderMap, StatusCode};
+use maud::{html, Markup, PreEscaped};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    clock::{Clock, SharedClock, SystemClock},
+    events::HandlerError
+};
+
+pub const OPERATIONS_ROUTE: &str = "/_blandwork/operations";
+
+/// What the browser is shown once a deferred operation completes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub enum Outcome {
+    /// `HX-Redirect` to the result (the generated report).
+    Redirect(String),
+
+    /// Rendered in place of the progress fragment.
+    Fragment(String),
+}
+
+impl Outcome {
+    pub fn redirect(to: &str) -> Self {
+        Outcome::Redirect(to.to_owned())
+    }
+
+    pub fn fragment(markup: Markup) -> Self {
+        Outcome::Fragment(markup.into_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum OperationState {
+    Running { percent: Option<u8>, message: Option<String> },
+    Complete { outcome: Outcome },
+    Failed { error: String },
+}
+
+/// A deferred operation as stored, see `Context::defer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub id: String,
+
+    // identity of the requester, only they can poll it
+    pub owner: Option<String>,
+    pub state: OperationState,
+    pub updated_at: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum OperationError {
+    Store(String),
+
+    // `Context::defer` outside of a built App
+    Unavailable,
+}
+
+impl Display for OperationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OperationError::Store(e) => write!(f, "operation store error: {e}"),
+            OperationError::Unavailable => write!(f, "deferred operations are only available in a built App"),
+        }
+    }
+}
+
+impl Error for OperationError {}
+
+/// Storage contract for operation state, a durable store keeps it across restarts.
+#[async_trait]
+pub trait OperationStore: Send + Sync {
+    async fn load(&self, id: &str) -> Result<Option<Operation>, OperationError>;
+
+    async fn save(&self, operation: Operation) -> Result<(), OperationError>;
+
+    /// Remove operations last updated before `before`, returns how many.
+    async fn purge(&self, before: SystemTime) -> Result<u64, OperationError>;
+}
+
+#[derive(Default)]
+pub struct MemoryOperationStore {
+    operations: Mutex<HashMap<String, Operation>>
+}
+
+#[async_trait]
+impl OperationStore for MemoryOperationStore {
+    async fn load(&self, id: &str) -> Result<Option<Operation>, OperationError> {
+        Ok(self.operations.lock().unwrap().get(id).cloned())
+    }
+
+    async fn save(&self, operation: Operation) -> Result<(), OperationError> {
+        self.operations.lock().unwrap().insert(operation.id.clone(), operation);
+        Ok(())
+    }
+
+    async fn purge(&self, before: SystemTime) -> Result<u64, OperationError> {
+        let mut operations = self.operations.lock().unwrap();
+        let count: usize = operations.len();
+
+        operations.retain(|_, operation| operation.updated_at >= before);
+
+        Ok((count - operations.len()) as u64)
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresOperationStore;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use std::time::SystemTime;
+
+    use async_trait::async_trait;
+    use tokio_postgres::Row;
+
+    use crate::db::ConnectionPool;
+
+    use super::{Operation, OperationError, OperationState, OperationStore};
+
+    impl From<tokio_postgres::Error> for OperationError {
+        fn from(value: tokio_postgres::Error) -> Self {
+            OperationError::Store(value.to_string())
+        }
+    }
+
+    /// Create with `PostgresOperationStore::TABLE` before use.
+    pub struct PostgresOperationStore {
+        pool: ConnectionPool
+    }
+
+    impl PostgresOperationStore {
+        pub const TABLE: &'static str = "CREATE TABLE IF NOT EXISTS blandwork_operations (
+            id TEXT PRIMARY KEY,
+            owner TEXT,
+            state TEXT NOT NULL,
+            updated_at TIMESTAMPTZ NOT NULL
+        )";
+
+        pub fn new(pool: ConnectionPool) -> Self {
+            Self { pool }
+        }
+
+        pub async fn migrate(&self) -> Result<(), OperationError> {
+            let connection = self.pool.get().await.map_err(|e| OperationError::Store(e.to_string()))?;
+            connection.batch_execute(Self::TABLE).await?;
+            Ok(())
+        }
+    }
+
+    fn operation(row: &Row) -> Result<Operation, OperationError> {
+        let state: String = row.get("state");
+
+        Ok(Operation {
+            id: row.get("id"),
+            owner: row.get("owner"),
+            state: serde_json::from_str::<OperationState>(&state).map_err(|e| OperationError::Store(e.to_string()))?,
+            updated_at: row.get("updated_at"),
+        })
+    }
+
+    #[async_trait]
+    impl OperationStore for PostgresOperationStore {
+        async fn load(&self, id: &str) -> Result<Option<Operation>, OperationError> {
+            let connection = self.pool.get().await.map_err(|e| OperationError::Store(e.to_string()))?;
+
+            let row = connection.query_opt("SELECT id, owner, state, updated_at FROM blandwork_operations WHERE id = $1", &[&id]).await?;
+
+            row.as_ref().map(operation).transpose()
+        }
+
+        async fn save(&self, operation: Operation) -> Result<(), OperationError> {
+            let connection = self.pool.get().await.map_err(|e| OperationError::Store(e.to_string()))?;
+            let state: String = serde_json::to_string(&operation.state).map_err(|e| OperationError::Store(e.to_string()))?;
+
+            connection.execute(
+                "INSERT INTO blandwork_operations (id, owner, state, updated_at) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (id) DO UPDATE SET state = EXCLUDED.state, updated_at = EXCLUDED.updated_at",
+                &[&operation.id, &operation.owner, &state, &operation.updated_at]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn purge(&self, before: SystemTime) -> Result<u64, OperationError> {
+            let connection = self.pool.get().await.map_err(|e| OperationError::Store(e.to_string()))?;
+
+            Ok(connection.execute("DELETE FROM blandwork_operations WHERE updated_at < $1", &[&before]).await?)
+        }
+    }
+}
+
+type Identity = Arc<dyn Fn(&HeaderMap) -> Option<String> + Send + Sync>;
+
+/// Work deferred past the request with `Context::defer`: the handler answers `202` with a
+/// fragment polling `OPERATIONS_ROUTE/:id`, which renders the progress reported by the job
+/// until it completes or fails.
+///
+/// ```ignore
+/// async fn generate(Extension(accessor): Extension<ContextAccessor>) -> Result<OperationHandle, StatusCode> {
+///     accessor.context().await.defer(|progress| async move {
+///         progress.report(50, "Collecting invoices").await;
+///         Ok(Outcome::redirect("/reports/2024"))
+///     }).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Operations {
+    store: Arc<dyn OperationStore>,
+    clock: SharedClock,
+
+    // resolves the requester, operations of a signed in user are only shown to them
+    identity: Identity,
+
+    // operations not updated for this long are gone
+    ttl: Duration,
+
+    // status poll interval of the progress fragment
+    poll: Duration,
+}
+
+impl Operations {
+    pub fn new(store: impl OperationStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            clock: Arc::new(SystemClock),
+            identity: Arc::new(|_: &HeaderMap| None),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            poll: Duration::from_secs(1),
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self::new(MemoryOperationStore::default())
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How the requester is identified, e.g. from a session header.
+    pub fn identity(mut self, identity: impl Fn(&HeaderMap) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn poll_every(mut self, poll: Duration) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    pub(crate) fn owner(&self, headers: &HeaderMap) -> Option<String> {
+        (self.identity)(headers)
+    }
+
+    async fn update(&self, id: &str, owner: &Option<String>, state: OperationState) {
+        let operation: Operation = Operation { id: id.to_owned(), owner: owner.clone(), state, updated_at: self.clock.now() };
+
+        if let Err(e) = self.store.save(operation).await {
+            tracing::error!("unable to save the state of operation {id}: {e}");
+        }
+    }
+
+    /// Runs `job` in the background, see `Context::defer`.
+    pub async fn start<F, Fut>(&self, owner: Option<String>, job: F) -> Result<OperationHandle, OperationError>
+    where
+        F: FnOnce(OperationProgress) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<Outcome, HandlerError>> + Send + 'static,
+    {
+        let id: String = Uuid::new_v4().to_string();
+
+        self.store.save(Operation {
+            id: id.clone(),
+            owner: owner.clone(),
+            state: OperationState::Running { percent: None, message: None },
+            updated_at: self.clock.now(),
+        }).await?;
+
+        let progress: OperationProgress = OperationProgress { id: id.clone(), owner: owner.clone(), operations: self.clone() };
+        let operations: Operations = self.clone();
+        let operation: String = id.clone();
+
+        tokio::spawn(async move {
+            // spawned again so a panicking job is reported as failed
+            let state: OperationState = match tokio::spawn(job(progress)).await {
+                Ok(Ok(outcome)) => OperationState::Complete { outcome },
+                Ok(Err(e)) => OperationState::Failed { error: e.to_string() },
+                Err(e) => OperationState::Failed { error: e.to_string() },
+            };
+
+            if let OperationState::Failed { error } = &state {
+                tracing::warn!("operation {operation} failed: {error}");
+            }
+
+            operations.update(&operation, &owner, state).await;
+        });
+
+        Ok(OperationHandle { id, poll: self.poll })
+    }
+
+    /// The operation when it exists, is not expired and belongs to `owner`.
+    pub async fn status(&self, id: &str, owner: Option<&str>) -> Result<Option<Operation>, OperationError> {
+        let expired_before: SystemTime = self.clock.now() - self.ttl;
+
+        Ok(self.store.load(id).await?.filter(|operation| {
+            let visible: bool = match operation.owner.as_deref() {
+                Some(requester) => Some(requester) == owner,
+                None => true,
+            };
+
+            operation.updated_at >= expired_before && visible
+        }))
+    }
+
+    /// Remove expired operations, meant to run periodically.
+    pub async fn purge_expired(&self) -> Result<u64, OperationError> {
+        self.store.purge(self.clock.now() - self.ttl).await
+    }
+
+    async fn status_route(Extension(operations): Extension<Operations>, Path(id): Path<String>, headers: HeaderMap) -> Response {
+        let owner: Option<String> = operations.owner(&headers);
+
+        match operations.status(&id, owner.as_deref()).await {
+            Ok(Some(operation)) => render(&operation, operations.poll),
+            Ok(None) => (StatusCode::NOT_FOUND, html!{ p role="alert" { "This operation is no longer available." } }).into_response(),
+            Err(e) => {
+                tracing::error!("unable to load operation {id}: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+
+    pub(crate) fn router(&self) -> Router {
+        Router::new()
+            .route(&format!("{OPERATIONS_ROUTE}/:id"), get(Operations::status_route))
+            .layer(Extension(self.clone()))
+    }
+}
+
+/// Handed to a deferred job to report how far it got.
+pub struct OperationProgress {
+    id: String,
+    owner: Option<String>,
+    operations: Operations,
+}
+
+impl OperationProgress {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub async fn report(&self, percent: u8, message: &str) {
+        let state: OperationState = OperationState::Running { percent: Some(percent.min(100)), message: Some(message.to_owned()) };
+
+        self.operations.update(&self.id, &self.owner, state).await;
+    }
+}
+
+fn status_url(id: &str) -> String {
+    format!("{OPERATIONS_ROUTE}/{id}")
+}
+
+/// Polls `OPERATIONS_ROUTE/:id` and replaces itself with the outcome.
+fn progress(id: &str, poll: Duration, percent: Option<u8>, message: Option<&str>) -> Markup {
+    html!{
+        div .operation role="status"
+            hx-get=(status_url(id))
+            hx-trigger=(format!("every {}ms", poll.as_millis()))
+            hx-swap="outerHTML" {
+            @match percent {
+                Some(percent) => { progress value=(percent) max="100" { (percent) "%" } },
+                None => { progress {} },
+            }
+            @if let Some(message) = message {
+                p { (message) }
+            }
+        }
+    }
+}
+
+fn render(operation: &Operation, poll: Duration) -> Response {
+    match &operation.state {
+        OperationState::Running { percent, message } => progress(&operation.id, poll, *percent, message.as_deref()).into_response(),
+        OperationState::Complete { outcome: Outcome::Redirect(to) } => (StatusCode::OK, [(HX_REDIRECT, to.clone())]).into_response(),
+        OperationState::Complete { outcome: Outcome::Fragment(fragment) } => PreEscaped(fragment.clone()).into_response(),
+        // the error is logged, it may not be fit for users
+        OperationState::Failed { .. } => html!{
+            div .operation .operation-failed role="alert" { "Something went wrong, please try again." }
+        }.into_response(),
+    }
+}
+
+/// A started operation, answers `202` with the progress fragment.
+#[derive(Debug, Clone)]
+pub struct OperationHandle {
+    pub id: String,
+    poll: Duration,
+}
+
+impl OperationHandle {
+    pub fn status_url(&self) -> String {
+        status_url(&self.id)
+    }
+
+    pub fn fragment(&self) -> Markup {
+        progress(&self.id, self.poll, None, None)
+    }
+}
+
+impl IntoResponse for OperationHandle {
+    fn into_response(self) -> Response {
+        (StatusCode::ACCEPTED, self.fragment()).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{body::{to_bytes, Body}, extract::Request, http::HeaderValue, response::Response, routing::post, Extension, Router};
+    use axum_htmx::{HX_REDIRECT, HX_REQUEST};
+    use hyper::StatusCode;
+    use maud::{html, Markup};
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, events::HandlerError, test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{OperationHandle, OperationState, Operations, Outcome};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Reports {
+        release: Arc<Notify>,
+        fail: bool,
+    }
+
+    impl Feature for Reports {
+        fn supplemental(&self) -> Option<Router> {
+            Some(Router::new()
+                .route("/reports", post(|Extension(accessor): Extension<ContextAccessor>, Extension(release): Extension<Arc<Notify>>, Extension(fail): Extension<bool>| async move {
+                    accessor.context().await.defer(move |progress| async move {
+                        progress.report(50, "Collecting invoices").await;
+                        release.notified().await;
+
+                        match fail {
+                            true => Err::<Outcome, HandlerError>("ledger unavailable".into()),
+                            false => Ok(Outcome::redirect("/reports/2024")),
+                        }
+                    }).await.unwrap()
+                }))
+                .layer(Extension(self.release.clone()))
+                .layer(Extension(self.fail)))
+        }
+    }
+
+    async fn send(router: &Router, request: axum::http::request::Builder) -> (StatusCode, Response) {
+        let response = router.clone().oneshot(request.header(HX_REQUEST, "true").body(Body::empty()).unwrap()).await.unwrap();
+        (response.status(), response)
+    }
+
+    async fn body(response: Response) -> String {
+        String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    /// (HX-Redirect, body) once the operation left `Running`.
+    async fn settled(router: &Router, url: &str) -> (Option<HeaderValue>, String) {
+        for _ in 0..1000 {
+            let (_, response) = send(router, Request::get(url)).await;
+            let redirect = response.headers().get(HX_REDIRECT).cloned();
+            let page = body(response).await;
+
+            if redirect.is_some() || !page.contains("hx-get") {
+                return (redirect, page);
+            }
+
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        panic!("operation did not settle");
+    }
+
+    fn app(fail: bool) -> (Router, Arc<Notify>) {
+        let release = Arc::new(Notify::new());
+        let app = App::new(Config::default(), TestTemplate)
+            .operations(Operations::memory())
+            .register_feature(Reports { release: release.clone(), fail })
+            .build();
+
+        (router(&app), release)
+    }
+
+    async fn defer(router: &Router) -> String {
+        let (status, response) = send(router, Request::post("/reports")).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let body = body(response).await;
+        let start = body.find("hx-get=\"").unwrap() + 8;
+        body[start..start + body[start..].find('"').unwrap()].to_owned()
+    }
+
+    #[tokio::test]
+    async fn test_defer_poll_complete() {
+        let (app, release) = app(false);
+        let url = defer(&app).await;
+
+        // the job reports before waiting on the release
+        let mut running = String::new();
+        for _ in 0..1000 {
+            running = body(send(&app, Request::get(&url)).await.1).await;
+            if running.contains("Collecting invoices") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(running.contains(r#"<progress value="50" max="100">"#));
+
+        release.notify_one();
+
+        let (redirect, _) = settled(&app, &url).await;
+        assert_eq!(redirect.unwrap(), "/reports/2024");
+    }
+
+    #[tokio::test]
+    async fn test_failure_rendering() {
+        let (app, release) = app(true);
+        let url = defer(&app).await;
+        release.notify_one();
+
+        let (_, failed) = settled(&app, &url).await;
+        assert!(failed.contains(r#"role="alert""#));
+        assert!(!failed.contains("ledger unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_identity_scoping() {
+        let operations = Operations::memory().identity(|headers| headers.get("x-user").map(|user| user.to_str().unwrap().to_owned()));
+        let router = operations.router();
+
+        let handle: OperationHandle = operations.start(Some("ada".to_owned()), |_| async { Ok(Outcome::fragment(html! { p { "done" } })) }).await.unwrap();
+
+        let (status, _) = send(&router, Request::get(handle.status_url()).header("x-user", "grace")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&router, Request::get(handle.status_url())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&router, Request::get(handle.status_url()).header("x-user", "ada")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_ttl_cleanup() {
+        let clock = TestClock::new();
+        let operations = Operations::memory().with_clock(clock.clone()).ttl(Duration::from_secs(60));
+
+        let handle = operations.start(None, |_| async { Ok(Outcome::redirect("/")) }).await.unwrap();
+
+        for _ in 0..1000 {
+            match operations.status(&handle.id, None).await.unwrap().map(|operation| operation.state) {
+                Some(OperationState::Complete { .. }) => break,
+                _ => tokio::time::sleep(Duration::from_millis(1)).await,
+            }
+        }
+
+        clock.advance(Duration::from_secs(30));
+        assert!(operations.status(&handle.id, None).await.unwrap().is_some());
+        assert_eq!(operations.purge_expired().await.unwrap(), 0);
+
+        clock.advance(Duration::from_secs(31));
+        assert!(operations.status(&handle.id, None).await.unwrap().is_none());
+        assert_eq!(operations.purge_expired().await.unwrap(), 1);
+    }
+}
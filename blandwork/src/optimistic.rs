@@ -0,0 +1,216 @@
+use axum::{body::Body, response::{IntoResponse, Response}};
+use axum_htmx::{HX_RESWAP, HX_TRIGGER};
+use hyper::{header::CONTENT_TYPE, http::HeaderValue, HeaderMap, StatusCode};
+use maud::html;
+use serde::Serialize;
+use serde_json::json;
+
+/// Sent by the integration script with an optimistic request, the selector of the row it hid.
+pub const OPTIMISTIC_HEADER: &str = "x-blandwork-optimistic";
+
+/// Trigger of a failed htmx action, `evt.detail` is an `ActionFailure`:
+///
+/// ```json
+/// { "status": 409, "target": "#invoice-42", "message": "The invoice is already paid" }
+/// ```
+///
+/// `target` is the row hidden by an optimistic request (or `null`). The integration script
+/// shows it again and toasts the message. Replays of the offline queue fire it too.
+pub const ACTION_FAILED: &str = "blandwork:action-failed";
+
+/// Trigger of a soft delete, `evt.detail` is a `SoftDeleted`. The integration script keeps
+/// the row hidden and toasts the message with an undo button posting to `undo`.
+pub const SOFT_DELETED: &str = "blandwork:soft-deleted";
+
+/// The failure envelope of htmx actions, answered by handlers and by the error page of
+/// `CatchPanicLayer` for htmx requests. Swaps nothing, the trigger carries the error.
+///
+/// ```ignore
+/// if invoice.is_paid() {
+///     return ActionFailure::new(StatusCode::CONFLICT, "The invoice is already paid")
+///         .for_request(&headers)
+///         .into_response();
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActionFailure {
+    pub status: u16,
+    pub target: Option<String>,
+    pub message: String,
+}
+
+impl ActionFailure {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self { status: status.as_u16(), target: None, message: message.into() }
+    }
+
+    /// The row to show again, `#invoice-42`.
+    pub fn target(mut self, selector: &str) -> Self {
+        self.target = Some(selector.to_owned());
+        self
+    }
+
+    /// Targets the row the request hid, see `OPTIMISTIC_HEADER`.
+    pub fn for_request(mut self, headers: &HeaderMap) -> Self {
+        if let Some(selector) = headers.get(OPTIMISTIC_HEADER).and_then(|value| value.to_str().ok()) {
+            self.target = Some(selector.to_owned());
+        }
+        self
+    }
+
+    /// `HX-Trigger` value firing `ACTION_FAILED`.
+    pub fn trigger(&self) -> HeaderValue {
+        trigger(ACTION_FAILED, self)
+    }
+}
+
+impl IntoResponse for ActionFailure {
+    fn into_response(self) -> Response {
+        let status: StatusCode = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+        let mut response: Response = (status, html! { p role="alert" { (self.message) } }).into_response();
+        response.headers_mut().insert(HX_TRIGGER, self.trigger());
+        response.headers_mut().insert(HX_RESWAP, HeaderValue::from_static("none"));
+
+        response
+    }
+}
+
+/// Answer of a soft delete: the row stays hidden until `undo` is posted to.
+///
+/// ```ignore
+/// store.soft_delete(id).await?;
+/// SoftDeleted::new("#invoice-42", &format!("/invoices/{id}/restore"), "Invoice deleted").into_response()
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SoftDeleted {
+    pub target: String,
+    pub undo: String,
+    pub message: String,
+}
+
+impl SoftDeleted {
+    pub fn new(target: &str, undo: &str, message: impl Into<String>) -> Self {
+        Self { target: target.to_owned(), undo: undo.to_owned(), message: message.into() }
+    }
+}
+
+impl IntoResponse for SoftDeleted {
+    fn into_response(self) -> Response {
+        let mut response: Response = Response::new(Body::empty());
+        response.headers_mut().insert(HX_TRIGGER, trigger(SOFT_DELETED, &self));
+        response.headers_mut().insert(HX_RESWAP, HeaderValue::from_static("none"));
+        response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+
+        response
+    }
+}
+
+/// hx attributes of a delete button hiding its row before the server answers,
+/// see `optimistic_delete_attrs`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimisticDelete {
+    pub url: String,
+    pub target: String,
+    pub swap: String,
+
+    /// `data-blandwork-optimistic`, the row the integration script hides.
+    pub optimistic: String,
+
+    /// `data-blandwork-undo`, set for soft deletes.
+    pub undo: Option<String>,
+}
+
+impl OptimisticDelete {
+    /// A soft delete: the row is only hidden (`hx-swap="none"`), the handler answers
+    /// `SoftDeleted` and the undo toast restores it.
+    pub fn undo(mut self, undo_route: &str) -> Self {
+        self.swap = "none".to_owned();
+        self.undo = Some(undo_route.to_owned());
+        self
+    }
+}
+
+/// The row is hidden when the request is sent. A success removes it (`hx-swap="delete"`),
+/// an `ActionFailure` shows it again with the error.
+///
+/// ```ignore
+/// let delete = optimistic_delete_attrs(&format!("/invoices/{id}"), &format!("#invoice-{id}"));
+/// html! {
+///     button hx-delete=(delete.url) hx-target=(delete.target) hx-swap=(delete.swap)
+///         data-blandwork-optimistic=(delete.optimistic) data-blandwork-undo=[delete.undo] { "Delete" }
+/// }
+/// ```
+pub fn optimistic_delete_attrs(route: &str, row_selector: &str) -> OptimisticDelete {
+    OptimisticDelete {
+        url: route.to_owned(),
+        target: row_selector.to_owned(),
+        swap: "delete".to_owned(),
+        optimistic: row_selector.to_owned(),
+        undo: None,
+    }
+}
+
+fn trigger<T: Serialize>(event: &str, detail: &T) -> HeaderValue {
+    HeaderValue::from_str(&json!({ event: detail }).to_string())
+        .unwrap_or_else(|_| HeaderValue::from_static("{}"))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::to_bytes, response::IntoResponse};
+    use axum_htmx::{HX_RESWAP, HX_TRIGGER};
+    use hyper::{HeaderMap, StatusCode};
+    use serde_json::{json, Value};
+
+    use super::{optimistic_delete_attrs, ActionFailure, SoftDeleted, OPTIMISTIC_HEADER};
+
+    #[test]
+    fn test_attrs() {
+        let delete = optimistic_delete_attrs("/invoices/42", "#invoice-42");
+
+        assert_eq!(delete.url, "/invoices/42");
+        assert_eq!(delete.target, "#invoice-42");
+        assert_eq!(delete.swap, "delete");
+        assert_eq!(delete.optimistic, "#invoice-42");
+        assert_eq!(delete.undo, None);
+    }
+
+    #[tokio::test]
+    async fn test_failure_envelope() {
+        let mut headers: HeaderMap = HeaderMap::new();
+        headers.insert(OPTIMISTIC_HEADER, "#invoice-42".parse().unwrap());
+
+        let response = ActionFailure::new(StatusCode::CONFLICT, "The invoice is already paid")
+            .for_request(&headers)
+            .into_response();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(response.headers()[HX_RESWAP], "none");
+
+        let trigger: Value = serde_json::from_str(response.headers()[HX_TRIGGER].to_str().unwrap()).unwrap();
+        assert_eq!(trigger, json!({
+            "blandwork:action-failed": { "status": 409, "target": "#invoice-42", "message": "The invoice is already paid" }
+        }));
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, r#"<p role="alert">The invoice is already paid</p>"#);
+    }
+
+    #[test]
+    fn test_soft_delete_undo() {
+        let delete = optimistic_delete_attrs("/invoices/42", "#invoice-42").undo("/invoices/42/restore");
+
+        // hidden, not removed, so the undo can show it again
+        assert_eq!(delete.swap, "none");
+        assert_eq!(delete.undo.as_deref(), Some("/invoices/42/restore"));
+
+        let response = SoftDeleted::new("#invoice-42", "/invoices/42/restore", "Invoice deleted").into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let trigger: Value = serde_json::from_str(response.headers()[HX_TRIGGER].to_str().unwrap()).unwrap();
+        assert_eq!(trigger, json!({
+            "blandwork:soft-deleted": { "target": "#invoice-42", "undo": "/invoices/42/restore", "message": "Invoice deleted" }
+        }));
+    }
+}
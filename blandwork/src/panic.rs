@@ -0,0 +1,242 @@
+use std::{
+    any::Any, future::Future, panic::{catch_unwind, AssertUnwindSafe}, pin::Pin, sync::Arc,
+    task::{Context as TaskContext, Poll}
+};
+
+use axum::{body::Body, extract::Request, http::HeaderValue};
+use axum_htmx::{HX_REQUEST, HX_TRIGGER};
+use hyper::{header::CONTENT_TYPE, Response, StatusCode};
+use maud::Markup;
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::{access_log::REQUEST_ID, optimistic::ActionFailure, Context, ContextAccessor, Template};
+
+/// A handler panic turned into a 500 by `CatchPanicLayer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PanicReport {
+    /// The `x-request-id` of the request (see `AccessLogLayer`), generated without one.
+    pub request_id: String,
+    pub method: String,
+    pub path: String,
+    pub message: String,
+}
+
+/// Receives every `PanicReport`, e.g. to forward it to an error tracker, see `App::error_reporter`.
+pub type ErrorReporter = Arc<dyn Fn(&PanicReport) + Send + Sync>;
+
+type ErrorPage = Arc<dyn Fn(&Context, &PanicReport) -> Markup + Send + Sync>;
+
+/// The reporter of a built App unless replaced, logs the panic.
+pub(crate) fn log_panic() -> ErrorReporter {
+    Arc::new(|report: &PanicReport| {
+        tracing::error!(request_id = report.request_id, "{} {} panicked: {}", report.method, report.path, report.message);
+    })
+}
+
+/// Answers a request whose handler panicked with a 500 rendered by `Template::error`
+/// instead of dropping the connection, and reports it. Part of the core layers
+/// (`server.core_layers.catch_panic`).
+#[derive(Clone)]
+pub struct CatchPanicLayer {
+    page: ErrorPage,
+    reporter: ErrorReporter,
+}
+
+impl CatchPanicLayer {
+    pub fn new<T: Template + 'static>(template: T) -> Self {
+        Self {
+            page: Arc::new(move |context: &Context, report: &PanicReport| template.error(context, report)),
+            reporter: log_panic(),
+        }
+    }
+
+    pub fn reporter(mut self, reporter: ErrorReporter) -> Self {
+        self.reporter = reporter;
+        self
+    }
+}
+
+impl<S> Layer<S> for CatchPanicLayer {
+    type Service = CatchPanicService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CatchPanicService { inner, page: self.page.clone(), reporter: self.reporter.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct CatchPanicService<S> {
+    inner: S,
+    page: ErrorPage,
+    reporter: ErrorReporter,
+}
+
+impl<S> Service<Request> for CatchPanicService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // what the error page is rendered from, the request itself is gone by then
+        let mut parts: Request = Request::new(Body::empty());
+        *parts.method_mut() = req.method().clone();
+        *parts.uri_mut() = req.uri().clone();
+        *parts.headers_mut() = req.headers().clone();
+        *parts.extensions_mut() = req.extensions().clone();
+
+        let page: ErrorPage = self.page.clone();
+        let reporter: ErrorReporter = self.reporter.clone();
+
+        let mut inner = match catch_unwind(AssertUnwindSafe(|| self.inner.call(req))) {
+            Ok(inner) => Box::pin(inner),
+            Err(payload) => return Box::pin(async move { Ok(error_response(parts, payload, page, reporter).await) }),
+        };
+
+        Box::pin(async move {
+            let caught = std::future::poll_fn(|cx| match catch_unwind(AssertUnwindSafe(|| inner.as_mut().poll(cx))) {
+                Ok(Poll::Ready(result)) => Poll::Ready(Ok(result)),
+                Ok(Poll::Pending) => Poll::Pending,
+                Err(payload) => Poll::Ready(Err(payload)),
+            }).await;
+
+            match caught {
+                Ok(result) => result,
+                Err(payload) => Ok(error_response(parts, payload, page, reporter).await),
+            }
+        })
+    }
+}
+
+async fn error_response(request: Request, payload: Box<dyn Any + Send>, page: ErrorPage, reporter: ErrorReporter) -> Response<Body> {
+    let message: String = match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => (*message).to_owned(),
+            Err(_) => "non-string panic payload".to_owned(),
+        },
+    };
+
+    let report: PanicReport = PanicReport {
+        request_id: request.headers().get(REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_owned())
+            .unwrap_or_else(|| Uuid::new_v4().to_string()),
+        method: request.method().to_string(),
+        path: request.uri().path().to_owned(),
+        message,
+    };
+
+    reporter(&report);
+
+    let accessor: ContextAccessor = ContextAccessor::from_request(&request);
+    let markup: Markup = page(&accessor.context().await, &report);
+
+    let mut response: Response<Body> = Response::new(Body::from(markup.into_string()));
+    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+
+    if let Ok(value) = HeaderValue::from_str(&report.request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+
+    // htmx swaps nothing on a 500, an optimistic row is shown again from the envelope
+    if request.headers().contains_key(HX_REQUEST) {
+        let failure: ActionFailure = ActionFailure::new(StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong, please try again.")
+            .for_request(request.headers());
+        response.headers_mut().insert(HX_TRIGGER, failure.trigger());
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Router};
+    use hyper::StatusCode;
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use axum_htmx::{HX_REQUEST, HX_TRIGGER};
+
+    use crate::{access_log::REQUEST_ID, test::router, App, Config, Context, Feature, Template, OPTIMISTIC_HEADER};
+
+    use super::PanicReport;
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Broken;
+
+    async fn no_lines() -> Markup {
+        panic!("the invoice has no lines")
+    }
+
+    async fn locked() -> Markup {
+        panic!("the invoice is locked")
+    }
+
+    impl Feature for Broken {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new()
+                .route("/broken", get(no_lines).delete(locked))
+                .route("/fine", get(|| async { html! { "fine" } })))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_handler() {
+        let reports: Arc<Mutex<Vec<PanicReport>>> = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+
+        let mut app = App::new(Config::default(), TestTemplate)
+            .error_reporter(move |report| sink.lock().unwrap().push(report.clone()))
+            .register_feature(Broken);
+        let app = router(&app.build());
+
+        let request = Request::get("/broken").header(REQUEST_ID, "req-7").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers()[REQUEST_ID], "req-7");
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = String::from_utf8(body.to_vec()).unwrap();
+        assert!(html.starts_with("<html><body><h1>Something went wrong</h1>"));
+        assert!(html.contains("<code>req-7</code>"));
+
+        let reports = reports.lock().unwrap().clone();
+        assert_eq!(reports, vec![PanicReport {
+            request_id: "req-7".to_owned(),
+            method: "GET".to_owned(),
+            path: "/broken".to_owned(),
+            message: "the invoice has no lines".to_owned(),
+        }]);
+
+        // htmx requests get the failure envelope, the hidden row comes back
+        let request = Request::delete("/broken").header(HX_REQUEST, "true").header(OPTIMISTIC_HEADER, "#invoice-7").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.headers()[HX_TRIGGER].to_str().unwrap().contains(r##""target":"#invoice-7""##));
+
+        // the app keeps answering
+        let response = app.oneshot(Request::get("/fine").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
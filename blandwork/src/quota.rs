@@ -0,0 +1,708 @@
+use std::{
+    collections::{BTreeMap, HashMap}, error::Error, fmt::Display,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex}
+};
+
+use async_trait::async_trait;
+use axum::body::Bytes;
+use maud::{html, Markup};
+use serde::Serialize;
+use tokio::{fs, io::AsyncWriteExt};
+use tokio_stream::{Stream, StreamExt};
+use uuid::Uuid;
+
+use crate::{config::Quotas, schedule::ScheduledJob, Context};
+
+/// Trigger sent with `quota_exceeded`, detail `{"level", "limit", "used"}`.
+pub const QUOTA_EXCEEDED: &str = "storage:quota-exceeded";
+
+// suffix of files being written, skipped by reconciliation
+const PARTIAL: &str = ".partial";
+
+/// Whose bytes an upload counts against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct QuotaScope {
+    pub tenant: String,
+    pub feature: String,
+}
+
+impl QuotaScope {
+    pub fn new(tenant: &str, feature: &str) -> Self {
+        Self { tenant: tenant.to_owned(), feature: feature.to_owned() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaLevel {
+    Tenant,
+    Feature,
+}
+
+/// The limits applying to one scope, None is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QuotaLimits {
+    pub tenant: Option<u64>,
+    pub feature: Option<u64>,
+}
+
+/// Where limits come from, `server.quotas` or the application (a plan per tenant).
+pub trait QuotaProvider: Send + Sync {
+    fn limits(&self, scope: &QuotaScope) -> QuotaLimits;
+}
+
+impl QuotaProvider for Quotas {
+    fn limits(&self, scope: &QuotaScope) -> QuotaLimits {
+        QuotaLimits {
+            tenant: self.tenants.get(&scope.tenant).copied().or(self.tenant_bytes),
+            feature: self.features.get(&scope.feature).copied(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageError {
+    QuotaExceeded { level: QuotaLevel, limit: u64, used: u64, requested: u64 },
+    InvalidKey(String),
+    Body(String),
+    Io(String),
+    Store(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::QuotaExceeded { level, limit, used, requested } => {
+                write!(f, "{level:?} quota of {limit} bytes exceeded: {used} used, {requested} requested")
+            },
+            StorageError::InvalidKey(key) => write!(f, "invalid storage key {key}"),
+            StorageError::Body(e) => write!(f, "upload body error: {e}"),
+            StorageError::Io(e) => write!(f, "storage io error: {e}"),
+            StorageError::Store(e) => write!(f, "usage store error: {e}"),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(value: std::io::Error) -> Self {
+        StorageError::Io(value.to_string())
+    }
+}
+
+/// Stored bytes of a scope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    pub scope: QuotaScope,
+    pub bytes: u64,
+}
+
+/// Usage counters, `reserve` checks and adds in one step so concurrent uploads
+/// can't both fit into the same remaining budget.
+#[async_trait]
+pub trait UsageStore: Send + Sync {
+    async fn reserve(&self, scope: &QuotaScope, bytes: u64, limits: QuotaLimits) -> Result<(), StorageError>;
+
+    async fn release(&self, scope: &QuotaScope, bytes: u64) -> Result<(), StorageError>;
+
+    /// Overwrites the counter, used by reconciliation.
+    async fn set(&self, scope: &QuotaScope, bytes: u64) -> Result<(), StorageError>;
+
+    async fn usage(&self) -> Result<Vec<Usage>, StorageError>;
+}
+
+/// Checks a reservation against the tenant total and the scope's own usage.
+fn check(tenant_used: u64, feature_used: u64, bytes: u64, limits: QuotaLimits) -> Result<(), StorageError> {
+    for (level, limit, used) in [(QuotaLevel::Tenant, limits.tenant, tenant_used), (QuotaLevel::Feature, limits.feature, feature_used)] {
+        if let Some(limit) = limit {
+            if used + bytes > limit {
+                return Err(StorageError::QuotaExceeded { level, limit, used, requested: bytes });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct MemoryUsageStore {
+    usage: Mutex<HashMap<QuotaScope, u64>>,
+}
+
+#[async_trait]
+impl UsageStore for MemoryUsageStore {
+    async fn reserve(&self, scope: &QuotaScope, bytes: u64, limits: QuotaLimits) -> Result<(), StorageError> {
+        let mut usage = self.usage.lock().unwrap();
+
+        let tenant_used: u64 = usage.iter()
+            .filter(|(other, _)| other.tenant == scope.tenant)
+            .map(|(_, bytes)| bytes)
+            .sum();
+
+        check(tenant_used, usage.get(scope).copied().unwrap_or_default(), bytes, limits)?;
+
+        *usage.entry(scope.clone()).or_default() += bytes;
+        Ok(())
+    }
+
+    async fn release(&self, scope: &QuotaScope, bytes: u64) -> Result<(), StorageError> {
+        let mut usage = self.usage.lock().unwrap();
+        let used = usage.entry(scope.clone()).or_default();
+        *used = used.saturating_sub(bytes);
+        Ok(())
+    }
+
+    async fn set(&self, scope: &QuotaScope, bytes: u64) -> Result<(), StorageError> {
+        self.usage.lock().unwrap().insert(scope.clone(), bytes);
+        Ok(())
+    }
+
+    async fn usage(&self) -> Result<Vec<Usage>, StorageError> {
+        let mut usage: Vec<Usage> = self.usage.lock().unwrap()
+            .iter()
+            .map(|(scope, bytes)| Usage { scope: scope.clone(), bytes: *bytes })
+            .collect();
+
+        usage.sort_by(|a, b| a.scope.cmp(&b.scope));
+        Ok(usage)
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresUsageStore;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use async_trait::async_trait;
+
+    use crate::db::ConnectionPool;
+
+    use super::{check, QuotaLimits, QuotaScope, StorageError, Usage, UsageStore};
+
+    impl From<tokio_postgres::Error> for StorageError {
+        fn from(value: tokio_postgres::Error) -> Self {
+            StorageError::Store(value.to_string())
+        }
+    }
+
+    /// Create with `PostgresUsageStore::TABLE` before use.
+    pub struct PostgresUsageStore {
+        pool: ConnectionPool
+    }
+
+    impl PostgresUsageStore {
+        pub const TABLE: &'static str = "CREATE TABLE IF NOT EXISTS blandwork_storage_usage (
+            tenant TEXT NOT NULL,
+            feature TEXT NOT NULL,
+            bytes BIGINT NOT NULL,
+            PRIMARY KEY (tenant, feature)
+        )";
+
+        pub fn new(pool: ConnectionPool) -> Self {
+            Self { pool }
+        }
+
+        pub async fn migrate(&self) -> Result<(), StorageError> {
+            let connection = self.pool.get().await.map_err(|e| StorageError::Store(e.to_string()))?;
+            connection.batch_execute(Self::TABLE).await?;
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl UsageStore for PostgresUsageStore {
+        async fn reserve(&self, scope: &QuotaScope, bytes: u64, limits: QuotaLimits) -> Result<(), StorageError> {
+            let mut connection = self.pool.get().await.map_err(|e| StorageError::Store(e.to_string()))?;
+            let transaction = connection.transaction().await?;
+
+            // serializes reservations of a tenant, its rows may not exist yet
+            transaction.execute("SELECT pg_advisory_xact_lock(hashtext($1))", &[&scope.tenant]).await?;
+
+            let rows = transaction.query(
+                "SELECT feature, bytes FROM blandwork_storage_usage WHERE tenant = $1",
+                &[&scope.tenant]
+            ).await?;
+
+            let tenant_used: u64 = rows.iter().map(|row| row.get::<_, i64>("bytes") as u64).sum();
+            let feature_used: u64 = rows.iter()
+                .filter(|row| row.get::<_, &str>("feature") == scope.feature)
+                .map(|row| row.get::<_, i64>("bytes") as u64)
+                .sum();
+
+            check(tenant_used, feature_used, bytes, limits)?;
+
+            transaction.execute(
+                "INSERT INTO blandwork_storage_usage (tenant, feature, bytes) VALUES ($1, $2, $3)
+                 ON CONFLICT (tenant, feature) DO UPDATE SET bytes = blandwork_storage_usage.bytes + EXCLUDED.bytes",
+                &[&scope.tenant, &scope.feature, &(bytes as i64)]
+            ).await?;
+
+            transaction.commit().await?;
+            Ok(())
+        }
+
+        async fn release(&self, scope: &QuotaScope, bytes: u64) -> Result<(), StorageError> {
+            let connection = self.pool.get().await.map_err(|e| StorageError::Store(e.to_string()))?;
+
+            connection.execute(
+                "UPDATE blandwork_storage_usage SET bytes = GREATEST(bytes - $3, 0) WHERE tenant = $1 AND feature = $2",
+                &[&scope.tenant, &scope.feature, &(bytes as i64)]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn set(&self, scope: &QuotaScope, bytes: u64) -> Result<(), StorageError> {
+            let connection = self.pool.get().await.map_err(|e| StorageError::Store(e.to_string()))?;
+
+            connection.execute(
+                "INSERT INTO blandwork_storage_usage (tenant, feature, bytes) VALUES ($1, $2, $3)
+                 ON CONFLICT (tenant, feature) DO UPDATE SET bytes = EXCLUDED.bytes",
+                &[&scope.tenant, &scope.feature, &(bytes as i64)]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn usage(&self) -> Result<Vec<Usage>, StorageError> {
+            let connection = self.pool.get().await.map_err(|e| StorageError::Store(e.to_string()))?;
+
+            let rows = connection.query(
+                "SELECT tenant, feature, bytes FROM blandwork_storage_usage ORDER BY tenant, feature",
+                &[]
+            ).await?;
+
+            Ok(rows.iter().map(|row| Usage {
+                scope: QuotaScope { tenant: row.get("tenant"), feature: row.get("feature") },
+                bytes: row.get::<_, i64>("bytes") as u64,
+            }).collect())
+        }
+    }
+}
+
+/// Uploads on disk under `root/<tenant>/<feature>/<key>`, counted against quotas.
+#[derive(Clone)]
+pub struct FileStorage {
+    root: PathBuf,
+    limits: Arc<dyn QuotaProvider>,
+    usage: Arc<dyn UsageStore>,
+}
+
+impl FileStorage {
+    pub fn new(root: impl Into<PathBuf>, limits: Arc<dyn QuotaProvider>, usage: Arc<dyn UsageStore>) -> Self {
+        Self { root: root.into(), limits, usage }
+    }
+
+    fn path(&self, scope: &QuotaScope, key: &str) -> Result<PathBuf, StorageError> {
+        let valid = |part: &str| {
+            !part.is_empty() && !part.ends_with(PARTIAL)
+                && Path::new(part).components().all(|component| matches!(component, Component::Normal(_)))
+        };
+
+        if !valid(&scope.tenant) || !valid(&scope.feature) || !valid(key) {
+            return Err(StorageError::InvalidKey(key.to_owned()));
+        }
+
+        Ok(self.root.join(&scope.tenant).join(&scope.feature).join(key))
+    }
+
+    /// Writes `body` to `key`. A declared `content_length` is reserved before the
+    /// body is read, so an upload which can't fit is rejected without streaming it.
+    /// Otherwise bytes are reserved as they arrive and the upload is aborted once
+    /// over quota. Nothing of a failed upload stays on disk or in the counters.
+    /// The bytes of a replaced file count towards its replacement, only growth is reserved.
+    pub async fn put<S, E>(&self, scope: &QuotaScope, key: &str, content_length: Option<u64>, mut body: S) -> Result<u64, StorageError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin + Send,
+        E: Display,
+    {
+        let path: PathBuf = self.path(scope, key)?;
+        let limits: QuotaLimits = self.limits.limits(scope);
+
+        // already counted in the usage, released if the new file is smaller
+        let replaced: u64 = fs::metadata(&path).await.map(|metadata| metadata.len()).unwrap_or_default();
+
+        let mut reserved: u64 = replaced;
+        if let Some(length) = content_length.filter(|length| *length > replaced) {
+            self.usage.reserve(scope, length - replaced, limits).await?;
+            reserved = length;
+        }
+
+        // one per upload, concurrent puts of a key must not write into the same file
+        let partial: PathBuf = PathBuf::from(format!("{}.{}{PARTIAL}", path.display(), Uuid::new_v4().simple()));
+
+        let written: Result<u64, StorageError> = async {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+
+            let mut file = fs::File::create(&partial).await?;
+            let mut written: u64 = 0;
+
+            while let Some(chunk) = body.next().await {
+                let chunk: Bytes = chunk.map_err(|e| StorageError::Body(e.to_string()))?;
+                written += chunk.len() as u64;
+
+                if written > reserved {
+                    self.usage.reserve(scope, written - reserved, limits).await?;
+                    reserved = written;
+                }
+
+                file.write_all(&chunk).await?;
+            }
+
+            file.flush().await?;
+            Ok(written)
+        }.await;
+
+        let written: u64 = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = fs::remove_file(&partial).await;
+                self.usage.release(scope, reserved - replaced).await?;
+                return Err(e);
+            }
+        };
+
+        if let Err(e) = fs::rename(&partial, &path).await {
+            let _ = fs::remove_file(&partial).await;
+            self.usage.release(scope, reserved - replaced).await?;
+            return Err(e.into());
+        }
+
+        // a declared length larger than the body, or a smaller replacement
+        if reserved > written {
+            self.usage.release(scope, reserved - written).await?;
+        }
+
+        Ok(written)
+    }
+
+    /// False when there was nothing stored at `key`.
+    pub async fn delete(&self, scope: &QuotaScope, key: &str) -> Result<bool, StorageError> {
+        let path: PathBuf = self.path(scope, key)?;
+
+        let size: u64 = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        fs::remove_file(&path).await?;
+        self.usage.release(scope, size).await?;
+
+        Ok(true)
+    }
+
+    pub async fn usage(&self) -> Result<Vec<Usage>, StorageError> {
+        self.usage.usage().await
+    }
+
+    /// Recomputes every counter from the files on disk, returning the scopes which had drifted.
+    pub async fn reconcile(&self) -> Result<Vec<Usage>, StorageError> {
+        let mut actual: BTreeMap<QuotaScope, u64> = BTreeMap::new();
+
+        for tenant in directories(&self.root).await? {
+            for feature in directories(&self.root.join(&tenant)).await? {
+                let bytes: u64 = directory_size(self.root.join(&tenant).join(&feature)).await?;
+                actual.insert(QuotaScope { tenant: tenant.clone(), feature }, bytes);
+            }
+        }
+
+        // counters without files left
+        for usage in self.usage.usage().await? {
+            actual.entry(usage.scope).or_default();
+        }
+
+        let recorded: HashMap<QuotaScope, u64> = self.usage.usage().await?
+            .into_iter()
+            .map(|usage| (usage.scope, usage.bytes))
+            .collect();
+
+        let mut drifted: Vec<Usage> = Vec::new();
+
+        for (scope, bytes) in actual {
+            if recorded.get(&scope).copied().unwrap_or_default() != bytes {
+                tracing::warn!(tenant = scope.tenant, feature = scope.feature, "storage usage drifted, now {bytes} bytes");
+                self.usage.set(&scope, bytes).await?;
+                drifted.push(Usage { scope, bytes });
+            }
+        }
+
+        Ok(drifted)
+    }
+
+    /// `reconcile` as a job for `Feature::schedule`.
+    pub fn reconcile_job(&self, cron: &str) -> ScheduledJob {
+        let storage: FileStorage = self.clone();
+
+        ScheduledJob::new("storage usage reconciliation", cron, move |_| {
+            let storage: FileStorage = storage.clone();
+
+            async move {
+                storage.reconcile().await?;
+                Ok(())
+            }
+        })
+    }
+}
+
+async fn directories(path: &Path) -> Result<Vec<String>, StorageError> {
+    let mut names: Vec<String> = Vec::new();
+
+    let mut entries = match fs::read_dir(path).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+        Err(e) => return Err(e.into()),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_type().await?.is_dir() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    Ok(names)
+}
+
+async fn directory_size(path: PathBuf) -> Result<u64, StorageError> {
+    let mut size: u64 = 0;
+    let mut pending: Vec<PathBuf> = vec![path];
+
+    while let Some(directory) = pending.pop() {
+        let mut entries = fs::read_dir(&directory).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else if !entry.file_name().to_string_lossy().ends_with(PARTIAL) {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+#[derive(Serialize)]
+struct QuotaExceeded {
+    level: QuotaLevel,
+    limit: u64,
+    used: u64,
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_048_576.0)
+}
+
+/// The error fragment of an upload form, with the `QUOTA_EXCEEDED` trigger
+/// for a rejected upload.
+pub fn quota_exceeded(context: &mut Context, error: &StorageError) -> Markup {
+    let StorageError::QuotaExceeded { level, limit, used, .. } = error else {
+        return html! { p.upload-error role="alert" { "The upload failed, please try again." } };
+    };
+
+    context.add_trigger(QUOTA_EXCEEDED.to_owned(), QuotaExceeded { level: *level, limit: *limit, used: *used });
+
+    let owner: &str = match level {
+        QuotaLevel::Tenant => "Your account",
+        QuotaLevel::Feature => "This section",
+    };
+
+    html! {
+        p.upload-error role="alert" {
+            (owner) " has used " (megabytes(*used)) " of its " (megabytes(*limit)) " storage, delete files to make room."
+        }
+    }
+}
+
+/// Stored bytes per tenant against its limit, for an admin page.
+pub fn usage_panel(usage: &[Usage], limits: &dyn QuotaProvider) -> Markup {
+    let mut tenants: BTreeMap<&str, u64> = BTreeMap::new();
+    for usage in usage {
+        *tenants.entry(usage.scope.tenant.as_str()).or_default() += usage.bytes;
+    }
+
+    html! {
+        table.storage-usage {
+            thead { tr { th { "Tenant" } th { "Used" } th { "Limit" } } }
+            tbody {
+                @for (tenant, bytes) in tenants {
+                    @let limit = limits.limits(&QuotaScope::new(tenant, "")).tenant;
+                    tr id=(crate::morph_id("storage", tenant)) {
+                        td { (tenant) }
+                        td { (megabytes(bytes)) }
+                        td {
+                            @match limit {
+                                Some(limit) => { (megabytes(limit)) },
+                                None => { "unlimited" },
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        convert::Infallible, path::PathBuf,
+        sync::{atomic::{AtomicUsize, Ordering}, Arc}
+    };
+
+    use axum::{body::{Body, Bytes}, extract::Request};
+    use tokio_stream::StreamExt;
+    use uuid::Uuid;
+
+    use crate::{config::Quotas, ContextAccessor};
+
+    use super::{quota_exceeded, FileStorage, QUOTA_EXCEEDED, MemoryUsageStore, QuotaLevel, QuotaScope, StorageError, Usage, UsageStore};
+
+    fn storage(tenant_bytes: u64) -> (FileStorage, Arc<MemoryUsageStore>, PathBuf) {
+        let root = std::env::temp_dir().join(format!("blandwork-storage-{}", Uuid::new_v4()));
+        let usage = Arc::new(MemoryUsageStore::default());
+
+        let quotas = Quotas {
+            tenant_bytes: Some(tenant_bytes),
+            features: [("Avatars".to_owned(), 4)].into(),
+            ..Default::default()
+        };
+
+        (FileStorage::new(&root, Arc::new(quotas), usage.clone()), usage, root)
+    }
+
+    fn body(chunks: &[&'static str]) -> impl tokio_stream::Stream<Item = Result<Bytes, Infallible>> + Unpin + Send {
+        tokio_stream::iter(chunks.iter().map(|chunk| Ok(Bytes::from_static(chunk.as_bytes()))).collect::<Vec<_>>())
+    }
+
+    #[tokio::test]
+    async fn test_put_and_delete() {
+        let (storage, usage, root) = storage(10);
+        let scope = QuotaScope::new("acme", "Documents");
+
+        assert_eq!(storage.put(&scope, "a.txt", Some(6), body(&["abc", "def"])).await, Ok(6));
+        assert_eq!(std::fs::read_to_string(root.join("acme/Documents/a.txt")).unwrap(), "abcdef");
+
+        // replacing counts the old size, 6 + 8 would be over the limit
+        assert_eq!(storage.put(&scope, "a.txt", None, body(&["abcdefgh"])).await, Ok(8));
+        assert_eq!(usage.usage().await.unwrap(), vec![Usage { scope: scope.clone(), bytes: 8 }]);
+
+        assert_eq!(storage.put(&scope, "a.txt", Some(3), body(&["abc"])).await, Ok(3));
+        assert_eq!(usage.usage().await.unwrap()[0].bytes, 3);
+
+        assert_eq!(storage.delete(&scope, "a.txt").await, Ok(true));
+        assert_eq!(storage.delete(&scope, "a.txt").await, Ok(false));
+        assert_eq!(usage.usage().await.unwrap()[0].bytes, 0);
+
+        assert!(matches!(storage.put(&scope, "../b.txt", None, body(&["x"])).await, Err(StorageError::InvalidKey(_))));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_early_rejection() {
+        let (storage, usage, root) = storage(10);
+        let scope = QuotaScope::new("acme", "Documents");
+
+        let polled = Arc::new(AtomicUsize::new(0));
+        let counter = polled.clone();
+        let stream = body(&["0123456789ab"]).map(move |chunk| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            chunk
+        });
+
+        let result = storage.put(&scope, "big.bin", Some(12), Box::pin(stream)).await;
+
+        assert_eq!(result, Err(StorageError::QuotaExceeded { level: QuotaLevel::Tenant, limit: 10, used: 0, requested: 12 }));
+        assert_eq!(polled.load(Ordering::Relaxed), 0);
+        assert!(!root.join("acme/Documents").exists());
+        assert!(usage.usage().await.unwrap().is_empty());
+
+        // per feature
+        let avatars = QuotaScope::new("acme", "Avatars");
+        assert!(matches!(
+            storage.put(&avatars, "me.png", Some(5), body(&["12345"])).await,
+            Err(StorageError::QuotaExceeded { level: QuotaLevel::Feature, limit: 4, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mid_stream_abort() {
+        let (storage, usage, root) = storage(10);
+        let scope = QuotaScope::new("acme", "Documents");
+
+        storage.put(&scope, "kept.txt", None, body(&["1234"])).await.unwrap();
+
+        let result = storage.put(&scope, "stream.bin", None, body(&["abc", "def", "ghi"])).await;
+
+        assert!(matches!(result, Err(StorageError::QuotaExceeded { level: QuotaLevel::Tenant, .. })));
+        assert_eq!(std::fs::read_dir(root.join("acme/Documents")).unwrap().count(), 1);
+        assert_eq!(usage.usage().await.unwrap(), vec![Usage { scope, bytes: 4 }]);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_puts() {
+        let (storage, _, root) = storage(100);
+        let scope = QuotaScope::new("acme", "Documents");
+
+        let (first, second) = tokio::join!(
+            storage.put(&scope, "a.txt", None, body(&["aaaa", "aaaa"])),
+            storage.put(&scope, "a.txt", None, body(&["bbbb", "bbbb"]))
+        );
+
+        assert_eq!((first.unwrap(), second.unwrap()), (8, 8));
+
+        // one of the two uploads as a whole, nothing left over
+        let content: String = std::fs::read_to_string(root.join("acme/Documents/a.txt")).unwrap();
+        assert!(content == "aaaaaaaa" || content == "bbbbbbbb");
+        assert_eq!(std::fs::read_dir(root.join("acme/Documents")).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let (storage, usage, root) = storage(100);
+        let documents = QuotaScope::new("acme", "Documents");
+        let stale = QuotaScope::new("globex", "Documents");
+
+        storage.put(&documents, "a.txt", None, body(&["12345"])).await.unwrap();
+        std::fs::write(root.join("acme/Documents/a.txt.partial"), "left over").unwrap();
+
+        usage.set(&documents, 42).await.unwrap();
+        usage.set(&stale, 7).await.unwrap();
+
+        let drifted = storage.reconcile().await.unwrap();
+
+        assert_eq!(drifted, vec![Usage { scope: documents.clone(), bytes: 5 }, Usage { scope: stale.clone(), bytes: 0 }]);
+        assert_eq!(usage.usage().await.unwrap(), vec![Usage { scope: documents, bytes: 5 }, Usage { scope: stale, bytes: 0 }]);
+        assert!(storage.reconcile().await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_error_fragment() {
+        let request = Request::builder().uri("/upload").body(Body::empty()).unwrap();
+        let accessor = ContextAccessor::from_request(&request);
+        let mut context = accessor.context().await;
+
+        let error = StorageError::QuotaExceeded { level: QuotaLevel::Tenant, limit: 10_485_760, used: 10_000_000, requested: 1_048_576 };
+        let markup = quota_exceeded(&mut context, &error).into_string();
+
+        assert_eq!(markup, r#"<p class="upload-error" role="alert">Your account has used 9.5 MB of its 10.0 MB storage, delete files to make room.</p>"#);
+
+        let trigger = context.triggers().unwrap();
+        let trigger = trigger.to_str().unwrap();
+        assert!(trigger.contains(QUOTA_EXCEEDED));
+        assert!(trigger.contains(r#""level":"tenant""#));
+    }
+}
@@ -0,0 +1,570 @@
+use std::{
+    collections::VecDeque, future::Future, io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::Request,
+    http::HeaderValue,
+    response::IntoResponse
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{header::{CONTENT_LENGTH, CONTENT_TYPE}, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_stream::StreamExt;
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::{
+    config::Recording,
+    cookies::{CookieJar, CookieSettings},
+    experiment::ExperimentAssignments
+};
+
+/// Set on `5xx` responses of recorded requests, the id of their `RequestRecord`.
+pub const RECORD_HEADER: &str = "x-blandwork-record";
+
+const REDACTED: &str = "[redacted]";
+
+/// Route and locale of a request, left on the response by the context layer.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestInfo {
+    pub route: Option<String>,
+    pub locale: Option<String>,
+}
+
+/// A sanitized request and the outcome it had, enough to replay it (see `test::replay`).
+/// Only built by the recording layer, which redacts before any sink sees it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct RequestRecord {
+    pub id: String,
+
+    // unix milliseconds
+    pub recorded_at: u64,
+    pub method: String,
+    pub uri: String,
+    pub headers: Vec<(String, String)>,
+
+    // base64, redacted then cut to `max_body`
+    pub body: String,
+    pub body_truncated: bool,
+
+    pub route: Option<String>,
+    pub locale: Option<String>,
+    pub experiments: Option<String>,
+
+    pub status: u16,
+
+    // sha256 of html responses, compare a replay with the original
+    pub fragment_hash: Option<String>,
+}
+
+impl RequestRecord {
+    /// Redacts headers and body fields named by `config` before anything is kept.
+    fn capture(request: &Request, body: Option<&[u8]>, config: &Recording) -> Self {
+        let headers: Vec<(String, String)> = request.headers()
+            .iter()
+            .map(|(name, value)| {
+                let redacted: bool = config.redact_headers.iter().any(|redact| name.as_str().eq_ignore_ascii_case(redact));
+
+                let value: String = match redacted {
+                    true => REDACTED.to_owned(),
+                    false => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                };
+
+                (name.as_str().to_owned(), value)
+            })
+            .collect();
+
+        let content_type: &str = request.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let (body, body_truncated) = match body {
+            Some(body) => {
+                let mut body: Vec<u8> = redact_body(content_type, body, &config.redact_fields);
+                let truncated: bool = body.len() > config.max_body;
+                body.truncate(config.max_body);
+
+                (STANDARD.encode(body), truncated)
+            },
+            None => (String::new(), true),
+        };
+
+        let settings: Arc<CookieSettings> = request.extensions().get::<Arc<CookieSettings>>().cloned().unwrap_or_default();
+
+        Self {
+            id: Uuid::new_v4().to_string(),
+            recorded_at: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+            method: request.method().to_string(),
+            uri: request.uri().to_string(),
+            headers,
+            body,
+            body_truncated,
+            route: None,
+            locale: None,
+            experiments: CookieJar::from_request(request, &settings)
+                .get::<ExperimentAssignments>()
+                .map(|assignments| assignments.label())
+                .filter(|label| !label.is_empty()),
+            status: 0,
+            fragment_hash: None,
+        }
+    }
+
+    pub fn body_bytes(&self) -> Vec<u8> {
+        STANDARD.decode(&self.body).unwrap_or_default()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.status >= 500
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        serde_json::from_str(&std::fs::read_to_string(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The request again, without the redacted headers.
+    pub fn to_request(&self) -> Request {
+        let mut request = Request::builder().method(self.method.as_str()).uri(self.uri.as_str());
+
+        for (name, value) in self.headers.iter().filter(|(_, value)| value != REDACTED) {
+            request = request.header(name.as_str(), value.as_str());
+        }
+
+        request.body(Body::from(self.body_bytes())).unwrap_or_default()
+    }
+}
+
+/// Replaces `fields` in urlencoded and JSON bodies, other bodies are kept as they are.
+fn redact_body(content_type: &str, body: &[u8], fields: &[String]) -> Vec<u8> {
+    let redacted = |name: &str| fields.iter().any(|field| name.eq_ignore_ascii_case(field));
+
+    if content_type.starts_with("application/x-www-form-urlencoded") {
+        return String::from_utf8_lossy(body)
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if redacted(name) => format!("{name}={}", "%5Bredacted%5D"),
+                _ => pair.to_owned(),
+            })
+            .collect::<Vec<String>>()
+            .join("&")
+            .into_bytes();
+    }
+
+    if content_type.starts_with("application/json") {
+        fn walk(value: &mut serde_json::Value, redacted: &dyn Fn(&str) -> bool) {
+            match value {
+                serde_json::Value::Object(map) => {
+                    for (key, value) in map.iter_mut() {
+                        match redacted(key) {
+                            true => *value = serde_json::Value::String(REDACTED.to_owned()),
+                            false => walk(value, redacted),
+                        }
+                    }
+                },
+                serde_json::Value::Array(items) => items.iter_mut().for_each(|item| walk(item, redacted)),
+                _ => {},
+            }
+        }
+
+        // unparsable JSON could hide anything, keep none of it
+        return match serde_json::from_slice::<serde_json::Value>(body) {
+            Ok(mut value) => {
+                walk(&mut value, &redacted);
+                value.to_string().into_bytes()
+            },
+            Err(_) => REDACTED.as_bytes().to_vec(),
+        };
+    }
+
+    body.to_vec()
+}
+
+/// Where records go, only ever handed sanitized records.
+pub trait RecordSink: Send + Sync {
+    fn write(&self, record: &RequestRecord);
+}
+
+/// The last `capacity` records, provided to handlers as an extension when recording.
+#[derive(Clone)]
+pub struct MemoryRecorder {
+    records: Arc<Mutex<VecDeque<RequestRecord>>>,
+    capacity: usize,
+}
+
+impl MemoryRecorder {
+    pub fn new(capacity: usize) -> Self {
+        Self { records: Arc::new(Mutex::new(VecDeque::new())), capacity }
+    }
+
+    /// Oldest first.
+    pub fn records(&self) -> Vec<RequestRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<RequestRecord> {
+        self.records.lock().unwrap().iter().find(|record| record.id == id).cloned()
+    }
+
+    /// Records of failed requests, each linked from its response by `RECORD_HEADER`.
+    pub fn errors(&self) -> Vec<RequestRecord> {
+        self.records.lock().unwrap().iter().filter(|record| record.is_error()).cloned().collect()
+    }
+}
+
+impl RecordSink for MemoryRecorder {
+    fn write(&self, record: &RequestRecord) {
+        let mut records = self.records.lock().unwrap();
+
+        if records.len() >= self.capacity {
+            records.pop_front();
+        }
+
+        records.push_back(record.clone());
+    }
+}
+
+/// One `<id>.json` per record in `dir`.
+pub struct FileSink {
+    dir: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl RecordSink for FileSink {
+    fn write(&self, record: &RequestRecord) {
+        let path: PathBuf = self.dir.join(format!("{}.json", record.id));
+
+        let written = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| serde_json::to_vec_pretty(record).map_err(io::Error::from))
+            .and_then(|json| std::fs::write(&path, json));
+
+        if let Err(e) = written {
+            tracing::error!("unable to write request record {}: {e}", path.display());
+        }
+    }
+}
+
+/// Records sampled requests into its sinks, see `server.recording`.
+/// Bodies declared larger than `max_body` are not buffered and recorded empty.
+#[derive(Clone)]
+pub struct RecordingLayer {
+    config: Arc<Recording>,
+
+    // every request is recorded in development
+    development: bool,
+    sinks: Vec<Arc<dyn RecordSink>>,
+}
+
+impl RecordingLayer {
+    pub fn new(config: Recording, development: bool) -> Self {
+        Self { config: Arc::new(config), development, sinks: Vec::new() }
+    }
+
+    pub fn sink(mut self, sink: Arc<dyn RecordSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    fn sampled(&self) -> bool {
+        if self.development {
+            return true;
+        }
+
+        let random: u64 = Uuid::new_v4().as_u64_pair().0;
+        (random as f64 / u64::MAX as f64) < self.config.sample
+    }
+}
+
+impl<S> Layer<S> for RecordingLayer {
+    type Service = RecordingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordingService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct RecordingService<S> {
+    inner: S,
+    layer: RecordingLayer,
+}
+
+impl<S> Service<Request> for RecordingService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if !self.layer.sampled() {
+            return Box::pin(self.inner.call(req));
+        }
+
+        // the ready service answers this request, a clone is left for the next
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer: RecordingLayer = self.layer.clone();
+
+        Box::pin(async move {
+            let declared: Option<usize> = req.headers().get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
+
+            let (record, req) = match declared {
+                Some(length) if length > layer.config.max_body => (RequestRecord::capture(&req, None, &layer.config), req),
+                _ => {
+                    let (req, bytes) = read_ahead(req, layer.config.max_body).await;
+                    (RequestRecord::capture(&req, Some(&bytes), &layer.config), req)
+                }
+            };
+
+            let response: Response<Body> = inner.call(req).await?;
+            let mut record: RequestRecord = record;
+
+            record.status = response.status().as_u16();
+
+            if let Some(info) = response.extensions().get::<RequestInfo>() {
+                record.route = info.route.clone();
+                record.locale = info.locale.clone();
+            }
+
+            let html: bool = response.headers().get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.starts_with("text/html"))
+                .unwrap_or(false);
+
+            let mut response: Response<Body> = match html {
+                true => {
+                    let (parts, body) = response.into_parts();
+
+                    match to_bytes(body, usize::MAX).await {
+                        Ok(bytes) => {
+                            record.fragment_hash = Some(fragment_hash(&bytes));
+                            Response::from_parts(parts, Body::from(bytes))
+                        },
+                        Err(e) => {
+                            tracing::error!("unable to read the response of {} {}: {e}", record.method, record.uri);
+                            record.status = StatusCode::INTERNAL_SERVER_ERROR.as_u16();
+                            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                        }
+                    }
+                },
+                false => response,
+            };
+
+            if record.is_error() {
+                tracing::error!(record = record.id, "{} {} failed with {}, recorded", record.method, record.uri, record.status);
+
+                if let Ok(id) = HeaderValue::from_str(&record.id) {
+                    response.headers_mut().insert(RECORD_HEADER, id);
+                }
+            }
+
+            for sink in layer.sinks.iter() {
+                sink.write(&record);
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+/// Reads up to `max` bytes (and the rest of the chunk crossing it) of a body without a
+/// declared length, the handler still gets all of it: what was read, then the remainder
+/// as it streams in. The bytes read are longer than `max` when the body was cut.
+async fn read_ahead(req: Request, max: usize) -> (Request, Bytes) {
+    let (parts, body) = req.into_parts();
+    let mut stream = body.into_data_stream();
+    let mut read: Vec<u8> = Vec::new();
+
+    while read.len() <= max {
+        match stream.next().await {
+            Some(Ok(chunk)) => read.extend_from_slice(&chunk),
+            Some(Err(e)) => {
+                tracing::error!("unable to record request body: {e}");
+                return (Request::from_parts(parts, Body::empty()), Bytes::new());
+            },
+            None => {
+                let read: Bytes = Bytes::from(read);
+                return (Request::from_parts(parts, Body::from(read.clone())), read);
+            },
+        }
+    }
+
+    let read: Bytes = Bytes::from(read);
+    let body: Body = Body::from_stream(tokio_stream::once(Ok(read.clone())).chain(stream));
+
+    (Request::from_parts(parts, body), read)
+}
+
+pub(crate) fn fragment_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::{get, post}, Router};
+    use hyper::{header::CONTENT_TYPE, StatusCode};
+    use maud::html;
+    use tower::ServiceExt;
+
+    use crate::{config::Recording, test::replay};
+
+    use super::{fragment_hash, MemoryRecorder, RecordingLayer, RequestRecord, RECORD_HEADER};
+
+    fn app(recorder: &MemoryRecorder, config: Recording) -> Router {
+        Router::new()
+            .route("/invoices", post(|body: String| async move { html! { p { "saved " (body.len()) } } }))
+            .route("/broken", get(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "boom") }))
+            .layer(RecordingLayer::new(config, true).sink(Arc::new(recorder.clone())))
+    }
+
+    fn form(uri: &str, content_type: &str, body: &str) -> Request {
+        Request::post(uri)
+            .header(CONTENT_TYPE, content_type)
+            .header("cookie", "session=sessi0nvalue")
+            .header("authorization", "Bearer s3cret")
+            .header("accept", "text/html")
+            .body(Body::from(body.to_owned()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_redaction() {
+        let recorder = MemoryRecorder::new(10);
+        let app = app(&recorder, Recording::default());
+
+        app.clone().oneshot(form("/invoices", "application/x-www-form-urlencoded", "email=a%40b.c&password=hunter2")).await.unwrap();
+        app.clone().oneshot(form("/invoices", "application/json", r#"{"user":{"name":"a","Token":"t0k"},"items":[{"secret":1}]}"#)).await.unwrap();
+        app.oneshot(form("/invoices", "application/json", r#"{"password": "hunter2"#)).await.unwrap();
+
+        let records = recorder.records();
+        let serialized = serde_json::to_string(&records).unwrap();
+
+        for secret in ["sessi0nvalue", "s3cret", "hunter2", "t0k"] {
+            assert!(!serialized.contains(secret), "{secret} leaked");
+        }
+
+        assert!(records[0].headers.contains(&("cookie".to_owned(), "[redacted]".to_owned())));
+        assert!(records[0].headers.contains(&("accept".to_owned(), "text/html".to_owned())));
+        assert_eq!(String::from_utf8(records[0].body_bytes()).unwrap(), "email=a%40b.c&password=%5Bredacted%5D");
+        assert_eq!(
+            String::from_utf8(records[1].body_bytes()).unwrap(),
+            r#"{"items":[{"secret":"[redacted]"}],"user":{"Token":"[redacted]","name":"a"}}"#
+        );
+        assert_eq!(String::from_utf8(records[2].body_bytes()).unwrap(), "[redacted]");
+    }
+
+    #[tokio::test]
+    async fn test_size_cap() {
+        let recorder = MemoryRecorder::new(10);
+        let config = Recording { max_body: 4, ..Default::default() };
+
+        let router = app(&recorder, config);
+
+        // buffered then cut, the handler still gets everything
+        let response = router.clone().oneshot(form("/invoices", "text/plain", "0123456789")).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "<p>saved 10</p>");
+
+        // streamed without a length, read up to the cap and passed on whole
+        let chunks = tokio_stream::iter(["0123", "4567", "89"].map(Ok::<_, std::io::Error>));
+        let streamed = Request::post("/invoices").body(Body::from_stream(chunks)).unwrap();
+        let response = router.clone().oneshot(streamed).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "<p>saved 10</p>");
+
+        // declared too large, never buffered
+        let large = Request::post("/invoices").header("content-length", "10").body(Body::from("0123456789")).unwrap();
+        router.oneshot(large).await.unwrap();
+
+        let records = recorder.records();
+        assert_eq!(records[0].body_bytes(), b"0123");
+        assert!(records[0].body_truncated);
+        assert_eq!(records[1].body_bytes(), b"0123");
+        assert!(records[1].body_truncated);
+        assert!(records[2].body_bytes().is_empty());
+        assert!(records[2].body_truncated);
+
+        // the ring buffer keeps the latest
+        let small = MemoryRecorder::new(1);
+        let router = app(&small, Recording::default());
+        router.clone().oneshot(form("/invoices", "text/plain", "a")).await.unwrap();
+        router.oneshot(form("/invoices", "text/plain", "bb")).await.unwrap();
+        assert_eq!(small.records().len(), 1);
+        assert_eq!(small.records()[0].body_bytes(), b"bb");
+    }
+
+    #[tokio::test]
+    async fn test_replay_round_trip() {
+        let recorder = MemoryRecorder::new(10);
+        let app = app(&recorder, Recording::default());
+
+        app.clone().oneshot(form("/invoices", "text/plain", "draft")).await.unwrap();
+
+        let json = serde_json::to_string(&recorder.records()[0]).unwrap();
+        let record: RequestRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(record, recorder.records()[0]);
+
+        let replayed = replay(&app, &record).await;
+        assert_eq!(replayed.status().as_u16(), record.status);
+
+        let body = to_bytes(replayed.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(Some(fragment_hash(&body)), record.fragment_hash);
+    }
+
+    #[tokio::test]
+    async fn test_failed_response_body() {
+        let recorder = MemoryRecorder::new(10);
+        let app = Router::new()
+            .route("/report", get(|| async {
+                let chunks = tokio_stream::iter([Ok("<p>"), Err(std::io::Error::other("disk gone"))]);
+                ([(CONTENT_TYPE, "text/html")], Body::from_stream(chunks))
+            }))
+            .layer(RecordingLayer::new(Recording::default(), true).sink(Arc::new(recorder.clone())));
+
+        // never an empty page looking like a success
+        let response = app.oneshot(Request::get("/report").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(recorder.records()[0].status, 500);
+    }
+
+    #[tokio::test]
+    async fn test_error_linked_to_record() {
+        let recorder = MemoryRecorder::new(10);
+        let app = app(&recorder, Recording::default());
+
+        let ok = app.clone().oneshot(form("/invoices", "text/plain", "a")).await.unwrap();
+        assert!(ok.headers().get(RECORD_HEADER).is_none());
+
+        let failed = app.oneshot(Request::get("/broken").body(Body::empty()).unwrap()).await.unwrap();
+        let id = failed.headers()[RECORD_HEADER].to_str().unwrap();
+
+        let record = recorder.get(id).unwrap();
+        assert_eq!(record.status, 500);
+        assert_eq!(record.uri, "/broken");
+        assert_eq!(recorder.errors(), vec![record]);
+    }
+}
@@ -0,0 +1,265 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::LOCATION, request::Parts, StatusCode},
+    response::{IntoResponse, Response}
+};
+use axum_htmx::{HX_LOCATION, HX_REDIRECT, HX_REFRESH, HX_REQUEST};
+use serde_json::json;
+
+use crate::list::HIGHLIGHT_PARAM;
+
+/// The page region HTMX navigation swaps into, `<main id="content">` of the app template.
+pub const CONTENT_TARGET: &str = "#content";
+
+/// Redirects that HTMX follows: a `200` with `HX-Redirect`/`HX-Location` for HTMX
+/// requests (boosted or not), a plain `302` otherwise. Extract it in the handler:
+///
+/// ```ignore
+/// async fn save(redirect: HtmxRedirect, Form(invoice): Form<Invoice>) -> Redirection {
+///     // ...
+///     redirect.to(&format!("/invoices/{}", invoice.id))
+/// }
+/// ```
+///
+/// Prefer `location` for pages of the same app, HTMX swaps them in without a full reload.
+#[derive(Debug, Clone, Copy)]
+pub struct HtmxRedirect {
+    htmx: bool,
+}
+
+impl HtmxRedirect {
+    pub fn new(htmx: bool) -> Self {
+        Self { htmx }
+    }
+
+    /// Full page navigation, `HX-Redirect` for HTMX.
+    pub fn to(self, uri: &str) -> Redirection {
+        Redirection { htmx: self.htmx, status: StatusCode::FOUND, header: HX_REDIRECT, value: uri.to_owned(), uri: uri.to_owned() }
+    }
+
+    /// Navigation without a reload, `HX-Location` for HTMX.
+    pub fn location(self, uri: &str) -> Redirection {
+        Redirection { htmx: self.htmx, status: StatusCode::FOUND, header: HX_LOCATION, value: uri.to_owned(), uri: uri.to_owned() }
+    }
+
+    /// `location` swapped into `target` with `swap` (`innerHTML`, `outerHTML`...), either
+    /// left to HTMX's defaults when None. A plain request is redirected to `uri`.
+    pub fn location_with(self, uri: &str, target: Option<&str>, swap: Option<&str>) -> Redirection {
+        if target.is_none() && swap.is_none() {
+            return self.location(uri);
+        }
+
+        let mut value: serde_json::Map<String, serde_json::Value> = serde_json::Map::new();
+        value.insert("path".to_owned(), uri.into());
+
+        if let Some(target) = target {
+            value.insert("target".to_owned(), target.into());
+        }
+
+        if let Some(swap) = swap {
+            value.insert("swap".to_owned(), swap.into());
+        }
+
+        Redirection {
+            htmx: self.htmx,
+            status: StatusCode::FOUND,
+            header: HX_LOCATION,
+            value: serde_json::Value::Object(value).to_string(),
+            uri: uri.to_owned(),
+        }
+    }
+
+    /// Full reload of the page, `HX-Refresh` for HTMX, a redirect to `uri` (the page) otherwise.
+    pub fn refresh(self, uri: &str) -> Redirection {
+        Redirection { htmx: self.htmx, status: StatusCode::FOUND, header: HX_REFRESH, value: "true".to_owned(), uri: uri.to_owned() }
+    }
+
+    /// Back to the list at `list_route` after creating a row, `row_id` being its `morph_id`.
+    /// HTMX swaps the list into `CONTENT_TARGET`, `?highlight=` has the list render the page
+    /// holding the row and reveal it (`ListQuery`). Without HTMX the browser scrolls to the
+    /// `#row_id` fragment of a plain redirect.
+    pub fn to_row(self, list_route: &str, row_id: &str) -> Redirection {
+        let separator: char = if list_route.contains('?') { '&' } else { '?' };
+        let path: String = format!("{list_route}{separator}{HIGHLIGHT_PARAM}={row_id}");
+
+        Redirection {
+            htmx: self.htmx,
+            status: StatusCode::FOUND,
+            header: HX_LOCATION,
+            value: json!({ "path": path, "target": CONTENT_TARGET }).to_string(),
+            uri: format!("{path}#{row_id}"),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for HtmxRedirect
+where
+    S: Send + Sync,
+{
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(HtmxRedirect::new(parts.headers.contains_key(HX_REQUEST)))
+    }
+}
+
+/// Built by `HtmxRedirect`, the response of the handler.
+#[derive(Debug, Clone)]
+pub struct Redirection {
+    htmx: bool,
+
+    // of the plain redirect
+    status: StatusCode,
+    header: &'static str,
+
+    // of the HTMX header, a uri or HX-Location's JSON
+    value: String,
+
+    // Location of the plain redirect
+    uri: String,
+}
+
+impl Redirection {
+    /// A `303 See Other` rather than a `302` for plain requests, the browser follows it
+    /// with a GET whatever the method of the submission (login, logout).
+    pub fn see_other(mut self) -> Self {
+        self.status = StatusCode::SEE_OTHER;
+        self
+    }
+}
+
+impl IntoResponse for Redirection {
+    fn into_response(self) -> Response {
+        match self.htmx {
+            true => (StatusCode::OK, [(self.header, self.value)]).into_response(),
+            false => (self.status, [(LOCATION, self.uri)]).into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request, routing::{get, post}, Extension, Router};
+    use axum_htmx::{HX_BOOSTED, HX_LOCATION, HX_REDIRECT, HX_REFRESH, HX_REQUEST};
+    use hyper::{header::{LOCATION, REFERER}, StatusCode};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::HtmxRedirect;
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Invoices;
+
+    impl Feature for Invoices {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new()
+                .route("/invoices", post(|redirect: HtmxRedirect| async move { redirect.to("/invoices/7") }))
+                .route("/invoices/7", post(|redirect: HtmxRedirect| async move { redirect.location("/invoices") }))
+                .route("/invoices/new", post(|redirect: HtmxRedirect| async move { redirect.to_row("/invoices?status=open", "invoice-8") }))
+                .route("/login", post(|Extension(accessor): Extension<ContextAccessor>| async move {
+                    accessor.context().await.redirect("/invoices");
+                    "ignored"
+                }))
+                .route("/invoices/7/close", post(|Extension(accessor): Extension<ContextAccessor>| async move {
+                    accessor.context().await.location("/invoices/7", Some("#invoice"), Some("outerHTML"));
+                }))
+                .route("/invoices/7/reopen", get(reopen).post(reopen)))
+        }
+    }
+
+    async fn reopen(Extension(accessor): Extension<ContextAccessor>) -> Markup {
+        accessor.context().await.refresh();
+        html! { "reopened" }
+    }
+
+    async fn send(uri: &str, headers: &[(&str, &str)]) -> axum::response::Response {
+        let app = router(&App::new(Config::default(), TestTemplate).register_feature(Invoices).build());
+
+        let mut request = Request::post(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_plain_request_gets_302() {
+        let response = send("/invoices", &[]).await;
+
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()[LOCATION], "/invoices/7");
+        assert!(!response.headers().contains_key(HX_REDIRECT));
+    }
+
+    #[tokio::test]
+    async fn test_htmx_request_gets_header() {
+        let boosted = send("/invoices", &[(HX_REQUEST, "true"), (HX_BOOSTED, "true")]).await;
+        assert_eq!(boosted.status(), StatusCode::OK);
+        assert_eq!(boosted.headers()[HX_REDIRECT], "/invoices/7");
+
+        // not boosted, the template would otherwise wrap it into a page
+        let htmx = send("/invoices/7", &[(HX_REQUEST, "true")]).await;
+        assert_eq!(htmx.status(), StatusCode::OK);
+        assert_eq!(htmx.headers()[HX_LOCATION], "/invoices");
+        assert!(!htmx.headers().contains_key(LOCATION));
+    }
+
+    #[tokio::test]
+    async fn test_to_row() {
+        let htmx = send("/invoices/new", &[(HX_REQUEST, "true")]).await;
+        assert_eq!(htmx.status(), StatusCode::OK);
+
+        let location: serde_json::Value = serde_json::from_str(htmx.headers()[HX_LOCATION].to_str().unwrap()).unwrap();
+        assert_eq!(location, serde_json::json!({ "path": "/invoices?status=open&highlight=invoice-8", "target": "#content" }));
+
+        let plain = send("/invoices/new", &[]).await;
+        assert_eq!(plain.status(), StatusCode::FOUND);
+        assert_eq!(plain.headers()[LOCATION], "/invoices?status=open&highlight=invoice-8#invoice-8");
+    }
+
+    #[tokio::test]
+    async fn test_context_redirects() {
+        let plain = send("/login", &[]).await;
+        assert_eq!(plain.status(), StatusCode::SEE_OTHER);
+        assert_eq!(plain.headers()[LOCATION], "/invoices");
+
+        let htmx = send("/login", &[(HX_REQUEST, "true")]).await;
+        assert_eq!(htmx.status(), StatusCode::OK);
+        assert_eq!(htmx.headers()[HX_REDIRECT], "/invoices");
+        assert!(!htmx.headers().contains_key(LOCATION));
+
+        let location = send("/invoices/7/close", &[(HX_REQUEST, "true")]).await;
+        let location: serde_json::Value = serde_json::from_str(location.headers()[HX_LOCATION].to_str().unwrap()).unwrap();
+        assert_eq!(location, serde_json::json!({ "path": "/invoices/7", "target": "#invoice", "swap": "outerHTML" }));
+
+        let refresh = send("/invoices/7/reopen", &[(HX_REQUEST, "true")]).await;
+        assert_eq!(refresh.headers()[HX_REFRESH], "true");
+
+        // a submission goes back to the page it came from, or keeps its response
+        let plain = send("/invoices/7/reopen", &[(REFERER.as_str(), "/invoices/7?tab=history")]).await;
+        assert_eq!(plain.status(), StatusCode::SEE_OTHER);
+        assert_eq!(plain.headers()[LOCATION], "/invoices/7?tab=history");
+
+        let plain = send("/invoices/7/reopen", &[]).await;
+        assert_eq!(plain.status(), StatusCode::OK);
+        assert!(!plain.headers().contains_key(LOCATION));
+
+        let app = router(&App::new(Config::default(), TestTemplate).register_feature(Invoices).build());
+        let plain = app.oneshot(Request::get("/invoices/7/reopen?tab=history").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(plain.status(), StatusCode::SEE_OTHER);
+        assert_eq!(plain.headers()[LOCATION], "/invoices/7/reopen?tab=history");
+    }
+}
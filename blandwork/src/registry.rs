@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::{feature::Link, group::{RouteGroup, RouteKind}, Feature};
+
+/// A feature installed by `build` and the routers it exposes.
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledFeature {
+    pub name: String,
+    pub api: bool,
+    pub web: bool,
+    pub supplemental: bool,
+
+    /// The feature's entry route, see `Feature::link`.
+    pub link: Option<Link>,
+}
+
+/// Every feature of the app in registration order, recorded by `build` and an extension
+/// of every route, for admin and status pages listing what is installed.
+///
+/// ```ignore
+/// async fn status(Extension(registry): Extension<FeatureRegistry>) -> Markup {
+///     html! {
+///         ul {
+///             @for feature in registry.iter() {
+///                 li { (feature.name) @if let Some(link) = &feature.link { " " a href=(link.route) { (link.label) } } }
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FeatureRegistry(Arc<Vec<InstalledFeature>>);
+
+impl FeatureRegistry {
+    /// From the groups `build` mounts (one list per feature), their routers aren't built again.
+    pub(crate) fn from_groups(features: &[Box<dyn Feature>], groups: &[Vec<RouteGroup>]) -> Self {
+        let exposes = |groups: &[RouteGroup], kind: RouteKind| groups.iter().any(|group| group.kind() == kind);
+
+        Self(Arc::new(features.iter().zip(groups)
+            .map(|(feature, groups)| InstalledFeature {
+                name: feature.name(),
+                api: exposes(groups, RouteKind::Api),
+                web: exposes(groups, RouteKind::Web),
+                supplemental: exposes(groups, RouteKind::Supplemental),
+                link: feature.link(),
+            })
+            .collect()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InstalledFeature> {
+        self.0.iter().find(|feature| feature.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &InstalledFeature> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Refuses a second feature of the same name, their routers, spans and toggles would be merged.
+pub(crate) fn assert_unique(features: &[Box<dyn Feature>], feature: &dyn Feature) {
+    let name: String = feature.name();
+
+    if features.iter().any(|registered| registered.name() == name) {
+        panic!("App error: a feature named `{name}` is already registered, override `Feature::name` to tell them apart");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{feature::Link, test::router, App, Config, Context, Feature, Template};
+
+    use super::FeatureRegistry;
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Invoices;
+
+    impl Feature for Invoices {
+        fn link(&self) -> Option<Link> {
+            Some(Link {
+                active: false,
+                title: "Invoices".to_owned(),
+                label: "I".to_owned(),
+                route: "/invoices".to_owned(),
+                icon: None,
+                css: None,
+                remember: false,
+            })
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/status", get(|Extension(registry): Extension<FeatureRegistry>| async move {
+                html! {
+                    @for feature in registry.iter() {
+                        (feature.name) " " (feature.web) " " (feature.api) " " (feature.link.as_ref().map(|link| link.route.as_str()).unwrap_or("-")) ";"
+                    }
+                }
+            })))
+        }
+    }
+
+    struct Reports;
+
+    impl Feature for Reports {
+        fn api(&self) -> Option<Router> {
+            Some(Router::new().route("/api/reports", get(|| async { "[]" })))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry() {
+        let mut app = App::new(Config::default(), TestTemplate).register_feature(Invoices).register_feature(Reports);
+        let response = router(&app.build()).oneshot(Request::get("/status").body(Body::empty()).unwrap()).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        assert_eq!(body, "<html><body>Invoices true false /invoices;Reports false true -;</body></html>");
+    }
+
+    #[derive(Default)]
+    struct Counted {
+        builds: Arc<AtomicUsize>,
+    }
+
+    impl Feature for Counted {
+        fn web(&self) -> Option<Router> {
+            self.builds.fetch_add(1, Ordering::SeqCst);
+            Some(Router::new().route("/counted", get(|| async { "counted" })))
+        }
+    }
+
+    #[test]
+    fn test_routers_built_once() {
+        let counted = Counted::default();
+        let builds = counted.builds.clone();
+
+        // the registry records the router `build` mounts
+        let _ = App::new(Config::default(), TestTemplate).register_feature(counted).build();
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "a feature named `Reports` is already registered")]
+    fn test_duplicate_name() {
+        let _ = App::new(Config::default(), TestTemplate).register_feature(Reports).register_feature(Reports);
+    }
+}
@@ -0,0 +1,518 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error, fmt::Display,
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH}
+};
+
+use axum::{response::{IntoResponse, Response}, routing::{get, post}, Extension, Json, Router};
+use hyper::{header::HeaderName, HeaderMap, StatusCode};
+use serde::Serialize;
+use serde_json::json;
+use tracing_subscriber::{filter::Directive, EnvFilter};
+
+use crate::{config::{Cdn, Limits}, logging::LogLevels, toggle::{authorized, AdminToken, FeatureFlags}, Config};
+
+/// Reload events as JSON, `POST {CONFIG_ROUTE}/reload` reloads the config file.
+pub const CONFIG_ROUTE: &str = "/_blandwork/config";
+
+// reload events kept for `CONFIG_ROUTE`
+const MAX_EVENTS: usize = 20;
+
+// keys of the config file applied by a reload, the others need a restart
+const RELOADABLE: [&str; 5] = ["features", "server.logging.filter", "server.logging.targets", "server.limits", "server.cdn.sets"];
+
+/// A value the layers read on every request and a config reload replaces in place,
+/// readers keep the `Arc` they loaded until they're done with it.
+#[derive(Debug, Default)]
+pub struct Live<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Clone for Live<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Live<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(value))))
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn store(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReloadError {
+    /// No `App::config_file` to reload from.
+    NoFile,
+    Read(String),
+
+    /// The new values failed validation, the running config is kept.
+    Invalid(Vec<String>),
+}
+
+impl Display for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReloadError::NoFile => write!(f, "the app was not started from a config file"),
+            ReloadError::Read(e) => write!(f, "unable to read the config file: {e}"),
+            ReloadError::Invalid(errors) => write!(f, "invalid config: {}", errors.join("; ")),
+        }
+    }
+}
+
+impl Error for ReloadError {}
+
+impl IntoResponse for ReloadError {
+    fn into_response(self) -> Response {
+        match self {
+            ReloadError::NoFile => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            ReloadError::Read(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response(),
+            ReloadError::Invalid(errors) => (StatusCode::UNPROCESSABLE_ENTITY, Json(json!({ "errors": errors }))).into_response(),
+        }
+    }
+}
+
+/// One reload attempt, `changed` are the applied keys (`features.beta`, `server.limits.max_headers`),
+/// `ignored` the changed keys which need a restart.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReloadEvent {
+    // seconds since the epoch
+    pub at: u64,
+    pub applied: bool,
+    pub changed: Vec<String>,
+    pub ignored: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Reloads the safelisted settings of the config file in place: `[features]`, the levels of
+/// `server.logging`, `server.limits` and the header sets of `server.cdn`. Anything else
+/// (bind address, database, templates) needs a restart and is ignored with a warning.
+/// Triggered by SIGHUP once the app runs, or `POST /_blandwork/config/reload`.
+#[derive(Clone)]
+pub struct ConfigReloader {
+    path: Arc<Mutex<Option<PathBuf>>>,
+
+    // flattened keys of the file as applied, what the next reload is compared to
+    applied: Arc<Mutex<BTreeMap<String, String>>>,
+
+    flags: FeatureFlags,
+    log_levels: LogLevels,
+    limits: Live<Limits>,
+    cdn: Live<Cdn>,
+
+    events: Arc<Mutex<Vec<ReloadEvent>>>,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new(config: &Config, log_levels: LogLevels) -> Self {
+        Self {
+            path: Arc::new(Mutex::new(None)),
+            applied: Arc::new(Mutex::new(BTreeMap::new())),
+            flags: FeatureFlags::from_config(config),
+            log_levels,
+            limits: Live::new(config.server.limits.clone()),
+            cdn: Live::new(config.server.cdn.clone()),
+            events: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// The file the app's config was loaded from, its current content is the baseline
+    /// the next reload is compared to.
+    pub(crate) fn set_path(&self, path: PathBuf) {
+        match read(&path) {
+            Ok((_, keys)) => *self.applied.lock().unwrap() = keys,
+            Err(e) => tracing::warn!("{e}, the first reload reports every key as changed"),
+        }
+
+        *self.path.lock().unwrap() = Some(path);
+    }
+
+    pub(crate) fn flags(&self) -> FeatureFlags {
+        self.flags.clone()
+    }
+
+    pub(crate) fn limits(&self) -> Live<Limits> {
+        self.limits.clone()
+    }
+
+    pub(crate) fn cdn(&self) -> Live<Cdn> {
+        self.cdn.clone()
+    }
+
+    /// The latest reload attempts, oldest first.
+    pub fn events(&self) -> Vec<ReloadEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Read the config file again and apply its safelisted settings, nothing is applied
+    /// when a new value is invalid.
+    pub fn reload(&self) -> Result<ReloadEvent, ReloadError> {
+        let path: PathBuf = self.path.lock().unwrap().clone().ok_or(ReloadError::NoFile)?;
+
+        let (config, keys) = match read(&path) {
+            Ok(read) => read,
+            Err(ReloadError::Invalid(errors)) => return Err(self.reject(errors)),
+            Err(e) => return Err(e),
+        };
+
+        let errors: Vec<String> = self.validate(&config);
+        if !errors.is_empty() {
+            return Err(self.reject(errors));
+        }
+
+        let mut applied = self.applied.lock().unwrap();
+
+        let (changed, ignored): (Vec<String>, Vec<String>) = diff(&applied, &keys).into_iter()
+            .partition(|key| reloadable(key));
+
+        for key in ignored.iter() {
+            tracing::warn!("{key} changed, it is not reloadable and needs a restart");
+        }
+
+        self.flags.replace(config.features.clone());
+
+        if let Err(e) = self.log_levels.reconfigure(&config.server.logging) {
+            tracing::error!("{e}");
+        }
+
+        self.limits.store(config.server.limits.clone());
+
+        let mut cdn: Cdn = (*self.cdn.load()).clone();
+        cdn.sets = config.server.cdn.sets.clone();
+        self.cdn.store(cdn);
+
+        // ignored keys keep their old value, they are reported again until the restart
+        for key in changed.iter() {
+            match keys.get(key) {
+                Some(value) => applied.insert(key.clone(), value.clone()),
+                None => applied.remove(key),
+            };
+        }
+
+        tracing::info!(changed = ?changed, "config reloaded from {}", path.display());
+
+        Ok(self.record(ReloadEvent { at: now(), applied: true, changed, ignored, errors: Vec::new() }))
+    }
+
+    fn reject(&self, errors: Vec<String>) -> ReloadError {
+        tracing::error!("config reload rejected, the running config is kept: {}", errors.join("; "));
+
+        self.record(ReloadEvent { at: now(), applied: false, changed: Vec::new(), ignored: Vec::new(), errors: errors.clone() });
+        ReloadError::Invalid(errors)
+    }
+
+    fn record(&self, event: ReloadEvent) -> ReloadEvent {
+        let mut events = self.events.lock().unwrap();
+        events.push(event.clone());
+
+        if events.len() > MAX_EVENTS {
+            events.remove(0);
+        }
+
+        event
+    }
+
+    fn validate(&self, config: &Config) -> Vec<String> {
+        let mut errors: Vec<String> = Vec::new();
+        let logging = &config.server.logging;
+
+        if let Err(e) = EnvFilter::try_new(&logging.filter) {
+            errors.push(format!("server.logging.filter: {e}"));
+        }
+
+        for (target, level) in logging.targets.iter() {
+            if let Err(e) = format!("{target}={level}").parse::<Directive>() {
+                errors.push(format!("server.logging.targets.{target}: {e}"));
+            }
+        }
+
+        let limits: &Limits = &config.server.limits;
+
+        for (key, value) in [("max_headers", limits.max_headers as u64), ("max_header_bytes", limits.max_header_bytes), ("max_uri_length", limits.max_uri_length as u64)] {
+            if value == 0 {
+                errors.push(format!("server.limits.{key}: must be greater than 0"));
+            }
+        }
+
+        for (set, headers) in config.server.cdn.sets.iter() {
+            for name in headers.keys() {
+                if HeaderName::try_from(name.as_str()).is_err() {
+                    errors.push(format!("server.cdn.sets.{set}: invalid header name {name:?}"));
+                }
+            }
+        }
+
+        // the rules are not reloaded, they have to find their sets
+        for rule in self.cdn.load().rules.iter() {
+            if !config.server.cdn.sets.contains_key(&rule.set) {
+                errors.push(format!("server.cdn.sets: the set {} of the rule for {} is missing", rule.set, rule.route));
+            }
+        }
+
+        errors
+    }
+
+    /// `GET /_blandwork/config` lists the reload events, `POST /_blandwork/config/reload` reloads.
+    /// Mounted with `server.reload.token`, both then need it as a bearer token. In development
+    /// it is also mounted without a token, unauthenticated.
+    pub(crate) fn router(&self, token: Option<&str>) -> Router {
+        let router: Router = Router::new()
+            .route(CONFIG_ROUTE, get(events))
+            .route(&format!("{CONFIG_ROUTE}/reload"), post(reload))
+            .layer(Extension(self.clone()));
+
+        match token {
+            Some(token) => router.layer(Extension(AdminToken(Arc::from(token)))),
+            None => router,
+        }
+    }
+
+    /// Reload on SIGHUP, when the app was started from a config file.
+    pub(crate) fn listen_for_hangup(&self) {
+        if self.path.lock().unwrap().is_none() {
+            return;
+        }
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let reloader: ConfigReloader = self.clone();
+
+            tokio::spawn(async move {
+                let mut hangup = match signal(SignalKind::hangup()) {
+                    Ok(hangup) => hangup,
+                    Err(e) => {
+                        tracing::error!("unable to listen for SIGHUP, config reload is endpoint only: {e}");
+                        return;
+                    }
+                };
+
+                while hangup.recv().await.is_some() {
+                    if let Err(e) = reloader.reload() {
+                        tracing::error!("{e}");
+                    }
+                }
+            });
+        }
+    }
+}
+
+// a token mounted with the router has to be presented
+fn allowed(headers: &HeaderMap, token: Option<Extension<AdminToken>>) -> bool {
+    token.map_or(true, |Extension(token)| authorized(headers, &token))
+}
+
+async fn events(
+    headers: HeaderMap,
+    token: Option<Extension<AdminToken>>,
+    Extension(reloader): Extension<ConfigReloader>,
+) -> Response {
+    if !allowed(&headers, token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    Json(json!({ "events": reloader.events() })).into_response()
+}
+
+async fn reload(
+    headers: HeaderMap,
+    token: Option<Extension<AdminToken>>,
+    Extension(reloader): Extension<ConfigReloader>,
+) -> Response {
+    if !allowed(&headers, token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match reloader.reload() {
+        Ok(event) => Json(event).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// The file as a Config and as flattened `section.key` values.
+fn read(path: &PathBuf) -> Result<(Config, BTreeMap<String, String>), ReloadError> {
+    let source: String = std::fs::read_to_string(path)
+        .map_err(|e| ReloadError::Read(format!("{}: {e}", path.display())))?;
+
+    let value: toml::Value = toml::from_str(&source).map_err(|e| ReloadError::Invalid(vec![e.to_string()]))?;
+    let config: Config = toml::from_str(&source).map_err(|e| ReloadError::Invalid(vec![e.to_string()]))?;
+
+    let mut keys: BTreeMap<String, String> = BTreeMap::new();
+    flatten("", &value, &mut keys);
+
+    Ok((config, keys))
+}
+
+fn flatten(prefix: &str, value: &toml::Value, keys: &mut BTreeMap<String, String>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter() {
+                let key: String = match prefix.is_empty() {
+                    true => key.clone(),
+                    false => format!("{prefix}.{key}"),
+                };
+
+                flatten(&key, value, keys);
+            }
+        },
+        value => { keys.insert(prefix.to_owned(), value.to_string()); },
+    }
+}
+
+/// Keys added, removed or changed.
+fn diff(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<String> {
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect()
+}
+
+fn reloadable(key: &str) -> bool {
+    // flags only, the sections of `Feature::config_schema` are parsed once by `build`
+    if let Some(feature) = key.strip_prefix("features.") {
+        return !feature.contains('.');
+    }
+
+    RELOADABLE.iter().any(|section| key == *section || key.starts_with(&format!("{section}.")))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Extension, Router};
+    use hyper::{header, StatusCode};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::{test::router, App, Config, Context, ContextAccessor, Feature, Template};
+
+    use super::{ReloadError, CONFIG_ROUTE};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Beta;
+
+    impl Feature for Beta {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/beta", get(|Extension(accessor): Extension<ContextAccessor>| async move {
+                html! { (accessor.context().await.feature_enabled("beta")) }
+            })))
+        }
+    }
+
+    fn file(server: &str, features: &str) -> String {
+        format!("
+            [database]
+            host = 'localhost'
+            port = 5432
+            database = 'app'
+            username = 'app'
+            password = 'secret'
+
+            [server]
+            host = '0.0.0.0'
+            {server}
+
+            [server.reload]
+            token = 'reload-token'
+
+            [features]
+            {features}
+        ")
+    }
+
+    fn write(path: &PathBuf, source: &str) {
+        std::fs::write(path, source).unwrap();
+    }
+
+    async fn send(app: &Router, request: Request) -> (StatusCode, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn beta() -> Request {
+        Request::get("/beta").body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let path = std::env::temp_dir().join(format!("blandwork-{}.toml", Uuid::new_v4()));
+        write(&path, &file("port = 3001", "beta = false"));
+
+        let mut app = App::new(Config::from_path(path.to_str().unwrap()).unwrap(), TestTemplate)
+            .config_file(&path)
+            .register_feature(Beta);
+        let app = app.build();
+        let routes = router(&app);
+
+        assert!(send(&routes, beta()).await.1.ends_with("false</body></html>"));
+
+        // the flag flips for the next request, the port waits for a restart
+        write(&path, &file("port = 4000", "beta = true"));
+        let event = app.config_reloader().reload().unwrap();
+
+        assert_eq!(event.changed, vec!["features.beta".to_owned()]);
+        assert_eq!(event.ignored, vec!["server.port".to_owned()]);
+        assert!(send(&routes, beta()).await.1.ends_with("true</body></html>"));
+
+        // invalid values are rejected as a whole
+        write(&path, &file("port = 4000\n[server.limits]\nmax_headers = 0", "beta = false"));
+
+        // the endpoint wants the token
+        let (status, _) = send(&routes, Request::post(format!("{CONFIG_ROUTE}/reload")).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(&routes, Request::get(CONFIG_ROUTE).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let request: Request = Request::post(format!("{CONFIG_ROUTE}/reload"))
+            .header(header::AUTHORIZATION, "Bearer reload-token")
+            .body(Body::empty())
+            .unwrap();
+
+        let (status, body) = send(&routes, request).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body, r#"{"errors":["server.limits.max_headers: must be greater than 0"]}"#);
+        assert!(send(&routes, beta()).await.1.ends_with("true</body></html>"));
+
+        let events = app.config_reloader().events();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].applied && !events[1].applied);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_without_file() {
+        let app = App::new(Config::default(), TestTemplate).register_feature(Beta).build();
+
+        assert_eq!(app.config_reloader().reload(), Err(ReloadError::NoFile));
+    }
+}
@@ -0,0 +1,478 @@
+use std::{
+    error::Error, fmt::Display,
+    future::Future, pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use axum::http::Extensions;
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    clock::{civil_from_days, days_from_civil, SharedClock},
+    events::{EventBus, HandlerError},
+    jobs::JobStore
+};
+
+type JobFuture = Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send>>;
+type JobCallback = Arc<dyn Fn(JobContext) -> JobFuture + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronError(pub String);
+
+impl Display for CronError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid cron expression: {}", self.0)
+    }
+}
+
+impl Error for CronError {}
+
+/// A five field cron expression: `minute hour day-of-month month day-of-week`.
+///
+/// Fields accept `*`, numbers, ranges (`1-5`), lists (`1,15`) and steps (`*/15`, `0-30/10`),
+/// day-of-week counts from Sunday (0 or 7). `@hourly`, `@daily`, `@weekly`, `@monthly`
+/// and `@yearly` are shorthands. Names (`MON`, `JAN`) are not supported.
+/// As in cron, when both day fields are restricted a day matching either one runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+
+    // `*` day fields don't take part in the either-day rule
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn field(value: &str, min: u32, max: u32) -> Result<u64, CronError> {
+    let mut bits: u64 = 0;
+
+    for part in value.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| CronError(part.to_owned()))?),
+            None => (part, 1),
+        };
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse::<u32>().map_err(|_| CronError(part.to_owned()))?,
+                    end.parse::<u32>().map_err(|_| CronError(part.to_owned()))?
+                ),
+                // `5/15` runs from 5 to the end of the field
+                None => {
+                    let start: u32 = range.parse::<u32>().map_err(|_| CronError(part.to_owned()))?;
+                    (start, if part.contains('/') { max } else { start })
+                }
+            }
+        };
+
+        if step == 0 || start < min || end > max || start > end {
+            return Err(CronError(part.to_owned()));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let expression: &str = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(CronError(format!("{expression} (expected 5 fields)")));
+        };
+
+        let mut weekday_bits: u64 = field(weekdays, 0, 7)?;
+
+        // 7 is another Sunday
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits |= 1;
+        }
+
+        Ok(Cron {
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)?,
+            days: field(days, 1, 31)?,
+            months: field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            any_day: days == "*",
+            any_weekday: weekdays == "*",
+        })
+    }
+}
+
+impl Cron {
+    fn day_matches(&self, day: u32, weekday: u32) -> bool {
+        let day: bool = self.days & (1 << day) != 0;
+        let weekday: bool = self.weekdays & (1 << weekday) != 0;
+
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first matching minute strictly after `after`, evaluated at `offset` from UTC.
+    /// `None` when nothing matches within a few years (`0 0 30 2 *`).
+    pub fn next_after(&self, after: SystemTime, offset: UtcOffset) -> Option<SystemTime> {
+        let after: i64 = after.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64 + offset.0 as i64;
+
+        // local seconds, starting at the next whole minute
+        let mut t: i64 = (after.div_euclid(60) + 1) * 60;
+
+        for _ in 0..100_000 {
+            let days: i64 = t.div_euclid(86400);
+            let rest: i64 = t.rem_euclid(86400);
+            let (year, month, day) = civil_from_days(days);
+
+            if self.months & (1 << month) == 0 {
+                let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+                t = days_from_civil(year, month, 1) * 86400;
+                continue;
+            }
+
+            // 1970-01-01 was a Thursday
+            let weekday: u32 = (days + 4).rem_euclid(7) as u32;
+
+            if !self.day_matches(day, weekday) {
+                t = (days + 1) * 86400;
+                continue;
+            }
+
+            let hour: i64 = rest / 3600;
+
+            if self.hours & (1 << hour) == 0 {
+                t = days * 86400 + (hour + 1) * 3600;
+                continue;
+            }
+
+            let minute: i64 = (rest % 3600) / 60;
+
+            if self.minutes & (1 << minute) == 0 {
+                t += 60;
+                continue;
+            }
+
+            return Some(UNIX_EPOCH + Duration::from_secs((t - offset.0 as i64).max(0) as u64));
+        }
+
+        None
+    }
+}
+
+/// Fixed offset the cron expressions are evaluated in, see `config::Schedule`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UtcOffset(pub i32);
+
+impl FromStr for UtcOffset {
+    type Err = CronError;
+
+    /// `UTC`, `Z` or `+HH:MM` / `-HH:MM`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value: &str = value.trim();
+
+        if value.eq_ignore_ascii_case("utc") || value == "Z" {
+            return Ok(UtcOffset(0));
+        }
+
+        let invalid = || CronError(format!("timezone {value} (expected UTC or +HH:MM)"));
+
+        let (sign, rest) = match (value.strip_prefix('+'), value.strip_prefix('-')) {
+            (Some(rest), _) => (1, rest),
+            (_, Some(rest)) => (-1, rest),
+            _ => return Err(invalid()),
+        };
+
+        let (hours, minutes) = rest.split_once(':').ok_or_else(invalid)?;
+        let hours: i32 = hours.parse().map_err(|_| invalid())?;
+        let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+
+        if hours > 14 || minutes > 59 {
+            return Err(invalid());
+        }
+
+        Ok(UtcOffset(sign * (hours * 3600 + minutes * 60)))
+    }
+}
+
+/// What a job gets to work with. `extensions` holds the pool of a connected app.
+pub struct JobContext {
+    pub name: String,
+    pub scheduled_at: SystemTime,
+    pub events: EventBus,
+    pub extensions: Extensions,
+}
+
+#[cfg(feature = "postgres")]
+impl JobContext {
+    pub fn pool(&self) -> Option<&crate::db::ConnectionPool> {
+        self.extensions.get::<crate::db::ConnectionPool>()
+    }
+}
+
+/// A job contributed by `Feature::schedule`, run by `App::run` whenever `cron` matches.
+/// A run never overlaps the previous one of the same job, a missed time is skipped.
+#[derive(Clone)]
+pub struct ScheduledJob {
+    pub name: String,
+    pub cron: Cron,
+    callback: JobCallback,
+}
+
+impl ScheduledJob {
+    /// Panics on an invalid expression, see `ScheduledJob::try_new`.
+    pub fn new<F, Fut>(name: &str, cron: &str, callback: F) -> Self
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        match Self::try_new(name, cron, callback) {
+            Ok(job) => job,
+            Err(e) => panic!("scheduled job {name}: {e}"),
+        }
+    }
+
+    pub fn try_new<F, Fut>(name: &str, cron: &str, callback: F) -> Result<Self, CronError>
+    where
+        F: Fn(JobContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), HandlerError>> + Send + 'static,
+    {
+        Ok(Self {
+            name: name.to_owned(),
+            cron: cron.parse()?,
+            callback: Arc::new(move |context: JobContext| -> JobFuture { Box::pin(callback(context)) }),
+        })
+    }
+}
+
+/// Runs the scheduled jobs until `Schedule::shutdown`.
+pub struct Scheduler {
+    jobs: Vec<ScheduledJob>,
+    clock: SharedClock,
+    offset: UtcOffset,
+    events: EventBus,
+    extensions: Extensions,
+
+    // decides which instance runs a tick, every instance runs every tick without
+    leadership: Option<Arc<dyn JobStore>>,
+}
+
+impl Scheduler {
+    pub fn new(jobs: Vec<ScheduledJob>, clock: SharedClock, offset: UtcOffset, events: EventBus) -> Self {
+        Self { jobs, clock, offset, events, extensions: Extensions::new(), leadership: None }
+    }
+
+    /// Instances sharing `store` run each tick of a job once, see `JobStore::lead`.
+    pub fn leadership(mut self, store: Arc<dyn JobStore>) -> Self {
+        self.leadership = Some(store);
+        self
+    }
+
+    /// Handed to every job, e.g. the connection pool.
+    pub fn extension<T: Clone + Send + Sync + 'static>(mut self, value: T) -> Self {
+        self.extensions.insert(value);
+        self
+    }
+
+    pub fn start(self) -> Schedule {
+        let (shutdown, receiver) = watch::channel(false);
+
+        let tasks: Vec<JoinHandle<()>> = self.jobs.into_iter().map(|job| {
+            let clock: SharedClock = self.clock.clone();
+            let events: EventBus = self.events.clone();
+            let extensions: Extensions = self.extensions.clone();
+            let offset: UtcOffset = self.offset;
+            let leadership: Option<Arc<dyn JobStore>> = self.leadership.clone();
+            let mut shutdown = receiver.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    let Some(next) = job.cron.next_after(clock.now(), offset) else {
+                        tracing::warn!(job = %job.name, "cron expression never matches, job not scheduled");
+                        return;
+                    };
+
+                    tokio::select! {
+                        _ = clock.sleep_until(next) => {},
+                        _ = shutdown.changed() => return,
+                    }
+
+                    if *shutdown.borrow() {
+                        return;
+                    }
+
+                    if let Some(store) = leadership.as_ref() {
+                        match store.lead(&job.name, next).await {
+                            Ok(true) => {},
+                            Ok(false) => {
+                                tracing::debug!(job = %job.name, "scheduled job run by another instance");
+                                continue;
+                            },
+                            Err(e) => {
+                                tracing::error!(job = %job.name, "unable to elect the instance running the job, skipped: {e}");
+                                continue;
+                            }
+                        }
+                    }
+
+                    let context: JobContext = JobContext {
+                        name: job.name.clone(),
+                        scheduled_at: next,
+                        events: events.clone(),
+                        extensions: extensions.clone(),
+                    };
+
+                    // an in-flight run is finished before shutdown completes
+                    match (job.callback)(context).await {
+                        Ok(()) => tracing::info!(job = %job.name, "scheduled job finished"),
+                        Err(e) => tracing::error!(job = %job.name, "scheduled job failed: {e}"),
+                    }
+                }
+            })
+        }).collect();
+
+        Schedule { shutdown, tasks }
+    }
+}
+
+/// The running jobs of a `Scheduler`.
+pub struct Schedule {
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Schedule {
+    /// Stop scheduling and wait for the runs in progress.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+
+        for task in self.tasks {
+            let _ = task.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{Arc, Mutex}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+    use crate::{clock::{days_from_civil, TestClock}, events::EventBus};
+
+    use super::{Cron, ScheduledJob, Scheduler, UtcOffset};
+
+    fn at(year: i64, month: u32, day: u32, hour: u64, minute: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(days_from_civil(year, month, day) as u64 * 86400 + hour * 3600 + minute * 60)
+    }
+
+    fn next(cron: &str, after: SystemTime) -> Option<SystemTime> {
+        cron.parse::<Cron>().unwrap().next_after(after, UtcOffset(0))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!("* * * * *".parse::<Cron>().is_ok());
+        assert!("*/15 0-6,22 1 */3 1-5".parse::<Cron>().is_ok());
+        assert!("@daily".parse::<Cron>().is_ok());
+
+        assert!("* * * *".parse::<Cron>().is_err());
+        assert!("60 * * * *".parse::<Cron>().is_err());
+        assert!("*/0 * * * *".parse::<Cron>().is_err());
+        assert!("5-1 * * * *".parse::<Cron>().is_err());
+        assert!("0 0 * JAN *".parse::<Cron>().is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        // nightly at 2am
+        assert_eq!(next("0 2 * * *", at(2024, 3, 1, 1, 30)), Some(at(2024, 3, 1, 2, 0)));
+        assert_eq!(next("0 2 * * *", at(2024, 3, 1, 2, 0)), Some(at(2024, 3, 2, 2, 0)));
+
+        // every 15 minutes
+        assert_eq!(next("*/15 * * * *", at(2024, 3, 1, 10, 7)), Some(at(2024, 3, 1, 10, 15)));
+
+        // month and year rollover
+        assert_eq!(next("@yearly", at(2024, 6, 1, 0, 0)), Some(at(2025, 1, 1, 0, 0)));
+
+        // 2024-03-01 is a Friday, next Monday 9am
+        assert_eq!(next("0 9 * * 1", at(2024, 3, 1, 12, 0)), Some(at(2024, 3, 4, 9, 0)));
+
+        // either day field: the 15th or a Sunday (7)
+        assert_eq!(next("0 0 15 * 7", at(2024, 3, 1, 12, 0)), Some(at(2024, 3, 3, 0, 0)));
+
+        // leap day
+        assert_eq!(next("0 0 29 2 *", at(2024, 3, 1, 0, 0)), Some(at(2028, 2, 29, 0, 0)));
+        assert_eq!(next("0 0 30 2 *", at(2024, 3, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn test_offset() {
+        let offset: UtcOffset = "+02:00".parse().unwrap();
+        assert_eq!(offset, UtcOffset(7200));
+        assert_eq!("utc".parse::<UtcOffset>().unwrap(), UtcOffset(0));
+        assert!("Europe/Berlin".parse::<UtcOffset>().is_err());
+
+        // 2am at +02:00 is midnight UTC
+        let cron: Cron = "0 2 * * *".parse().unwrap();
+        assert_eq!(cron.next_after(at(2024, 3, 1, 12, 0), offset), Some(at(2024, 3, 2, 0, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_runs_and_stops() {
+        let clock = TestClock::at(at(2024, 3, 1, 1, 59));
+        let runs: Arc<Mutex<Vec<SystemTime>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = runs.clone();
+        let job = ScheduledJob::new("nightly", "0 2 * * *", move |context| {
+            let recorded = recorded.clone();
+            async move {
+                recorded.lock().unwrap().push(context.scheduled_at);
+                Ok(())
+            }
+        });
+
+        let schedule = Scheduler::new(vec![job], Arc::new(clock.clone()), UtcOffset(0), EventBus::new()).start();
+
+        for _ in 0..100 {
+            if clock.pending() == 1 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        clock.advance(Duration::from_secs(60));
+
+        for _ in 0..100 {
+            if !runs.lock().unwrap().is_empty() && clock.pending() == 1 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(*runs.lock().unwrap(), vec![at(2024, 3, 1, 2, 0)]);
+
+        // waiting for tomorrow, shutdown does not wait for it
+        schedule.shutdown().await;
+        assert_eq!(runs.lock().unwrap().len(), 1);
+    }
+}
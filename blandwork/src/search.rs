@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::{extract::Query, response::{IntoResponse, Response}, routing::get, Extension, Router};
+use hyper::StatusCode;
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{feature::Link, list::Page, ContextAccessor, Feature, HandlerError};
+
+/// The search page, a box with the results of `?q=` below it.
+pub const SEARCH_ROUTE: &str = "/search";
+
+/// Fragment of the results, requested by the box as the user types.
+pub const SEARCH_RESULTS_ROUTE: &str = "/search/results";
+
+/// Element id the results are swapped into.
+pub const SEARCH_RESULTS_ID: &str = "search-results";
+
+// results per page unless `SearchFeature::page_size`
+const PAGE_SIZE: usize = 20;
+
+/// One search result.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Hit {
+    pub title: String,
+    pub url: String,
+    pub snippet: Option<String>,
+}
+
+impl Hit {
+    pub fn new(title: impl Into<String>, url: impl Into<String>) -> Self {
+        Self { title: title.into(), url: url.into(), snippet: None }
+    }
+
+    pub fn snippet(mut self, snippet: impl Into<String>) -> Self {
+        self.snippet = Some(snippet.into());
+        self
+    }
+}
+
+/// The app's search over its own data, see `SearchFeature`. A full page of hits
+/// (`page.size` of them) offers the next page.
+#[async_trait]
+pub trait Searcher: Send + Sync + 'static {
+    async fn search(&self, query: &str, page: Page) -> Result<Vec<Hit>, HandlerError>;
+}
+
+/// A search page at `SEARCH_ROUTE` whose box fetches `SEARCH_RESULTS_ROUTE` fragments
+/// while typing (debounced), paginated with a "more results" button.
+///
+/// ```ignore
+/// App::new(config, template).register_feature(SearchFeature::new(ProductSearch::new(db)))
+/// ```
+pub struct SearchFeature<S: Searcher> {
+    searcher: Arc<S>,
+    page_size: usize,
+}
+
+impl<S: Searcher> SearchFeature<S> {
+    pub fn new(searcher: S) -> Self {
+        Self { searcher: Arc::new(searcher), page_size: PAGE_SIZE }
+    }
+
+    pub fn page_size(mut self, size: usize) -> Self {
+        self.page_size = size.max(1);
+        self
+    }
+
+    fn state(&self) -> SearchState {
+        SearchState { searcher: self.searcher.clone(), page_size: self.page_size }
+    }
+}
+
+#[derive(Clone)]
+struct SearchState {
+    searcher: Arc<dyn Searcher>,
+    page_size: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SearchQuery {
+    q: String,
+    page: Option<usize>,
+}
+
+impl<S: Searcher> Feature for SearchFeature<S> {
+    fn name(&self) -> String {
+        "Search".to_owned()
+    }
+
+    fn link(&self) -> Option<Link> {
+        Some(Link {
+            active: false,
+            title: "Search".to_owned(),
+            label: "Search".to_owned(),
+            route: SEARCH_ROUTE.to_owned(),
+            icon: None,
+            css: None,
+            remember: false,
+        })
+    }
+
+    fn web(&self) -> Option<Router> {
+        Some(Router::new()
+            .route(SEARCH_ROUTE, get(page))
+            .layer(Extension(self.state())))
+    }
+
+    fn supplemental(&self) -> Option<Router> {
+        Some(Router::new()
+            .route(SEARCH_RESULTS_ROUTE, get(results))
+            .layer(Extension(self.state())))
+    }
+}
+
+/// The box, with the results of a linked (or submitted without JavaScript) query.
+async fn page(
+    Extension(state): Extension<SearchState>,
+    Extension(accessor): Extension<ContextAccessor>,
+    Query(query): Query<SearchQuery>
+) -> Response {
+    let results: Markup = match render(&state, &accessor, &query).await {
+        Ok(results) => results,
+        Err(status) => return status.into_response(),
+    };
+
+    html!{
+        form role="search" action=(SEARCH_ROUTE) method="get" {
+            input type="search" name="q" value=(query.q) placeholder="Search" aria-label="Search" autocomplete="off"
+                hx-get=(SEARCH_RESULTS_ROUTE)
+                hx-trigger="input changed delay:300ms, search"
+                hx-target={"#" (SEARCH_RESULTS_ID)}
+                hx-swap="innerHTML"
+                hx-sync="this:replace";
+        }
+        div id=(SEARCH_RESULTS_ID) aria-live="polite" {
+            (results)
+        }
+    }.into_response()
+}
+
+async fn results(
+    Extension(state): Extension<SearchState>,
+    Extension(accessor): Extension<ContextAccessor>,
+    Query(query): Query<SearchQuery>
+) -> Response {
+    match render(&state, &accessor, &query).await {
+        Ok(results) => results.into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Nothing for an empty query, the empty state when the first page has no hits.
+async fn render(state: &SearchState, accessor: &ContextAccessor, query: &SearchQuery) -> Result<Markup, StatusCode> {
+    let q: &str = query.q.trim();
+
+    if q.is_empty() {
+        return Ok(html!{});
+    }
+
+    let page: Page = Page::new(query.page.unwrap_or(1), state.page_size);
+
+    let hits: Vec<Hit> = state.searcher.search(q, page).await.map_err(|e| {
+        tracing::error!("search for {q:?} failed: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    if hits.is_empty() {
+        return Ok(match page.number {
+            1 => accessor.context().await.empty_state(&format!("No results for \u{201c}{q}\u{201d}"), None),
+            _ => html!{},
+        });
+    }
+
+    let more: bool = hits.len() >= page.size;
+
+    Ok(html!{
+        ul .search-hits {
+            @for hit in hits.iter() {
+                li {
+                    a href=(hit.url) { (hit.title) }
+                    @if let Some(snippet) = hit.snippet.as_deref() {
+                        p { (snippet) }
+                    }
+                }
+            }
+        }
+        @if more {
+            // replaced by the next page, which brings its own button
+            button type="button"
+                hx-get=(SEARCH_RESULTS_ROUTE)
+                hx-vals=(json!({ "q": q, "page": page.number + 1 }).to_string())
+                hx-target="this"
+                hx-swap="outerHTML"
+                hx-sync="this:drop" {
+                    "More results"
+                }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use async_trait::async_trait;
+    use axum::{body::{to_bytes, Body}, extract::Request, Router};
+    use axum_htmx::HX_REQUEST;
+    use hyper::StatusCode;
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{list::Page, test::router, App, Config, Context, HandlerError, Template};
+
+    use super::{Hit, SearchFeature, Searcher, SEARCH_RESULTS_ROUTE};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Fruit;
+
+    #[async_trait]
+    impl Searcher for Fruit {
+        async fn search(&self, query: &str, page: Page) -> Result<Vec<Hit>, HandlerError> {
+            let fruit = ["apple", "apricot", "grape", "pineapple", "banana"];
+
+            Ok(fruit.iter()
+                .filter(|name| name.contains(query))
+                .skip(page.offset())
+                .take(page.size)
+                .map(|name| Hit::new(*name, format!("/fruit/{name}")))
+                .collect())
+        }
+    }
+
+    fn app() -> Router {
+        let mut app = App::new(Config::default(), TestTemplate).register_feature(SearchFeature::new(Fruit).page_size(2));
+        router(&app.build())
+    }
+
+    async fn get(uri: &str, htmx: bool) -> (StatusCode, String) {
+        let mut request = Request::get(uri);
+        if htmx {
+            request = request.header(HX_REQUEST, "true");
+        }
+
+        let response = app().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_results_fragment() {
+        // empty query, nothing to show
+        assert_eq!(get(&format!("{SEARCH_RESULTS_ROUTE}?q=%20"), true).await, (StatusCode::OK, String::new()));
+
+        let (_, html) = get(&format!("{SEARCH_RESULTS_ROUTE}?q=kiwi"), true).await;
+        assert!(html.contains("No results for \u{201c}kiwi\u{201d}"));
+
+        // a full first page offers the next one
+        let (_, html) = get(&format!("{SEARCH_RESULTS_ROUTE}?q=ap"), true).await;
+        assert!(html.contains(r#"<a href="/fruit/apple">apple</a>"#));
+        assert!(html.contains(r#"<a href="/fruit/apricot">apricot</a>"#));
+        assert!(html.contains(r#"hx-vals="{&quot;page&quot;:2,&quot;q&quot;:&quot;ap&quot;}""#));
+
+        let (_, html) = get(&format!("{SEARCH_RESULTS_ROUTE}?q=ap&page=2"), true).await;
+        assert!(html.contains("grape"));
+        assert!(html.contains("pineapple"));
+        assert!(html.contains("More results"));
+
+        // past the last hit
+        let (_, html) = get(&format!("{SEARCH_RESULTS_ROUTE}?q=ap&page=3"), true).await;
+        assert_eq!(html, "");
+
+        // supplemental, not for direct navigation
+        assert_eq!(get(&format!("{SEARCH_RESULTS_ROUTE}?q=ap"), false).await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_search_page() {
+        let (status, html) = get("/search", false).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains(r#"<form role="search" action="/search" method="get">"#));
+        assert!(html.contains(r#"hx-trigger="input changed delay:300ms, search""#));
+        assert!(html.contains(r#"<div id="search-results" aria-live="polite"></div>"#));
+
+        // a linked query renders its results right away
+        let (_, html) = get("/search?q=banana", false).await;
+        assert!(html.contains(r#"value="banana""#));
+        assert!(html.contains(r#"<a href="/fruit/banana">banana</a>"#));
+    }
+}
@@ -0,0 +1,164 @@
+use maud::{html, Markup};
+
+use crate::Context;
+
+/// Class of the placeholder shapes, styled (and animated) by the app under the theme's
+/// `<html>` class, e.g. `.dark .blandwork-skeleton`.
+pub const SKELETON_CLASS: &str = "blandwork-skeleton";
+
+/// Class of the element a lazy region's fragment is swapped into.
+pub const LAZY_REGION_CLASS: &str = "blandwork-region";
+
+/// Outline of the content a lazy region is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkeletonShape {
+    /// Lines of text.
+    Lines(usize),
+
+    /// A grid of cards.
+    Cards(usize),
+
+    /// Rows of a table.
+    Rows { rows: usize, columns: usize },
+}
+
+impl SkeletonShape {
+    fn name(&self) -> &'static str {
+        match self {
+            SkeletonShape::Lines(_) => "lines",
+            SkeletonShape::Cards(_) => "cards",
+            SkeletonShape::Rows { .. } => "rows",
+        }
+    }
+}
+
+/// Placeholder of `shape`, hidden from assistive technology: the region it is in is `aria-busy`.
+pub fn skeleton(shape: SkeletonShape) -> Markup {
+    html! {
+        div class={ (SKELETON_CLASS) " " (SKELETON_CLASS) "-" (shape.name()) } aria-hidden="true" data-nosnippet {
+            @match shape {
+                SkeletonShape::Lines(lines) => {
+                    @for _ in 0..lines {
+                        span .skeleton-line {}
+                    }
+                },
+                SkeletonShape::Cards(cards) => {
+                    @for _ in 0..cards {
+                        div .skeleton-card {}
+                    }
+                },
+                SkeletonShape::Rows { rows, columns } => {
+                    @for _ in 0..rows {
+                        div .skeleton-row {
+                            @for _ in 0..columns {
+                                span .skeleton-cell {}
+                            }
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// A region of the page loaded after it, showing a skeleton until its fragment arrives.
+///
+/// Crawlers run no JavaScript and never see the skeleton: they get the `inline` content
+/// when there is one, otherwise an empty region. Browsers without JavaScript get it
+/// through `<noscript>`.
+///
+/// ```ignore
+/// html! {
+///     (LazyRegion::new("/invoices/recent", SkeletonShape::Rows { rows: 5, columns: 4 })
+///         .id("recent-invoices")
+///         .render(&context))
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LazyRegion {
+    url: String,
+    shape: SkeletonShape,
+    id: Option<String>,
+    inline: Option<Markup>,
+}
+
+impl LazyRegion {
+    pub fn new(url: &str, shape: SkeletonShape) -> Self {
+        Self { url: url.to_owned(), shape, id: None, inline: None }
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_owned());
+        self
+    }
+
+    /// The content rendered in place for crawlers and browsers without JavaScript,
+    /// e.g. the fragment itself when it is cheap enough for a full page.
+    pub fn inline(mut self, content: Markup) -> Self {
+        self.inline = Some(content);
+        self
+    }
+
+    pub fn render(&self, context: &Context) -> Markup {
+        if context.is_crawler() {
+            return html! {
+                div id=[self.id.as_deref()] class=(LAZY_REGION_CLASS) {
+                    @if let Some(inline) = self.inline.as_ref() {
+                        (inline)
+                    }
+                }
+            };
+        }
+
+        html! {
+            div id=[self.id.as_deref()] class=(LAZY_REGION_CLASS) aria-busy="true"
+                hx-get=(self.url) hx-trigger="load" hx-swap="innerHTML" {
+                    (skeleton(self.shape))
+                    @if let Some(inline) = self.inline.as_ref() {
+                        noscript { (inline) }
+                    }
+            }
+        }
+    }
+}
+
+/// `LazyRegion` of `url` with a skeleton of `shape`.
+pub fn lazy_region(context: &Context, url: &str, shape: SkeletonShape) -> Markup {
+    LazyRegion::new(url, shape).render(context)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request};
+    use hyper::header::USER_AGENT;
+    use maud::html;
+
+    use crate::ContextAccessor;
+
+    use super::{lazy_region, LazyRegion, SkeletonShape};
+
+    #[tokio::test]
+    async fn test_skeleton() {
+        let accessor = ContextAccessor::from_request(&Request::get("/").body(Body::empty()).unwrap());
+        let html = lazy_region(&accessor.context().await, "/recent", SkeletonShape::Rows { rows: 2, columns: 3 }).into_string();
+
+        assert!(html.starts_with("<div class=\"blandwork-region\" aria-busy=\"true\" hx-get=\"/recent\" hx-trigger=\"load\""));
+        assert!(html.contains("<div class=\"blandwork-skeleton blandwork-skeleton-rows\" aria-hidden=\"true\" data-nosnippet>"));
+        assert_eq!(html.matches("skeleton-row\"").count(), 2);
+        assert_eq!(html.matches("skeleton-cell").count(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_crawler_inline() {
+        let request = Request::get("/").header(USER_AGENT, "Mozilla/5.0 (compatible; Googlebot/2.1)").body(Body::empty()).unwrap();
+        let accessor = ContextAccessor::from_request(&request);
+
+        let html = LazyRegion::new("/recent", SkeletonShape::Lines(3))
+            .id("recent")
+            .inline(html! { p { "Invoice A-1" } })
+            .render(&accessor.context().await)
+            .into_string();
+
+        assert_eq!(html, "<div id=\"recent\" class=\"blandwork-region\"><p>Invoice A-1</p></div>");
+    }
+}
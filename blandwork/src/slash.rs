@@ -0,0 +1,115 @@
+use std::{future::Future, pin::Pin, task::{Context as TaskContext, Poll}};
+
+use axum::{body::Body, extract::Request, http::{uri::PathAndQuery, HeaderValue}, response::IntoResponse};
+use hyper::{header::LOCATION, Response, StatusCode, Uri};
+use tower::{Layer, Service};
+
+use crate::config::TrailingSlash;
+
+/// Path without its trailing slashes and with a single leading one, `None` when it already is
+/// (or is the root). `//host/` would otherwise redirect to the protocol relative `//host`.
+fn canonical(path: &str) -> Option<&str> {
+    let trimmed: &str = path.trim_end_matches('/');
+    let leading: usize = trimmed.len() - trimmed.trim_start_matches('/').len();
+    let trimmed: &str = &trimmed[leading.saturating_sub(1)..];
+
+    match trimmed.len() == path.len() || path == "/" {
+        true => None,
+        false if trimmed.is_empty() => Some("/"),
+        false => Some(trimmed),
+    }
+}
+
+fn with_path(uri: &Uri, path: &str) -> Option<Uri> {
+    let path_and_query: String = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+
+    Uri::from_parts(parts).ok()
+}
+
+/// Treats `/users/` as `/users` before routing.
+///
+/// Wraps the whole router (routing has already happened inside a `Router::layer`),
+/// so it sees the full path including any nest or feature prefix.
+/// Routes are expected to be declared without a trailing slash.
+#[derive(Debug, Clone, Copy)]
+pub struct TrailingSlashLayer {
+    mode: TrailingSlash,
+}
+
+impl TrailingSlashLayer {
+    pub fn new(mode: TrailingSlash) -> Self {
+        Self { mode }
+    }
+}
+
+impl<S> Layer<S> for TrailingSlashLayer {
+    type Service = TrailingSlashService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TrailingSlashService { inner, mode: self.mode }
+    }
+}
+
+#[derive(Clone)]
+pub struct TrailingSlashService<S> {
+    inner: S,
+    mode: TrailingSlash,
+}
+
+impl<S> Service<Request> for TrailingSlashService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let uri: Option<Uri> = canonical(req.uri().path()).and_then(|path| with_path(req.uri(), path));
+
+        match (self.mode, uri) {
+            (TrailingSlash::Redirect, Some(uri)) => {
+                // 308 keeps the method and body of a form post
+                let location: Option<HeaderValue> = uri.path_and_query()
+                    .and_then(|location| HeaderValue::from_str(location.as_str()).ok());
+
+                if let Some(location) = location {
+                    return Box::pin(async move {
+                        Ok((StatusCode::PERMANENT_REDIRECT, [(LOCATION, location)]).into_response())
+                    });
+                }
+            },
+            (TrailingSlash::Ignore, Some(uri)) => *req.uri_mut() = uri,
+            _ => {},
+        }
+
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::canonical;
+
+    #[test]
+    fn test_canonical() {
+        assert_eq!(canonical("/"), None);
+        assert_eq!(canonical("/users"), None);
+        assert_eq!(canonical("/users/"), Some("/users"));
+        assert_eq!(canonical("/app/users//"), Some("/app/users"));
+        assert_eq!(canonical("//"), Some("/"));
+        assert_eq!(canonical("//evil.com/"), Some("/evil.com"));
+        assert_eq!(canonical("//evil.com"), Some("/evil.com"));
+    }
+}
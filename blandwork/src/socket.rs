@@ -0,0 +1,547 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime
+};
+
+use tokio::sync::watch;
+use uuid::Uuid;
+
+#[cfg(feature = "ws")]
+pub use upgrade::{ws_route, Socket, WsError, SOCKET_QUEUE};
+
+/// An open WebSocket connection.
+#[derive(Debug, Clone)]
+pub struct SocketInfo {
+    pub id: Uuid,
+    pub path: String,
+    pub opened_at: SystemTime,
+}
+
+// by connection, the sender closes it
+type Sockets = Arc<Mutex<HashMap<Uuid, (SocketInfo, watch::Sender<bool>)>>>;
+
+/// The WebSocket connections of an App, closed by `run` on shutdown.
+/// Presence and admin views list them, `close` ends one from the server side.
+#[derive(Clone, Default)]
+pub struct Connections {
+    sockets: Sockets,
+}
+
+/// Removes its connection from `Connections` when the socket ends.
+#[cfg(feature = "ws")]
+pub(crate) struct Registration {
+    id: Uuid,
+    connections: Connections,
+}
+
+#[cfg(feature = "ws")]
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.connections.sockets.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl Connections {
+    /// Track a new connection, the receiver changes when it should be closed.
+    #[cfg(feature = "ws")]
+    pub(crate) fn open(&self, path: &str) -> (Registration, watch::Receiver<bool>) {
+        let info: SocketInfo = SocketInfo { id: Uuid::new_v4(), path: path.to_owned(), opened_at: SystemTime::now() };
+        let (sender, receiver) = watch::channel(false);
+        let id: Uuid = info.id;
+
+        self.sockets.lock().unwrap().insert(id, (info, sender));
+
+        (Registration { id, connections: self.clone() }, receiver)
+    }
+
+    /// Open connections, oldest first.
+    pub fn list(&self) -> Vec<SocketInfo> {
+        let mut sockets: Vec<SocketInfo> = self.sockets.lock().unwrap()
+            .values()
+            .map(|(info, _)| info.clone())
+            .collect();
+
+        sockets.sort_by_key(|info| info.opened_at);
+        sockets
+    }
+
+    pub fn len(&self) -> usize {
+        self.sockets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Send a close frame to one connection, false when it is already gone.
+    pub fn close(&self, id: Uuid) -> bool {
+        match self.sockets.lock().unwrap().get(&id) {
+            Some((_, closer)) => closer.send(true).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn close_all(&self) {
+        for (_, closer) in self.sockets.lock().unwrap().values() {
+            let _ = closer.send(true);
+        }
+    }
+}
+
+#[cfg(feature = "ws")]
+mod upgrade {
+    use std::{
+        collections::{HashMap, HashSet},
+        error::Error, fmt::Display, future::Future,
+        sync::{Arc, Mutex}
+    };
+
+    use axum::{
+        extract::{ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade}, RawPathParams},
+        http::{header::{HOST, ORIGIN}, HeaderMap, StatusCode},
+        response::IntoResponse, routing::get, Extension, Router
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::json;
+    use tokio::sync::{mpsc::{self, error::TrySendError}, oneshot, watch};
+    use uuid::Uuid;
+
+    use crate::{config::Sockets, context::DetachedContext, events::{EventBus, Topic}, ContextAccessor};
+
+    use super::{Connections, Registration};
+
+    /// Messages queued per direction and connection, a full send queue makes `send` wait
+    /// and drops broadcast copies for that connection.
+    pub const SOCKET_QUEUE: usize = 64;
+
+    #[derive(Debug)]
+    pub enum WsError {
+        Closed,
+        Json(serde_json::Error),
+    }
+
+    impl Display for WsError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                WsError::Closed => write!(f, "socket closed"),
+                WsError::Json(e) => write!(f, "invalid socket message: {e}"),
+            }
+        }
+    }
+
+    impl Error for WsError {}
+
+    impl From<serde_json::Error> for WsError {
+        fn from(value: serde_json::Error) -> Self {
+            WsError::Json(value)
+        }
+    }
+
+    /// A WebSocket route of a feature. The upgrade request passes the feature's layers and
+    /// the ContextLayer like any other request, a layer rejecting it refuses the handshake.
+    /// A browser's handshake from another site is refused with 403, see `server.sockets`.
+    /// `handler` runs for the lifetime of the connection, the socket closes when it returns.
+    ///
+    /// ```ignore
+    /// fn router(&self) -> Router {
+    ///     Router::new().merge(ws_route("/board/:id/ws", |mut socket: Socket, context: DetachedContext| async move {
+    ///         socket.subscribe(&CARD_MOVED);
+    ///
+    ///         while let Some(Ok(moved)) = socket.recv_json::<CardMoved>().await {
+    ///             ...
+    ///         }
+    ///     }))
+    /// }
+    /// ```
+    pub fn ws_route<F, Fut>(path: &str, handler: F) -> Router
+    where
+        F: Fn(Socket, DetachedContext) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Router::new().route(path, get(move |
+            upgrade: WebSocketUpgrade,
+            params: RawPathParams,
+            headers: HeaderMap,
+            Extension(accessor): Extension<ContextAccessor>,
+            connections: Option<Extension<Connections>>,
+            sockets: Option<Extension<Arc<Sockets>>>
+        | {
+            let handler = handler.clone();
+
+            async move {
+                let origins: &[String] = sockets.as_ref().map(|Extension(sockets)| sockets.origins.as_slice()).unwrap_or_default();
+
+                if !allowed_origin(&headers, origins) {
+                    return StatusCode::FORBIDDEN.into_response();
+                }
+
+                // the guard is released before the response passes the ContextLayer
+                let context: DetachedContext = accessor.context().await.detach();
+                let connections: Connections = connections.map(|Extension(connections)| connections).unwrap_or_default();
+                let params: HashMap<String, String> = params.iter()
+                    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                    .collect();
+
+                upgrade.on_upgrade(move |ws| async move {
+                    let socket: Socket = Socket::start(ws, &connections, &context, params);
+                    handler(socket, context).await;
+                })
+            }
+        }))
+    }
+
+    // browsers always send an Origin with the handshake and don't apply CORS to it, so
+    // it has to be the page's own host or a configured origin. Other clients send none.
+    fn allowed_origin(headers: &HeaderMap, origins: &[String]) -> bool {
+        let Some(origin) = headers.get(ORIGIN) else {
+            return true;
+        };
+
+        let Ok(origin) = origin.to_str() else {
+            return false;
+        };
+
+        if origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            return true;
+        }
+
+        let host: Option<&str> = headers.get(HOST).and_then(|host| host.to_str().ok());
+
+        match (origin.split_once("://"), host) {
+            (Some((_, authority)), Some(host)) => authority.eq_ignore_ascii_case(host),
+            // "null" of sandboxed frames and file:// pages
+            _ => false,
+        }
+    }
+
+    /// One connection as seen by its handler, messages go through bounded queues
+    /// drained by a task owning the WebSocket.
+    pub struct Socket {
+        id: Uuid,
+        params: HashMap<String, String>,
+        outgoing: mpsc::Sender<Message>,
+        incoming: mpsc::Receiver<Message>,
+        events: Option<EventBus>,
+
+        // topics copied to this connection, see `subscribe`
+        topics: Arc<Mutex<HashSet<String>>>,
+
+        // stops the topic forwarder with the socket
+        forwarder: Option<oneshot::Sender<()>>,
+    }
+
+    impl Socket {
+        fn start(ws: WebSocket, connections: &Connections, context: &DetachedContext, params: HashMap<String, String>) -> Self {
+            let (registration, close) = connections.open(context.path());
+            let id: Uuid = registration.id;
+
+            let (outgoing, outgoing_rx) = mpsc::channel(SOCKET_QUEUE);
+            let (incoming_tx, incoming) = mpsc::channel(SOCKET_QUEUE);
+
+            tokio::spawn(drive(ws, outgoing_rx, incoming_tx, close, registration));
+
+            Self {
+                id,
+                params,
+                outgoing,
+                incoming,
+                events: context.events().cloned(),
+                topics: Arc::new(Mutex::new(HashSet::new())),
+                forwarder: None,
+            }
+        }
+
+        /// The id listed by `Connections`.
+        pub fn id(&self) -> Uuid {
+            self.id
+        }
+
+        /// A path parameter of the route, `id` of `/board/:id/ws`.
+        pub fn param(&self, name: &str) -> Option<&str> {
+            self.params.get(name).map(|value| value.as_str())
+        }
+
+        /// The next text or binary message, None once the client is gone.
+        pub async fn recv(&mut self) -> Option<Message> {
+            self.incoming.recv().await
+        }
+
+        /// The next message decoded from JSON, e.g. a serde tagged enum of the client's commands.
+        pub async fn recv_json<T: DeserializeOwned>(&mut self) -> Option<Result<T, WsError>> {
+            loop {
+                let decoded = match self.recv().await? {
+                    Message::Text(text) => serde_json::from_str(&text),
+                    Message::Binary(bytes) => serde_json::from_slice(&bytes),
+                    _ => continue,
+                };
+
+                return Some(decoded.map_err(WsError::from));
+            }
+        }
+
+        /// Queue a raw message, waits while the send queue is full.
+        pub async fn send(&self, message: Message) -> Result<(), WsError> {
+            self.outgoing.send(message).await.map_err(|_| WsError::Closed)
+        }
+
+        pub async fn send_json<T: Serialize>(&self, value: &T) -> Result<(), WsError> {
+            self.send(Message::Text(serde_json::to_string(value)?)).await
+        }
+
+        /// Copy the events published on `topic` with `Published::broadcast` to this connection,
+        /// the same copies the SSE stream gets, as `{"topic": ..., "payload": ...}`.
+        pub fn subscribe<T>(&mut self, topic: &Topic<T>) {
+            self.topics.lock().unwrap().insert(topic.key().to_owned());
+
+            if self.forwarder.is_some() {
+                return;
+            }
+
+            let Some(bus) = self.events.as_ref() else {
+                tracing::warn!("socket subscribed to {} without an event bus", topic.key());
+                return;
+            };
+
+            let (stop, mut stopped) = oneshot::channel();
+            let mut events = bus.coalesced_events();
+            let outgoing = self.outgoing.downgrade();
+            let topics = self.topics.clone();
+
+            self.forwarder = Some(stop);
+
+            tokio::spawn(async move {
+                loop {
+                    let event = tokio::select! {
+                        _ = &mut stopped => break,
+                        event = events.recv() => match event {
+                            Some(event) => event,
+                            None => break,
+                        },
+                    };
+
+                    if !topics.lock().unwrap().contains(&event.topic) {
+                        continue;
+                    }
+
+                    let Some(outgoing) = outgoing.upgrade() else {
+                        break;
+                    };
+
+                    let message: Message = Message::Text(json!({ "topic": event.topic, "payload": event.payload }).to_string());
+
+                    match outgoing.try_send(message) {
+                        Ok(_) => {},
+                        Err(TrySendError::Full(_)) => tracing::warn!("socket send queue full, dropping {}", event.topic),
+                        Err(TrySendError::Closed(_)) => break,
+                    }
+                }
+            });
+        }
+    }
+
+    async fn drive(
+        mut ws: WebSocket,
+        mut outgoing: mpsc::Receiver<Message>,
+        incoming: mpsc::Sender<Message>,
+        mut close: watch::Receiver<bool>,
+        _registration: Registration,
+    ) {
+        loop {
+            tokio::select! {
+                _ = close.changed() => {
+                    let frame: CloseFrame = CloseFrame { code: close_code::AWAY, reason: "shutting down".into() };
+                    let _ = ws.send(Message::Close(Some(frame))).await;
+                    break;
+                },
+                message = outgoing.recv() => match message {
+                    Some(message) => if ws.send(message).await.is_err() {
+                        break;
+                    },
+                    // the handler returned
+                    None => {
+                        let _ = ws.send(Message::Close(None)).await;
+                        break;
+                    },
+                },
+                message = ws.recv() => match message {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(message)) => if incoming.send(message).await.is_err() {
+                        break;
+                    },
+                },
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "ws"))]
+mod test {
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use axum::{
+        extract::Request, http::StatusCode, middleware::{from_fn, Next},
+        response::{IntoResponse, Response}, Extension, Router
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use serde::{Deserialize, Serialize};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{connect_async, tungstenite::{self, client::IntoClientRequest, Message}};
+
+    use crate::{config::Sockets, context::{ContextLayer, DetachedContext}, events::{EventBus, Topic}};
+
+    use super::{ws_route, Connections, Socket};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    enum Command {
+        Move { card: u32, column: String },
+        Moved { board: String, card: u32, column: String },
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct CardMoved {
+        card: u32,
+    }
+
+    const CARD_MOVED: Topic<CardMoved> = Topic::new("board.card_moved");
+
+    async fn echo(mut socket: Socket, _: DetachedContext) {
+        let board: String = socket.param("id").unwrap_or_default().to_owned();
+
+        while let Some(Ok(command)) = socket.recv_json::<Command>().await {
+            if let Command::Move { card, column } = command {
+                socket.send_json(&Command::Moved { board: board.clone(), card, column }).await.unwrap();
+            }
+        }
+    }
+
+    async fn board(mut socket: Socket, _: DetachedContext) {
+        socket.subscribe(&CARD_MOVED);
+        socket.send(axum::extract::ws::Message::Text("ready".to_owned())).await.unwrap();
+
+        while socket.recv().await.is_some() {}
+    }
+
+    async fn members_only(request: Request, next: Next) -> Response {
+        match request.headers().contains_key("x-member") {
+            true => next.run(request).await,
+            false => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+
+    async fn serve(connections: Connections, bus: EventBus) -> SocketAddr {
+        let router = Router::new()
+            .merge(ws_route("/echo/:id/ws", echo))
+            .merge(ws_route("/board/:id/ws", board))
+            .merge(ws_route("/private/ws", echo).route_layer(from_fn(members_only)))
+            .layer(ContextLayer::new())
+            .layer(Extension(bus))
+            .layer(Extension(connections))
+            .layer(Extension(Arc::new(Sockets { origins: vec!["https://app.example".to_owned()] })));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        addr
+    }
+
+    async fn wait_until(done: impl Fn() -> bool) {
+        for _ in 0..1000 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejected_by_policy() {
+        let addr = serve(Connections::default(), EventBus::new()).await;
+
+        match connect_async(format!("ws://{addr}/private/ws")).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+            other => panic!("handshake should be refused: {:?}", other.map(|(_, response)| response.status())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_echo() {
+        let addr = serve(Connections::default(), EventBus::new()).await;
+        let (mut client, _) = connect_async(format!("ws://{addr}/echo/7/ws")).await.unwrap();
+
+        client.send(Message::Text(r#"{"type":"Move","card":3,"column":"done"}"#.to_owned())).await.unwrap();
+
+        let reply: Command = match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected {other:?}"),
+        };
+
+        assert_eq!(reply, Command::Moved { board: "7".to_owned(), card: 3, column: "done".to_owned() });
+    }
+
+    #[tokio::test]
+    async fn test_topic_broadcast() {
+        let bus = EventBus::new();
+        let addr = serve(Connections::default(), bus.clone()).await;
+        let (mut client, _) = connect_async(format!("ws://{addr}/board/1/ws")).await.unwrap();
+
+        assert_eq!(client.next().await.unwrap().unwrap(), Message::Text("ready".to_owned()));
+
+        bus.publish(&CARD_MOVED, &CardMoved { card: 9 }).broadcast();
+
+        let message: serde_json::Value = match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("unexpected {other:?}"),
+        };
+
+        assert_eq!(message, serde_json::json!({ "topic": "board.card_moved", "payload": { "card": 9 } }));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_sockets() {
+        let connections = Connections::default();
+        let addr = serve(connections.clone(), EventBus::new()).await;
+        let (mut client, _) = connect_async(format!("ws://{addr}/echo/1/ws")).await.unwrap();
+
+        wait_until(|| connections.len() == 1).await;
+        assert_eq!(connections.list()[0].path, "/echo/1/ws");
+
+        connections.close_all();
+
+        match client.next().await.unwrap().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1001),
+            other => panic!("unexpected {other:?}"),
+        }
+
+        wait_until(|| connections.is_empty()).await;
+        assert!(connections.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cross_site_handshake_refused() {
+        let addr = serve(Connections::default(), EventBus::new()).await;
+
+        let handshake = |origin: String| {
+            let mut request = format!("ws://{addr}/echo/1/ws").into_client_request().unwrap();
+            request.headers_mut().insert("origin", origin.parse().unwrap());
+            connect_async(request)
+        };
+
+        match handshake("https://evil.example".to_owned()).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+            other => panic!("handshake should be refused: {:?}", other.map(|(_, response)| response.status())),
+        }
+
+        match handshake("null".to_owned()).await {
+            Err(tungstenite::Error::Http(response)) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+            other => panic!("handshake should be refused: {:?}", other.map(|(_, response)| response.status())),
+        }
+
+        // the page's own host and the configured origins
+        assert!(handshake(format!("http://{addr}")).await.is_ok());
+        assert!(handshake("https://app.example".to_owned()).await.is_ok());
+    }
+}
@@ -0,0 +1,107 @@
+use std::fmt::Display;
+
+use axum::{
+    body::{Body, Bytes},
+    http::HeaderValue,
+    response::{IntoResponse, Response}
+};
+use hyper::header::CONTENT_TYPE;
+use serde::Serialize;
+use tokio_stream::{Stream, StreamExt};
+
+/// Response extension of a body produced incrementally,
+/// buffering layers (response cache, header audit) pass these through untouched.
+#[derive(Debug, Clone, Copy)]
+pub struct Streaming;
+
+/// Stream `items` to the client as one JSON array without buffering them.
+///
+/// Items are pulled as the client reads, a slow client slows the source down.
+/// The status is sent before the first item, an error part way through aborts
+/// the response (the client sees a truncated array) and is logged.
+///
+/// ```ignore
+/// async fn invoices(db: Db) -> Response {
+///     let rows = db.stream_rows("SELECT * FROM invoice", Vec::new(), 500);
+///     json_array(rows.map(|row| row.map(Invoice::from)))
+/// }
+/// ```
+pub fn json_array<S, T, E>(items: S) -> Response
+where
+    S: Stream<Item = Result<T, E>> + Send + 'static,
+    T: Serialize,
+    E: Display,
+{
+    let mut first: bool = true;
+
+    let items = items.map(move |item| -> Result<Bytes, std::io::Error> {
+        let item: T = item.map_err(|e| {
+            tracing::error!("json stream aborted: {e}");
+            std::io::Error::other(e.to_string())
+        })?;
+
+        let mut chunk: Vec<u8> = match first {
+            true => Vec::new(),
+            false => b",".to_vec(),
+        };
+        first = false;
+
+        serde_json::to_writer(&mut chunk, &item).map_err(|e| {
+            tracing::error!("json stream aborted, unable to serialize item: {e}");
+            std::io::Error::other(e)
+        })?;
+
+        Ok(Bytes::from(chunk))
+    });
+
+    let open = tokio_stream::once(Ok::<Bytes, std::io::Error>(Bytes::from_static(b"[")));
+    let close = tokio_stream::once(Ok(Bytes::from_static(b"]")));
+
+    let mut response: Response = Body::from_stream(open.chain(items).chain(close)).into_response();
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response.extensions_mut().insert(Streaming);
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::to_bytes;
+    use serde::Serialize;
+
+    use super::{json_array, Streaming};
+
+    #[derive(Serialize)]
+    struct Row {
+        id: u32,
+    }
+
+    async fn body(items: Vec<Result<Row, String>>) -> Result<String, axum::Error> {
+        let response = json_array(tokio_stream::iter(items));
+
+        assert_eq!(response.headers()["content-type"], "application/json");
+        assert!(response.extensions().get::<Streaming>().is_some());
+
+        let bytes = to_bytes(response.into_body(), usize::MAX).await?;
+        Ok(String::from_utf8(bytes.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_array() {
+        let rows = vec![Ok(Row { id: 1 }), Ok(Row { id: 2 }), Ok(Row { id: 3 })];
+
+        assert_eq!(body(rows).await.unwrap(), r#"[{"id":1},{"id":2},{"id":3}]"#);
+    }
+
+    #[tokio::test]
+    async fn test_empty() {
+        assert_eq!(body(Vec::new()).await.unwrap(), "[]");
+    }
+
+    #[tokio::test]
+    async fn test_error_aborts() {
+        let rows = vec![Ok(Row { id: 1 }), Err("connection reset".to_owned())];
+
+        assert!(body(rows).await.is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use maud::{html, Markup};
+
+use crate::{feature::Link, Context, Feature};
+
+/// Element id of the region the shell reserves for the active feature's submenu.
+pub const SUBMENU_ID: &str = "submenu";
+
+/// A feature's top-level link and its sub-navigation, see `Feature::submenu`.
+#[derive(Debug, Clone)]
+pub struct FeatureMenu {
+    pub feature: String,
+    pub link: Link,
+    pub submenu: Vec<Link>,
+}
+
+/// Every feature with a top-level link, provided to the context by `build`.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureMenus(pub Arc<Vec<FeatureMenu>>);
+
+pub(crate) fn feature_menus(features: &[Box<dyn Feature>]) -> FeatureMenus {
+    FeatureMenus(Arc::new(features.iter()
+        .filter_map(|feature| feature.link().map(|link| FeatureMenu {
+            feature: feature.name(),
+            link,
+            submenu: feature.submenu().unwrap_or_default(),
+        }))
+        .collect()))
+}
+
+impl FeatureMenus {
+    /// The feature whose top-level link is active, the most specific route wins
+    /// (`/settings/team` over `/settings`).
+    pub fn active(&self, context: &Context) -> Option<&FeatureMenu> {
+        self.0.iter()
+            .filter(|menu| menu.link.is_active(context))
+            .max_by_key(|menu| menu.link.route.len())
+    }
+}
+
+/// Default rendering of `Context::submenu`, nothing outside of a feature with one.
+pub fn render_submenu(context: &Context) -> Markup {
+    let Some((feature, links)) = context.active_feature().zip(context.submenu()) else {
+        return html!{};
+    };
+
+    html!{
+        nav id=(SUBMENU_ID) aria-label=(feature) {
+            ul {
+                @for link in links.iter().filter(|link| context.link_enabled(&link.route)) {
+                    @let active: bool = link.is_active(context);
+                    li {
+                        a href=(context.url_for(&link.route))
+                            title=(link.title)
+                            class=[active.then_some("active")]
+                            aria-current=[active.then_some("page")]
+                            hx-target="#content"
+                            hx-swap="innerHTML" {
+                                (link.label)
+                            }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, response::IntoResponse, routing::get, Router};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{feature::Link, test::router, App, Config, Context, Feature, Template};
+
+    use super::render_submenu;
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, context: &Context, body: Markup) -> Markup {
+            html! {
+                html { body {
+                    title { (context.title("Home")) }
+                    header { (context.active_feature().unwrap_or_default()) }
+                    ul { @for link in context.links() { li class=[link.active.then_some("active")] { (link.route) } } }
+                    (render_submenu(context))
+                    main { (body) }
+                } }
+            }
+        }
+    }
+
+    fn link(route: &str, label: &str) -> Link {
+        Link { active: false, title: label.to_owned(), label: label.to_owned(), route: route.to_owned(), icon: None, css: None, remember: false }
+    }
+
+    struct Settings;
+
+    impl Feature for Settings {
+        fn name(&self) -> String {
+            "Settings".to_owned()
+        }
+
+        fn link(&self) -> Option<Link> {
+            Some(link("/settings", "Settings"))
+        }
+
+        fn submenu(&self) -> Option<Vec<Link>> {
+            Some(vec![link("/settings/profile", "Profile"), link("/settings/billing", "Billing")])
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/settings/*page", get(|| async { "settings".into_response() })))
+        }
+    }
+
+    struct Reports;
+
+    impl Feature for Reports {
+        fn name(&self) -> String {
+            "Reports".to_owned()
+        }
+
+        fn link(&self) -> Option<Link> {
+            Some(link("/reports", "Reports"))
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/reports", get(|| async { "reports".into_response() })))
+        }
+    }
+
+    struct Sample;
+
+    impl Feature for Sample {
+        fn link(&self) -> Option<Link> {
+            Some(link("/sample", "Sample"))
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new()
+                .route("/sample", get(|| async { "sample".into_response() }))
+                .route("/about", get(|| async { "about".into_response() })))
+        }
+    }
+
+    struct SampleToo;
+
+    impl Feature for SampleToo {
+        fn link(&self) -> Option<Link> {
+            Some(link("/sample-too", "Sample too"))
+        }
+
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/sample-too/:id", get(|| async { "sample too".into_response() })))
+        }
+    }
+
+    async fn page(path: &str) -> String {
+        let mut app = App::new(Config::default(), TestTemplate)
+            .register_feature(Settings)
+            .register_feature(Reports)
+            .register_feature(Sample)
+            .register_feature(SampleToo);
+
+        let response = router(&app.build())
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_submenu_of_active_feature() {
+        let html: String = page("/settings/billing").await;
+
+        assert!(html.contains("<header>Settings</header>"));
+        assert!(html.contains(r#"<nav id="submenu" aria-label="Settings">"#));
+        assert!(html.contains(r#"href="/settings/profile" title="Profile" hx-target"#));
+        assert!(html.contains(r#"href="/settings/billing" title="Billing" class="active" aria-current="page""#));
+
+        // an active feature without a submenu leaves the region empty
+        let html: String = page("/reports").await;
+
+        assert!(html.contains("<header>Reports</header>"));
+        assert!(!html.contains("submenu"));
+    }
+
+    #[tokio::test]
+    async fn test_links_and_title() {
+        let html: String = page("/sample-too/42").await;
+
+        // a prefix of the path is not enough, `/sample` is not active
+        assert!(html.contains("<title>Sample too</title>"));
+        assert!(html.contains(r#"<li>/sample</li><li class="active">/sample-too</li>"#));
+
+        // no link leads to the page
+        let html: String = page("/about").await;
+
+        assert!(html.contains("<title>Home</title>"));
+        assert!(!html.contains(r#"class="active""#));
+    }
+}
@@ -0,0 +1,841 @@
+use std::{
+    collections::HashSet,
+    error::Error, fmt::Display,
+    future::Future, path::{Path, PathBuf}, pin::Pin,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+use axum::{
+    extract::{Path as UrlPath, Query, Request},
+    http::{request::Parts, Extensions},
+    response::{IntoResponse, Response},
+    routing::get, Extension, Router
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use hyper::{header::{CONTENT_DISPOSITION, CONTENT_TYPE}, StatusCode};
+use maud::{html, Markup};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::{
+    clock::{Clock, SharedClock, SystemClock},
+    events::{EventBus, HandlerError, Topic},
+    schedule::ScheduledJob,
+    Feature
+};
+
+pub const TAKEOUT_ROUTE: &str = "/_blandwork/takeout";
+
+/// Published after every feature's section of an export, see `TakeoutFeature`.
+pub const TAKEOUT_PROGRESS: Topic<TakeoutProgress> = Topic::new("takeout:progress");
+
+/// Published once the archive is written, subscribe to mail the user the download link.
+pub const TAKEOUT_READY: Topic<TakeoutReady> = Topic::new("takeout:ready");
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TakeoutProgress {
+    pub export: String,
+    pub feature: String,
+    pub failed: bool,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TakeoutReady {
+    pub export: String,
+    pub user: String,
+    pub url: String,
+
+    // unix seconds
+    pub expires_at: u64,
+    pub manifest: TakeoutManifest,
+}
+
+#[derive(Debug)]
+pub enum TakeoutError {
+    Io(String),
+}
+
+impl Display for TakeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TakeoutError::Io(e) => write!(f, "takeout io error: {e}"),
+        }
+    }
+}
+
+impl Error for TakeoutError {}
+
+impl From<std::io::Error> for TakeoutError {
+    fn from(value: std::io::Error) -> Self {
+        TakeoutError::Io(value.to_string())
+    }
+}
+
+/// What a provider gets to look at: the user and the request extensions
+/// (the pool, the event bus and whatever the application added).
+pub struct UserDataScope {
+    pub user: String,
+    pub extensions: Extensions,
+}
+
+#[cfg(feature = "postgres")]
+impl UserDataScope {
+    pub fn pool(&self) -> Option<&crate::db::ConnectionPool> {
+        self.extensions.get::<crate::db::ConnectionPool>()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SectionContent {
+    Json(Value),
+    Csv(String),
+
+    // an uploaded file, copied into the archive as is
+    File(PathBuf),
+}
+
+/// One named part of a feature's data, `{feature}/{name}.json` in the archive.
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub content: SectionContent,
+}
+
+impl Section {
+    pub fn json(name: &str, value: Value) -> Self {
+        Self { name: name.to_owned(), content: SectionContent::Json(value) }
+    }
+
+    pub fn csv(name: &str, csv: impl Into<String>) -> Self {
+        Self { name: name.to_owned(), content: SectionContent::Csv(csv.into()) }
+    }
+
+    /// Kept under its own file name, `name` only labels it in the manifest.
+    pub fn file(name: &str, path: impl Into<PathBuf>) -> Self {
+        Self { name: name.to_owned(), content: SectionContent::File(path.into()) }
+    }
+}
+
+type ProviderFuture = Pin<Box<dyn Future<Output = Result<Vec<Section>, HandlerError>> + Send>>;
+
+/// Everything a feature holds about a user, see `Feature::user_data`.
+#[derive(Clone)]
+pub struct UserDataProvider(Arc<dyn Fn(UserDataScope) -> ProviderFuture + Send + Sync>);
+
+impl UserDataProvider {
+    pub fn new<F, Fut>(provider: F) -> Self
+    where
+        F: Fn(UserDataScope) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<Section>, HandlerError>> + Send + 'static,
+    {
+        Self(Arc::new(move |scope: UserDataScope| -> ProviderFuture { Box::pin(provider(scope)) }))
+    }
+}
+
+/// Every feature's provider by feature name, provided to the takeout as an extension by `build`.
+#[derive(Clone, Default)]
+pub struct UserDataProviders(pub Arc<Vec<(String, UserDataProvider)>>);
+
+pub(crate) fn user_data_providers(features: &[Box<dyn Feature>]) -> UserDataProviders {
+    UserDataProviders(Arc::new(features.iter()
+        .filter_map(|feature| feature.user_data().map(|provider| (feature.name(), provider)))
+        .collect()))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum SectionStatus {
+    Contributed { files: Vec<String> },
+    Failed { error: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub feature: String,
+
+    #[serde(flatten)]
+    pub status: SectionStatus,
+}
+
+/// `manifest.json` of an archive, which features contributed and which failed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TakeoutManifest {
+    pub export: String,
+    pub user: String,
+
+    // unix seconds
+    pub created_at: u64,
+    pub features: Vec<ManifestEntry>,
+}
+
+/// A written archive and its signed download link.
+#[derive(Debug, Clone)]
+pub struct TakeoutExport {
+    pub path: PathBuf,
+    pub url: String,
+    pub expires_at: u64,
+    pub manifest: TakeoutManifest,
+}
+
+type HmacSha256 = Hmac<Sha256>;
+type Identity = Arc<dyn Fn(&Parts) -> Option<String> + Send + Sync>;
+
+/// Assembles every feature's `user_data` into a zip archive downloadable through a signed,
+/// expiring link. A failing provider only marks its feature failed in the manifest.
+#[derive(Clone)]
+pub struct Takeout {
+    key: Vec<u8>,
+    dir: PathBuf,
+    clock: SharedClock,
+
+    // resolves the signed in user, no export without one
+    identity: Identity,
+
+    // how long a download link is valid
+    ttl: Duration,
+
+    // users with an export being written, one at a time each
+    running: Arc<Mutex<HashSet<String>>>,
+
+    // attached by the takeout feature during `build`
+    events: Arc<RwLock<Option<EventBus>>>,
+}
+
+/// A user's running export, released when the export task ends (or panics).
+struct Running {
+    user: String,
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().remove(&self.user);
+    }
+}
+
+impl Takeout {
+    pub fn new(key: impl Into<Vec<u8>>, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            key: key.into(),
+            dir: dir.into(),
+            clock: Arc::new(SystemClock),
+            identity: Arc::new(|_: &Parts| None),
+            ttl: Duration::from_secs(7 * 24 * 60 * 60),
+            running: Arc::new(Mutex::new(HashSet::new())),
+            events: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How the export button finds the current user, e.g. from an auth extension.
+    pub fn identity(mut self, identity: impl Fn(&Parts) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.identity = Arc::new(identity);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+
+    fn publish<T: Serialize>(&self, topic: &Topic<T>, payload: &T) {
+        if let Some(events) = self.events.read().unwrap().as_ref() {
+            events.publish(topic, payload);
+        }
+    }
+
+    fn signature(&self, export: &str, expires_at: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+        mac.update(format!("takeout.{export}.{expires_at}").as_bytes());
+        mac
+    }
+
+    /// Download link of an export, valid until `expires_at` (unix seconds).
+    pub fn download_url(&self, export: &str, expires_at: u64) -> String {
+        let signature: String = URL_SAFE_NO_PAD.encode(self.signature(export, expires_at).finalize().into_bytes());
+
+        format!("{TAKEOUT_ROUTE}/{export}?expires={expires_at}&signature={signature}")
+    }
+
+    fn verify(&self, export: &str, expires_at: u64, signature: &str) -> bool {
+        URL_SAFE_NO_PAD.decode(signature)
+            .map(|signature| self.signature(export, expires_at).verify_slice(&signature).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn archive(&self, export: &str) -> PathBuf {
+        self.dir.join(format!("{export}.zip"))
+    }
+
+    /// None while `user` has an export running already.
+    fn start(&self, user: &str) -> Option<Running> {
+        match self.running.lock().unwrap().insert(user.to_owned()) {
+            true => Some(Running { user: user.to_owned(), running: self.running.clone() }),
+            false => None,
+        }
+    }
+
+    /// Deletes the archives older than the link validity, whose links have all expired.
+    pub async fn sweep(&self) -> Result<usize, TakeoutError> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut deleted: usize = 0;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path: PathBuf = entry.path();
+
+            if path.extension().and_then(|extension| extension.to_str()) == Some("zip") && self.delete_expired(&path).await? {
+                deleted += 1;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    // links signed later with `download_url` may outlive the one of `run`, the archive
+    // goes once it is older than the validity of a link
+    async fn delete_expired(&self, path: &Path) -> Result<bool, TakeoutError> {
+        let written: SystemTime = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata.modified()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+
+        if written > self.clock.now() - self.ttl {
+            return Ok(false);
+        }
+
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// `sweep` as a job for `Feature::schedule`, `TakeoutFeature` runs it hourly.
+    pub fn sweep_job(&self, cron: &str) -> ScheduledJob {
+        let takeout: Takeout = self.clone();
+
+        ScheduledJob::new("takeout archive sweep", cron, move |_| {
+            let takeout: Takeout = takeout.clone();
+
+            async move {
+                takeout.sweep().await?;
+                Ok(())
+            }
+        })
+    }
+
+    /// Runs every provider for `user` and writes the archive, publishing `TAKEOUT_PROGRESS`
+    /// along the way and `TAKEOUT_READY` with the link at the end.
+    pub async fn run(&self, user: &str, providers: &UserDataProviders, extensions: &Extensions) -> Result<TakeoutExport, TakeoutError> {
+        let export: String = Uuid::new_v4().to_string();
+        let total: usize = providers.0.len();
+
+        let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut features: Vec<ManifestEntry> = Vec::with_capacity(total);
+
+        for (done, (feature, provider)) in providers.0.iter().enumerate() {
+            let scope: UserDataScope = UserDataScope { user: user.to_owned(), extensions: extensions.clone() };
+
+            // spawned so a panicking provider only fails its own section
+            let status: SectionStatus = match tokio::spawn((provider.0)(scope)).await {
+                Ok(Ok(sections)) => match entries(feature, sections).await {
+                    Ok(entries) => {
+                        let names: Vec<String> = entries.iter().map(|(name, _)| name.clone()).collect();
+                        files.extend(entries);
+                        SectionStatus::Contributed { files: names }
+                    },
+                    Err(e) => SectionStatus::Failed { error: e.to_string() },
+                },
+                Ok(Err(e)) => SectionStatus::Failed { error: e.to_string() },
+                Err(e) => SectionStatus::Failed { error: e.to_string() },
+            };
+
+            if let SectionStatus::Failed { error } = &status {
+                tracing::warn!("takeout {export} of {user}: {feature} failed: {error}");
+            }
+
+            self.publish(&TAKEOUT_PROGRESS, &TakeoutProgress {
+                export: export.clone(),
+                feature: feature.clone(),
+                failed: matches!(status, SectionStatus::Failed { .. }),
+                done: done + 1,
+                total,
+            });
+
+            features.push(ManifestEntry { feature: feature.clone(), status });
+        }
+
+        let manifest: TakeoutManifest = TakeoutManifest { export: export.clone(), user: user.to_owned(), created_at: self.now(), features };
+        files.insert(0, ("manifest.json".to_owned(), serde_json::to_vec_pretty(&manifest).unwrap_or_default()));
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(self.archive(&export), zip(&files)).await?;
+
+        let expires_at: u64 = manifest.created_at + self.ttl.as_secs();
+        let url: String = self.download_url(&export, expires_at);
+
+        tracing::info!(target: "blandwork::audit", user = %user, export = %export, "user data exported");
+
+        self.publish(&TAKEOUT_READY, &TakeoutReady {
+            export: export.clone(),
+            user: user.to_owned(),
+            url: url.clone(),
+            expires_at,
+            manifest: manifest.clone(),
+        });
+
+        Ok(TakeoutExport { path: self.archive(&export), url, expires_at, manifest })
+    }
+}
+
+/// Archive entries of a feature's sections.
+async fn entries(feature: &str, sections: Vec<Section>) -> Result<Vec<(String, Vec<u8>)>, TakeoutError> {
+    let mut entries: Vec<(String, Vec<u8>)> = Vec::with_capacity(sections.len());
+
+    for section in sections {
+        let entry: (String, Vec<u8>) = match section.content {
+            SectionContent::Json(value) => (format!("{feature}/{}.json", section.name), serde_json::to_vec_pretty(&value).unwrap_or_default()),
+            SectionContent::Csv(csv) => (format!("{feature}/{}.csv", section.name), csv.into_bytes()),
+            SectionContent::File(path) => {
+                let name: &str = path.file_name().and_then(|name| name.to_str()).unwrap_or(&section.name);
+                (format!("{feature}/files/{name}"), tokio::fs::read(&path).await?)
+            },
+        };
+
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+
+    for byte in data {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
+
+/// An uncompressed (stored) zip archive of `files`.
+fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    // utf-8 names, 1980-01-01 00:00
+    const FLAGS: u16 = 0x0800;
+    const DATE: u16 = 0x0021;
+
+    let mut archive: Vec<u8> = Vec::new();
+    let mut directory: Vec<u8> = Vec::new();
+
+    for (name, data) in files {
+        let offset: u32 = archive.len() as u32;
+        let crc: u32 = crc32(data);
+
+        let header = |signature: u32, out: &mut Vec<u8>, central: bool| {
+            out.extend_from_slice(&signature.to_le_bytes());
+            if central {
+                out.extend_from_slice(&20u16.to_le_bytes());
+            }
+            for value in [20u16, FLAGS, 0, 0, DATE] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            for value in [crc, data.len() as u32, data.len() as u32] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            if central {
+                // comment, disk, internal and external attributes, offset
+                out.extend_from_slice(&[0; 10]);
+                out.extend_from_slice(&offset.to_le_bytes());
+            }
+            out.extend_from_slice(name.as_bytes());
+        };
+
+        header(0x0403_4b50, &mut archive, false);
+        archive.extend_from_slice(data);
+        header(0x0201_4b50, &mut directory, true);
+    }
+
+    let directory_offset: u32 = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+
+    archive.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    for value in [0u16, 0, files.len() as u16, files.len() as u16] {
+        archive.extend_from_slice(&value.to_le_bytes());
+    }
+    archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+
+    archive
+}
+
+/// Placeholder for an account page, the export button of the signed in user.
+pub fn takeout_button() -> Markup {
+    html!{
+        div #blandwork-takeout hx-get=(TAKEOUT_ROUTE) hx-trigger="load" {}
+    }
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    expires: u64,
+    signature: String,
+}
+
+/// Mounts the export button and the signed download route, exports run in the background
+/// with every feature's `Feature::user_data`.
+#[derive(Clone)]
+pub struct TakeoutFeature {
+    takeout: Takeout,
+}
+
+impl TakeoutFeature {
+    pub fn new(takeout: Takeout) -> Self {
+        Self { takeout }
+    }
+
+    async fn button(Extension(takeout): Extension<Takeout>, request: Request) -> Response {
+        let (parts, _) = request.into_parts();
+
+        if (takeout.identity)(&parts).is_none() {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+
+        html!{
+            form hx-post=(TAKEOUT_ROUTE) hx-swap="outerHTML" hx-sync="this:drop" {
+                button type="submit" { "Export my data" }
+            }
+        }.into_response()
+    }
+
+    async fn request(
+        Extension(takeout): Extension<Takeout>,
+        providers: Option<Extension<UserDataProviders>>,
+        request: Request
+    ) -> Response {
+        let (parts, _) = request.into_parts();
+        let providers: UserDataProviders = providers.map(|Extension(providers)| providers).unwrap_or_default();
+
+        let Some(user) = (takeout.identity)(&parts) else {
+            return StatusCode::UNAUTHORIZED.into_response();
+        };
+
+        let Some(running) = takeout.start(&user) else {
+            return (StatusCode::ACCEPTED, html!{
+                p role="status" { "Your export is already being prepared, we will send you a download link." }
+            }).into_response();
+        };
+
+        tokio::spawn(async move {
+            let _running: Running = running;
+
+            if let Err(e) = takeout.run(&user, &providers, &parts.extensions).await {
+                tracing::error!("unable to export the data of {user}: {e}");
+            }
+        });
+
+        (StatusCode::ACCEPTED, html!{
+            p role="status" { "Your export is being prepared, we will send you a download link." }
+        }).into_response()
+    }
+
+    async fn download(
+        Extension(takeout): Extension<Takeout>,
+        UrlPath(export): UrlPath<String>,
+        Query(query): Query<DownloadQuery>
+    ) -> Response {
+        if Uuid::parse_str(&export).is_err() || !takeout.verify(&export, query.expires, &query.signature) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+
+        let path: PathBuf = takeout.archive(&export);
+
+        if takeout.now() >= query.expires {
+            if let Err(e) = takeout.delete_expired(&path).await {
+                tracing::warn!("unable to delete the expired takeout {export}: {e}");
+            }
+
+            return StatusCode::GONE.into_response();
+        }
+
+        match tokio::fs::read(&path).await {
+            Ok(archive) => (
+                [
+                    (CONTENT_TYPE, "application/zip".to_owned()),
+                    (CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name(&path))),
+                ],
+                archive
+            ).into_response(),
+            Err(_) => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name().and_then(|name| name.to_str()).unwrap_or("takeout.zip")
+}
+
+impl Feature for TakeoutFeature {
+    fn supplemental(&self) -> Option<Router> {
+        Some(Router::new()
+            .route(TAKEOUT_ROUTE, get(TakeoutFeature::button).post(TakeoutFeature::request))
+            .route(&format!("{TAKEOUT_ROUTE}/:export"), get(TakeoutFeature::download))
+            .layer(Extension(self.takeout.clone())))
+    }
+
+    fn noindex(&self) -> bool {
+        true
+    }
+
+    fn schedule(&self) -> Vec<ScheduledJob> {
+        vec![self.takeout.sweep_job("0 * * * *")]
+    }
+
+    fn subscribe(&self, events: &EventBus) {
+        *self.takeout.events.write().unwrap() = Some(events.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}, time::{Duration, SystemTime}};
+
+    use axum::{body::{to_bytes, Body}, extract::Request, http::Extensions, Extension, Router};
+    use hyper::StatusCode;
+    use serde_json::json;
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use crate::{clock::TestClock, events::{EventBus, HandlerError}, Feature};
+
+    use super::{
+        crc32, Section, SectionStatus, Takeout, TakeoutFeature, TakeoutProgress,
+        UserDataProvider, UserDataProviders, TAKEOUT_PROGRESS, TAKEOUT_ROUTE
+    };
+
+    /// (name, contents) of a stored zip, read through the local headers.
+    fn unzip(archive: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut files = Vec::new();
+        let mut at: usize = 0;
+        let u16_at = |at: usize| u16::from_le_bytes([archive[at], archive[at + 1]]) as usize;
+        let u32_at = |at: usize| u32::from_le_bytes(archive[at..at + 4].try_into().unwrap());
+
+        while u32_at(at) == 0x0403_4b50 {
+            let size: usize = u32_at(at + 18) as usize;
+            let name_len: usize = u16_at(at + 26);
+            let start: usize = at + 30 + name_len;
+
+            let data: Vec<u8> = archive[start..start + size].to_vec();
+            assert_eq!(u32_at(at + 14), crc32(&data));
+
+            files.push((String::from_utf8(archive[at + 30..start].to_vec()).unwrap(), data));
+            at = start + size;
+        }
+
+        files
+    }
+
+    fn providers() -> UserDataProviders {
+        UserDataProviders(Arc::new(vec![
+            ("Notes".to_owned(), UserDataProvider::new(|scope| async move {
+                Ok(vec![
+                    Section::json("notes", json!([{ "owner": scope.user, "text": "hello" }])),
+                    Section::csv("tags", "tag,count\nwork,2\n"),
+                ])
+            })),
+            ("Billing".to_owned(), UserDataProvider::new(|_| async {
+                Err::<Vec<Section>, HandlerError>("billing database unavailable".into())
+            })),
+        ]))
+    }
+
+    fn dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("blandwork-takeout-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[tokio::test]
+    async fn test_export_with_failing_provider() {
+        let dir = dir();
+        let bus = EventBus::new();
+        let progress: Arc<Mutex<Vec<TakeoutProgress>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let received = progress.clone();
+        bus.subscribe(&TAKEOUT_PROGRESS, "test", move |event: TakeoutProgress| {
+            let received = received.clone();
+            async move {
+                received.lock().unwrap().push(event);
+                Ok::<(), HandlerError>(())
+            }
+        });
+
+        let takeout = Takeout::new("secret", &dir);
+        TakeoutFeature::new(takeout.clone()).subscribe(&bus);
+
+        let export = takeout.run("ada", &providers(), &Extensions::new()).await.unwrap();
+
+        let files = unzip(&std::fs::read(&export.path).unwrap());
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["manifest.json", "Notes/notes.json", "Notes/tags.csv"]);
+        assert!(String::from_utf8_lossy(&files[1].1).contains(r#""owner": "ada""#));
+        assert_eq!(files[2].1, b"tag,count\nwork,2\n");
+
+        let manifest: serde_json::Value = serde_json::from_slice(&files[0].1).unwrap();
+        assert_eq!(manifest["features"][0]["status"], "contributed");
+        assert_eq!(manifest["features"][1]["feature"], "Billing");
+        assert_eq!(manifest["features"][1]["status"], "failed");
+        assert_eq!(export.manifest.features[1].status, SectionStatus::Failed { error: "billing database unavailable".to_owned() });
+
+        for _ in 0..1000 {
+            if progress.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let progress = progress.lock().unwrap();
+        assert_eq!(progress.iter().map(|event| (event.feature.as_str(), event.failed, event.done)).collect::<Vec<_>>(),
+            vec![("Notes", false, 1), ("Billing", true, 2)]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_link_expiry() {
+        let dir = dir();
+        // the archives' age is their modification time
+        let clock = TestClock::at(SystemTime::now());
+        let takeout = Takeout::new("secret", &dir).with_clock(clock.clone()).ttl(Duration::from_secs(3600));
+        let router: Router = TakeoutFeature::new(takeout.clone()).supplemental().unwrap();
+
+        let export = takeout.run("ada", &providers(), &Extensions::new()).await.unwrap();
+
+        let get = |uri: String| {
+            let router = router.clone();
+            async move { router.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap() }
+        };
+
+        let response = get(export.url.clone()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/zip");
+        assert_eq!(to_bytes(response.into_body(), usize::MAX).await.unwrap(), std::fs::read(&export.path).unwrap());
+
+        // a longer validity is not signed
+        let extended = export.url.replace(&export.expires_at.to_string(), &(export.expires_at + 3600).to_string());
+        assert_eq!(get(extended).await.status(), StatusCode::FORBIDDEN);
+
+        clock.advance(Duration::from_secs(3660));
+        assert_eq!(get(export.url.clone()).await.status(), StatusCode::GONE);
+        assert!(!export.path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sweep() {
+        let dir = dir();
+        let clock = TestClock::at(SystemTime::now());
+        let takeout = Takeout::new("secret", &dir).with_clock(clock.clone()).ttl(Duration::from_secs(3600));
+
+        let export = takeout.run("ada", &providers(), &Extensions::new()).await.unwrap();
+        assert_eq!(takeout.sweep().await.unwrap(), 0);
+
+        clock.advance(Duration::from_secs(3660));
+        assert_eq!(takeout.sweep().await.unwrap(), 1);
+        assert!(!export.path.exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_one_export_per_user() {
+        let dir = dir();
+        let calls: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+        let release: Arc<Notify> = Arc::new(Notify::new());
+
+        let (counted, released) = (calls.clone(), release.clone());
+        let providers = UserDataProviders(Arc::new(vec![
+            ("Notes".to_owned(), UserDataProvider::new(move |_| {
+                let (counted, released) = (counted.clone(), released.clone());
+                async move {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    released.notified().await;
+                    Ok(vec![Section::csv("tags", "tag\n")])
+                }
+            })),
+        ]));
+
+        let takeout = Takeout::new("secret", &dir).identity(|_| Some("ada".to_owned()));
+        let router: Router = TakeoutFeature::new(takeout.clone()).supplemental().unwrap().layer(Extension(providers));
+
+        let post = || router.clone().oneshot(Request::post(TAKEOUT_ROUTE).body(Body::empty()).unwrap());
+
+        assert_eq!(post().await.unwrap().status(), StatusCode::ACCEPTED);
+
+        while calls.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let again = to_bytes(post().await.unwrap().into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&again).contains("already being prepared"));
+
+        // the next export starts once the first one is written
+        release.notify_one();
+
+        while !takeout.running.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let next = to_bytes(post().await.unwrap().into_body(), usize::MAX).await.unwrap();
+        assert!(!String::from_utf8_lossy(&next).contains("already"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_export_requires_identity() {
+        let takeout = Takeout::new("secret", dir()).identity(|parts| parts.headers.get("x-user").map(|user| user.to_str().unwrap().to_owned()));
+        let router: Router = TakeoutFeature::new(takeout).supplemental().unwrap().layer(Extension(UserDataProviders::default()));
+
+        let anonymous = router.clone().oneshot(Request::post(TAKEOUT_ROUTE).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+
+        let signed_in = router.oneshot(Request::get(TAKEOUT_ROUTE).header("x-user", "ada").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(signed_in.status(), StatusCode::OK);
+    }
+}
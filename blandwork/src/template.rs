@@ -1,18 +1,22 @@
-use std::{future::Future, pin::Pin, sync::Arc, 
+use std::{collections::BTreeMap, future::Future, pin::Pin, sync::Arc, 
     task::{Context as TaskContext, Poll}
 };
 use tokio::sync::Mutex;
 
-use hyper::Response;
-use maud::{Markup, PreEscaped};
+use hyper::{header::VARY, Method, Response, StatusCode};
+use maud::{html, Markup, PreEscaped};
 use tower::{Layer, Service};
 use axum::{
     body::{to_bytes, Body}, 
-    extract::Request, response::IntoResponse
+    extract::Request, http::{Extensions, HeaderValue}, response::IntoResponse
     // http:{Request, Response}
 };
+use axum_htmx::{HX_LOCATION, HX_REDIRECT};
 
-use crate::{Context, ContextAccessor, Feature};
+use crate::{
+    audit::DualRepresentation, livereload, nav_tree::{NavActive, NavTreePrefs, NavTrees, NAV_ACTIVE},
+    panic::PanicReport, transform::BodyTransform, Context, ContextAccessor, Feature
+};
 
 /// Defines the root frame for rendering components
 pub trait Template: Clone + Send + Sync {
@@ -27,18 +31,81 @@ pub trait Template: Clone + Send + Sync {
     fn register(&mut self, feature: &Box<dyn Feature>) {}
 
     fn page(&self, context: &Context, body: Markup) -> Markup;
+
+    /// Alternative frame `name`, chosen by `server.shells` or `Context::use_shell`,
+    /// e.g. a marketing shell for anonymous visitors. Unknown names fall back to `page`.
+    fn shell(&self, _name: &str, context: &Context, body: Markup) -> Markup {
+        self.page(context, body)
+    }
+
+    /// Page of a request whose handler panicked, answered with a 500 by `CatchPanicLayer`.
+    /// Shows the request id to quote when reporting it.
+    fn error(&self, context: &Context, report: &PanicReport) -> Markup {
+        self.page(context, html! {
+            h1 { "Something went wrong" }
+            p { "The error has been reported." }
+            p { "Request id: " code { (report.request_id) } }
+        })
+    }
 }
 
 #[derive(Clone)]
 pub struct TemplateLayer<T: Template> {
-    template: T
+    template: T,
+
+    // development only, append the live reload script to full pages
+    live_reload: bool,
+
+    // full page post-processing, in order
+    transforms: Vec<BodyTransform>,
 }
 
 impl<T> TemplateLayer<T>
 where T: Template {
     pub fn new(template: T) -> Self {
-        Self { template }
+        Self { template, live_reload: false, transforms: Vec::new() }
+    }
+
+    pub fn live_reload(mut self, enabled: bool) -> Self {
+        self.live_reload = enabled;
+        self
+    }
+
+    pub fn transforms(mut self, transforms: Vec<BodyTransform>) -> Self {
+        self.transforms = transforms;
+        self
+    }
+
+    /// The full page this layer serves for `content`, without a request: layout tests
+    /// render against a context of their own.
+    ///
+    /// ```ignore
+    /// let accessor = ContextAccessor::from_request(&Request::get("/orders").body(Body::empty())?);
+    /// let html = TemplateLayer::new(AppTemplate).render_shell(&accessor.context().await, html!{ p { "body" } });
+    /// ```
+    pub fn render_shell(&self, context: &Context, content: Markup) -> Markup {
+        render_shell(&self.template, context, content, &self.transforms, self.live_reload)
+    }
+}
+
+/// `template` wrapped around `content` and post-processed as a served page.
+fn render_shell<T: Template>(template: &T, context: &Context, content: Markup, transforms: &[BodyTransform], live_reload: bool) -> Markup {
+    let page: Markup = match context.shell() {
+        Some(name) => template.shell(&name, context, content),
+        None => template.page(context, content),
+    };
+
+    let mut html: String = page.into_string();
+
+    for transform in transforms.iter() {
+        html = transform(context, html);
+    }
+
+    if live_reload {
+        html = livereload::inject_script(html);
     }
+
+    PreEscaped(html)
 }
 
 impl<S, T> Layer<S> for TemplateLayer<T>
@@ -49,6 +116,8 @@ where T: Template {
         TemplateService { 
             inner, 
             template: self.template.clone(),
+            live_reload: self.live_reload,
+            transforms: self.transforms.clone(),
         }
     }
 }
@@ -56,7 +125,9 @@ where T: Template {
 #[derive(Clone)]
 pub struct TemplateService<S, T> {
     inner: S,
-    template: T
+    template: T,
+    live_reload: bool,
+    transforms: Vec<BodyTransform>,
 }
 
 impl<S, T> Service<Request> for TemplateService<S, T>
@@ -84,43 +155,179 @@ where
 
         let accessor: ContextAccessor = extensions.get::<ContextAccessor>().unwrap().clone();
 
+        // navigation trees of the app, their ancestry is resolved from the request path
+        let nav: Option<(NavTrees, Extensions, String)> = req.extensions().get::<NavTrees>()
+            .filter(|trees| !trees.is_empty())
+            .map(|trees| (trees.clone(), req.extensions().clone(), req.uri().path().to_owned()));
+
+        let live_reload: bool = self.live_reload;
+        let transforms: Vec<BodyTransform> = self.transforms.clone();
+
+        // axum answers HEAD with the GET handler, which sees a GET. The page is built as for
+        // a GET, axum's route sets the Content-Length from it before dropping the body
+        // (a page the `ResponseCache` stored answers before reaching us)
+        if req.method() == Method::HEAD {
+            *req.method_mut() = Method::GET;
+        }
+
+        // answered by `OptionsLayer` from the route's 405, no page is rendered for it
+        let options: bool = req.method() == Method::OPTIONS;
+
         let inner = self.inner.call(req);
         
         Box::pin(async move {
             let mut response: Response<axum::body::Body> = inner.await?;
 
-            let context: Context = accessor.context().await;
+            if options {
+                return Ok(response);
+            }
+
+            // rendered before the template locks the context, providers may use the request's
+            let boosted: bool = accessor.context().await.is_boosted();
+            let nav_trees: Option<BTreeMap<String, Markup>> = match nav.as_ref() {
+                Some((trees, extensions, path)) if !boosted && !is_redirect(&response) => {
+                    let prefs: NavTreePrefs = accessor.context().await.typed_cookie::<NavTreePrefs>().unwrap_or_default();
+                    Some(trees.render(path, &prefs, extensions).await)
+                },
+                _ => None,
+            };
+
+            let mut context: Context = accessor.context().await;
 
             let template = template.lock().await;
             
             tracing::info!("Framework request end...");
 
-            if context.is_boosted() {
+            if template.ignored() || is_redirect(&response) {
                 return Ok(response);
             }
 
-            if template.ignored() {
-                return Ok(response);
+            if context.is_boosted() {
+                // the trees stay on the page, their active node follows the navigation
+                if let Some((_, _, path)) = nav {
+                    context.add_trigger(NAV_ACTIVE.to_owned(), NavActive { route: path });
+                }
+
+                return Ok(dual_representation(response));
             }
 
+            if let Some(nav_trees) = nav_trees {
+                context.set_nav_trees(nav_trees);
+            }
+
+            // the page keeps the handler's status, e.g. a 404 from `App::apply_fallback_with`
+            let status: StatusCode = response.status();
             let body: Body = response.into_body();
 
             // read the entire inner response body into bytes
             // then convert to string and pass into page template
             response = match to_bytes(body, usize::MAX).await {
                 Ok(s) => {
-                    let new_body = template.page(&context,
-                    PreEscaped(String::from_utf8(s.to_vec()).unwrap()));
-                    
-                    new_body.into_response()
+                    let content: Markup = PreEscaped(String::from_utf8(s.to_vec()).unwrap());
+
+                    (status, render_shell(&*template, &context, content, &transforms, live_reload)).into_response()
                 },
                 Err(_e) => {
                     Response::new("FAILED!".into())
                 }
             };
 
-            Ok(response)
+            Ok(dual_representation(response))
         })
     }
 
 }
+
+/// Redirects, including HTMX's, are never wrapped into a page, see `HtmxRedirect`.
+fn is_redirect(response: &Response<Body>) -> bool {
+    response.status().is_redirection()
+        || response.headers().contains_key(HX_REDIRECT)
+        || response.headers().contains_key(HX_LOCATION)
+}
+
+/// The same route answers with a page or a fragment depending on HTMX headers,
+/// caches must key on them. Boosted requests get their own shell, so both count.
+fn dual_representation(mut response: Response<Body>) -> Response<Body> {
+    response.headers_mut().append(VARY, HeaderValue::from_static("HX-Request, HX-Boosted"));
+    response.extensions_mut().insert(DualRepresentation);
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, middleware::map_request, response::IntoResponse, routing::get, Extension, Router};
+    use hyper::header::VARY;
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{config::Shells, test::router, App, Config, Context, ContextAccessor, CurrentUser, Feature, Template};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body .default { (body) } } }
+        }
+
+        fn shell(&self, name: &str, context: &Context, body: Markup) -> Markup {
+            match name {
+                "marketing" => html! { html { body .marketing { (body) } } },
+                "app" => html! { html { body .app { p { (context.user().unwrap_or_default()) } (body) } } },
+                _ => self.page(context, body),
+            }
+        }
+    }
+
+    struct Pages;
+
+    impl Feature for Pages {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new()
+                .route("/home", get(|| async { "home" }))
+                .route("/print", get(|Extension(accessor): Extension<ContextAccessor>| async move {
+                    accessor.context().await.use_shell("print");
+                    "print".into_response()
+                })))
+        }
+    }
+
+    async fn page(path: &str, user: Option<&str>) -> String {
+        let mut config = Config::default();
+        config.server.shells = Shells { anonymous_shell: Some("marketing".to_owned()), authenticated_shell: Some("app".to_owned()) };
+
+        let mut app = App::new(config, TestTemplate).register_feature(Pages);
+
+        // the app's authentication, outside of the routes
+        let user: Option<String> = user.map(str::to_owned);
+        let router = router(&app.build()).layer(map_request(move |mut request: Request| {
+            let user = user.clone();
+            async move {
+                if let Some(user) = user {
+                    request.extensions_mut().insert(CurrentUser(user));
+                }
+                request
+            }
+        }));
+
+        let response = router.oneshot(Request::get(path).body(Body::empty()).unwrap()).await.unwrap();
+        String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shell_by_authentication() {
+        assert!(page("/home", None).await.contains(r#"<body class="marketing">home"#));
+        assert!(page("/home", Some("ada")).await.contains(r#"<body class="app"><p>ada</p>home"#));
+
+        // the handler's choice wins, an unknown shell is the template's page
+        assert!(page("/print", Some("ada")).await.contains(r#"<body class="default">print"#));
+    }
+
+    #[tokio::test]
+    async fn test_vary_on_htmx_headers() {
+        let app = App::new(Config::default(), TestTemplate).register_feature(Pages).build();
+        let response = router(&app).oneshot(Request::get("/home").body(Body::empty()).unwrap()).await.unwrap();
+
+        assert!(response.headers().get_all(VARY).iter().any(|vary| vary == "HX-Request, HX-Boosted"));
+    }
+}
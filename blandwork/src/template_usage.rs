@@ -0,0 +1,469 @@
+//! Which template files are still needed: the include/extends graph of the sources,
+//! the templates rendered at runtime, and what neither of them reaches.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fmt::Display, io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime
+};
+
+use crate::hx_check::TemplateSource;
+
+// file extensions picked up by `TemplateAudit::load_dir`
+const TEMPLATE_EXTENSIONS: [&str; 4] = ["html", "jinja", "j2", "txt"];
+
+// by template and block
+type Uses = Arc<Mutex<HashMap<(String, Option<String>), TemplateUse>>>;
+
+/// How often a template (or one of its blocks) was rendered, and for which route last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateUse {
+    pub template: String,
+    pub block: Option<String>,
+    pub count: u64,
+    pub last_used: SystemTime,
+    pub route: String,
+}
+
+/// Runtime record of rendered templates and blocks, filled by the application's
+/// template loader in development and read by `TemplateAudit::usage`.
+#[derive(Clone, Default)]
+pub struct TemplateUsage {
+    uses: Uses,
+}
+
+impl TemplateUsage {
+    /// `block` is None for the template as a whole.
+    pub fn record(&self, template: &str, block: Option<&str>, route: &str) {
+        let key: (String, Option<String>) = (template.to_owned(), block.map(|block| block.to_owned()));
+        let mut uses = self.uses.lock().unwrap();
+
+        let entry = uses.entry(key).or_insert_with(|| TemplateUse {
+            template: template.to_owned(),
+            block: block.map(|block| block.to_owned()),
+            count: 0,
+            last_used: SystemTime::now(),
+            route: route.to_owned(),
+        });
+
+        entry.count += 1;
+        entry.last_used = SystemTime::now();
+        entry.route = route.to_owned();
+    }
+
+    /// Every recorded use, by template then block.
+    pub fn uses(&self) -> Vec<TemplateUse> {
+        let mut uses: Vec<TemplateUse> = self.uses.lock().unwrap().values().cloned().collect();
+        uses.sort_by(|a, b| (&a.template, &a.block).cmp(&(&b.template, &b.block)));
+        uses
+    }
+
+    fn rendered(&self, template: &str, block: Option<&str>) -> bool {
+        self.uses.lock().unwrap().contains_key(&(template.to_owned(), block.map(|block| block.to_owned())))
+    }
+}
+
+/// A template statement pointing at another template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reference {
+    /// `include`, `extends`, `import` or `from .. import` of a literal name.
+    Literal(String),
+
+    /// The name is computed (`{% include "widgets/" ~ kind ~ ".html" %}`), kept verbatim.
+    Dynamic(String),
+}
+
+/// A string literal at the start of `expression` and what follows it.
+fn literal(expression: &str) -> Option<(String, &str)> {
+    let quote: char = expression.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let end: usize = expression[1..].find(quote)? + 1;
+
+    Some((expression[1..end].to_owned(), expression[end + 1..].trim_start()))
+}
+
+/// The template references of one `{% .. %}` statement body.
+fn statement_references(statement: &str) -> Vec<Reference> {
+    let statement: &str = statement.trim_matches('-').trim();
+    let (keyword, rest) = statement.split_once(char::is_whitespace).unwrap_or((statement, ""));
+    let rest: &str = rest.trim();
+
+    if !matches!(keyword, "include" | "extends" | "import" | "from") {
+        return Vec::new();
+    }
+
+    // modifiers allowed after a literal name
+    let trailing = |after: &str| {
+        after.is_empty() || ["ignore", "with", "without", "as", "import"].iter().any(|modifier| after.starts_with(modifier))
+    };
+
+    // include ["a.html", "b.html"]
+    if let Some(list) = rest.strip_prefix('[') {
+        if let Some((items, after)) = list.split_once(']') {
+            let names: Option<Vec<String>> = items.split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(|item| literal(item).filter(|(_, after)| after.is_empty()).map(|(name, _)| name))
+                .collect();
+
+            if let (Some(names), true) = (names, trailing(after.trim())) {
+                return names.into_iter().map(Reference::Literal).collect();
+            }
+        }
+
+        return vec![Reference::Dynamic(rest.to_owned())];
+    }
+
+    match literal(rest) {
+        Some((name, after)) if trailing(after) => vec![Reference::Literal(name)],
+        _ => vec![Reference::Dynamic(rest.to_owned())],
+    }
+}
+
+/// Bodies of the `{% .. %}` statements of a template source.
+fn statements(source: &str) -> Vec<&str> {
+    let mut statements: Vec<&str> = Vec::new();
+    let mut rest: &str = source;
+
+    while let Some(start) = rest.find("{%") {
+        let Some(end) = rest[start..].find("%}") else {
+            break;
+        };
+
+        statements.push(&rest[start + 2..start + end]);
+        rest = &rest[start + end + 2..];
+    }
+
+    statements
+}
+
+/// Templates a source includes, extends or imports, in order.
+pub fn references(source: &str) -> Vec<Reference> {
+    statements(source).into_iter().flat_map(statement_references).collect()
+}
+
+/// Names of the `{% block .. %}` declared by a source.
+pub fn blocks(source: &str) -> Vec<String> {
+    statements(source)
+        .into_iter()
+        .map(|statement| statement.trim_matches('-').trim())
+        .filter_map(|statement| statement.strip_prefix("block "))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .map(|name| name.to_owned())
+        .collect()
+}
+
+/// A computed template name and the templates it might resolve to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicInclude {
+    pub template: String,
+    pub expression: String,
+    pub candidates: Vec<String>,
+}
+
+/// Leftovers of the template directory, nothing is deleted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateReport {
+    /// Not a root, never rendered and no template refers to it.
+    pub unreferenced: Vec<String>,
+
+    /// Only referenced by templates which are unreferenced (or orphans) themselves.
+    pub transitive_orphans: Vec<String>,
+
+    /// Unreached except possibly through a dynamic include, can't be decided statically.
+    pub unknown: Vec<String>,
+
+    pub dynamic: Vec<DynamicInclude>,
+
+    /// (template, block) of used templates never rendered, only with recorded usage.
+    pub unused_blocks: Vec<(String, String)>,
+}
+
+impl TemplateReport {
+    pub fn is_clean(&self) -> bool {
+        self.unreferenced.is_empty() && self.transitive_orphans.is_empty() && self.unused_blocks.is_empty()
+    }
+}
+
+impl Display for TemplateReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "unreferenced templates ({})", self.unreferenced.len())?;
+        for template in self.unreferenced.iter() {
+            writeln!(f, "  {template}")?;
+        }
+
+        writeln!(f, "referenced only by unreferenced templates ({})", self.transitive_orphans.len())?;
+        for template in self.transitive_orphans.iter() {
+            writeln!(f, "  {template}")?;
+        }
+
+        writeln!(f, "blocks never rendered ({})", self.unused_blocks.len())?;
+        for (template, block) in self.unused_blocks.iter() {
+            writeln!(f, "  {template}: {block}")?;
+        }
+
+        for template in self.unknown.iter() {
+            writeln!(f, "  {template}: unknown — dynamic include")?;
+        }
+
+        for include in self.dynamic.iter() {
+            writeln!(f, "  {}: dynamic include {}", include.template, include.expression)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Cross-references the template sources with the declared entry points (feature
+/// templates, the shell) and the recorded usage.
+///
+/// ```ignore
+/// #[test]
+/// fn template_leftovers() {
+///     let report = TemplateAudit::load_dir("templates").unwrap()
+///         .root("shell.html")
+///         .root("invoices/list.html")
+///         .run();
+///
+///     println!("{report}");
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct TemplateAudit {
+    sources: BTreeMap<String, TemplateSource>,
+    roots: BTreeSet<String>,
+    usage: Option<TemplateUsage>,
+}
+
+impl TemplateAudit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every template below `dir`, named by their path relative to it (`partials/nav.html`).
+    pub fn load_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir: &Path = dir.as_ref();
+        let mut audit: TemplateAudit = Self::new();
+        let mut pending: VecDeque<std::path::PathBuf> = VecDeque::from([dir.to_path_buf()]);
+
+        while let Some(current) = pending.pop_front() {
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+
+                if path.is_dir() {
+                    pending.push_back(path);
+                    continue;
+                }
+
+                let template: bool = path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .map(|extension| TEMPLATE_EXTENSIONS.contains(&extension))
+                    .unwrap_or(false);
+
+                if !template {
+                    continue;
+                }
+
+                let name: String = path.strip_prefix(dir)
+                    .unwrap_or(&path)
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<String>>()
+                    .join("/");
+
+                audit = audit.source(&name, TemplateSource::load(&path)?);
+            }
+        }
+
+        Ok(audit)
+    }
+
+    pub fn source(mut self, name: &str, source: TemplateSource) -> Self {
+        self.sources.insert(name.to_owned(), source);
+        self
+    }
+
+    /// A template rendered by name from code: a feature's templates, the shell.
+    pub fn root(mut self, name: &str) -> Self {
+        self.roots.insert(name.to_owned());
+        self
+    }
+
+    pub fn usage(mut self, usage: TemplateUsage) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    pub fn run(&self) -> TemplateReport {
+        let mut report: TemplateReport = TemplateReport::default();
+
+        // literal edges, and the dynamic includes of every template
+        let mut edges: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        let mut dynamic: Vec<(&str, String)> = Vec::new();
+
+        for (name, source) in self.sources.iter() {
+            for reference in references(&source.source) {
+                match reference {
+                    Reference::Literal(target) => edges.entry(name.as_str()).or_default().push(target),
+                    Reference::Dynamic(expression) => dynamic.push((name.as_str(), expression)),
+                }
+            }
+        }
+
+        // live: reachable from a root or a rendered template
+        let used = |name: &str| self.usage.as_ref().map(|usage| usage.rendered(name, None)).unwrap_or(false);
+
+        let mut live: BTreeSet<&str> = BTreeSet::new();
+        let mut pending: VecDeque<&str> = self.sources.keys()
+            .map(|name| name.as_str())
+            .filter(|name| self.roots.contains(*name) || used(name))
+            .collect();
+
+        while let Some(name) = pending.pop_front() {
+            if !live.insert(name) {
+                continue;
+            }
+
+            for target in edges.get(name).into_iter().flatten() {
+                if let Some((target, _)) = self.sources.get_key_value(target) {
+                    pending.push_back(target.as_str());
+                }
+            }
+        }
+
+        // dynamic includes of live templates may reach anything matching their literal parts
+        for (template, expression) in dynamic.iter() {
+            let prefix: Option<String> = literal(expression).map(|(prefix, _)| prefix);
+            let suffix: Option<String> = expression.rsplit(['~', '+']).next()
+                .map(|last| last.trim())
+                .and_then(|last| literal(last).filter(|(_, after)| after.is_empty()).map(|(suffix, _)| suffix));
+
+            let candidates: Vec<String> = match (prefix.as_deref(), suffix.as_deref()) {
+                (None, None) => Vec::new(),
+                (prefix, suffix) => self.sources.keys()
+                    .filter(|name| !live.contains(name.as_str()))
+                    .filter(|name| name.starts_with(prefix.unwrap_or_default()) && name.ends_with(suffix.unwrap_or_default()))
+                    .cloned()
+                    .collect(),
+            };
+
+            if live.contains(template) {
+                report.unknown.extend(candidates.iter().cloned());
+            }
+
+            report.dynamic.push(DynamicInclude { template: template.to_string(), expression: expression.clone(), candidates });
+        }
+
+        report.unknown.sort();
+        report.unknown.dedup();
+
+        for name in self.sources.keys().filter(|name| !live.contains(name.as_str()) && !report.unknown.contains(name)) {
+            let referenced: bool = edges.iter().any(|(from, targets)| *from != name.as_str() && targets.contains(name));
+
+            match referenced {
+                true => report.transitive_orphans.push(name.clone()),
+                false => report.unreferenced.push(name.clone()),
+            }
+        }
+
+        if let Some(usage) = self.usage.as_ref() {
+            for name in live.iter() {
+                for block in blocks(&self.sources[*name].source) {
+                    if !usage.rendered(name, Some(&block)) {
+                        report.unused_blocks.push((name.to_string(), block));
+                    }
+                }
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use uuid::Uuid;
+
+    use super::{blocks, references, DynamicInclude, Reference, TemplateAudit, TemplateUsage};
+
+    #[test]
+    fn test_references() {
+        let source = r#"
+            {% extends "shell.html" %}
+            {% import 'macros.html' as macros %}
+            {% from "forms.html" import field %}
+            {%- include "partials/nav.html" ignore missing -%}
+            {% include ["a.html", "b.html"] %}
+            {% include "widgets/" ~ kind ~ ".html" %}
+            {% include template_name %}
+            {% block content %}{% endblock %}
+        "#;
+
+        assert_eq!(references(source), vec![
+            Reference::Literal("shell.html".to_owned()),
+            Reference::Literal("macros.html".to_owned()),
+            Reference::Literal("forms.html".to_owned()),
+            Reference::Literal("partials/nav.html".to_owned()),
+            Reference::Literal("a.html".to_owned()),
+            Reference::Literal("b.html".to_owned()),
+            Reference::Dynamic(r#""widgets/" ~ kind ~ ".html""#.to_owned()),
+            Reference::Dynamic("template_name".to_owned()),
+        ]);
+
+        assert_eq!(blocks(source), vec!["content"]);
+    }
+
+    fn fixture(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blandwork-templates-{}", Uuid::new_v4()));
+
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        dir
+    }
+
+    #[test]
+    fn test_report() {
+        let dir = fixture(&[
+            ("shell.html", r#"<html>{% include "partials/nav.html" %}{% block content %}{% endblock %}</html>"#),
+            ("partials/nav.html", "<nav></nav>"),
+            ("pages/home.html", r#"{% extends "shell.html" %}{% block content %}{% include "widgets/" ~ kind ~ ".html" %}{% endblock %}{% block sidebar %}{% endblock %}"#),
+            ("widgets/chart.html", "<canvas></canvas>"),
+            ("old/report.html", r#"{% include "old/report_row.html" %}"#),
+            ("old/report_row.html", "<tr></tr>"),
+            ("notes.md", "not a template"),
+        ]);
+
+        let usage = TemplateUsage::default();
+        usage.record("pages/home.html", None, "/");
+        usage.record("pages/home.html", Some("content"), "/");
+        usage.record("shell.html", Some("content"), "/");
+
+        let report = TemplateAudit::load_dir(&dir).unwrap()
+            .root("shell.html")
+            .usage(usage)
+            .run();
+
+        assert_eq!(report.unreferenced, vec!["old/report.html"]);
+        assert_eq!(report.transitive_orphans, vec!["old/report_row.html"]);
+        assert_eq!(report.unknown, vec!["widgets/chart.html"]);
+        assert_eq!(report.dynamic, vec![DynamicInclude {
+            template: "pages/home.html".to_owned(),
+            expression: r#""widgets/" ~ kind ~ ".html""#.to_owned(),
+            candidates: vec!["widgets/chart.html".to_owned()],
+        }]);
+        assert_eq!(report.unused_blocks, vec![("pages/home.html".to_owned(), "sidebar".to_owned())]);
+
+        let printed = report.to_string();
+        assert!(printed.contains("widgets/chart.html: unknown — dynamic include"));
+        assert!(!report.is_clean());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,610 @@
+//! Helpers for exercising an application from tests.
+
+use std::collections::{HashSet, VecDeque};
+
+use axum::{body::{to_bytes, Body}, extract::Request, response::Response, Router};
+use axum_htmx::{HX_BOOSTED, HX_REQUEST};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use tower::ServiceExt;
+
+use crate::{recording::RequestRecord, template::Template, App, Features, NoPool};
+
+pub use crate::clock::TestClock;
+
+/// Attributes that reference another URL.
+const LINK_ATTRIBUTES: [&str; 8] = [
+    "href", "src", "action", "hx-get", "hx-post", "hx-put", "hx-patch", "hx-delete"
+];
+
+/// A URL found in rendered HTML along with where it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoundLink {
+    pub tag: String,
+    pub attribute: String,
+    pub url: String,
+}
+
+impl FoundLink {
+    /// Links a user (or HTMX) navigates with a GET, as opposed to assets and mutations.
+    pub fn is_navigation(&self) -> bool {
+        match self.attribute.as_str() {
+            "href" => self.tag == "a" || self.tag == "area",
+            "hx-get" => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrokenLink {
+    pub page: String,
+    pub url: String,
+    pub reason: String,
+}
+
+/// Every path the application answers.
+/// Patterns accept axum style parameters (`/items/:id`, `/items/{id}`) and wildcards (`/*rest`, `/{*rest}`).
+#[derive(Debug, Clone, Default)]
+pub struct RouteManifest {
+    patterns: Vec<String>,
+    mounts: Vec<String>,
+    allowed: Vec<String>,
+}
+
+impl RouteManifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, pattern: &str) -> Self {
+        self.patterns.push(pattern.to_owned());
+        self
+    }
+
+    /// Static directories (`/web`) where any path below the prefix is valid.
+    pub fn mount(mut self, prefix: &str) -> Self {
+        self.mounts.push(prefix.trim_end_matches('/').to_owned());
+        self
+    }
+
+    /// Intentionally unchecked URL prefixes (dynamic or proxied paths).
+    pub fn allow(mut self, prefix: &str) -> Self {
+        self.allowed.push(prefix.to_owned());
+        self
+    }
+
+    pub fn is_allowed(&self, path: &str) -> bool {
+        self.allowed.iter().any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let path: &str = strip_query(path);
+
+        self.patterns.iter().any(|pattern| pattern_matches(pattern, path))
+            || self.mounts.iter().any(|mount| path == mount || path.starts_with(&format!("{mount}/")))
+    }
+}
+
+fn strip_query(url: &str) -> &str {
+    let end: usize = url.find(['?', '#']).unwrap_or(url.len());
+    &url[..end]
+}
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let pattern_segments: Vec<&str> = pattern.split('/').collect();
+    let path_segments: Vec<&str> = path.split('/').collect();
+
+    for (index, segment) in pattern_segments.iter().enumerate() {
+        if segment.starts_with('*') || segment.starts_with("{*") {
+            // wildcards need at least one segment
+            return path_segments.len() > index && !path_segments[index..].concat().is_empty();
+        }
+
+        let Some(actual) = path_segments.get(index) else {
+            return false;
+        };
+
+        let parameter: bool = segment.starts_with(':') || (segment.starts_with('{') && segment.ends_with('}'));
+
+        match parameter {
+            true if actual.is_empty() => return false,
+            true => continue,
+            false if segment != actual => return false,
+            false => continue,
+        }
+    }
+
+    pattern_segments.len() == path_segments.len()
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+/// Pull every URL carrying attribute out of an HTML document or fragment.
+pub fn extract_links(html: &str) -> Vec<FoundLink> {
+    let mut links: Vec<FoundLink> = Vec::new();
+    let bytes: &[u8] = html.as_bytes();
+    let mut index: usize = 0;
+
+    while let Some(offset) = html[index..].find('<') {
+        index += offset + 1;
+
+        if html[index..].starts_with("!--") {
+            index = match html[index..].find("-->") {
+                Some(end) => index + end + 3,
+                None => html.len(),
+            };
+            continue;
+        }
+
+        // tag name, closing tags and doctype are skipped
+        let name_end: usize = html[index..]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .map(|end| index + end)
+            .unwrap_or(html.len());
+
+        let tag: String = html[index..name_end].to_ascii_lowercase();
+        index = name_end;
+
+        if tag.is_empty() || tag.starts_with('!') {
+            continue;
+        }
+
+        // attributes until the end of the tag
+        loop {
+            while index < bytes.len() && (bytes[index].is_ascii_whitespace() || bytes[index] == b'/') {
+                index += 1;
+            }
+
+            if index >= bytes.len() || bytes[index] == b'>' {
+                break;
+            }
+
+            let attribute_end: usize = html[index..]
+                .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+                .map(|end| index + end)
+                .unwrap_or(html.len());
+
+            let attribute: String = html[index..attribute_end].to_ascii_lowercase();
+            index = attribute_end;
+
+            while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+                index += 1;
+            }
+
+            if index >= bytes.len() || bytes[index] != b'=' {
+                // attribute without a value
+                if attribute.is_empty() {
+                    index += 1;
+                }
+                continue;
+            }
+
+            index += 1;
+
+            while index < bytes.len() && bytes[index].is_ascii_whitespace() {
+                index += 1;
+            }
+
+            let value: &str = match bytes.get(index) {
+                Some(quote @ (b'"' | b'\'')) => {
+                    let start: usize = index + 1;
+                    let end: usize = html[start..]
+                        .find(*quote as char)
+                        .map(|end| start + end)
+                        .unwrap_or(html.len());
+
+                    index = (end + 1).min(html.len());
+                    &html[start..end]
+                },
+                _ => {
+                    let start: usize = index;
+                    index = html[start..]
+                        .find(|c: char| c.is_whitespace() || c == '>')
+                        .map(|end| start + end)
+                        .unwrap_or(html.len());
+
+                    &html[start..index]
+                }
+            };
+
+            if LINK_ATTRIBUTES.contains(&attribute.as_str()) {
+                links.push(FoundLink {
+                    tag: tag.clone(),
+                    attribute,
+                    url: decode_entities(value),
+                });
+            }
+        }
+    }
+
+    links
+}
+
+/// Resolve a link against the page it was found on.
+/// External links (other schemes, protocol relative, mailto, fragments only) resolve to `None`.
+pub fn resolve(page: &str, url: &str) -> Option<String> {
+    let url: &str = url.trim();
+
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") {
+        return None;
+    }
+
+    // scheme: http:, https:, mailto:, javascript:, data: ...
+    if let Some(colon) = url.find(':') {
+        if !url[..colon].contains('/') && !url[..colon].contains('?') {
+            return None;
+        }
+    }
+
+    let url: &str = strip_query(url);
+
+    if url.is_empty() {
+        // only a query string, same page
+        return Some(strip_query(page).to_owned());
+    }
+
+    let joined: String = match url.starts_with('/') {
+        true => url.to_owned(),
+        false => {
+            let page: &str = strip_query(page);
+            let directory: &str = &page[..page.rfind('/').map(|i| i + 1).unwrap_or(0)];
+            format!("{directory}{url}")
+        }
+    };
+
+    // normalize . and .. segments
+    let mut segments: Vec<&str> = Vec::new();
+    let trailing: bool = joined.ends_with('/') || joined.ends_with("/.") || joined.ends_with("/..");
+
+    for segment in joined.split('/') {
+        match segment {
+            "" | "." => {},
+            ".." => { segments.pop(); },
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut path: String = format!("/{}", segments.join("/"));
+
+    if trailing && path != "/" {
+        path.push('/');
+    }
+
+    Some(path)
+}
+
+/// Report internal links in `html` that no mounted route or static mount would answer.
+/// Relative links are resolved against `/`, use `check_page_links` for pages elsewhere.
+pub fn check_links(html: &str, manifest: &RouteManifest) -> Vec<BrokenLink> {
+    check_page_links("/", html, manifest)
+}
+
+pub fn check_page_links(page: &str, html: &str, manifest: &RouteManifest) -> Vec<BrokenLink> {
+    extract_links(html)
+        .into_iter()
+        .filter_map(|link| {
+            let path: String = resolve(page, &link.url)?;
+
+            if manifest.is_allowed(&path) || manifest.matches(&path) {
+                return None;
+            }
+
+            Some(BrokenLink {
+                page: page.to_owned(),
+                url: link.url,
+                reason: format!("no route matches {path} ({} on <{}>)", link.attribute, link.tag),
+            })
+        })
+        .collect()
+}
+
+/// The router of a built application, for driving it in process, `App::into_router` without
+/// giving the app up.
+pub fn router<P, F, T: Template>(app: &App<P, F, T>) -> Router {
+    app.router.clone()
+}
+
+/// Requests against a built application in process, no server or network involved.
+///
+/// ```ignore
+/// let client = TestClient::ephemeral(App::new(Config::default(), template).register_feature(Invoices));
+/// let response = client.post_form("/invoices", "amount=42").await;
+/// assert_eq!(response.status(), StatusCode::SEE_OTHER);
+/// ```
+#[derive(Clone)]
+pub struct TestClient {
+    router: Router,
+}
+
+impl TestClient {
+    pub fn new(router: Router) -> Self {
+        Self { router }
+    }
+
+    /// Builds the app with `App::ephemeral`, the preset of CI integration tests.
+    pub fn ephemeral<T: Template + 'static>(app: App<NoPool, Features, T>) -> Self {
+        Self::new(app.ephemeral().build().router)
+    }
+
+    pub async fn request(&self, request: Request) -> Response {
+        match self.router.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        }
+    }
+
+    pub async fn get(&self, path: &str) -> Response {
+        self.request(Request::get(path).body(Body::empty()).unwrap()).await
+    }
+
+    /// A GET as a boosted htmx navigation sends it, answered with a fragment.
+    pub async fn htmx(&self, path: &str) -> Response {
+        let mut request: Request = Request::get(path).body(Body::empty()).unwrap();
+        request.headers_mut().insert(HX_REQUEST, HeaderValue::from_static("true"));
+        request.headers_mut().insert(HX_BOOSTED, HeaderValue::from_static("true"));
+
+        self.request(request).await
+    }
+
+    /// POST of an url encoded form, `name=value&other=value`.
+    pub async fn post_form(&self, path: &str, form: &str) -> Response {
+        let request: Request = Request::post(path)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_owned()))
+            .unwrap();
+
+        self.request(request).await
+    }
+
+    /// The body of a response as text.
+    pub async fn text(response: Response) -> String {
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap_or_default();
+        String::from_utf8_lossy(&body).into_owned()
+    }
+}
+
+/// Re-issues a recorded request (`RequestRecord::load`) against the router, for stepping
+/// through a reported bug or turning it into a regression test.
+pub async fn replay(router: &Router, record: &RequestRecord) -> Response {
+    match router.clone().oneshot(record.to_request()).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    }
+}
+
+/// Follow internal navigation links (`a href`, `hx-get`) from `start` through the router,
+/// up to `depth` hops, and report every link which did not answer with a success or redirect.
+/// `hx-get` links are requested as HTMX does, fragment only routes answer them.
+pub async fn crawl(router: Router, start: &str, depth: usize) -> Vec<BrokenLink> {
+    let mut broken: Vec<BrokenLink> = Vec::new();
+    let mut visited: HashSet<String> = HashSet::new();
+    let mut pending: VecDeque<(String, String, usize, bool)> = VecDeque::new();
+
+    pending.push_back((start.to_owned(), start.to_owned(), 0, false));
+
+    while let Some((referrer, path, hops, htmx)) = pending.pop_front() {
+        if !visited.insert(path.clone()) {
+            continue;
+        }
+
+        let mut request: Request = match Request::get(path.as_str()).body(Body::empty()) {
+            Ok(request) => request,
+            Err(e) => {
+                broken.push(BrokenLink { page: referrer, url: path, reason: format!("invalid request: {e}") });
+                continue;
+            }
+        };
+
+        if htmx {
+            request.headers_mut().insert(HX_REQUEST, HeaderValue::from_static("true"));
+        }
+
+        let response = match router.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        };
+        let status = response.status();
+
+        if !(status.is_success() || status.is_redirection()) {
+            broken.push(BrokenLink {
+                page: referrer,
+                url: path,
+                reason: format!("responded with {status}"),
+            });
+            continue;
+        }
+
+        let html: bool = response.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.contains("text/html"))
+            .unwrap_or(false);
+
+        if !html || hops >= depth {
+            continue;
+        }
+
+        let body = match to_bytes(response.into_body(), usize::MAX).await {
+            Ok(body) => body,
+            Err(e) => {
+                broken.push(BrokenLink { page: referrer, url: path, reason: format!("unreadable body: {e}") });
+                continue;
+            }
+        };
+        let body: String = String::from_utf8_lossy(&body).into_owned();
+
+        for link in extract_links(&body).into_iter().filter(FoundLink::is_navigation) {
+            if let Some(next) = resolve(&path, &link.url) {
+                pending.push_back((path.clone(), next, hops + 1, link.attribute.starts_with("hx-")));
+            }
+        }
+    }
+
+    broken
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::Extensions, routing::{get, post}, Extension, Router};
+    use hyper::StatusCode;
+    use maud::{html, Markup};
+
+    use crate::{config::JobQueue, jobs::{JobHandler, Jobs}, App, Config, Context, Feature, Template};
+
+    use super::{check_links, check_page_links, crawl, extract_links, resolve, RouteManifest, TestClient};
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { (body) } } }
+        }
+    }
+
+    struct Invoices;
+
+    impl Feature for Invoices {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new()
+                .route("/invoices", post(|Extension(jobs): Extension<Jobs>| async move {
+                    jobs.enqueue("send_invoice", "42").await.unwrap();
+                    html! { "queued" }
+                }))
+                .route("/outbox", get(|Extension(jobs): Extension<Jobs>| async move {
+                    let sent: usize = jobs.run_due("test", &JobQueue::default(), &Extensions::new()).await.unwrap();
+                    html! { (sent) }
+                })))
+        }
+
+        fn jobs(&self) -> Vec<JobHandler> {
+            vec![JobHandler::new("send_invoice", |_| async { Ok(()) })]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ephemeral_client() {
+        let client = TestClient::ephemeral(App::new(Config::default(), TestTemplate).register_feature(Invoices));
+
+        let response = client.post_form("/invoices", "amount=42").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // queued in memory, run in process
+        assert_eq!(TestClient::text(client.htmx("/outbox").await).await, "1");
+        assert_eq!(TestClient::text(client.get("/outbox").await).await, "<html><body>0</body></html>");
+    }
+
+    fn manifest() -> RouteManifest {
+        RouteManifest::new()
+            .route("/")
+            .route("/items")
+            .route("/items/{id}")
+            .route("/items/:id/edit")
+            .route("/files/*path")
+            .mount("/web")
+    }
+
+    #[test]
+    fn test_match_params() {
+        let manifest = manifest();
+
+        assert!(manifest.matches("/items/42"));
+        assert!(manifest.matches("/items/42/edit"));
+        assert!(manifest.matches("/files/a/b/c.txt"));
+        assert!(!manifest.matches("/items/42/delete"));
+        assert!(!manifest.matches("/files/"));
+    }
+
+    #[test]
+    fn test_match_trailing_slash() {
+        let manifest = manifest();
+
+        assert!(manifest.matches("/"));
+        assert!(manifest.matches("/items"));
+        assert!(!manifest.matches("/items/"));
+    }
+
+    #[test]
+    fn test_match_query_string() {
+        let manifest = manifest();
+
+        assert!(manifest.matches("/items?page=2"));
+        assert!(manifest.matches("/items/42#details"));
+        assert!(manifest.matches("/web/dist/output.css?v=abc"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve("/items/42", "edit"), Some("/items/edit".to_owned()));
+        assert_eq!(resolve("/items/42/", "edit"), Some("/items/42/edit".to_owned()));
+        assert_eq!(resolve("/items/42", "../web/a.css"), Some("/web/a.css".to_owned()));
+        assert_eq!(resolve("/items", "?page=2"), Some("/items".to_owned()));
+        assert_eq!(resolve("/items", "https://example.com/items"), None);
+        assert_eq!(resolve("/items", "//cdn.example.com/a.js"), None);
+        assert_eq!(resolve("/items", "mailto:someone@example.com"), None);
+        assert_eq!(resolve("/items", "#top"), None);
+    }
+
+    #[test]
+    fn test_extract_links() {
+        let links = extract_links(r##"
+            <!-- <a href="/commented"> -->
+            <a href="/items?a=1&amp;b=2" hx-target="#content">items</a>
+            <button hx-delete='/items/1' disabled>x</button>
+            <form action=/items method="post"></form>
+            <img src="/web/logo.png"/>
+        "##);
+
+        let urls: Vec<&str> = links.iter().map(|l| l.url.as_str()).collect();
+
+        assert_eq!(urls, vec!["/items?a=1&b=2", "/items/1", "/items", "/web/logo.png"]);
+        assert!(links[0].is_navigation());
+        assert!(!links[1].is_navigation());
+    }
+
+    #[test]
+    fn test_check_links() {
+        let html = r#"
+            <a href="/items/42">ok</a>
+            <a href="/itmes/42">typo</a>
+            <a href="https://example.com">external</a>
+            <div hx-get="/search?q=x"></div>
+            <div hx-get="/dynamic/abc"></div>
+            <script src="/web/htmx_integration.js"></script>
+        "#;
+
+        let broken = check_links(html, &manifest().allow("/dynamic/"));
+        let urls: Vec<&str> = broken.iter().map(|b| b.url.as_str()).collect();
+
+        assert_eq!(urls, vec!["/itmes/42", "/search?q=x"]);
+    }
+
+    #[test]
+    fn test_check_relative_links() {
+        let broken = check_page_links("/items/42/", r#"<a href="edit">edit</a><a href="remove">remove</a>"#, &manifest());
+
+        assert_eq!(broken.len(), 1);
+        assert_eq!(broken[0].url, "remove");
+    }
+
+    #[tokio::test]
+    async fn test_crawl_reports_unrequestable_links() {
+        let router: Router = Router::new()
+            .route("/", get(|| async { html! { a href="/ok" { "ok" } a href="/bad path" { "bad" } a href="/gone" { "gone" } } }))
+            .route("/ok", get(|| async { html! { "ok" } }));
+
+        let broken = crawl(router, "/", 1).await;
+        let reasons: Vec<(&str, &str)> = broken.iter().map(|b| (b.url.as_str(), b.reason.as_str())).collect();
+
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons[0].0 == "/bad path" && reasons[0].1.starts_with("invalid request"));
+        assert_eq!(reasons[1], ("/gone", "responded with 404 Not Found"));
+    }
+}
@@ -0,0 +1,80 @@
+use hyper::HeaderMap;
+
+use crate::{config::Theme, cookies::parse_cookies};
+
+/// The `<html>` class of a request, rendered by the shell so the page paints in the
+/// user's theme before any script runs:
+///
+/// ```ignore
+/// html lang="en" class=(context.theme_class()) { ... }
+/// ```
+///
+/// The theme cookie only selects among the configured themes, anything else
+/// (first visit, a removed theme, a tampered value) gets the default.
+pub(crate) fn theme_class(theme: &Theme, headers: &HeaderMap) -> String {
+    parse_cookies(headers)
+        .into_iter()
+        .find(|(name, _)| *name == theme.cookie)
+        .map(|(_, value)| value)
+        .filter(|value| theme.themes.contains(value))
+        .unwrap_or_else(|| theme.default.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, routing::get, Router};
+    use hyper::HeaderMap;
+    use maud::{html, Markup, DOCTYPE};
+    use tower::ServiceExt;
+
+    use crate::{config::Theme, test::router, App, Config, Context, Feature, Template};
+
+    use super::theme_class;
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, context: &Context, body: Markup) -> Markup {
+            html! { (DOCTYPE) html class=(context.theme_class()) { body { (body) } } }
+        }
+    }
+
+    struct Home;
+
+    impl Feature for Home {
+        fn web(&self) -> Option<Router> {
+            Some(Router::new().route("/", get(|| async { "home" })))
+        }
+    }
+
+    async fn page(app: &Router, cookie: Option<&str>) -> String {
+        let mut request = Request::get("/");
+        if let Some(cookie) = cookie {
+            request = request.header("cookie", cookie);
+        }
+
+        let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        String::from_utf8(to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_unknown_theme() {
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", r#"theme=dark" onload="x"#.parse().unwrap());
+
+        assert_eq!(theme_class(&Theme::default(), &headers), "light");
+        assert_eq!(theme_class(&Theme::default(), &HeaderMap::new()), "light");
+    }
+
+    #[tokio::test]
+    async fn test_class_reflects_cookie() {
+        let mut config = Config::default();
+        config.server.theme.default = "dark".to_owned();
+        let app = router(&App::new(config, TestTemplate).register_feature(Home).build());
+
+        assert!(page(&app, None).await.contains(r#"<html class="dark">"#));
+        assert!(page(&app, Some("session=1; theme=light")).await.contains(r#"<html class="light">"#));
+        assert!(page(&app, Some("theme=sepia")).await.contains(r#"<html class="dark">"#));
+    }
+}
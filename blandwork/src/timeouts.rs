@@ -0,0 +1,210 @@
+use std::{
+    future::Future, pin::Pin, sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::Duration
+};
+
+use axum::{body::Body, extract::Request, response::IntoResponse};
+use hyper::{Response, StatusCode};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+use crate::config::{RouteTimeout, Server};
+
+/// When the request times out, an extension set by `RouteTimeoutLayer`.
+/// `Db` bounds the statement timeout of its queries by what is left of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn at(instant: Instant) -> Self {
+        Self(instant)
+    }
+
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Zero once the deadline passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Answers `408` once a request takes longer than its timeout: the `server.route_timeouts`
+/// entry matching the path, otherwise `default`. An exact route beats a prefix (`/export/*`),
+/// the longest prefix wins and the first declared entry wins a tie.
+#[derive(Clone)]
+pub struct RouteTimeoutLayer {
+    // None leaves the paths without an entry unbounded
+    default: Option<Duration>,
+    routes: Arc<Vec<RouteTimeout>>,
+}
+
+impl RouteTimeoutLayer {
+    pub fn new(default: Duration, routes: Vec<RouteTimeout>) -> Self {
+        Self { default: Some(default), routes: Arc::new(routes) }
+    }
+
+    /// Only the paths with an entry time out.
+    pub fn routes(routes: Vec<RouteTimeout>) -> Self {
+        Self { default: None, routes: Arc::new(routes) }
+    }
+
+    /// `server.request_timeout_secs` and `server.route_timeouts`, None when neither bounds a request.
+    pub(crate) fn from_config(server: &Server) -> Option<Self> {
+        match server.request_timeout() {
+            Some(timeout) => Some(Self::new(timeout, server.route_timeouts.clone())),
+            None if !server.route_timeouts.is_empty() => Some(Self::routes(server.route_timeouts.clone())),
+            None => None,
+        }
+    }
+
+    pub fn timeout(&self, path: &str) -> Option<Duration> {
+        // (exact, prefix length)
+        let mut best: Option<((bool, usize), Duration)> = None;
+
+        for route in self.routes.iter() {
+            let rank: (bool, usize) = if route.route == path {
+                (true, path.len())
+            } else if let Some(prefix) = route.route.strip_suffix('*') {
+                match path.starts_with(prefix) {
+                    true => (false, prefix.len()),
+                    false => continue,
+                }
+            } else {
+                continue
+            };
+
+            if best.map(|(current, _)| rank > current).unwrap_or(true) {
+                best = Some((rank, route.timeout));
+            }
+        }
+
+        best.map(|(_, timeout)| timeout).or(self.default)
+    }
+}
+
+impl<S> Layer<S> for RouteTimeoutLayer {
+    type Service = RouteTimeoutService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RouteTimeoutService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct RouteTimeoutService<S> {
+    inner: S,
+    layer: RouteTimeoutLayer,
+}
+
+impl<S> Service<Request> for RouteTimeoutService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        let Some(timeout) = self.layer.timeout(req.uri().path()) else {
+            return Box::pin(self.inner.call(req));
+        };
+
+        let path: String = req.uri().path().to_owned();
+        req.extensions_mut().insert(Deadline::after(timeout));
+
+        let inner = self.inner.call(req);
+
+        Box::pin(async move {
+            match tokio::time::timeout(timeout, inner).await {
+                Ok(response) => response,
+                Err(_) => {
+                    tracing::warn!("{path} timed out after {timeout:?}");
+                    Ok(StatusCode::REQUEST_TIMEOUT.into_response())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::{body::Body, extract::Request, routing::get, Extension, Router};
+    use hyper::StatusCode;
+    use tower::ServiceExt;
+
+    use crate::config::RouteTimeout;
+
+    use super::{Deadline, RouteTimeoutLayer};
+
+    fn route(route: &str, seconds: u64) -> RouteTimeout {
+        RouteTimeout { route: route.to_owned(), timeout: Duration::from_secs(seconds) }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let layer = RouteTimeoutLayer::new(Duration::from_secs(10), vec![
+            route("/export/*", 120),
+            route("/export/quick/*", 5),
+            route("/export/yearly", 600),
+            route("/export/*", 1),
+        ]);
+
+        assert_eq!(layer.timeout("/invoices"), Some(Duration::from_secs(10)));
+        assert_eq!(layer.timeout("/export"), Some(Duration::from_secs(10)));
+        assert_eq!(layer.timeout("/export/monthly"), Some(Duration::from_secs(120)));
+        assert_eq!(layer.timeout("/export/monthly/2024"), Some(Duration::from_secs(120)));
+        assert_eq!(layer.timeout("/export/quick/csv"), Some(Duration::from_secs(5)));
+        assert_eq!(layer.timeout("/export/yearly"), Some(Duration::from_secs(600)));
+
+        let routes = RouteTimeoutLayer::routes(vec![route("/export/*", 120)]);
+        assert_eq!(routes.timeout("/invoices"), None);
+        assert_eq!(routes.timeout("/export/monthly"), Some(Duration::from_secs(120)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout_per_route() {
+        let slow = || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            "done"
+        };
+
+        let router = Router::new()
+            .route("/export/yearly", get(slow))
+            .route("/invoices", get(slow))
+            .layer(RouteTimeoutLayer::new(Duration::from_secs(10), vec![route("/export/*", 120)]));
+
+        let request = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        assert_eq!(router.clone().oneshot(request("/invoices")).await.unwrap().status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(router.oneshot(request("/export/yearly")).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deadline_extension() {
+        let router = Router::new()
+            .route("/export/yearly", get(|Extension(deadline): Extension<Deadline>| async move {
+                tokio::time::sleep(Duration::from_secs(30)).await;
+                deadline.remaining().as_secs().to_string()
+            }))
+            .layer(RouteTimeoutLayer::new(Duration::from_secs(10), vec![route("/export/*", 120)]));
+
+        let response = router.oneshot(Request::get("/export/yearly").body(Body::empty()).unwrap()).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        assert_eq!(&body[..], b"90");
+    }
+}
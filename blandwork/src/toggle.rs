@@ -0,0 +1,396 @@
+use std::{
+    collections::BTreeMap, future::Future, pin::Pin,
+    sync::{atomic::{AtomicBool, Ordering}, Arc, RwLock},
+    task::{Context as TaskContext, Poll}
+};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    response::{IntoResponse, Response},
+    routing::get, Extension, Json, Router
+};
+use axum_htmx::{HX_REDIRECT, HX_REQUEST};
+use hyper::{header::{AUTHORIZATION, LOCATION, RETRY_AFTER}, HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+
+use crate::{reload::Live, Config};
+
+/// `GET` lists the features, `POST {"feature": "Exports", "enabled": false}` toggles one.
+/// Requires `Authorization: Bearer <server.toggles.token>`, not mounted without a token.
+pub const FEATURES_ROUTE: &str = "/_blandwork/features";
+
+/// Switches a feature's routes on and off at runtime, see `App::toggles`.
+#[derive(Debug, Clone)]
+pub struct FeatureHandle {
+    name: Arc<str>,
+    enabled: Arc<AtomicBool>,
+}
+
+impl FeatureHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The `[features]` flags of the config, read by `Context::feature_enabled`.
+/// Replaced in place by a config reload, see `ConfigReloader`.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags {
+    flags: Live<BTreeMap<String, bool>>,
+}
+
+impl FeatureFlags {
+    pub fn from_config(config: &Config) -> Self {
+        Self { flags: Live::new(config.features.clone()) }
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.flags.load().get(name).copied().unwrap_or(false)
+    }
+
+    pub(crate) fn replace(&self, flags: BTreeMap<String, bool>) {
+        self.flags.store(flags);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FeatureState {
+    pub feature: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Toggle {
+    feature: String,
+    enabled: bool,
+}
+
+// a feature's handle and the route of its nav link
+type Toggles = Arc<RwLock<Vec<(FeatureHandle, Option<String>)>>>;
+
+/// The enabled flag of every feature registered by `build`, with the route of its nav link.
+#[derive(Clone, Default)]
+pub struct FeatureToggles {
+    features: Toggles,
+}
+
+impl FeatureToggles {
+    /// The handle of `name`, created enabled on first registration.
+    pub(crate) fn register(&self, name: &str, link: Option<String>) -> FeatureHandle {
+        let mut features = self.features.write().unwrap();
+
+        if let Some((handle, _)) = features.iter().find(|(handle, _)| *handle.name == *name) {
+            return handle.clone();
+        }
+
+        let handle: FeatureHandle = FeatureHandle { name: Arc::from(name), enabled: Arc::new(AtomicBool::new(true)) };
+        features.push((handle.clone(), link));
+
+        handle
+    }
+
+    pub fn handle(&self, name: &str) -> Option<FeatureHandle> {
+        self.features.read().unwrap()
+            .iter()
+            .find(|(handle, _)| handle.name() == name)
+            .map(|(handle, _)| handle.clone())
+    }
+
+    /// False when no feature is called `name`.
+    pub fn set(&self, name: &str, enabled: bool) -> bool {
+        match self.handle(name) {
+            Some(handle) => {
+                handle.enabled.store(enabled, Ordering::Relaxed);
+                tracing::info!(feature = name, "feature {}", if enabled { "enabled" } else { "disabled" });
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> Vec<FeatureState> {
+        self.features.read().unwrap()
+            .iter()
+            .map(|(handle, _)| FeatureState { feature: handle.name().to_owned(), enabled: handle.is_enabled() })
+            .collect()
+    }
+
+    /// False when `route` is the nav link of a disabled feature.
+    pub fn link_enabled(&self, route: &str) -> bool {
+        self.features.read().unwrap()
+            .iter()
+            .filter(|(_, link)| link.as_deref() == Some(route))
+            .all(|(handle, _)| handle.is_enabled())
+    }
+
+    /// The `FEATURES_ROUTE` admin endpoint, `token` is compared in constant time.
+    pub fn router(&self, token: &str) -> Router {
+        let token: Arc<str> = Arc::from(token);
+
+        Router::new()
+            .route(FEATURES_ROUTE, get(list).post(set))
+            .layer(Extension(self.clone()))
+            .layer(Extension(AdminToken(token)))
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct AdminToken(pub(crate) Arc<str>);
+
+/// An empty token never authorizes, neither does a request without one.
+pub(crate) fn authorized(headers: &HeaderMap, token: &AdminToken) -> bool {
+    let Some(presented) = headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|value| value.as_bytes())
+    else {
+        return false;
+    };
+
+    let expected: &[u8] = token.0.as_bytes();
+
+    !expected.is_empty()
+        && presented.len() == expected.len()
+        && presented.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// A configured admin token, None when it is empty: its endpoint is not mounted with it.
+pub(crate) fn admin_token<'a>(setting: &str, token: Option<&'a str>) -> Option<&'a str> {
+    match token {
+        Some("") => {
+            tracing::warn!("{setting} is empty and ignored, an empty token authorizes nobody");
+            None
+        },
+        token => token,
+    }
+}
+
+async fn list(headers: HeaderMap, Extension(token): Extension<AdminToken>, Extension(toggles): Extension<FeatureToggles>) -> Response {
+    if !authorized(&headers, &token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    Json(toggles.list()).into_response()
+}
+
+async fn set(
+    headers: HeaderMap,
+    Extension(token): Extension<AdminToken>,
+    Extension(toggles): Extension<FeatureToggles>,
+    Json(toggle): Json<Toggle>,
+) -> Response {
+    if !authorized(&headers, &token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match toggles.set(&toggle.feature, toggle.enabled) {
+        true => Json(toggles.list()).into_response(),
+        false => (StatusCode::NOT_FOUND, format!("unknown feature {}", toggle.feature)).into_response(),
+    }
+}
+
+/// Answers for a disabled feature: `503` with `Retry-After`, or a redirect
+/// (`HX-Redirect` for HTMX requests) when `redirect` is set.
+#[derive(Clone)]
+pub struct FeatureGuardLayer {
+    handle: FeatureHandle,
+    redirect: Option<String>,
+}
+
+impl FeatureGuardLayer {
+    pub fn new(handle: FeatureHandle) -> Self {
+        Self { handle, redirect: None }
+    }
+
+    pub fn redirect(mut self, to: Option<String>) -> Self {
+        self.redirect = to;
+        self
+    }
+}
+
+impl<S> Layer<S> for FeatureGuardLayer {
+    type Service = FeatureGuardService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FeatureGuardService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub struct FeatureGuardService<S> {
+    inner: S,
+    layer: FeatureGuardLayer,
+}
+
+impl<S> Service<Request> for FeatureGuardService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if self.layer.handle.is_enabled() {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let response: Response = match self.layer.redirect.as_deref() {
+            Some(to) if req.headers().contains_key(HX_REQUEST) => (StatusCode::OK, [(HX_REDIRECT, to)]).into_response(),
+            Some(to) => (StatusCode::SEE_OTHER, [(LOCATION, to)]).into_response(),
+            None => (StatusCode::SERVICE_UNAVAILABLE, [(RETRY_AFTER, "300")]).into_response(),
+        };
+
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, extract::Request, routing::get, Router};
+    use axum_htmx::{HX_REDIRECT, HX_REQUEST};
+    use hyper::{header::{AUTHORIZATION, CONTENT_TYPE, LOCATION}, Method, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{Config, ContextAccessor, Link};
+
+    use super::{admin_token, FeatureFlags, FeatureGuardLayer, FeatureToggles, FEATURES_ROUTE};
+
+    async fn send(router: &Router, request: Request) -> axum::response::Response {
+        router.clone().oneshot(request).await.unwrap()
+    }
+
+    fn get_request(uri: &str) -> Request {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_guard() {
+        let toggles = FeatureToggles::default();
+        let handle = toggles.register("Exports", Some("/exports".to_owned()));
+
+        let router = Router::new()
+            .route("/exports", get(|| async { "exports" }))
+            .layer(FeatureGuardLayer::new(handle.clone()))
+            .merge(Router::new()
+                .route("/exports/web", get(|| async { "exports" }))
+                .layer(FeatureGuardLayer::new(handle.clone()).redirect(Some("/".to_owned()))));
+
+        assert_eq!(send(&router, get_request("/exports")).await.status(), StatusCode::OK);
+
+        handle.disable();
+
+        assert_eq!(send(&router, get_request("/exports")).await.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let redirect = send(&router, get_request("/exports/web")).await;
+        assert_eq!(redirect.status(), StatusCode::SEE_OTHER);
+        assert_eq!(redirect.headers()[LOCATION], "/");
+
+        let htmx = send(&router, Request::get("/exports/web").header(HX_REQUEST, "true").body(Body::empty()).unwrap()).await;
+        assert_eq!(htmx.headers()[HX_REDIRECT], "/");
+
+        toggles.set("Exports", true);
+        assert_eq!(send(&router, get_request("/exports")).await.status(), StatusCode::OK);
+    }
+
+    fn toggle(token: Option<&str>, body: &str) -> Request {
+        let mut request = Request::builder().method(Method::POST).uri(FEATURES_ROUTE).header(CONTENT_TYPE, "application/json");
+
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        request.body(Body::from(body.to_owned())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_admin_endpoint() {
+        let toggles = FeatureToggles::default();
+        toggles.register("Exports", None);
+
+        let router = toggles.router("s3cret");
+
+        assert_eq!(send(&router, toggle(None, r#"{"feature":"Exports","enabled":false}"#)).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&router, toggle(Some("wrong"), r#"{"feature":"Exports","enabled":false}"#)).await.status(), StatusCode::UNAUTHORIZED);
+        assert!(toggles.handle("Exports").unwrap().is_enabled());
+
+        assert_eq!(send(&router, toggle(Some("s3cret"), r#"{"feature":"Exports","enabled":false}"#)).await.status(), StatusCode::OK);
+        assert!(!toggles.handle("Exports").unwrap().is_enabled());
+
+        assert_eq!(send(&router, toggle(Some("s3cret"), r#"{"feature":"Missing","enabled":false}"#)).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_empty_token_never_authorizes() {
+        let toggles = FeatureToggles::default();
+        toggles.register("Exports", None);
+
+        let router = toggles.router("");
+
+        assert_eq!(send(&router, toggle(None, r#"{"feature":"Exports","enabled":false}"#)).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&router, toggle(Some(""), r#"{"feature":"Exports","enabled":false}"#)).await.status(), StatusCode::UNAUTHORIZED);
+        assert!(toggles.handle("Exports").unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_empty_token_is_not_mounted() {
+        assert_eq!(admin_token("server.toggles.token", Some("")), None);
+        assert_eq!(admin_token("server.toggles.token", Some("s3cret")), Some("s3cret"));
+        assert_eq!(admin_token("server.toggles.token", None), None);
+    }
+
+    #[tokio::test]
+    async fn test_link_hidden_when_disabled() {
+        let toggles = FeatureToggles::default();
+        let handle = toggles.register("Exports", Some("/exports".to_owned()));
+
+        let link = Link { active: false, title: "Exports".to_owned(), label: "E".to_owned(), route: "/exports".to_owned(), icon: None, css: None, remember: false };
+
+        let request = Request::builder().uri("/").extension(toggles.clone()).body(Body::empty()).unwrap();
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert!(link.render(&context).into_string().contains("href=\"/exports\""));
+
+        handle.disable();
+        assert!(link.render(&context).into_string().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_feature_flags() {
+        let mut config = Config::default();
+        config.features.insert("beta_ui".to_owned(), true);
+        config.features.insert("dark_mode".to_owned(), false);
+
+        let request = Request::builder().uri("/").extension(FeatureFlags::from_config(&config)).body(Body::empty()).unwrap();
+        let accessor = ContextAccessor::from_request(&request);
+        let context = accessor.context().await;
+
+        assert!(context.feature_enabled("beta_ui"));
+        assert!(!context.feature_enabled("dark_mode"));
+        assert!(!context.feature_enabled("missing"));
+
+        // outside of a built App every flag is off
+        let accessor = ContextAccessor::from_request(&get_request("/"));
+        assert!(!accessor.context().await.feature_enabled("beta_ui"));
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::Arc;
+
+use crate::Context;
+
+/// Post-processing of the rendered HTML, registered with `App::body_transform`.
+///
+/// Transforms only run on full pages wrapped by the template (`text/html`),
+/// never on boosted fragments, api or supplemental responses.
+/// They run in registration order, each receiving the output of the previous one,
+/// the live reload script is injected after the last one.
+/// Every transform is a full pass over the page, keep them cheap.
+pub type BodyTransform = Arc<dyn Fn(&Context, String) -> String + Send + Sync>;
+
+// contents kept verbatim by the minifier
+const RAW_ELEMENTS: [&str; 4] = ["pre", "textarea", "script", "style"];
+
+/// Collapses whitespace runs to a single space and drops indentation between tags.
+/// `pre`, `textarea`, `script` and `style` contents are left untouched.
+pub fn minify_html() -> BodyTransform {
+    Arc::new(|_, html| minify(&html))
+}
+
+/// Prefixes root relative asset URLs (`href`/`src` starting with `prefix`) with `base`,
+/// for applications served below a base path: `rewrite_asset_urls("/web", "/portal")`
+/// turns `/web/css/output.css` into `/portal/web/css/output.css`.
+pub fn rewrite_asset_urls(prefix: &str, base: &str) -> BodyTransform {
+    let prefix: String = format!("{}/", prefix.trim_end_matches('/'));
+    let base: String = base.trim_end_matches('/').to_owned();
+
+    Arc::new(move |_, html| rewrite(&html, &prefix, &base))
+}
+
+fn minify(html: &str) -> String {
+    // ascii lowercasing keeps byte offsets identical
+    let lower: String = html.to_ascii_lowercase();
+    let mut minified: String = String::with_capacity(html.len());
+    let mut raw: Option<String> = None;
+    let mut position: usize = 0;
+
+    while position < html.len() {
+        if let Some(close) = raw.take() {
+            match lower[position..].find(&close) {
+                Some(offset) => {
+                    minified.push_str(&html[position..position + offset]);
+                    position += offset;
+                    continue;
+                },
+                None => {
+                    minified.push_str(&html[position..]);
+                    break;
+                }
+            }
+        }
+
+        let rest: &str = &html[position..];
+        let c: char = rest.chars().next().unwrap();
+
+        if c.is_ascii_whitespace() {
+            let run: usize = rest.find(|c: char| !c.is_ascii_whitespace()).unwrap_or(rest.len());
+            let between_tags: bool = minified.ends_with('>') && rest[run..].starts_with('<');
+
+            if !(between_tags && rest[..run].contains('\n')) {
+                minified.push(' ');
+            }
+
+            position += run;
+            continue;
+        }
+
+        if c == '<' {
+            raw = RAW_ELEMENTS.iter()
+                .find(|element| {
+                    let after: &str = &lower[position + 1..];
+                    after.starts_with(*element) && after[element.len()..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+                })
+                .map(|element| format!("</{element}"));
+        }
+
+        minified.push(c);
+        position += c.len_utf8();
+    }
+
+    minified
+}
+
+fn rewrite(html: &str, prefix: &str, base: &str) -> String {
+    let mut rewritten: String = html.to_owned();
+
+    for attribute in ["href=", "src="] {
+        for quote in ['"', '\''] {
+            rewritten = rewritten.replace(
+                &format!("{attribute}{quote}{prefix}"),
+                &format!("{attribute}{quote}{base}{prefix}")
+            );
+        }
+    }
+
+    rewritten
+}
+
+#[cfg(test)]
+mod test {
+    use super::{minify, rewrite};
+
+    #[test]
+    fn test_minify() {
+        let html = "<html>\n  <body>\n    <p>hello   <b>big</b>\n world</p>\n  </body>\n</html>";
+
+        assert_eq!(minify(html), "<html><body><p>hello <b>big</b> world</p></body></html>");
+    }
+
+    #[test]
+    fn test_minify_keeps_raw_elements() {
+        let html = "<div>\n  <pre>a\n   b</pre>\n  <script>let x =  1;\n</script>\n</div>";
+
+        assert_eq!(minify(html), "<div><pre>a\n   b</pre><script>let x =  1;\n</script></div>");
+    }
+
+    #[test]
+    fn test_minify_ignores_similar_tags() {
+        // <preview> is not <pre>
+        assert_eq!(minify("<preview>a   b</preview>"), "<preview>a b</preview>");
+    }
+
+    #[test]
+    fn test_rewrite_asset_urls() {
+        let html = r#"<link href="/web/css/output.css"><script src='/web/app.js'></script><a href="/website">"#;
+
+        assert_eq!(
+            rewrite(html, "/web/", "/portal"),
+            r#"<link href="/portal/web/css/output.css"><script src='/portal/web/app.js'></script><a href="/website">"#
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use std::{error::Error, fmt::Display, time::Duration};
+
+use serde::{Deserialize, Deserializer};
+
+/// Error produced when a duration or size string can not be understood.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitError {
+    Empty,
+    MissingUnit { value: String, expected: &'static str },
+    InvalidNumber { value: String },
+    UnknownUnit { value: String, unit: String, expected: &'static str },
+    OutOfRange { value: String },
+}
+
+impl Display for UnitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnitError::Empty => write!(f, "expected a value with a unit but found an empty string"),
+            UnitError::MissingUnit { value, expected } =>
+                write!(f, "missing unit in `{value}`, expected one of {expected}"),
+            UnitError::InvalidNumber { value } =>
+                write!(f, "`{value}` does not start with a valid number"),
+            UnitError::UnknownUnit { value, unit, expected } =>
+                write!(f, "unknown unit `{unit}` in `{value}`, expected one of {expected}"),
+            UnitError::OutOfRange { value } =>
+                write!(f, "`{value}` is out of range"),
+        }
+    }
+}
+
+impl Error for UnitError {}
+
+const DURATION_UNITS: &str = "ms, s, m, h, d";
+const SIZE_UNITS: &str = "B, KB, MB, GB, KiB, MiB, GiB";
+
+/// Split `"30s"` / `"8 MiB"` / `"1.5h"` into the numeric part and the unit.
+fn split_unit(value: &str) -> Result<(f64, &str), UnitError> {
+    let trimmed: &str = value.trim();
+
+    if trimmed.is_empty() {
+        return Err(UnitError::Empty);
+    }
+
+    let index: usize = trimmed
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(trimmed.len());
+
+    let (number, unit) = trimmed.split_at(index);
+
+    let number: f64 = number.parse().map_err(|_| UnitError::InvalidNumber {
+        value: value.to_owned()
+    })?;
+
+    Ok((number, unit.trim()))
+}
+
+/// Parse a human readable duration such as `"250ms"`, `"30s"`, `"5m"`, `"12h"` or `"7d"`.
+pub fn parse_duration(value: &str) -> Result<Duration, UnitError> {
+    let (number, unit) = split_unit(value)?;
+
+    let seconds: f64 = match unit.to_ascii_lowercase().as_str() {
+        "" => return Err(UnitError::MissingUnit { value: value.to_owned(), expected: DURATION_UNITS }),
+        "ms" | "msec" | "millis" | "milliseconds" => number / 1000.0,
+        "s" | "sec" | "secs" | "second" | "seconds" => number,
+        "m" | "min" | "mins" | "minute" | "minutes" => number * 60.0,
+        "h" | "hr" | "hrs" | "hour" | "hours" => number * 60.0 * 60.0,
+        "d" | "day" | "days" => number * 60.0 * 60.0 * 24.0,
+        _ => return Err(UnitError::UnknownUnit {
+            value: value.to_owned(),
+            unit: unit.to_owned(),
+            expected: DURATION_UNITS
+        }),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| UnitError::OutOfRange { value: value.to_owned() })
+}
+
+/// Parse a human readable byte count such as `"512B"`, `"64KB"` or `"8MiB"`.
+/// Decimal units (KB, MB, GB) are powers of 1000, binary units (KiB, MiB, GiB) powers of 1024.
+pub fn parse_size(value: &str) -> Result<u64, UnitError> {
+    let (number, unit) = split_unit(value)?;
+
+    let multiplier: f64 = match unit.to_ascii_lowercase().as_str() {
+        "" => return Err(UnitError::MissingUnit { value: value.to_owned(), expected: SIZE_UNITS }),
+        "b" => 1.0,
+        "kb" => 1_000.0,
+        "mb" => 1_000_000.0,
+        "gb" => 1_000_000_000.0,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(UnitError::UnknownUnit {
+            value: value.to_owned(),
+            unit: unit.to_owned(),
+            expected: SIZE_UNITS
+        }),
+    };
+
+    let bytes: f64 = (number * multiplier).round();
+
+    if bytes > u64::MAX as f64 {
+        return Err(UnitError::OutOfRange { value: value.to_owned() });
+    }
+
+    Ok(bytes as u64)
+}
+
+/// Config values may be written as a bare number (kept for existing configs)
+/// or as a string with a unit.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Raw {
+    Number(u64),
+    Text(String),
+}
+
+/// Serde helper for `Duration` fields.
+/// A bare integer is read as seconds, strings require a unit.
+///
+/// ```ignore
+/// #[serde(deserialize_with = "blandwork::units::duration::deserialize")]
+/// pub timeout: Duration,
+/// ```
+pub mod duration {
+    use std::time::Duration;
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    use super::{parse_duration, Raw};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de> {
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(seconds) => Ok(Duration::from_secs(seconds)),
+            Raw::Text(value) => parse_duration(&value).map_err(D::Error::custom),
+        }
+    }
+}
+
+/// Serde helper for optional `Duration` fields.
+/// Missing values, `0` and `"0s"` all mean "disabled" and deserialize to `None`.
+pub mod option_duration {
+    use std::time::Duration;
+    use serde::Deserializer;
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+    where
+        D: Deserializer<'de> {
+        let duration: Duration = super::duration::deserialize(deserializer)?;
+
+        match duration.is_zero() {
+            true => Ok(None),
+            false => Ok(Some(duration)),
+        }
+    }
+}
+
+/// Serde helper for byte counts.
+/// A bare integer is read as bytes, strings require a unit.
+pub mod byte_size {
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    use super::{parse_size, Raw};
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+    where
+        D: Deserializer<'de> {
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(bytes) => Ok(bytes),
+            Raw::Text(value) => parse_size(&value).map_err(D::Error::custom),
+        }
+    }
+}
+
+/// Wrapper usable directly as a field type when the serde attribute is inconvenient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HumanDuration(pub Duration);
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de> {
+        duration::deserialize(deserializer).map(HumanDuration)
+    }
+}
+
+/// Wrapper usable directly as a field type when the serde attribute is inconvenient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ByteSize(pub u64);
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de> {
+        byte_size::deserialize(deserializer).map(ByteSize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use serde::Deserialize;
+
+    use super::{parse_duration, parse_size, UnitError};
+
+    #[derive(Deserialize, Debug)]
+    struct Limits {
+        #[serde(deserialize_with = "super::duration::deserialize")]
+        timeout: Duration,
+
+        #[serde(deserialize_with = "super::byte_size::deserialize")]
+        max_body: u64,
+
+        #[serde(default, deserialize_with = "super::option_duration::deserialize")]
+        session_ttl: Option<Duration>,
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration(" 7 days ").unwrap(), Duration::from_secs(7 * 86400));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512B").unwrap(), 512);
+        assert_eq!(parse_size("64KB").unwrap(), 64_000);
+        assert_eq!(parse_size("8MiB").unwrap(), 8 * 1024 * 1024);
+        assert_eq!(parse_size("1 gib").unwrap(), 1024 * 1024 * 1024);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_duration(""), Err(UnitError::Empty));
+        assert!(matches!(parse_duration("30"), Err(UnitError::MissingUnit { .. })));
+        assert!(matches!(parse_duration("soon"), Err(UnitError::InvalidNumber { .. })));
+        assert!(matches!(parse_size("8MiBs"), Err(UnitError::UnknownUnit { .. })));
+
+        assert_eq!(
+            parse_duration("30y").unwrap_err().to_string(),
+            "unknown unit `y` in `30y`, expected one of ms, s, m, h, d"
+        );
+    }
+
+    #[test]
+    fn test_deserialize_config_units() {
+        let limits: Limits = toml::from_str(r#"
+            timeout = "30s"
+            max_body = "8MiB"
+            session_ttl = "7d"
+        "#).unwrap();
+
+        assert_eq!(limits.timeout, Duration::from_secs(30));
+        assert_eq!(limits.max_body, 8 * 1024 * 1024);
+        assert_eq!(limits.session_ttl, Some(Duration::from_secs(7 * 86400)));
+    }
+
+    #[test]
+    fn test_deserialize_bare_numbers() {
+        let limits: Limits = toml::from_str(r#"
+            timeout = 10
+            max_body = 1024
+            session_ttl = 0
+        "#).unwrap();
+
+        assert_eq!(limits.timeout, Duration::from_secs(10));
+        assert_eq!(limits.max_body, 1024);
+        assert_eq!(limits.session_ttl, None);
+    }
+
+    #[test]
+    fn test_deserialize_bad_unit() {
+        let error = toml::from_str::<Limits>(r#"
+            timeout = "30 fortnights"
+            max_body = "8MiB"
+        "#).unwrap_err();
+
+        assert!(error.to_string().contains("unknown unit `fortnights`"));
+    }
+}
@@ -0,0 +1,462 @@
+use std::{
+    collections::HashMap, error::Error, fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime}
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+use uuid::Uuid;
+
+use crate::clock::{Clock, SharedClock, SystemClock};
+
+// session key holding the pending challenge, one ceremony at a time
+const CHALLENGE_KEY: &str = "blandwork.webauthn.challenge";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+/// Random value the authenticator signs, single use and short lived.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Challenge {
+    // base64url, as sent to `navigator.credentials`
+    pub value: String,
+    pub ceremony: Ceremony,
+    pub user: Option<String>,
+    pub expires_at: SystemTime,
+}
+
+impl Challenge {
+    pub fn new(ceremony: Ceremony, user: Option<String>, expires_at: SystemTime) -> Self {
+        let mut bytes: Vec<u8> = Vec::with_capacity(32);
+        bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+        bytes.extend_from_slice(Uuid::new_v4().as_bytes());
+
+        Self { value: URL_SAFE_NO_PAD.encode(bytes), ceremony, user, expires_at }
+    }
+
+    fn check(&self, ceremony: Ceremony, now: SystemTime) -> Result<(), WebAuthnError> {
+        if self.ceremony != ceremony {
+            return Err(WebAuthnError::Challenge("challenge was issued for another ceremony"));
+        }
+
+        if now >= self.expires_at {
+            return Err(WebAuthnError::Challenge("challenge expired"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Browser response to `navigator.credentials.create`, fields base64url encoded.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistrationResponse {
+    pub credential_id: String,
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// Browser response to `navigator.credentials.get`, fields base64url encoded.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssertionResponse {
+    pub credential_id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+/// Result of a successfully verified registration.
+#[derive(Debug, Clone)]
+pub struct VerifiedRegistration {
+    pub credential_id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PasskeyCredential {
+    pub credential_id: String,
+    pub user: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+    pub created_at: SystemTime,
+}
+
+#[derive(Debug)]
+pub enum WebAuthnError {
+    Challenge(&'static str),
+    Verification(String),
+    UnknownCredential,
+    CounterRegression { stored: u32, received: u32 },
+    Session(String),
+    Store(String),
+}
+
+impl Display for WebAuthnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebAuthnError::Challenge(e) => write!(f, "invalid challenge: {e}"),
+            WebAuthnError::Verification(e) => write!(f, "verification failed: {e}"),
+            WebAuthnError::UnknownCredential => write!(f, "unknown credential"),
+            WebAuthnError::CounterRegression { stored, received } =>
+                write!(f, "signature counter went from {stored} to {received}, the authenticator may be cloned"),
+            WebAuthnError::Session(e) => write!(f, "session error: {e}"),
+            WebAuthnError::Store(e) => write!(f, "credential store error: {e}"),
+        }
+    }
+}
+
+impl Error for WebAuthnError {}
+
+impl From<tower_sessions::session::Error> for WebAuthnError {
+    fn from(value: tower_sessions::session::Error) -> Self {
+        WebAuthnError::Session(value.to_string())
+    }
+}
+
+/// The cryptography, implemented by the application (e.g. with `webauthn-rs` or `passkey`).
+/// Implementations check the client data (type, challenge, origin), the rp id hash
+/// and the signature, the challenge passed in has already been checked for expiry.
+pub trait PasskeyVerifier: Send + Sync {
+    fn verify_registration(&self, challenge: &Challenge, response: &RegistrationResponse) -> Result<VerifiedRegistration, WebAuthnError>;
+
+    /// Returns the signature counter reported by the authenticator.
+    fn verify_assertion(&self, challenge: &Challenge, credential: &PasskeyCredential, response: &AssertionResponse) -> Result<u32, WebAuthnError>;
+}
+
+/// Storage contract for passkey public keys.
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    async fn save(&self, credential: PasskeyCredential) -> Result<(), WebAuthnError>;
+
+    async fn find(&self, credential_id: &str) -> Result<Option<PasskeyCredential>, WebAuthnError>;
+
+    async fn for_user(&self, user: &str) -> Result<Vec<PasskeyCredential>, WebAuthnError>;
+
+    async fn update_sign_count(&self, credential_id: &str, sign_count: u32) -> Result<(), WebAuthnError>;
+
+    async fn delete(&self, credential_id: &str) -> Result<(), WebAuthnError>;
+}
+
+#[derive(Default)]
+pub struct MemoryCredentialStore {
+    credentials: Mutex<HashMap<String, PasskeyCredential>>
+}
+
+#[async_trait]
+impl CredentialStore for MemoryCredentialStore {
+    async fn save(&self, credential: PasskeyCredential) -> Result<(), WebAuthnError> {
+        self.credentials.lock().unwrap().insert(credential.credential_id.clone(), credential);
+        Ok(())
+    }
+
+    async fn find(&self, credential_id: &str) -> Result<Option<PasskeyCredential>, WebAuthnError> {
+        Ok(self.credentials.lock().unwrap().get(credential_id).cloned())
+    }
+
+    async fn for_user(&self, user: &str) -> Result<Vec<PasskeyCredential>, WebAuthnError> {
+        Ok(self.credentials.lock().unwrap().values().filter(|c| c.user == user).cloned().collect())
+    }
+
+    async fn update_sign_count(&self, credential_id: &str, sign_count: u32) -> Result<(), WebAuthnError> {
+        if let Some(credential) = self.credentials.lock().unwrap().get_mut(credential_id) {
+            credential.sign_count = sign_count;
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, credential_id: &str) -> Result<(), WebAuthnError> {
+        self.credentials.lock().unwrap().remove(credential_id);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresCredentialStore;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use async_trait::async_trait;
+
+    use crate::db::ConnectionPool;
+
+    use super::{CredentialStore, PasskeyCredential, WebAuthnError};
+
+    impl From<tokio_postgres::Error> for WebAuthnError {
+        fn from(value: tokio_postgres::Error) -> Self {
+            WebAuthnError::Store(value.to_string())
+        }
+    }
+
+    /// Create with `PostgresCredentialStore::TABLE` before use.
+    pub struct PostgresCredentialStore {
+        pool: ConnectionPool
+    }
+
+    impl PostgresCredentialStore {
+        pub const TABLE: &'static str = "CREATE TABLE IF NOT EXISTS blandwork_passkeys (
+            credential_id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            public_key BYTEA NOT NULL,
+            sign_count BIGINT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS blandwork_passkeys_user ON blandwork_passkeys (user_id)";
+
+        pub fn new(pool: ConnectionPool) -> Self {
+            Self { pool }
+        }
+
+        pub async fn migrate(&self) -> Result<(), WebAuthnError> {
+            let connection = self.pool.get().await.map_err(|e| WebAuthnError::Store(e.to_string()))?;
+            connection.batch_execute(Self::TABLE).await?;
+            Ok(())
+        }
+    }
+
+    fn credential(row: &tokio_postgres::Row) -> PasskeyCredential {
+        PasskeyCredential {
+            credential_id: row.get("credential_id"),
+            user: row.get("user_id"),
+            public_key: row.get("public_key"),
+            sign_count: row.get::<_, i64>("sign_count") as u32,
+            created_at: row.get("created_at"),
+        }
+    }
+
+    #[async_trait]
+    impl CredentialStore for PostgresCredentialStore {
+        async fn save(&self, credential: PasskeyCredential) -> Result<(), WebAuthnError> {
+            let connection = self.pool.get().await.map_err(|e| WebAuthnError::Store(e.to_string()))?;
+
+            connection.execute(
+                "INSERT INTO blandwork_passkeys (credential_id, user_id, public_key, sign_count, created_at) VALUES ($1, $2, $3, $4, $5)",
+                &[&credential.credential_id, &credential.user, &credential.public_key, &(credential.sign_count as i64), &credential.created_at]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn find(&self, credential_id: &str) -> Result<Option<PasskeyCredential>, WebAuthnError> {
+            let connection = self.pool.get().await.map_err(|e| WebAuthnError::Store(e.to_string()))?;
+
+            let row = connection.query_opt(
+                "SELECT * FROM blandwork_passkeys WHERE credential_id = $1",
+                &[&credential_id]
+            ).await?;
+
+            Ok(row.as_ref().map(credential))
+        }
+
+        async fn for_user(&self, user: &str) -> Result<Vec<PasskeyCredential>, WebAuthnError> {
+            let connection = self.pool.get().await.map_err(|e| WebAuthnError::Store(e.to_string()))?;
+
+            let rows = connection.query(
+                "SELECT * FROM blandwork_passkeys WHERE user_id = $1 ORDER BY created_at",
+                &[&user]
+            ).await?;
+
+            Ok(rows.iter().map(credential).collect())
+        }
+
+        async fn update_sign_count(&self, credential_id: &str, sign_count: u32) -> Result<(), WebAuthnError> {
+            let connection = self.pool.get().await.map_err(|e| WebAuthnError::Store(e.to_string()))?;
+
+            connection.execute(
+                "UPDATE blandwork_passkeys SET sign_count = $2 WHERE credential_id = $1",
+                &[&credential_id, &(sign_count as i64)]
+            ).await?;
+
+            Ok(())
+        }
+
+        async fn delete(&self, credential_id: &str) -> Result<(), WebAuthnError> {
+            let connection = self.pool.get().await.map_err(|e| WebAuthnError::Store(e.to_string()))?;
+
+            connection.execute("DELETE FROM blandwork_passkeys WHERE credential_id = $1", &[&credential_id]).await?;
+
+            Ok(())
+        }
+    }
+}
+
+/// Authenticators which count signatures must move forward, zero means no counter.
+fn check_sign_count(stored: u32, received: u32) -> Result<(), WebAuthnError> {
+    if (stored != 0 || received != 0) && received <= stored {
+        return Err(WebAuthnError::CounterRegression { stored, received });
+    }
+
+    Ok(())
+}
+
+/// Passkey registration and sign in ceremonies.
+/// Challenges live in the session (tower-sessions) and are consumed by the matching `finish_*`.
+#[derive(Clone)]
+pub struct Passkeys {
+    store: Arc<dyn CredentialStore>,
+    verifier: Arc<dyn PasskeyVerifier>,
+    clock: SharedClock,
+
+    // time the browser has to complete a ceremony
+    challenge_ttl: Duration,
+}
+
+impl Passkeys {
+    pub fn new(store: impl CredentialStore + 'static, verifier: impl PasskeyVerifier + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            verifier: Arc::new(verifier),
+            clock: Arc::new(SystemClock),
+            challenge_ttl: Duration::from_secs(5 * 60),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn challenge_ttl(mut self, challenge_ttl: Duration) -> Self {
+        self.challenge_ttl = challenge_ttl;
+        self
+    }
+
+    async fn issue(&self, session: &Session, ceremony: Ceremony, user: Option<String>) -> Result<Challenge, WebAuthnError> {
+        let challenge: Challenge = Challenge::new(ceremony, user, self.clock.now() + self.challenge_ttl);
+
+        session.insert(CHALLENGE_KEY, challenge.clone()).await?;
+
+        Ok(challenge)
+    }
+
+    /// Removes the pending challenge whatever the outcome, a challenge is never reused.
+    async fn take(&self, session: &Session, ceremony: Ceremony) -> Result<Challenge, WebAuthnError> {
+        let challenge: Challenge = session.remove::<Challenge>(CHALLENGE_KEY).await?
+            .ok_or(WebAuthnError::Challenge("no pending challenge"))?;
+
+        challenge.check(ceremony, self.clock.now())?;
+
+        Ok(challenge)
+    }
+
+    pub async fn start_registration(&self, session: &Session, user: &str) -> Result<Challenge, WebAuthnError> {
+        self.issue(session, Ceremony::Registration, Some(user.to_owned())).await
+    }
+
+    pub async fn finish_registration(&self, session: &Session, response: &RegistrationResponse) -> Result<PasskeyCredential, WebAuthnError> {
+        let challenge: Challenge = self.take(session, Ceremony::Registration).await?;
+
+        let verified: VerifiedRegistration = self.verifier.verify_registration(&challenge, response)?;
+
+        let credential: PasskeyCredential = PasskeyCredential {
+            credential_id: verified.credential_id,
+            user: challenge.user.unwrap_or_default(),
+            public_key: verified.public_key,
+            sign_count: verified.sign_count,
+            created_at: self.clock.now(),
+        };
+
+        self.store.save(credential.clone()).await?;
+
+        Ok(credential)
+    }
+
+    /// The challenge and, for a known user, the credentials the browser may offer
+    /// (empty for discoverable credentials).
+    pub async fn start_authentication(&self, session: &Session, user: Option<&str>) -> Result<(Challenge, Vec<PasskeyCredential>), WebAuthnError> {
+        let allowed: Vec<PasskeyCredential> = match user {
+            Some(user) => self.store.for_user(user).await?,
+            None => Vec::new(),
+        };
+
+        let challenge: Challenge = self.issue(session, Ceremony::Authentication, user.map(str::to_owned)).await?;
+
+        Ok((challenge, allowed))
+    }
+
+    /// The signed in credential, its owner is `credential.user`.
+    pub async fn finish_authentication(&self, session: &Session, response: &AssertionResponse) -> Result<PasskeyCredential, WebAuthnError> {
+        let challenge: Challenge = self.take(session, Ceremony::Authentication).await?;
+
+        let mut credential: PasskeyCredential = self.store.find(&response.credential_id).await?
+            .ok_or(WebAuthnError::UnknownCredential)?;
+
+        if challenge.user.as_ref().is_some_and(|user| *user != credential.user) {
+            return Err(WebAuthnError::UnknownCredential);
+        }
+
+        let sign_count: u32 = self.verifier.verify_assertion(&challenge, &credential, response)?;
+
+        check_sign_count(credential.sign_count, sign_count)?;
+
+        self.store.update_sign_count(&credential.credential_id, sign_count).await?;
+        credential.sign_count = sign_count;
+
+        Ok(credential)
+    }
+
+    pub async fn credentials(&self, user: &str) -> Result<Vec<PasskeyCredential>, WebAuthnError> {
+        self.store.for_user(user).await
+    }
+
+    pub async fn remove(&self, credential_id: &str) -> Result<(), WebAuthnError> {
+        self.store.delete(credential_id).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    use crate::clock::{Clock, TestClock};
+
+    use super::{check_sign_count, Ceremony, Challenge, WebAuthnError};
+
+    #[test]
+    fn test_challenge_encoding() {
+        let challenge = Challenge::new(Ceremony::Registration, None, TestClock::new().now());
+
+        // unpadded base64url of 32 random bytes
+        assert_eq!(challenge.value.len(), 43);
+        assert_eq!(URL_SAFE_NO_PAD.decode(&challenge.value).unwrap().len(), 32);
+    }
+
+    #[test]
+    fn test_challenge_is_random() {
+        let clock = TestClock::new();
+        let a = Challenge::new(Ceremony::Registration, None, clock.now());
+        let b = Challenge::new(Ceremony::Registration, None, clock.now());
+
+        assert_eq!(a.value.len(), 43);
+        assert_ne!(a.value, b.value);
+    }
+
+    #[test]
+    fn test_challenge_check() {
+        let clock = TestClock::new();
+        let challenge = Challenge::new(Ceremony::Registration, Some("ada".to_owned()), clock.now() + Duration::from_secs(300));
+
+        assert!(challenge.check(Ceremony::Registration, clock.now()).is_ok());
+        assert!(matches!(challenge.check(Ceremony::Authentication, clock.now()), Err(WebAuthnError::Challenge(_))));
+
+        clock.advance(Duration::from_secs(300));
+        assert!(matches!(challenge.check(Ceremony::Registration, clock.now()), Err(WebAuthnError::Challenge(_))));
+    }
+
+    #[test]
+    fn test_sign_count() {
+        // authenticators without a counter always report zero
+        assert!(check_sign_count(0, 0).is_ok());
+        assert!(check_sign_count(4, 5).is_ok());
+        assert!(matches!(check_sign_count(5, 5), Err(WebAuthnError::CounterRegression { .. })));
+        assert!(matches!(check_sign_count(5, 0), Err(WebAuthnError::CounterRegression { .. })));
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::BTreeMap, error::Error, fmt::Display, future::Future, pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+    time::{Duration, UNIX_EPOCH}
+};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, COOKIE, ORIGIN, SET_COOKIE, VARY
+        },
+        HeaderName, HeaderValue, Method, StatusCode
+    },
+    response::{IntoResponse, Response},
+    routing::get, Router
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use tower::{Layer, Service};
+
+use crate::{
+    clock::{Clock, SharedClock, SystemClock},
+    config::WidgetEmbed,
+    Context, Template
+};
+
+/// The loader third-party pages include, see `web/embed.js`.
+pub const EMBED_SCRIPT_ROUTE: &str = "/_blandwork/embed.js";
+
+/// Query parameter of the embed token in the widget's url.
+pub const EMBED_PARAM: &str = "embed";
+
+/// The embed token of requests made from within the widget, set by the widget shell.
+pub const EMBED_HEADER: HeaderName = HeaderName::from_static("x-blandwork-embed");
+
+const EMBED_SCRIPT: &str = include_str!("../../web/embed.js");
+const RESIZE_SCRIPT: &str = include_str!("../../web/widget.js");
+
+// HTMX request headers a script embedding the widget sends
+const ALLOWED_HEADERS: &str = "hx-request, hx-boosted, hx-current-url, hx-target, hx-trigger, x-blandwork-embed";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Who embeds a widget: the customer, display options of the embed and when the token expires
+/// (unix seconds). Handlers of widget routes extract it with `Extension<EmbedToken>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmbedToken {
+    pub widget: String,
+    pub customer: String,
+
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmbedTokenError {
+    Malformed,
+    Signature,
+    Expired,
+
+    /// A valid token of another widget.
+    WrongWidget,
+}
+
+impl Display for EmbedTokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EmbedTokenError::Malformed => write!(f, "malformed embed token"),
+            EmbedTokenError::Signature => write!(f, "embed token signature mismatch"),
+            EmbedTokenError::Expired => write!(f, "embed token expired"),
+            EmbedTokenError::WrongWidget => write!(f, "embed token of another widget"),
+        }
+    }
+}
+
+impl Error for EmbedTokenError {}
+
+/// Issues and verifies the signed embed tokens of widget urls with `server.widgets.key`:
+///
+/// ```ignore
+/// let tokens = EmbedTokens::new(key.as_bytes()).ttl(Duration::from_secs(30 * 24 * 60 * 60));
+/// let token = tokens.issue("booking", "acme", BTreeMap::from([("theme".into(), "dark".into())]));
+/// let url = format!("/booking?embed={token}");
+/// ```
+#[derive(Clone)]
+pub struct EmbedTokens {
+    key: Vec<u8>,
+    clock: SharedClock,
+    ttl: Duration,
+}
+
+impl EmbedTokens {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.to_vec(), clock: Arc::new(SystemClock), ttl: Duration::from_secs(365 * 24 * 60 * 60) }
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub(crate) fn shared_clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// How long issued tokens are valid, a year by default.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+    }
+
+    fn signature(&self, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+        mac.update(format!("widget.{payload}").as_bytes());
+        mac
+    }
+
+    pub fn issue(&self, widget: &str, customer: &str, options: BTreeMap<String, String>) -> String {
+        let token = EmbedToken {
+            widget: widget.to_owned(),
+            customer: customer.to_owned(),
+            options,
+            expires_at: self.now() + self.ttl.as_secs(),
+        };
+
+        let payload: String = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&token).unwrap_or_default());
+        let signature: String = URL_SAFE_NO_PAD.encode(self.signature(&payload).finalize().into_bytes());
+
+        format!("{payload}.{signature}")
+    }
+
+    pub fn verify(&self, token: &str) -> Result<EmbedToken, EmbedTokenError> {
+        let (payload, signature) = token.split_once('.').ok_or(EmbedTokenError::Malformed)?;
+        let signature: Vec<u8> = URL_SAFE_NO_PAD.decode(signature).map_err(|_| EmbedTokenError::Malformed)?;
+
+        self.signature(payload).verify_slice(&signature).map_err(|_| EmbedTokenError::Signature)?;
+
+        let token: EmbedToken = URL_SAFE_NO_PAD.decode(payload).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or(EmbedTokenError::Malformed)?;
+
+        if token.expires_at <= self.now() {
+            return Err(EmbedTokenError::Expired);
+        }
+
+        Ok(token)
+    }
+}
+
+/// Page frame of widget groups: no navigation, the group's critical CSS inlined and the
+/// snippet reporting the height to `embed.js`. Requests from within the widget carry the
+/// embed token (`hx-headers`).
+#[derive(Clone)]
+pub(crate) struct WidgetShell {
+    pub css: Option<String>,
+}
+
+impl Template for WidgetShell {
+    fn page(&self, context: &Context, body: Markup) -> Markup {
+        let headers: Option<String> = context.embed_token()
+            .map(|token| json!({ "X-Blandwork-Embed": token }).to_string());
+
+        html! {
+            (DOCTYPE)
+            html lang="en" class=(context.theme_class()) {
+                head {
+                    meta charset="utf-8";
+                    meta name="viewport" content="width=device-width, initial-scale=1.0";
+
+                    @if let Some(css) = self.css.as_deref() {
+                        style { (PreEscaped(css)) }
+                    }
+                }
+                body class="blandwork-widget" hx-headers=[headers] {
+                    (body)
+                    script { (PreEscaped(RESIZE_SCRIPT)) }
+                }
+            }
+        }
+    }
+}
+
+/// Serves `EMBED_SCRIPT_ROUTE`.
+pub(crate) fn router() -> Router {
+    Router::new().route(EMBED_SCRIPT_ROUTE, get(|| async {
+        ([(CONTENT_TYPE, "text/javascript"), (CACHE_CONTROL, "public, max-age=3600")], EMBED_SCRIPT).into_response()
+    }))
+}
+
+/// The token of the widget's url or, for requests from within the widget, of `EMBED_HEADER`.
+fn request_token(request: &Request) -> Option<String> {
+    if let Some(token) = request.headers().get(EMBED_HEADER).and_then(|value| value.to_str().ok()) {
+        return Some(token.to_owned());
+    }
+
+    request.uri().query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == EMBED_PARAM)
+        .map(|(_, token)| token.to_owned())
+}
+
+/// Requires a valid embed token of `widget`, frames the response for the allowed sites and
+/// keeps the main app's cookies out: none reach the handler, none are set by the response.
+#[derive(Clone)]
+pub(crate) struct WidgetLayer {
+    widget: String,
+    tokens: Option<EmbedTokens>,
+    origins: Arc<Vec<String>>,
+    frame_ancestors: HeaderValue,
+}
+
+impl WidgetLayer {
+    pub fn new(widget: &str, tokens: Option<EmbedTokens>, embed: Option<&WidgetEmbed>) -> Self {
+        let embed: WidgetEmbed = embed.cloned().unwrap_or_default();
+
+        let frame_ancestors: String = match embed.frame_ancestors.is_empty() {
+            true => "frame-ancestors 'none'".to_owned(),
+            false => format!("frame-ancestors {}", embed.frame_ancestors.join(" ")),
+        };
+
+        Self {
+            widget: widget.to_owned(),
+            tokens,
+            origins: Arc::new(embed.origins),
+            frame_ancestors: HeaderValue::from_str(&frame_ancestors).unwrap_or(HeaderValue::from_static("frame-ancestors 'none'")),
+        }
+    }
+}
+
+impl<S> Layer<S> for WidgetLayer {
+    type Service = WidgetService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WidgetService { inner, layer: self.clone() }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct WidgetService<S> {
+    inner: S,
+    layer: WidgetLayer,
+}
+
+impl<S> Service<Request> for WidgetService<S>
+where
+    S: Service<Request, Response = Response<Body>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request) -> Self::Future {
+        // the ready service is taken, its clone waits for the next call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer: WidgetLayer = self.layer.clone();
+
+        // CORS for pages embedding the widget with a script
+        let origin: Option<HeaderValue> = req.headers().get(ORIGIN)
+            .filter(|origin| layer.origins.iter().any(|allowed| *origin == allowed.as_str()))
+            .cloned();
+
+        if req.method() == Method::OPTIONS {
+            if let Some(origin) = origin {
+                return Box::pin(async move {
+                    Ok((StatusCode::NO_CONTENT, [
+                        (ACCESS_CONTROL_ALLOW_ORIGIN, origin),
+                        (ACCESS_CONTROL_ALLOW_METHODS, HeaderValue::from_static("GET, POST")),
+                        (ACCESS_CONTROL_ALLOW_HEADERS, HeaderValue::from_static(ALLOWED_HEADERS)),
+                        (VARY, HeaderValue::from_static("origin")),
+                    ]).into_response())
+                });
+            }
+        }
+
+        let verified: Result<EmbedToken, EmbedTokenError> = match (layer.tokens.as_ref(), request_token(&req)) {
+            (Some(tokens), Some(token)) => tokens.verify(&token)
+                .and_then(|embed| if embed.widget == layer.widget { Ok(embed) } else { Err(EmbedTokenError::WrongWidget) })
+                .inspect(|_| {
+                    if let Ok(value) = HeaderValue::from_str(&token) {
+                        req.headers_mut().insert(EMBED_HEADER, value);
+                    }
+                }),
+            (Some(_), None) => Err(EmbedTokenError::Malformed),
+            (None, _) => {
+                tracing::error!("widget {} requested but server.widgets.key is not set", layer.widget);
+                Err(EmbedTokenError::Signature)
+            },
+        };
+
+        let embed: EmbedToken = match verified {
+            Ok(embed) => embed,
+            Err(e) => {
+                tracing::debug!("{} refused: {e}", req.uri().path());
+                return Box::pin(async move { Ok(StatusCode::FORBIDDEN.into_response()) });
+            }
+        };
+
+        // the main app's session never rides along a third-party page
+        req.headers_mut().remove(COOKIE);
+        req.extensions_mut().insert(embed);
+
+        Box::pin(async move {
+            let mut response: Response<Body> = inner.call(req).await?;
+
+            let headers = response.headers_mut();
+            headers.remove(SET_COOKIE);
+            headers.insert(CONTENT_SECURITY_POLICY, layer.frame_ancestors.clone());
+
+            if let Some(origin) = origin {
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+                headers.append(VARY, HeaderValue::from_static("origin"));
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use axum::{
+        body::{to_bytes, Body},
+        extract::Request,
+        response::{IntoResponse, Response},
+        routing::get, Extension, Router
+    };
+    use hyper::{header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_SECURITY_POLICY, COOKIE, ORIGIN, SET_COOKIE}, StatusCode};
+    use maud::{html, Markup};
+    use tower::ServiceExt;
+
+    use crate::{
+        config::WidgetEmbed, group::RouteGroup, test::router,
+        App, Config, Context, ContextAccessor, Feature, Template, TestClock
+    };
+
+    use super::{EmbedToken, EmbedTokenError, EmbedTokens};
+
+    const KEY: &[u8] = b"widget-key";
+
+    #[derive(Clone)]
+    struct TestTemplate;
+
+    impl Template for TestTemplate {
+        fn page(&self, _: &Context, body: Markup) -> Markup {
+            html! { html { body { nav { "navigator" } (body) } } }
+        }
+    }
+
+    struct Booking;
+
+    impl Feature for Booking {
+        fn groups(&self) -> Vec<RouteGroup> {
+            let widget = Router::new().route("/booking", get(|Extension(embed): Extension<EmbedToken>, Extension(accessor): Extension<ContextAccessor>| async move {
+                let session: Option<String> = accessor.context().await.cookie("session");
+
+                let markup = html! { p { "booking for " (embed.customer) " session " (session.unwrap_or_default()) } };
+                ([(SET_COOKIE, "session=widget")], markup).into_response()
+            }));
+
+            vec![RouteGroup::widget("booking", widget).inline_css("p{margin:0}")]
+        }
+    }
+
+    fn app(clock: &TestClock) -> Router {
+        let mut config = Config::default();
+        config.server.widgets.key = Some("widget-key".to_owned());
+        config.server.widgets.embeds.insert("booking".to_owned(), WidgetEmbed {
+            frame_ancestors: vec!["https://customer.example".to_owned()],
+            origins: vec!["https://script.example".to_owned()],
+        });
+
+        router(&App::new(config, TestTemplate).with_clock(clock.clone()).register_feature(Booking).build())
+    }
+
+    async fn get_widget(app: Router, uri: &str, headers: &[(&str, &str)]) -> (Response, String) {
+        let mut request = Request::get(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body, usize::MAX).await.unwrap();
+
+        (Response::from_parts(parts, Body::empty()), String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_token_validation() {
+        let clock = TestClock::new();
+        let tokens = EmbedTokens::new(KEY).with_clock(clock.clone()).ttl(Duration::from_secs(60));
+
+        let token = tokens.issue("booking", "acme", BTreeMap::from([("theme".to_owned(), "dark".to_owned())]));
+        let embed = tokens.verify(&token).unwrap();
+        assert_eq!(embed.customer, "acme");
+        assert_eq!(embed.options["theme"], "dark");
+
+        let forged = EmbedTokens::new(b"other-key").issue("booking", "acme", BTreeMap::new());
+        assert_eq!(tokens.verify(&forged), Err(EmbedTokenError::Signature));
+        assert_eq!(tokens.verify("not-a-token"), Err(EmbedTokenError::Malformed));
+
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(tokens.verify(&token), Err(EmbedTokenError::Expired));
+    }
+
+    #[tokio::test]
+    async fn test_widget_shell_and_frame_ancestors() {
+        let clock = TestClock::new();
+        let token = EmbedTokens::new(KEY).with_clock(clock.clone()).issue("booking", "acme", BTreeMap::new());
+
+        let (response, body) = get_widget(app(&clock), &format!("/booking?embed={token}"), &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_SECURITY_POLICY], "frame-ancestors https://customer.example");
+
+        // the widget shell, not the app template
+        assert!(body.contains("<style>p{margin:0}</style>"));
+        assert!(body.contains("<p>booking for acme session </p>"));
+        assert!(body.contains("blandwork:widget-height"));
+        assert!(!body.contains("navigator"));
+
+        let (missing, _) = get_widget(app(&clock), "/booking", &[]).await;
+        assert_eq!(missing.status(), StatusCode::FORBIDDEN);
+
+        let other = EmbedTokens::new(KEY).with_clock(clock.clone()).issue("newsletter", "acme", BTreeMap::new());
+        let (wrong, _) = get_widget(app(&clock), &format!("/booking?embed={other}"), &[]).await;
+        assert_eq!(wrong.status(), StatusCode::FORBIDDEN);
+
+        clock.advance(Duration::from_secs(366 * 24 * 60 * 60));
+        let (expired, _) = get_widget(app(&clock), &format!("/booking?embed={token}"), &[]).await;
+        assert_eq!(expired.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_isolation_and_cors() {
+        let clock = TestClock::new();
+        let token = EmbedTokens::new(KEY).with_clock(clock.clone()).issue("booking", "acme", BTreeMap::new());
+
+        let (response, body) = get_widget(app(&clock), &format!("/booking?embed={token}"), &[
+            (COOKIE.as_str(), "session=main-app"),
+            (ORIGIN.as_str(), "https://script.example"),
+        ]).await;
+
+        assert!(body.contains("<p>booking for acme session </p>"));
+        assert!(!response.headers().contains_key(SET_COOKIE));
+        assert_eq!(response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "https://script.example");
+
+        let (response, _) = get_widget(app(&clock), &format!("/booking?embed={token}"), &[(ORIGIN.as_str(), "https://evil.example")]).await;
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
@@ -0,0 +1,115 @@
+use axum::{routing::get, Extension, Form};
+use blandwork::{ContextAccessor, Feature, FormErrors, HtmxRedirect, IntoResponse, Link, Router};
+use maud::{html, Markup};
+use serde::Deserialize;
+
+pub const CONTACT_ROUTE: &str = "/contact";
+pub const THANKS_ROUTE: &str = "/contact/thanks";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Message {
+    pub name: String,
+    pub email: String,
+    pub body: String,
+}
+
+impl Message {
+    fn validate(&self) -> FormErrors {
+        let mut errors = FormErrors::new().form("#contact");
+
+        if self.name.trim().is_empty() {
+            errors = errors.field("name", "is required");
+        }
+
+        if !self.email.contains('@') {
+            errors = errors.field("email", "is not an email address");
+        }
+
+        if self.body.trim().len() < 10 {
+            errors = errors.field("body", "is too short");
+        }
+
+        errors
+    }
+}
+
+/// A form validated on the server, errors are re-rendered in place and announced
+/// through `blandwork:form-error`.
+#[derive(Clone, Default)]
+pub struct ContactFeature;
+
+impl ContactFeature {
+    async fn page() -> Markup {
+        form(&Message::default(), &FormErrors::new())
+    }
+
+    async fn submit(
+        Extension(accessor): Extension<ContextAccessor>,
+        redirect: HtmxRedirect,
+        Form(message): Form<Message>
+    ) -> impl IntoResponse {
+        let errors: FormErrors = message.validate();
+
+        if errors.is_empty() {
+            tracing::info!("message from {}", message.email);
+            return redirect.location(THANKS_ROUTE).into_response();
+        }
+
+        let markup: Markup = form(&message, &errors);
+        accessor.context().await.add_error_trigger(errors);
+
+        markup.into_response()
+    }
+
+    async fn thanks() -> Markup {
+        html!{
+            p { "Thanks, we'll get back to you." }
+        }
+    }
+}
+
+fn field(name: &str, label: &str, value: &str, errors: &FormErrors) -> Markup {
+    let messages: &[String] = errors.messages(name);
+
+    html!{
+        label {
+            (label)
+            input type="text" name=(name) value=(value) aria-invalid=[(!messages.is_empty()).then_some("true")];
+        }
+        @for message in messages {
+            p .error { (label) " " (message) }
+        }
+    }
+}
+
+fn form(message: &Message, errors: &FormErrors) -> Markup {
+    html!{
+        form #contact action=(CONTACT_ROUTE) method="post" hx-target="#content" {
+            (field("name", "Name", &message.name, errors))
+            (field("email", "Email", &message.email, errors))
+            (field("body", "Message", &message.body, errors))
+            button.btn-primary type="submit" { "Send" }
+        }
+    }
+}
+
+impl Feature for ContactFeature {
+    fn link(&self) -> Option<Link> {
+        Some(Link {
+            title: "Contact".to_string(),
+            label: "Contact".to_string(),
+            active: false,
+            route: CONTACT_ROUTE.to_string(),
+            icon: None,
+            css: None,
+            remember: false
+        })
+    }
+
+    fn web(&self) -> Option<Router> {
+        Some(Router::new()
+            .route(CONTACT_ROUTE, get(ContactFeature::page).post(ContactFeature::submit))
+            .route(THANKS_ROUTE, get(ContactFeature::thanks)))
+    }
+}
@@ -1,13 +1,17 @@
+use contact::ContactFeature;
+use search::Pages;
 use template::VanillaTemplate;
 
-use blandwork::{App, Config, Context, ContextAccessor, Feature, HeaderMap, IntoResponse, Link, Router, StatusCode};
+use blandwork::{App, Config, Context, ContextAccessor, Feature, Features, HeaderMap, IntoResponse, Link, NoPool, Router, SearchFeature, StatusCode};
 use maud::{html, Markup};
 use axum::routing::get;
 use axum::Extension;
 use serde::Serialize;
 
+mod contact;
 mod template;
 mod navigator;
+mod search;
 
 
 // Say we want to send a custom event from our feature to HTMX.
@@ -100,6 +104,16 @@ impl SampleFeature {
         }
     }
 
+    // pages of the feature which do not exist, wrapped in the shell like any other
+    async fn not_found() -> impl IntoResponse {
+        (StatusCode::NOT_FOUND, html!{
+            div class="flex flex-col justify-start items-center w-full" {
+                h2 { "Nothing to see here" }
+                a href="/sample/web" { "Back to the sample" }
+            }
+        })
+    }
+
     async fn select() -> Markup {
         return html!{
             b { "outer content (should not see this)" }
@@ -114,12 +128,13 @@ impl SampleFeature {
 impl Feature for SampleFeature {
     fn link(&self) -> Option<Link> {
         Some(Link {
-            title: "A".to_string(),
-            label: "A".to_string(),
+            title: "Sample".to_string(),
+            label: "Sample".to_string(),
             active: false,
             route: "/sample/web".to_string(),
             icon: None,
-            css: None
+            css: None,
+            remember: false
         })
     }
 
@@ -129,6 +144,7 @@ impl Feature for SampleFeature {
             .route("/sample/more", get(SampleFeature::more))
             .route("/sample/select-", get(SampleFeature::select))
             .route("/sample/other", get(SampleFeature::other))
+            .route("/sample/*missing", get(SampleFeature::not_found))
             
             // a feature has a choice to use the framework middleware
             // or to be a vanilla handler
@@ -139,11 +155,184 @@ impl Feature for SampleFeature {
     }
 }
 
-#[tokio::main]
-async fn main() {
-    App::new(Config::default(), VanillaTemplate::default())
+/// Every feature of the sample, not built yet.
+fn features(config: Config) -> App<NoPool, Features, VanillaTemplate> {
+    App::new(config, VanillaTemplate::default())
         .register_feature_default::<SampleFeature>()
+        .register_feature_default::<ContactFeature>()
+        .register_feature(SearchFeature::new(Pages))
         .apply_fallback()
-        .build()
-        .run().await;
-}
\ No newline at end of file
+}
+
+/// Every feature of the sample, built and run by `main` and driven in process by the tests.
+fn app(config: Config) -> App<NoPool, Features, VanillaTemplate> {
+    features(config).build()
+}
+
+#[tokio::main]
+async fn main() {
+    // `sample-web check` prints the startup problems as JSON and fails when there is one
+    if std::env::args().nth(1).as_deref() == Some("check") {
+        let report = features(Config::default()).check();
+        println!("{}", report.to_json());
+        std::process::exit(if report.is_empty() { 0 } else { 1 });
+    }
+
+    app(Config::default()).run().await;
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::{to_bytes, Body}, extract::Request, http::header::{CONTENT_TYPE, LOCATION}};
+    use blandwork::{test::{crawl, router}, Config, ContextAccessor, Router, StatusCode, TemplateLayer};
+    use maud::html;
+    use tower::ServiceExt;
+
+    use crate::{app, template::VanillaTemplate};
+
+    fn sample() -> Router {
+        router(&app(Config::default()))
+    }
+
+    // status, headers and body, `htmx` as a boosted navigation or submission sends it
+    async fn send(request: axum::http::request::Builder, body: &str, htmx: bool) -> (StatusCode, axum::http::HeaderMap, String) {
+        let request = match htmx {
+            true => request.header("HX-Request", "true").header("HX-Boosted", "true"),
+            false => request,
+        };
+
+        let response = sample().oneshot(request.body(Body::from(body.to_owned())).unwrap()).await.unwrap();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, headers, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn get(path: &str, htmx: bool) -> (StatusCode, String) {
+        let (status, _, body) = send(Request::get(path), "", htmx).await;
+        (status, body)
+    }
+
+    async fn post(path: &str, form: &str, htmx: bool) -> (StatusCode, axum::http::HeaderMap, String) {
+        let request = Request::post(path).header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+        send(request, form, htmx).await
+    }
+
+    // fails when a route is renamed without updating the links rendered for it
+    #[tokio::test]
+    async fn test_navigation_links() {
+        let broken = crawl(sample(), "/sample/web", 3).await;
+
+        assert!(broken.is_empty(), "{broken:#?}");
+    }
+
+    // every page links to every feature, and each of them answers with a page
+    #[tokio::test]
+    async fn test_navigator() {
+        for route in ["/sample/web", "/contact", "/search"] {
+            let (status, html) = get(route, false).await;
+
+            assert_eq!(status, StatusCode::OK, "{route}");
+            assert!(html.contains(r#"<nav id="navigator""#), "{route}");
+
+            for link in ["/sample/web", "/contact", "/search"] {
+                assert!(html.contains(&format!(r#"href="{link}""#)), "{route} links to {link}");
+            }
+        }
+
+        let (_, html) = get("/contact", false).await;
+        assert!(html.contains("<title>Contact</title>"));
+    }
+
+    #[tokio::test]
+    async fn test_shell_or_fragment() {
+        let (_, page) = get("/sample/other", false).await;
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<b>Some Other Page!</b>"));
+
+        let (_, fragment) = get("/sample/other", true).await;
+        assert!(!fragment.contains("<html"));
+        assert!(!fragment.contains("navigator"));
+        assert!(fragment.contains("<b>Some Other Page!</b>"));
+    }
+
+    #[tokio::test]
+    async fn test_trigger() {
+        let (_, headers, _) = send(Request::get("/sample/more"), "", true).await;
+        assert!(headers["HX-Trigger"].to_str().unwrap().contains("MY_FEATURE_TRIGGER"));
+
+        // and the shell has a listener for it
+        let (_, page) = get("/sample/web", false).await;
+        assert!(page.contains(r#"addEventListener("MY_FEATURE_TRIGGER""#));
+        assert!(page.contains(r#"<output id="trigger-log"></output>"#));
+    }
+
+    #[tokio::test]
+    async fn test_contact_form() {
+        let (status, html) = get("/contact", false).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains(r#"<form id="contact" action="/contact" method="post""#));
+
+        // invalid, the form comes back with its errors
+        let (status, headers, html) = post("/contact", "name=&email=nope&body=hi", true).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["HX-Retarget"], "#contact");
+        assert!(headers["HX-Trigger-After-Settle"].to_str().unwrap().contains("blandwork:form-error"));
+        assert!(html.contains("Name is required"));
+        assert!(html.contains("Email is not an email address"));
+        assert!(html.contains("Message is too short"));
+        assert!(html.contains(r#"value="nope""#));
+        assert!(!html.contains("<html"));
+
+        // valid, on to the thanks page
+        let valid = "name=Ada&email=ada%40example.com&body=Hello%20there%2C%20sample";
+
+        let (status, headers, _) = post("/contact", valid, true).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["HX-Location"], "/contact/thanks");
+
+        let (status, headers, _) = post("/contact", valid, false).await;
+        assert!(status.is_redirection());
+        assert_eq!(headers[LOCATION], "/contact/thanks");
+
+        assert_eq!(get("/contact/thanks", false).await.0, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_search_fragment() {
+        let (status, html) = get("/search/results?q=form", true).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert!(html.contains(r#"<a href="/contact">Contact</a>"#));
+        assert!(!html.contains("<html"));
+
+        let (_, html) = get("/search?q=other", false).await;
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(r#"<a href="/sample/other">Other</a>"#));
+    }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        // themed within the sample
+        let (status, html) = get("/sample/missing", false).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(html.contains(r#"<nav id="navigator""#));
+        assert!(html.contains("Nothing to see here"));
+
+        // the app's fallback elsewhere
+        assert_eq!(get("/missing", false).await, (StatusCode::NOT_FOUND, "nothing to see here".to_owned()));
+    }
+
+    // the layout as served, update snapshots/shell.html along with intended changes
+    #[tokio::test]
+    async fn test_shell_snapshot() {
+        let accessor = ContextAccessor::from_request(&Request::get("/sample/web").body(Body::empty()).unwrap());
+        let context = accessor.context().await;
+
+        let html = TemplateLayer::new(VanillaTemplate::default())
+            .render_shell(&context, html! { p { "Hello" } })
+            .into_string();
+
+        assert_eq!(html, include_str!("snapshots/shell.html").trim_end());
+    }
+}
@@ -1,9 +1,10 @@
-use std::cmp::Reverse;
 use blandwork::Link;
 use maud::{html, Markup};
 
 use crate::Context;
 
+/// Top-level links of the registered features, see `Template::register`.
+/// Which one is active follows the request, see `Link::is_active`.
 #[derive(Debug, Clone)]
 pub struct Navigator {
     links: Vec<Link>
@@ -18,31 +19,16 @@ impl Navigator {
         return self.links.len();
     }
 
-    pub fn set_current(&mut self, path: &str) {
-        // let mut links: Vec<_> = self.links.clone().into_iter().collect();
-        self.links.sort_by_key(|link| Reverse(link.route.clone()));
-
-        self.links.iter_mut().for_each(|x| {
-            x.active = false;
-        });
-
-        for link in self.links.iter_mut() {
-            tracing::info!("checking link {:#?} with {:#?}", link, path);
-            if link.route.starts_with(path) {
-                link.active = true;
-                break;
-            }
-        }
-    }
-
     pub fn add_link(&mut self, link: Link) {
         self.links.push(link)
     }
 
-    pub fn current_link(&self) -> Option<&Link> {
-        self.links.iter().find(|&x| x.active)
+    /// The most specific link covering the page, `/sample/web` over `/sample`.
+    pub fn current_link(&self, context: &Context) -> Option<&Link> {
+        self.links.iter()
+            .filter(|link| link.is_active(context))
+            .max_by_key(|link| link.route.len())
     }
-
 }
 
 impl Navigator {
@@ -58,4 +44,4 @@ impl Default for Navigator {
     fn default() -> Self {
         Self { links: vec![] }
     }
-}
\ No newline at end of file
+}
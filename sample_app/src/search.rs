@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+use blandwork::{HandlerError, Hit, Page, Searcher};
+
+/// Searches the sample's own pages, backing `SearchFeature`.
+pub struct Pages;
+
+const PAGES: [(&str, &str, &str); 4] = [
+    ("Sample", "/sample/web", "The sample feature's landing page"),
+    ("Other", "/sample/other", "Some other page"),
+    ("Contact", "/contact", "A form validated on the server"),
+    ("Search", "/search", "This search"),
+];
+
+#[async_trait]
+impl Searcher for Pages {
+    async fn search(&self, query: &str, page: Page) -> Result<Vec<Hit>, HandlerError> {
+        let query: String = query.to_lowercase();
+
+        Ok(PAGES.iter()
+            .filter(|(title, _, snippet)| title.to_lowercase().contains(&query) || snippet.to_lowercase().contains(&query))
+            .skip(page.offset())
+            .take(page.size)
+            .map(|(title, url, snippet)| Hit::new(*title, *url).snippet(*snippet))
+            .collect())
+    }
+}
@@ -1,5 +1,5 @@
-use blandwork::{Context, Template};
-use maud::{html, Markup, DOCTYPE};
+use blandwork::{Context, Feature, Template};
+use maud::{html, Markup, PreEscaped, DOCTYPE};
 
 use crate::navigator::Navigator;
 
@@ -23,7 +23,7 @@ impl VanillaTemplate{
 
                 link
                     rel="stylesheet"
-                    href="/web/dist/output.css" {}
+                    href=(context.asset("dist/output.css")) {}
 
                 // For now use the CDN and load everything. 
                 // Optimize for performance later..
@@ -31,18 +31,29 @@ impl VanillaTemplate{
                 script src="https://unpkg.com/htmx.org@1.9.9" {}
                 
                 title {
-                    (context.title())
+                    (self.navigator.current_link(context).map(|link| link.title.as_str()).unwrap_or("Sample"))
                 }
             }
         }
     }
 }
 
+// consumes the trigger sent by `SampleFeature::more`
+const TRIGGER_SCRIPT: &str = r#"document.body.addEventListener("MY_FEATURE_TRIGGER", (evt) => {
+    document.getElementById("trigger-log").textContent = evt.detail.data;
+});"#;
+
 impl Template for VanillaTemplate {
+    fn register(&mut self, feature: &Box<dyn Feature>) {
+        if let Some(link) = feature.link() {
+            self.navigator.add_link(link);
+        }
+    }
+
     fn page(&self, context: &Context, body: Markup) -> Markup {
         html! {
             (DOCTYPE)
-            html lang="en" {
+            html lang="en" class=(context.theme_class()) {
                 // <head>
                 (self.head(context))
 
@@ -53,13 +64,10 @@ impl Template for VanillaTemplate {
                     }
                     div #root class="h-lvh bg-pink-500 lg:bg-green-500 md:bg-red-500 p-4" {
 
-                        // div #navigator
-                        //     class="flex flex-col items-center justify-start p-2"
-                        //     hx-boost="true"
-                        //     hx-target="#content"
-                        //     hx-swap="innerHTML" {
-                        //     (self.navigator.render(context))
-                        // }
+                        nav #navigator
+                            class="flex flex-row items-center justify-start p-2" {
+                            (self.navigator.render(context))
+                        }
 
                         div 
                             class="flex flex-col justify-start w-full" {
@@ -120,11 +128,14 @@ impl Template for VanillaTemplate {
                             div #content {
                                 (body)
                             }
+
+                            output #trigger-log {}
                         }
                     }
                 }
 
-                script src="/web/htmx_integration.js" {}
+                script src=(context.asset("htmx_integration.js")) {}
+                script { (PreEscaped(TRIGGER_SCRIPT)) }
             }
         }
     }
@@ -0,0 +1,39 @@
+// widget loader for third-party pages:
+// <script src="https://app.example/_blandwork/embed.js" data-widget="/booking?embed=TOKEN" async></script>
+// data-mode="script" injects the fragment into a div instead of an iframe (needs the site in `origins`)
+(function(){
+    const script = document.currentScript;
+    if (!script || !script.dataset.widget) {
+        return;
+    }
+
+    const origin = new URL(script.src).origin;
+    const url = new URL(script.dataset.widget, origin);
+
+    if (script.dataset.mode === "script") {
+        const container = document.createElement("div");
+        container.className = "blandwork-widget";
+        script.after(container);
+
+        fetch(url, { headers: { "HX-Request": "true", "HX-Boosted": "true" } })
+            .then((response) => response.ok ? response.text() : "")
+            .then((html) => { container.innerHTML = html; });
+        return;
+    }
+
+    const frame = document.createElement("iframe");
+    frame.src = url.toString();
+    frame.title = script.dataset.title || "widget";
+    frame.style.cssText = "width:100%;border:0;overflow:hidden";
+    frame.setAttribute("scrolling", "no");
+    script.after(frame);
+
+    window.addEventListener("message", function(evt){
+        if (evt.origin !== origin || evt.source !== frame.contentWindow) {
+            return;
+        }
+        if (evt.data && evt.data.type === "blandwork:widget-height") {
+            frame.style.height = `${evt.data.height}px`;
+        }
+    });
+})();
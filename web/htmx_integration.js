@@ -17,7 +17,8 @@ document.body.addEventListener("navigator", function(evt){
         for (let link of links) {
             console.log(`${link.href} === ${evt.detail.route}`);
             // Check if the href value equals x
-            const linkPath = new URL(link.href).pathname;
+            // links leading back to a remembered page (Link::remember) keep their route aside
+            const linkPath = link.dataset.canonical || new URL(link.href).pathname;
             if (linkPath === evt.detail.route) {
                 console.log(`MATCHED ${evt.detail}`);
                 // Add the class if href equals x
@@ -33,4 +34,463 @@ document.body.addEventListener("navigator", function(evt){
             }
         }
     }
-})
\ No newline at end of file
+})
+// bot guard proof of work: find a counter whose sha256 with the form token has enough leading zero bits
+function leadingZeroBits(bytes) {
+    let bits = 0;
+    for (const byte of bytes) {
+        if (byte === 0) {
+            bits += 8;
+            continue;
+        }
+        bits += Math.clz32(byte) - 24;
+        break;
+    }
+    return bits;
+}
+
+async function solveProofOfWork(form) {
+    const field = form.querySelector("input[data-bw-pow]");
+    const token = form.querySelector("input[name='_bw_guard']");
+    if (!field || !token || field.value !== "") {
+        return;
+    }
+
+    const difficulty = parseInt(field.dataset.bwPow, 10);
+    const encoder = new TextEncoder();
+
+    for (let counter = 0; ; counter++) {
+        const digest = await crypto.subtle.digest("SHA-256", encoder.encode(`${token.value}:${counter}`));
+        if (leadingZeroBits(new Uint8Array(digest)) >= difficulty) {
+            field.value = `${counter}`;
+            return;
+        }
+    }
+}
+
+// htmx waits for the returned promise before sending the request
+document.body.addEventListener("htmx:confirm", function(evt){
+    const form = evt.target.closest("form");
+    if (!form || !form.querySelector("input[data-bw-pow]")) {
+        return;
+    }
+
+    evt.preventDefault();
+    solveProofOfWork(form).then(() => evt.detail.issueRequest());
+})
+
+// accessibility: move focus once swapped content settled (Context::focus)
+document.body.addEventListener("blandwork:focus", function(evt){
+    const target = document.querySelector(evt.detail.selector);
+    if (!target) {
+        return;
+    }
+
+    if (!target.hasAttribute("tabindex") && target.tabIndex < 0) {
+        target.setAttribute("tabindex", "-1");
+    }
+    target.focus({ preventScroll: false });
+})
+
+// accessibility: announcements for pages rendered without the live regions
+document.body.addEventListener("blandwork:announce", function(evt){
+    if (document.getElementById("blandwork-announcer")) {
+        return;
+    }
+
+    const announcements = Array.isArray(evt.detail.value) ? evt.detail.value : [evt.detail];
+    for (const announcement of announcements) {
+        const region = document.createElement("div");
+        region.setAttribute("aria-live", announcement.politeness);
+        region.setAttribute("role", "status");
+        region.style.cssText = "position:absolute;width:1px;height:1px;overflow:hidden;clip:rect(0,0,0,0)";
+        document.body.appendChild(region);
+        setTimeout(() => { region.textContent = announcement.message; }, 50);
+    }
+})
+
+// offline tolerance (server.offline): submissions which failed on the network are queued
+// in localStorage and replayed with their idempotency key once the connection is back
+const offlineMeta = document.querySelector("meta[name='blandwork-offline']");
+const OFFLINE_QUEUE = "blandwork-offline-queue";
+
+function offlineQueue() {
+    try {
+        return JSON.parse(localStorage.getItem(OFFLINE_QUEUE) || "[]");
+    } catch (e) {
+        return [];
+    }
+}
+
+function saveOfflineQueue(queue) {
+    localStorage.setItem(OFFLINE_QUEUE, JSON.stringify(queue));
+}
+
+function offlineAnnounce(message) {
+    const region = document.getElementById("blandwork-announcer");
+    if (region) {
+        region.textContent = message;
+        return;
+    }
+    document.body.dispatchEvent(new CustomEvent("blandwork:announce", { detail: { message: message, politeness: "polite" } }));
+}
+
+function isQueueable(verb) {
+    return ["post", "put", "patch", "delete"].includes(verb);
+}
+
+let replaying = false;
+
+async function replayOfflineQueue() {
+    if (replaying || !navigator.onLine) {
+        return;
+    }
+    replaying = true;
+
+    try {
+        for (const entry of offlineQueue()) {
+            const target = entry.target ? document.querySelector(entry.target) : null;
+            const headers = { "Idempotency-Key": entry.key, "X-Blandwork-Replayed": "true" };
+            if (entry.optimistic) {
+                headers["X-Blandwork-Optimistic"] = entry.optimistic;
+            }
+
+            try {
+                if (target) {
+                    // swapped like the original request, conflict fragments included
+                    await htmx.ajax(entry.verb.toUpperCase(), entry.path, { target: target, values: entry.values, headers: headers });
+                } else {
+                    const response = await fetch(entry.path, {
+                        method: entry.verb.toUpperCase(),
+                        headers: Object.assign({ "HX-Request": "true", "Content-Type": "application/x-www-form-urlencoded" }, headers),
+                        body: new URLSearchParams(entry.values),
+                    });
+                    if (response.status === 409 && response.headers.has("Retry-After")) {
+                        continue;
+                    }
+                    if (!response.ok) {
+                        actionFailed(response.headers.get("HX-Trigger"), entry.optimistic);
+                    }
+                }
+            } catch (e) {
+                // still offline, keep the rest for later
+                break;
+            }
+
+            saveOfflineQueue(offlineQueue().filter((queued) => queued.key !== entry.key));
+            offlineAnnounce("Saved changes have been sent.");
+        }
+    } finally {
+        replaying = false;
+    }
+}
+
+if (offlineMeta) {
+    const retry = parseInt((offlineMeta.content.match(/retry=(\d+)/) || [])[1] || "30000", 10);
+
+    document.body.addEventListener("htmx:configRequest", function(evt){
+        if (isQueueable(evt.detail.verb) && !evt.detail.headers["Idempotency-Key"]) {
+            evt.detail.headers["Idempotency-Key"] = crypto.randomUUID();
+        }
+    })
+
+    document.body.addEventListener("htmx:sendError", function(evt){
+        const config = evt.detail.requestConfig;
+        if (!config || !isQueueable(config.verb) || config.headers["X-Blandwork-Replayed"]) {
+            return;
+        }
+
+        const values = {};
+        const parameters = config.parameters instanceof FormData ? Object.fromEntries(config.parameters) : config.parameters;
+        for (const [name, value] of Object.entries(parameters || {})) {
+            if (typeof value === "string") {
+                values[name] = value;
+            }
+        }
+
+        const queue = offlineQueue();
+        queue.push({
+            key: config.headers["Idempotency-Key"],
+            verb: config.verb,
+            path: config.path,
+            values: values,
+            target: config.target && config.target.id ? `#${config.target.id}` : null,
+            optimistic: config.headers["X-Blandwork-Optimistic"] || null,
+        });
+        saveOfflineQueue(queue);
+
+        offlineAnnounce("Saved locally, will retry when the connection is back.");
+    })
+
+    window.addEventListener("online", replayOfflineQueue);
+    setInterval(replayOfflineQueue, retry);
+    replayOfflineQueue();
+}
+
+// out of order swaps (server.sequencing): requests are numbered per target, the server
+// echoes the number and a response older than the last one swapped into its target is dropped
+if (document.querySelector("meta[name='blandwork-sequence']")) {
+    const issued = new WeakMap();
+    const applied = new WeakMap();
+
+    document.body.addEventListener("htmx:configRequest", function(evt){
+        const target = evt.detail.target;
+        if (!target) {
+            return;
+        }
+
+        const sequence = (issued.get(target) || 0) + 1;
+        issued.set(target, sequence);
+        evt.detail.headers["X-Blandwork-Seq"] = String(sequence);
+    })
+
+    document.body.addEventListener("htmx:beforeSwap", function(evt){
+        const target = evt.detail.target;
+        const sequence = parseInt(evt.detail.xhr.getResponseHeader("X-Blandwork-Seq") || "", 10);
+        if (!target || isNaN(sequence)) {
+            return;
+        }
+
+        if (sequence < (applied.get(target) || 0)) {
+            evt.detail.shouldSwap = false;
+            return;
+        }
+        applied.set(target, sequence);
+    })
+}
+
+// form validation (Context::add_error_trigger): focus the first invalid input and announce the errors
+document.body.addEventListener("blandwork:form-error", function(evt){
+    const detail = evt.detail;
+    if (!detail || !Array.isArray(detail.errors) || detail.errors.length === 0) {
+        return;
+    }
+
+    const form = (detail.form && document.querySelector(detail.form)) || document;
+    const first = form.querySelector(`[name="${CSS.escape(detail.errors[0].field)}"]`);
+    if (first) {
+        first.scrollIntoView({ block: "center" });
+        first.focus({ preventScroll: true });
+    }
+
+    const count = detail.errors.length;
+    const message = `${count} field${count === 1 ? " needs" : "s need"} attention`;
+    const region = document.getElementById("blandwork-announcer-assertive");
+    if (region) {
+        region.textContent = message;
+    }
+})
+
+// optimistic deletes (optimistic_delete_attrs): the row is hidden when the request is sent and
+// shown again by a failure (ActionFailure), soft deletes (SoftDeleted) offer an undo
+function toast(message, action) {
+    const toast = document.createElement("div");
+    toast.className = "blandwork-toast";
+    toast.setAttribute("role", "alert");
+    toast.textContent = message;
+
+    if (action) {
+        const button = document.createElement("button");
+        button.type = "button";
+        button.textContent = action.label;
+        button.addEventListener("click", function(){
+            toast.remove();
+            action.run();
+        });
+        toast.appendChild(button);
+    }
+
+    document.body.appendChild(toast);
+    setTimeout(() => toast.remove(), action ? 8000 : 5000);
+}
+
+function showRow(selector) {
+    const row = selector ? document.querySelector(selector) : null;
+    if (row) {
+        row.hidden = false;
+    }
+}
+
+// a failure envelope out of an HX-Trigger value, for responses htmx did not process (offline replays)
+function actionFailed(trigger, selector) {
+    let detail = null;
+    try {
+        detail = JSON.parse(trigger || "{}")["blandwork:action-failed"];
+    } catch (e) {}
+
+    showRow((detail && detail.target) || selector);
+    toast((detail && detail.message) || "Something went wrong, please try again.");
+}
+
+document.body.addEventListener("htmx:configRequest", function(evt){
+    const selector = evt.detail.elt.dataset.blandworkOptimistic;
+    const row = selector ? document.querySelector(selector) : null;
+    if (row) {
+        row.hidden = true;
+        evt.detail.headers["X-Blandwork-Optimistic"] = selector;
+    }
+})
+
+document.body.addEventListener("htmx:afterRequest", function(evt){
+    const selector = evt.detail.elt.dataset.blandworkOptimistic;
+    if (!selector || evt.detail.successful) {
+        return;
+    }
+
+    // kept hidden while queued for a replay
+    if (evt.detail.xhr.status === 0 && offlineMeta) {
+        return;
+    }
+    showRow(selector);
+})
+
+document.body.addEventListener("blandwork:action-failed", function(evt){
+    showRow(evt.detail.target);
+    toast(evt.detail.message);
+})
+
+document.body.addEventListener("blandwork:soft-deleted", function(evt){
+    const detail = evt.detail;
+    toast(detail.message, {
+        label: "Undo",
+        run: () => htmx.ajax("POST", detail.undo, { swap: "none" }).then(() => showRow(detail.target)),
+    });
+})
+
+// head assets (Feature::head_assets): a fragment appends the assets it needs to the head out
+// of band, the ones the page already has are dropped so nothing loads twice
+document.body.addEventListener("htmx:oobBeforeSwap", function(evt){
+    const fragment = evt.detail.fragment || evt.detail.elt;
+    if (!fragment || !fragment.querySelectorAll) {
+        return;
+    }
+
+    for (const asset of fragment.querySelectorAll("[data-blandwork-asset]")) {
+        const name = CSS.escape(asset.dataset.blandworkAsset);
+        if (document.head.querySelector(`[data-blandwork-asset="${name}"]`) || document.body.querySelector(`[data-blandwork-asset="${name}"]`)) {
+            asset.remove();
+        }
+    }
+})
+
+// created rows (Context::redirect_to_row): the highlight of the revealed row fades once it settled
+function clearHighlights(root) {
+    for (const row of root.querySelectorAll(".blandwork-highlight")) {
+        setTimeout(() => row.classList.remove("blandwork-highlight"), 2000);
+    }
+}
+
+document.body.addEventListener("htmx:afterSettle", function(evt){
+    clearHighlights(evt.detail.elt || document);
+})
+
+clearHighlights(document);
+
+// navigation trees (Feature::nav_tree): boosted navigation moves the active node
+document.body.addEventListener("blandwork:nav-active", function(evt){
+    if (!evt.detail || !evt.detail.route) {
+        return;
+    }
+
+    for (const link of document.querySelectorAll(".blandwork-nav-tree [data-nav-route]")) {
+        const active = link.dataset.navRoute === evt.detail.route;
+        link.classList.toggle("active", active);
+        if (active) {
+            link.setAttribute("aria-current", "page");
+        } else {
+            link.removeAttribute("aria-current");
+        }
+    }
+})
+
+// remembered pages (Link::remember): a long press or alt click on the link resets the view
+// to the canonical route, which then becomes the remembered page
+function resetView(link) {
+    htmx.ajax("GET", link.dataset.canonical, { target: link.getAttribute("hx-target") || "#content", swap: "innerHTML" })
+        .then(() => history.pushState({}, "", link.dataset.canonical));
+}
+
+let pressTimer = null;
+
+document.body.addEventListener("pointerdown", function(evt){
+    const link = evt.target.closest("a[data-canonical]");
+    if (!link) {
+        return;
+    }
+
+    pressTimer = setTimeout(() => {
+        pressTimer = null;
+        link.dataset.resetting = "true";
+        resetView(link);
+    }, 600);
+})
+
+for (const type of ["pointerup", "pointerleave", "pointercancel"]) {
+    document.body.addEventListener(type, function(){
+        clearTimeout(pressTimer);
+        pressTimer = null;
+    })
+}
+
+document.body.addEventListener("click", function(evt){
+    const link = evt.target.closest("a[data-canonical]");
+    if (!link) {
+        return;
+    }
+
+    // the click ending a long press
+    if (link.dataset.resetting) {
+        delete link.dataset.resetting;
+        evt.preventDefault();
+        evt.stopPropagation();
+        return;
+    }
+
+    if (evt.altKey) {
+        evt.preventDefault();
+        evt.stopPropagation();
+        resetView(link);
+    }
+}, true)
+
+// draft restore banner (Drafts::restore_banner): fills the draft's form, the one with the
+// form id as its id or autosaving to it, with the urlencoded fields of the banner
+function draftForm(formId) {
+    return document.getElementById(formId)
+        || document.querySelector(`form[hx-post="/_blandwork/drafts/${CSS.escape(formId)}"]`);
+}
+
+document.body.addEventListener("click", function(evt){
+    const button = evt.target.closest("button[data-draft-restore]");
+    const banner = button && button.closest("[data-draft-fields]");
+    const form = banner && draftForm(button.dataset.draftRestore);
+    if (!form) {
+        return;
+    }
+
+    const fields = new URLSearchParams(banner.dataset.draftFields);
+    const seen = {};
+
+    for (const element of form.elements) {
+        if (!element.name || !fields.has(element.name) || element.type === "file") {
+            continue;
+        }
+
+        const values = fields.getAll(element.name);
+
+        if (element.type === "checkbox" || element.type === "radio") {
+            element.checked = values.includes(element.value);
+        } else if (element.multiple) {
+            for (const option of element.options) {
+                option.selected = values.includes(option.value);
+            }
+        } else {
+            // repeated names take their values in order
+            const index = seen[element.name] || 0;
+            seen[element.name] = index + 1;
+            element.value = values[index] ?? "";
+        }
+    }
+
+    banner.remove();
+})
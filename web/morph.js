@@ -0,0 +1,124 @@
+// morph swaps (hx-swap / HX-Reswap "morph:innerHTML", "morph:outerHTML"), served at
+// /_blandwork/morph.js when server.morph.enabled. Children are matched by id first,
+// then by position and tag, so list items keep their DOM nodes, focus and scroll.
+(function () {
+    function sameNode(a, b) {
+        if (a.nodeType !== b.nodeType) {
+            return false;
+        }
+        if (a.nodeType !== Node.ELEMENT_NODE) {
+            return true;
+        }
+        return a.tagName === b.tagName && a.id === b.id;
+    }
+
+    function morphAttributes(from, to) {
+        for (const attribute of Array.from(from.attributes)) {
+            if (!to.hasAttribute(attribute.name)) {
+                from.removeAttribute(attribute.name);
+            }
+        }
+        for (const attribute of Array.from(to.attributes)) {
+            if (from.getAttribute(attribute.name) !== attribute.value) {
+                from.setAttribute(attribute.name, attribute.value);
+            }
+        }
+    }
+
+    function morphNode(from, to) {
+        if (from.nodeType !== Node.ELEMENT_NODE) {
+            if (from.nodeValue !== to.nodeValue) {
+                from.nodeValue = to.nodeValue;
+            }
+            return;
+        }
+
+        morphAttributes(from, to);
+
+        // what the user is typing wins over the server's value
+        const focused = from === document.activeElement;
+        if ((from.tagName === "INPUT" || from.tagName === "TEXTAREA") && !focused) {
+            from.value = to.value;
+        }
+
+        morphChildren(from, to);
+    }
+
+    function morphChildren(from, to) {
+        const byId = new Map();
+        for (const child of Array.from(from.children)) {
+            if (child.id) {
+                byId.set(child.id, child);
+            }
+        }
+
+        let cursor = from.firstChild;
+
+        for (const next of Array.from(to.childNodes)) {
+            let match = null;
+
+            if (next.nodeType === Node.ELEMENT_NODE && next.id && byId.has(next.id) && sameNode(byId.get(next.id), next)) {
+                match = byId.get(next.id);
+                byId.delete(next.id);
+            } else if (cursor && sameNode(cursor, next)) {
+                match = cursor;
+            }
+
+            if (match) {
+                if (match !== cursor) {
+                    from.insertBefore(match, cursor);
+                } else {
+                    cursor = cursor.nextSibling;
+                }
+                morphNode(match, next);
+            } else {
+                from.insertBefore(document.importNode(next, true), cursor);
+            }
+        }
+
+        while (cursor) {
+            const stale = cursor;
+            cursor = cursor.nextSibling;
+            from.removeChild(stale);
+        }
+    }
+
+    function register() {
+        htmx.defineExtension("morph", {
+            isInlineSwap: function (swapStyle) {
+                return swapStyle === "morph" || swapStyle === "morph:outerHTML";
+            },
+            handleSwap: function (swapStyle, target, fragment) {
+                if (swapStyle === "morph" || swapStyle === "morph:outerHTML") {
+                    const next = fragment.firstElementChild;
+                    if (next && !sameNode(target, next)) {
+                        // a different element (display for a form), nothing to keep
+                        const replacement = document.importNode(next, true);
+                        target.replaceWith(replacement);
+                        return [replacement];
+                    }
+                    if (next) {
+                        morphNode(target, next);
+                    }
+                    return [target];
+                }
+                if (swapStyle === "morph:innerHTML") {
+                    morphChildren(target, fragment);
+                    return [target];
+                }
+            }
+        });
+
+        // extensions are looked up per request, the body carries it for every element
+        const extensions = (document.body.getAttribute("hx-ext") || "").split(",").map(function (e) { return e.trim(); });
+        if (extensions.indexOf("morph") === -1) {
+            document.body.setAttribute("hx-ext", extensions.concat(["morph"]).filter(Boolean).join(","));
+        }
+    }
+
+    if (document.readyState === "loading") {
+        document.addEventListener("DOMContentLoaded", register);
+    } else {
+        register();
+    }
+})();
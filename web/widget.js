@@ -0,0 +1,19 @@
+// widget shell: report the document height to the embedding page (embed.js resizes the iframe)
+(function(){
+    if (window.parent === window) {
+        return;
+    }
+
+    let last = 0;
+    function report() {
+        const height = document.documentElement.scrollHeight;
+        if (height !== last) {
+            last = height;
+            window.parent.postMessage({ type: "blandwork:widget-height", height: height }, "*");
+        }
+    }
+
+    new ResizeObserver(report).observe(document.documentElement);
+    document.addEventListener("htmx:afterSettle", report);
+    report();
+})();